@@ -0,0 +1,71 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use s68k::lexer::Lexer;
+
+/// A program with every kind of line and operand, repeated to the number of lines
+fn get_program(lines: usize) -> String {
+    //every equ is replaced in every line, so there is one like in most programs
+    let block = "
+; a comment line
+start:
+    move.l #count, d0 ; load the counter
+    lea data(pc), a0
+loop:
+    move.w (a0)+, d1
+    add.l 4(a0,d1.w), d2
+    movem.l d0-d3/a0-a2, -(sp)
+    movem.l (sp)+, d0-d3/a0-a2
+    bfextu (a0){4:8}, d3
+    divs.l d0, d1:d2
+    move.l ([8,a0],d1*4,2), d4
+    lea (a0,d2.l*4), a1
+    dbra d0, loop
+    rts
+data: dc.b 'hello, world', 0
+    ds.w 4
+";
+    let count = block.lines().count();
+    format!("count equ 10{}", block.repeat(lines / count + 1))
+}
+
+const OPERANDS: &[&str] = &[
+    "d0", "a7", "sp", "fp3", "d1.w", "d2*4", "d0:d1", "d0-d3/a0-a2", "#10", "#'a'", "(a0)", "4(a0)",
+    "4(a0,d1.w)", "([8,a0],d1*4,2)", "(a0)+", "-(sp)", "(a0){4:8}", "label", "$1000",
+];
+
+fn lex(c: &mut Criterion) {
+    let program = get_program(10_000);
+    let mut group = c.benchmark_group("lexer");
+    group.throughput(Throughput::Elements(program.lines().count() as u64));
+    group.bench_function("lex 10k lines", |b| {
+        b.iter(|| {
+            let mut lexer = Lexer::new();
+            lexer.lex(black_box(&program)).unwrap();
+        })
+    });
+    group.finish();
+}
+
+fn update(c: &mut Criterion) {
+    let mut lexer = Lexer::new();
+    lexer.lex(&get_program(10_000)).unwrap();
+    c.bench_function("update a line of 10k lines", |b| {
+        b.iter(|| lexer.update(black_box(5..6), black_box("    lea data(pc), a1")))
+    });
+}
+
+fn parse_operands(c: &mut Criterion) {
+    let lexer = Lexer::new();
+    let mut group = c.benchmark_group("operands");
+    group.throughput(Throughput::Elements(OPERANDS.len() as u64));
+    group.bench_function("parse every kind", |b| {
+        b.iter(|| {
+            for operand in OPERANDS {
+                let _ = black_box(lexer.parse_operand(black_box(operand)));
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, lex, update, parse_operands);
+criterion_main!(benches);
@@ -0,0 +1,67 @@
+/*
+    C interface of the s68k assembler and interpreter, build the library with the "capi" feature:
+    cargo build --release --features capi
+
+    Every handle must be released with its s68k_*_free function. Strings returned by the library
+    are owned by the handle and stay valid until the handle is freed, the error of an interpreter
+    stays valid until the next step/run on the same interpreter.
+*/
+#ifndef S68K_H
+#define S68K_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct S68kLexer S68kLexer;
+typedef struct S68kProgram S68kProgram;
+typedef struct S68kInterpreter S68kInterpreter;
+
+#define S68K_MODEL_68000 0
+#define S68K_MODEL_68010 1
+#define S68K_MODEL_68020 2
+#define S68K_MODEL_CPU32 3
+
+#define S68K_STATUS_ERROR -1
+#define S68K_STATUS_RUNNING 0
+#define S68K_STATUS_INTERRUPT 1
+#define S68K_STATUS_TERMINATED 2
+#define S68K_STATUS_TERMINATED_WITH_EXCEPTION 3
+
+/* Lexer, returns NULL if the code is not valid UTF-8 */
+S68kLexer *s68k_lexer_new(const uint8_t *code, size_t length);
+size_t s68k_lexer_line_count(const S68kLexer *lexer);
+int32_t s68k_lexer_is_instruction(const S68kLexer *lexer, size_t index);
+void s68k_lexer_free(S68kLexer *lexer);
+
+/* Assembler, always returns a program, the program can be run only if it has no diagnostics */
+S68kProgram *s68k_assemble(const uint8_t *code, size_t length, uint32_t model);
+size_t s68k_program_diagnostic_count(const S68kProgram *program);
+const char *s68k_program_diagnostic_message(const S68kProgram *program, size_t index);
+/* Zero based line of the diagnostic, -1 when it is not tied to a line */
+int64_t s68k_program_diagnostic_line(const S68kProgram *program, size_t index);
+void s68k_program_free(S68kProgram *program);
+
+/* Interpreter, the compiled code is moved out of the program, NULL if the program had errors or was already used */
+S68kInterpreter *s68k_interpreter_new(S68kProgram *program);
+void s68k_interpreter_free(S68kInterpreter *interpreter);
+/* Both return one of the S68K_STATUS_ values, a limit of 0 runs without limit */
+int32_t s68k_interpreter_step(S68kInterpreter *interpreter);
+int32_t s68k_interpreter_run(S68kInterpreter *interpreter, size_t limit);
+/* Message of the error of the last step/run, NULL if it succeeded */
+const char *s68k_interpreter_last_error(const S68kInterpreter *interpreter);
+uint32_t s68k_interpreter_get_d_reg(const S68kInterpreter *interpreter, size_t index);
+uint32_t s68k_interpreter_get_a_reg(const S68kInterpreter *interpreter, size_t index);
+uint32_t s68k_interpreter_get_pc(const S68kInterpreter *interpreter);
+uint8_t s68k_interpreter_get_ccr(const S68kInterpreter *interpreter);
+/* Returns 0 on success and -1 if the range is out of memory */
+int32_t s68k_interpreter_read_memory(const S68kInterpreter *interpreter, size_t address, uint8_t *buffer, size_t length);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
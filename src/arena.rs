@@ -0,0 +1,347 @@
+use alloc::{boxed::Box, string::{String, ToString}, vec, vec::Vec};
+use crate::lexer::{LexedLine, LexedOperand, LexedRegisterType, LexedSize, Lexer, ParsedLine};
+
+/*
+    Arena backed AST for batch workloads. All the text of a program lives in one string and all the
+    operands in one vector, the nodes point to them by index, so a whole program is a handful of
+    allocations and is dropped at once. The lines are lexed one at a time and moved in the arena,
+    only the nodes of the line being lexed are allocated on their own.
+    The children of a node are stored next to each other, a range of operands is a list of children
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextRef {
+    start: u32,
+    end: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OperandRef(u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OperandRange {
+    start: u32,
+    end: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextRange {
+    start: u32,
+    end: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ArenaOperand {
+    Immediate(TextRef),
+    RegisterRange { mask: u16 },
+    Register(LexedRegisterType, TextRef),
+    RegisterWithSize(LexedRegisterType, TextRef, LexedSize),
+    ScaledRegister(LexedRegisterType, TextRef, LexedSize, u8),
+    RegisterPair(TextRef, TextRef),
+    Bitfield {
+        operand: OperandRef,
+        offset: TextRef,
+        width: TextRef,
+    },
+    Indirect(OperandRef),
+    IndirectDisplacement {
+        offset: TextRef,
+        operand: OperandRef,
+    },
+    IndirectIndex {
+        offset: TextRef,
+        operands: OperandRange,
+    },
+    MemoryIndirect {
+        inner: OperandRange,
+        outer: OperandRange,
+    },
+    PostIndirect(OperandRef),
+    PreIndirect(OperandRef),
+    Absolute(TextRef),
+    Label(TextRef),
+    Other(TextRef),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ArenaLine {
+    Label {
+        name: TextRef,
+    },
+    Directive {
+        name: TextRef,
+        size: LexedSize,
+        args: TextRange,
+    },
+    Instruction {
+        name: TextRef,
+        operands: OperandRange,
+        size: LexedSize,
+    },
+    Comment {
+        content: TextRef,
+    },
+    Empty,
+    Unknown {
+        content: TextRef,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArenaParsedLine {
+    pub parsed: ArenaLine,
+    pub line: TextRef,
+    pub line_index: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AstArena {
+    text: String,
+    texts: Vec<TextRef>,
+    operands: Vec<ArenaOperand>,
+    lines: Vec<ArenaParsedLine>,
+}
+
+impl AstArena {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn from_lines(lines: &[ParsedLine]) -> Self {
+        let mut arena = Self::new();
+        for line in lines {
+            arena.push_line(line);
+        }
+        arena
+    }
+    pub fn push_line(&mut self, line: &ParsedLine) {
+        let parsed = self.alloc_line(&line.parsed);
+        //a source line lexed into many lines is stored once
+        let text = match self.lines.last() {
+            Some(last) if last.line_index == line.line_index => last.line,
+            _ => self.alloc_text(&line.line),
+        };
+        self.lines.push(ArenaParsedLine {
+            parsed,
+            line: text,
+            line_index: line.line_index,
+        });
+    }
+    pub fn lines(&self) -> &[ArenaParsedLine] {
+        &self.lines
+    }
+    pub fn get_text(&self, text: TextRef) -> &str {
+        &self.text[text.start as usize..text.end as usize]
+    }
+    pub fn get_operand(&self, operand: OperandRef) -> &ArenaOperand {
+        &self.operands[operand.0 as usize]
+    }
+    pub fn get_operands(&self, range: OperandRange) -> &[ArenaOperand] {
+        &self.operands[range.start as usize..range.end as usize]
+    }
+    pub fn get_texts(&self, range: TextRange) -> &[TextRef] {
+        &self.texts[range.start as usize..range.end as usize]
+    }
+    /// Builds the owned lines, to pass the program to the semantic checker and compiler
+    pub fn to_parsed_lines(&self) -> Vec<ParsedLine> {
+        self.lines
+            .iter()
+            .map(|line| {
+                ParsedLine::new(
+                    self.to_lexed_line(&line.parsed),
+                    self.get_text(line.line).to_string(),
+                    line.line_index,
+                )
+            })
+            .collect()
+    }
+    pub fn to_lexed_line(&self, line: &ArenaLine) -> LexedLine {
+        match line {
+            ArenaLine::Label { name } => LexedLine::new_label(self.get_text(*name)),
+            ArenaLine::Directive { name, size, args } => LexedLine::Directive {
+                name: self.get_text(*name).to_string(),
+                size: size.clone(),
+                args: self
+                    .get_texts(*args)
+                    .iter()
+                    .map(|arg| self.get_text(*arg).to_string())
+                    .collect(),
+            },
+            ArenaLine::Instruction { name, operands, size } => LexedLine::Instruction {
+                name: self.get_text(*name).to_string(),
+                operands: self.to_lexed_operands(*operands),
+                size: size.clone(),
+            },
+            ArenaLine::Comment { content } => LexedLine::Comment {
+                content: self.get_text(*content).to_string(),
+            },
+            ArenaLine::Empty => LexedLine::Empty,
+            ArenaLine::Unknown { content } => LexedLine::Unknown {
+                content: self.get_text(*content).to_string(),
+            },
+        }
+    }
+    fn to_lexed_operands(&self, range: OperandRange) -> Vec<LexedOperand> {
+        self.get_operands(range)
+            .iter()
+            .map(|operand| self.to_lexed_operand(operand))
+            .collect()
+    }
+    fn to_boxed_operand(&self, operand: OperandRef) -> Box<LexedOperand> {
+        Box::new(self.to_lexed_operand(self.get_operand(operand)))
+    }
+    pub fn to_lexed_operand(&self, operand: &ArenaOperand) -> LexedOperand {
+        let text = |text: &TextRef| self.get_text(*text).to_string();
+        match operand {
+            ArenaOperand::Immediate(value) => LexedOperand::Immediate(text(value)),
+            ArenaOperand::RegisterRange { mask } => LexedOperand::RegisterRange { mask: *mask },
+            ArenaOperand::Register(kind, name) => LexedOperand::Register(kind.clone(), text(name)),
+            ArenaOperand::RegisterWithSize(kind, name, size) => {
+                LexedOperand::RegisterWithSize(kind.clone(), text(name), size.clone())
+            }
+            ArenaOperand::ScaledRegister(kind, name, size, scale) => {
+                LexedOperand::ScaledRegister(kind.clone(), text(name), size.clone(), *scale)
+            }
+            ArenaOperand::RegisterPair(high, low) => LexedOperand::RegisterPair(text(high), text(low)),
+            ArenaOperand::Bitfield { operand, offset, width } => LexedOperand::Bitfield {
+                operand: self.to_boxed_operand(*operand),
+                offset: text(offset),
+                width: text(width),
+            },
+            ArenaOperand::Indirect(operand) => LexedOperand::Indirect(self.to_boxed_operand(*operand)),
+            ArenaOperand::IndirectDisplacement { offset, operand } => LexedOperand::IndirectDisplacement {
+                offset: text(offset),
+                operand: self.to_boxed_operand(*operand),
+            },
+            ArenaOperand::IndirectIndex { offset, operands } => LexedOperand::IndirectIndex {
+                offset: text(offset),
+                operands: self.to_lexed_operands(*operands),
+            },
+            ArenaOperand::MemoryIndirect { inner, outer } => LexedOperand::MemoryIndirect {
+                inner: self.to_lexed_operands(*inner),
+                outer: self.to_lexed_operands(*outer),
+            },
+            ArenaOperand::PostIndirect(operand) => LexedOperand::PostIndirect(self.to_boxed_operand(*operand)),
+            ArenaOperand::PreIndirect(operand) => LexedOperand::PreIndirect(self.to_boxed_operand(*operand)),
+            ArenaOperand::Absolute(value) => LexedOperand::Absolute(text(value)),
+            ArenaOperand::Label(value) => LexedOperand::Label(text(value)),
+            ArenaOperand::Other(value) => LexedOperand::Other(text(value)),
+        }
+    }
+
+    fn alloc_text(&mut self, text: &str) -> TextRef {
+        let start = self.text.len() as u32;
+        self.text.push_str(text);
+        TextRef {
+            start,
+            end: self.text.len() as u32,
+        }
+    }
+    fn alloc_line(&mut self, line: &LexedLine) -> ArenaLine {
+        match line {
+            LexedLine::Label { name, .. } => ArenaLine::Label {
+                name: self.alloc_text(name),
+            },
+            LexedLine::Directive { name, size, args } => {
+                let name = self.alloc_text(name);
+                let start = self.texts.len() as u32;
+                for arg in args {
+                    let arg = self.alloc_text(arg);
+                    self.texts.push(arg);
+                }
+                ArenaLine::Directive {
+                    name,
+                    size: size.clone(),
+                    args: TextRange {
+                        start,
+                        end: self.texts.len() as u32,
+                    },
+                }
+            }
+            LexedLine::Instruction { name, operands, size } => ArenaLine::Instruction {
+                name: self.alloc_text(name),
+                operands: self.alloc_operands(operands),
+                size: size.clone(),
+            },
+            LexedLine::Comment { content } => ArenaLine::Comment {
+                content: self.alloc_text(content),
+            },
+            LexedLine::Empty => ArenaLine::Empty,
+            //the arena doesn't keep the diagnostics, they are in the lexer
+            LexedLine::Unknown { content } | LexedLine::Error { content, .. } => ArenaLine::Unknown {
+                content: self.alloc_text(content),
+            },
+        }
+    }
+    /// The slots of the operands are reserved first so they stay next to each other
+    fn alloc_operands(&mut self, operands: &[LexedOperand]) -> OperandRange {
+        let start = self.operands.len();
+        self.operands
+            .extend(operands.iter().map(|_| ArenaOperand::RegisterRange { mask: 0 }));
+        for (i, operand) in operands.iter().enumerate() {
+            self.operands[start + i] = self.alloc_operand_node(operand);
+        }
+        OperandRange {
+            start: start as u32,
+            end: (start + operands.len()) as u32,
+        }
+    }
+    fn alloc_operand(&mut self, operand: &LexedOperand) -> OperandRef {
+        let range = self.alloc_operands(core::slice::from_ref(operand));
+        OperandRef(range.start)
+    }
+    fn alloc_operand_node(&mut self, operand: &LexedOperand) -> ArenaOperand {
+        match operand {
+            LexedOperand::Immediate(value) => ArenaOperand::Immediate(self.alloc_text(value)),
+            LexedOperand::RegisterRange { mask } => ArenaOperand::RegisterRange { mask: *mask },
+            LexedOperand::Register(kind, name) => ArenaOperand::Register(kind.clone(), self.alloc_text(name)),
+            LexedOperand::RegisterWithSize(kind, name, size) => {
+                ArenaOperand::RegisterWithSize(kind.clone(), self.alloc_text(name), size.clone())
+            }
+            LexedOperand::ScaledRegister(kind, name, size, scale) => {
+                ArenaOperand::ScaledRegister(kind.clone(), self.alloc_text(name), size.clone(), *scale)
+            }
+            LexedOperand::RegisterPair(high, low) => {
+                ArenaOperand::RegisterPair(self.alloc_text(high), self.alloc_text(low))
+            }
+            LexedOperand::Bitfield { operand, offset, width } => ArenaOperand::Bitfield {
+                operand: self.alloc_operand(operand),
+                offset: self.alloc_text(offset),
+                width: self.alloc_text(width),
+            },
+            LexedOperand::Indirect(operand) => ArenaOperand::Indirect(self.alloc_operand(operand)),
+            LexedOperand::IndirectDisplacement { offset, operand } => ArenaOperand::IndirectDisplacement {
+                offset: self.alloc_text(offset),
+                operand: self.alloc_operand(operand),
+            },
+            LexedOperand::IndirectIndex { offset, operands } => ArenaOperand::IndirectIndex {
+                offset: self.alloc_text(offset),
+                operands: self.alloc_operands(operands),
+            },
+            LexedOperand::MemoryIndirect { inner, outer } => ArenaOperand::MemoryIndirect {
+                inner: self.alloc_operands(inner),
+                outer: self.alloc_operands(outer),
+            },
+            LexedOperand::PostIndirect(operand) => ArenaOperand::PostIndirect(self.alloc_operand(operand)),
+            LexedOperand::PreIndirect(operand) => ArenaOperand::PreIndirect(self.alloc_operand(operand)),
+            LexedOperand::Absolute(value) => ArenaOperand::Absolute(self.alloc_text(value)),
+            LexedOperand::Label(value) => ArenaOperand::Label(self.alloc_text(value)),
+            LexedOperand::Other(value) => ArenaOperand::Other(self.alloc_text(value)),
+        }
+    }
+}
+
+impl Lexer {
+    /// Lexes the code straight into an arena, the lexer keeps its previous lines. Malformed operands are kept like in lex_lossy
+    pub fn lex_arena(&self, code: &str) -> AstArena {
+        let lines = code.lines().map(String::from).collect::<Vec<String>>();
+        let equ_map = self.make_equ_map(&lines);
+        let mut arena = AstArena::new();
+        arena.text.reserve(code.len());
+        for (i, line) in lines.iter().enumerate() {
+            for parsed in self.lex_source_line(line, i, &equ_map, &mut vec![]) {
+                arena.push_line(&parsed);
+            }
+        }
+        arena
+    }
+}
@@ -0,0 +1,1045 @@
+use alloc::{format, string::String, vec, vec::Vec};
+use core::{error::Error, fmt};
+
+use serde::Serialize;
+
+use crate::{
+    compiler::{AssembleError, Compiler, Directive, Layout},
+    cost_report::{fits_in_word, get_encoded_size},
+    cpu_model::CpuModel,
+    diagnostic::DiagnosticCode,
+    instructions::{
+        BitfieldOperation, BitfieldValue, Condition, ControlRegister, FloatCondition, FloatFormat, FloatOperand,
+        IndexRegister, Instruction, Operand, RegisterOperand, ShiftDirection, Sign, Size, StatusRegister, TargetDirection,
+    },
+    lexer::{LexedLine, ParsedLine},
+    listing::{generate_listing, Listing},
+    semantic::validate_instruction,
+    symbol_map::SymbolMap,
+    symbol_table::SymbolTable,
+};
+
+/*
+    Machine code of the 68000 family. The compiler already placed every instruction at the size of its
+    encoding, so each one is encoded in its opcode word followed by its extension words, big endian,
+    next to the data of the dc, ds and dcb directives, the gaps left by an org are zero.
+    Branches always use a word displacement and absolute addresses are short when they fit in a word,
+    the same sizes of the cost report. The interpreter runs the same addresses with these bytes in memory
+*/
+
+#[derive(Debug)]
+#[cfg_attr(
+    feature = "serialize",
+    derive(Serialize, serde::Deserialize),
+    serde(tag = "type", content = "value")
+)]
+pub enum EncodeError {
+    Assemble(AssembleError),
+    /// An operand the instruction can't be encoded with
+    InvalidOperand { line_index: usize, message: String },
+    /// A value that doesn't fit in its field, like a branch too far away
+    OutOfRange { line_index: usize, message: String },
+}
+
+impl EncodeError {
+    pub fn get_line_index(&self) -> Option<usize> {
+        match self {
+            EncodeError::Assemble(error) => Some(error.get_line_index()),
+            EncodeError::InvalidOperand { line_index, .. } | EncodeError::OutOfRange { line_index, .. } => {
+                Some(*line_index)
+            }
+        }
+    }
+    /// The stable code of the error, see the diagnostic module
+    pub fn get_code(&self) -> DiagnosticCode {
+        match self {
+            EncodeError::Assemble(error) => error.get_code(),
+            EncodeError::InvalidOperand { .. } => DiagnosticCode::UnencodableOperand,
+            EncodeError::OutOfRange { .. } => DiagnosticCode::FieldOutOfRange,
+        }
+    }
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EncodeError::Assemble(error) => write!(f, "{}", error),
+            EncodeError::InvalidOperand { line_index, message } => {
+                write!(f, "Can't encode the instruction at line {}: {}", line_index, message)
+            }
+            EncodeError::OutOfRange { line_index, message } => {
+                write!(f, "Value out of range at line {}: {}", line_index, message)
+            }
+        }
+    }
+}
+
+impl Error for EncodeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            EncodeError::Assemble(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<AssembleError> for EncodeError {
+    fn from(error: AssembleError) -> Self {
+        EncodeError::Assemble(error)
+    }
+}
+
+/// The bytes of a line of the source, an instruction or the data of a directive
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct EncodedLine {
+    pub line_index: usize,
+    pub address: usize,
+    pub length: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MachineCode {
+    /// Address of the first byte
+    pub origin: usize,
+    pub bytes: Vec<u8>,
+    /// Where the program starts, the first instruction after the START label or the first instruction
+    pub start_address: usize,
+    /// The lines that have bytes, in the order of the source
+    pub lines: Vec<EncodedLine>,
+}
+
+impl MachineCode {
+    /// Address after the last byte
+    pub fn get_end_address(&self) -> usize {
+        self.origin + self.bytes.len()
+    }
+    pub fn get_line_bytes(&self, line: &EncodedLine) -> &[u8] {
+        let start = line.address - self.origin;
+        &self.bytes[start..start + line.length]
+    }
+}
+
+/// Mode and register of an effective address, with the extension words that follow the opcode
+struct EffectiveAddress {
+    field: u16,
+    extension: Vec<u8>,
+}
+
+impl EffectiveAddress {
+    /// The destination of a MOVE has the register before the mode
+    fn get_reversed_field(&self) -> u16 {
+        ((self.field & 7) << 3) | (self.field >> 3)
+    }
+}
+
+fn get_size_bits(size: Size) -> u16 {
+    match size {
+        Size::Byte => 0,
+        Size::Word => 1,
+        Size::Long => 2,
+    }
+}
+
+fn get_move_size_bits(size: Size) -> u16 {
+    match size {
+        Size::Byte => 1,
+        Size::Word => 3,
+        Size::Long => 2,
+    }
+}
+
+fn get_condition_bits(condition: &Condition) -> u16 {
+    match condition {
+        Condition::True => 0,
+        Condition::False => 1,
+        Condition::High => 2,
+        Condition::LowOrSame => 3,
+        Condition::CarryClear => 4,
+        Condition::CarrySet => 5,
+        Condition::NotEqual => 6,
+        Condition::Equal => 7,
+        Condition::OverflowClear => 8,
+        Condition::OverflowSet => 9,
+        Condition::Plus => 10,
+        Condition::Minus => 11,
+        Condition::GreaterThanOrEqual => 12,
+        Condition::LessThan => 13,
+        Condition::GreaterThan => 14,
+        Condition::LessThanOrEqual => 15,
+    }
+}
+
+/// The IEEE aware predicates, the ones that set BSUN on unordered operands
+fn get_float_condition_bits(condition: &FloatCondition) -> u16 {
+    match condition {
+        FloatCondition::False => 0x00,
+        FloatCondition::Equal => 0x01,
+        FloatCondition::Ordered => 0x07,
+        FloatCondition::Unordered => 0x08,
+        FloatCondition::NotEqual => 0x0E,
+        FloatCondition::True => 0x0F,
+        FloatCondition::GreaterThan => 0x12,
+        FloatCondition::GreaterThanOrEqual => 0x13,
+        FloatCondition::LessThan => 0x14,
+        FloatCondition::LessThanOrEqual => 0x15,
+        FloatCondition::GreaterOrLess => 0x16,
+        FloatCondition::GreaterLessOrEqual => 0x17,
+        FloatCondition::NotGreaterLessOrEqual => 0x18,
+        FloatCondition::NotGreaterOrLess => 0x19,
+        FloatCondition::NotLessThanOrEqual => 0x1A,
+        FloatCondition::NotLessThan => 0x1B,
+        FloatCondition::NotGreaterThanOrEqual => 0x1C,
+        FloatCondition::NotGreaterThan => 0x1D,
+    }
+}
+
+fn get_float_format_bits(format: FloatFormat) -> u16 {
+    match format {
+        FloatFormat::Long => 0,
+        FloatFormat::Single => 1,
+        FloatFormat::Extended => 2,
+        FloatFormat::Word => 4,
+        FloatFormat::Double => 5,
+        FloatFormat::Byte => 6,
+    }
+}
+
+fn get_bitfield_operation_bits(operation: &BitfieldOperation) -> u16 {
+    match operation {
+        BitfieldOperation::Test => 0,
+        BitfieldOperation::ExtractUnsigned => 1,
+        BitfieldOperation::Change => 2,
+        BitfieldOperation::ExtractSigned => 3,
+        BitfieldOperation::Clear => 4,
+        BitfieldOperation::FindFirstOne => 5,
+        BitfieldOperation::Set => 6,
+        BitfieldOperation::Insert => 7,
+    }
+}
+
+/// The 96 bit extended format of the FPU, the exponent word is followed by an empty word and the mantissa
+fn to_extended(value: f64) -> [u8; 12] {
+    let bits = value.to_bits();
+    let sign = (bits >> 63) as u16;
+    let exponent = ((bits >> 52) & 0x7FF) as i64;
+    let fraction = bits & ((1 << 52) - 1);
+    let (exponent, mantissa) = match exponent {
+        0 if fraction == 0 => (0, 0),
+        //subnormal doubles are normal in the extended format
+        0 => {
+            let zeros = fraction.leading_zeros() as i64;
+            (16383 - 1011 - zeros, fraction << zeros)
+        }
+        0x7FF => (0x7FFF, (1 << 63) | (fraction << 11)),
+        _ => (exponent - 1023 + 16383, (1 << 63) | (fraction << 11)),
+    };
+    let mut result = [0; 12];
+    result[..2].copy_from_slice(&((sign << 15) | exponent as u16).to_be_bytes());
+    result[4..].copy_from_slice(&mantissa.to_be_bytes());
+    result
+}
+
+fn get_float_immediate(value: f64, format: FloatFormat) -> Vec<u8> {
+    match format {
+        FloatFormat::Byte => vec![0, value as i64 as u8],
+        FloatFormat::Word => (value as i64 as i16).to_be_bytes().to_vec(),
+        FloatFormat::Long => (value as i64 as i32).to_be_bytes().to_vec(),
+        FloatFormat::Single => (value as f32).to_bits().to_be_bytes().to_vec(),
+        FloatFormat::Double => value.to_bits().to_be_bytes().to_vec(),
+        FloatFormat::Extended => to_extended(value).to_vec(),
+    }
+}
+
+/// Size field of the base and outer displacements of the full extension word, 1 is a null displacement
+fn get_displacement_bits(displacement: i32) -> u16 {
+    match displacement {
+        0 => 1,
+        _ if fits_in_word(displacement as i64) => 2,
+        _ => 3,
+    }
+}
+
+fn push_displacement(extension: &mut Vec<u8>, displacement: i32) {
+    match get_displacement_bits(displacement) {
+        1 => {}
+        2 => extension.extend_from_slice(&(displacement as i16).to_be_bytes()),
+        _ => extension.extend_from_slice(&displacement.to_be_bytes()),
+    }
+}
+
+struct Encoder {
+    line_index: usize,
+    address: usize,
+    bytes: Vec<u8>,
+}
+
+impl Encoder {
+    fn invalid<T>(&self, message: impl Into<String>) -> Result<T, EncodeError> {
+        Err(EncodeError::InvalidOperand {
+            line_index: self.line_index,
+            message: message.into(),
+        })
+    }
+    fn out_of_range<T>(&self, message: impl Into<String>) -> Result<T, EncodeError> {
+        Err(EncodeError::OutOfRange {
+            line_index: self.line_index,
+            message: message.into(),
+        })
+    }
+    fn word(&mut self, word: u16) {
+        self.bytes.extend_from_slice(&word.to_be_bytes());
+    }
+    fn long(&mut self, long: u32) {
+        self.bytes.extend_from_slice(&long.to_be_bytes());
+    }
+    fn immediate(&mut self, value: u32, size: Size) {
+        match size {
+            Size::Byte => self.word(value as u16 & 0xFF),
+            Size::Word => self.word(value as u16),
+            Size::Long => self.long(value),
+        }
+    }
+    /// Opcode word followed by the extension words of the effective addresses
+    fn emit(&mut self, opcode: u16, addresses: &[&EffectiveAddress]) {
+        self.word(opcode);
+        for address in addresses {
+            self.bytes.extend_from_slice(&address.extension);
+        }
+    }
+    fn get_data_register(&self, register: &RegisterOperand) -> Result<u16, EncodeError> {
+        match register {
+            RegisterOperand::Data(register) => Ok(*register as u16 & 7),
+            RegisterOperand::Address(_) => self.invalid("Expected a data register"),
+        }
+    }
+    fn get_address_register(&self, register: &RegisterOperand) -> Result<u16, EncodeError> {
+        match register {
+            RegisterOperand::Address(register) => Ok(*register as u16 & 7),
+            RegisterOperand::Data(_) => self.invalid("Expected an address register"),
+        }
+    }
+    /// D/A, register, W/L and scale of an index, shared by the brief and full extension words
+    fn get_index_bits(&self, index: &IndexRegister) -> Result<u16, EncodeError> {
+        let scale = match index.scale {
+            1 => 0,
+            2 => 1,
+            4 => 2,
+            8 => 3,
+            scale => return self.invalid(format!("Invalid index scale {}, expected 1, 2, 4 or 8", scale)),
+        };
+        let long = match index.size {
+            Size::Long => 0x800,
+            _ => 0,
+        };
+        Ok((index.register.to_index() << 12) | long | (scale << 9))
+    }
+    fn get_effective_address(&self, operand: &Operand, size: Size) -> Result<EffectiveAddress, EncodeError> {
+        let mut extension = vec![];
+        let field = match operand {
+            Operand::Register(RegisterOperand::Data(register)) => *register as u16 & 7,
+            Operand::Register(RegisterOperand::Address(register)) => 0o10 | (*register as u16 & 7),
+            Operand::Indirect(register) => 0o20 | (*register as u16 & 7),
+            Operand::PostIndirect(register) => 0o30 | (*register as u16 & 7),
+            Operand::PreIndirect(register) => 0o40 | (*register as u16 & 7),
+            Operand::IndirectDisplacement { offset, base } => {
+                let base = self.get_address_register(base)?;
+                match fits_in_word(*offset as i64) {
+                    true => {
+                        extension.extend_from_slice(&(*offset as i16).to_be_bytes());
+                        0o50 | base
+                    }
+                    //full extension word without index and a long base displacement
+                    false => {
+                        extension.extend_from_slice(&0x0170u16.to_be_bytes());
+                        extension.extend_from_slice(&offset.to_be_bytes());
+                        0o60 | base
+                    }
+                }
+            }
+            Operand::IndirectIndex { base, offset, index } => {
+                let base = self.get_address_register(base)?;
+                let index = self.get_index_bits(index)?;
+                match *offset >= i8::MIN as i32 && *offset <= i8::MAX as i32 {
+                    true => extension.extend_from_slice(&(index | (*offset as u8 as u16)).to_be_bytes()),
+                    false => {
+                        let full = index | 0x100 | (get_displacement_bits(*offset) << 4);
+                        extension.extend_from_slice(&full.to_be_bytes());
+                        push_displacement(&mut extension, *offset);
+                    }
+                }
+                0o60 | base
+            }
+            Operand::MemoryIndirect {
+                base,
+                base_displacement,
+                index,
+                post_indexed,
+                outer_displacement,
+            } => {
+                let (register, base_suppressed) = match base {
+                    Some(base) => (self.get_address_register(base)?, 0),
+                    None => (0, 0x80),
+                };
+                let index_bits = match index {
+                    Some(index) => self.get_index_bits(index)?,
+                    None => 0x40,
+                };
+                let outer = get_displacement_bits(*outer_displacement);
+                let indirect = match (index, post_indexed) {
+                    (Some(_), true) => 4 | outer,
+                    _ => outer,
+                };
+                let full = index_bits | 0x100 | base_suppressed | (get_displacement_bits(*base_displacement) << 4) | indirect;
+                extension.extend_from_slice(&full.to_be_bytes());
+                push_displacement(&mut extension, *base_displacement);
+                push_displacement(&mut extension, *outer_displacement);
+                0o60 | register
+            }
+            Operand::Absolute(address) => match fits_in_word(*address as i32 as i64) {
+                true => {
+                    extension.extend_from_slice(&(*address as u16).to_be_bytes());
+                    0o70
+                }
+                false => {
+                    extension.extend_from_slice(&(*address as u32).to_be_bytes());
+                    0o71
+                }
+            },
+            Operand::Immediate(value) => {
+                match size {
+                    Size::Byte => extension.extend_from_slice(&(*value as u16 & 0xFF).to_be_bytes()),
+                    Size::Word => extension.extend_from_slice(&(*value as u16).to_be_bytes()),
+                    Size::Long => extension.extend_from_slice(&value.to_be_bytes()),
+                }
+                0o74
+            }
+            Operand::RegisterList(_) => return self.invalid("A register list is not an effective address"),
+        };
+        Ok(EffectiveAddress { field, extension })
+    }
+    /// Word displacement of a branch, from the address after the opcode word
+    fn get_branch_displacement(&self, target: u32) -> Result<u16, EncodeError> {
+        let displacement = target as i64 - (self.address as i64 + 2);
+        match fits_in_word(displacement) {
+            true => Ok(displacement as u16),
+            false => self.out_of_range(format!(
+                "The branch target is {} bytes away, more than a word displacement can reach",
+                displacement
+            )),
+        }
+    }
+    fn encode_immediate_instruction(&mut self, base: u16, value: u32, dest: &Operand, size: Size) -> Result<(), EncodeError> {
+        if let Operand::Register(RegisterOperand::Address(_)) = dest {
+            return self.invalid("An address register can't be the destination of an immediate instruction");
+        }
+        let dest = self.get_effective_address(dest, size)?;
+        self.word(base | (get_size_bits(size) << 6) | dest.field);
+        self.immediate(value, size);
+        self.bytes.extend_from_slice(&dest.extension);
+        Ok(())
+    }
+    /// ADD, SUB, AND and OR, one of the operands is a data register
+    fn encode_arithmetic(&mut self, base: u16, source: &Operand, dest: &Operand, size: Size) -> Result<(), EncodeError> {
+        let (register, direction, address) = match (source, dest) {
+            (_, Operand::Register(RegisterOperand::Data(register))) => (*register, 0, source),
+            (Operand::Register(RegisterOperand::Data(register)), _) => (*register, 4, dest),
+            _ => return self.invalid("One of the operands must be a data register"),
+        };
+        let address = self.get_effective_address(address, size)?;
+        let opcode = base | ((register as u16 & 7) << 9) | ((direction + get_size_bits(size)) << 6) | address.field;
+        self.emit(opcode, &[&address]);
+        Ok(())
+    }
+    /// ADDA, SUBA and CMPA
+    fn encode_address_arithmetic(&mut self, base: u16, source: &Operand, dest: &RegisterOperand, size: Size) -> Result<(), EncodeError> {
+        let register = self.get_address_register(dest)?;
+        let opmode = match size {
+            Size::Word => 0x00C0,
+            Size::Long => 0x01C0,
+            Size::Byte => return self.invalid("Byte size not allowed for address register"),
+        };
+        let source = self.get_effective_address(source, size)?;
+        self.emit(base | (register << 9) | opmode | source.field, &[&source]);
+        Ok(())
+    }
+    fn encode_quick(&mut self, base: u16, value: u8, dest: &Operand, size: Size) -> Result<(), EncodeError> {
+        if !(1..=8).contains(&value) {
+            return self.out_of_range(format!("The quick value {} must be between 1 and 8", value));
+        }
+        let dest = self.get_effective_address(dest, size)?;
+        let opcode = base | ((value as u16 & 7) << 9) | (get_size_bits(size) << 6) | dest.field;
+        self.emit(opcode, &[&dest]);
+        Ok(())
+    }
+    /// CLR, NEG, NOT and TST
+    fn encode_single(&mut self, base: u16, operand: &Operand, size: Size) -> Result<(), EncodeError> {
+        let operand = self.get_effective_address(operand, size)?;
+        self.emit(base | (get_size_bits(size) << 6) | operand.field, &[&operand]);
+        Ok(())
+    }
+    /// ANDI/ORI/EORI to CCR or SR, the immediate is a word with the condition codes in the low byte
+    fn encode_status_immediate(&mut self, base: u16, value: u16, target: &StatusRegister) {
+        match target {
+            StatusRegister::SR => {
+                self.word(base | 0x7C);
+                self.word(value);
+            }
+            StatusRegister::CCR => {
+                self.word(base | 0x3C);
+                self.word(value & 0xFF);
+            }
+        }
+    }
+    /// JMP, JSR, PEA and the other instructions with only a control address
+    fn encode_control(&mut self, base: u16, operand: &Operand) -> Result<(), EncodeError> {
+        let operand = self.get_effective_address(operand, Size::Long)?;
+        self.emit(base | operand.field, &[&operand]);
+        Ok(())
+    }
+    fn encode_branch(&mut self, base: u16, target: u32) -> Result<(), EncodeError> {
+        let displacement = self.get_branch_displacement(target)?;
+        self.word(base);
+        self.word(displacement);
+        Ok(())
+    }
+    fn encode_shift(&mut self, kind: u16, count: &Operand, dest: &Operand, direction: &ShiftDirection, size: Size) -> Result<(), EncodeError> {
+        let direction = match direction {
+            ShiftDirection::Right => 0,
+            ShiftDirection::Left => 0x100,
+        };
+        match (count, dest) {
+            (_, Operand::Register(RegisterOperand::Data(register))) => {
+                let (count, register_count) = match count {
+                    Operand::Immediate(count) if (1..=8).contains(count) => (*count as u16 & 7, 0),
+                    Operand::Immediate(count) => {
+                        return self.out_of_range(format!("The shift count {} must be between 1 and 8", count))
+                    }
+                    Operand::Register(RegisterOperand::Data(register)) => (*register as u16 & 7, 0x20),
+                    _ => return self.invalid("The shift count must be an immediate or a data register"),
+                };
+                self.word(
+                    0xE000
+                        | (count << 9)
+                        | direction
+                        | (get_size_bits(size) << 6)
+                        | register_count
+                        | (kind << 3)
+                        | (*register as u16 & 7),
+                );
+                Ok(())
+            }
+            //memory is shifted by one bit and is a word
+            (Operand::Immediate(1), _) if size == Size::Word => {
+                let dest = self.get_effective_address(dest, Size::Word)?;
+                self.emit(0xE0C0 | (kind << 9) | direction | dest.field, &[&dest]);
+                Ok(())
+            }
+            _ => self.invalid("Memory can only be shifted by one bit with word size"),
+        }
+    }
+    fn encode_bit(&mut self, kind: u16, bit: &Operand, dest: &Operand) -> Result<(), EncodeError> {
+        let dest = self.get_effective_address(dest, Size::Byte)?;
+        match bit {
+            Operand::Register(RegisterOperand::Data(register)) => {
+                let opcode = 0x0100 | ((*register as u16 & 7) << 9) | (kind << 6) | dest.field;
+                self.emit(opcode, &[&dest]);
+            }
+            Operand::Immediate(bit) => {
+                self.word(0x0800 | (kind << 6) | dest.field);
+                self.word(*bit as u16 & 0xFF);
+                self.bytes.extend_from_slice(&dest.extension);
+            }
+            _ => return self.invalid("The bit number must be an immediate or a data register"),
+        }
+        Ok(())
+    }
+    fn get_bitfield_bits(&self, value: &BitfieldValue, is_width: bool) -> Result<u16, EncodeError> {
+        match (value, is_width) {
+            (BitfieldValue::Register(register), _) => Ok(0x20 | self.get_data_register(register)?),
+            (BitfieldValue::Immediate(offset), false) if *offset <= 31 => Ok(*offset as u16),
+            (BitfieldValue::Immediate(width), true) if (1..=32).contains(width) => Ok(*width as u16 & 31),
+            (BitfieldValue::Immediate(offset), false) => {
+                self.out_of_range(format!("The bitfield offset {} must be between 0 and 31", offset))
+            }
+            (BitfieldValue::Immediate(width), true) => {
+                self.out_of_range(format!("The bitfield width {} must be between 1 and 32", width))
+            }
+        }
+    }
+    /// A general instruction of the FPU with a register destination
+    fn encode_float(&mut self, opmode: u16, source: &FloatOperand, dest: u8, format: FloatFormat) -> Result<(), EncodeError> {
+        let command = (get_float_format_bits(format) << 10) | ((dest as u16 & 7) << 7) | opmode;
+        match source {
+            FloatOperand::Register(source) => {
+                self.word(0xF200);
+                self.word(((*source as u16 & 7) << 10) | (command & 0x3FF));
+            }
+            //the immediate is in the format of the instruction
+            FloatOperand::Immediate(value) => {
+                self.word(0xF200 | 0o74);
+                self.word(0x4000 | command);
+                self.bytes.extend_from_slice(&get_float_immediate(*value, format));
+            }
+            FloatOperand::Effective(Operand::Immediate(value)) => {
+                self.word(0xF200 | 0o74);
+                self.word(0x4000 | command);
+                self.bytes.extend_from_slice(&get_float_immediate(*value as i32 as f64, format));
+            }
+            FloatOperand::Effective(operand) => {
+                let source = self.get_effective_address(operand, format.to_size().unwrap_or(Size::Long))?;
+                self.word(0xF200 | source.field);
+                self.word(0x4000 | command);
+                self.bytes.extend_from_slice(&source.extension);
+            }
+        }
+        Ok(())
+    }
+    fn encode(&mut self, instruction: &Instruction) -> Result<(), EncodeError> {
+        match instruction {
+            Instruction::MOVE(source, dest, size) => {
+                let source = self.get_effective_address(source, *size)?;
+                let dest = self.get_effective_address(dest, *size)?;
+                let opcode = (get_move_size_bits(*size) << 12) | (dest.get_reversed_field() << 6) | source.field;
+                self.emit(opcode, &[&source, &dest]);
+            }
+            Instruction::MOVEA(source, dest, size) => {
+                let register = self.get_address_register(dest)?;
+                if *size == Size::Byte {
+                    return self.invalid("Byte size not allowed for address register");
+                }
+                let source = self.get_effective_address(source, *size)?;
+                let opcode = (get_move_size_bits(*size) << 12) | (register << 9) | 0x0040 | source.field;
+                self.emit(opcode, &[&source]);
+            }
+            Instruction::MOVEQ(value, dest) => {
+                let register = self.get_data_register(dest)?;
+                self.word(0x7000 | (register << 9) | *value as u16);
+            }
+            Instruction::MOVEM {
+                direction,
+                size,
+                registers_mask,
+                target,
+            } => {
+                let direction = match direction {
+                    TargetDirection::ToMemory => 0,
+                    TargetDirection::FromMemory => 0x400,
+                };
+                let long = match size {
+                    Size::Long => 0x40,
+                    _ => 0,
+                };
+                let target = self.get_effective_address(target, *size)?;
+                self.word(0x4880 | direction | long | target.field);
+                self.word(*registers_mask);
+                self.bytes.extend_from_slice(&target.extension);
+            }
+            Instruction::ADD(source, dest, size) => self.encode_arithmetic(0xD000, source, dest, *size)?,
+            Instruction::SUB(source, dest, size) => self.encode_arithmetic(0x9000, source, dest, *size)?,
+            Instruction::AND(Operand::Immediate(value), dest, size) if !matches!(dest, Operand::Register(RegisterOperand::Data(_))) => {
+                self.encode_immediate_instruction(0x0200, *value, dest, *size)?
+            }
+            Instruction::OR(Operand::Immediate(value), dest, size) if !matches!(dest, Operand::Register(RegisterOperand::Data(_))) => {
+                self.encode_immediate_instruction(0x0000, *value, dest, *size)?
+            }
+            Instruction::AND(source, dest, size) => self.encode_arithmetic(0xC000, source, dest, *size)?,
+            Instruction::OR(source, dest, size) => self.encode_arithmetic(0x8000, source, dest, *size)?,
+            Instruction::EOR(source, dest, size) => match source {
+                Operand::Immediate(value) => self.encode_immediate_instruction(0x0A00, *value, dest, *size)?,
+                Operand::Register(RegisterOperand::Data(register)) => {
+                    let dest = self.get_effective_address(dest, *size)?;
+                    let opcode = 0xB100 | ((*register as u16 & 7) << 9) | (get_size_bits(*size) << 6) | dest.field;
+                    self.emit(opcode, &[&dest]);
+                }
+                _ => return self.invalid("The source of EOR must be a data register or an immediate"),
+            },
+            Instruction::ADDA(source, dest, size) => self.encode_address_arithmetic(0xD000, source, dest, *size)?,
+            Instruction::SUBA(source, dest, size) => self.encode_address_arithmetic(0x9000, source, dest, *size)?,
+            Instruction::CMPA(source, dest, size) => self.encode_address_arithmetic(0xB000, source, dest, *size)?,
+            Instruction::CMP(source, RegisterOperand::Address(register), size) => {
+                self.encode_address_arithmetic(0xB000, source, &RegisterOperand::Address(*register), *size)?
+            }
+            Instruction::CMP(source, dest, size) => {
+                self.encode_arithmetic(0xB000, source, &Operand::Register(*dest), *size)?
+            }
+            Instruction::CMPM(source, dest, size) => match (source, dest) {
+                (Operand::PostIndirect(source), Operand::PostIndirect(dest)) => {
+                    self.word(
+                        0xB108 | ((*dest as u16 & 7) << 9) | (get_size_bits(*size) << 6) | (*source as u16 & 7),
+                    );
+                }
+                _ => return self.invalid("The operands of CMPM must be (An)+"),
+            },
+            //add.l #6, sp is compiled as an ADDI but encoded as the ADDA the 68000 has
+            Instruction::ADDI(value, Operand::Register(dest @ RegisterOperand::Address(_)), size) => {
+                self.encode_address_arithmetic(0xD000, &Operand::Immediate(*value), dest, *size)?
+            }
+            Instruction::SUBI(value, Operand::Register(dest @ RegisterOperand::Address(_)), size) => {
+                self.encode_address_arithmetic(0x9000, &Operand::Immediate(*value), dest, *size)?
+            }
+            Instruction::CMPI(value, Operand::Register(dest @ RegisterOperand::Address(_)), size) => {
+                self.encode_address_arithmetic(0xB000, &Operand::Immediate(*value), dest, *size)?
+            }
+            Instruction::ORI(value, dest, size) => self.encode_immediate_instruction(0x0000, *value, dest, *size)?,
+            Instruction::ANDI(value, dest, size) => self.encode_immediate_instruction(0x0200, *value, dest, *size)?,
+            Instruction::SUBI(value, dest, size) => self.encode_immediate_instruction(0x0400, *value, dest, *size)?,
+            Instruction::ADDI(value, dest, size) => self.encode_immediate_instruction(0x0600, *value, dest, *size)?,
+            Instruction::EORI(value, dest, size) => self.encode_immediate_instruction(0x0A00, *value, dest, *size)?,
+            Instruction::CMPI(value, dest, size) => self.encode_immediate_instruction(0x0C00, *value, dest, *size)?,
+            Instruction::ADDQ(value, dest, size) => self.encode_quick(0x5000, *value, dest, *size)?,
+            Instruction::SUBQ(value, dest, size) => self.encode_quick(0x5100, *value, dest, *size)?,
+            Instruction::DIVx(source, dest, sign) | Instruction::MULx(source, dest, sign) => {
+                let base = match (instruction, sign) {
+                    (Instruction::DIVx(..), Sign::Unsigned) => 0x80C0,
+                    (Instruction::DIVx(..), Sign::Signed) => 0x81C0,
+                    (_, Sign::Unsigned) => 0xC0C0,
+                    (_, Sign::Signed) => 0xC1C0,
+                };
+                let register = self.get_data_register(dest)?;
+                let source = self.get_effective_address(source, Size::Word)?;
+                self.emit(base | (register << 9) | source.field, &[&source]);
+            }
+            Instruction::MULxL { source, low, high, sign } => {
+                let source = self.get_effective_address(source, Size::Long)?;
+                let signed = match sign {
+                    Sign::Signed => 0x800,
+                    Sign::Unsigned => 0,
+                };
+                let high = match high {
+                    Some(high) => 0x400 | self.get_data_register(high)?,
+                    None => 0,
+                };
+                self.word(0x4C00 | source.field);
+                self.word((self.get_data_register(low)? << 12) | signed | high);
+                self.bytes.extend_from_slice(&source.extension);
+            }
+            Instruction::DIVxL {
+                source,
+                quotient,
+                remainder,
+                sign,
+                long_dividend,
+            } => {
+                let source = self.get_effective_address(source, Size::Long)?;
+                let signed = match sign {
+                    Sign::Signed => 0x800,
+                    Sign::Unsigned => 0,
+                };
+                let long_dividend = match long_dividend {
+                    true => 0x400,
+                    false => 0,
+                };
+                let quotient = self.get_data_register(quotient)?;
+                //without a remainder register both are the quotient
+                let remainder = match remainder {
+                    Some(remainder) => self.get_data_register(remainder)?,
+                    None => quotient,
+                };
+                self.word(0x4C40 | source.field);
+                self.word((quotient << 12) | signed | long_dividend | remainder);
+                self.bytes.extend_from_slice(&source.extension);
+            }
+            Instruction::BFx {
+                operation,
+                target,
+                offset,
+                width,
+                register,
+            } => {
+                let target = self.get_effective_address(target, Size::Byte)?;
+                let register = match register {
+                    Some(register) => self.get_data_register(register)?,
+                    None => 0,
+                };
+                let offset = self.get_bitfield_bits(offset, false)?;
+                let width = self.get_bitfield_bits(width, true)?;
+                self.word(0xE8C0 | (get_bitfield_operation_bits(operation) << 8) | target.field);
+                self.word((register << 12) | (offset << 6) | width);
+                self.bytes.extend_from_slice(&target.extension);
+            }
+            Instruction::SWAP(register) => {
+                let register = self.get_data_register(register)?;
+                self.word(0x4840 | register);
+            }
+            Instruction::CLR(operand, size) => self.encode_single(0x4200, operand, *size)?,
+            Instruction::NEG(operand, size) => self.encode_single(0x4400, operand, *size)?,
+            Instruction::NOT(operand, size) => self.encode_single(0x4600, operand, *size)?,
+            Instruction::TST(operand, size) => self.encode_single(0x4A00, operand, *size)?,
+            Instruction::EXG(first, second) => {
+                let opcode = match (first, second) {
+                    (RegisterOperand::Data(x), RegisterOperand::Data(y)) => 0xC140 | ((*x as u16) << 9) | *y as u16,
+                    (RegisterOperand::Address(x), RegisterOperand::Address(y)) => {
+                        0xC148 | ((*x as u16) << 9) | *y as u16
+                    }
+                    (RegisterOperand::Data(x), RegisterOperand::Address(y))
+                    | (RegisterOperand::Address(y), RegisterOperand::Data(x)) => {
+                        0xC188 | ((*x as u16) << 9) | *y as u16
+                    }
+                };
+                self.word(opcode);
+            }
+            Instruction::LEA(source, dest) => {
+                let register = self.get_address_register(dest)?;
+                self.encode_control(0x41C0 | (register << 9), source)?
+            }
+            Instruction::PEA(source) => self.encode_control(0x4840, source)?,
+            Instruction::JSR(target) => self.encode_control(0x4E80, target)?,
+            Instruction::JMP(target) => self.encode_control(0x4EC0, target)?,
+            Instruction::EXT(register, from, to) => {
+                let register = self.get_data_register(register)?;
+                let opmode = match (from, to) {
+                    (Size::Byte, Size::Word) => 0x4880,
+                    (Size::Word, Size::Long) => 0x48C0,
+                    (Size::Byte, Size::Long) => 0x49C0,
+                    _ => return self.invalid(format!("Can't extend from {:?} to {:?}", from, to)),
+                };
+                self.word(opmode | register);
+            }
+            Instruction::Bcc(target, condition) => {
+                self.encode_branch(0x6000 | (get_condition_bits(condition) << 8), *target)?
+            }
+            Instruction::BRA(target) => self.encode_branch(0x6000, *target)?,
+            Instruction::BSR(target) => self.encode_branch(0x6100, *target)?,
+            Instruction::DBcc(register, target, condition) => {
+                let register = self.get_data_register(register)?;
+                self.encode_branch(0x50C8 | (get_condition_bits(condition) << 8) | register, *target)?
+            }
+            Instruction::Scc(operand, condition) => {
+                let operand = self.get_effective_address(operand, Size::Byte)?;
+                self.emit(0x50C0 | (get_condition_bits(condition) << 8) | operand.field, &[&operand]);
+            }
+            Instruction::LINK(register, displacement) => {
+                let register = self.get_address_register(register)?;
+                if !fits_in_word(*displacement as i32 as i64) {
+                    return self.out_of_range(format!(
+                        "The LINK displacement {} must fit in a word",
+                        *displacement as i32
+                    ));
+                }
+                self.word(0x4E50 | register);
+                self.word(*displacement as u16);
+            }
+            Instruction::UNLK(register) => {
+                let register = self.get_address_register(register)?;
+                self.word(0x4E58 | register);
+            }
+            Instruction::ASd(count, dest, direction, size) => self.encode_shift(0, count, dest, direction, *size)?,
+            Instruction::LSd(count, dest, direction, size) => self.encode_shift(1, count, dest, direction, *size)?,
+            Instruction::ROd(count, dest, direction, size) => self.encode_shift(3, count, dest, direction, *size)?,
+            Instruction::BTST(bit, dest) => self.encode_bit(0, bit, dest)?,
+            Instruction::BCHG(bit, dest) => self.encode_bit(1, bit, dest)?,
+            Instruction::BCLR(bit, dest) => self.encode_bit(2, bit, dest)?,
+            Instruction::BSET(bit, dest) => self.encode_bit(3, bit, dest)?,
+            Instruction::TRAP(vector) => self.word(0x4E40 | (*vector as u16 & 15)),
+            Instruction::RTS => self.word(0x4E75),
+            Instruction::RTE => self.word(0x4E73),
+            Instruction::TRAPV => self.word(0x4E76),
+            Instruction::ILLEGAL => self.word(0x4AFC),
+            Instruction::NOP => self.word(0x4E71),
+            Instruction::SIMHALT => self.long(0xFFFF_FFFF),
+            Instruction::CHK(source, dest) => {
+                let register = self.get_data_register(dest)?;
+                let source = self.get_effective_address(source, Size::Word)?;
+                self.emit(0x4180 | (register << 9) | source.field, &[&source]);
+            }
+            Instruction::RTR => self.word(0x4E77),
+            Instruction::MOVEtoSR(source, target) => {
+                let base = match target {
+                    StatusRegister::SR => 0x46C0,
+                    StatusRegister::CCR => 0x44C0,
+                };
+                let source = self.get_effective_address(source, Size::Word)?;
+                self.emit(base | source.field, &[&source]);
+            }
+            Instruction::MOVEfromSR(dest, target) => {
+                let base = match target {
+                    StatusRegister::SR => 0x40C0,
+                    StatusRegister::CCR => 0x42C0,
+                };
+                let dest = self.get_effective_address(dest, Size::Word)?;
+                self.emit(base | dest.field, &[&dest]);
+            }
+            Instruction::ANDItoSR(value, target) => self.encode_status_immediate(0x0200, *value, target),
+            Instruction::ORItoSR(value, target) => self.encode_status_immediate(0x0000, *value, target),
+            Instruction::EORItoSR(value, target) => self.encode_status_immediate(0x0A00, *value, target),
+            Instruction::MOVEUSP { register, to_usp } => {
+                let register = self.get_address_register(register)?;
+                let direction = match to_usp {
+                    true => 0,
+                    false => 0x8,
+                };
+                self.word(0x4E60 | direction | register);
+            }
+            Instruction::RTD(displacement) => {
+                self.word(0x4E74);
+                self.word(*displacement as u16);
+            }
+            Instruction::MOVEC {
+                register,
+                control_register,
+                to_control,
+            } => {
+                let control = match control_register {
+                    ControlRegister::SFC => 0x000,
+                    ControlRegister::DFC => 0x001,
+                    ControlRegister::USP => 0x800,
+                    ControlRegister::VBR => 0x801,
+                };
+                self.word(0x4E7A | *to_control as u16);
+                self.word((register.to_index() << 12) | control);
+            }
+            Instruction::MOVES(source, dest, size) => {
+                let (register, address, to_memory) = match (source, dest) {
+                    (Operand::Register(register), address) => (register, address, 0x800),
+                    (address, Operand::Register(register)) => (register, address, 0),
+                    _ => return self.invalid("One of the operands of MOVES must be a register"),
+                };
+                let address = self.get_effective_address(address, *size)?;
+                self.word(0x0E00 | (get_size_bits(*size) << 6) | address.field);
+                self.word((register.to_index() << 12) | to_memory);
+                self.bytes.extend_from_slice(&address.extension);
+            }
+            Instruction::LINEF(opcode, operand) => match operand {
+                Some(operand) => {
+                    let operand = self.get_effective_address(operand, Size::Long)?;
+                    self.emit(opcode | operand.field, &[&operand]);
+                }
+                None => self.word(*opcode),
+            },
+            Instruction::FMOVE(source, dest, format) => match (source, dest) {
+                (_, FloatOperand::Register(dest)) => self.encode_float(0x00, source, *dest, *format)?,
+                (FloatOperand::Register(source), FloatOperand::Effective(dest)) => {
+                    let dest = self.get_effective_address(dest, format.to_size().unwrap_or(Size::Long))?;
+                    self.word(0xF200 | dest.field);
+                    self.word(0x6000 | (get_float_format_bits(*format) << 10) | ((*source as u16 & 7) << 7));
+                    self.bytes.extend_from_slice(&dest.extension);
+                }
+                _ => return self.invalid("One of the operands of FMOVE must be a floating point register"),
+            },
+            Instruction::FADD(source, dest, format) => self.encode_float(0x22, source, *dest, *format)?,
+            Instruction::FSUB(source, dest, format) => self.encode_float(0x28, source, *dest, *format)?,
+            Instruction::FMUL(source, dest, format) => self.encode_float(0x23, source, *dest, *format)?,
+            Instruction::FDIV(source, dest, format) => self.encode_float(0x20, source, *dest, *format)?,
+            Instruction::FCMP(source, dest, format) => self.encode_float(0x38, source, *dest, *format)?,
+            Instruction::FBcc(target, condition) => {
+                self.encode_branch(0xF280 | get_float_condition_bits(condition), *target)?
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Encodes a compiled instruction placed at the address
+pub fn encode_instruction(instruction: &Instruction, address: usize, line_index: usize) -> Result<Vec<u8>, EncodeError> {
+    let mut encoder = Encoder {
+        line_index,
+        address,
+        bytes: vec![],
+    };
+    encoder.encode(instruction)?;
+    Ok(encoder.bytes)
+}
+
+pub struct Assembler {
+    lines: Vec<ParsedLine>,
+    compiler: Compiler,
+    machine_code: MachineCode,
+}
+
+impl Assembler {
+    pub fn new(lines: &[ParsedLine]) -> Result<Assembler, EncodeError> {
+        Assembler::new_with_model(lines, CpuModel::default())
+    }
+    pub fn new_with_model(lines: &[ParsedLine], cpu_model: CpuModel) -> Result<Assembler, EncodeError> {
+        Assembler::new_with_layout(lines, cpu_model, Layout::default())
+    }
+    /// Assembles the program moved and with the externals of the layout
+    pub fn new_with_layout(lines: &[ParsedLine], cpu_model: CpuModel, layout: Layout) -> Result<Assembler, EncodeError> {
+        let compiler = Compiler::new_with_layout(lines, cpu_model, layout)?;
+        let machine_code = Assembler::encode(lines, &compiler)?;
+        Ok(Assembler {
+            lines: lines.to_vec(),
+            compiler,
+            machine_code,
+        })
+    }
+    /// The program compiled with the addresses of the machine code
+    pub fn get_compiler(&self) -> &Compiler {
+        &self.compiler
+    }
+    /// The labels at their addresses in the machine code and the equs
+    pub fn get_symbol_table(&self) -> &SymbolTable {
+        self.compiler.get_symbol_table()
+    }
+    pub fn get_machine_code(&self) -> &MachineCode {
+        &self.machine_code
+    }
+    pub fn into_machine_code(self) -> MachineCode {
+        self.machine_code
+    }
+    /// The labels with their address and size in the machine code and the equs, see the symbol_map module
+    pub fn get_symbol_map(&self) -> SymbolMap {
+        SymbolMap::new(self)
+    }
+    /// The source with the address and the bytes of every line, see the listing module
+    pub fn generate_listing(&self) -> Listing {
+        generate_listing(&self.lines, &self.compiler, &self.machine_code)
+    }
+    fn encode(lines: &[ParsedLine], compiler: &Compiler) -> Result<MachineCode, EncodeError> {
+        let addresses = compiler.get_line_addresses();
+        let mut instructions = compiler.get_instructions().iter();
+        let mut directives = compiler.get_directives().iter();
+        let mut chunks: Vec<(EncodedLine, Vec<u8>)> = vec![];
+        for (i, line) in lines.iter().enumerate() {
+            let bytes = match &line.parsed {
+                LexedLine::Instruction { .. } => match instructions.next() {
+                    Some(instruction) => {
+                        //the compiler trusts the checker, the operands the tables reject have no encoding
+                        if let Some(error) = validate_instruction(line) {
+                            return Err(EncodeError::InvalidOperand {
+                                line_index: line.line_index,
+                                message: error.get_error().into(),
+                            });
+                        }
+                        let bytes = encode_instruction(&instruction.instruction, addresses[i], line.line_index)?;
+                        debug_assert_eq!(bytes.len(), get_encoded_size(&instruction.instruction));
+                        bytes
+                    }
+                    None => continue,
+                },
+                LexedLine::Directive { .. } => match directives.next() {
+                    Some(Directive::DC { data, .. } | Directive::DCB { data, .. }) => data.clone(),
+                    //the space reserved by a ds is the one between its address and the next line
+                    Some(Directive::DS { .. }) => {
+                        let end = addresses.get(i + 1).copied().unwrap_or(compiler.get_end_address());
+                        vec![0; end.saturating_sub(addresses[i])]
+                    }
+                    _ => continue,
+                },
+                _ => continue,
+            };
+            if bytes.is_empty() {
+                continue;
+            }
+            let line = EncodedLine {
+                line_index: line.line_index,
+                address: addresses[i],
+                length: bytes.len(),
+            };
+            chunks.push((line, bytes));
+        }
+        let origin = chunks.iter().map(|(line, _)| line.address).min().unwrap_or(0);
+        let end = chunks.iter().map(|(line, _)| line.address + line.length).max().unwrap_or(0);
+        let mut bytes = vec![0; end.saturating_sub(origin)];
+        for (line, data) in &chunks {
+            let start = line.address - origin;
+            bytes[start..start + line.length].copy_from_slice(data);
+        }
+        Ok(MachineCode {
+            origin,
+            bytes,
+            start_address: compiler.get_start_address(),
+            lines: chunks.into_iter().map(|(line, _)| line).collect(),
+        })
+    }
+}
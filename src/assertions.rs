@@ -0,0 +1,247 @@
+use alloc::{format, string::{String, ToString}, vec, vec::Vec};
+use core::{error::Error, fmt};
+
+use serde::Serialize;
+
+use crate::{
+    grading::{CheckResult, ConditionFlag, Expectation},
+    instructions::RegisterOperand,
+    lexer::{Lexer, LexedLine},
+    utils::{parse_absolute_expression, split_comment},
+    S68k,
+    symbol::LabelTable,
+};
+
+/*
+    Assertions written in the comments of the source, so that an exercise checks itself without a grading config:
+
+        ;ASSERT d0 = 5
+        ;ASSERT word[result] = 42
+        ;ASSERT Z = 1
+        ;ASSERT_OUTPUT "Hello\n"
+
+    The left side is a register (d0-d7, a0-a7, sp), a flag (X, N, Z, V, C) or the memory at an address with
+    byte[], word[] or long[]. Addresses and values are expressions that can use labels and equs.
+    An assertion is checked when the program ends, unless it is written on the line of a label or on the
+    comment lines right after it, then the label is a checkpoint and the assertion is checked every time
+    the program reaches it. ASSERT_OUTPUT checks all the text written until then.
+    The assertions are checked by the Grader together with its own expectations
+*/
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SourceAssertion {
+    pub line_index: usize,
+    pub expectation: Expectation,
+    /// Label where the assertion is checked, missing when it is checked at the end of the program
+    pub checkpoint: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AssertionResult {
+    pub assertion: SourceAssertion,
+    pub passed: bool,
+    /// Times the assertion was checked, a checkpoint can be reached many times
+    pub hits: usize,
+    /// The first check that failed, or the last one if all passed. Missing when it was never checked
+    pub check: Option<CheckResult>,
+}
+
+impl AssertionResult {
+    pub fn new(assertion: SourceAssertion) -> Self {
+        Self {
+            assertion,
+            passed: false,
+            hits: 0,
+            check: None,
+        }
+    }
+    /// Adds a check of the assertion, once one fails the result keeps it
+    pub fn add_check(&mut self, check: CheckResult) {
+        let failed_before = self.hits > 0 && !self.passed;
+        self.hits += 1;
+        if !failed_before {
+            self.passed = check.passed;
+            self.check = Some(check);
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssertionError {
+    pub line_index: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AssertionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid assertion at line {}: {}", self.line_index + 1, self.message)
+    }
+}
+
+impl Error for AssertionError {}
+
+impl From<AssertionError> for String {
+    fn from(error: AssertionError) -> Self {
+        error.to_string()
+    }
+}
+
+fn parse_string(text: &str) -> Result<String, String> {
+    let inner = match text.len() >= 2 && text.starts_with('"') && text.ends_with('"') {
+        true => &text[1..text.len() - 1],
+        false => return Err(format!("Expected a string in double quotes, found \"{}\"", text)),
+    };
+    let mut result = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('n') => result.push('\n'),
+                Some('t') => result.push('\t'),
+                Some('r') => result.push('\r'),
+                Some(c) => result.push(c),
+                None => return Err("The string ends with a \\".to_string()),
+            },
+            _ => result.push(c),
+        }
+    }
+    Ok(result)
+}
+
+struct ExpressionContext<'a> {
+    lexer: Lexer,
+    equs: Vec<(String, String)>,
+    labels: &'a LabelTable,
+}
+
+impl ExpressionContext<'_> {
+    fn evaluate(&self, expression: &str) -> Result<i64, String> {
+        let expression = self
+            .lexer
+            .apply_equ_to_expression_string(expression.trim().to_string(), &self.equs);
+        match expression.is_empty() {
+            true => Err("Missing value".to_string()),
+            false => parse_absolute_expression(&expression, self.labels),
+        }
+    }
+}
+
+pub(crate) fn parse_register(name: &str) -> Option<RegisterOperand> {
+    match name.as_bytes() {
+        [b'd', index @ b'0'..=b'7'] => Some(RegisterOperand::Data(index - b'0')),
+        [b'a', index @ b'0'..=b'7'] => Some(RegisterOperand::Address(index - b'0')),
+        b"sp" => Some(RegisterOperand::Address(7)),
+        _ => None,
+    }
+}
+
+fn parse_flag(name: &str) -> Option<ConditionFlag> {
+    match name {
+        "x" => Some(ConditionFlag::Extend),
+        "n" => Some(ConditionFlag::Negative),
+        "z" => Some(ConditionFlag::Zero),
+        "v" => Some(ConditionFlag::Overflow),
+        "c" => Some(ConditionFlag::Carry),
+        _ => None,
+    }
+}
+
+fn parse_comparison(text: &str, context: &ExpressionContext) -> Result<Expectation, String> {
+    let (target, value) = match text.split_once("==").or_else(|| text.split_once('=')) {
+        Some(split) => split,
+        None => return Err(format!("Expected \"target = value\", found \"{}\"", text)),
+    };
+    let target = target.trim().to_lowercase();
+    let value = context.evaluate(value)?;
+    if let Some(register) = parse_register(&target) {
+        return Ok(Expectation::Register {
+            register,
+            value: value as u32,
+        });
+    }
+    if let Some(flag) = parse_flag(&target) {
+        return match value {
+            0 | 1 => Ok(Expectation::Flag { flag, value: value == 1 }),
+            _ => Err(format!("A flag can be 0 or 1, found {}", value)),
+        };
+    }
+    let (size, address) = match target.split_once('[') {
+        Some((size, rest)) if rest.ends_with(']') => (size.trim(), &rest[..rest.len() - 1]),
+        _ => return Err(format!("Unknown register, flag or memory \"{}\"", target)),
+    };
+    let length = match size {
+        "byte" => 1,
+        "word" => 2,
+        "long" => 4,
+        _ => return Err(format!("Unknown size \"{}\", expected byte, word or long", size)),
+    };
+    let bits = length * 8;
+    if value >= 1 << bits || value < -(1 << (bits - 1)) {
+        return Err(format!("The value {} doesn't fit in a {}", value, size));
+    }
+    let address = context.evaluate(address)?;
+    if address < 0 {
+        return Err(format!("Invalid address {}", address));
+    }
+    Ok(Expectation::Memory {
+        address: address as usize,
+        bytes: (value as u32).to_be_bytes()[4 - length..].to_vec(),
+    })
+}
+
+/// The expectation of a comment, none if the comment is not an assertion
+fn parse_comment(comment: &str, context: &ExpressionContext) -> Option<Result<Expectation, String>> {
+    let text = comment.trim_start_matches([';', '*']).trim();
+    let (keyword, rest) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+    match keyword.to_uppercase().as_str() {
+        "ASSERT" => Some(parse_comparison(rest.trim(), context)),
+        "ASSERT_OUTPUT" => Some(parse_string(rest.trim()).map(Expectation::Output)),
+        _ => None,
+    }
+}
+
+/// Reads the assertions of the source, the labels are the ones of the compiled program
+pub fn parse_assertions(
+    s68k: &S68k,
+    labels: &LabelTable,
+) -> Result<Vec<SourceAssertion>, Vec<AssertionError>> {
+    let lexer = Lexer::new();
+    let lines: Vec<String> = s68k.get_code().lines().map(String::from).collect();
+    let context = ExpressionContext {
+        equs: lexer.make_equ_map(&lines),
+        lexer,
+        labels,
+    };
+    let mut assertions = vec![];
+    let mut errors = vec![];
+    let mut checkpoint: Option<String> = None;
+    let mut parsed = s68k.get_lexed_lines().iter().peekable();
+    for (line_index, line) in lines.iter().enumerate() {
+        let mut ends_checkpoint = false;
+        while let Some(parsed_line) = parsed.next_if(|parsed| parsed.line_index <= line_index) {
+            match &parsed_line.parsed {
+                LexedLine::Label { name, .. } => checkpoint = Some(name.clone()),
+                LexedLine::Instruction { .. } | LexedLine::Directive { .. } => ends_checkpoint = true,
+                _ => {}
+            }
+        }
+        if let (_, Some(comment)) = split_comment(line) {
+            match parse_comment(comment, &context) {
+                Some(Ok(expectation)) => assertions.push(SourceAssertion {
+                    line_index,
+                    expectation,
+                    checkpoint: checkpoint.clone(),
+                }),
+                Some(Err(message)) => errors.push(AssertionError { line_index, message }),
+                None => {}
+            }
+        }
+        if ends_checkpoint {
+            checkpoint = None;
+        }
+    }
+    match errors.is_empty() {
+        true => Ok(assertions),
+        false => Err(errors),
+    }
+}
@@ -0,0 +1,592 @@
+use console::{Key, Term};
+use s68k::{
+    compiler::Compiler,
+    cost_report::get_cost_report,
+    cpu_model::CpuModel,
+    formatter::{format_code, FormatterOptions, LetterCase},
+    lesson_profile::LessonProfile,
+    memory_image::{MemoryImage, MemoryImageFormat, MemoryImageOptions},
+    host_io::HostIo,
+    interpreter::{Interpreter, InterpreterOptions, InterpreterStatus},
+    repl::Repl,
+    scripted_input::{ExhaustedPolicy, ScriptedInput},
+    srec::{write_srec, SrecOptions},
+    trace::TraceRecorder,
+    S68k,
+};
+use std::{env, fs, io::Write, process};
+
+/*
+    Command line interface for the assembler and interpreter:
+
+        r68k assemble file.s [-o out] [--format listing|bin|srec|s19|s28|s37|elf|map|map-json|mif|hex|coe|costs|costs-json] [--model 68000]
+            [--word-width 8] [--depth 1024]
+        r68k run file.s [--limit 1M] [--input in.txt] [--on-input-end fail|repeat|eof=-1] [--model 68000]
+            [--trace trace.csv] [--lesson "week 3: move, add, sub, branches"]
+        r68k debug file.s [--break 12] [--input in.txt] [--on-input-end fail] [--model 68000]
+        r68k fmt file.s [--write | --check] [--case lower|upper|preserve] [--mnemonic-column 8]
+        r68k repl [--model 68000]
+
+    The srec format has the machine code of the program as S-records, the smallest of S19, S28 and S37 that
+    fits the addresses, s19, s28 and s37 force one of them, see the srec module. The elf format is a relocatable
+    object for the GNU m68k linker, see the elf module. The listing has the address and the bytes of every line.
+    The map formats have the address and size of every label and the value of every equ, see the symbol_map module.
+    The bin and memory image formats have the machine code from its lowest to its highest address.
+    The word width in bits and the depth in words are used by the mif, hex and coe memory images.
+    The costs formats have the bytes and cycles of every line and subroutine, see the cost_report module.
+    With --input the read traps take the lines of the file, --on-input-end says what they get once it is over.
+    With --trace every step of the run is written to the file, as CSV if it ends with .csv, otherwise as JSON.
+    With --lesson only the instructions of the lesson profile can be used, see the lesson_profile module
+*/
+
+const USAGE: &str = "Usage:
+    r68k assemble <file> [-o <output>] [--format listing|bin|srec|s19|s28|s37|elf|map|map-json|mif|hex|coe|costs|costs-json] [--model <model>]
+        [--word-width <bits>] [--depth <words>]
+    r68k run <file> [--limit <instructions>] [--input <file>] [--on-input-end <policy>] [--model <model>]
+        [--trace <file>] [--lesson <profile>]
+    r68k debug <file> [--break <line>]... [--input <file>] [--on-input-end <policy>] [--model <model>]
+        [--lesson <profile>]
+    r68k fmt <file> [--write | --check] [-o <output>] [--case lower|upper|preserve]
+        [--label-column <n>] [--mnemonic-column <n>] [--operands-column <n>] [--comment-column <n>] [--no-space-after-comma]
+    r68k repl [--model <model>]";
+
+struct Options {
+    output: Option<String>,
+    format: String,
+    limit: Option<usize>,
+    input: Option<String>,
+    input_end: ExhaustedPolicy,
+    trace: Option<String>,
+    model: CpuModel,
+    lesson: Option<LessonProfile>,
+    breakpoints: Vec<usize>,
+    formatter: FormatterOptions,
+    memory_image: MemoryImageOptions,
+    write: bool,
+    check: bool,
+}
+
+/// Parses a count like 1000, 10K or 1M
+fn parse_limit(value: &str) -> Result<usize, String> {
+    let value = value.trim().to_uppercase();
+    let (number, multiplier) = match value.chars().last() {
+        Some('K') => (&value[..value.len() - 1], 1_000),
+        Some('M') => (&value[..value.len() - 1], 1_000_000),
+        Some('G') => (&value[..value.len() - 1], 1_000_000_000),
+        _ => (value.as_str(), 1),
+    };
+    let number = number
+        .parse::<usize>()
+        .map_err(|_| format!("Invalid limit \"{}\"", value))?;
+    //a limit that overflows is refused instead of wrapping around to a small one
+    number
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("Limit \"{}\" is too large", value))
+}
+
+fn parse_column(value: &str) -> Result<usize, String> {
+    value
+        .parse::<usize>()
+        .map_err(|_| format!("Invalid column \"{}\"", value))
+}
+
+fn parse_options(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        output: None,
+        format: "listing".to_string(),
+        limit: None,
+        input: None,
+        input_end: ExhaustedPolicy::Fail,
+        trace: None,
+        model: CpuModel::M68000,
+        lesson: None,
+        breakpoints: vec![],
+        formatter: FormatterOptions::default(),
+        memory_image: MemoryImageOptions::default(),
+        write: false,
+        check: false,
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .cloned()
+                .ok_or_else(|| format!("Missing value for {}", arg))
+        };
+        match arg.as_str() {
+            "-o" | "--output" => options.output = Some(value()?),
+            "--format" => options.format = value()?,
+            "--limit" => options.limit = Some(parse_limit(&value()?)?),
+            "--input" => options.input = Some(value()?),
+            "--on-input-end" => options.input_end = value()?.parse()?,
+            "--trace" => options.trace = Some(value()?),
+            "--model" => options.model = value()?.parse()?,
+            "--lesson" => options.lesson = Some(value()?.parse()?),
+            "--break" => {
+                let line = value()?;
+                let line = line
+                    .parse::<usize>()
+                    .map_err(|_| format!("Invalid breakpoint line \"{}\"", line))?;
+                options.breakpoints.push(line.saturating_sub(1));
+            }
+            "--word-width" => {
+                let width = value()?;
+                options.memory_image.word_width = width
+                    .parse::<usize>()
+                    .map_err(|_| format!("Invalid word width \"{}\"", width))?;
+            }
+            "--depth" => options.memory_image.depth = Some(parse_limit(&value()?)?),
+            "--write" => options.write = true,
+            "--check" => options.check = true,
+            "--no-space-after-comma" => options.formatter.space_after_comma = false,
+            "--case" => {
+                options.formatter.case = match value()?.as_str() {
+                    "lower" => LetterCase::Lower,
+                    "upper" => LetterCase::Upper,
+                    "preserve" => LetterCase::Preserve,
+                    case => return Err(format!("Unknown case \"{}\", expected lower, upper or preserve", case)),
+                }
+            }
+            "--label-column" => options.formatter.label_column = parse_column(&value()?)?,
+            "--mnemonic-column" => options.formatter.mnemonic_column = parse_column(&value()?)?,
+            "--operands-column" => options.formatter.operands_column = parse_column(&value()?)?,
+            "--comment-column" => options.formatter.comment_column = parse_column(&value()?)?,
+            _ => return Err(format!("Unknown option {}", arg)),
+        }
+    }
+    Ok(options)
+}
+
+fn load(path: &str, options: &Options) -> Result<(S68k, Compiler), String> {
+    let code = fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path, e))?;
+    let mut s68k = S68k::new(code);
+    s68k.set_cpu_model(options.model);
+    s68k.set_lesson_profile(options.lesson.clone());
+    let errors = s68k.semantic_check();
+    if !errors.is_empty() {
+        let messages: Vec<String> = errors.iter().map(|e| e.get_message_with_line()).collect();
+        return Err(messages.join("\n"));
+    }
+    let compiled = s68k.compile()?;
+    Ok((s68k, compiled))
+}
+
+fn assemble(path: &str, options: &Options) -> Result<(), String> {
+    let (s68k, compiled) = load(path, options)?;
+    let output = match options.format.as_str() {
+        "listing" => s68k
+            .assemble()
+            .map_err(|e| e.to_string())?
+            .generate_listing()
+            .to_text()
+            .into_bytes(),
+        "costs" => get_cost_report(&s68k, &compiled).to_text(s68k.get_code()).into_bytes(),
+        #[cfg(feature = "serialize")]
+        "costs-json" => get_cost_report(&s68k, &compiled).to_json().into_bytes(),
+        #[cfg(not(feature = "serialize"))]
+        "costs-json" => return Err("The costs-json format needs the serialize feature, use costs".to_string()),
+        "srec" | "s19" | "s28" | "s37" => {
+            let machine_code = s68k.assemble().map_err(|e| e.to_string())?.into_machine_code();
+            let mut srec = SrecOptions::default().with_header(path);
+            if options.format != "srec" {
+                srec = srec.with_format(options.format.parse()?);
+            }
+            write_srec(&machine_code, &srec)?.into_bytes()
+        }
+        "map" => s68k.assemble().map_err(|e| e.to_string())?.get_symbol_map().to_text().into_bytes(),
+        #[cfg(feature = "serialize")]
+        "map-json" => s68k.assemble().map_err(|e| e.to_string())?.get_symbol_map().to_json().into_bytes(),
+        #[cfg(not(feature = "serialize"))]
+        "map-json" => return Err("The map-json format needs the serialize feature, use map".to_string()),
+        "elf" => s68k.assemble_object().map_err(|e| e.to_string())?.to_elf(),
+        "bin" | "mif" | "hex" | "coe" => {
+            let assembler = s68k.assemble().map_err(|e| e.to_string())?;
+            let image = MemoryImage::from_machine_code(assembler.get_machine_code());
+            match options.format.as_str() {
+                "bin" => image.bytes,
+                format => {
+                    let format: MemoryImageFormat = format.parse()?;
+                    image.write(format, &options.memory_image)?.into_bytes()
+                }
+            }
+        }
+        format => return Err(format!(
+            "Unknown format \"{}\", expected listing, bin, srec, s19, s28, s37, elf, map, map-json, mif, hex, coe, costs or costs-json",
+            format
+        )),
+    };
+    match &options.output {
+        Some(output_path) => fs::write(output_path, output).map_err(|e| format!("Could not write {}: {}", output_path, e)),
+        None => std::io::stdout().write_all(&output).map_err(|e| e.to_string()),
+    }
+}
+
+fn fmt(path: &str, options: &Options) -> Result<(), String> {
+    let code = fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path, e))?;
+    let formatted = format_code(&code, &options.formatter);
+    if options.check {
+        return match formatted == code {
+            true => Ok(()),
+            false => Err(format!("{} is not formatted", path)),
+        };
+    }
+    let output = match options.write {
+        true => Some(path),
+        false => options.output.as_deref(),
+    };
+    match output {
+        Some(output_path) => fs::write(output_path, formatted).map_err(|e| format!("Could not write {}: {}", output_path, e)),
+        None => std::io::stdout().write_all(formatted.as_bytes()).map_err(|e| e.to_string()),
+    }
+}
+
+/// Where the input traps read from, either the terminal or the content of the --input file
+enum Input {
+    Terminal,
+    Script(ScriptedInput),
+}
+
+impl Input {
+    fn new(options: &Options) -> Result<Self, String> {
+        match &options.input {
+            Some(path) => fs::read_to_string(path)
+                .map(|text| Input::Script(ScriptedInput::from_text(&text, options.input_end)))
+                .map_err(|e| format!("Could not read {}: {}", path, e)),
+            None => Ok(Input::Terminal),
+        }
+    }
+    fn read_line(&mut self) -> Result<String, String> {
+        match self {
+            Input::Terminal => Ok(Term::stdout().read_line().unwrap_or_default()),
+            Input::Script(script) => Ok(script.read_line()?),
+        }
+    }
+    fn read_number(&mut self) -> Result<i32, String> {
+        match self {
+            Input::Terminal => {
+                let line = self.read_line()?;
+                line.trim()
+                    .parse::<i32>()
+                    .map_err(|_| format!("Expected a number as input, received \"{}\"", line))
+            }
+            Input::Script(script) => Ok(script.read_number()?),
+        }
+    }
+    fn read_char(&mut self) -> Result<char, String> {
+        match self {
+            Input::Terminal => Ok(Term::stdout().read_char().unwrap_or('\0')),
+            Input::Script(script) => Ok(script.read_char()?),
+        }
+    }
+}
+
+/// The console of the program, the text it writes is collected in the output
+struct Console<'a> {
+    input: &'a mut Input,
+    output: &'a mut String,
+}
+
+impl HostIo for Console<'_> {
+    fn write(&mut self, text: &str) {
+        self.output.push_str(text);
+    }
+    fn read_line(&mut self) -> Result<String, String> {
+        self.input.read_line()
+    }
+    fn read_char(&mut self) -> Result<char, String> {
+        self.input.read_char()
+    }
+    fn read_number(&mut self) -> Result<i32, String> {
+        self.input.read_number()
+    }
+    fn has_input(&mut self) -> bool {
+        match self.input {
+            Input::Terminal => true,
+            Input::Script(script) => !script.is_exhausted(),
+        }
+    }
+    fn get_time(&mut self) -> u32 {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| ((d.as_millis() % 86_400_000) / 10) as u32)
+            .unwrap_or(0)
+    }
+    fn clear_screen(&mut self) {
+        if let Input::Terminal = self.input {
+            let _ = Term::stdout().clear_screen();
+        }
+        self.output.clear();
+    }
+}
+
+fn answer_interrupt(interpreter: &mut Interpreter, input: &mut Input, output: &mut String) -> Result<(), String> {
+    interpreter
+        .answer_interrupt_with(&mut Console { input, output })
+        .map_err(|e| e.get_message())
+}
+
+fn write_trace(path: &str, trace: &TraceRecorder) -> Result<(), String> {
+    let content = match path.to_lowercase().ends_with(".csv") {
+        true => trace.to_csv(),
+        #[cfg(feature = "serialize")]
+        false => trace.to_json(),
+        #[cfg(not(feature = "serialize"))]
+        false => return Err("JSON traces need the serialize feature, use a .csv file".to_string()),
+    };
+    fs::write(path, content).map_err(|e| format!("Could not write {}: {}", path, e))
+}
+
+fn run(path: &str, options: &Options) -> Result<(), String> {
+    let (s68k, compiled) = load(path, options)?;
+    //the memory writes of the trace come from the history
+    let interpreter_options = options.trace.as_ref().map(|_| InterpreterOptions {
+        keep_history: true,
+        history_size: 1,
+        ..Default::default()
+    });
+    let mut interpreter = s68k.create_interpreter(compiled, interpreter_options);
+    let mut trace = TraceRecorder::new();
+    let result = run_interpreter(&mut interpreter, options, options.trace.as_ref().map(|_| &mut trace));
+    if let Some(trace_path) = &options.trace {
+        write_trace(trace_path, &trace)?;
+    }
+    result
+}
+
+fn run_interpreter(
+    interpreter: &mut Interpreter,
+    options: &Options,
+    mut trace: Option<&mut TraceRecorder>,
+) -> Result<(), String> {
+    let mut input = Input::new(options)?;
+    let limit = options.limit.unwrap_or(usize::MAX);
+    let mut executed = 0;
+    loop {
+        match interpreter.get_status() {
+            InterpreterStatus::Interrupt => {
+                let mut output = String::new();
+                answer_interrupt(interpreter, &mut input, &mut output)?;
+                print!("{}", output);
+                let _ = std::io::stdout().flush();
+            }
+            InterpreterStatus::Terminated => return Ok(()),
+            InterpreterStatus::TerminatedWithException => {
+                return Err("Program terminated with an exception".to_string())
+            }
+            InterpreterStatus::Running if executed >= limit => {
+                return Err(format!("Execution limit of {} instructions reached", limit))
+            }
+            InterpreterStatus::Running => {
+                match trace.as_deref_mut() {
+                    Some(trace) => trace.record_step(interpreter),
+                    None => interpreter.step(),
+                }
+                .map_err(|e| interpreter.get_error_message(&e))?;
+                executed += 1;
+            }
+        }
+    }
+}
+
+struct DebugView {
+    lines: Vec<String>,
+    breakpoints: Vec<usize>,
+    output: String,
+    message: String,
+}
+
+impl DebugView {
+    fn draw(&self, term: &Term, interpreter: &Interpreter) -> std::io::Result<()> {
+        term.clear_screen()?;
+        let current = interpreter
+            .get_instruction_at(interpreter.get_pc())
+            .map(|i| i.parsed_line.line_index);
+        let center = current.unwrap_or(self.lines.len());
+        let start = center.saturating_sub(8);
+        for (i, line) in self.lines.iter().enumerate().skip(start).take(17) {
+            let marker = match (Some(i) == current, self.breakpoints.contains(&i)) {
+                (true, _) => "->",
+                (false, true) => " *",
+                (false, false) => "  ",
+            };
+            term.write_line(&format!("{} {:4} {}", marker, i + 1, line))?;
+        }
+        term.write_line("")?;
+        let cpu = interpreter.get_cpu();
+        for i in 0..8 {
+            term.write_line(&format!(
+                "D{}: {:08X}    A{}: {:08X}",
+                i,
+                cpu.wasm_get_d_reg(i).get_long(),
+                i,
+                cpu.wasm_get_a_reg(i).get_long()
+            ))?;
+        }
+        term.write_line(&format!(
+            "PC: {:08X}    {}    cycles: {}",
+            interpreter.get_pc(),
+            cpu.wasm_get_ccr().get_status(),
+            interpreter.get_cycles()
+        ))?;
+        let calls: Vec<String> = interpreter
+            .get_pretty_call_stack()
+            .iter()
+            .map(|l| l.name.clone())
+            .collect();
+        term.write_line(&format!("Calls: {}", calls.join(" > ")))?;
+        term.write_line("")?;
+        term.write_line(&format!("Output: {}", self.output))?;
+        term.write_line(&self.message)?;
+        term.write_line("[s]tep  [n]ext  [o]ut  [c]ontinue  [u]ndo  [b]reakpoint  [q]uit")
+    }
+}
+
+enum DebugStep {
+    In,
+    Over(usize),
+    Out(usize),
+    Continue,
+}
+
+fn debug_resume(
+    interpreter: &mut Interpreter,
+    view: &mut DebugView,
+    input: &mut Input,
+    step: DebugStep,
+) -> Result<(), String> {
+    loop {
+        match interpreter.get_status() {
+            InterpreterStatus::Interrupt => {
+                answer_interrupt(interpreter, input, &mut view.output)?;
+                continue;
+            }
+            InterpreterStatus::Terminated | InterpreterStatus::TerminatedWithException => {
+                view.message = "The program has terminated".to_string();
+                return Ok(());
+            }
+            InterpreterStatus::Running => {}
+        }
+        interpreter.step().map_err(|e| interpreter.get_error_message(&e))?;
+        let depth = interpreter.get_call_depth();
+        let line = interpreter
+            .get_instruction_at(interpreter.get_pc())
+            .map(|i| i.parsed_line.line_index);
+        let done = match step {
+            DebugStep::In => true,
+            DebugStep::Over(start) => depth <= start,
+            DebugStep::Out(start) => depth < start,
+            DebugStep::Continue => false,
+        };
+        let on_breakpoint = match line {
+            Some(line) => view.breakpoints.contains(&line),
+            None => false,
+        };
+        if done || on_breakpoint || interpreter.has_terminated() {
+            return Ok(());
+        }
+    }
+}
+
+fn debug(path: &str, options: &Options) -> Result<(), String> {
+    let (s68k, compiled) = load(path, options)?;
+    let mut view = DebugView {
+        lines: s68k.get_code().lines().map(String::from).collect(),
+        breakpoints: options.breakpoints.clone(),
+        output: String::new(),
+        message: String::new(),
+    };
+    let history = InterpreterOptions {
+        keep_history: true,
+        history_size: 1000,
+        ..Default::default()
+    };
+    let mut interpreter = s68k.create_interpreter(compiled, Some(history));
+    let mut input = Input::new(options)?;
+    let term = Term::stdout();
+    loop {
+        view.draw(&term, &interpreter).map_err(|e| e.to_string())?;
+        view.message = String::new();
+        let key = term.read_key().map_err(|e| e.to_string())?;
+        let depth = interpreter.get_call_depth();
+        let result = match key {
+            Key::Char('s') | Key::ArrowRight => debug_resume(&mut interpreter, &mut view, &mut input, DebugStep::In),
+            Key::Char('n') | Key::ArrowDown => {
+                debug_resume(&mut interpreter, &mut view, &mut input, DebugStep::Over(depth))
+            }
+            Key::Char('o') => debug_resume(&mut interpreter, &mut view, &mut input, DebugStep::Out(depth)),
+            Key::Char('c') => debug_resume(&mut interpreter, &mut view, &mut input, DebugStep::Continue),
+            Key::Char('u') | Key::ArrowLeft => interpreter.undo().map(|_| ()).map_err(|e| e.get_message()),
+            Key::Char('b') => {
+                if let Some(ins) = interpreter.get_instruction_at(interpreter.get_pc()) {
+                    let line = ins.parsed_line.line_index;
+                    match view.breakpoints.contains(&line) {
+                        true => view.breakpoints.retain(|l| *l != line),
+                        false => view.breakpoints.push(line),
+                    }
+                }
+                Ok(())
+            }
+            Key::Char('q') | Key::Escape => return Ok(()),
+            _ => Ok(()),
+        };
+        if let Err(e) = result {
+            view.message = format!("Error: {}", e);
+        }
+    }
+}
+
+fn repl(options: &Options) -> Result<(), String> {
+    let mut repl = Repl::new(options.model)?;
+    let mut input = Input::Terminal;
+    println!("Type an instruction or directive to run it, :help for the commands, :quit to exit");
+    loop {
+        print!("> ");
+        let _ = std::io::stdout().flush();
+        let mut line = String::new();
+        //the input ends with ctrl+d or the end of a piped file
+        if std::io::stdin().read_line(&mut line).map_err(|e| e.to_string())? == 0 {
+            return Ok(());
+        }
+        if line.trim() == ":quit" || line.trim() == ":q" {
+            return Ok(());
+        }
+        match repl.eval(&line) {
+            Ok(result) if result.is_empty() => {}
+            Ok(result) => println!("{}", result),
+            Err(e) => println!("Error: {}", e),
+        }
+        while *repl.get_interpreter().get_status() == InterpreterStatus::Interrupt {
+            let mut output = String::new();
+            if let Err(e) = answer_interrupt(repl.get_interpreter_mut(), &mut input, &mut output) {
+                println!("Error: {}", e);
+                break;
+            }
+            println!("{}", output);
+        }
+    }
+}
+
+fn main() {
+    let args = env::args().skip(1).collect::<Vec<String>>();
+    let result = match &args[..] {
+        [command, rest @ ..] if command == "repl" => match parse_options(rest) {
+            Ok(options) => repl(&options),
+            Err(e) => Err(format!("{}\n{}", e, USAGE)),
+        },
+        [command, file, rest @ ..] => match parse_options(rest) {
+            Ok(options) => match command.as_str() {
+                "assemble" => assemble(file, &options),
+                "run" => run(file, &options),
+                "debug" => debug(file, &options),
+                "fmt" => fmt(file, &options),
+                _ => Err(format!("Unknown command {}\n{}", command, USAGE)),
+            },
+            Err(e) => Err(format!("{}\n{}", e, USAGE)),
+        },
+        _ => Err(USAGE.to_string()),
+    };
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,8 @@
+use std::io::{stdin, stdout};
+
+fn main() {
+    if let Err(e) = s68k::debug_adapter::run(stdin().lock(), stdout().lock()) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
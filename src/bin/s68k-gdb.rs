@@ -0,0 +1,55 @@
+use s68k::{
+    cpu_model::CpuModel,
+    gdb_stub::{serve, GdbStub},
+    interpreter::InterpreterOptions,
+    S68k,
+};
+use std::{env, fs, net::TcpListener};
+
+const USAGE: &str = "Usage: s68k-gdb <file> [--listen <address>] [--input <file>] [--model <model>]";
+
+fn load(args: &[String]) -> Result<(GdbStub, String), String> {
+    let path = args.first().ok_or_else(|| USAGE.to_string())?;
+    let mut address = "127.0.0.1:1234".to_string();
+    let mut input = String::new();
+    let mut model = CpuModel::M68000;
+    let mut rest = args[1..].iter();
+    while let Some(flag) = rest.next() {
+        let value = rest.next().ok_or_else(|| USAGE.to_string())?;
+        match flag.as_str() {
+            "--listen" => address = value.clone(),
+            "--input" => input = fs::read_to_string(value).map_err(|e| format!("Could not read {}: {}", value, e))?,
+            "--model" => model = value.parse()?,
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+    let code = fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path, e))?;
+    let mut s68k = S68k::new(code);
+    s68k.set_cpu_model(model);
+    let errors = s68k.semantic_check();
+    if !errors.is_empty() {
+        let messages: Vec<String> = errors.iter().map(|e| e.get_message_with_line()).collect();
+        return Err(messages.join("\n"));
+    }
+    let compiled = s68k.compile()?;
+    let interpreter = s68k.create_interpreter(compiled, Some(InterpreterOptions::default()));
+    let mut stub = GdbStub::new(interpreter);
+    stub.set_input(input);
+    Ok((stub, address))
+}
+
+fn run(args: &[String]) -> Result<(), String> {
+    let (mut stub, address) = load(args)?;
+    let listener = TcpListener::bind(&address).map_err(|e| e.to_string())?;
+    eprintln!("Waiting for gdb on {}", address);
+    let (stream, _) = listener.accept().map_err(|e| e.to_string())?;
+    serve(stream, &mut stub)
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let Err(e) = run(&args) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
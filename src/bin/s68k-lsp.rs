@@ -0,0 +1,15 @@
+use lsp_server::Connection;
+
+fn main() {
+    let (connection, io_threads) = Connection::stdio();
+    let result = s68k::language_server::run(&connection);
+    drop(connection);
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+    if let Err(e) = io_threads.join() {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
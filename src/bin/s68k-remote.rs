@@ -0,0 +1,15 @@
+use std::io::{stdin, stdout};
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match args.as_slice() {
+        [] => s68k::remote_debug::run(stdin().lock(), stdout().lock()),
+        #[cfg(feature = "websocket")]
+        [flag, address] if flag == "--websocket" => s68k::remote_debug::serve_websocket(address),
+        _ => Err("Usage: s68k-remote [--websocket <address>]".to_string()),
+    };
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,70 @@
+use alloc::string::String;
+use serde::{Deserialize, Serialize};
+
+use crate::memory_region::{Access, Permissions};
+
+/*
+    Where the run loop pauses. A breakpoint stops before the instruction at its address runs, it can be placed
+    on a line, a label or an address and is resolved to the address of the instruction when it is added.
+    A watchpoint stops after the instruction that reads or writes a byte of its range, with the access that
+    triggered it, the access is the one of the instruction so a long read over the start of the range is
+    reported at the address of the read.
+    Breakpoints and watchpoints share the ids, so a hit tells which one fired
+*/
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "value")]
+pub enum BreakpointLocation {
+    Line(usize),
+    Label(String),
+    Address(usize),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Breakpoint {
+    pub id: usize,
+    pub location: BreakpointLocation,
+    /// Address of the instruction it stops at
+    pub address: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Watchpoint {
+    pub id: usize,
+    pub address: usize,
+    pub length: usize,
+    /// The accesses that trigger it, read and write
+    pub accesses: Permissions,
+}
+
+impl Watchpoint {
+    pub fn matches(&self, address: usize, length: usize, access: Access) -> bool {
+        self.accesses.contains(access.get_permission())
+            && address < self.address + self.length
+            && address + length.max(1) > self.address
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", content = "value")]
+pub enum BreakHit {
+    Breakpoint {
+        id: usize,
+        address: usize,
+    },
+    Watchpoint {
+        id: usize,
+        /// Address and length of the access
+        address: usize,
+        length: usize,
+        access: Access,
+    },
+}
+
+impl BreakHit {
+    pub fn get_id(&self) -> usize {
+        match self {
+            BreakHit::Breakpoint { id, .. } | BreakHit::Watchpoint { id, .. } => *id,
+        }
+    }
+}
@@ -0,0 +1,200 @@
+use alloc::{boxed::Box, format, string::{String, ToString}, vec, vec::Vec};
+use core::{error::Error, fmt};
+
+use crate::{
+    constants::INSTRUCTIONS,
+    lexer::{LexedLine, LexedOperand, LexedRegisterType, LexedSize, Lexer, ParsedLine},
+    printer::{get_size_suffix, print_lexed_line},
+    S68k,
+};
+
+/*
+    Builds programs from rust without formatting and lexing text, for test harnesses and code generators.
+    The nodes are the same the lexer would give for the printed source, so the program goes to the
+    semantic checker and compiler as it is, every node is on its own source line. The mnemonics are
+    checked when the program is built, an unknown one is an error instead of a line the checker refuses:
+
+    let s68k = ProgramBuilder::new()
+        .inst("move", LexedSize::Long, [imm(3), d(0)])
+        .label("loop")
+        .inst("add", LexedSize::Long, [d(0), d(1)])
+        .inst("dbra", LexedSize::Unspecified, [d(0), label("loop")])
+        .into_s68k()?;
+*/
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BuilderError {
+    /// The mnemonic of the node is not an instruction
+    UnknownInstruction { index: usize, name: String },
+}
+
+impl fmt::Display for BuilderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BuilderError::UnknownInstruction { index, name } => {
+                write!(f, "Unknown instruction \"{}\" at node {}", name, index)
+            }
+        }
+    }
+}
+
+impl Error for BuilderError {}
+
+impl From<BuilderError> for String {
+    fn from(error: BuilderError) -> Self {
+        error.to_string()
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ProgramBuilder {
+    lines: Vec<LexedLine>,
+}
+
+impl ProgramBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn label(mut self, name: &str) -> Self {
+        self.lines.push(LexedLine::new_label(name));
+        self
+    }
+    pub fn inst(mut self, name: &str, size: LexedSize, operands: impl IntoIterator<Item = LexedOperand>) -> Self {
+        self.lines.push(LexedLine::Instruction {
+            name: name.to_lowercase(),
+            operands: operands.into_iter().collect(),
+            size,
+        });
+        self
+    }
+    /// Any directive, the args are written as they would be in the source
+    pub fn directive(mut self, name: &str, size: LexedSize, args: &[&str]) -> Self {
+        let name = name.to_lowercase();
+        let directive = format!("{}{}", name, get_size_suffix(&size));
+        let mut all_args = vec![directive];
+        all_args.extend(args.iter().map(|arg| arg.to_string()));
+        self.lines.push(LexedLine::Directive {
+            name,
+            size,
+            args: all_args,
+        });
+        self
+    }
+    pub fn equ(mut self, name: &str, value: &str) -> Self {
+        self.lines.push(LexedLine::Directive {
+            name: "equ".to_string(),
+            size: LexedSize::Unspecified,
+            args: vec![name.to_string(), "equ".to_string(), value.to_string()],
+        });
+        self
+    }
+    pub fn org(self, address: u32) -> Self {
+        self.directive("org", LexedSize::Unspecified, &[&format!("${:X}", address)])
+    }
+    pub fn dc(self, size: LexedSize, values: &[&str]) -> Self {
+        self.directive("dc", size, values)
+    }
+    pub fn bra(self, target: &str) -> Self {
+        self.inst("bra", LexedSize::Unspecified, [label(target)])
+    }
+    pub fn bsr(self, target: &str) -> Self {
+        self.inst("bsr", LexedSize::Unspecified, [label(target)])
+    }
+    pub fn beq(self, target: &str) -> Self {
+        self.inst("beq", LexedSize::Unspecified, [label(target)])
+    }
+    pub fn bne(self, target: &str) -> Self {
+        self.inst("bne", LexedSize::Unspecified, [label(target)])
+    }
+    pub fn rts(self) -> Self {
+        self.inst("rts", LexedSize::Unspecified, [])
+    }
+    /// The lines of the program, one source line for each node. The equs are applied like the lexer does
+    pub fn build(&self) -> Result<Vec<ParsedLine>, BuilderError> {
+        for (index, line) in self.lines.iter().enumerate() {
+            if let LexedLine::Instruction { name, .. } = line {
+                if !INSTRUCTIONS.contains(&name.as_str()) {
+                    return Err(BuilderError::UnknownInstruction { index, name: name.clone() });
+                }
+            }
+        }
+        let lexer = Lexer::new();
+        let mut equ_map = self
+            .lines
+            .iter()
+            .filter_map(|line| match line {
+                LexedLine::Directive { name, args, .. } if name == "equ" && args.len() >= 3 => {
+                    Some((args[0].clone(), args[2..].join(" ")))
+                }
+                _ => None,
+            })
+            .collect::<Vec<(String, String)>>();
+        equ_map.sort_by_key(|e| core::cmp::Reverse(e.0.len()));
+        let mut lines = vec![];
+        for (line_index, parsed) in self.lines.iter().enumerate() {
+            let line = print_builder_line(parsed);
+            //a line with only a label is lexed with an empty line after it
+            if let LexedLine::Label { .. } = parsed {
+                lines.push(ParsedLine::new(parsed.clone(), line.clone(), line_index));
+                lines.push(ParsedLine::new(LexedLine::Empty, line, line_index));
+                continue;
+            }
+            lines.push(ParsedLine::new(lexer.apply_equ_to_line(parsed.clone(), &equ_map), line, line_index));
+        }
+        Ok(lines)
+    }
+    /// The program as text, it lexes to the same lines given by build
+    pub fn to_source(&self) -> String {
+        self.lines.iter().map(print_builder_line).collect::<Vec<String>>().join("\n")
+    }
+    pub fn into_s68k(self) -> Result<S68k, BuilderError> {
+        Ok(S68k::from_lines(self.build()?))
+    }
+}
+
+fn print_builder_line(line: &LexedLine) -> String {
+    match line {
+        LexedLine::Label { .. } => print_lexed_line(line),
+        LexedLine::Directive { name, .. } if name == "equ" => print_lexed_line(line),
+        _ => format!("    {}", print_lexed_line(line)),
+    }
+}
+
+pub fn imm(value: i64) -> LexedOperand {
+    LexedOperand::Immediate(format!("#{}", value))
+}
+pub fn d(register: u8) -> LexedOperand {
+    LexedOperand::Register(LexedRegisterType::Data, format!("d{}", register))
+}
+pub fn a(register: u8) -> LexedOperand {
+    LexedOperand::Register(LexedRegisterType::Address, format!("a{}", register))
+}
+pub fn sp() -> LexedOperand {
+    LexedOperand::Register(LexedRegisterType::SP, "sp".to_string())
+}
+/// (An)
+pub fn ind(register: LexedOperand) -> LexedOperand {
+    LexedOperand::Indirect(Box::new(register))
+}
+/// (An)+
+pub fn post_inc(register: LexedOperand) -> LexedOperand {
+    LexedOperand::PostIndirect(Box::new(register))
+}
+/// -(An)
+pub fn pre_dec(register: LexedOperand) -> LexedOperand {
+    LexedOperand::PreIndirect(Box::new(register))
+}
+/// offset(An)
+pub fn disp(offset: i32, register: LexedOperand) -> LexedOperand {
+    LexedOperand::IndirectDisplacement {
+        offset: offset.to_string(),
+        operand: Box::new(register),
+    }
+}
+pub fn abs(address: u32) -> LexedOperand {
+    LexedOperand::Absolute(format!("${:X}", address))
+}
+/// A label used as an operand, the lexer gives them as absolute values
+pub fn label(name: &str) -> LexedOperand {
+    LexedOperand::Absolute(name.to_string())
+}
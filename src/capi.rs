@@ -0,0 +1,352 @@
+use std::{
+    ffi::{c_char, CString},
+    ptr, slice,
+};
+
+use crate::{
+    compiler::Compiler,
+    cpu_model::CpuModel,
+    interpreter::{Interpreter, InterpreterOptions, InterpreterStatus, RuntimeError},
+    lexer::{LexedLine, Lexer},
+    S68k,
+};
+
+/*
+    C interface, the declarations are in include/s68k.h.
+    Every handle returned by a s68k_*_new/assemble function must be released with its s68k_*_free function,
+    strings returned by the library are owned by the handle and stay valid until the handle is freed
+    or, for errors, until the next call on the same handle
+*/
+
+pub struct S68kLexer {
+    lexer: Lexer,
+}
+
+pub struct S68kProgram {
+    compiler: Option<Compiler>,
+    diagnostics: Vec<(i64, CString)>,
+}
+
+pub struct S68kInterpreter {
+    interpreter: Interpreter,
+    last_error: Option<CString>,
+}
+
+pub const S68K_STATUS_ERROR: i32 = -1;
+
+fn to_c_string(string: String) -> CString {
+    //interior null bytes can't be represented, they are dropped
+    CString::new(string.replace('\0', "")).unwrap_or_default()
+}
+
+fn status_to_int(status: InterpreterStatus) -> i32 {
+    match status {
+        InterpreterStatus::Running => 0,
+        InterpreterStatus::Interrupt => 1,
+        InterpreterStatus::Terminated => 2,
+        InterpreterStatus::TerminatedWithException => 3,
+    }
+}
+
+fn model_from_int(model: u32) -> Option<CpuModel> {
+    match model {
+        0 => Some(CpuModel::M68000),
+        1 => Some(CpuModel::M68010),
+        2 => Some(CpuModel::M68020),
+        3 => Some(CpuModel::CPU32),
+        _ => None,
+    }
+}
+
+unsafe fn read_code(code: *const u8, length: usize) -> Option<String> {
+    if code.is_null() {
+        return None;
+    }
+    let bytes = slice::from_raw_parts(code, length);
+    std::str::from_utf8(bytes).ok().map(String::from)
+}
+
+/// # Safety
+/// `code` must point to `length` readable bytes of UTF-8 text
+#[no_mangle]
+pub unsafe extern "C" fn s68k_lexer_new(code: *const u8, length: usize) -> *mut S68kLexer {
+    match read_code(code, length) {
+        Some(code) => {
+            let mut lexer = Lexer::new();
+            lexer.lex_lossy(&code);
+            Box::into_raw(Box::new(S68kLexer { lexer }))
+        }
+        None => ptr::null_mut(),
+    }
+}
+
+/// # Safety
+/// `lexer` must be a handle returned by `s68k_lexer_new`
+#[no_mangle]
+pub unsafe extern "C" fn s68k_lexer_line_count(lexer: *const S68kLexer) -> usize {
+    match lexer.as_ref() {
+        Some(lexer) => lexer.lexer.lines().len(),
+        None => 0,
+    }
+}
+
+/// Returns 1 if the lexed line at the index is an instruction, 0 otherwise
+///
+/// # Safety
+/// `lexer` must be a handle returned by `s68k_lexer_new`
+#[no_mangle]
+pub unsafe extern "C" fn s68k_lexer_is_instruction(lexer: *const S68kLexer, index: usize) -> i32 {
+    match lexer.as_ref().and_then(|l| l.lexer.lines().get(index)) {
+        Some(line) => match line.parsed {
+            LexedLine::Instruction { .. } => 1,
+            _ => 0,
+        },
+        None => 0,
+    }
+}
+
+/// # Safety
+/// `lexer` must be a handle returned by `s68k_lexer_new` that was not freed already, or null
+#[no_mangle]
+pub unsafe extern "C" fn s68k_lexer_free(lexer: *mut S68kLexer) {
+    if !lexer.is_null() {
+        drop(Box::from_raw(lexer));
+    }
+}
+
+/// Checks and compiles the code, the diagnostics can be read from the returned program.
+/// The model is 0 for the 68000, 1 for the 68010, 2 for the 68020 and 3 for the CPU32
+///
+/// # Safety
+/// `code` must point to `length` readable bytes of UTF-8 text
+#[no_mangle]
+pub unsafe extern "C" fn s68k_assemble(code: *const u8, length: usize, model: u32) -> *mut S68kProgram {
+    let mut program = S68kProgram {
+        compiler: None,
+        diagnostics: vec![],
+    };
+    match (read_code(code, length), model_from_int(model)) {
+        (Some(code), Some(model)) => {
+            let mut s68k = S68k::new(code);
+            s68k.set_cpu_model(model);
+            program.diagnostics = s68k
+                .semantic_check()
+                .iter()
+                .map(|e| (e.get_line_index() as i64, to_c_string(e.get_message())))
+                .collect();
+            if program.diagnostics.is_empty() {
+                match s68k.compile() {
+                    Ok(compiler) => program.compiler = Some(compiler),
+                    Err(e) => program
+                        .diagnostics
+                        .push((e.get_line_index() as i64, to_c_string(e.to_string()))),
+                }
+            }
+        }
+        (None, _) => program
+            .diagnostics
+            .push((-1, to_c_string("The code is not valid UTF-8".to_string()))),
+        (_, None) => program
+            .diagnostics
+            .push((-1, to_c_string(format!("Unknown CPU model {}", model)))),
+    }
+    Box::into_raw(Box::new(program))
+}
+
+/// # Safety
+/// `program` must be a handle returned by `s68k_assemble`
+#[no_mangle]
+pub unsafe extern "C" fn s68k_program_diagnostic_count(program: *const S68kProgram) -> usize {
+    match program.as_ref() {
+        Some(program) => program.diagnostics.len(),
+        None => 0,
+    }
+}
+
+/// # Safety
+/// `program` must be a handle returned by `s68k_assemble`
+#[no_mangle]
+pub unsafe extern "C" fn s68k_program_diagnostic_message(
+    program: *const S68kProgram,
+    index: usize,
+) -> *const c_char {
+    match program.as_ref().and_then(|p| p.diagnostics.get(index)) {
+        Some((_, message)) => message.as_ptr(),
+        None => ptr::null(),
+    }
+}
+
+/// Zero based line of the diagnostic, -1 when it is not tied to a line
+///
+/// # Safety
+/// `program` must be a handle returned by `s68k_assemble`
+#[no_mangle]
+pub unsafe extern "C" fn s68k_program_diagnostic_line(program: *const S68kProgram, index: usize) -> i64 {
+    match program.as_ref().and_then(|p| p.diagnostics.get(index)) {
+        Some((line, _)) => *line,
+        None => -1,
+    }
+}
+
+/// # Safety
+/// `program` must be a handle returned by `s68k_assemble` that was not freed already, or null
+#[no_mangle]
+pub unsafe extern "C" fn s68k_program_free(program: *mut S68kProgram) {
+    if !program.is_null() {
+        drop(Box::from_raw(program));
+    }
+}
+
+/// Creates an interpreter from an assembled program, the compiled code is moved into the interpreter
+/// so a program can only be used once. Returns null if the program had errors or was already used
+///
+/// # Safety
+/// `program` must be a handle returned by `s68k_assemble`
+#[no_mangle]
+pub unsafe extern "C" fn s68k_interpreter_new(program: *mut S68kProgram) -> *mut S68kInterpreter {
+    match program.as_mut().and_then(|p| p.compiler.take()) {
+        Some(compiler) => {
+            let options = InterpreterOptions {
+                cpu_model: compiler.get_cpu_model(),
+                ..Default::default()
+            };
+            Box::into_raw(Box::new(S68kInterpreter {
+                interpreter: Interpreter::new(compiler, Some(options)),
+                last_error: None,
+            }))
+        }
+        None => ptr::null_mut(),
+    }
+}
+
+/// # Safety
+/// `interpreter` must be a handle returned by `s68k_interpreter_new` that was not freed already, or null
+#[no_mangle]
+pub unsafe extern "C" fn s68k_interpreter_free(interpreter: *mut S68kInterpreter) {
+    if !interpreter.is_null() {
+        drop(Box::from_raw(interpreter));
+    }
+}
+
+impl S68kInterpreter {
+    fn record_result(&mut self, result: Result<InterpreterStatus, RuntimeError>) -> i32 {
+        match result {
+            Ok(status) => {
+                self.last_error = None;
+                status_to_int(status)
+            }
+            Err(e) => {
+                self.last_error = Some(to_c_string(e.get_message()));
+                S68K_STATUS_ERROR
+            }
+        }
+    }
+}
+
+/// Executes one instruction, returns the status or S68K_STATUS_ERROR
+///
+/// # Safety
+/// `interpreter` must be a handle returned by `s68k_interpreter_new`
+#[no_mangle]
+pub unsafe extern "C" fn s68k_interpreter_step(interpreter: *mut S68kInterpreter) -> i32 {
+    match interpreter.as_mut() {
+        Some(handle) => {
+            let result = handle.interpreter.step();
+            handle.record_result(result)
+        }
+        None => S68K_STATUS_ERROR,
+    }
+}
+
+/// Runs until the program ends or stops for an interrupt, a limit of 0 means no limit
+///
+/// # Safety
+/// `interpreter` must be a handle returned by `s68k_interpreter_new`
+#[no_mangle]
+pub unsafe extern "C" fn s68k_interpreter_run(interpreter: *mut S68kInterpreter, limit: usize) -> i32 {
+    match interpreter.as_mut() {
+        Some(handle) => {
+            let result = match limit {
+                0 => handle.interpreter.run(),
+                limit => handle.interpreter.run_with_limit(limit),
+            };
+            handle.record_result(result)
+        }
+        None => S68K_STATUS_ERROR,
+    }
+}
+
+/// Message of the error of the last step/run, null if it succeeded
+///
+/// # Safety
+/// `interpreter` must be a handle returned by `s68k_interpreter_new`
+#[no_mangle]
+pub unsafe extern "C" fn s68k_interpreter_last_error(interpreter: *const S68kInterpreter) -> *const c_char {
+    match interpreter.as_ref().and_then(|i| i.last_error.as_ref()) {
+        Some(error) => error.as_ptr(),
+        None => ptr::null(),
+    }
+}
+
+/// # Safety
+/// `interpreter` must be a handle returned by `s68k_interpreter_new`
+#[no_mangle]
+pub unsafe extern "C" fn s68k_interpreter_get_d_reg(interpreter: *const S68kInterpreter, index: usize) -> u32 {
+    match interpreter.as_ref() {
+        Some(handle) if index < 8 => handle.interpreter.get_cpu().wasm_get_d_reg(index).get_long(),
+        _ => 0,
+    }
+}
+
+/// # Safety
+/// `interpreter` must be a handle returned by `s68k_interpreter_new`
+#[no_mangle]
+pub unsafe extern "C" fn s68k_interpreter_get_a_reg(interpreter: *const S68kInterpreter, index: usize) -> u32 {
+    match interpreter.as_ref() {
+        Some(handle) if index < 8 => handle.interpreter.get_cpu().wasm_get_a_reg(index).get_long(),
+        _ => 0,
+    }
+}
+
+/// # Safety
+/// `interpreter` must be a handle returned by `s68k_interpreter_new`
+#[no_mangle]
+pub unsafe extern "C" fn s68k_interpreter_get_pc(interpreter: *const S68kInterpreter) -> u32 {
+    match interpreter.as_ref() {
+        Some(handle) => handle.interpreter.get_pc() as u32,
+        None => 0,
+    }
+}
+
+/// # Safety
+/// `interpreter` must be a handle returned by `s68k_interpreter_new`
+#[no_mangle]
+pub unsafe extern "C" fn s68k_interpreter_get_ccr(interpreter: *const S68kInterpreter) -> u8 {
+    match interpreter.as_ref() {
+        Some(handle) => handle.interpreter.get_cpu().wasm_get_ccr().to_ccr(),
+        None => 0,
+    }
+}
+
+/// Copies `length` bytes of memory starting at `address` into `buffer`, returns 0 on success and -1 on error
+///
+/// # Safety
+/// `interpreter` must be a handle returned by `s68k_interpreter_new`, `buffer` must have room for `length` bytes
+#[no_mangle]
+pub unsafe extern "C" fn s68k_interpreter_read_memory(
+    interpreter: *const S68kInterpreter,
+    address: usize,
+    buffer: *mut u8,
+    length: usize,
+) -> i32 {
+    if buffer.is_null() {
+        return -1;
+    }
+    match interpreter.as_ref().map(|h| h.interpreter.get_memory().read_bytes(address, length)) {
+        Some(Ok(bytes)) => {
+            ptr::copy_nonoverlapping(bytes.as_ptr(), buffer, length);
+            0
+        }
+        _ => -1,
+    }
+}
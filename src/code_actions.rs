@@ -0,0 +1,277 @@
+use alloc::{format, string::{String, ToString}, vec, vec::Vec};
+use serde::Serialize;
+
+use crate::{
+    compiler::AssembleError,
+    completion::get_valid_sizes,
+    constants::INSTRUCTIONS,
+    lexer::{LexedLine, LexedSize},
+    utils::{split_comment, split_operands},
+    S68k,
+};
+
+/*
+    Code actions for editors, fixes that can be applied without asking the user anything.
+    A quick fix is tied to the error it fixes and is only offered if the program checked after the edit
+    doesn't have that error anymore. The fixes are: a wrong size suffix is replaced by the valid ones or removed
+    when the instruction is not sized, an unknown mnemonic is replaced by the closest known ones, an immediate
+    destination is swapped with the source, and an instruction at an odd address gets a padding byte before it. Branches don't take a size in this assembler, so there
+    is no short branch to convert to. The refactors add the size suffix to an instruction that has none.
+    Edits use UTF-16 columns like the semantic tokens
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum CodeActionKind {
+    QuickFix,
+    Refactor,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TextEdit {
+    pub line: usize,
+    pub start: usize,
+    pub end: usize,
+    pub new_text: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CodeAction {
+    pub title: String,
+    pub kind: CodeActionKind,
+    /// Message of the error that is fixed, missing for refactors
+    pub diagnostic: Option<String>,
+    pub edits: Vec<TextEdit>,
+}
+
+/// Byte ranges of the mnemonic, of its size suffix and of the operands of an instruction line
+struct Statement {
+    mnemonic: (usize, usize),
+    suffix: usize,
+    operands: (usize, usize),
+}
+
+fn get_statement(line: &str) -> Option<Statement> {
+    let (code, _) = split_comment(line);
+    let code = code.trim_end();
+    //the label is before the first colon, if there is one
+    let start = code.find(':').map(|i| i + 1).unwrap_or(0);
+    let start = start + code[start..].len() - code[start..].trim_start().len();
+    let end = code[start..].find(char::is_whitespace).map(|i| start + i).unwrap_or(code.len());
+    if start == end {
+        return None;
+    }
+    let operands_start = end + code[end..].len() - code[end..].trim_start().len();
+    Some(Statement {
+        mnemonic: (start, end),
+        suffix: code[start..end].find('.').map(|i| start + i).unwrap_or(end),
+        operands: (operands_start, code.len()),
+    })
+}
+
+fn utf16_len(text: &str) -> usize {
+    text.encode_utf16().count()
+}
+
+fn replace(line_index: usize, line: &str, start: usize, end: usize, new_text: String) -> TextEdit {
+    TextEdit {
+        line: line_index,
+        start: utf16_len(&line[..start]),
+        end: utf16_len(&line[..end]),
+        new_text,
+    }
+}
+
+/// The code with the edits applied, the edits must be on different lines or not overlap
+fn apply_edits(code: &str, edits: &[TextEdit]) -> String {
+    let mut lines: Vec<String> = code.lines().map(String::from).collect();
+    let mut edits = edits.to_vec();
+    edits.sort_by_key(|edit| core::cmp::Reverse((edit.line, edit.start)));
+    for edit in edits {
+        if let Some(line) = lines.get_mut(edit.line) {
+            let text: Vec<u16> = line.encode_utf16().collect();
+            let start = edit.start.min(text.len());
+            let end = edit.end.clamp(start, text.len());
+            *line = format!(
+                "{}{}{}",
+                String::from_utf16_lossy(&text[..start]),
+                edit.new_text,
+                String::from_utf16_lossy(&text[end..])
+            );
+        }
+    }
+    lines.join("\n")
+}
+
+/// If the edits remove the error from the line
+fn fixes(s68k: &S68k, line_index: usize, error: &str, edits: &[TextEdit]) -> bool {
+    let mut edited = S68k::new(apply_edits(s68k.get_code(), edits));
+    edited.set_cpu_model(s68k.get_cpu_model());
+    !edited
+        .semantic_check()
+        .iter()
+        .any(|e| e.get_line_index() == line_index && e.get_error() == error)
+}
+
+fn get_size_fixes(line_index: usize, line: &str, name: &str, error: &str) -> Vec<(String, Vec<TextEdit>)> {
+    let statement = match get_statement(line) {
+        Some(statement) => statement,
+        None => return vec![],
+    };
+    let (suffix, end) = (statement.suffix, statement.mnemonic.1);
+    if error == "Invalid size, instruction is not sized" {
+        let edit = replace(line_index, line, suffix, end, String::new());
+        return vec![("Remove the size suffix".to_string(), vec![edit])];
+    }
+    get_valid_sizes(name)
+        .into_iter()
+        .map(|size| {
+            let edit = replace(line_index, line, suffix, end, format!(".{}", size));
+            (format!("Change the size to .{}", size), vec![edit])
+        })
+        .collect()
+}
+
+/// Number of single character insertions, deletions and substitutions that turn a word into the other
+fn get_edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + (ca != *cb) as usize;
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// The known mnemonics closest to a misspelled one, at most two edits away
+fn get_mnemonic_fixes(line_index: usize, line: &str, name: &str) -> Vec<(String, Vec<TextEdit>)> {
+    let statement = match get_statement(line) {
+        Some(statement) => statement,
+        None => return vec![],
+    };
+    let distances: Vec<(usize, &str)> = INSTRUCTIONS
+        .iter()
+        .map(|instruction| (get_edit_distance(name, instruction), *instruction))
+        .filter(|(distance, _)| *distance <= 2)
+        .collect();
+    let closest = distances.iter().map(|(distance, _)| *distance).min();
+    distances
+        .iter()
+        .filter(|(distance, _)| Some(*distance) == closest)
+        .map(|(_, instruction)| {
+            let edit = replace(line_index, line, statement.mnemonic.0, statement.suffix, instruction.to_string());
+            (format!("Replace with {}", instruction), vec![edit])
+        })
+        .collect()
+}
+
+fn get_swap_fix(line_index: usize, line: &str) -> Vec<(String, Vec<TextEdit>)> {
+    let statement = match get_statement(line) {
+        Some(statement) => statement,
+        None => return vec![],
+    };
+    let (start, end) = statement.operands;
+    match split_operands(&line[start..end]).as_slice() {
+        [source, dest] => {
+            let edit = replace(line_index, line, start, end, format!("{}, {}", dest, source));
+            vec![("Swap the operands".to_string(), vec![edit])]
+        }
+        _ => vec![],
+    }
+}
+
+fn get_quick_fixes(s68k: &S68k, line_index: usize) -> Vec<CodeAction> {
+    let mut actions = vec![];
+    let errors = s68k.semantic_check();
+    for error in errors.iter().filter(|e| e.get_line_index() == line_index) {
+        let line = &error.get_line().line;
+        let message = error.get_error();
+        let candidates = match &error.get_line().parsed {
+            LexedLine::Instruction { name, .. } if message.contains("size") => {
+                get_size_fixes(line_index, line, name, message)
+            }
+            LexedLine::Instruction { name, .. } if message.starts_with("Unknown instruction") => {
+                get_mnemonic_fixes(line_index, line, name)
+            }
+            LexedLine::Instruction { .. }
+                if message.starts_with("Incorrect second operand addressing mode, received \"Im\"") =>
+            {
+                get_swap_fix(line_index, line)
+            }
+            _ => vec![],
+        };
+        for (title, edits) in candidates {
+            if fixes(s68k, line_index, message, &edits) {
+                actions.push(CodeAction {
+                    title,
+                    kind: CodeActionKind::QuickFix,
+                    diagnostic: Some(message.to_string()),
+                    edits,
+                });
+            }
+        }
+    }
+    //only a checked program can be compiled
+    if !errors.is_empty() {
+        return actions;
+    }
+    if let Err(error @ AssembleError::OddAddress { line_index: odd_line, .. }) = s68k.compile() {
+        if odd_line == line_index {
+            actions.push(CodeAction {
+                title: "Insert a padding byte to align the instruction".to_string(),
+                kind: CodeActionKind::QuickFix,
+                diagnostic: Some(error.to_string()),
+                edits: vec![TextEdit {
+                    line: line_index,
+                    start: 0,
+                    end: 0,
+                    new_text: "    dc.b 0\n".to_string(),
+                }],
+            });
+        }
+    }
+    actions
+}
+
+fn get_refactors(s68k: &S68k, line_index: usize) -> Vec<CodeAction> {
+    let without_size = s68k.get_lexed_lines().iter().find_map(|line| match &line.parsed {
+        LexedLine::Instruction {
+            name,
+            size: LexedSize::Unspecified,
+            ..
+        } if line.line_index == line_index => Some((&line.line, name)),
+        _ => None,
+    });
+    let (line, name, statement) = match without_size {
+        Some((line, name)) => match get_statement(line) {
+            Some(statement) => (line, name, statement),
+            None => return vec![],
+        },
+        None => return vec![],
+    };
+    let end = statement.mnemonic.1;
+    let sizes = get_valid_sizes(name);
+    //an instruction with a single size has nothing to choose
+    if sizes.len() < 2 {
+        return vec![];
+    }
+    sizes
+        .into_iter()
+        .map(|size| CodeAction {
+            title: format!("Add the size suffix .{}", size),
+            kind: CodeActionKind::Refactor,
+            diagnostic: None,
+            edits: vec![replace(line_index, line, end, end, format!(".{}", size))],
+        })
+        .collect()
+}
+
+/// The quick fixes of the errors on the line followed by the refactors of the line
+pub fn get_code_actions(s68k: &S68k, line_index: usize) -> Vec<CodeAction> {
+    let mut actions = get_quick_fixes(s68k, line_index);
+    actions.extend(get_refactors(s68k, line_index));
+    actions
+}
@@ -0,0 +1,9 @@
+#[cfg(feature = "std")]
+pub use std::collections::{HashMap, HashSet};
+#[cfg(not(feature = "std"))]
+pub use hashbrown::{HashMap, HashSet};
+
+/*
+    The hash maps of the crate, the ones of std when it is enabled, else the ones of hashbrown
+    that have the same api and only need alloc
+*/
@@ -1,17 +1,27 @@
-use std::{collections::HashMap, vec};
-use std::fmt;
+use alloc::{format, string::{String, ToString}, vec, vec::Vec};
+use crate::collections::HashMap;
+use core::error::Error;
+use core::fmt;
 
 use serde::Serialize;
+#[cfg(feature = "wasm")]
 use wasm_bindgen::prelude::wasm_bindgen;
 
 use crate::{
+    cost_report::get_encoded_size,
+    cpu_model::{CpuFeature, CpuModel},
+    diagnostic::DiagnosticCode,
     instructions::{
-        Condition, Instruction, Label, Operand, RegisterOperand,
-        ShiftDirection, Sign, Size,
+        BitfieldOperation, BitfieldValue, Condition, FloatFormat, FloatOperand, Instruction, Label, Operand, RegisterOperand,
+        ShiftDirection, Sign, Size, StatusRegister,
     },
-    lexer::{LexedLine, LexedOperand, LexedRegisterType, LexedSize, ParsedLine},
+    lexer::{get_assembled_lines, LexError, LexedLine, LexedOperand, LexedRegisterType, LexedSize, ParsedLine},
+    local_labels::get_label_key,
     math::sign_extend_to_long,
-    utils::{parse_absolute_expression, parse_string_into_padded_bytes},
+    source_map::SourceMap,
+    symbol_table::{SymbolEntry, SymbolKind, SymbolTable},
+    utils::parse_string_into_padded_bytes,
+    symbol::LabelTable,
 };
 use crate::instructions::{IndexRegister, TargetDirection};
 
@@ -23,17 +33,37 @@ pub enum Directive {
     Other,
 }
 
-#[wasm_bindgen]
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
 pub struct Compiler {
-    labels: HashMap<String, Label>,
+    labels: LabelTable,
+    symbols: SymbolTable,
     line_addresses: Vec<usize>,
+    /// Address after the last line
+    end_address: usize,
     directives: Vec<Directive>,
     instructions: Vec<InstructionLine>,
     start_address: usize,
     final_instrucion_address: usize,
+    cpu_model: CpuModel,
+    layout: Layout,
+    /// Bytes of every line, the encoded size of the instructions and 0 for the other lines
+    instruction_sizes: Vec<usize>,
+}
+
+/// How many times the lines are placed before giving up on sizes that keep changing
+const MAX_LAYOUT_PASSES: usize = 8;
+
+/// Where the program is placed, the default one starts at $1000 without externals
+#[derive(Debug, Clone, Default)]
+pub struct Layout {
+    /// Added to the start and to the address of every ORG
+    pub offset: usize,
+    /// Addresses of the symbols imported with XREF, the ones without an address are not defined
+    pub externals: HashMap<String, usize>,
 }
 
 #[derive(Clone, Serialize)]
+#[cfg_attr(feature = "serialize", derive(serde::Deserialize))]
 pub struct InstructionLine {
     pub instruction: Instruction,
     pub address: usize,
@@ -41,11 +71,17 @@ pub struct InstructionLine {
 }
 
 #[derive(Debug)]
+#[cfg_attr(
+    feature = "serialize",
+    derive(Serialize, serde::Deserialize),
+    serde(tag = "type", content = "value")
+)]
 pub enum CompilationError {
     Raw(String),
     InvalidTrap(String),
     InvalidAddressingMode(String),
     ParseError(String),
+    Lex(LexError),
 }
 
 impl CompilationError {
@@ -55,12 +91,160 @@ impl CompilationError {
             | CompilationError::InvalidTrap(message)
             | CompilationError::InvalidAddressingMode(message)
             | CompilationError::ParseError(message) => message.clone(),
+            CompilationError::Lex(error) => error.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for CompilationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.get_message())
+    }
+}
+
+impl Error for CompilationError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CompilationError::Lex(error) => Some(error),
+            _ => None,
         }
     }
 }
 
 pub type CompilationResult<T> = Result<T, CompilationError>;
 
+/*
+    Error of the whole assembly of a program, it tells the line where it stopped and
+    has the error of the instruction or directive as source
+*/
+#[derive(Debug)]
+#[cfg_attr(
+    feature = "serialize",
+    derive(Serialize, serde::Deserialize),
+    serde(tag = "type", content = "value")
+)]
+pub enum AssembleError {
+    Instruction {
+        line_index: usize,
+        source: CompilationError,
+    },
+    Directive {
+        line_index: usize,
+        source: CompilationError,
+    },
+    DuplicateLabel {
+        line_index: usize,
+        name: String,
+    },
+    OddAddress {
+        line_index: usize,
+        address: usize,
+    },
+    InvalidOrg {
+        line_index: usize,
+        value: String,
+        source: CompilationError,
+    },
+    OrgBelowAddress {
+        line_index: usize,
+        address: usize,
+        previous_address: usize,
+    },
+    /// The number of elements of a ds or dcb directive
+    InvalidLength {
+        line_index: usize,
+        directive: String,
+        source: CompilationError,
+    },
+    /// The size of the instruction kept changing while placing the program
+    UnstableLayout {
+        line_index: usize,
+    },
+}
+
+impl AssembleError {
+    pub fn get_line_index(&self) -> usize {
+        match self {
+            AssembleError::Instruction { line_index, .. }
+            | AssembleError::Directive { line_index, .. }
+            | AssembleError::DuplicateLabel { line_index, .. }
+            | AssembleError::OddAddress { line_index, .. }
+            | AssembleError::InvalidOrg { line_index, .. }
+            | AssembleError::OrgBelowAddress { line_index, .. }
+            | AssembleError::InvalidLength { line_index, .. }
+            | AssembleError::UnstableLayout { line_index } => *line_index,
+        }
+    }
+    /// The stable code of the error, see the diagnostic module
+    pub fn get_code(&self) -> DiagnosticCode {
+        match self {
+            AssembleError::Instruction { .. } => DiagnosticCode::InvalidInstruction,
+            AssembleError::Directive { .. } => DiagnosticCode::InvalidDirective,
+            AssembleError::DuplicateLabel { .. } => DiagnosticCode::DuplicateLabel,
+            AssembleError::OddAddress { .. } => DiagnosticCode::OddAddress,
+            AssembleError::InvalidOrg { .. } => DiagnosticCode::InvalidOrg,
+            AssembleError::OrgBelowAddress { .. } => DiagnosticCode::OrgBelowAddress,
+            AssembleError::InvalidLength { .. } => DiagnosticCode::InvalidLength,
+            AssembleError::UnstableLayout { .. } => DiagnosticCode::UnstableLayout,
+        }
+    }
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AssembleError::Instruction { line_index, source } => write!(f, "{}; at line {}", source, line_index),
+            AssembleError::Directive { line_index, source } => {
+                write!(f, "Error parsing directive at line {}: {}", line_index, source)
+            }
+            AssembleError::DuplicateLabel { line_index, name } => {
+                write!(f, "Label {} already defined at line {}", name, line_index)
+            }
+            AssembleError::OddAddress { line_index, address } => write!(
+                f,
+                "Instruction address must not be odd, maybe you defined an odd number of byte constants in memory somewhere? found {} at line {}",
+                address, line_index
+            ),
+            AssembleError::InvalidOrg { line_index, value, source } => {
+                write!(f, "Invalid hex ORG address: {}; at line {}, {}", value, line_index, source)
+            }
+            AssembleError::OrgBelowAddress { line_index, address, previous_address } => write!(
+                f,
+                "The address of the ORG directive ({}) must be greater than the previous address ({}); at line {}",
+                address, previous_address, line_index
+            ),
+            AssembleError::InvalidLength { line_index, directive, source } => write!(
+                f,
+                "Invalid number of bytes for {} directive at line {}, {}",
+                directive, line_index, source
+            ),
+            AssembleError::UnstableLayout { line_index } => write!(
+                f,
+                "The size of the instruction at line {} kept changing after {} passes",
+                line_index, MAX_LAYOUT_PASSES
+            ),
+        }
+    }
+}
+
+impl Error for AssembleError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            AssembleError::Instruction { source, .. }
+            | AssembleError::Directive { source, .. }
+            | AssembleError::InvalidOrg { source, .. }
+            | AssembleError::InvalidLength { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<AssembleError> for String {
+    fn from(error: AssembleError) -> Self {
+        error.to_string()
+    }
+}
+
 impl fmt::Debug for InstructionLine {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("InstructionLine")
@@ -72,25 +256,58 @@ impl fmt::Debug for InstructionLine {
 }
 
 impl Compiler {
-    pub fn new(lines: &[ParsedLine]) -> Result<Compiler, String> {
+    pub fn new(lines: &[ParsedLine]) -> Result<Compiler, AssembleError> {
+        Compiler::new_with_model(lines, CpuModel::default())
+    }
+    pub fn new_with_model(lines: &[ParsedLine], cpu_model: CpuModel) -> Result<Compiler, AssembleError> {
+        Compiler::new_with_layout(lines, cpu_model, Layout::default())
+    }
+    /// Places the program with the layout, every instruction takes the bytes of its encoding
+    pub fn new_with_layout(lines: &[ParsedLine], cpu_model: CpuModel, layout: Layout) -> Result<Compiler, AssembleError> {
+        //the sizes depend on the addresses of the labels, which depend on the sizes
+        let mut sizes: Vec<usize> = vec![];
+        let mut changed = 0;
+        for _ in 0..MAX_LAYOUT_PASSES {
+            let compiler = Compiler::place(lines, cpu_model, layout.clone(), sizes.clone())?;
+            let placed = compiler.get_instruction_sizes(lines);
+            match (0..lines.len()).find(|i| placed.get(*i) != sizes.get(*i)) {
+                Some(i) => changed = lines[i].line_index,
+                None => return Ok(compiler),
+            }
+            sizes = placed;
+        }
+        Err(AssembleError::UnstableLayout { line_index: changed })
+    }
+    fn place(
+        lines: &[ParsedLine],
+        cpu_model: CpuModel,
+        layout: Layout,
+        instruction_sizes: Vec<usize>,
+    ) -> Result<Compiler, AssembleError> {
         let mut pre_interpreter = Compiler {
-            labels: HashMap::new(),
+            labels: LabelTable::new(),
+            symbols: SymbolTable::new(),
             line_addresses: Vec::new(),
+            end_address: 0,
             directives: Vec::new(),
             instructions: Vec::new(),
             start_address: 0,
             final_instrucion_address: 0,
+            cpu_model,
+            layout,
+            instruction_sizes,
         };
         pre_interpreter.load(lines)?;
         Ok(pre_interpreter)
     }
 
+    #[cfg(feature = "std")]
     pub fn debug_print(&self) {
         if self.labels.is_empty() {
             println!("\n[NO LABELS]\n");
         } else {
             println!("\n[LABELS]\n");
-            for (key, value) in &self.labels {
+            for (key, value) in self.labels.iter() {
                 println!("{}: {:?}", key, value);
             }
         }
@@ -100,6 +317,10 @@ impl Compiler {
         }
     }
 
+    pub fn get_cpu_model(&self) -> CpuModel {
+        self.cpu_model
+    }
+
     pub fn get_start_address(&self) -> usize {
         self.start_address
     }
@@ -112,16 +333,51 @@ impl Compiler {
         &self.instructions
     }
 
-    pub fn get_labels_map(&self) -> &HashMap<String, Label> {
+    pub fn get_labels_map(&self) -> &LabelTable {
         &self.labels
     }
+    /// The labels and equs of the program with their values
+    pub fn get_symbol_table(&self) -> &SymbolTable {
+        &self.symbols
+    }
     pub fn get_directives(&self) -> &Vec<Directive> {
         &self.directives
     }
-    fn load(&mut self, lines: &[ParsedLine]) -> Result<(), String> {
+    /// Address where each lexed line starts, in the same order as the lines
+    pub fn get_line_addresses(&self) -> &[usize] {
+        &self.line_addresses
+    }
+    pub fn get_end_address(&self) -> usize {
+        self.end_address
+    }
+    /// The lines of the instructions and the addresses of the lines, see the source_map module
+    pub fn get_source_map(&self) -> SourceMap {
+        SourceMap::new(&self.instructions)
+    }
+    /// The size of every line, in the same order as the lines
+    fn get_instruction_sizes(&self, lines: &[ParsedLine]) -> Vec<usize> {
+        let mut instructions = self.instructions.iter();
+        lines
+            .iter()
+            .map(|line| match &line.parsed {
+                LexedLine::Instruction { .. } => instructions
+                    .next()
+                    .map(|instruction| get_encoded_size(&instruction.instruction))
+                    .unwrap_or(0),
+                _ => 0,
+            })
+            .collect()
+    }
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(lines = lines.len())))]
+    fn load(&mut self, lines: &[ParsedLine]) -> Result<(), AssembleError> {
         self.parse_labels_and_addresses(lines)?; //has side effect, place before the parsing
         self.parse_instruction_lines(lines)?;
-        self.start_address = match self.labels.get("START") {
+        //the label of the END directive, like END START in Easy68K
+        let entry = match get_assembled_lines(lines).last().map(|line| &line.parsed) {
+            Some(LexedLine::Directive { name, args, .. }) if name == "end" && args.len() > 1 => args[1].as_str(),
+            _ => "START",
+        };
+        self.start_address = match self.labels.get(entry) {
             Some(label) => {
                 //find the closest instruction after the label
                 self.instructions
@@ -143,58 +399,348 @@ impl Compiler {
         Ok(())
     }
 
-    fn parse_instruction_lines(&mut self, lines: &[ParsedLine]) -> Result<(), String> {
-        for (i, line) in lines.iter().enumerate() {
-            match &line.parsed {
-                LexedLine::Instruction {
-                    name,
-                    operands,
-                    size,
-                } => {
-                    let parsed_operands = operands
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
+    fn parse_instruction_lines(&mut self, lines: &[ParsedLine]) -> Result<(), AssembleError> {
+        for (i, line) in get_assembled_lines(lines).iter().enumerate() {
+            if let LexedLine::Instruction { name, operands, size } = &line.parsed {
+                let instruction = match name.as_str() {
+                    //control registers are not normal operands, so they are parsed separately
+                    "movec" => self.parse_movec(operands),
+                    "move" | "andi" | "ori" | "eori"
+                        if operands.iter().any(|operand| operand.get_special_register().is_some()) =>
+                    {
+                        self.parse_status_instruction(name, operands, line)
+                    }
+                    "rtr" => match operands.is_empty() {
+                        true => Ok(Instruction::RTR),
+                        false => Err(CompilationError::ParseError("RTR has no operands".to_string())),
+                    },
+                    "bftst" | "bfextu" | "bfexts" | "bfffo" | "bfchg" | "bfclr" | "bfset"
+                    | "bfins" => self.parse_bitfield(name, operands, line),
+                    "divsl" | "divul" => self.parse_long_mul_div(name, operands, line),
+                    "fmove" | "fadd" | "fsub" | "fmul" | "fdiv" | "fcmp" => {
+                        self.parse_float_instruction(name, operands, size, line)
+                    }
+                    "muls" | "mulu" | "divs" | "divu" if *size == LexedSize::Long => {
+                        self.parse_long_mul_div(name, operands, line)
+                    }
+                    _ => operands
                         .iter()
                         .map(|x| self.parse_operand(x, line))
-                        .collect::<CompilationResult<Vec<Operand>>>();
-                    match parsed_operands {
-                        Ok(ops) => {
-                            let instruction = self.parse_instruction(name, ops, size);
-                            match instruction {
-                                Ok(ins) => {
-                                    let address = self.line_addresses[i];
-                                    if address & 0x1 != 0 {
-                                        return Err(format!(
-                                            "Instruction address must not be odd, maybe you defined an odd number of byte constants in memory somewhere? found {} at line {}",
-                                            address, line.line_index
-                                        ));
-                                    }
-                                    let instuction_line = InstructionLine {
-                                        instruction: ins,
-                                        address: self.line_addresses[i],
-                                        parsed_line: line.clone(),
-                                    };
-                                    self.instructions.push(instuction_line);
-                                }
-                                Err(e) => {
-                                    return Err(format!(
-                                        "{}; at line {}",
-                                        e.get_message(),
-                                        line.line_index
-                                    )
-                                        .to_string());
-                                }
-                            }
-                        }
-                        Err(e) => {
-                            return Err(format!("{}; at line {}", e.get_message(), line.line_index)
-                                .to_string());
+                        .collect::<CompilationResult<Vec<Operand>>>()
+                        .and_then(|ops| self.parse_instruction(name, ops, size)),
+                };
+                match instruction {
+                    Ok(ins) => {
+                        let address = self.line_addresses[i];
+                        if address & 0x1 != 0 {
+                            return Err(AssembleError::OddAddress {
+                                line_index: line.line_index,
+                                address,
+                            });
                         }
+                        let instuction_line = InstructionLine {
+                            instruction: ins,
+                            address: self.line_addresses[i],
+                            parsed_line: line.clone(),
+                        };
+                        self.instructions.push(instuction_line);
+                    }
+                    Err(e) => {
+                        return Err(AssembleError::Instruction {
+                            line_index: line.line_index,
+                            source: e,
+                        });
                     }
                 }
-                _ => {}
             }
         }
         Ok(())
     }
+    fn parse_movec(&mut self, operands: &[LexedOperand]) -> CompilationResult<Instruction> {
+        self.cpu_model
+            .verify_instruction("movec")
+            .map_err(CompilationError::Raw)?;
+        let (control, register, to_control) = match operands {
+            [LexedOperand::Absolute(control), register] => (control, register, false),
+            [register, LexedOperand::Absolute(control)] => (control, register, true),
+            _ => {
+                return Err(CompilationError::InvalidAddressingMode(
+                    "Invalid operands for MOVEC".to_string(),
+                ));
+            }
+        };
+        let control_register = control.parse().map_err(CompilationError::ParseError)?;
+        let register = match register {
+            LexedOperand::Register(register_type, register_name) => {
+                self.parse_register(register_type, register_name)?
+            }
+            _ => {
+                return Err(CompilationError::InvalidAddressingMode(
+                    "MOVEC requires a Dn/An register".to_string(),
+                ));
+            }
+        };
+        Ok(Instruction::MOVEC {
+            register,
+            control_register,
+            to_control,
+        })
+    }
+    /// MOVE and ANDI/ORI/EORI to or from SR and CCR, and MOVE to or from USP
+    fn parse_status_instruction(
+        &mut self,
+        name: &str,
+        operands: &[LexedOperand],
+        line: &ParsedLine,
+    ) -> CompilationResult<Instruction> {
+        let invalid = || {
+            CompilationError::InvalidAddressingMode(format!(
+                "Invalid operands for {} with a status register",
+                name.to_uppercase()
+            ))
+        };
+        let (source, dest) = match operands {
+            [source, dest] => (source, dest),
+            _ => return Err(invalid()),
+        };
+        match (name, source.get_special_register(), dest.get_special_register()) {
+            ("move", Some(usp), None) | ("move", None, Some(usp)) if usp == "usp" => {
+                let (register, to_usp) = match dest.get_special_register() {
+                    Some(_) => (source, true),
+                    None => (dest, false),
+                };
+                let register = match self.parse_operand(register, line)? {
+                    Operand::Register(register @ RegisterOperand::Address(_)) => register,
+                    _ => return Err(invalid()),
+                };
+                Ok(Instruction::MOVEUSP { register, to_usp })
+            }
+            ("move", None, Some(target)) => Ok(Instruction::MOVEtoSR(
+                self.parse_operand(source, line)?,
+                target.parse().map_err(CompilationError::ParseError)?,
+            )),
+            ("move", Some(target), None) => {
+                let target: StatusRegister = target.parse().map_err(CompilationError::ParseError)?;
+                if target == StatusRegister::CCR {
+                    self.cpu_model
+                        .verify_feature(CpuFeature::VectorBaseRegister, "move from ccr")
+                        .map_err(CompilationError::Raw)?;
+                }
+                Ok(Instruction::MOVEfromSR(self.parse_operand(dest, line)?, target))
+            }
+            (_, None, Some(target)) if target != "usp" => {
+                let target = target.parse().map_err(CompilationError::ParseError)?;
+                let value = match self.parse_operand(source, line)? {
+                    Operand::Immediate(value) => value as u16,
+                    _ => return Err(invalid()),
+                };
+                Ok(match name {
+                    "andi" => Instruction::ANDItoSR(value, target),
+                    "ori" => Instruction::ORItoSR(value, target),
+                    _ => Instruction::EORItoSR(value, target),
+                })
+            }
+            _ => Err(invalid()),
+        }
+    }
+    fn parse_long_mul_div(
+        &mut self,
+        name: &str,
+        operands: &[LexedOperand],
+        line: &ParsedLine,
+    ) -> CompilationResult<Instruction> {
+        self.cpu_model
+            .verify_feature(CpuFeature::LongMultiplyDivide, &format!("{}.l", name))
+            .map_err(CompilationError::Raw)?;
+        let (source, dest) = match operands {
+            [source, dest] => (self.parse_operand(source, line)?, dest),
+            _ => {
+                return Err(CompilationError::InvalidAddressingMode(format!(
+                    "Invalid operands for {}",
+                    name
+                )));
+            }
+        };
+        let (high, low) = match dest {
+            LexedOperand::RegisterPair(high, low) => (
+                Some(self.parse_register(&LexedRegisterType::Data, high)?),
+                self.parse_register(&LexedRegisterType::Data, low)?,
+            ),
+            _ => {
+                let register = self.parse_operand(dest, line)?;
+                (None, self.extract_register(register)?)
+            }
+        };
+        let sign = match name {
+            "muls" | "divs" | "divsl" => Sign::Signed,
+            _ => Sign::Unsigned,
+        };
+        Ok(match name {
+            "muls" | "mulu" => Instruction::MULxL {
+                source,
+                low,
+                high,
+                sign,
+            },
+            _ => Instruction::DIVxL {
+                source,
+                quotient: low,
+                remainder: high,
+                sign,
+                long_dividend: high.is_some() && !name.ends_with('l'),
+            },
+        })
+    }
+    fn parse_float_instruction(
+        &mut self,
+        name: &str,
+        operands: &[LexedOperand],
+        size: &LexedSize,
+        line: &ParsedLine,
+    ) -> CompilationResult<Instruction> {
+        let format = match size {
+            LexedSize::Byte => FloatFormat::Byte,
+            LexedSize::Word => FloatFormat::Word,
+            LexedSize::Long => FloatFormat::Long,
+            LexedSize::Single => FloatFormat::Single,
+            LexedSize::Double => FloatFormat::Double,
+            LexedSize::Extended | LexedSize::Unspecified => FloatFormat::Extended,
+            LexedSize::Unknown => {
+                return Err(CompilationError::ParseError(format!(
+                    "Invalid size: {:?}",
+                    size
+                )));
+            }
+        };
+        let (source, dest) = match operands {
+            [source, dest] => (
+                self.parse_float_operand(source, format, line)?,
+                self.parse_float_operand(dest, format, line)?,
+            ),
+            _ => {
+                return Err(CompilationError::InvalidAddressingMode(format!(
+                    "Invalid operands for {}",
+                    name
+                )));
+            }
+        };
+        if name == "fmove" {
+            return Ok(Instruction::FMOVE(source, dest, format));
+        }
+        let dest = match dest {
+            FloatOperand::Register(register) => register,
+            _ => {
+                return Err(CompilationError::InvalidAddressingMode(format!(
+                    "Destination of {} must be a floating point register",
+                    name
+                )));
+            }
+        };
+        Ok(match name {
+            "fadd" => Instruction::FADD(source, dest, format),
+            "fsub" => Instruction::FSUB(source, dest, format),
+            "fmul" => Instruction::FMUL(source, dest, format),
+            "fdiv" => Instruction::FDIV(source, dest, format),
+            _ => Instruction::FCMP(source, dest, format),
+        })
+    }
+    fn parse_float_operand(
+        &mut self,
+        operand: &LexedOperand,
+        format: FloatFormat,
+        line: &ParsedLine,
+    ) -> CompilationResult<FloatOperand> {
+        match operand {
+            LexedOperand::Register(LexedRegisterType::Float, name) => match name[2..].parse() {
+                Ok(register) => Ok(FloatOperand::Register(register)),
+                Err(_) => Err(CompilationError::ParseError(format!(
+                    "Invalid fp register name: {}",
+                    name
+                ))),
+            },
+            LexedOperand::Immediate(value) => match (format.to_size(), value[1..].parse::<f64>()) {
+                (Some(size), _) if format != FloatFormat::Single => {
+                    let value = self.parse_immediate(value)?;
+                    Ok(FloatOperand::Immediate(sign_extend_to_long(value, size) as f64))
+                }
+                (_, Ok(value)) => Ok(FloatOperand::Immediate(value)),
+                (_, Err(_)) => Ok(FloatOperand::Immediate(self.parse_immediate(value)? as i32 as f64)),
+            },
+            _ => Ok(FloatOperand::Effective(self.parse_operand(operand, line)?)),
+        }
+    }
+    fn parse_bitfield(
+        &mut self,
+        name: &str,
+        operands: &[LexedOperand],
+        line: &ParsedLine,
+    ) -> CompilationResult<Instruction> {
+        self.cpu_model
+            .verify_instruction(name)
+            .map_err(CompilationError::Raw)?;
+        let operation: BitfieldOperation = name.parse().map_err(CompilationError::ParseError)?;
+        let (field, register) = match (operation, operands) {
+            (BitfieldOperation::Insert, [register, field]) => (field, Some(register)),
+            (
+                BitfieldOperation::ExtractUnsigned
+                | BitfieldOperation::ExtractSigned
+                | BitfieldOperation::FindFirstOne,
+                [field, register],
+            ) => (field, Some(register)),
+            (
+                BitfieldOperation::Test
+                | BitfieldOperation::Change
+                | BitfieldOperation::Clear
+                | BitfieldOperation::Set,
+                [field],
+            ) => (field, None),
+            _ => {
+                return Err(CompilationError::InvalidAddressingMode(format!(
+                    "Invalid operands for {}",
+                    name
+                )));
+            }
+        };
+        let register = match register {
+            Some(register) => {
+                let register = self.parse_operand(register, line)?;
+                Some(self.extract_register(register)?)
+            }
+            None => None,
+        };
+        match field {
+            LexedOperand::Bitfield {
+                operand,
+                offset,
+                width,
+            } => Ok(Instruction::BFx {
+                operation,
+                target: self.parse_operand(operand, line)?,
+                offset: self.parse_bitfield_value(offset)?,
+                width: self.parse_bitfield_value(width)?,
+                register,
+            }),
+            _ => Err(CompilationError::InvalidAddressingMode(format!(
+                "{} requires a bitfield operand",
+                name
+            ))),
+        }
+    }
+    fn parse_bitfield_value(&mut self, value: &str) -> CompilationResult<BitfieldValue> {
+        match LexedRegisterType::from_string(value) {
+            Ok(LexedRegisterType::Data) => Ok(BitfieldValue::Register(
+                self.parse_register(&LexedRegisterType::Data, value)?,
+            )),
+            _ => match self.resolve_expression(value) {
+                Ok(value) => Ok(BitfieldValue::Immediate(value as u32)),
+                Err(_) => Err(CompilationError::ParseError(format!(
+                    "Invalid bitfield value: {}",
+                    value
+                ))),
+            },
+        }
+    }
     fn parse_instruction(
         &self,
         name: &String,
@@ -202,6 +748,9 @@ impl Compiler {
         size: &LexedSize,
     ) -> CompilationResult<Instruction> {
         //TODO add better error logging
+        self.cpu_model
+            .verify_instruction(name)
+            .map_err(CompilationError::Raw)?;
         if operands.len() == 2 {
             let (op1, op2) = (operands.remove(0), operands.remove(0));
             let parsed = match name.as_str() {
@@ -215,12 +764,12 @@ impl Compiler {
                 },
                 "movem" => {
                     let (mut register_mask, target, direction) = match (op1, op2) {
-                        (Operand::Immediate(mask), op2) => (mask as u16, op2, TargetDirection::ToMemory),
+                        (Operand::RegisterList(mask), op2) => (mask, op2, TargetDirection::ToMemory),
                         (Operand::Register(op), op2) => {
                             let index = op.to_index();
                             (1 << index, op2, TargetDirection::ToMemory)
                         }
-                        (op1, Operand::Immediate(mask)) => (mask as u16, op1, TargetDirection::FromMemory),
+                        (op1, Operand::RegisterList(mask)) => (mask, op1, TargetDirection::FromMemory),
                         (op1, Operand::Register(op)) => {
                             let index = op.to_index();
                             (1 << index, op1, TargetDirection::FromMemory)
@@ -397,6 +946,7 @@ impl Compiler {
                     self.get_size(size, Size::Word)?,
                 ),
                 "lea" => Instruction::LEA(op1, self.extract_register(op2)?),
+                "chk" => Instruction::CHK(op1, self.extract_register(op2)?),
                 "ror" => Instruction::ROd(
                     op1,
                     op2,
@@ -431,6 +981,8 @@ impl Compiler {
                 "link" => {
                     Instruction::LINK(self.extract_register(op1)?, self.extract_immediate(&op2)?)
                 }
+                "moves" => Instruction::MOVES(op1, op2, self.get_size(size, Size::Word)?),
+                "linef" => Instruction::LINEF(self.extract_immediate(&op1)? as u16, Some(op2)),
                 _ => {
                     return Err(CompilationError::Raw(format!(
                         "Unknown instruction {}",
@@ -445,6 +997,13 @@ impl Compiler {
                 "clr" => Instruction::CLR(op, self.get_size(size, Size::Word)?),
                 "neg" => Instruction::NEG(op, self.get_size(size, Size::Word)?),
                 "pea" => Instruction::PEA(op),
+                //memory is shifted by one bit
+                "lsl" => Instruction::LSd(Operand::Immediate(1), op, ShiftDirection::Left, self.get_size(size, Size::Word)?),
+                "lsr" => Instruction::LSd(Operand::Immediate(1), op, ShiftDirection::Right, self.get_size(size, Size::Word)?),
+                "asl" => Instruction::ASd(Operand::Immediate(1), op, ShiftDirection::Left, self.get_size(size, Size::Word)?),
+                "asr" => Instruction::ASd(Operand::Immediate(1), op, ShiftDirection::Right, self.get_size(size, Size::Word)?),
+                "rol" => Instruction::ROd(Operand::Immediate(1), op, ShiftDirection::Left, self.get_size(size, Size::Word)?),
+                "ror" => Instruction::ROd(Operand::Immediate(1), op, ShiftDirection::Right, self.get_size(size, Size::Word)?),
                 "ext" => Instruction::EXT(
                     self.extract_register(op)?,
                     //from
@@ -471,6 +1030,11 @@ impl Compiler {
                 "unlk" => Instruction::UNLK(self.extract_register(op)?),
                 "extb" => Instruction::EXT(self.extract_register(op)?, Size::Byte, Size::Long),
                 "tst" => Instruction::TST(op, self.get_size(size, Size::Word)?),
+                "fbeq" | "fbne" | "fbgt" | "fbngt" | "fbge" | "fbnge" | "fblt" | "fbnlt" | "fble"
+                | "fbnle" | "fbgl" | "fbngl" | "fbgle" | "fbngle" | "fbor" | "fbun" | "fbt" | "fbf" => {
+                    let address = self.extract_address(&op)?;
+                    Instruction::FBcc(address, name[2..].parse().map_err(CompilationError::ParseError)?)
+                }
                 "bcc" | "bcs" | "beq" | "bne" | "blt" | "ble" | "bgt" | "bge" | "blo" | "bls"
                 | "bhi" | "bhs" | "bpl" | "bmi" | "bvc" | "bvs" => {
                     let address = self.extract_address(&op)?;
@@ -507,6 +1071,8 @@ impl Compiler {
                     }
                 }
                 "swap" => Instruction::SWAP(self.extract_register(op)?),
+                "rtd" => Instruction::RTD(self.extract_immediate(&op)? as i16),
+                "linef" => Instruction::LINEF(self.extract_immediate(&op)? as u16, None),
                 //not sure if the default is word
                 "not" => Instruction::NOT(op, self.get_size(size, Size::Word)?),
                 "jsr" => Instruction::JSR(op),
@@ -532,6 +1098,11 @@ impl Compiler {
         } else if operands.is_empty() {
             let result = match name.as_str() {
                 "rts" => Instruction::RTS,
+                "rte" => Instruction::RTE,
+                "trapv" => Instruction::TRAPV,
+                "illegal" => Instruction::ILLEGAL,
+                "simhalt" => Instruction::SIMHALT,
+                "nop" => Instruction::NOP,
                 _ => {
                     return Err(CompilationError::Raw(format!(
                         "Unknown instruction {}",
@@ -608,7 +1179,37 @@ impl Compiler {
             ))),
         }
     }
-    fn parse_register(&mut self, register_type: &LexedRegisterType, register_name: &String) -> CompilationResult<RegisterOperand> {
+    fn parse_index_register(&mut self, operand: &LexedOperand, line: &ParsedLine) -> CompilationResult<IndexRegister> {
+        match operand {
+            LexedOperand::ScaledRegister(register_type, register_name, size, scale) => {
+                let (register, size) = self.parse_register_with_size(
+                    &LexedOperand::RegisterWithSize(register_type.clone(), register_name.clone(), size.clone()),
+                    line,
+                )?;
+                self.cpu_model
+                    .verify_index_scale(register_name, *scale)
+                    .map_err(CompilationError::InvalidAddressingMode)?;
+                Ok(IndexRegister { register, scale: *scale, size })
+            }
+            _ => {
+                let (register, size) = self.parse_register_with_size(operand, line)?;
+                Ok(IndexRegister { register, scale: 1, size })
+            }
+        }
+    }
+    fn parse_displacement(&self, displacement: Option<&String>) -> CompilationResult<i32> {
+        match displacement {
+            Some(displacement) => match self.resolve_expression(displacement) {
+                Ok(value) => Ok(value as i32),
+                Err(_) => Err(CompilationError::ParseError(format!(
+                    "Invalid displacement: {}",
+                    displacement
+                ))),
+            },
+            None => Ok(0),
+        }
+    }
+    fn parse_register(&mut self, register_type: &LexedRegisterType, register_name: &str) -> CompilationResult<RegisterOperand> {
         match register_type {
             LexedRegisterType::Address => match register_name[1..].parse() {
                 Ok(reg) => Ok(RegisterOperand::Address(reg)),
@@ -625,6 +1226,10 @@ impl Compiler {
                 ))),
             },
             LexedRegisterType::SP => Ok(RegisterOperand::Address(7)),
+            LexedRegisterType::Float => Err(CompilationError::InvalidAddressingMode(format!(
+                "Floating point register {} can only be used by FPU instructions",
+                register_name
+            ))),
         }
     }
     fn parse_operand(
@@ -644,8 +1249,8 @@ impl Compiler {
                     value
                 ))),
             },
-            LexedOperand::Label(label) => match self.labels.get(label) {
-                Some(label) => Ok(Operand::Absolute(label.address)),
+            LexedOperand::Label(label) => match self.symbols.get_address(label) {
+                Some(address) => Ok(Operand::Absolute(address)),
                 None => Err(CompilationError::ParseError(format!(
                     "Label \"{}\" not found",
                     label
@@ -658,7 +1263,7 @@ impl Compiler {
                 let offset = if offset.trim() == "" {
                     0
                 } else {
-                    match parse_absolute_expression(offset, &self.labels) {
+                    match self.resolve_expression(offset) {
                         Ok(offset) => sign_extend_to_long(offset as u32, Size::Word),
                         Err(_) => {
                             return Err(CompilationError::ParseError(format!(
@@ -677,8 +1282,15 @@ impl Compiler {
                 let offset = if offset.is_empty() {
                     0
                 } else {
-                    match parse_absolute_expression(offset, &self.labels) {
-                        Ok(offset) => sign_extend_to_long(offset as u32, Size::Byte),
+                    match self.resolve_expression(offset) {
+                        Ok(offset) if (i8::MIN as i64..=i8::MAX as i64).contains(&offset) => offset as i32,
+                        //the byte displacement is not truncated
+                        Ok(offset) => {
+                            return Err(CompilationError::ParseError(format!(
+                                "Invalid offset: {}, must be between -128 and 127",
+                                offset
+                            )));
+                        }
                         Err(_) => {
                             return Err(CompilationError::ParseError(format!(
                                 "Invalid offset: {}",
@@ -696,7 +1308,7 @@ impl Compiler {
                 }
                 let first = self.parse_operand(&operands[0], line)?;
                 let first = self.extract_register(first)?;
-                let second = self.parse_register_with_size(&operands[1], line)?;
+                let index = self.parse_index_register(&operands[1], line)?;
                 match first {
                     RegisterOperand::Data(_) => {
                         Err(CompilationError::InvalidAddressingMode(
@@ -707,14 +1319,36 @@ impl Compiler {
                         Ok(Operand::IndirectIndex {
                             offset,
                             base: first,
-                            index: IndexRegister {
-                                register: second.0,
-                                size: second.1,
-                            },
+                            index,
                         })
                     }
                 }
             }
+            LexedOperand::MemoryIndirect { inner, outer } => {
+                self.cpu_model
+                    .verify_feature(CpuFeature::MemoryIndirect, "([bd,An],Xn,od)")
+                    .map_err(CompilationError::InvalidAddressingMode)?;
+                let parts = LexedOperand::split_memory_indirect(inner, outer)
+                    .map_err(CompilationError::Lex)?;
+                let base = match parts.base {
+                    Some(base) => {
+                        let base = self.parse_operand(base, line)?;
+                        Some(self.extract_register(base)?)
+                    }
+                    None => None,
+                };
+                let index = match parts.index {
+                    Some(index) => Some(self.parse_index_register(index, line)?),
+                    None => None,
+                };
+                Ok(Operand::MemoryIndirect {
+                    base,
+                    base_displacement: self.parse_displacement(parts.base_displacement)?,
+                    index,
+                    post_indexed: parts.post_indexed,
+                    outer_displacement: self.parse_displacement(parts.outer_displacement)?,
+                })
+            }
             LexedOperand::Indirect(operand) => {
                 let parsed_operand = self.parse_operand(operand, line)?;
                 let parsed_operand = self.extract_register(parsed_operand)?;
@@ -758,7 +1392,7 @@ impl Compiler {
                     e.get_message()
                 ))),
             },
-            LexedOperand::RegisterRange { mask } => Ok(Operand::Immediate(*mask as u32)),
+            LexedOperand::RegisterRange { mask } => Ok(Operand::RegisterList(*mask)),
             _ => Err(CompilationError::ParseError(format!(
                 "Invalid operand: {:?}",
                 operand
@@ -770,8 +1404,11 @@ impl Compiler {
         self.parse_absolute(&num[1..])
     }
 
+    fn resolve_expression(&self, expression: &str) -> Result<i64, String> {
+        Ok(self.symbols.resolve(expression)?)
+    }
     fn parse_absolute(&self, num: &str) -> CompilationResult<u32> {
-        match parse_absolute_expression(num, &self.labels) {
+        match self.resolve_expression(num) {
             Ok(absolute) => Ok(absolute as u32),
             Err(e) => Err(CompilationError::ParseError(e)),
         }
@@ -783,22 +1420,23 @@ impl Compiler {
     }
     fn parse_directive(
         &self,
-        name: &String,
+        name: &str,
         size: &LexedSize,
-        args: &Vec<String>,
+        args: &[String],
         address: usize,
     ) -> CompilationResult<Directive> {
-        match name.as_str() {
+        match name {
             "dc" => {
                 let mut data: Vec<u8> = vec![];
 
                 for arg in args[1..].iter() {
                     match arg {
-                        _ if arg.starts_with('\'') && arg.ends_with('\'') => {
+                        _ if arg.len() >= 2 && arg.starts_with('\'') && arg.ends_with('\'') => {
                             let string_bytes = parse_string_into_padded_bytes(
                                 &arg[1..arg.len() - 1],
                                 size.to_bytes_word_default() as usize,
-                            );
+                            )
+                            .map_err(|_| CompilationError::Raw("Invalid size for DC directive".to_string()))?;
                             data.extend_from_slice(&string_bytes);
                         }
                         _ => {
@@ -883,65 +1521,78 @@ impl Compiler {
             _ => Ok(Directive::Other),
         }
     }
-    fn get_next_address(&self, line: &ParsedLine, last_address: usize) -> Result<usize, String> {
+    fn get_next_address(&self, index: usize, line: &ParsedLine, last_address: usize) -> Result<usize, AssembleError> {
         let mut next_address = last_address;
         match &line.parsed {
             LexedLine::Directive { args, name, size } => {
+                //a missing argument is reported by the parse of the empty string
+                let first_arg = args.get(1).map(String::as_str).unwrap_or_default();
                 match name.as_str() {
                     "org" => {
-                        let parsed = match self.parse_absolute(&args[1]) {
-                            Ok(value) => value as usize,
+                        let parsed = match self.parse_absolute(first_arg) {
+                            Ok(value) => value as usize + self.layout.offset,
                             Err(e) => {
-                                return Err(format!(
-                                    "Invalid hex ORG address: {}; at line {}, {:?}",
-                                    args[1], line.line_index, e
-                                ));
+                                return Err(AssembleError::InvalidOrg {
+                                    line_index: line.line_index,
+                                    value: first_arg.to_string(),
+                                    source: e,
+                                });
                             }
                         };
                         if parsed < last_address {
-                            return Err(format!("The address of the ORG directive ({}) must be greater than the previous address ({}); at line {}", parsed, last_address, line.line_index));
+                            return Err(AssembleError::OrgBelowAddress {
+                                line_index: line.line_index,
+                                address: parsed - self.layout.offset,
+                                previous_address: last_address - self.layout.offset,
+                            });
                         }
                         next_address = parsed;
                         //align at 2 bytes intervals
                         //TODO should i align here?
-                        if next_address % 2 != 0 {
+                        if !next_address.is_multiple_of(2) {
                             next_address += 1;
                         }
                     }
-                    "ds" => match self.parse_absolute(&args[1]) {
+                    "ds" => match self.parse_absolute(first_arg) {
                         Ok(bytes) => {
                             next_address = last_address
                                 + (bytes * size.to_bytes_word_default() as u32) as usize;
                         }
                         Err(e) => {
-                            return Err(format!(
-                                "Invalid number of bytes for DS directive at line {}, {:?}",
-                                line.line_index, e
-                            ));
+                            return Err(AssembleError::InvalidLength {
+                                line_index: line.line_index,
+                                directive: "DS".to_string(),
+                                source: e,
+                            });
                         }
                     },
-                    "dcb" => match self.parse_absolute(&args[1]) {
+                    "dcb" => match self.parse_absolute(first_arg) {
                         Ok(bytes) => {
                             next_address = last_address
                                 + (bytes * size.to_bytes_word_default() as u32) as usize;
                         }
                         Err(e) => {
-                            return Err(format!(
-                                "Invalid number of bytes for dcb directive at line {}, {:?}",
-                                line.line_index, e
-                            ));
+                            return Err(AssembleError::InvalidLength {
+                                line_index: line.line_index,
+                                directive: "dcb".to_string(),
+                                source: e,
+                            });
                         }
                     },
                     "dc" => {
                         next_address = last_address;
                         for arg in args[1..].iter() {
                             match arg {
-                                _ if arg.starts_with('\'') && arg.ends_with('\'') => {
+                                _ if arg.len() >= 2 && arg.starts_with('\'') && arg.ends_with('\'') => {
                                     next_address += parse_string_into_padded_bytes(
                                         &arg[1..arg.len() - 1],
                                         size.to_bytes_word_default() as usize,
                                     )
-                                        .len();
+                                    .map_err(|_| AssembleError::Directive {
+                                        line_index: line.line_index,
+                                        source: CompilationError::Raw("Invalid size for DC directive".to_string()),
+                                    })?
+                                    .len();
                                 }
                                 _ => {
                                     next_address += size.to_bytes_word_default() as usize;
@@ -954,43 +1605,44 @@ impl Compiler {
             }
             LexedLine::Instruction { .. } => {
                 //align at 2 bytes intervals
-                if next_address % 2 != 0 {
+                if !next_address.is_multiple_of(2) {
                     next_address += 1;
                 }
-                next_address += 4;
+                next_address += self.instruction_sizes.get(index).copied().unwrap_or(4);
             }
 
             _ => {}
         }
         Ok(next_address)
     }
-    fn parse_labels_and_addresses(&mut self, lines: &[ParsedLine]) -> Result<(), String> {
-        let mut last_address = 4096; //same as ORG $1000
-        let mut labels: HashMap<String, Label> = HashMap::new();
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
+    fn parse_labels_and_addresses(&mut self, lines: &[ParsedLine]) -> Result<(), AssembleError> {
+        let mut last_address = 4096 + self.layout.offset; //same as ORG $1000
+        let mut symbols = SymbolTable::new();
         let mut directives: Vec<Directive> = Vec::new();
         let mut line_addresses: Vec<usize> = Vec::new();
-        for line in lines.iter() {
+        let assembled = get_assembled_lines(lines);
+        for (i, line) in lines.iter().enumerate() {
             line_addresses.push(last_address);
-            match &line.parsed {
-                LexedLine::Label { name } => {
-                    if labels.contains_key(name) {
-                        return Err(format!(
-                            "Label {} already defined at line {}",
-                            name, line.line_index
-                        ));
-                    }
-                    labels.insert(
-                        name.clone(),
-                        Label {
-                            address: last_address,
-                            name: name.clone(),
-                            line: line.line_index,
-                        },
-                    );
+            if i >= assembled.len() {
+                continue;
+            }
+            if let LexedLine::Label { name, owner, .. } = &line.parsed {
+                let name = get_label_key(name, owner.as_deref());
+                let label = SymbolEntry {
+                    name: name.clone(),
+                    kind: SymbolKind::Label,
+                    value: last_address as i64,
+                    line_index: line.line_index,
+                };
+                if symbols.define(label).is_err() {
+                    return Err(AssembleError::DuplicateLabel {
+                        line_index: line.line_index,
+                        name,
+                    });
                 }
-                _ => {}
             }
-            match self.get_next_address(line, last_address) {
+            match self.get_next_address(i, line, last_address) {
                 Ok(address) => {
                     last_address = address;
                 }
@@ -999,26 +1651,86 @@ impl Compiler {
                 }
             }
         }
+        //the symbols of the other modules
+        for line in assembled {
+            match &line.parsed {
+                LexedLine::Directive { name, args, .. } if name == "xref" => {
+                    for symbol in &args[1..] {
+                        let address = match self.layout.externals.get(symbol) {
+                            Some(address) => *address,
+                            None => continue,
+                        };
+                        let external = SymbolEntry {
+                            name: symbol.clone(),
+                            kind: SymbolKind::External,
+                            value: address as i64,
+                            line_index: line.line_index,
+                        };
+                        if let Err(defined) = symbols.define(external) {
+                            if defined.kind != SymbolKind::External {
+                                return Err(AssembleError::DuplicateLabel {
+                                    line_index: line.line_index,
+                                    name: symbol.clone(),
+                                });
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        //the equs are defined once every label has its address, so they can use the ones after them
+        for line in assembled {
+            match &line.parsed {
+                LexedLine::Directive { name, args, .. } if name == "equ" && args.len() > 2 => {
+                    //the lexer lowercases the name, the one in the source keeps its case
+                    let name = line
+                        .line
+                        .split_whitespace()
+                        .find(|word| word.eq_ignore_ascii_case(&args[0]))
+                        .unwrap_or(&args[0])
+                        .to_string();
+                    if let Ok(value) = symbols.resolve(&args[2..].join(" ")) {
+                        let _ = symbols.define(SymbolEntry {
+                            name,
+                            kind: SymbolKind::Equ,
+                            value,
+                            line_index: line.line_index,
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+        let mut labels = LabelTable::new();
+        for label in symbols.get_labels() {
+            labels.insert(
+                &label.name,
+                Label {
+                    name: label.name.clone(),
+                    address: label.value as usize,
+                    line: label.line_index,
+                },
+            );
+        }
         self.labels = labels;
+        self.symbols = symbols;
         self.line_addresses = line_addresses;
+        self.end_address = last_address;
         //TODO i could merge this inthe previous loop but it would now allow for labels to be defined after the directive
         for (i, line) in lines.iter().enumerate() {
-            match &line.parsed {
-                LexedLine::Directive { name, size, args } => {
-                    match self.parse_directive(name, size, args, self.line_addresses[i]) {
-                        Ok(directive) => {
-                            directives.push(directive);
-                        }
-                        Err(e) => {
-                            return Err(format!(
-                                "Error parsing directive at line {}: {}",
-                                line.line_index,
-                                e.get_message()
-                            ));
-                        }
+            if let LexedLine::Directive { name, size, args } = &line.parsed {
+                match self.parse_directive(name, size, args, self.line_addresses[i]) {
+                    Ok(directive) => {
+                        directives.push(directive);
+                    }
+                    Err(e) => {
+                        return Err(AssembleError::Directive {
+                            line_index: line.line_index,
+                            source: e,
+                        });
                     }
                 }
-                _ => {}
             }
         }
         self.directives = directives;
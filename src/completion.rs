@@ -0,0 +1,367 @@
+use alloc::{format, string::{String, ToString}, vec, vec::Vec};
+use crate::collections::HashMap;
+
+use lazy_static::lazy_static;
+use serde::Serialize;
+
+use crate::{
+    constants::{DIRECTIVE_DESCRIPTIONS, EQU, INSTRUCTIONS},
+    cpu_model::CpuModel,
+    lexer::{LexedLine, Lexer},
+    semantic_checker::{SemanticChecker, SemanticError},
+    utils::{split_comment, split_operands},
+    S68k,
+};
+
+/*
+    Completion candidates for editors, the context is read from the text before the cursor:
+    at the start of a line (after the label) the mnemonics with their size variants and the directives,
+    in the operands the addressing modes that are legal for the instruction at that position, the registers
+    and the labels and equs of the document. The legal sizes and addressing modes are not kept in a table,
+    they are found by checking sample lines with the semantic checker, so they always agree with it
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum CompletionKind {
+    Instruction,
+    Directive,
+    AddressingMode,
+    Register,
+    Label,
+    Constant,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CompletionCandidate {
+    pub label: String,
+    pub kind: CompletionKind,
+    pub detail: Option<String>,
+    pub documentation: Option<String>,
+    /// Snippet in the LSP syntax, with $1, ${1:placeholder} tab stops, when it differs from the label
+    pub snippet: Option<String>,
+}
+
+impl CompletionCandidate {
+    fn new(label: &str, kind: CompletionKind, detail: Option<String>) -> Self {
+        Self {
+            label: label.to_string(),
+            kind,
+            detail,
+            documentation: None,
+            snippet: None,
+        }
+    }
+}
+
+pub const REGISTERS: &[&str] = &[
+    "d0", "d1", "d2", "d3", "d4", "d5", "d6", "d7", "a0", "a1", "a2", "a3", "a4", "a5", "a6",
+    "a7", "sp", "pc", "sr", "ccr",
+];
+
+const FLOAT_REGISTERS: &[&str] = &["fp0", "fp1", "fp2", "fp3", "fp4", "fp5", "fp6", "fp7"];
+
+/// Name, samples used to check the mode, snippet and description of every addressing mode
+const ADDRESSING_MODES: &[(&str, &[&str], &str, &str)] = &[
+    ("Dn", &["d0"], "d${1:0}", "Data register direct"),
+    ("An", &["a0"], "a${1:0}", "Address register direct"),
+    ("(An)", &["(a0)"], "(a${1:0})", "Address register indirect"),
+    ("(An)+", &["(a0)+"], "(a${1:0})+", "Address register indirect with postincrement"),
+    ("-(An)", &["-(a0)"], "-(a${1:0})", "Address register indirect with predecrement"),
+    ("d(An)", &["4(a0)"], "${1:0}(a${2:0})", "Address register indirect with displacement"),
+    ("d(An,Xn)", &["4(a0,d0.l)"], "${1:0}(a${2:0},d${3:0}.${4:l})", "Address register indirect with index"),
+    ("([bd,An],Xn,od)", &["([4,a0],d0.l)"], "([${1:0},a${2:0}],d${3:0}.l,${4:0})", "Memory indirect postindexed"),
+    ("#imm", &["#1", "#15"], "#${1:0}", "Immediate value"),
+    ("abs", &["$2000"], "\\$${1:1000}", "Absolute address"),
+    ("reg list", &["d0-d1/a0"], "d${1:0}-d${2:7}/a${3:0}-a${4:6}", "Register list"),
+    ("Dh:Dl", &["d0:d1"], "d${1:0}:d${2:1}", "Register pair"),
+    ("FPn", &["fp0"], "fp${1:0}", "Floating point register"),
+];
+
+const SIZES: &[&str] = &["b", "w", "l", "s", "d", "x"];
+
+pub fn get_register_description(name: &str) -> Option<String> {
+    match name {
+        "sp" | "a7" => Some("Address register 7, the stack pointer".to_string()),
+        "pc" => Some("Program counter".to_string()),
+        "sr" => Some("Status register".to_string()),
+        "ccr" => Some("Condition code register, the low byte of the status register".to_string()),
+        _ if FLOAT_REGISTERS.contains(&name) => Some(format!("Floating point register {}", &name[2..])),
+        _ => match REGISTERS.contains(&name) {
+            true => match &name[..1] {
+                "d" => Some(format!("Data register {}", &name[1..])),
+                _ => Some(format!("Address register {}", &name[1..])),
+            },
+            false => None,
+        },
+    }
+}
+
+/// The model feature needed by the instruction, and if it is missing in the model
+pub fn describe_instruction(name: &str, cpu_model: CpuModel) -> String {
+    match CpuModel::get_instruction_feature(name) {
+        Some(feature) => {
+            let minimum = feature.get_minimum_model();
+            let mut content = format!(
+                "`{}` instruction\n\nNeeds the {}, the minimum model is the {}",
+                name,
+                feature.get_name(),
+                minimum.get_name()
+            );
+            if !cpu_model.supports(feature) {
+                content.push_str(&format!(", it is not available on the {}", cpu_model.get_name()));
+            }
+            content
+        }
+        None => format!("`{}` instruction", name),
+    }
+}
+
+/// Checks sample lines, the same lexer is reused for every check
+struct SampleChecker {
+    lexer: Lexer,
+    cpu_model: CpuModel,
+}
+
+impl SampleChecker {
+    fn new(cpu_model: CpuModel) -> Self {
+        Self {
+            lexer: Lexer::new(),
+            cpu_model,
+        }
+    }
+    fn check(&mut self, line: String) -> Vec<SemanticError> {
+        SemanticChecker::new(self.lexer.lex_lossy(&line), self.cpu_model).get_errors()
+    }
+    fn is_legal(&mut self, mnemonic: &str, operands: &[&str]) -> bool {
+        self.check(format!("{} {}", mnemonic, operands.join(", "))).is_empty()
+    }
+}
+
+/// The checker only rejects the modes an instruction forbids, so the modes made
+/// for a few instructions are only offered for them
+fn is_mode_of_instruction(mode: &str, name: &str) -> bool {
+    match mode {
+        "reg list" => name == "movem",
+        "Dh:Dl" => matches!(name, "divs" | "divu" | "muls" | "mulu" | "divsl" | "divul"),
+        "FPn" => name.starts_with('f'),
+        _ => true,
+    }
+}
+
+lazy_static! {
+    static ref VALID_SIZES: HashMap<&'static str, Vec<&'static str>> =
+        {
+        let mut checker = SampleChecker::new(CpuModel::M68000);
+        INSTRUCTIONS.iter().map(|name| (*name, find_valid_sizes(name, &mut checker))).collect()
+    };
+}
+
+/// Sizes accepted by the mnemonic, the plain mnemonic is always accepted
+pub fn get_valid_sizes(name: &str) -> Vec<&'static str> {
+    VALID_SIZES.get(name).cloned().unwrap_or_default()
+}
+
+/// Checks every size on the model that supports the mnemonic
+fn find_valid_sizes(name: &str, checker: &mut SampleChecker) -> Vec<&'static str> {
+    checker.cpu_model = match CpuModel::get_instruction_feature(name) {
+        Some(feature) => feature.get_minimum_model(),
+        None => CpuModel::M68000,
+    };
+    SIZES
+        .iter()
+        //floating point sizes can only be used by FPU instructions
+        .filter(|size| name.starts_with('f') || !matches!(**size, "s" | "d" | "x"))
+        .filter(|size| {
+            !checker
+                .check(format!("{}.{}", name, size))
+                .iter()
+                .any(|e| e.get_error().starts_with("Invalid size") || e.get_error().starts_with("Unknown size"))
+        })
+        .copied()
+        .collect()
+}
+
+/// Addressing modes that are legal at the position, the other operands are the typed ones when they are legal
+fn get_legal_modes(mnemonic: &str, position: usize, typed: &[String], cpu_model: CpuModel) -> Vec<usize> {
+    let name = mnemonic.split('.').next().unwrap_or_default();
+    let samples: Vec<&str> = ADDRESSING_MODES.iter().map(|(_, samples, _, _)| samples[0]).collect();
+    let mut checker = SampleChecker::new(cpu_model);
+    let mut probe = |others: &[&str]| -> Vec<usize> {
+        (0..ADDRESSING_MODES.len())
+            .filter(|mode| is_mode_of_instruction(ADDRESSING_MODES[*mode].0, name))
+            .filter(|mode| {
+                //some instructions only take a few values, like TRAP #15
+                ADDRESSING_MODES[*mode].1.iter().any(|sample| match position {
+                    0 => checker.is_legal(mnemonic, &[sample])
+                        || others.iter().any(|other| checker.is_legal(mnemonic, &[sample, other])),
+                    1 => others.iter().any(|other| checker.is_legal(mnemonic, &[other, sample])),
+                    _ => false,
+                })
+            })
+            .collect()
+    };
+    let other = match position {
+        0 => typed.get(1),
+        _ => typed.first(),
+    };
+    match other.filter(|other| !other.is_empty()) {
+        Some(other) => {
+            let modes = probe(&[other.as_str()]);
+            match modes.is_empty() {
+                true => probe(&samples),
+                false => modes,
+            }
+        }
+        None => probe(&samples),
+    }
+}
+
+/// Names of the addressing modes accepted by the operand of the mnemonic, like "Dn" or "d(An)"
+pub fn get_addressing_modes(mnemonic: &str, position: usize, cpu_model: CpuModel) -> Vec<&'static str> {
+    get_typed_addressing_modes(mnemonic, position, &[], cpu_model)
+}
+
+/// Like get_addressing_modes, but the modes depend on the other operands when they are already typed
+pub(crate) fn get_typed_addressing_modes(
+    mnemonic: &str,
+    position: usize,
+    typed: &[String],
+    cpu_model: CpuModel,
+) -> Vec<&'static str> {
+    get_legal_modes(mnemonic, position, typed, cpu_model)
+        .into_iter()
+        .map(|mode| ADDRESSING_MODES[mode].0)
+        .collect()
+}
+
+fn get_symbols(s68k: &S68k) -> Vec<CompletionCandidate> {
+    s68k.get_lexed_lines()
+        .iter()
+        .filter_map(|line| match &line.parsed {
+            LexedLine::Label { name, .. } => Some(CompletionCandidate::new(
+                name,
+                CompletionKind::Label,
+                Some(format!("label on line {}", line.line_index + 1)),
+            )),
+            //the equ replacement is also applied to the args, so the name is taken from the source line
+            LexedLine::Directive { name, args, .. } if name == EQU => {
+                let constant = line.line.split_whitespace().next()?;
+                Some(CompletionCandidate::new(
+                    constant,
+                    CompletionKind::Constant,
+                    args.get(2..).map(|value| value.join(" ")),
+                ))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+fn get_mnemonics(cpu_model: CpuModel) -> Vec<CompletionCandidate> {
+    let mut result = vec![];
+    for name in INSTRUCTIONS.iter().filter(|name| cpu_model.verify_instruction(name).is_ok()) {
+        let sizes = get_valid_sizes(name);
+        let detail = match sizes.is_empty() {
+            true => None,
+            false => Some(format!("sizes: {}", sizes.join(", "))),
+        };
+        let documentation = Some(describe_instruction(name, cpu_model));
+        result.push(CompletionCandidate {
+            documentation: documentation.clone(),
+            ..CompletionCandidate::new(name, CompletionKind::Instruction, detail.clone())
+        });
+        for size in sizes {
+            result.push(CompletionCandidate {
+                documentation: documentation.clone(),
+                ..CompletionCandidate::new(&format!("{}.{}", name, size), CompletionKind::Instruction, detail.clone())
+            });
+        }
+    }
+    for (name, description) in DIRECTIVE_DESCRIPTIONS {
+        let documentation = Some(description.to_string());
+        result.push(CompletionCandidate {
+            documentation: documentation.clone(),
+            ..CompletionCandidate::new(name, CompletionKind::Directive, None)
+        });
+        if matches!(*name, "dc" | "ds" | "dcb") {
+            for size in ["b", "w", "l"] {
+                result.push(CompletionCandidate {
+                    documentation: documentation.clone(),
+                    ..CompletionCandidate::new(&format!("{}.{}", name, size), CompletionKind::Directive, None)
+                });
+            }
+        }
+    }
+    result
+}
+
+fn get_operand_candidates(s68k: &S68k, mnemonic: &str, position: usize, typed: &[String]) -> Vec<CompletionCandidate> {
+    let cpu_model = s68k.get_cpu_model();
+    let name = mnemonic.split('.').next().unwrap_or_default().to_lowercase();
+    if !INSTRUCTIONS.contains(&name.as_str()) {
+        //directives only take values
+        return get_symbols(s68k);
+    }
+    let modes = get_legal_modes(&mnemonic.to_lowercase(), position, typed, cpu_model);
+    let has_mode = |name: &str| modes.iter().any(|mode| ADDRESSING_MODES[*mode].0 == name);
+    let mut result: Vec<CompletionCandidate> = modes
+        .iter()
+        .map(|mode| {
+            let (label, _, snippet, description) = ADDRESSING_MODES[*mode];
+            CompletionCandidate {
+                documentation: Some(format!("Legal for operand {} of `{}`", position + 1, mnemonic)),
+                snippet: Some(snippet.to_string()),
+                ..CompletionCandidate::new(label, CompletionKind::AddressingMode, Some(description.to_string()))
+            }
+        })
+        .collect();
+    let registers = REGISTERS
+        .iter()
+        .chain(FLOAT_REGISTERS.iter())
+        .filter(|register| match register.as_bytes()[0] {
+            b'd' => has_mode("Dn"),
+            b'a' => has_mode("An"),
+            b'f' => has_mode("FPn"),
+            b's' if **register == "sp" => has_mode("An"),
+            _ => false,
+        })
+        .map(|register| CompletionCandidate::new(register, CompletionKind::Register, get_register_description(register)));
+    result.extend(registers);
+    //equs are replaced before checking so they can be anything, labels are addresses
+    result.extend(
+        get_symbols(s68k)
+            .into_iter()
+            .filter(|symbol| symbol.kind == CompletionKind::Constant || has_mode("abs") || has_mode("#imm")),
+    );
+    result
+}
+
+/// Candidates at the position of the document, the column is counted in characters
+pub fn get_completions(s68k: &S68k, line_index: usize, column: usize) -> Vec<CompletionCandidate> {
+    let line = s68k.get_code().lines().nth(line_index).unwrap_or_default();
+    let before: String = line.chars().take(column).collect();
+    if split_comment(&before).1.is_some() {
+        return vec![];
+    }
+    let (code, _) = split_comment(line);
+    //the label is only before the cursor if the colon is
+    let (before, code) = match before.find(':') {
+        Some(index) => (&before[index + 1..], &code[index + 1..]),
+        None => (before.as_str(), code),
+    };
+    let before = before.trim_start();
+    let code = code.trim_start();
+    if !before.contains(char::is_whitespace) {
+        return get_mnemonics(s68k.get_cpu_model());
+    }
+    let mut words = code.splitn(2, char::is_whitespace);
+    let mnemonic = words.next().unwrap_or_default();
+    let operands = words.next().unwrap_or_default();
+    let typed_before = before.split_once(char::is_whitespace).map(|(_, o)| o).unwrap_or_default();
+    //"name equ value", only the value is completed
+    if operands.split_whitespace().next().map(|w| w.to_lowercase()) == Some(EQU.to_string()) {
+        return get_symbols(s68k);
+    }
+    let position = split_operands(typed_before).len().max(1) - 1;
+    get_operand_candidates(s68k, mnemonic, position, &split_operands(operands))
+}
@@ -0,0 +1,185 @@
+use alloc::{string::{String, ToString}, vec, vec::Vec};
+use core::{error::Error, fmt};
+use crate::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    expression::{evaluate, ExpressionError},
+    lexer::LexError,
+    macros::{split_statement, ExpandedLine},
+};
+
+/*
+    Conditional assembly, the lines of an inactive block are removed before the lines are lexed:
+        IFEQ expr       the block is assembled if the expression is 0
+        IFNE expr       if it is not 0
+        IFGT, IFGE, IFLT, IFLE expr     if it is greater, greater or equal, less, less or equal than 0
+        IFD name        if the name is an equ or a label defined before
+        IFND name       if it is not
+        ELSE            switches to the other branch
+        ENDC            ends the block
+    Blocks can be nested. The expressions can use numbers and the equs defined before the block, the
+    source is read once from the top like the assembler does, so a later equ is not known yet.
+    It runs after the macros are expanded, so a block in a macro can test its arguments.
+    The directives and the inactive lines are lexed as empty lines
+*/
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "value")]
+pub enum ConditionalError {
+    /// The directive that is missing its ENDC
+    Unterminated(String),
+    UnexpectedElse,
+    UnexpectedEndc,
+    DuplicateElse,
+    /// The directive without its expression or name
+    MissingArgument(String),
+    InvalidExpression(ExpressionError),
+}
+
+impl fmt::Display for ConditionalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConditionalError::Unterminated(directive) => write!(f, "{} is missing its ENDC", directive.to_uppercase()),
+            ConditionalError::UnexpectedElse => write!(f, "ELSE without an IF"),
+            ConditionalError::UnexpectedEndc => write!(f, "ENDC without an IF"),
+            ConditionalError::DuplicateElse => write!(f, "The IF already has an ELSE"),
+            ConditionalError::MissingArgument(directive) => {
+                write!(f, "{} is missing its argument", directive.to_uppercase())
+            }
+            ConditionalError::InvalidExpression(error) => write!(f, "Invalid condition: {}", error),
+        }
+    }
+}
+
+impl Error for ConditionalError {}
+
+pub(crate) const CONDITIONS: [&str; 8] = ["ifeq", "ifne", "ifgt", "ifge", "iflt", "ifle", "ifd", "ifnd"];
+
+struct Block {
+    line: usize,
+    text: String,
+    directive: String,
+    parent_active: bool,
+    taken: bool,
+    in_else: bool,
+}
+
+impl Block {
+    fn is_active(&self) -> bool {
+        self.parent_active && self.taken != self.in_else
+    }
+}
+
+/// The names defined so far, the value is missing for labels and equs that are not constant
+pub(crate) type Symbols = HashMap<String, Option<i64>>;
+
+pub(crate) fn evaluate_with_symbols(expression: &str, symbols: &Symbols) -> Result<i64, ExpressionError> {
+    evaluate(expression, &|name| symbols.get(name).copied().flatten())
+}
+
+/// Adds the equ or the label of the line to the symbols
+pub(crate) fn define_symbols(line: &str, symbols: &mut Symbols) {
+    let (label, statement) = split_statement(line);
+    let words = statement.split_whitespace().collect::<Vec<&str>>();
+    match (label, &words[..]) {
+        (_, [name, equ, ..]) if equ.eq_ignore_ascii_case("equ") => {
+            let value = statement[name.len()..].trim_start()[equ.len()..].trim();
+            let value = evaluate_with_symbols(value, symbols).ok();
+            symbols.insert(name.to_string(), value);
+        }
+        (Some(label), _) => {
+            symbols.insert(label.to_string(), None);
+        }
+        _ => {}
+    }
+}
+
+fn evaluate_condition(directive: &str, argument: &str, symbols: &Symbols) -> Result<bool, ConditionalError> {
+    if argument.is_empty() {
+        return Err(ConditionalError::MissingArgument(directive.to_string()));
+    }
+    let value = match directive {
+        "ifd" => return Ok(symbols.contains_key(argument)),
+        "ifnd" => return Ok(!symbols.contains_key(argument)),
+        _ => evaluate_with_symbols(argument, symbols).map_err(ConditionalError::InvalidExpression)?,
+    };
+    Ok(match directive {
+        "ifeq" => value == 0,
+        "ifne" => value != 0,
+        "ifgt" => value > 0,
+        "ifge" => value >= 0,
+        "iflt" => value < 0,
+        _ => value <= 0,
+    })
+}
+
+fn set_error(line: &mut ExpandedLine, error: ConditionalError) {
+    line.error = Some(LexError::InvalidConditional(error));
+}
+
+/// Empties the conditional directives and the lines of the inactive blocks
+pub fn apply_conditionals(lines: &mut [ExpandedLine]) {
+    let mut symbols: Symbols = HashMap::new();
+    let mut blocks: Vec<Block> = vec![];
+    for (i, line) in lines.iter_mut().enumerate() {
+        let active = blocks.last().map(Block::is_active).unwrap_or(true);
+        let text = core::mem::take(&mut line.text);
+        let statement = split_statement(&text).1;
+        let word = statement.split_whitespace().next().unwrap_or_default();
+        let directive = word.to_lowercase();
+        let argument = statement[word.len()..].trim();
+        match directive.as_str() {
+            _ if CONDITIONS.contains(&directive.as_str()) => {
+                //the conditions of an inactive block are not evaluated, its blocks are all inactive
+                let taken = match active {
+                    true => evaluate_condition(&directive, argument, &symbols).unwrap_or_else(|error| {
+                        set_error(line, error);
+                        false
+                    }),
+                    false => false,
+                };
+                if line.error.is_some() {
+                    line.text = text.clone();
+                }
+                blocks.push(Block {
+                    line: i,
+                    text,
+                    directive,
+                    parent_active: active,
+                    taken,
+                    in_else: false,
+                });
+            }
+            "else" => match blocks.last_mut() {
+                Some(block) if !block.in_else => block.in_else = true,
+                Some(_) => {
+                    set_error(line, ConditionalError::DuplicateElse);
+                    line.text = text;
+                }
+                None => {
+                    set_error(line, ConditionalError::UnexpectedElse);
+                    line.text = text;
+                }
+            },
+            "endc" => {
+                if blocks.pop().is_none() {
+                    set_error(line, ConditionalError::UnexpectedEndc);
+                    line.text = text;
+                }
+            }
+            _ if !active => {}
+            _ => {
+                define_symbols(&text, &mut symbols);
+                line.text = text;
+            }
+        }
+    }
+    //the lines after an unterminated block keep the state of the block
+    for block in blocks {
+        let line = &mut lines[block.line];
+        set_error(line, ConditionalError::Unterminated(block.directive));
+        line.text = block.text;
+    }
+}
@@ -7,7 +7,47 @@ macro_rules! string_vec {
 }
 
 pub const DIRECTIVES: &[&str] = &["equ", "org"];
+pub const DIRECTIVE_DESCRIPTIONS: &[(&str, &str)] = &[
+    ("org", "Sets the address where the following code is placed"),
+    ("dc", "Defines constants in memory"),
+    ("ds", "Reserves space in memory"),
+    ("dcb", "Defines a block of repeated constants in memory"),
+    ("equ", "Defines a name that is replaced by its value in the code"),
+    ("xdef", "Exports labels of the module to the other modules it is linked with"),
+    ("public", "Exports labels of the module to the other modules it is linked with, same as xdef"),
+    ("xref", "Imports labels that another module defines, they are resolved by the linker"),
+    ("end", "Ends the source, the program starts from the label after it"),
+];
 pub const COMMENT_1: char = ';';
 pub const COMMENT_2: char  = '*';
 pub const OPERAND_SEPARATOR: char = ',';
 pub const EQU: &str = "equ";
+/// Every mnemonic accepted by the semantic checker, without size suffixes
+pub const INSTRUCTIONS: &[&str] = &[
+    "add", "adda", "addi", "addq", "and", "andi", "asl", "asr", "bchg", "bclr", "bset", "btst",
+    "bcc", "bcs", "beq", "bne", "blt", "ble", "bgt", "bge", "bls", "bhi", "bpl", "bmi", "blo", "bhs",
+    "bvc", "bvs", "bsr", "bra", "bfchg", "bfclr", "bfexts", "bfextu", "bfffo", "bfins", "bfset",
+    "bftst", "chk", "clr", "cmp", "cmpa", "cmpi", "cmpm", "dbcc", "dbcs", "dbeq", "dbne", "dbge", "dbgt",
+    "dble", "dbls", "dblt", "dbhi", "dbmi", "dbpl", "dbvc", "dbvs", "dbf", "dbt", "dbhs", "dblo", "dbra", "divs",
+    "divsl", "divu", "divul", "eor", "eori", "exg", "ext", "extb", "fadd", "fcmp", "fdiv", "fmove",
+    "fmul", "fsub", "fbeq", "fbne", "fbgt", "fbngt", "fbge", "fbnge", "fblt", "fbnlt", "fble",
+    "fbnle", "fbgl", "fbngl", "fbgle", "fbngle", "fbor", "fbun", "fbt", "fbf", "illegal", "jmp", "jsr", "lea",
+    "linef", "link", "lsl", "lsr", "move", "movea", "movec", "movem", "moveq", "moves", "muls",
+    "mulu", "neg", "nop", "not", "or", "ori", "pea", "rol", "ror", "rtd", "rte", "rtr", "rts", "scc", "scs", "seq",
+    "sne", "sge", "sgt", "sle", "sls", "slt", "shi", "smi", "spl", "svc", "svs", "slo", "shs", "sf",
+    "simhalt", "st", "sub", "suba", "subi", "subq", "swap", "trap", "trapv", "tst", "unlk",
+];
+/// Conditional branches, the Bcc family
+pub const BRANCHES: &[&str] = &[
+    "bcc", "bcs", "beq", "bne", "blt", "ble", "bgt", "bge", "bls", "bhi", "bpl", "bmi", "blo", "bhs", "bvc", "bvs",
+];
+/// The DBcc family
+pub const DECREMENT_BRANCHES: &[&str] = &[
+    "dbcc", "dbcs", "dbeq", "dbne", "dbge", "dbgt", "dble", "dbls", "dblt", "dbhi", "dbmi", "dbpl", "dbvc", "dbvs",
+    "dbf", "dbt", "dbhs", "dblo", "dbra",
+];
+/// The Scc family
+pub const SETS: &[&str] = &[
+    "scc", "scs", "seq", "sne", "sge", "sgt", "sle", "sls", "slt", "shi", "smi", "spl", "svc", "svs", "slo", "shs",
+    "sf", "st",
+];
@@ -0,0 +1,39 @@
+use alloc::vec::Vec;
+use core::any::Any;
+
+use crate::{
+    instructions::{Instruction, Operand, Size},
+    interpreter::RuntimeResult,
+};
+
+/*
+    Coprocessors receive the instructions of the line-F opcode space, the interpreter routes each of them
+    to the coprocessor whose id matches bits 9-11 of the opcode word, like the 68020 coprocessor interface.
+    The 68881 FPU is attached with id 1, other coprocessors (MMUs or made up ones) can be attached with
+    Interpreter::attach_coprocessor and are reached with the generic LINEF instruction.
+    The state of a coprocessor is saved before each of its instructions so that undo can restore it
+*/
+
+/// Access to the main processor given to a coprocessor while it executes an instruction
+pub trait CoprocessorBus {
+    fn read_operand(&mut self, op: &Operand, size: Size) -> RuntimeResult<u32>;
+    fn write_operand(&mut self, op: &Operand, value: u32, size: Size) -> RuntimeResult<()>;
+    /// Address of a memory operand, (An)+ and -(An) are incremented/decremented by the amount of bytes
+    fn get_operand_address(&mut self, op: &Operand, bytes: u32) -> RuntimeResult<usize>;
+    fn read_bytes(&mut self, address: usize, length: usize) -> RuntimeResult<Vec<u8>>;
+    fn write_bytes(&mut self, address: usize, bytes: &[u8]) -> RuntimeResult<()>;
+    fn set_pc(&mut self, address: usize);
+}
+
+pub trait Coprocessor {
+    /// Id between 0 and 7 used to select the coprocessor in the line-F opcode
+    fn get_id(&self) -> u8;
+    fn get_name(&self) -> &str;
+    fn execute(&mut self, ins: &Instruction, bus: &mut dyn CoprocessorBus) -> RuntimeResult<()>;
+    fn save_state(&self) -> Vec<u8>;
+    fn restore_state(&mut self, state: &[u8]) -> RuntimeResult<()>;
+    fn reset(&mut self);
+    fn as_any(&self) -> &dyn Any;
+}
+
+pub use crate::instructions::FPU_COPROCESSOR_ID;
@@ -0,0 +1,347 @@
+use alloc::{format, string::{String, ToString}, vec, vec::Vec};
+use serde::Serialize;
+
+use crate::{
+    collections::HashMap,
+    compiler::{Compiler, InstructionLine},
+    cpu_model::CpuModel,
+    instructions::{FloatOperand, Instruction, Operand, Size},
+    lexer::LexedLine,
+    timing::get_timing_table,
+    S68k,
+};
+
+/*
+    Cost of every line of the source in ROM space and time, for who is optimizing a routine:
+        bytes           the size of the line once encoded, the opcode word and its extension words,
+                        or the data of a dc, ds and dcb
+        best_cycles     the fewest cycles the line takes, like a branch that is not taken
+        worst_cycles    the most cycles the line takes
+    The size of an instruction is the one the assembler module encodes it in, the addresses are the ones
+    the compiler placed the instructions at with those sizes. A line that REPT or a macro repeats costs
+    the sum of its copies, at the address of the first one.
+    Branches are counted with a word displacement, absolute addresses as short when they fit in a word.
+    Subroutines are grouped like the folding ranges, from a label to the next one that doesn't start with
+    a dot, their cycles are the ones of running every line once
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct LineCost {
+    pub line_index: usize,
+    pub address: usize,
+    pub bytes: usize,
+    pub best_cycles: u32,
+    pub worst_cycles: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SubroutineCost {
+    /// None for the lines before the first label
+    pub name: Option<String>,
+    pub start_line: usize,
+    /// Last line of the subroutine, included
+    pub end_line: usize,
+    pub bytes: usize,
+    pub best_cycles: u32,
+    pub worst_cycles: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CostReport {
+    pub cpu_model: CpuModel,
+    /// Only the lines with an instruction or data
+    pub lines: Vec<LineCost>,
+    pub subroutines: Vec<SubroutineCost>,
+    pub total_bytes: usize,
+}
+
+pub(crate) fn fits_in_word(value: i64) -> bool {
+    value >= i16::MIN as i64 && value <= i16::MAX as i64
+}
+
+fn get_displacement_size(displacement: i32) -> usize {
+    match displacement {
+        0 => 0,
+        _ if fits_in_word(displacement as i64) => 2,
+        _ => 4,
+    }
+}
+
+/// Bytes of the extension words of an effective address
+fn get_operand_size(operand: &Operand, size: Size) -> usize {
+    match operand {
+        Operand::Register(_)
+        | Operand::RegisterList(_)
+        | Operand::Indirect(_)
+        | Operand::PostIndirect(_)
+        | Operand::PreIndirect(_) => 0,
+        Operand::Immediate(_) => match size {
+            Size::Long => 4,
+            _ => 2,
+        },
+        Operand::IndirectDisplacement { offset, .. } => match fits_in_word(*offset as i64) {
+            true => 2,
+            false => 6,
+        },
+        //brief extension word with an 8 bit displacement, otherwise the full one of the 68020
+        Operand::IndirectIndex { offset, .. } => match *offset >= i8::MIN as i32 && *offset <= i8::MAX as i32 {
+            true => 2,
+            false => 2 + get_displacement_size(*offset),
+        },
+        Operand::MemoryIndirect {
+            base_displacement,
+            outer_displacement,
+            ..
+        } => 2 + get_displacement_size(*base_displacement) + get_displacement_size(*outer_displacement),
+        Operand::Absolute(address) => match fits_in_word(*address as i32 as i64) {
+            true => 2,
+            false => 4,
+        },
+    }
+}
+
+fn get_float_operand_size(operand: &FloatOperand, bytes: usize) -> usize {
+    match operand {
+        FloatOperand::Register(_) => 0,
+        FloatOperand::Immediate(_) => bytes.max(2),
+        FloatOperand::Effective(operand) => match operand {
+            Operand::Immediate(_) => bytes.max(2),
+            _ => get_operand_size(operand, Size::Long),
+        },
+    }
+}
+
+/// Bytes of the encoded instruction, the opcode word and its extension words
+pub fn get_encoded_size(instruction: &Instruction) -> usize {
+    match instruction {
+        Instruction::MOVE(source, dest, size)
+        | Instruction::ADD(source, dest, size)
+        | Instruction::SUB(source, dest, size)
+        | Instruction::AND(source, dest, size)
+        | Instruction::OR(source, dest, size)
+        | Instruction::EOR(source, dest, size)
+        | Instruction::CMPM(source, dest, size) => {
+            2 + get_operand_size(source, *size) + get_operand_size(dest, *size)
+        }
+        Instruction::MOVES(source, dest, size) => 4 + get_operand_size(source, *size) + get_operand_size(dest, *size),
+        Instruction::ADDA(source, _, size)
+        | Instruction::SUBA(source, _, size)
+        | Instruction::CMPA(source, _, size)
+        | Instruction::MOVEA(source, _, size)
+        | Instruction::CMP(source, _, size) => 2 + get_operand_size(source, *size),
+        Instruction::ADDQ(_, dest, size)
+        | Instruction::SUBQ(_, dest, size)
+        | Instruction::CLR(dest, size)
+        | Instruction::NEG(dest, size)
+        | Instruction::NOT(dest, size)
+        | Instruction::TST(dest, size) => 2 + get_operand_size(dest, *size),
+        Instruction::MOVEtoSR(operand, _) | Instruction::MOVEfromSR(operand, _) => {
+            2 + get_operand_size(operand, Size::Word)
+        }
+        Instruction::ADDI(_, dest, size)
+        | Instruction::SUBI(_, dest, size)
+        | Instruction::ANDI(_, dest, size)
+        | Instruction::ORI(_, dest, size)
+        | Instruction::EORI(_, dest, size)
+        | Instruction::CMPI(_, dest, size) => {
+            2 + get_operand_size(&Operand::Immediate(0), *size) + get_operand_size(dest, *size)
+        }
+        Instruction::DIVx(source, _, _) | Instruction::MULx(source, _, _) | Instruction::CHK(source, _) => {
+            2 + get_operand_size(source, Size::Word)
+        }
+        Instruction::MULxL { source, .. } | Instruction::DIVxL { source, .. } => 4 + get_operand_size(source, Size::Long),
+        Instruction::BFx { target, .. } => 4 + get_operand_size(target, Size::Byte),
+        Instruction::MOVEM { target, size, .. } => 4 + get_operand_size(target, *size),
+        Instruction::LEA(source, _)
+        | Instruction::PEA(source)
+        | Instruction::JSR(source)
+        | Instruction::JMP(source)
+        | Instruction::Scc(source, _) => 2 + get_operand_size(source, Size::Long),
+        //a shift of memory is always by one and the operand is a word
+        Instruction::ASd(_, dest, _, _) | Instruction::LSd(_, dest, _, _) | Instruction::ROd(_, dest, _, _) => {
+            2 + get_operand_size(dest, Size::Word)
+        }
+        Instruction::BTST(bit, dest)
+        | Instruction::BCLR(bit, dest)
+        | Instruction::BSET(bit, dest)
+        | Instruction::BCHG(bit, dest) => {
+            let bit = match bit {
+                Operand::Immediate(_) => 2,
+                _ => 0,
+            };
+            2 + bit + get_operand_size(dest, Size::Byte)
+        }
+        Instruction::Bcc(..)
+        | Instruction::BRA(_)
+        | Instruction::BSR(_)
+        | Instruction::DBcc(..)
+        | Instruction::FBcc(..)
+        | Instruction::LINK(..)
+        | Instruction::RTD(_)
+        | Instruction::ANDItoSR(..)
+        | Instruction::ORItoSR(..)
+        | Instruction::EORItoSR(..)
+        | Instruction::MOVEC { .. }
+        | Instruction::SIMHALT => 4,
+        Instruction::MOVEQ(..)
+        | Instruction::SWAP(_)
+        | Instruction::EXG(..)
+        | Instruction::EXT(..)
+        | Instruction::UNLK(_)
+        | Instruction::TRAP(_)
+        | Instruction::TRAPV
+        | Instruction::ILLEGAL
+        | Instruction::NOP
+        | Instruction::RTS
+        | Instruction::RTE
+        | Instruction::RTR
+        | Instruction::MOVEUSP { .. } => 2,
+        Instruction::FMOVE(source, dest, format) => {
+            4 + get_float_operand_size(source, format.to_bytes()) + get_float_operand_size(dest, format.to_bytes())
+        }
+        Instruction::FADD(source, _, format)
+        | Instruction::FSUB(source, _, format)
+        | Instruction::FMUL(source, _, format)
+        | Instruction::FDIV(source, _, format)
+        | Instruction::FCMP(source, _, format) => 4 + get_float_operand_size(source, format.to_bytes()),
+        Instruction::LINEF(_, operand) => 2 + operand.map(|op| get_operand_size(&op, Size::Long)).unwrap_or(0),
+    }
+}
+
+fn is_data(line: &LexedLine) -> bool {
+    matches!(line, LexedLine::Directive { name, .. } if matches!(name.as_str(), "dc" | "ds" | "dcb"))
+}
+
+/// The size and cycles of every line of the compiled program, with the cycles of the model of the program
+pub fn get_cost_report(s68k: &S68k, compiled: &Compiler) -> CostReport {
+    let timing = get_timing_table(compiled.get_cpu_model());
+    let lines = s68k.get_lexed_lines();
+    let addresses = compiled.get_line_addresses();
+    let instructions: HashMap<usize, &InstructionLine> = compiled
+        .get_instructions()
+        .iter()
+        .map(|instruction| (instruction.address, instruction))
+        .collect();
+    //the cost of every lexed line, a line of the source has one lexed line for each copy that REPT and macros make
+    let mut lexed_costs: Vec<Option<LineCost>> = vec![];
+    for (i, line) in lines.iter().enumerate() {
+        let Some(&address) = addresses.get(i) else {
+            lexed_costs.push(None);
+            continue;
+        };
+        let cost = match &line.parsed {
+            LexedLine::Instruction { .. } => instructions
+                .get(&address)
+                .map(|instruction| {
+                    let not_taken = timing.get_instruction_cycles(&instruction.instruction, false);
+                    let taken = timing.get_instruction_cycles(&instruction.instruction, true);
+                    (get_encoded_size(&instruction.instruction), not_taken.min(taken), not_taken.max(taken))
+                }),
+            parsed if is_data(parsed) => {
+                let end = addresses.get(i + 1).copied().unwrap_or(compiled.get_end_address());
+                Some((end.saturating_sub(address), 0, 0))
+            }
+            _ => None,
+        };
+        lexed_costs.push(cost.map(|(bytes, best_cycles, worst_cycles)| LineCost {
+            line_index: line.line_index,
+            address,
+            bytes,
+            best_cycles,
+            worst_cycles,
+        }));
+    }
+    let mut costs: Vec<LineCost> = vec![];
+    for cost in lexed_costs.iter().flatten() {
+        match costs.iter_mut().find(|line| line.line_index == cost.line_index) {
+            Some(line) => {
+                line.bytes += cost.bytes;
+                line.best_cycles += cost.best_cycles;
+                line.worst_cycles += cost.worst_cycles;
+            }
+            None => costs.push(*cost),
+        }
+    }
+    let mut subroutines: Vec<SubroutineCost> = vec![];
+    for (line, cost) in lines.iter().zip(&lexed_costs) {
+        match &line.parsed {
+            LexedLine::Label { name, local: false, .. } => subroutines.push(SubroutineCost {
+                name: Some(name.clone()),
+                start_line: line.line_index,
+                end_line: line.line_index,
+                bytes: 0,
+                best_cycles: 0,
+                worst_cycles: 0,
+            }),
+            _ if subroutines.is_empty() => subroutines.push(SubroutineCost {
+                name: None,
+                start_line: line.line_index,
+                end_line: line.line_index,
+                bytes: 0,
+                best_cycles: 0,
+                worst_cycles: 0,
+            }),
+            _ => {}
+        }
+        let Some(subroutine) = subroutines.last_mut() else {
+            continue;
+        };
+        subroutine.end_line = line.line_index;
+        if let Some(cost) = cost {
+            subroutine.bytes += cost.bytes;
+            subroutine.best_cycles += cost.best_cycles;
+            subroutine.worst_cycles += cost.worst_cycles;
+        }
+    }
+    //the lines before the first label are only a subroutine if they have code
+    subroutines.retain(|subroutine| subroutine.name.is_some() || subroutine.bytes > 0);
+    CostReport {
+        cpu_model: compiled.get_cpu_model(),
+        total_bytes: costs.iter().map(|cost| cost.bytes).sum(),
+        lines: costs,
+        subroutines,
+    }
+}
+
+fn format_cycles(best: u32, worst: u32) -> String {
+    match best == worst {
+        true => best.to_string(),
+        false => format!("{}-{}", best, worst),
+    }
+}
+
+impl CostReport {
+    pub fn get_line(&self, line_index: usize) -> Option<&LineCost> {
+        self.lines.iter().find(|cost| cost.line_index == line_index)
+    }
+    /// Every line of the code with its bytes and cycles in front, then the subroutines and the total
+    pub fn to_text(&self, code: &str) -> String {
+        let mut text = String::from(" bytes  cycles  source\n");
+        for (line_index, line) in code.lines().enumerate() {
+            let cost = match self.get_line(line_index) {
+                Some(cost) if cost.worst_cycles > 0 => {
+                    format!("{:>6}  {:>6}", cost.bytes, format_cycles(cost.best_cycles, cost.worst_cycles))
+                }
+                Some(cost) => format!("{:>6}  {:>6}", cost.bytes, ""),
+                None => format!("{:>6}  {:>6}", "", ""),
+            };
+            text.push_str(format!("{}  {}", cost, line).trim_end());
+            text.push('\n');
+        }
+        text.push_str(&format!("\n bytes  cycles  subroutine ({})\n", self.cpu_model.get_name()));
+        for subroutine in &self.subroutines {
+            text.push_str(&format!(
+                "{:>6}  {:>6}  {}\n",
+                subroutine.bytes,
+                format_cycles(subroutine.best_cycles, subroutine.worst_cycles),
+                subroutine.name.as_deref().unwrap_or("(start)")
+            ));
+        }
+        text.push_str(&format!("{:>6}          total\n", self.total_bytes));
+        text
+    }
+    #[cfg(feature = "serialize")]
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap_or_default()
+    }
+}
@@ -0,0 +1,150 @@
+use alloc::{format, string::String};
+use core::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::wasm_bindgen;
+
+/*
+    The CPU model decides which instructions and addressing modes are accepted by the semantic checker,
+    encoded by the compiler and executed by the interpreter. The 68000 is the default so that existing
+    programs behave exactly like before.
+*/
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash, Default)]
+pub enum CpuModel {
+    #[default]
+    M68000,
+    M68010,
+    M68020,
+    CPU32,
+}
+
+impl FromStr for CpuModel {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "68000" | "m68000" => Ok(CpuModel::M68000),
+            "68010" | "m68010" => Ok(CpuModel::M68010),
+            "68020" | "m68020" => Ok(CpuModel::M68020),
+            "cpu32" => Ok(CpuModel::CPU32),
+            _ => Err(format!("Unknown CPU model \"{}\", expected 68000, 68010, 68020 or cpu32", s)),
+        }
+    }
+}
+
+/// Features that are not present on every model of the family
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CpuFeature {
+    /// VBR, MOVEC, MOVES, RTD and MOVE from CCR
+    VectorBaseRegister,
+    /// Scale factor on the index register, like (a0,d0.w*4)
+    ScaledIndex,
+    /// Memory indirect and full extension word addressing modes
+    MemoryIndirect,
+    /// EXTB.L
+    ExtendByteToLong,
+    /// 32 bit MULS.L/MULU.L/DIVS.L/DIVU.L
+    LongMultiplyDivide,
+    /// BFEXTU/BFINS/BFFFO and the rest of the bitfield family
+    Bitfield,
+}
+
+impl CpuFeature {
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            CpuFeature::VectorBaseRegister => "vector base register",
+            CpuFeature::ScaledIndex => "scaled index",
+            CpuFeature::MemoryIndirect => "memory indirect addressing",
+            CpuFeature::ExtendByteToLong => "byte to long sign extension",
+            CpuFeature::LongMultiplyDivide => "32 bit multiply/divide",
+            CpuFeature::Bitfield => "bitfield instructions",
+        }
+    }
+    /// The oldest model of the family that has the feature
+    pub fn get_minimum_model(&self) -> CpuModel {
+        match self {
+            CpuFeature::VectorBaseRegister => CpuModel::M68010,
+            _ => CpuModel::M68020,
+        }
+    }
+}
+
+impl CpuModel {
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            CpuModel::M68000 => "68000",
+            CpuModel::M68010 => "68010",
+            CpuModel::M68020 => "68020",
+            CpuModel::CPU32 => "CPU32",
+        }
+    }
+    /// Whether the model is older than the other one, the CPU32 is counted as a 68020
+    pub fn is_older_than(&self, other: CpuModel) -> bool {
+        let generation = |model: &CpuModel| match model {
+            CpuModel::M68000 => 0,
+            CpuModel::M68010 => 1,
+            CpuModel::M68020 | CpuModel::CPU32 => 2,
+        };
+        generation(self) < generation(&other)
+    }
+    pub fn supports(&self, feature: CpuFeature) -> bool {
+        match self {
+            CpuModel::M68000 => false,
+            CpuModel::M68010 => matches!(feature, CpuFeature::VectorBaseRegister),
+            CpuModel::M68020 => true,
+            //the CPU32 is a 68020 without bitfields and the memory indirect modes
+            CpuModel::CPU32 => !matches!(feature, CpuFeature::Bitfield | CpuFeature::MemoryIndirect),
+        }
+    }
+    /// Returns the feature needed by an instruction, if the instruction is not available on every model
+    pub fn get_instruction_feature(name: &str) -> Option<CpuFeature> {
+        match name {
+            "extb" => Some(CpuFeature::ExtendByteToLong),
+            "movec" | "moves" | "rtd" => Some(CpuFeature::VectorBaseRegister),
+            "divsl" | "divul" => Some(CpuFeature::LongMultiplyDivide),
+            "bftst" | "bfextu" | "bfexts" | "bfffo" | "bfchg" | "bfclr" | "bfset" | "bfins" => {
+                Some(CpuFeature::Bitfield)
+            }
+            _ => None,
+        }
+    }
+    /// Instructions of the newer models that are recognised but not implemented, with the model that introduced them
+    pub fn get_unimplemented_instruction_model(name: &str) -> Option<CpuModel> {
+        match name {
+            "bkpt" => Some(CpuModel::M68010),
+            "cas" | "cas2" | "chk2" | "cmp2" | "pack" | "unpk" | "callm" | "rtm" | "trapcc"
+            | "trapcs" | "trapeq" | "trapne" | "traplt" | "traple" | "trapgt" | "trapge"
+            | "traplo" | "trapls" | "traphi" | "traphs" | "trappl" | "trapmi" | "trapvc"
+            | "trapvs" | "trapt" | "trapf" => Some(CpuModel::M68020),
+            _ => None,
+        }
+    }
+    /// The scale of an index register, like d1*4, the checker and the compiler report the same error
+    pub fn verify_index_scale(&self, register: &str, scale: u8) -> Result<(), String> {
+        match scale {
+            1 => Ok(()),
+            2 | 4 | 8 => self.verify_feature(CpuFeature::ScaledIndex, &format!("{}*{}", register, scale)),
+            _ => Err(format!("Invalid scale factor {}, must be 1, 2, 4 or 8", scale)),
+        }
+    }
+    pub fn verify_instruction(&self, name: &str) -> Result<(), String> {
+        match CpuModel::get_instruction_feature(name) {
+            Some(feature) => self.verify_feature(feature, name),
+            None => Ok(()),
+        }
+    }
+    pub fn verify_feature(&self, feature: CpuFeature, context: &str) -> Result<(), String> {
+        if self.supports(feature) {
+            Ok(())
+        } else {
+            Err(format!(
+                "\"{}\" uses {}, which is not available on the {}, the minimum model is the {}",
+                context,
+                feature.get_name(),
+                self.get_name(),
+                feature.get_minimum_model().get_name()
+            ))
+        }
+    }
+}
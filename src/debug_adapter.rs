@@ -0,0 +1,471 @@
+use std::io::{BufRead, Write};
+
+use serde_json::{json, Value};
+
+use crate::{
+    cpu_model::CpuModel,
+    host_io::ScriptedIo,
+    interpreter::{Flags, Interpreter, InterpreterOptions, InterpreterStatus},
+    scripted_input::{ExhaustedPolicy, ScriptedInput},
+    S68k,
+};
+
+/*
+    Debug Adapter Protocol server, it lets editors like VS Code debug a program with the interpreter.
+    The launch arguments are:
+    {
+        "program": "path/to/file.s",
+        "cpuModel": "68000",                            optional
+        "stopOnEntry": true,                            optional
+        "input": "text read by the input traps",        optional
+        "inputEnd": "eof=-1",                           optional, fail, repeat or eof=<number> once the input is over
+        "watchMemory": [{ "address": 4096, "length": 16 }],   optional, shown in the Memory scope
+        "limit": 1000000                                optional, instructions run before pausing
+    }
+    There is a single thread, the frames come from the calls tracked by the debugger and the
+    scopes are the registers, the flags and the watched memory
+*/
+
+const THREAD_ID: i64 = 1;
+const REGISTERS_REFERENCE: i64 = 1;
+const FLAGS_REFERENCE: i64 = 2;
+const MEMORY_REFERENCE: i64 = 3;
+const DEFAULT_LIMIT: usize = 1_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum StepMode {
+    Continue,
+    StepIn,
+    /// Stops once the call depth is back to the one when the step started
+    StepOver(usize),
+    StepOut(usize),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DebugEvent {
+    Initialized,
+    Stopped { reason: String, description: Option<String> },
+    Output(String),
+    Terminated,
+}
+
+impl DebugEvent {
+    fn to_json(&self) -> (&'static str, Value) {
+        match self {
+            DebugEvent::Initialized => ("initialized", json!({})),
+            DebugEvent::Stopped { reason, description } => (
+                "stopped",
+                json!({
+                    "reason": reason,
+                    "description": description,
+                    "threadId": THREAD_ID,
+                    "allThreadsStopped": true
+                }),
+            ),
+            DebugEvent::Output(output) => ("output", json!({ "category": "stdout", "output": output })),
+            DebugEvent::Terminated => ("terminated", json!({})),
+        }
+    }
+}
+
+pub struct DebugSession {
+    interpreter: Option<Interpreter>,
+    program_path: String,
+    breakpoints: Vec<usize>,
+    watched_memory: Vec<(usize, usize)>,
+    input: ScriptedInput,
+    limit: usize,
+    stop_on_entry: bool,
+    lines_start_at_1: bool,
+    ended: bool,
+}
+
+impl Default for DebugSession {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DebugSession {
+    pub fn new() -> Self {
+        Self {
+            interpreter: None,
+            program_path: String::new(),
+            breakpoints: vec![],
+            watched_memory: vec![],
+            input: ScriptedInput::default(),
+            limit: DEFAULT_LIMIT,
+            stop_on_entry: false,
+            lines_start_at_1: true,
+            ended: false,
+        }
+    }
+    pub fn get_interpreter(&self) -> Option<&Interpreter> {
+        self.interpreter.as_ref()
+    }
+    pub fn has_ended(&self) -> bool {
+        self.ended
+    }
+    fn get_interpreter_mut(&mut self) -> Result<&mut Interpreter, String> {
+        self.interpreter
+            .as_mut()
+            .ok_or_else(|| "The program was not launched".to_string())
+    }
+    fn get_client_line(&self, line: usize) -> usize {
+        match self.lines_start_at_1 {
+            true => line + 1,
+            false => line,
+        }
+    }
+    fn get_server_line(&self, line: usize) -> usize {
+        match self.lines_start_at_1 {
+            true => line.saturating_sub(1),
+            false => line,
+        }
+    }
+    fn get_line_at(&self, address: usize) -> Option<usize> {
+        self.interpreter
+            .as_ref()
+            .and_then(|i| i.get_instruction_at(address))
+            .map(|i| i.parsed_line.line_index)
+    }
+    /// Handles a request, returning the body of the response and the events to send after it
+    pub fn handle_request(&mut self, command: &str, arguments: &Value) -> Result<(Value, Vec<DebugEvent>), String> {
+        match command {
+            "initialize" => {
+                self.lines_start_at_1 = arguments["linesStartAt1"].as_bool().unwrap_or(true);
+                Ok((
+                    json!({
+                        "supportsConfigurationDoneRequest": true,
+                        "supportsStepBack": true
+                    }),
+                    vec![DebugEvent::Initialized],
+                ))
+            }
+            "launch" => {
+                self.launch(arguments)?;
+                Ok((json!({}), vec![]))
+            }
+            "setBreakpoints" => {
+                let lines: Vec<usize> = match arguments["breakpoints"].as_array() {
+                    Some(breakpoints) => breakpoints
+                        .iter()
+                        .filter_map(|b| b["line"].as_u64())
+                        .map(|line| self.get_server_line(line as usize))
+                        .collect(),
+                    None => vec![],
+                };
+                let breakpoints: Vec<Value> = lines
+                    .iter()
+                    .map(|line| {
+                        let verified = match &self.interpreter {
+                            Some(interpreter) => interpreter
+                                .get_program()
+                                .iter()
+                                .any(|i| i.parsed_line.line_index == *line),
+                            None => true,
+                        };
+                        json!({ "verified": verified, "line": self.get_client_line(*line) })
+                    })
+                    .collect();
+                self.breakpoints = lines;
+                Ok((json!({ "breakpoints": breakpoints }), vec![]))
+            }
+            "configurationDone" => match self.stop_on_entry {
+                true => Ok((
+                    json!({}),
+                    vec![DebugEvent::Stopped {
+                        reason: "entry".to_string(),
+                        description: None,
+                    }],
+                )),
+                false => Ok((json!({}), self.resume(StepMode::Continue)?)),
+            },
+            "threads" => Ok((json!({ "threads": [{ "id": THREAD_ID, "name": "main" }] }), vec![])),
+            "stackTrace" => Ok((json!({ "stackFrames": self.get_stack_frames()? }), vec![])),
+            "scopes" => Ok((
+                json!({ "scopes": [
+                    { "name": "Registers", "variablesReference": REGISTERS_REFERENCE, "expensive": false },
+                    { "name": "Flags", "variablesReference": FLAGS_REFERENCE, "expensive": false },
+                    { "name": "Memory", "variablesReference": MEMORY_REFERENCE, "expensive": false }
+                ]}),
+                vec![],
+            )),
+            "variables" => {
+                let reference = arguments["variablesReference"].as_i64().unwrap_or(0);
+                Ok((json!({ "variables": self.get_variables(reference)? }), vec![]))
+            }
+            "continue" => Ok((json!({ "allThreadsContinued": true }), self.resume(StepMode::Continue)?)),
+            "next" => {
+                let depth = self.get_interpreter_mut()?.get_call_depth();
+                Ok((json!({}), self.resume(StepMode::StepOver(depth))?))
+            }
+            "stepIn" => Ok((json!({}), self.resume(StepMode::StepIn)?)),
+            "stepOut" => {
+                let depth = self.get_interpreter_mut()?.get_call_depth();
+                Ok((json!({}), self.resume(StepMode::StepOut(depth))?))
+            }
+            "stepBack" => {
+                self.get_interpreter_mut()?
+                    .undo()
+                    .map_err(|e| e.get_message())?;
+                Ok((
+                    json!({}),
+                    vec![DebugEvent::Stopped {
+                        reason: "step".to_string(),
+                        description: None,
+                    }],
+                ))
+            }
+            "disconnect" | "terminate" => {
+                self.ended = true;
+                Ok((json!({}), vec![DebugEvent::Terminated]))
+            }
+            _ => Err(format!("Unsupported command {}", command)),
+        }
+    }
+    fn launch(&mut self, arguments: &Value) -> Result<(), String> {
+        let path = arguments["program"]
+            .as_str()
+            .ok_or_else(|| "Missing \"program\" in the launch arguments".to_string())?;
+        let code = std::fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path, e))?;
+        let cpu_model: CpuModel = match arguments["cpuModel"].as_str() {
+            Some(model) => model.parse()?,
+            None => CpuModel::M68000,
+        };
+        let mut s68k = S68k::new(code);
+        s68k.set_cpu_model(cpu_model);
+        let errors = s68k.semantic_check();
+        if !errors.is_empty() {
+            let messages: Vec<String> = errors.iter().map(|e| e.get_message()).collect();
+            return Err(messages.join("\n"));
+        }
+        let compiled = s68k.compile()?;
+        let options = InterpreterOptions {
+            keep_history: true,
+            history_size: 1000,
+            ..Default::default()
+        };
+        self.interpreter = Some(s68k.create_interpreter(compiled, Some(options)));
+        self.program_path = path.to_string();
+        self.stop_on_entry = arguments["stopOnEntry"].as_bool().unwrap_or(false);
+        let input_end = match arguments["inputEnd"].as_str() {
+            Some(policy) => policy.parse()?,
+            None => ExhaustedPolicy::Eof(0),
+        };
+        self.input = ScriptedInput::from_text(arguments["input"].as_str().unwrap_or_default(), input_end);
+        self.limit = arguments["limit"].as_u64().map(|l| l as usize).unwrap_or(DEFAULT_LIMIT);
+        self.watched_memory = match arguments["watchMemory"].as_array() {
+            Some(watches) => watches
+                .iter()
+                .filter_map(|w| match (w["address"].as_u64(), w["length"].as_u64()) {
+                    (Some(address), Some(length)) => Some((address as usize, length as usize)),
+                    _ => None,
+                })
+                .collect(),
+            None => vec![],
+        };
+        Ok(())
+    }
+    fn answer_interrupt(&mut self, events: &mut Vec<DebugEvent>) -> Result<(), String> {
+        let mut output = String::new();
+        let interpreter = self
+            .interpreter
+            .as_mut()
+            .ok_or_else(|| "The program was not launched".to_string())?;
+        interpreter
+            .answer_interrupt_with(&mut ScriptedIo::new(&mut self.input, &mut output))
+            .map_err(|e| e.get_message())?;
+        if !output.is_empty() {
+            events.push(DebugEvent::Output(output));
+        }
+        Ok(())
+    }
+    fn resume(&mut self, mode: StepMode) -> Result<Vec<DebugEvent>, String> {
+        let mut events = vec![];
+        let stop = |reason: &str, description: Option<String>| DebugEvent::Stopped {
+            reason: reason.to_string(),
+            description,
+        };
+        for _ in 0..self.limit {
+            let interpreter = self.get_interpreter_mut()?;
+            match *interpreter.get_status() {
+                InterpreterStatus::Interrupt => {
+                    self.answer_interrupt(&mut events)?;
+                    continue;
+                }
+                InterpreterStatus::Terminated | InterpreterStatus::TerminatedWithException => {
+                    events.push(DebugEvent::Terminated);
+                    return Ok(events);
+                }
+                InterpreterStatus::Running => {}
+            }
+            match interpreter.step() {
+                Ok(InterpreterStatus::Interrupt) => continue,
+                Ok(_) => {}
+                Err(e) => {
+                    events.push(DebugEvent::Output(format!("{}\n", e.get_message())));
+                    events.push(stop("exception", Some(e.get_message())));
+                    return Ok(events);
+                }
+            }
+            if interpreter.has_terminated() {
+                events.push(DebugEvent::Terminated);
+                return Ok(events);
+            }
+            let depth = interpreter.get_call_depth();
+            let pc = interpreter.get_pc();
+            let done = match mode {
+                StepMode::Continue => false,
+                StepMode::StepIn => true,
+                StepMode::StepOver(start) => depth <= start,
+                StepMode::StepOut(start) => depth < start,
+            };
+            let on_breakpoint = match self.get_line_at(pc) {
+                Some(line) => self.breakpoints.contains(&line),
+                None => false,
+            };
+            if on_breakpoint {
+                events.push(stop("breakpoint", None));
+                return Ok(events);
+            }
+            if done {
+                events.push(stop("step", None));
+                return Ok(events);
+            }
+        }
+        events.push(stop("pause", Some(format!("Paused after {} instructions", self.limit))));
+        Ok(events)
+    }
+    fn get_stack_frames(&self) -> Result<Vec<Value>, String> {
+        let interpreter = self
+            .interpreter
+            .as_ref()
+            .ok_or_else(|| "The program was not launched".to_string())?;
+        let calls = interpreter.get_pretty_call_stack();
+        let call_sites = interpreter.get_call_sites();
+        //the innermost frame is at the pc, the others are stopped at the instruction that made the call
+        let mut addresses = vec![interpreter.get_pc()];
+        addresses.extend(call_sites.iter().rev());
+        let frames = addresses
+            .iter()
+            .enumerate()
+            .map(|(i, address)| {
+                let name = match calls.len().checked_sub(i + 1).and_then(|index| calls.get(index)) {
+                    Some(label) if !label.name.is_empty() => label.name.clone(),
+                    Some(label) => format!("${:X}", label.address),
+                    None => "main".to_string(),
+                };
+                let line = self.get_line_at(*address).unwrap_or(0);
+                json!({
+                    "id": i,
+                    "name": name,
+                    "source": { "path": self.program_path },
+                    "line": self.get_client_line(line),
+                    "column": self.get_client_line(0),
+                    "instructionPointerReference": format!("0x{:X}", address)
+                })
+            })
+            .collect();
+        Ok(frames)
+    }
+    fn get_variables(&self, reference: i64) -> Result<Vec<Value>, String> {
+        let interpreter = self
+            .interpreter
+            .as_ref()
+            .ok_or_else(|| "The program was not launched".to_string())?;
+        let variable = |name: String, value: String| json!({ "name": name, "value": value, "variablesReference": 0 });
+        let cpu = interpreter.get_cpu();
+        match reference {
+            REGISTERS_REFERENCE => {
+                let mut variables: Vec<Value> = (0..8)
+                    .map(|i| variable(format!("d{}", i), format!("0x{:08X}", cpu.wasm_get_d_reg(i).get_long())))
+                    .chain((0..8).map(|i| variable(format!("a{}", i), format!("0x{:08X}", cpu.wasm_get_a_reg(i).get_long()))))
+                    .collect();
+                variables.push(variable("pc".to_string(), format!("0x{:08X}", interpreter.get_pc())));
+                variables.push(variable("sr".to_string(), format!("0x{:04X}", interpreter.get_sr())));
+                variables.push(variable("cycles".to_string(), interpreter.get_cycles().to_string()));
+                Ok(variables)
+            }
+            FLAGS_REFERENCE => Ok([
+                ("X", Flags::Extend),
+                ("N", Flags::Negative),
+                ("Z", Flags::Zero),
+                ("V", Flags::Overflow),
+                ("C", Flags::Carry),
+            ]
+            .iter()
+            .map(|(name, flag)| variable(name.to_string(), (interpreter.get_flag(*flag) as u8).to_string()))
+            .collect()),
+            MEMORY_REFERENCE => Ok(self
+                .watched_memory
+                .iter()
+                .map(|(address, length)| {
+                    let value = match interpreter.get_memory().read_bytes(*address, *length) {
+                        Ok(bytes) => bytes.iter().map(|b| format!("{:02X}", b)).collect::<Vec<String>>().join(" "),
+                        Err(e) => e.get_message(),
+                    };
+                    variable(format!("0x{:X}", address), value)
+                })
+                .collect()),
+            _ => Err(format!("Unknown variables reference {}", reference)),
+        }
+    }
+}
+
+fn read_message(reader: &mut impl BufRead) -> Result<Option<Value>, String> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        let read = reader.read_line(&mut header).map_err(|e| e.to_string())?;
+        if read == 0 {
+            return Ok(None);
+        }
+        let header = header.trim();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = Some(value.trim().parse::<usize>().map_err(|e| e.to_string())?);
+        }
+    }
+    let length = length.ok_or_else(|| "Missing Content-Length header".to_string())?;
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).map_err(|e| e.to_string())?;
+    serde_json::from_slice(&body).map(Some).map_err(|e| e.to_string())
+}
+
+fn write_message(writer: &mut impl Write, message: &Value) -> Result<(), String> {
+    let body = message.to_string();
+    write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body).map_err(|e| e.to_string())?;
+    writer.flush().map_err(|e| e.to_string())
+}
+
+/// Answers the requests read from the reader until the client disconnects
+pub fn run(mut reader: impl BufRead, mut writer: impl Write) -> Result<(), String> {
+    let mut session = DebugSession::new();
+    let mut seq = 1;
+    while let Some(message) = read_message(&mut reader)? {
+        let command = message["command"].as_str().unwrap_or_default().to_string();
+        let (response, events) = match session.handle_request(&command, &message["arguments"]) {
+            Ok((body, events)) => (json!({ "success": true, "body": body }), events),
+            Err(e) => (json!({ "success": false, "message": e }), vec![]),
+        };
+        let mut response = response;
+        response["seq"] = json!(seq);
+        response["type"] = json!("response");
+        response["request_seq"] = message["seq"].clone();
+        response["command"] = json!(command);
+        write_message(&mut writer, &response)?;
+        seq += 1;
+        for event in events {
+            let (name, body) = event.to_json();
+            write_message(&mut writer, &json!({ "seq": seq, "type": "event", "event": name, "body": body }))?;
+            seq += 1;
+        }
+        if session.has_ended() {
+            break;
+        }
+    }
+    Ok(())
+}
@@ -1,11 +1,15 @@
-use std::collections::{LinkedList, HashMap};
+use alloc::{collections::LinkedList, string::ToString, vec, vec::Vec};
+use crate::collections::HashMap;
 
 use serde::Serialize;
+#[cfg(feature = "wasm")]
 use wasm_bindgen::{prelude::wasm_bindgen};
 
 use crate::{
-    instructions::{RegisterOperand, Size, Label},
-    interpreter::Flags,
+    breakpoints::BreakHit,
+    instructions::{ControlRegister, RegisterOperand, Size, Label},
+    interpreter::{Flags, InterpreterStatus},
+    symbol::{LabelTable, Symbol},
 };
 
 #[derive(Debug, Clone, Serialize)]
@@ -25,6 +29,18 @@ pub enum MutationOperation {
         address: usize,
         old: Vec<u8>,
     },
+    WriteControlRegister {
+        register: ControlRegister,
+        old: u32,
+    },
+    /// The whole status register, restoring it also swaps back the stack pointers
+    WriteStatusRegister {
+        old: u16,
+    },
+    WriteCoprocessorState {
+        id: u8,
+        old: Vec<u8>,
+    },
     PushCall {
         to: usize,
         from: usize,
@@ -34,6 +50,52 @@ pub enum MutationOperation {
         from: usize,
     }
 }
+/// Where a debugging step stops, it also stops on a breakpoint, a watchpoint, an interrupt and at the end of the program
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepMode {
+    /// After one instruction, entering the subroutines
+    Into,
+    /// After one instruction, a BSR or JSR runs until its subroutine returns
+    Over,
+    /// Once the current subroutine returns
+    Out,
+    /// Before the instruction of the line runs
+    Line(usize),
+}
+
+/// A part of the state that a debugging step changed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", content = "value")]
+pub enum StateChange {
+    Register {
+        register: RegisterOperand,
+        old: u32,
+        new: u32,
+    },
+    StatusRegister {
+        old: u16,
+        new: u16,
+    },
+    /// The bytes of the memory that were written, as they are after the step
+    Memory {
+        address: usize,
+        bytes: Vec<u8>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StepResult {
+    /// Line of the last instruction that was run, the one undone by a step back
+    pub line_index: Option<usize>,
+    pub pc: usize,
+    pub status: InterpreterStatus,
+    /// Instructions that were run
+    pub executed: usize,
+    pub changes: Vec<StateChange>,
+    /// The breakpoint or watchpoint that stopped it before the end of the step
+    pub break_hit: Option<BreakHit>,
+}
+
 #[derive(Serialize)]
 pub struct ExecutionStep {
     mutations: Vec<MutationOperation>,
@@ -41,16 +103,18 @@ pub struct ExecutionStep {
     line: usize,
     old_ccr: Flags,
     new_ccr: Flags,
+    cycles: u64,
 }
 
 impl ExecutionStep {
-    pub fn new(pc: usize, ccr: Flags) -> Self {
+    pub fn new(pc: usize, ccr: Flags, cycles: u64) -> Self {
         Self {
             mutations: vec![],
             pc,
             old_ccr: ccr,
             new_ccr: ccr,
             line: 0,
+            cycles,
         }
     }
     pub fn add_mutation(&mut self, mutation: MutationOperation) {
@@ -71,30 +135,48 @@ impl ExecutionStep {
     pub fn get_ccr(&self) -> Flags {
         self.old_ccr
     }
+    pub fn get_cycles(&self) -> u64 {
+        self.cycles
+    }
+    /// Index of the line of the instruction
+    pub fn get_line(&self) -> usize {
+        self.line
+    }
 }
-#[wasm_bindgen]
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
 pub struct Debugger {
     history: LinkedList<ExecutionStep>,
     history_size: usize,
     call_stack: Vec<usize>,
-    labels: HashMap<usize, Label>
+    call_sites: Vec<usize>,
+    labels: LabelTable,
+    //the symbol of the label at each address
+    addresses: HashMap<usize, Symbol>,
 }
 
 
 impl Debugger {
-    pub fn new(history_size: usize, labels: &HashMap<String, Label>) -> Self {
-        let mut labels_map = HashMap::new();
-        for label in labels.values() {
-            labels_map.insert(label.address, label.clone());
+    pub fn new(history_size: usize, labels: &LabelTable) -> Self {
+        //a local label can share the address of its owner, the one defined first names the address
+        let mut addresses: HashMap<usize, Symbol> = HashMap::new();
+        for (name, label) in labels.iter() {
+            let Some(symbol) = labels.get_symbols().get(name) else {
+                continue;
+            };
+            match addresses.get(&label.address).and_then(|other| labels.get_by_symbol(*other)) {
+                Some(other) if other.line <= label.line => {}
+                _ => {
+                    addresses.insert(label.address, symbol);
+                }
+            }
         }
-        //include at least one to prevent initialization errors when pushing history state
-        let mut empty_history: LinkedList<ExecutionStep> = LinkedList::new();
-        empty_history.push_front(ExecutionStep::new(0, Flags::empty()));
         Self {
-            history: empty_history,
+            history: LinkedList::new(),
             history_size,
             call_stack: vec![],
-            labels: labels_map
+            call_sites: vec![],
+            labels: labels.clone(),
+            addresses,
         }
     }
     pub fn add_step(&mut self, step: ExecutionStep) {
@@ -118,23 +200,31 @@ impl Debugger {
     pub fn get_last_step(&self) -> Option<&ExecutionStep> {
         self.history.back()
     }
+    /// Keeps the last steps that fit the size, the older ones are forgotten
+    pub fn set_history_size(&mut self, history_size: usize) {
+        self.history_size = history_size;
+        while self.history.len() > history_size {
+            self.history.pop_front();
+        }
+    }
+    pub fn get_history_size(&self) -> usize {
+        self.history_size
+    }
+    //the changes made before the first step, like the registers set by a frontend, are not in the history
     pub fn set_new_ccr(&mut self, ccr: Flags) {
-        self.history
-            .back_mut()
-            .expect("No history to set new ccr")
-            .new_ccr = ccr;
+        if let Some(step) = self.history.back_mut() {
+            step.new_ccr = ccr;
+        }
     }
     pub fn set_line(&mut self, line: usize) {
-        self.history
-            .back_mut()
-            .expect("No history to set new line")
-            .line = line;
+        if let Some(step) = self.history.back_mut() {
+            step.line = line;
+        }
     }
     pub fn add_mutation(&mut self, operation: MutationOperation) {
-        self.history
-            .back_mut()
-            .expect("No history to add mutation to")
-            .add_mutation(operation);
+        if let Some(step) = self.history.back_mut() {
+            step.add_mutation(operation);
+        }
     }
     pub fn get_history(&self) -> &LinkedList<ExecutionStep> {
         &self.history
@@ -146,18 +236,31 @@ impl Debugger {
             .take(count)
             .collect::<Vec<&ExecutionStep>>()
     }
-    pub fn get_labels(&self) -> &HashMap<usize, Label> {
+    pub fn get_labels(&self) -> &LabelTable {
         &self.labels
     }
-    pub fn push_call(&mut self, address: usize) {
+    pub fn get_label_at(&self, address: usize) -> Option<&Label> {
+        self.addresses.get(&address).and_then(|symbol| self.labels.get_by_symbol(*symbol))
+    }
+    /// Pushes the address of the called function and the address of the instruction that called it
+    pub fn push_call(&mut self, address: usize, call_site: usize) {
         self.call_stack.push(address);
+        self.call_sites.push(call_site);
     }
     pub fn pop_call(&mut self) -> Option<usize> {
+        self.call_sites.pop();
         self.call_stack.pop()
     }
+    pub fn get_call_depth(&self) -> usize {
+        self.call_stack.len()
+    }
+    /// Addresses of the instructions that made each call, from the outermost one
+    pub fn get_call_sites(&self) -> &Vec<usize> {
+        &self.call_sites
+    }
     pub fn to_call_stack(&self) -> Vec<Label> {
         self.call_stack.iter().map(|address| {
-            match self.labels.get(address) {
+            match self.get_label_at(*address) {
                 Some(label) => label.clone(),
                 None => Label {
                     name: "".to_string(),
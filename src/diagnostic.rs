@@ -0,0 +1,202 @@
+use alloc::{string::{String, ToString}, vec, vec::Vec};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    assembler::EncodeError,
+    code_actions::TextEdit,
+    lexer::{LexError, TextSpan},
+    semantic_checker::{RelatedSpan, SemanticError},
+};
+
+/*
+    The problems of a program in a single shape, whatever part of the pipeline found them: the lexer errors of an
+    operand, the semantic checker errors and the errors of the compiler and the assembler.
+    Every problem has a stable code, grouped by the part that finds it:
+        E01xx   lexer
+        E02xx   semantic checker
+        E03xx   compiler and assembler
+    The codes are never reused once a problem is removed, new problems get the next free code of their group.
+    A diagnostic has a suggestion when there is a single fix that surely removes it, like the only valid size
+    of an instruction or the mnemonic a misspelled one was meant to be, so editors can apply it without asking
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Severity {
+    Error,
+    Warning,
+    Info,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DiagnosticCode {
+    InvalidRegister,
+    InvalidRegisterRange,
+    InvalidMemoryIndirect,
+    InvalidRegisterSize,
+    InvalidScale,
+    InvalidIndirect,
+    InvalidBitfield,
+    InvalidMacro,
+    InvalidConditional,
+    InvalidRepeat,
+    /// A line the semantic checker refuses that doesn't have a more specific code
+    InvalidStatement,
+    UnknownInstruction,
+    DuplicateLabel,
+    InvalidSize,
+    InvalidAddressingMode,
+    OperandCount,
+    ValueOutOfRange,
+    UnsupportedByModel,
+    NotInLesson,
+    InvalidDirectiveArguments,
+    UnknownDirective,
+    InvalidInstruction,
+    InvalidDirective,
+    OddAddress,
+    InvalidOrg,
+    OrgBelowAddress,
+    InvalidLength,
+    UnencodableOperand,
+    FieldOutOfRange,
+    UnstableLayout,
+}
+
+impl DiagnosticCode {
+    pub fn get_code(&self) -> &'static str {
+        match self {
+            DiagnosticCode::InvalidRegister => "E0101",
+            DiagnosticCode::InvalidRegisterRange => "E0102",
+            DiagnosticCode::InvalidMemoryIndirect => "E0103",
+            DiagnosticCode::InvalidRegisterSize => "E0104",
+            DiagnosticCode::InvalidScale => "E0105",
+            DiagnosticCode::InvalidIndirect => "E0106",
+            DiagnosticCode::InvalidBitfield => "E0107",
+            DiagnosticCode::InvalidMacro => "E0108",
+            DiagnosticCode::InvalidConditional => "E0109",
+            DiagnosticCode::InvalidRepeat => "E0110",
+            DiagnosticCode::InvalidStatement => "E0201",
+            DiagnosticCode::UnknownInstruction => "E0202",
+            DiagnosticCode::DuplicateLabel => "E0203",
+            DiagnosticCode::InvalidSize => "E0204",
+            DiagnosticCode::InvalidAddressingMode => "E0205",
+            DiagnosticCode::OperandCount => "E0206",
+            DiagnosticCode::ValueOutOfRange => "E0207",
+            DiagnosticCode::UnsupportedByModel => "E0208",
+            DiagnosticCode::NotInLesson => "E0209",
+            DiagnosticCode::InvalidDirectiveArguments => "E0210",
+            DiagnosticCode::UnknownDirective => "E0211",
+            DiagnosticCode::InvalidInstruction => "E0301",
+            DiagnosticCode::InvalidDirective => "E0302",
+            DiagnosticCode::OddAddress => "E0303",
+            DiagnosticCode::InvalidOrg => "E0304",
+            DiagnosticCode::OrgBelowAddress => "E0305",
+            DiagnosticCode::InvalidLength => "E0306",
+            DiagnosticCode::UnencodableOperand => "E0307",
+            DiagnosticCode::FieldOutOfRange => "E0308",
+            DiagnosticCode::UnstableLayout => "E0309",
+        }
+    }
+}
+
+impl From<&LexError> for DiagnosticCode {
+    fn from(error: &LexError) -> Self {
+        match error.get_cause() {
+            LexError::InvalidRegister(_) => DiagnosticCode::InvalidRegister,
+            LexError::InvalidRegisterRange(_) => DiagnosticCode::InvalidRegisterRange,
+            LexError::InvalidMemoryIndirect(_) => DiagnosticCode::InvalidMemoryIndirect,
+            LexError::InvalidSize(_) => DiagnosticCode::InvalidRegisterSize,
+            LexError::InvalidScale(_) => DiagnosticCode::InvalidScale,
+            LexError::InvalidIndirect(_) => DiagnosticCode::InvalidIndirect,
+            LexError::InvalidBitfield(_) => DiagnosticCode::InvalidBitfield,
+            LexError::InvalidMacro(_) => DiagnosticCode::InvalidMacro,
+            LexError::InvalidConditional(_) => DiagnosticCode::InvalidConditional,
+            LexError::InvalidRepeat(_) => DiagnosticCode::InvalidRepeat,
+            //the cause is never on a line
+            LexError::OnLine { .. } => DiagnosticCode::InvalidStatement,
+        }
+    }
+}
+
+/// A fix that can be applied without asking anything
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Suggestion {
+    pub message: String,
+    pub edits: Vec<TextEdit>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: DiagnosticCode,
+    /// Missing when the problem is not tied to a place of the source
+    pub span: Option<TextSpan>,
+    pub message: String,
+    pub suggestion: Option<Suggestion>,
+    /// Other places of the code that explain the problem
+    pub related: Vec<RelatedSpan>,
+}
+
+impl Diagnostic {
+    pub fn error(code: DiagnosticCode, span: Option<TextSpan>, message: impl Into<String>) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Error,
+            code,
+            span,
+            message: message.into(),
+            suggestion: None,
+            related: vec![],
+        }
+    }
+    pub fn with_suggestion(mut self, suggestion: Suggestion) -> Self {
+        self.suggestion = Some(suggestion);
+        self
+    }
+    pub fn get_code(&self) -> &'static str {
+        self.code.get_code()
+    }
+    pub fn get_line_index(&self) -> Option<usize> {
+        self.span.map(|span| span.line_index)
+    }
+}
+
+/// The whole line without the whitespace around it
+fn get_line_span(line_index: usize, line: &str) -> TextSpan {
+    let start = line.len() - line.trim_start().len();
+    let end = line.trim_end().len();
+    TextSpan {
+        line_index,
+        start: line[..start.min(end)].encode_utf16().count(),
+        end: line[..end].encode_utf16().count(),
+    }
+}
+
+impl From<&LexError> for Diagnostic {
+    fn from(error: &LexError) -> Self {
+        Diagnostic::error(error.into(), error.get_span(), error.get_cause().to_string())
+    }
+}
+
+impl From<&SemanticError> for Diagnostic {
+    fn from(error: &SemanticError) -> Self {
+        let line = error.get_line();
+        let span = match error.get_span() {
+            Some(span) => span,
+            None if line.code_span == TextSpan::default() => get_line_span(line.line_index, &line.line),
+            None => line.code_span,
+        };
+        let mut diagnostic = Diagnostic::error(error.get_code(), Some(span), error.get_error());
+        diagnostic.related = error.get_related().to_vec();
+        diagnostic
+    }
+}
+
+impl EncodeError {
+    /// The diagnostic of the error, placed on its line of the source of the program
+    pub fn to_diagnostic(&self, source: &str) -> Diagnostic {
+        let span = self
+            .get_line_index()
+            .and_then(|line_index| source.lines().nth(line_index).map(|line| get_line_span(line_index, line)));
+        Diagnostic::error(self.get_code(), span, self.to_string())
+    }
+}
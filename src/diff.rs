@@ -0,0 +1,163 @@
+use alloc::{vec, vec::Vec};
+use serde::Serialize;
+
+use crate::lexer::{LexedLine, LexedOperand, ParsedLine};
+
+/*
+    Difference between two programs on the lexed lines, so comments, whitespace and the way an operand
+    is spaced don't count. The lines of the two programs are matched with the longest common subsequence,
+    what is left between two matches is removed from the first program or inserted in the second.
+    A removed and an inserted line with the same mnemonic or directive are reported as one changed
+    line, with the operands that are different
+*/
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct OperandDiff {
+    pub index: usize,
+    /// Missing when the operand was added
+    pub before: Option<LexedOperand>,
+    /// Missing when the operand was removed
+    pub after: Option<LexedOperand>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", content = "value")]
+pub enum LineDiff {
+    Removed {
+        line_index: usize,
+        line: LexedLine,
+    },
+    Inserted {
+        line_index: usize,
+        line: LexedLine,
+    },
+    Changed {
+        before_index: usize,
+        after_index: usize,
+        before: LexedLine,
+        after: LexedLine,
+        /// Empty when only the size or the directive args changed
+        operands: Vec<OperandDiff>,
+    },
+}
+
+fn is_significant(line: &&ParsedLine) -> bool {
+    !matches!(line.parsed, LexedLine::Comment { .. } | LexedLine::Empty)
+}
+
+fn is_same_statement(a: &LexedLine, b: &LexedLine) -> bool {
+    match (a, b) {
+        (LexedLine::Instruction { name: a, .. }, LexedLine::Instruction { name: b, .. }) => a == b,
+        (LexedLine::Directive { name: a, .. }, LexedLine::Directive { name: b, .. }) => a == b,
+        _ => false,
+    }
+}
+
+fn diff_operands(before: &LexedLine, after: &LexedLine) -> Vec<OperandDiff> {
+    let (before, after) = match (before, after) {
+        (LexedLine::Instruction { operands: a, .. }, LexedLine::Instruction { operands: b, .. }) => (a, b),
+        _ => return vec![],
+    };
+    (0..before.len().max(after.len()))
+        .filter_map(|index| {
+            let (before, after) = (before.get(index), after.get(index));
+            match before == after {
+                true => None,
+                false => Some(OperandDiff {
+                    index,
+                    before: before.cloned(),
+                    after: after.cloned(),
+                }),
+            }
+        })
+        .collect()
+}
+
+/// Pairs of indexes of the elements that are the same in both sequences
+pub(crate) fn longest_common_subsequence<T>(a: &[T], b: &[T], same: impl Fn(&T, &T) -> bool) -> Vec<(usize, usize)> {
+    let mut lengths = vec![vec![0u32; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lengths[i][j] = match same(&a[i], &b[j]) {
+                true => lengths[i + 1][j + 1] + 1,
+                false => lengths[i + 1][j].max(lengths[i][j + 1]),
+            };
+        }
+    }
+    let mut pairs = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if same(&a[i], &b[j]) {
+            pairs.push((i, j));
+            i += 1;
+            j += 1;
+        } else if lengths[i + 1][j] >= lengths[i][j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    pairs
+}
+
+fn diff_gap(removed: &[&ParsedLine], inserted: &[&ParsedLine], result: &mut Vec<LineDiff>) {
+    let mut inserted = inserted.iter().peekable();
+    for before in removed {
+        //the inserted lines before the one that replaces this line are new
+        let replacement = inserted.clone().position(|after| is_same_statement(&before.parsed, &after.parsed));
+        match replacement {
+            Some(position) => {
+                for _ in 0..position {
+                    let after = inserted.next().unwrap();
+                    result.push(LineDiff::Inserted {
+                        line_index: after.line_index,
+                        line: after.parsed.clone(),
+                    });
+                }
+                let after = inserted.next().unwrap();
+                result.push(LineDiff::Changed {
+                    before_index: before.line_index,
+                    after_index: after.line_index,
+                    before: before.parsed.clone(),
+                    after: after.parsed.clone(),
+                    operands: diff_operands(&before.parsed, &after.parsed),
+                });
+            }
+            None => result.push(LineDiff::Removed {
+                line_index: before.line_index,
+                line: before.parsed.clone(),
+            }),
+        }
+    }
+    for after in inserted {
+        result.push(LineDiff::Inserted {
+            line_index: after.line_index,
+            line: after.parsed.clone(),
+        });
+    }
+}
+
+/// What changed from the first program to the second, empty if they do the same thing
+pub fn diff(before: &[ParsedLine], after: &[ParsedLine]) -> Vec<LineDiff> {
+    let before = before.iter().filter(is_significant).collect::<Vec<&ParsedLine>>();
+    let after = after.iter().filter(is_significant).collect::<Vec<&ParsedLine>>();
+    //the common start and end are skipped so the table is only as large as the edited part
+    let prefix = before.iter().zip(after.iter()).take_while(|(a, b)| a.parsed == b.parsed).count();
+    let suffix = before[prefix..]
+        .iter()
+        .rev()
+        .zip(after[prefix..].iter().rev())
+        .take_while(|(a, b)| a.parsed == b.parsed)
+        .count();
+    let before = &before[prefix..before.len() - suffix];
+    let after = &after[prefix..after.len() - suffix];
+    let mut result = vec![];
+    let (mut i, mut j) = (0, 0);
+    for (next_i, next_j) in longest_common_subsequence(before, after, |a, b| a.parsed == b.parsed) {
+        diff_gap(&before[i..next_i], &after[j..next_j], &mut result);
+        i = next_i + 1;
+        j = next_j + 1;
+    }
+    diff_gap(&before[i..], &after[j..], &mut result);
+    result
+}
@@ -0,0 +1,316 @@
+use alloc::{format, string::{String, ToString}, vec, vec::Vec};
+use serde::Serialize;
+
+use crate::{
+    cpu_model::CpuModel,
+    grading::{assemble, get_register_name, run_to_end, RunOutcome},
+    input_generator::InputGenerator,
+    instructions::{RegisterOperand, Size},
+    interpreter::{Interpreter, InterpreterOptions},
+    scripted_input::ScriptedInput,
+};
+
+/*
+    Differential testing against a reference solution, for assignments that have many valid implementations.
+    The program of the student and the reference run on the same inputs, for every input the report has the
+    first divergence between the two: how they ended, then the output, then the registers and memory regions
+    that were chosen to be compared, in that order:
+
+    let report = DifferentialTester::new(reference)
+        .with_input("3\n4")
+        .with_input("-1\n0")
+        .compare_register(RegisterOperand::Data(0))
+        .compare_label("result", 4)
+        .compare(student)?;
+
+    Memory at a label is read at the address of the label in each program, as the two can place it differently.
+    The reference must assemble, otherwise comparing fails
+*/
+
+const DEFAULT_LIMIT: usize = 1_000_000;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", content = "value")]
+pub enum MemoryRegion {
+    Address { address: usize, length: usize },
+    Label { name: String, length: usize },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", content = "value")]
+pub enum Divergence {
+    Outcome { student: RunOutcome, reference: RunOutcome },
+    /// The first character that is different, and its line
+    Output { position: usize, line: usize },
+    Register { register: RegisterOperand, student: u32, reference: u32 },
+    /// The first byte of the region that is different
+    Memory { region: MemoryRegion, offset: usize, student: Option<u8>, reference: Option<u8> },
+    /// The program of the student doesn't have a label of a compared region
+    MissingLabel(String),
+}
+
+impl Divergence {
+    pub fn get_message(&self) -> String {
+        match self {
+            Divergence::Outcome { student, reference } => {
+                format!("The program ended with {:?}, the reference with {:?}", student, reference)
+            }
+            Divergence::Output { position, line } => format!(
+                "The output is different from the reference from character {}, on line {}",
+                position,
+                line + 1
+            ),
+            Divergence::Register {
+                register,
+                student,
+                reference,
+            } => format!(
+                "{} is ${:08X}, the reference has ${:08X}",
+                get_register_name(register),
+                student,
+                reference
+            ),
+            Divergence::Memory {
+                region,
+                offset,
+                student,
+                reference,
+            } => {
+                let byte = |byte: &Option<u8>| match byte {
+                    Some(byte) => format!("${:02X}", byte),
+                    None => "unreadable".to_string(),
+                };
+                let start = match region {
+                    MemoryRegion::Address { address, .. } => format!("${:X}", address),
+                    MemoryRegion::Label { name, .. } => name.clone(),
+                };
+                format!(
+                    "The byte {} of {} is {}, the reference has {}",
+                    offset,
+                    start,
+                    byte(student),
+                    byte(reference)
+                )
+            }
+            Divergence::MissingLabel(name) => format!("The label \"{}\" is missing", name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DifferentialCase {
+    /// Index of the input, in the order they were added
+    pub input_index: usize,
+    /// Missing when the program behaved like the reference
+    pub divergence: Option<Divergence>,
+    pub student_output: String,
+    pub reference_output: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DifferentialReport {
+    /// If the program behaved like the reference on every input
+    pub passed: bool,
+    pub cases: Vec<DifferentialCase>,
+}
+
+impl DifferentialReport {
+    pub fn get_divergent_cases(&self) -> Vec<&DifferentialCase> {
+        self.cases.iter().filter(|case| case.divergence.is_some()).collect()
+    }
+}
+
+/// How a program ended, with the state needed by the comparison
+struct Run {
+    outcome: RunOutcome,
+    output: String,
+    interpreter: Option<Interpreter>,
+}
+
+impl Run {
+    fn get_region_start(&self, region: &MemoryRegion) -> Option<usize> {
+        match region {
+            MemoryRegion::Address { address, .. } => Some(*address),
+            MemoryRegion::Label { name, .. } => self
+                .interpreter
+                .as_ref()?
+                .get_labels()
+                .get(name)
+                .map(|label| label.address),
+        }
+    }
+    fn read_byte(&self, address: usize) -> Option<u8> {
+        let bytes = self.interpreter.as_ref()?.get_memory().read_bytes(address, 1).ok()?;
+        bytes.first().copied()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DifferentialTester {
+    reference: String,
+    inputs: Vec<ScriptedInput>,
+    registers: Vec<RegisterOperand>,
+    regions: Vec<MemoryRegion>,
+    limit: usize,
+    cpu_model: CpuModel,
+}
+
+impl DifferentialTester {
+    pub fn new(reference: &str) -> Self {
+        Self {
+            reference: reference.to_string(),
+            inputs: vec![],
+            registers: vec![],
+            regions: vec![],
+            limit: DEFAULT_LIMIT,
+            cpu_model: CpuModel::default(),
+        }
+    }
+    /// Adds an input to run the programs with, a line for every string or number that is read.
+    /// Without inputs the programs run once with no input
+    pub fn with_input(self, input: &str) -> Self {
+        self.with_scripted_input(ScriptedInput::from_text(input, Default::default()))
+    }
+    pub fn with_scripted_input(mut self, input: ScriptedInput) -> Self {
+        self.inputs.push(input);
+        self
+    }
+    /// Adds the first count cases of the generator, the index of a case is its input index
+    /// if there were no inputs before
+    pub fn with_generated_inputs(mut self, generator: &InputGenerator, count: usize) -> Result<Self, String> {
+        self.inputs.extend(generator.generate_cases(count)?);
+        Ok(self)
+    }
+    pub fn compare_register(mut self, register: RegisterOperand) -> Self {
+        self.registers.push(register);
+        self
+    }
+    pub fn compare_memory(mut self, address: usize, length: usize) -> Self {
+        self.regions.push(MemoryRegion::Address { address, length });
+        self
+    }
+    pub fn compare_label(mut self, name: &str, length: usize) -> Self {
+        self.regions.push(MemoryRegion::Label {
+            name: name.to_string(),
+            length,
+        });
+        self
+    }
+    /// Instructions each program can run before it is stopped
+    pub fn with_limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+    pub fn with_cpu_model(mut self, cpu_model: CpuModel) -> Self {
+        self.cpu_model = cpu_model;
+        self
+    }
+    fn run(&self, code: &str, input: &ScriptedInput) -> Run {
+        let (s68k, compiled) = match assemble(code, self.cpu_model) {
+            Ok(assembled) => assembled,
+            Err(errors) => {
+                return Run {
+                    outcome: RunOutcome::AssemblyFailed(errors),
+                    output: String::new(),
+                    interpreter: None,
+                }
+            }
+        };
+        let options = InterpreterOptions {
+            cpu_model: self.cpu_model,
+            ..Default::default()
+        };
+        let mut interpreter = s68k.create_interpreter(compiled, Some(options));
+        let mut input = input.clone();
+        let mut output = String::new();
+        let (outcome, _) = run_to_end(&mut interpreter, &mut input, &mut output, self.limit, |_, _| {});
+        Run {
+            outcome,
+            output,
+            interpreter: Some(interpreter),
+        }
+    }
+    fn find_divergence(&self, student: &Run, reference: &Run) -> Option<Divergence> {
+        if student.outcome != reference.outcome {
+            return Some(Divergence::Outcome {
+                student: student.outcome.clone(),
+                reference: reference.outcome.clone(),
+            });
+        }
+        if student.output != reference.output {
+            let position = student
+                .output
+                .chars()
+                .zip(reference.output.chars())
+                .position(|(a, b)| a != b)
+                .unwrap_or(student.output.chars().count().min(reference.output.chars().count()));
+            let line = student.output.chars().take(position).filter(|c| *c == '\n').count();
+            return Some(Divergence::Output { position, line });
+        }
+        //both programs assembled, otherwise the outcomes would be different
+        let (student_interpreter, reference_interpreter) = (student.interpreter.as_ref()?, reference.interpreter.as_ref()?);
+        for register in &self.registers {
+            let values = (
+                student_interpreter.get_register_value(register, Size::Long),
+                reference_interpreter.get_register_value(register, Size::Long),
+            );
+            if values.0 != values.1 {
+                return Some(Divergence::Register {
+                    register: *register,
+                    student: values.0,
+                    reference: values.1,
+                });
+            }
+        }
+        for region in &self.regions {
+            let (start, length) = match (student.get_region_start(region), region) {
+                (Some(start), MemoryRegion::Address { length, .. } | MemoryRegion::Label { length, .. }) => {
+                    (start, *length)
+                }
+                (None, MemoryRegion::Label { name, .. }) => return Some(Divergence::MissingLabel(name.clone())),
+                (None, MemoryRegion::Address { .. }) => continue,
+            };
+            let reference_start = reference.get_region_start(region).unwrap_or(start);
+            for offset in 0..length {
+                let bytes = (student.read_byte(start + offset), reference.read_byte(reference_start + offset));
+                if bytes.0 != bytes.1 {
+                    return Some(Divergence::Memory {
+                        region: region.clone(),
+                        offset,
+                        student: bytes.0,
+                        reference: bytes.1,
+                    });
+                }
+            }
+        }
+        None
+    }
+    /// Runs the program of the student and the reference on every input and compares them
+    pub fn compare(&self, student: &str) -> Result<DifferentialReport, String> {
+        if let Err(errors) = assemble(&self.reference, self.cpu_model) {
+            return Err(format!("The reference doesn't assemble:\n{}", errors.join("\n")));
+        }
+        let inputs = match self.inputs.is_empty() {
+            true => vec![ScriptedInput::default()],
+            false => self.inputs.clone(),
+        };
+        let cases: Vec<DifferentialCase> = inputs
+            .iter()
+            .enumerate()
+            .map(|(input_index, input)| {
+                let student_run = self.run(student, input);
+                let reference_run = self.run(&self.reference, input);
+                DifferentialCase {
+                    divergence: self.find_divergence(&student_run, &reference_run),
+                    input_index,
+                    student_output: student_run.output,
+                    reference_output: reference_run.output,
+                }
+            })
+            .collect();
+        Ok(DifferentialReport {
+            passed: cases.iter().all(|case| case.divergence.is_none()),
+            cases,
+        })
+    }
+}
@@ -0,0 +1,527 @@
+use alloc::{format, string::{String, ToString}, vec, vec::Vec};
+use core::{error::Error, fmt};
+
+use crate::{
+    assembler::{Assembler, EncodeError, MachineCode},
+    compiler::Layout,
+    cpu_model::CpuModel,
+    lexer::{LexedLine, ParsedLine},
+    symbol_table::SymbolKind,
+};
+
+/*
+    Relocatable ELF objects for the GNU m68k toolchain, ld places the code and fills the addresses of the labels.
+    The object has a single .text section with the whole program, from the first label or byte to the last byte,
+    the gaps between ORG are zeros. The addresses in the code are found by assembling the program twice
+    at two different places: the fields that change by the distance between the two are the addresses of
+    labels and get a relocation against the start of .text, a field that changes in any other way, like
+    label*2, can't be relocated. Labels are always long absolute addresses in the object since their final
+    address is not known.
+    The symbols of xdef and public are global and the others are local, an equ made of labels is an
+    address in .text, the others are absolute values.
+    The file is ELF32 big endian with RELA relocations like the ones of GNU as:
+        .text           the machine code, a relocated field holds its addend
+        .rela.text      R_68K_32, R_68K_16 and R_68K_8 relocations
+        .symtab         the section and the symbols
+        .strtab         names of the symbols
+        .shstrtab       names of the sections
+*/
+
+/// Where the first copy is placed, past 64k so the addresses of the labels never fit a word
+const FIRST_OFFSET: usize = 0x100000;
+/// Between the two copies, it changes every byte of a long so a word or a byte address is not taken for a long
+const RELOCATION_DISTANCE: usize = 0x01010102;
+/// Where the externals are in the first copy, the start of a program without ORG so a branch can reach them
+const EXTERNAL_ADDRESS: usize = FIRST_OFFSET + 0x1000;
+/// How much an external moves in its copy, it changes both bytes of a word and keeps a branch in range
+const EXTERNAL_DISTANCE: usize = 0x0102;
+
+const SECTION_NAMES: &str = "\0.text\0.rela.text\0.symtab\0.strtab\0.shstrtab\0";
+const HEADER_SIZE: usize = 52;
+const SECTION_HEADER_SIZE: usize = 40;
+const SYMBOL_SIZE: usize = 16;
+const RELOCATION_SIZE: usize = 12;
+
+#[derive(Debug)]
+pub enum ElfError {
+    Encode(EncodeError),
+    /// A value that changes with the address of the program but is not an address
+    Unrelocatable { line_index: usize },
+    /// The instructions have other sizes once the program is moved
+    UnstableLayout,
+}
+
+impl fmt::Display for ElfError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ElfError::Encode(error) => write!(f, "{}", error),
+            ElfError::Unrelocatable { line_index } => write!(
+                f,
+                "The value at line {} uses labels but is not an address, it can't be relocated",
+                line_index
+            ),
+            ElfError::UnstableLayout => write!(f, "The program changes size once it is moved"),
+        }
+    }
+}
+
+impl Error for ElfError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ElfError::Encode(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<EncodeError> for ElfError {
+    fn from(error: EncodeError) -> Self {
+        ElfError::Encode(error)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelocationKind {
+    Absolute32,
+    Absolute16,
+    Absolute8,
+    /// The distance from the field to the target, like the displacement of a branch
+    Relative16,
+}
+
+impl RelocationKind {
+    pub fn get_width(&self) -> usize {
+        match self {
+            RelocationKind::Absolute32 => 4,
+            RelocationKind::Absolute16 | RelocationKind::Relative16 => 2,
+            RelocationKind::Absolute8 => 1,
+        }
+    }
+    /// R_68K_32, R_68K_16, R_68K_8 and R_68K_PC16
+    pub fn get_elf_type(&self) -> u8 {
+        match self {
+            RelocationKind::Absolute32 => 1,
+            RelocationKind::Absolute16 => 2,
+            RelocationKind::Absolute8 => 3,
+            RelocationKind::Relative16 => 5,
+        }
+    }
+    fn get_mask(&self) -> u32 {
+        match self.get_width() {
+            4 => u32::MAX,
+            width => (1 << (width * 8)) - 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RelocationTarget {
+    /// The start of .text of the object
+    Section,
+    /// A symbol imported with XREF
+    External(String),
+}
+
+/// A field of .text that holds the address of the target plus the addend, or the distance to it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Relocation {
+    pub offset: usize,
+    pub kind: RelocationKind,
+    pub target: RelocationTarget,
+    pub addend: i64,
+    pub line_index: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolSection {
+    /// The value is an offset in .text
+    Text,
+    Absolute,
+    /// Imported with XREF, defined by another object
+    Undefined,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectSymbol {
+    pub name: String,
+    /// Offset in .text, the value itself when it is absolute or 0 when it is undefined
+    pub value: i64,
+    pub section: SymbolSection,
+    pub global: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct ObjectFile {
+    /// The bytes of .text
+    pub code: Vec<u8>,
+    pub relocations: Vec<Relocation>,
+    pub symbols: Vec<ObjectSymbol>,
+    pub cpu_model: CpuModel,
+}
+
+fn read_field(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0, |value, byte| (value << 8) | *byte as u32)
+}
+
+fn write_field(bytes: &mut [u8], value: u32) {
+    let width = bytes.len();
+    bytes.copy_from_slice(&value.to_be_bytes()[4 - width..]);
+}
+
+fn sign_extend(value: u32, width: usize) -> i64 {
+    match width {
+        4 => value as i32 as i64,
+        2 => value as u16 as i16 as i64,
+        _ => value as u8 as i8 as i64,
+    }
+}
+
+/// How much the field changed in the other copy, in the bits of the field
+fn get_change(first: &[u8], other: &[u8], field: usize, end: usize) -> u32 {
+    let change = read_field(&other[field..end]).wrapping_sub(read_field(&first[field..end]));
+    match end - field {
+        4 => change,
+        width => change & ((1 << (width * 8)) - 1),
+    }
+}
+
+/// The names of the symbols of the xref or the xdef and public directives
+pub(crate) fn get_module_symbols(lines: &[ParsedLine], directives: &[&str]) -> Vec<String> {
+    let mut symbols: Vec<String> = vec![];
+    for line in lines {
+        match &line.parsed {
+            LexedLine::Directive { name, args, .. } if directives.contains(&name.as_str()) => {
+                for symbol in &args[1..] {
+                    if !symbols.contains(symbol) {
+                        symbols.push(symbol.clone());
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    symbols
+}
+
+/// The program assembled at two places, .text starts at start in the first one, and once for every external
+/// with only that external moved
+struct Copies<'a> {
+    first: &'a MachineCode,
+    moved: &'a MachineCode,
+    externals: Vec<(&'a str, &'a MachineCode)>,
+    start: usize,
+}
+
+impl Copies<'_> {
+    /// The fields that change when the whole program moves, from the end so a field that spills in the bytes
+    /// before the change is read whole
+    fn find_absolute_fields(first: &[u8], moved: &[u8]) -> Option<Vec<(usize, RelocationKind)>> {
+        let mut fields = vec![];
+        let mut end = first.len();
+        while end > 0 {
+            if first[end - 1] == moved[end - 1] {
+                end -= 1;
+                continue;
+            }
+            let kind = [
+                RelocationKind::Absolute32,
+                RelocationKind::Absolute16,
+                RelocationKind::Absolute8,
+            ]
+            .into_iter()
+            .find(|kind| match end.checked_sub(kind.get_width()) {
+                Some(field) => get_change(first, moved, field, end) == RELOCATION_DISTANCE as u32 & kind.get_mask(),
+                None => false,
+            })?;
+            end -= kind.get_width();
+            fields.push((end, kind));
+        }
+        Some(fields)
+    }
+    fn find_relocations(&self, relocations: &mut Vec<Relocation>, code: &mut [u8]) -> Result<(), ElfError> {
+        for (i, line) in self.first.lines.iter().enumerate() {
+            let moved = &self.moved.lines[i];
+            let unstable = moved.length != line.length
+                || moved.address != line.address + RELOCATION_DISTANCE
+                || self.externals.iter().any(|(_, copy)| copy.lines[i] != *line);
+            if unstable {
+                return Err(ElfError::UnstableLayout);
+            }
+            let unrelocatable = || ElfError::Unrelocatable {
+                line_index: line.line_index,
+            };
+            let first = self.first.get_line_bytes(line);
+            let externals: Vec<(&str, &[u8])> = self
+                .externals
+                .iter()
+                .map(|(name, copy)| (*name, copy.get_line_bytes(line)))
+                .collect();
+            //the fields of the line with what they point to and the addend
+            let mut found: Vec<(usize, RelocationKind, RelocationTarget, i64)> = vec![];
+            let fields =
+                Copies::find_absolute_fields(first, self.moved.get_line_bytes(moved)).ok_or_else(unrelocatable)?;
+            for (field, kind) in fields {
+                let end = field + kind.get_width();
+                let value = read_field(&first[field..end]);
+                //the address of an external changes only in its copy
+                let mut changed = externals.iter().filter(|(_, bytes)| bytes[field..end] != first[field..end]);
+                match (changed.next(), changed.next()) {
+                    (None, _) => {
+                        let addend = value.wrapping_sub(self.start as u32) & kind.get_mask();
+                        found.push((field, kind, RelocationTarget::Section, sign_extend(addend, 4)))
+                    }
+                    (Some((name, bytes)), None)
+                        if get_change(first, bytes, field, end) == EXTERNAL_DISTANCE as u32 & kind.get_mask() =>
+                    {
+                        let addend = value.wrapping_sub(EXTERNAL_ADDRESS as u32) & kind.get_mask();
+                        let target = RelocationTarget::External(name.to_string());
+                        found.push((field, kind, target, sign_extend(addend, 4)))
+                    }
+                    _ => return Err(unrelocatable()),
+                }
+            }
+            //what changes with an external but not with the whole program is a distance to it
+            let mut relocated = vec![false; first.len()];
+            for (field, kind, _, _) in &found {
+                relocated[*field..*field + kind.get_width()].fill(true);
+            }
+            for (name, bytes) in &externals {
+                let mut end = first.len();
+                while end > 0 {
+                    if first[end - 1] == bytes[end - 1] || relocated[end - 1] {
+                        end -= 1;
+                        continue;
+                    }
+                    let kind = RelocationKind::Relative16;
+                    let field = match end.checked_sub(kind.get_width()) {
+                        Some(field) if get_change(first, bytes, field, end) == EXTERNAL_DISTANCE as u32 => field,
+                        _ => return Err(unrelocatable()),
+                    };
+                    if relocated[field..end].iter().any(|relocated| *relocated) {
+                        return Err(unrelocatable());
+                    }
+                    relocated[field..end].fill(true);
+                    //the field has the address of the external minus its own plus the addend
+                    let value = sign_extend(read_field(&first[field..end]), 2);
+                    let addend = value - EXTERNAL_ADDRESS as i64 + (line.address + field) as i64;
+                    found.push((field, kind, RelocationTarget::External(name.to_string()), addend));
+                    end = field;
+                }
+            }
+            for (field, kind, target, addend) in found {
+                let offset = line.address - self.start + field;
+                write_field(&mut code[offset..offset + kind.get_width()], addend as u32 & kind.get_mask());
+                relocations.push(Relocation {
+                    offset,
+                    kind,
+                    target,
+                    addend,
+                    line_index: line.line_index,
+                });
+            }
+        }
+        relocations.sort_by_key(|relocation| relocation.offset);
+        Ok(())
+    }
+}
+
+impl ObjectFile {
+    pub fn new(lines: &[ParsedLine], cpu_model: CpuModel) -> Result<ObjectFile, ElfError> {
+        let imports = get_module_symbols(lines, &["xref"]);
+        let exports = get_module_symbols(lines, &["xdef", "public"]);
+        //the externals move with the program, only the one of its copy moves alone
+        let assemble = |offset: usize, moved: Option<&str>| {
+            let externals = imports
+                .iter()
+                .map(|name| {
+                    let distance = match moved == Some(name.as_str()) {
+                        true => EXTERNAL_DISTANCE,
+                        false => 0,
+                    };
+                    (name.clone(), EXTERNAL_ADDRESS - FIRST_OFFSET + offset + distance)
+                })
+                .collect();
+            let layout = Layout { offset, externals };
+            Assembler::new_with_layout(lines, cpu_model, layout)
+        };
+        let first = assemble(FIRST_OFFSET, None)?;
+        let moved = assemble(FIRST_OFFSET + RELOCATION_DISTANCE, None)?;
+        let externals = imports
+            .iter()
+            .map(|name| assemble(FIRST_OFFSET, Some(name)))
+            .collect::<Result<Vec<Assembler>, EncodeError>>()?;
+        let (first_code, moved_code) = (first.get_machine_code(), moved.get_machine_code());
+        let copies = [moved_code].into_iter().chain(externals.iter().map(Assembler::get_machine_code));
+        for copy in copies {
+            if copy.bytes.len() != first_code.bytes.len() || copy.lines.len() != first_code.lines.len() {
+                return Err(ElfError::UnstableLayout);
+            }
+        }
+        let first_symbols = first.get_symbol_table();
+        let labels = first_symbols.get_labels().map(|label| label.value as usize);
+        let start = match first_code.bytes.is_empty() {
+            true => labels.min().unwrap_or(0),
+            false => labels.chain([first_code.origin]).min().unwrap_or(0),
+        };
+        let mut code = match first_code.bytes.is_empty() {
+            true => vec![],
+            false => vec![0; first_code.origin - start],
+        };
+        code.extend_from_slice(&first_code.bytes);
+        let mut relocations = vec![];
+        Copies {
+            first: first_code,
+            moved: moved_code,
+            externals: imports
+                .iter()
+                .map(String::as_str)
+                .zip(externals.iter().map(Assembler::get_machine_code))
+                .collect(),
+            start,
+        }
+        .find_relocations(&mut relocations, &mut code)?;
+        let mut symbols: Vec<ObjectSymbol> = first_symbols
+            .iter()
+            .zip(moved.get_symbol_table().iter())
+            .filter(|(symbol, _)| symbol.kind != SymbolKind::External)
+            .filter_map(|(symbol, moved)| {
+                let section = match moved.value - symbol.value {
+                    0 => SymbolSection::Absolute,
+                    distance if distance == RELOCATION_DISTANCE as i64 => SymbolSection::Text,
+                    //an equ that is not an address
+                    _ => return None,
+                };
+                Some(ObjectSymbol {
+                    name: symbol.name.clone(),
+                    value: match section {
+                        SymbolSection::Text => symbol.value - start as i64,
+                        _ => symbol.value,
+                    },
+                    section,
+                    global: exports.contains(&symbol.name),
+                })
+            })
+            .collect();
+        symbols.extend(imports.iter().map(|name| ObjectSymbol {
+            name: name.clone(),
+            value: 0,
+            section: SymbolSection::Undefined,
+            global: true,
+        }));
+        Ok(ObjectFile {
+            code,
+            relocations,
+            symbols,
+            cpu_model,
+        })
+    }
+    fn get_flags(&self) -> u32 {
+        match self.cpu_model {
+            CpuModel::M68000 => 0x01000000, //EF_M68K_M68000
+            CpuModel::CPU32 => 0x00810000,  //EF_M68K_CPU32
+            _ => 0,
+        }
+    }
+    /// The object as an ELF file
+    pub fn to_elf(&self) -> Vec<u8> {
+        let section_name = |name: &str| SECTION_NAMES.find(&format!("\0{}\0", name)).unwrap() as u32 + 1;
+        //the locals come before the globals, after the null symbol and the one of .text
+        let mut symbols: Vec<&ObjectSymbol> = self.symbols.iter().filter(|symbol| !symbol.global).collect();
+        let first_global = symbols.len() + 2;
+        symbols.extend(self.symbols.iter().filter(|symbol| symbol.global));
+        let mut names = vec![0u8];
+        let mut symtab = vec![0u8; SYMBOL_SIZE];
+        push_symbol(&mut symtab, 0, 0, 3, 1); //STT_SECTION, STB_LOCAL
+        for symbol in &symbols {
+            let name = names.len() as u32;
+            names.extend_from_slice(symbol.name.as_bytes());
+            names.push(0);
+            let binding = match symbol.global {
+                true => 1 << 4, //STB_GLOBAL
+                false => 0,
+            };
+            let section = match symbol.section {
+                SymbolSection::Text => 1,
+                SymbolSection::Absolute => 0xFFF1, //SHN_ABS
+                SymbolSection::Undefined => 0,
+            };
+            push_symbol(&mut symtab, name, symbol.value as u32, binding, section);
+        }
+        let mut rela = vec![];
+        for relocation in &self.relocations {
+            let symbol = match &relocation.target {
+                RelocationTarget::Section => 1,
+                RelocationTarget::External(name) => {
+                    let index = symbols.iter().position(|symbol| symbol.name == *name).unwrap_or_default();
+                    index as u32 + 2
+                }
+            };
+            push_u32(&mut rela, relocation.offset as u32);
+            push_u32(&mut rela, (symbol << 8) | relocation.kind.get_elf_type() as u32);
+            push_u32(&mut rela, relocation.addend as u32);
+        }
+        let mut elf = vec![];
+        let mut sections: Vec<[u32; 10]> = vec![[0; 10]];
+        elf.resize(HEADER_SIZE, 0);
+        let offset = place(&mut elf, &self.code, 2);
+        //name, type, flags, address, offset, size, link, info, align, entry size
+        sections.push([section_name(".text"), 1, 6, 0, offset, self.code.len() as u32, 0, 0, 2, 0]);
+        let offset = place(&mut elf, &rela, 4);
+        sections.push([section_name(".rela.text"), 4, 0x40, 0, offset, rela.len() as u32, 3, 1, 4, RELOCATION_SIZE as u32]);
+        let offset = place(&mut elf, &symtab, 4);
+        sections.push([section_name(".symtab"), 2, 0, 0, offset, symtab.len() as u32, 4, first_global as u32, 4, SYMBOL_SIZE as u32]);
+        let offset = place(&mut elf, &names, 1);
+        sections.push([section_name(".strtab"), 3, 0, 0, offset, names.len() as u32, 0, 0, 1, 0]);
+        let offset = place(&mut elf, SECTION_NAMES.as_bytes(), 1);
+        sections.push([section_name(".shstrtab"), 3, 0, 0, offset, SECTION_NAMES.len() as u32, 0, 0, 1, 0]);
+        let section_headers = place(&mut elf, &[], 4);
+        for section in &sections {
+            for value in section {
+                push_u32(&mut elf, *value);
+            }
+        }
+        let mut header = vec![0x7F, b'E', b'L', b'F', 1, 2, 1]; //32 bits, big endian, version 1
+        header.resize(16, 0);
+        push_u16(&mut header, 1); //ET_REL
+        push_u16(&mut header, 4); //EM_68K
+        push_u32(&mut header, 1);
+        push_u32(&mut header, 0); //entry
+        push_u32(&mut header, 0); //program headers
+        push_u32(&mut header, section_headers);
+        push_u32(&mut header, self.get_flags());
+        push_u16(&mut header, HEADER_SIZE as u16);
+        push_u16(&mut header, 0);
+        push_u16(&mut header, 0);
+        push_u16(&mut header, SECTION_HEADER_SIZE as u16);
+        push_u16(&mut header, sections.len() as u16);
+        push_u16(&mut header, sections.len() as u16 - 1);
+        elf[..HEADER_SIZE].copy_from_slice(&header);
+        elf
+    }
+}
+
+/// Appends the data aligned, returns where it starts
+fn place(elf: &mut Vec<u8>, data: &[u8], align: usize) -> u32 {
+    while !elf.len().is_multiple_of(align) {
+        elf.push(0);
+    }
+    elf.extend_from_slice(data);
+    (elf.len() - data.len()) as u32
+}
+
+fn push_u16(bytes: &mut Vec<u8>, value: u16) {
+    bytes.extend_from_slice(&value.to_be_bytes());
+}
+
+fn push_u32(bytes: &mut Vec<u8>, value: u32) {
+    bytes.extend_from_slice(&value.to_be_bytes());
+}
+
+fn push_symbol(symtab: &mut Vec<u8>, name: u32, value: u32, info: u8, section: u16) {
+    push_u32(symtab, name);
+    push_u32(symtab, value);
+    push_u32(symtab, 0);
+    symtab.push(info);
+    symtab.push(0);
+    push_u16(symtab, section);
+}
@@ -0,0 +1,73 @@
+use core::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/*
+    The exceptions of the 68000 a program can handle. When the vector holds the address of an instruction the
+    interpreter takes the exception like the cpu does: the frame is pushed on the supervisor stack, the S bit is
+    set and the trace bit cleared, then the handler runs until its RTE. A vector that doesn't point to an
+    instruction has no handler and the program stops with the error, TRAP #15 is always the Easy68K IO and never
+    goes through its vector.
+    The stack frames, from the top of the stack:
+        68000            SR, PC
+        68000 address    function code, access address, instruction register, SR, PC
+        68010            SR, PC, format 0 and vector offset
+        68020 and CPU32  SR, PC, format 2 and vector offset, address of the instruction for CHK, TRAPV and divide by zero
+    The PC is the one of the next instruction, for the illegal instruction and the privilege violation it is the
+    one of the instruction itself so the handler can emulate it. The instruction register of the address error
+    frame is 0 as the program is not in memory.
+    The address error frame of the 68010 and later models is not emulated, there the error stops the program
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "value")]
+pub enum Exception {
+    AddressError,
+    IllegalInstruction,
+    DivideByZero,
+    Chk,
+    TrapV,
+    PrivilegeViolation,
+    /// TRAP #n, from 0 to 14
+    Trap(u8),
+    /// Interrupt of the level, from 1 to 7
+    Autovector(u8),
+}
+
+impl Exception {
+    pub fn get_vector(&self) -> u8 {
+        match self {
+            Exception::AddressError => 3,
+            Exception::IllegalInstruction => 4,
+            Exception::DivideByZero => 5,
+            Exception::Chk => 6,
+            Exception::TrapV => 7,
+            Exception::PrivilegeViolation => 8,
+            Exception::Trap(n) => 32 + n,
+            Exception::Autovector(level) => 24 + level,
+        }
+    }
+    /// The pushed PC is the one of the instruction that caused it instead of the next one
+    pub fn restarts_instruction(&self) -> bool {
+        matches!(self, Exception::IllegalInstruction | Exception::PrivilegeViolation)
+    }
+    /// Exceptions that push the address of the instruction in the format 2 frame of the 68020 and CPU32
+    pub fn has_instruction_address(&self) -> bool {
+        matches!(self, Exception::DivideByZero | Exception::Chk | Exception::TrapV)
+    }
+}
+
+impl fmt::Display for Exception {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Exception::AddressError => write!(f, "address error"),
+            Exception::IllegalInstruction => write!(f, "illegal instruction"),
+            Exception::DivideByZero => write!(f, "divide by zero"),
+            Exception::Chk => write!(f, "CHK out of bounds"),
+            Exception::TrapV => write!(f, "TRAPV overflow"),
+            Exception::PrivilegeViolation => write!(f, "privilege violation"),
+            Exception::Trap(n) => write!(f, "TRAP #{}", n),
+            Exception::Autovector(level) => write!(f, "level {} interrupt", level),
+        }
+    }
+}
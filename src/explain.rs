@@ -0,0 +1,558 @@
+use alloc::{format, string::{String, ToString}, vec, vec::Vec};
+use serde::Serialize;
+
+use crate::{
+    cost_report::get_encoded_size,
+    instructions::{Condition, Instruction, Operand, RegisterOperand, ShiftDirection, Sign, Size, TargetDirection},
+    interpreter::{Flags, Interpreter, RuntimeError, RuntimeResult},
+    printer::print_register_mask,
+};
+
+/*
+    Explain mode, every step is narrated in plain language for teaching UIs:
+
+        moved the long word 4 from D0 into the address held in A1, then incremented A1 by 4; Z cleared
+
+    The narration comes from the decoded instruction and from what the step changed: the registers and flags
+    are compared before and after it, the value written to memory is read at the address the destination
+    pointed to before the step. The memory indirect modes of the 68020 are only called "memory"
+*/
+
+const FLAG_NAMES: [(Flags, &str); 5] = [
+    (Flags::Extend, "X"),
+    (Flags::Negative, "N"),
+    (Flags::Zero, "Z"),
+    (Flags::Overflow, "V"),
+    (Flags::Carry, "C"),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Explanation {
+    pub address: usize,
+    pub line_index: usize,
+    pub text: String,
+}
+
+/// The registers and flags before or after a step
+struct Snapshot {
+    d: [u32; 8],
+    a: [u32; 8],
+    flags: Vec<bool>,
+    pc: usize,
+}
+
+impl Snapshot {
+    fn new(interpreter: &Interpreter) -> Self {
+        let mut d = [0; 8];
+        let mut a = [0; 8];
+        for i in 0..8 {
+            d[i] = interpreter.get_register_value(&RegisterOperand::Data(i as u8), Size::Long);
+            a[i] = interpreter.get_register_value(&RegisterOperand::Address(i as u8), Size::Long);
+        }
+        Self {
+            d,
+            a,
+            flags: FLAG_NAMES.iter().map(|(flag, _)| interpreter.get_flag(*flag)).collect(),
+            pc: interpreter.get_pc(),
+        }
+    }
+    fn get_register(&self, register: &RegisterOperand) -> u32 {
+        match register {
+            RegisterOperand::Data(index) => self.d[*index as usize],
+            RegisterOperand::Address(index) => self.a[*index as usize],
+        }
+    }
+    /// Address the operand pointed to with these registers, none for registers, immediates and memory indirect
+    fn get_address(&self, operand: &Operand, size: Size) -> Option<usize> {
+        let address = match operand {
+            Operand::Indirect(index) | Operand::PostIndirect(index) => self.a[*index as usize],
+            Operand::PreIndirect(index) => self.a[*index as usize].wrapping_sub(size.to_bytes() as u32),
+            Operand::IndirectDisplacement { offset, base } => self.get_register(base).wrapping_add(*offset as u32),
+            Operand::IndirectIndex { base, offset, index } => {
+                let index_value = match index.size {
+                    Size::Word => self.get_register(&index.register) as u16 as i16 as u32,
+                    _ => self.get_register(&index.register),
+                };
+                self.get_register(base)
+                    .wrapping_add(*offset as u32)
+                    .wrapping_add(index_value.wrapping_mul(index.scale as u32))
+            }
+            Operand::Absolute(address) => *address as u32,
+            Operand::Immediate(_) | Operand::Register(_) | Operand::RegisterList(_) | Operand::MemoryIndirect { .. } => {
+                return None
+            }
+        };
+        Some(address as usize & 0x00FFFFFF)
+    }
+}
+
+fn get_register_name(register: &RegisterOperand) -> String {
+    match register {
+        RegisterOperand::Data(index) => format!("D{}", index),
+        RegisterOperand::Address(index) => format!("A{}", index),
+    }
+}
+
+fn get_size_name(size: Size) -> &'static str {
+    match size {
+        Size::Byte => "byte",
+        Size::Word => "word",
+        Size::Long => "long word",
+    }
+}
+
+fn format_value(value: u32) -> String {
+    match value < 10 {
+        true => value.to_string(),
+        false => format!("${:X}", value),
+    }
+}
+
+fn mask(value: u32, size: Size) -> u32 {
+    match size {
+        Size::Byte => value & 0xFF,
+        Size::Word => value & 0xFFFF,
+        Size::Long => value,
+    }
+}
+
+fn get_condition_name(condition: &Condition) -> &'static str {
+    match condition {
+        Condition::True => "true",
+        Condition::False => "false",
+        Condition::High => "higher",
+        Condition::LowOrSame => "lower or same",
+        Condition::CarryClear => "carry clear",
+        Condition::CarrySet => "carry set",
+        Condition::NotEqual => "not equal",
+        Condition::Equal => "equal",
+        Condition::OverflowClear => "overflow clear",
+        Condition::OverflowSet => "overflow set",
+        Condition::Plus => "plus",
+        Condition::Minus => "minus",
+        Condition::GreaterThanOrEqual => "greater or equal",
+        Condition::LessThan => "less than",
+        Condition::GreaterThan => "greater than",
+        Condition::LessThanOrEqual => "less or equal",
+    }
+}
+
+struct Narrator<'a> {
+    interpreter: &'a Interpreter,
+    before: &'a Snapshot,
+    after: &'a Snapshot,
+}
+
+impl Narrator<'_> {
+    fn get_target_name(&self, address: usize) -> String {
+        match self.interpreter.get_label_at(address) {
+            Some(label) => label.name.clone(),
+            None => format!("${:X}", address),
+        }
+    }
+    /// Address the operand pointed to before the step, none for registers, immediates and memory indirect
+    fn get_address(&self, operand: &Operand, size: Size) -> Option<usize> {
+        self.before.get_address(operand, size)
+    }
+    fn describe(&self, operand: &Operand) -> String {
+        match operand {
+            Operand::Immediate(value) => format_value(*value),
+            Operand::Register(register) => get_register_name(register),
+            Operand::Indirect(index) | Operand::PostIndirect(index) | Operand::PreIndirect(index) => {
+                format!("the address held in A{}", index)
+            }
+            Operand::IndirectDisplacement { offset, base } => {
+                format!("the address held in {} plus {}", get_register_name(base), offset)
+            }
+            Operand::IndirectIndex { base, offset, index } => format!(
+                "the address held in {} plus {} plus {}",
+                get_register_name(base),
+                get_register_name(&index.register),
+                offset
+            ),
+            Operand::Absolute(address) => format!("the address {}", self.get_target_name(*address)),
+            Operand::MemoryIndirect { .. } => "memory".to_string(),
+            Operand::RegisterList(mask) => format!("the registers {}", print_register_mask(*mask)),
+        }
+    }
+    /// The value of the operand after the step
+    fn get_value(&self, operand: &Operand, size: Size) -> Option<u32> {
+        match operand {
+            Operand::Register(register) => Some(mask(self.after.get_register(register), size)),
+            Operand::Immediate(value) => Some(mask(*value, size)),
+            _ => {
+                let address = self.get_address(operand, size)?;
+                let bytes = self.interpreter.get_memory().read_bytes(address, size.to_bytes()).ok()?;
+                Some(bytes.iter().fold(0, |value, byte| (value << 8) | *byte as u32))
+            }
+        }
+    }
+    fn describe_result(&self, operand: &Operand, size: Size) -> String {
+        match self.get_value(operand, size) {
+            Some(value) => format!(", which is now {}", format_value(value)),
+            None => String::new(),
+        }
+    }
+    fn describe_branch(&self, target: u32, condition: &Condition) -> String {
+        let name = self.get_target_name(target as usize);
+        match self.after.pc == target as usize {
+            true => format!("jumped to {} because the condition {} was true", name, get_condition_name(condition)),
+            false => format!(
+                "didn't jump to {} because the condition {} was false",
+                name,
+                get_condition_name(condition)
+            ),
+        }
+    }
+    fn describe_instruction(&self, instruction: &Instruction, address: usize) -> String {
+        let jumped = self.after.pc != address + get_encoded_size(instruction);
+        match instruction {
+            Instruction::MOVE(source, dest, size) | Instruction::MOVES(source, dest, size) => {
+                let value = self
+                    .get_value(dest, *size)
+                    .map(|value| format!(" {}", format_value(value)))
+                    .unwrap_or_default();
+                format!(
+                    "moved the {}{} from {} into {}",
+                    get_size_name(*size),
+                    value,
+                    self.describe(source),
+                    self.describe(dest)
+                )
+            }
+            Instruction::MOVEA(source, register, _) => format!(
+                "moved {} from {} into {}",
+                format_value(self.after.get_register(register)),
+                self.describe(source),
+                get_register_name(register)
+            ),
+            Instruction::MOVEQ(_, register) => format!(
+                "moved the long word {} into {}",
+                format_value(self.after.get_register(register)),
+                get_register_name(register)
+            ),
+            Instruction::MOVEM { direction, registers_mask, target, .. } => format!(
+                "moved {} registers {} {}",
+                registers_mask.count_ones(),
+                match direction {
+                    TargetDirection::ToMemory => "into",
+                    TargetDirection::FromMemory => "from",
+                },
+                self.describe(target)
+            ),
+            Instruction::ADD(source, dest, size) | Instruction::SUB(source, dest, size) => {
+                let verb = match instruction {
+                    Instruction::ADD(..) => "added",
+                    _ => "subtracted",
+                };
+                let preposition = match instruction {
+                    Instruction::ADD(..) => "to",
+                    _ => "from",
+                };
+                format!(
+                    "{} {} {} {}{}",
+                    verb,
+                    self.describe(source),
+                    preposition,
+                    self.describe(dest),
+                    self.describe_result(dest, *size)
+                )
+            }
+            Instruction::ADDA(source, register, _) => format!(
+                "added {} to {}{}",
+                self.describe(source),
+                get_register_name(register),
+                self.describe_result(&Operand::Register(*register), Size::Long)
+            ),
+            Instruction::SUBA(source, register, _) => format!(
+                "subtracted {} from {}{}",
+                self.describe(source),
+                get_register_name(register),
+                self.describe_result(&Operand::Register(*register), Size::Long)
+            ),
+            Instruction::ADDQ(value, dest, size) => format!(
+                "added {} to {}{}",
+                value,
+                self.describe(dest),
+                self.describe_result(dest, *size)
+            ),
+            Instruction::SUBQ(value, dest, size) => format!(
+                "subtracted {} from {}{}",
+                value,
+                self.describe(dest),
+                self.describe_result(dest, *size)
+            ),
+            Instruction::ADDI(value, dest, size) => format!(
+                "added {} to {}{}",
+                format_value(*value),
+                self.describe(dest),
+                self.describe_result(dest, *size)
+            ),
+            Instruction::SUBI(value, dest, size) => format!(
+                "subtracted {} from {}{}",
+                format_value(*value),
+                self.describe(dest),
+                self.describe_result(dest, *size)
+            ),
+            Instruction::AND(source, dest, size) | Instruction::OR(source, dest, size) | Instruction::EOR(source, dest, size) => {
+                let operation = match instruction {
+                    Instruction::AND(..) => "AND",
+                    Instruction::OR(..) => "OR",
+                    _ => "exclusive OR",
+                };
+                format!(
+                    "combined {} with {} using {}{}",
+                    self.describe(dest),
+                    self.describe(source),
+                    operation,
+                    self.describe_result(dest, *size)
+                )
+            }
+            Instruction::ANDI(value, dest, size) | Instruction::ORI(value, dest, size) | Instruction::EORI(value, dest, size) => {
+                let operation = match instruction {
+                    Instruction::ANDI(..) => "AND",
+                    Instruction::ORI(..) => "OR",
+                    _ => "exclusive OR",
+                };
+                format!(
+                    "combined {} with {} using {}{}",
+                    self.describe(dest),
+                    format_value(*value),
+                    operation,
+                    self.describe_result(dest, *size)
+                )
+            }
+            Instruction::CMP(source, register, _) | Instruction::CMPA(source, register, _) => {
+                format!("compared {} with {}", get_register_name(register), self.describe(source))
+            }
+            Instruction::CMPI(value, dest, _) => format!("compared {} with {}", self.describe(dest), format_value(*value)),
+            Instruction::CMPM(source, dest, _) => format!("compared {} with {}", self.describe(dest), self.describe(source)),
+            Instruction::TST(operand, size) => {
+                let value = self
+                    .get_value(operand, *size)
+                    .map(|value| format!(", which is {}", format_value(value)))
+                    .unwrap_or_default();
+                format!("tested the {} in {}{}", get_size_name(*size), self.describe(operand), value)
+            }
+            Instruction::CLR(operand, size) => format!("cleared the {} in {}", get_size_name(*size), self.describe(operand)),
+            Instruction::NEG(operand, size) => {
+                format!("negated {}{}", self.describe(operand), self.describe_result(operand, *size))
+            }
+            Instruction::NOT(operand, size) => format!(
+                "inverted the bits of {}{}",
+                self.describe(operand),
+                self.describe_result(operand, *size)
+            ),
+            Instruction::EXT(register, from, to) => format!(
+                "extended the sign of {} from a {} to a {}",
+                get_register_name(register),
+                get_size_name(*from),
+                get_size_name(*to)
+            ),
+            Instruction::SWAP(register) => format!("swapped the two words of {}", get_register_name(register)),
+            Instruction::EXG(first, second) => {
+                format!("exchanged {} and {}", get_register_name(first), get_register_name(second))
+            }
+            Instruction::LEA(_, register) => format!(
+                "loaded the address {} into {}",
+                self.get_target_name(self.after.get_register(register) as usize),
+                get_register_name(register)
+            ),
+            Instruction::PEA(operand) => format!(
+                "pushed the address of {} on the stack",
+                self.describe(operand)
+            ),
+            Instruction::MULx(source, register, sign) | Instruction::DIVx(source, register, sign) => {
+                let verb = match instruction {
+                    Instruction::MULx(..) => "multiplied",
+                    _ => "divided",
+                };
+                let sign = match sign {
+                    Sign::Signed => "signed",
+                    Sign::Unsigned => "unsigned",
+                };
+                format!(
+                    "{} {} by {} as {} numbers{}",
+                    verb,
+                    get_register_name(register),
+                    self.describe(source),
+                    sign,
+                    self.describe_result(&Operand::Register(*register), Size::Long)
+                )
+            }
+            Instruction::ASd(count, dest, direction, size)
+            | Instruction::LSd(count, dest, direction, size)
+            | Instruction::ROd(count, dest, direction, size) => {
+                let verb = match instruction {
+                    Instruction::ROd(..) => "rotated",
+                    _ => "shifted",
+                };
+                let direction = match direction {
+                    ShiftDirection::Left => "left",
+                    ShiftDirection::Right => "right",
+                };
+                format!(
+                    "{} {} {} by {}{}",
+                    verb,
+                    self.describe(dest),
+                    direction,
+                    self.describe(count),
+                    self.describe_result(dest, *size)
+                )
+            }
+            Instruction::BTST(bit, operand) => format!("tested bit {} of {}", self.describe(bit), self.describe(operand)),
+            Instruction::BSET(bit, operand) => format!("set bit {} of {}", self.describe(bit), self.describe(operand)),
+            Instruction::BCLR(bit, operand) => format!("cleared bit {} of {}", self.describe(bit), self.describe(operand)),
+            Instruction::BCHG(bit, operand) => format!("flipped bit {} of {}", self.describe(bit), self.describe(operand)),
+            Instruction::BRA(target) => format!("jumped to {}", self.get_target_name(*target as usize)),
+            Instruction::JMP(_) => format!("jumped to {}", self.get_target_name(self.after.pc)),
+            Instruction::Bcc(target, condition) => self.describe_branch(*target, condition),
+            Instruction::DBcc(register, target, condition) => {
+                //the counter is only decremented when the condition is false
+                let decremented = self.after.get_register(register) != self.before.get_register(register);
+                match (jumped, decremented) {
+                    (true, _) => format!(
+                        "decremented {} and jumped back to {} because the condition {} was false",
+                        get_register_name(register),
+                        self.get_target_name(*target as usize),
+                        get_condition_name(condition)
+                    ),
+                    (false, true) => format!("decremented {} past zero and ended the loop", get_register_name(register)),
+                    (false, false) => format!(
+                        "ended the loop because the condition {} was true",
+                        get_condition_name(condition)
+                    ),
+                }
+            }
+            Instruction::Scc(operand, condition) => format!(
+                "set {} to {} because the condition {} was {}",
+                self.describe(operand),
+                format_value(self.get_value(operand, Size::Byte).unwrap_or_default()),
+                get_condition_name(condition),
+                match self.get_value(operand, Size::Byte) {
+                    Some(0) => "false",
+                    _ => "true",
+                }
+            ),
+            Instruction::BSR(target) => format!("called the subroutine {}", self.get_target_name(*target as usize)),
+            Instruction::JSR(_) => format!("called the subroutine {}", self.get_target_name(self.after.pc)),
+            Instruction::RTS | Instruction::RTD(_) => format!("returned to ${:X}", self.after.pc),
+            Instruction::RTE => format!("returned from the exception to ${:X}", self.after.pc),
+            Instruction::RTR => format!("restored the condition codes and returned to ${:X}", self.after.pc),
+            Instruction::LINK(register, _) => format!(
+                "saved {} on the stack and made it point to a new stack frame",
+                get_register_name(register)
+            ),
+            Instruction::UNLK(register) => format!("removed the stack frame of {}", get_register_name(register)),
+            Instruction::TRAP(vector) => format!(
+                "asked the system for the task {} with trap #{}",
+                self.before.d[0] & 0xFF,
+                vector
+            ),
+            _ => format!("ran {}", instruction.get_instruction_name()),
+        }
+    }
+    /// The changes of the address registers made by the postincrement and predecrement modes
+    fn describe_side_effects(&self, instruction: &Instruction) -> Vec<String> {
+        let operands: Vec<&Operand> = match instruction {
+            Instruction::MOVE(source, dest, _)
+            | Instruction::ADD(source, dest, _)
+            | Instruction::SUB(source, dest, _)
+            | Instruction::AND(source, dest, _)
+            | Instruction::OR(source, dest, _)
+            | Instruction::EOR(source, dest, _)
+            | Instruction::CMPM(source, dest, _)
+            | Instruction::MOVES(source, dest, _) => vec![source, dest],
+            Instruction::ADDQ(_, operand, _)
+            | Instruction::SUBQ(_, operand, _)
+            | Instruction::ADDI(_, operand, _)
+            | Instruction::SUBI(_, operand, _)
+            | Instruction::ANDI(_, operand, _)
+            | Instruction::ORI(_, operand, _)
+            | Instruction::EORI(_, operand, _)
+            | Instruction::CMPI(_, operand, _)
+            | Instruction::CLR(operand, _)
+            | Instruction::NEG(operand, _)
+            | Instruction::NOT(operand, _)
+            | Instruction::TST(operand, _)
+            | Instruction::ADDA(operand, _, _)
+            | Instruction::SUBA(operand, _, _)
+            | Instruction::CMPA(operand, _, _)
+            | Instruction::MOVEA(operand, _, _)
+            | Instruction::CMP(operand, _, _)
+            | Instruction::MULx(operand, _, _)
+            | Instruction::DIVx(operand, _, _) => vec![operand],
+            _ => vec![],
+        };
+        let mut effects = vec![];
+        for operand in operands {
+            let (index, verb) = match operand {
+                Operand::PostIndirect(index) => (*index as usize, "incremented"),
+                Operand::PreIndirect(index) => (*index as usize, "decremented"),
+                _ => continue,
+            };
+            let change = self.after.a[index].wrapping_sub(self.before.a[index]) as i32;
+            if change != 0 {
+                effects.push(format!("{} A{} by {}", verb, index, change.unsigned_abs()));
+            }
+        }
+        effects
+    }
+    fn describe_flags(&self) -> Vec<String> {
+        FLAG_NAMES
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| self.before.flags[*i] != self.after.flags[*i])
+            .map(|(i, (_, name))| match self.after.flags[i] {
+                true => format!("{} set", name),
+                false => format!("{} cleared", name),
+            })
+            .collect()
+    }
+}
+
+/// The value of the operand before the next step, read without running it. None for memory indirect
+pub(crate) fn peek_operand(interpreter: &Interpreter, operand: &Operand, size: Size) -> Option<u32> {
+    let snapshot = Snapshot::new(interpreter);
+    match operand {
+        Operand::Register(register) => Some(mask(snapshot.get_register(register), size)),
+        Operand::Immediate(value) => Some(mask(*value, size)),
+        _ => {
+            let address = snapshot.get_address(operand, size)?;
+            let bytes = interpreter.get_memory().read_bytes(address, size.to_bytes()).ok()?;
+            Some(bytes.iter().fold(0, |value, byte| (value << 8) | *byte as u32))
+        }
+    }
+}
+
+/// Runs one instruction and explains what it did
+pub fn explain_step(interpreter: &mut Interpreter) -> RuntimeResult<Explanation> {
+    let address = interpreter.get_pc();
+    let (instruction, line_index) = match interpreter.get_instruction_at(address) {
+        Some(line) => (line.instruction, line.parsed_line.line_index),
+        None => return Err(RuntimeError::Raw(format!("There is no instruction at ${:X}", address))),
+    };
+    let before = Snapshot::new(interpreter);
+    interpreter.step()?;
+    let after = Snapshot::new(interpreter);
+    let narrator = Narrator {
+        interpreter,
+        before: &before,
+        after: &after,
+    };
+    let mut text = narrator.describe_instruction(&instruction, address);
+    for effect in narrator.describe_side_effects(&instruction) {
+        text.push_str(", then ");
+        text.push_str(&effect);
+    }
+    let flags = narrator.describe_flags();
+    if !flags.is_empty() {
+        text.push_str("; ");
+        text.push_str(&flags.join(", "));
+    }
+    Ok(Explanation {
+        address,
+        line_index,
+        text,
+    })
+}
@@ -0,0 +1,346 @@
+use alloc::{string::{String, ToString}, vec, vec::Vec};
+use core::{error::Error, fmt};
+
+use serde::{Deserialize, Serialize};
+
+use crate::utils::parse_string_into_u32_chunks;
+
+/*
+    Constant expressions of immediates and directive arguments, like #(BUFLEN*2)+1, 1<<3 or ~MASK & $FF.
+    Numbers can be decimal, $hex, %binary, @octal or a string of up to 4 characters like 'abcd',
+    a literal wider than 32 bits is an error.
+    Symbols are resolved by the caller, labels by the compiler and equs by the lexer before this runs.
+    The precedence is the one of C, from the lowest:
+        |
+        ^
+        &
+        << >>
+        + -
+        * / \       \ is the remainder
+        **          right associative
+        - + ~       unary
+    The values are 64 bits and wrap on overflow, the caller truncates them to the size it needs
+*/
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "value")]
+pub enum ExpressionError {
+    Empty,
+    InvalidNumber(String),
+    /// A literal that doesn't fit in 32 bits
+    Overflow(String),
+    UnknownSymbol(String),
+    UnexpectedToken(String),
+    UnexpectedEnd,
+    DivisionByZero,
+    InvalidShift(i64),
+    InvalidExponent(i64),
+    TooDeep,
+}
+
+impl fmt::Display for ExpressionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExpressionError::Empty => write!(f, "The expression is empty"),
+            ExpressionError::InvalidNumber(number) => write!(f, "Invalid number \"{}\"", number),
+            ExpressionError::Overflow(number) => write!(f, "The number \"{}\" doesn't fit in 32 bits", number),
+            ExpressionError::UnknownSymbol(symbol) => write!(f, "Unknown label or constant \"{}\"", symbol),
+            ExpressionError::UnexpectedToken(token) => write!(f, "Unexpected \"{}\" in the expression", token),
+            ExpressionError::UnexpectedEnd => write!(f, "The expression ended early"),
+            ExpressionError::DivisionByZero => write!(f, "Division by zero"),
+            ExpressionError::InvalidShift(amount) => write!(f, "Invalid shift amount {}", amount),
+            ExpressionError::InvalidExponent(exponent) => write!(f, "Invalid exponent {}", exponent),
+            ExpressionError::TooDeep => write!(f, "The expression has more than {} nested parts", MAX_DEPTH),
+        }
+    }
+}
+
+impl Error for ExpressionError {}
+
+impl From<ExpressionError> for String {
+    fn from(error: ExpressionError) -> Self {
+        error.to_string()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(i64),
+    Symbol(String),
+    Operator(&'static str),
+    Open,
+    Close,
+}
+
+const OPERATORS: &[&str] = &["**", "<<", ">>", "+", "-", "*", "/", "\\", "&", "|", "^", "~"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LiteralBase {
+    Decimal,
+    Hexadecimal,
+    Binary,
+    Octal,
+    Character,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Literal {
+    pub base: LiteralBase,
+    pub value: i64,
+}
+
+/// Whether the text is a single literal, it can still be invalid like $FG. Symbols and expressions are not
+pub fn is_literal(text: &str) -> bool {
+    match text.chars().next() {
+        Some('\'') => text.len() > 1 && text.ends_with('\'') && text[1..text.len() - 1].find('\'').is_none(),
+        Some('$' | '%' | '@' | '0'..='9') => text[1..].chars().all(|c| c.is_ascii_alphanumeric()),
+        _ => false,
+    }
+}
+
+/// Parses one literal, like $FF, %1010, @17, 'A' or 42. Negative numbers are expressions, not literals
+pub fn parse_literal(text: &str) -> Result<Literal, ExpressionError> {
+    let (digits, base) = match text.as_bytes().first() {
+        Some(b'$') => (&text[1..], LiteralBase::Hexadecimal),
+        Some(b'%') => (&text[1..], LiteralBase::Binary),
+        Some(b'@') => (&text[1..], LiteralBase::Octal),
+        Some(b'\'') => {
+            let string = match text[1..].strip_suffix('\'') {
+                Some(string) if !string.is_empty() => string,
+                _ => return Err(ExpressionError::InvalidNumber(text.to_string())),
+            };
+            return match parse_string_into_u32_chunks(string, false)[..] {
+                [value] => Ok(Literal {
+                    base: LiteralBase::Character,
+                    value: value as i64,
+                }),
+                _ => Err(ExpressionError::Overflow(text.to_string())),
+            };
+        }
+        _ => (text, LiteralBase::Decimal),
+    };
+    let radix = match base {
+        LiteralBase::Hexadecimal => 16,
+        LiteralBase::Binary => 2,
+        LiteralBase::Octal => 8,
+        _ => 10,
+    };
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+        return Err(ExpressionError::InvalidNumber(text.to_string()));
+    }
+    //parsed unsigned so $FFFFFFFF is not an overflow
+    match u32::from_str_radix(digits, radix) {
+        Ok(value) => Ok(Literal {
+            base,
+            value: value as i64,
+        }),
+        Err(_) => Err(ExpressionError::Overflow(text.to_string())),
+    }
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>, ExpressionError> {
+    let chars: Vec<char> = expression.chars().collect();
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let rest: String = chars[i..].iter().take(2).collect();
+        match c {
+            _ if c.is_whitespace() => i += 1,
+            '(' => {
+                tokens.push(Token::Open);
+                i += 1;
+            }
+            ')' => {
+                tokens.push(Token::Close);
+                i += 1;
+            }
+            '\'' => {
+                let end = chars[i + 1..]
+                    .iter()
+                    .position(|c| *c == '\'')
+                    .ok_or(ExpressionError::UnexpectedEnd)?;
+                let string: String = chars[i..i + end + 2].iter().collect();
+                tokens.push(Token::Number(parse_literal(&string)?.value));
+                i += end + 2;
+            }
+            //a local label like 1$ before the first global label
+            '0'..='9' if chars[i..].iter().find(|c| !c.is_ascii_digit()) == Some(&'$') => {
+                let length = chars[i..].iter().take_while(|c| c.is_ascii_digit()).count() + 1;
+                tokens.push(Token::Symbol(chars[i..i + length].iter().collect()));
+                i += length;
+            }
+            '$' | '%' | '@' | '0'..='9' => {
+                let length = chars[i + 1..].iter().take_while(|c| c.is_ascii_alphanumeric()).count() + 1;
+                let number: String = chars[i..i + length].iter().collect();
+                tokens.push(Token::Number(parse_literal(&number)?.value));
+                i += length;
+            }
+            _ if c.is_alphabetic() || c == '_' || c == '.' => {
+                //the $ is for the key of local labels like main.1$
+                let length = chars[i..]
+                    .iter()
+                    .take_while(|c| c.is_alphanumeric() || **c == '_' || **c == '.' || **c == '$')
+                    .count();
+                tokens.push(Token::Symbol(chars[i..i + length].iter().collect()));
+                i += length;
+            }
+            _ => match OPERATORS.iter().find(|o| rest.starts_with(*o)) {
+                Some(operator) => {
+                    tokens.push(Token::Operator(operator));
+                    i += operator.len();
+                }
+                None => return Err(ExpressionError::UnexpectedToken(c.to_string())),
+            },
+        }
+    }
+    Ok(tokens)
+}
+
+/// Binding power of the binary operators, higher binds tighter
+fn get_precedence(operator: &str) -> Option<u8> {
+    match operator {
+        "|" => Some(1),
+        "^" => Some(2),
+        "&" => Some(3),
+        "<<" | ">>" => Some(4),
+        "+" | "-" => Some(5),
+        "*" | "/" | "\\" => Some(6),
+        "**" => Some(7),
+        _ => None,
+    }
+}
+
+const UNARY_PRECEDENCE: u8 = 8;
+/// Parentheses and unary operators nested deeper than this are refused instead of overflowing the stack
+pub const MAX_DEPTH: usize = 256;
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    position: usize,
+    depth: usize,
+    resolve: &'a dyn Fn(&str) -> Option<i64>,
+}
+
+impl Parser<'_> {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+    fn parse_value(&mut self) -> Result<i64, ExpressionError> {
+        match self.next() {
+            Some(Token::Number(value)) => Ok(value),
+            Some(Token::Symbol(name)) => (self.resolve)(&name).ok_or(ExpressionError::UnknownSymbol(name)),
+            Some(Token::Open) => {
+                let value = self.parse_binary(0)?;
+                match self.next() {
+                    Some(Token::Close) => Ok(value),
+                    Some(token) => Err(unexpected(&token)),
+                    None => Err(ExpressionError::UnexpectedEnd),
+                }
+            }
+            Some(Token::Operator(operator)) => {
+                let value = self.parse_binary(UNARY_PRECEDENCE)?;
+                match operator {
+                    "-" => Ok(value.wrapping_neg()),
+                    "+" => Ok(value),
+                    "~" => Ok(!value),
+                    _ => Err(ExpressionError::UnexpectedToken(operator.to_string())),
+                }
+            }
+            Some(Token::Close) => Err(ExpressionError::UnexpectedToken(")".to_string())),
+            None => Err(ExpressionError::UnexpectedEnd),
+        }
+    }
+    fn parse_binary(&mut self, min_precedence: u8) -> Result<i64, ExpressionError> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(ExpressionError::TooDeep);
+        }
+        let result = self.parse_binary_inner(min_precedence);
+        self.depth -= 1;
+        result
+    }
+    fn parse_binary_inner(&mut self, min_precedence: u8) -> Result<i64, ExpressionError> {
+        let mut left = self.parse_value()?;
+        while let Some(Token::Operator(operator)) = self.peek() {
+            let operator = *operator;
+            let precedence = match get_precedence(operator) {
+                Some(precedence) if precedence > min_precedence => precedence,
+                Some(_) => break,
+                None => return Err(ExpressionError::UnexpectedToken(operator.to_string())),
+            };
+            self.position += 1;
+            //** is right associative, so the right side can have another ** in it
+            let right = match operator {
+                "**" => self.parse_binary(precedence - 1)?,
+                _ => self.parse_binary(precedence)?,
+            };
+            left = apply(operator, left, right)?;
+        }
+        Ok(left)
+    }
+}
+
+fn unexpected(token: &Token) -> ExpressionError {
+    let text = match token {
+        Token::Number(value) => value.to_string(),
+        Token::Symbol(name) => name.clone(),
+        Token::Operator(operator) => operator.to_string(),
+        Token::Open => "(".to_string(),
+        Token::Close => ")".to_string(),
+    };
+    ExpressionError::UnexpectedToken(text)
+}
+
+fn apply(operator: &str, left: i64, right: i64) -> Result<i64, ExpressionError> {
+    let shift = || u32::try_from(right).map_err(|_| ExpressionError::InvalidShift(right));
+    Ok(match operator {
+        "+" => left.wrapping_add(right),
+        "-" => left.wrapping_sub(right),
+        "*" => left.wrapping_mul(right),
+        "/" | "\\" if right == 0 => return Err(ExpressionError::DivisionByZero),
+        "/" => left.wrapping_div(right),
+        "\\" => left.wrapping_rem(right),
+        "**" => match u32::try_from(right) {
+            Ok(exponent) => left.wrapping_pow(exponent),
+            Err(_) => return Err(ExpressionError::InvalidExponent(right)),
+        },
+        "<<" => left.checked_shl(shift()?).unwrap_or(0),
+        ">>" => left.checked_shr(shift()?).unwrap_or(if left < 0 { -1 } else { 0 }),
+        "&" => left & right,
+        "|" => left | right,
+        "^" => left ^ right,
+        _ => return Err(ExpressionError::UnexpectedToken(operator.to_string())),
+    })
+}
+
+/// Evaluates the expression, a leading # of an immediate is ignored. The symbols are looked up with resolve
+pub fn evaluate(expression: &str, resolve: &dyn Fn(&str) -> Option<i64>) -> Result<i64, ExpressionError> {
+    let expression = expression.trim();
+    let expression = expression.strip_prefix('#').unwrap_or(expression);
+    let tokens = tokenize(expression)?;
+    if tokens.is_empty() {
+        return Err(ExpressionError::Empty);
+    }
+    let mut parser = Parser {
+        tokens,
+        position: 0,
+        depth: 0,
+        resolve,
+    };
+    let value = parser.parse_binary(0)?;
+    match parser.next() {
+        None => Ok(value),
+        Some(token) => Err(unexpected(&token)),
+    }
+}
+
+/// Evaluates an expression that doesn't use any symbol
+pub fn evaluate_constant(expression: &str) -> Result<i64, ExpressionError> {
+    evaluate(expression, &|_| None)
+}
@@ -0,0 +1,364 @@
+use alloc::{format, string::{String, ToString}, vec::Vec};
+use serde::Serialize;
+
+use crate::{
+    explain::peek_operand,
+    grading::ConditionFlag,
+    instructions::{Instruction, Operand, RegisterOperand, Size},
+    interpreter::{Flags, Interpreter, RuntimeResult},
+};
+
+/*
+    Explains why every flag of the CCR has its value after an arithmetic or logic operation, for quizzes and
+    for the debugger:
+
+        V set because adding two positives (100 and 50) produced a negative (-106)
+        C cleared because the unsigned sum 100 + 50 fits in a byte
+
+    explain_flags works on any operation and values, explain_step_flags reads the operands of the next
+    instruction, runs it and explains the flags it set. For subtractions and comparisons the result is
+    destination - source, like in sub.b d0, d1 which is d1 - d0
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum FlagOperation {
+    Add,
+    Subtract,
+    Compare,
+    Negate,
+    And,
+    Or,
+    ExclusiveOr,
+    Not,
+    Move,
+    Test,
+    Clear,
+}
+
+impl FlagOperation {
+    fn get_name(self) -> &'static str {
+        match self {
+            FlagOperation::Add => "an addition",
+            FlagOperation::Subtract => "a subtraction",
+            FlagOperation::Compare => "a comparison",
+            FlagOperation::Negate => "a negation",
+            FlagOperation::And => "an and",
+            FlagOperation::Or => "an or",
+            FlagOperation::ExclusiveOr => "an exclusive or",
+            FlagOperation::Not => "a not",
+            FlagOperation::Move => "a move",
+            FlagOperation::Test => "a test",
+            FlagOperation::Clear => "a clear",
+        }
+    }
+    /// The operations where X is a copy of C
+    fn sets_extend(self) -> bool {
+        matches!(self, FlagOperation::Add | FlagOperation::Subtract | FlagOperation::Negate)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FlagReason {
+    pub flag: ConditionFlag,
+    pub value: bool,
+    /// If the value is different from the one before the operation
+    pub changed: bool,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FlagExplanation {
+    pub operation: FlagOperation,
+    pub size: Size,
+    pub source: u32,
+    pub destination: u32,
+    pub result: u32,
+    /// X, N, Z, V and C in this order
+    pub flags: Vec<FlagReason>,
+}
+
+impl FlagExplanation {
+    pub fn get_flag(&self, flag: ConditionFlag) -> Option<&FlagReason> {
+        self.flags.iter().find(|reason| reason.flag == flag)
+    }
+    /// The reasons of the flags that changed
+    pub fn get_changes(&self) -> Vec<&FlagReason> {
+        self.flags.iter().filter(|reason| reason.changed).collect()
+    }
+}
+
+fn get_size_name(size: Size) -> &'static str {
+    match size {
+        Size::Byte => "byte",
+        Size::Word => "word",
+        Size::Long => "long word",
+    }
+}
+
+fn get_max(size: Size) -> u64 {
+    (1u64 << (size.to_bytes() * 8)) - 1
+}
+
+fn to_unsigned(value: u32, size: Size) -> u64 {
+    value as u64 & get_max(size)
+}
+
+fn to_signed(value: u32, size: Size) -> i64 {
+    match size {
+        Size::Byte => value as u8 as i8 as i64,
+        Size::Word => value as u16 as i16 as i64,
+        Size::Long => value as i32 as i64,
+    }
+}
+
+fn is_negative(value: u32, size: Size) -> bool {
+    to_signed(value, size) < 0
+}
+
+fn get_sign_name(value: u32, size: Size, plural: bool) -> &'static str {
+    match (is_negative(value, size), plural) {
+        (true, true) => "negatives",
+        (true, false) => "a negative",
+        (false, true) => "positives",
+        (false, false) => "a positive",
+    }
+}
+
+fn get_result(operation: FlagOperation, source: u32, destination: u32) -> u32 {
+    match operation {
+        FlagOperation::Add => destination.wrapping_add(source),
+        FlagOperation::Subtract | FlagOperation::Compare => destination.wrapping_sub(source),
+        FlagOperation::Negate => 0u32.wrapping_sub(destination),
+        FlagOperation::And => destination & source,
+        FlagOperation::Or => destination | source,
+        FlagOperation::ExclusiveOr => destination ^ source,
+        FlagOperation::Not => !destination,
+        FlagOperation::Move | FlagOperation::Test => source,
+        FlagOperation::Clear => 0,
+    }
+}
+
+fn explain_overflow(operation: FlagOperation, source: u32, destination: u32, result: u32, size: Size) -> (bool, String) {
+    let (s, d, r) = (to_signed(source, size), to_signed(destination, size), to_signed(result, size));
+    let size_name = get_size_name(size);
+    match operation {
+        FlagOperation::Add => {
+            let same_sign = is_negative(source, size) == is_negative(destination, size);
+            match (same_sign, is_negative(result, size) != is_negative(source, size)) {
+                (true, true) => (
+                    true,
+                    format!(
+                        "V set because adding two {} ({} and {}) produced {} ({})",
+                        get_sign_name(source, size, true),
+                        d,
+                        s,
+                        get_sign_name(result, size, false),
+                        r
+                    ),
+                ),
+                (true, false) => (false, format!("V cleared because the sum {} fits in a signed {}", d + s, size_name)),
+                (false, _) => (false, "V cleared because adding a positive and a negative can't overflow".to_string()),
+            }
+        }
+        FlagOperation::Subtract | FlagOperation::Compare => {
+            let different_sign = is_negative(source, size) != is_negative(destination, size);
+            match (different_sign, is_negative(result, size) != is_negative(destination, size)) {
+                (true, true) => (
+                    true,
+                    format!(
+                        "V set because subtracting {} ({}) from {} ({}) produced {} ({})",
+                        get_sign_name(source, size, false),
+                        s,
+                        get_sign_name(destination, size, false),
+                        d,
+                        get_sign_name(result, size, false),
+                        r
+                    ),
+                ),
+                (true, false) => (
+                    false,
+                    format!("V cleared because the difference {} fits in a signed {}", d - s, size_name),
+                ),
+                (false, _) => (
+                    false,
+                    "V cleared because subtracting two numbers with the same sign can't overflow".to_string(),
+                ),
+            }
+        }
+        FlagOperation::Negate => match d == to_signed(1 << (size.to_bytes() * 8 - 1), size) {
+            true => (
+                true,
+                format!("V set because {} is the most negative {}, its negation doesn't fit", d, size_name),
+            ),
+            false => (false, format!("V cleared because {} fits in a signed {}", -d, size_name)),
+        },
+        _ => (false, format!("V is always cleared by {}", operation.get_name())),
+    }
+}
+
+fn explain_carry(operation: FlagOperation, source: u32, destination: u32, size: Size) -> (bool, String) {
+    let (s, d) = (to_unsigned(source, size), to_unsigned(destination, size));
+    let size_name = get_size_name(size);
+    match operation {
+        FlagOperation::Add => match s + d > get_max(size) {
+            true => (
+                true,
+                format!("C set because the unsigned sum {} + {} is bigger than {}", d, s, get_max(size)),
+            ),
+            false => (false, format!("C cleared because the unsigned sum {} + {} fits in a {}", d, s, size_name)),
+        },
+        FlagOperation::Subtract | FlagOperation::Compare => match s > d {
+            true => (
+                true,
+                format!("C set because {} is bigger than {} as unsigned numbers, so a borrow was needed", s, d),
+            ),
+            false => (
+                false,
+                format!("C cleared because {} is not bigger than {} as unsigned numbers", s, d),
+            ),
+        },
+        FlagOperation::Negate => match d != 0 {
+            true => (true, "C set because the operand is not zero".to_string()),
+            false => (false, "C cleared because the operand is zero".to_string()),
+        },
+        _ => (false, format!("C is always cleared by {}", operation.get_name())),
+    }
+}
+
+/// Explains the flags after the operation, the flags it doesn't change keep the value they have in before
+pub fn explain_flags(operation: FlagOperation, source: u32, destination: u32, size: Size, before: Flags) -> FlagExplanation {
+    let result = get_result(operation, source, destination);
+    let (overflow, overflow_reason) = explain_overflow(operation, source, destination, result, size);
+    let (carry, carry_reason) = explain_carry(operation, source, destination, size);
+    let extend = match operation.sets_extend() {
+        true => (
+            carry,
+            format!(
+                "X {} because it is a copy of C after {}",
+                if carry { "set" } else { "cleared" },
+                operation.get_name()
+            ),
+        ),
+        false => (
+            before.contains(Flags::Extend),
+            format!("X is not changed by {}", operation.get_name()),
+        ),
+    };
+    let negative = match is_negative(result, size) {
+        true => (
+            true,
+            format!("N set because the result {} is negative, its top bit is 1", to_signed(result, size)),
+        ),
+        false => (
+            false,
+            format!("N cleared because the result {} is not negative, its top bit is 0", to_signed(result, size)),
+        ),
+    };
+    let zero = match to_unsigned(result, size) == 0 {
+        true => (true, "Z set because the result is zero".to_string()),
+        false => (
+            false,
+            format!("Z cleared because the result {} is not zero", to_unsigned(result, size)),
+        ),
+    };
+    let flags = [
+        (ConditionFlag::Extend, extend),
+        (ConditionFlag::Negative, negative),
+        (ConditionFlag::Zero, zero),
+        (ConditionFlag::Overflow, (overflow, overflow_reason)),
+        (ConditionFlag::Carry, (carry, carry_reason)),
+    ]
+    .into_iter()
+    .map(|(flag, (value, reason))| FlagReason {
+        flag,
+        value,
+        changed: before.contains(flag.to_flags()) != value,
+        reason,
+    })
+    .collect();
+    FlagExplanation {
+        operation,
+        size,
+        source: to_unsigned(source, size) as u32,
+        destination: to_unsigned(destination, size) as u32,
+        result: to_unsigned(result, size) as u32,
+        flags,
+    }
+}
+
+fn is_address_register(operand: &Operand) -> bool {
+    matches!(operand, Operand::Register(RegisterOperand::Address(_)))
+}
+
+/// The operation, source, destination and size of the instruction, none if its flags are not explained
+fn get_operation(instruction: &Instruction) -> Option<(FlagOperation, Option<Operand>, Option<Operand>, Size)> {
+    let operation = match instruction {
+        Instruction::ADD(source, dest, size) => (FlagOperation::Add, Some(*source), Some(*dest), *size),
+        Instruction::ADDI(value, dest, size) => (FlagOperation::Add, Some(Operand::Immediate(*value)), Some(*dest), *size),
+        Instruction::ADDQ(value, dest, size) if !is_address_register(dest) => {
+            (FlagOperation::Add, Some(Operand::Immediate(*value as u32)), Some(*dest), *size)
+        }
+        Instruction::SUB(source, dest, size) => (FlagOperation::Subtract, Some(*source), Some(*dest), *size),
+        Instruction::SUBI(value, dest, size) => {
+            (FlagOperation::Subtract, Some(Operand::Immediate(*value)), Some(*dest), *size)
+        }
+        Instruction::SUBQ(value, dest, size) if !is_address_register(dest) => {
+            (FlagOperation::Subtract, Some(Operand::Immediate(*value as u32)), Some(*dest), *size)
+        }
+        Instruction::CMP(source, register, size) => {
+            (FlagOperation::Compare, Some(*source), Some(Operand::Register(*register)), *size)
+        }
+        Instruction::CMPI(value, dest, size) => {
+            (FlagOperation::Compare, Some(Operand::Immediate(*value)), Some(*dest), *size)
+        }
+        Instruction::CMPM(source, dest, size) => (FlagOperation::Compare, Some(*source), Some(*dest), *size),
+        Instruction::NEG(dest, size) => (FlagOperation::Negate, None, Some(*dest), *size),
+        Instruction::AND(source, dest, size) => (FlagOperation::And, Some(*source), Some(*dest), *size),
+        Instruction::ANDI(value, dest, size) => (FlagOperation::And, Some(Operand::Immediate(*value)), Some(*dest), *size),
+        Instruction::OR(source, dest, size) => (FlagOperation::Or, Some(*source), Some(*dest), *size),
+        Instruction::ORI(value, dest, size) => (FlagOperation::Or, Some(Operand::Immediate(*value)), Some(*dest), *size),
+        Instruction::EOR(source, dest, size) => (FlagOperation::ExclusiveOr, Some(*source), Some(*dest), *size),
+        Instruction::EORI(value, dest, size) => {
+            (FlagOperation::ExclusiveOr, Some(Operand::Immediate(*value)), Some(*dest), *size)
+        }
+        Instruction::NOT(dest, size) => (FlagOperation::Not, None, Some(*dest), *size),
+        Instruction::MOVE(source, dest, size) => (FlagOperation::Move, Some(*source), Some(*dest), *size),
+        Instruction::MOVEQ(value, register) => (
+            FlagOperation::Move,
+            Some(Operand::Immediate(*value as i8 as i32 as u32)),
+            Some(Operand::Register(*register)),
+            Size::Long,
+        ),
+        Instruction::TST(operand, size) => (FlagOperation::Test, Some(*operand), None, *size),
+        Instruction::CLR(dest, size) => (FlagOperation::Clear, None, Some(*dest), *size),
+        _ => return None,
+    };
+    Some(operation)
+}
+
+/// Runs the next instruction and explains the flags it set, none if the flags of the instruction are not explained
+pub fn explain_step_flags(interpreter: &mut Interpreter) -> RuntimeResult<Option<FlagExplanation>> {
+    let operation = interpreter
+        .get_instruction_at(interpreter.get_pc())
+        .and_then(|line| get_operation(&line.instruction));
+    let (operation, source, destination, size) = match operation {
+        Some(operation) => operation,
+        None => {
+            interpreter.step()?;
+            return Ok(None);
+        }
+    };
+    let read = |operand: Option<Operand>| match operand {
+        Some(operand) => peek_operand(interpreter, &operand, size),
+        None => Some(0),
+    };
+    //the destination of a move and clear is only written
+    let destination_value = match operation {
+        FlagOperation::Move | FlagOperation::Clear => Some(0),
+        _ => read(destination),
+    };
+    let values = read(source).zip(destination_value);
+    let before = interpreter.get_cpu().wasm_get_ccr();
+    interpreter.step()?;
+    Ok(values.map(|(source, destination)| explain_flags(operation, source, destination, size, before)))
+}
@@ -0,0 +1,159 @@
+use alloc::{string::String, vec, vec::Vec};
+use serde::Serialize;
+
+use crate::{
+    lexer::{LexedLine, ParsedLine},
+    conditional::CONDITIONS,
+    macros::{expand_macros, split_statement},
+    S68k,
+};
+
+/*
+    Regions of the source that editors can fold. A subroutine goes from its label to the last return
+    before the next label, local labels that start with a dot are part of the subroutine before them.
+    A run of data directives (dc, ds, dcb) is folded when it has at least MIN_DATA_LINES lines, labels,
+    comments and empty lines between them don't break the run. A macro goes from its MACRO to its ENDM
+    and a conditional block from its IF to its ENDC, a repetition from its REPT to its ENDR
+*/
+
+/// Fewest data lines in a row that make a foldable region
+pub const MIN_DATA_LINES: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum FoldingKind {
+    Subroutine,
+    Data,
+    Macro,
+    Repeat,
+    Conditional,
+}
+
+/// Lines of a region, both inclusive
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct FoldingRange {
+    pub start_line: usize,
+    pub end_line: usize,
+    pub kind: FoldingKind,
+}
+
+fn is_return(line: &LexedLine) -> bool {
+    matches!(line, LexedLine::Instruction { name, .. } if matches!(name.as_str(), "rts" | "rte" | "rtr"))
+}
+
+fn is_data(line: &LexedLine) -> bool {
+    matches!(line, LexedLine::Directive { name, .. } if matches!(name.as_str(), "dc" | "ds" | "dcb"))
+}
+
+fn get_subroutines(lines: &[ParsedLine], ranges: &mut Vec<FoldingRange>) {
+    //start of the current subroutine and the line of its last return
+    let mut current: Option<(usize, Option<usize>)> = None;
+    for line in lines {
+        match &line.parsed {
+            LexedLine::Label { local: false, .. } => {
+                if let Some((start_line, Some(end_line))) = current {
+                    ranges.push(FoldingRange {
+                        start_line,
+                        end_line,
+                        kind: FoldingKind::Subroutine,
+                    });
+                }
+                current = Some((line.line_index, None));
+            }
+            parsed if is_return(parsed) => {
+                if let Some((_, end)) = &mut current {
+                    *end = Some(line.line_index);
+                }
+            }
+            _ => {}
+        }
+    }
+    if let Some((start_line, Some(end_line))) = current {
+        ranges.push(FoldingRange {
+            start_line,
+            end_line,
+            kind: FoldingKind::Subroutine,
+        });
+    }
+}
+
+fn get_data_runs(lines: &[ParsedLine], ranges: &mut Vec<FoldingRange>) {
+    //first line, last line and number of data lines of the current run
+    let mut run: Option<(usize, usize, usize)> = None;
+    let mut push_run = |run: Option<(usize, usize, usize)>| {
+        if let Some((start_line, end_line, count)) = run {
+            if count >= MIN_DATA_LINES {
+                ranges.push(FoldingRange {
+                    start_line,
+                    end_line,
+                    kind: FoldingKind::Data,
+                });
+            }
+        }
+    };
+    for line in lines {
+        match &line.parsed {
+            parsed if is_data(parsed) => {
+                run = match run {
+                    Some((start_line, _, count)) => Some((start_line, line.line_index, count + 1)),
+                    None => Some((line.line_index, line.line_index, 1)),
+                };
+            }
+            LexedLine::Label { .. } | LexedLine::Comment { .. } | LexedLine::Empty => {}
+            _ => push_run(run.take()),
+        }
+    }
+    push_run(run);
+}
+
+/// The foldable regions sorted by their first line, a region never starts and ends on the same line
+fn get_macros(code: &str, ranges: &mut Vec<FoldingRange>) {
+    let lines = code.lines().map(String::from).collect::<Vec<String>>();
+    for definition in expand_macros(&lines).macros {
+        ranges.push(FoldingRange {
+            start_line: definition.line_index,
+            end_line: definition.end_line_index,
+            kind: FoldingKind::Macro,
+        });
+    }
+}
+
+/// The conditional and the REPT blocks, the start of the block is matched with its end directive
+fn get_blocks(code: &str, ranges: &mut Vec<FoldingRange>) {
+    let mut conditionals = vec![];
+    let mut repetitions = vec![];
+    for (i, line) in code.lines().enumerate() {
+        let directive = split_statement(line).1.split_whitespace().next().unwrap_or_default().to_lowercase();
+        let (start, kind) = match directive.as_str() {
+            "endc" => (conditionals.pop(), FoldingKind::Conditional),
+            "endr" => (repetitions.pop(), FoldingKind::Repeat),
+            "rept" => {
+                repetitions.push(i);
+                continue;
+            }
+            directive if CONDITIONS.contains(&directive) => {
+                conditionals.push(i);
+                continue;
+            }
+            _ => continue,
+        };
+        if let Some(start_line) = start {
+            ranges.push(FoldingRange {
+                start_line,
+                end_line: i,
+                kind,
+            });
+        }
+    }
+}
+
+pub fn get_folding_ranges(s68k: &S68k) -> Vec<FoldingRange> {
+    let lines = s68k.get_lexed_lines();
+    let mut ranges = vec![];
+    get_subroutines(lines, &mut ranges);
+    get_data_runs(lines, &mut ranges);
+    get_macros(s68k.get_code(), &mut ranges);
+    get_blocks(s68k.get_code(), &mut ranges);
+    ranges.retain(|range| range.end_line > range.start_line);
+    ranges.sort_by_key(|range| (range.start_line, core::cmp::Reverse(range.end_line)));
+    ranges
+}
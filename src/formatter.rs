@@ -0,0 +1,190 @@
+use alloc::{format, string::{String, ToString}, vec, vec::Vec};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    lexer::{LexedLine, Lexer},
+    utils::{is_register, split_comment, split_operands},
+};
+
+/*
+    Source formatter, every line is laid out in columns:
+
+    label:  mnemonic.s  operand, operand        ; comment
+
+    Labels start at the label column, the first one by default, mnemonics and operands are aligned to their column and comments
+    after code to the comment column, when a part is longer than its column it is followed by a single space.
+    Comment lines and blank lines are kept, operands are copied from the source so expressions and strings
+    are not changed, only the separators and the case of registers and mnemonics are normalized
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LetterCase {
+    Lower,
+    Upper,
+    Preserve,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FormatterOptions {
+    pub label_column: usize,
+    pub mnemonic_column: usize,
+    pub operands_column: usize,
+    pub comment_column: usize,
+    /// Case of mnemonics, directives, size suffixes and registers
+    pub case: LetterCase,
+    pub space_after_comma: bool,
+}
+
+impl Default for FormatterOptions {
+    fn default() -> Self {
+        Self {
+            label_column: 0,
+            mnemonic_column: 8,
+            operands_column: 16,
+            comment_column: 40,
+            case: LetterCase::Lower,
+            space_after_comma: true,
+        }
+    }
+}
+
+fn apply_case(text: &str, case: LetterCase) -> String {
+    match case {
+        LetterCase::Lower => text.to_lowercase(),
+        LetterCase::Upper => text.to_uppercase(),
+        LetterCase::Preserve => text.to_string(),
+    }
+}
+
+/// Changes the case of the registers and of their size suffix, numbers, labels and strings are kept
+fn format_operand(operand: &str, case: LetterCase) -> String {
+    let chars: Vec<char> = operand.chars().collect();
+    let mut result = String::new();
+    let mut i = 0;
+    let mut in_string = false;
+    while i < chars.len() {
+        let c = chars[i];
+        if c == '\'' {
+            in_string = !in_string;
+        }
+        if in_string || !(c.is_alphanumeric() || c == '_') {
+            result.push(c);
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+            i += 1;
+        }
+        let word: String = chars[start..i].iter().collect();
+        let is_number = start > 0 && matches!(chars[start - 1], '$' | '%' | '@');
+        match !is_number && is_register(&word) {
+            true => {
+                result.push_str(&apply_case(&word, case));
+                //size suffix of an index register, like d0.w
+                if i + 1 < chars.len() && chars[i] == '.' && matches!(chars[i + 1].to_ascii_lowercase(), 'b' | 'w' | 'l') {
+                    result.push('.');
+                    result.push_str(&apply_case(&chars[i + 1].to_string(), case));
+                    i += 2;
+                }
+            }
+            false => result.push_str(&word),
+        }
+    }
+    result
+}
+
+/// Appends spaces until the column, or a single space when the line is already past it
+fn pad_to(line: &mut String, column: usize) {
+    let length = line.chars().count();
+    if length < column {
+        line.push_str(&" ".repeat(column - length));
+    } else if length > 0 && !line.ends_with(' ') {
+        line.push(' ');
+    }
+}
+
+fn format_line(line: &str, lexed: &[&LexedLine], options: &FormatterOptions) -> String {
+    let (code, comment) = split_comment(line);
+    let code = code.trim();
+    let comment = comment.map(|c| c.trim_end());
+    if code.is_empty() {
+        return match comment {
+            //comments at the start of the line stay there, the others are aligned with the mnemonics
+            Some(comment) if line.starts_with(comment) => comment.to_string(),
+            Some(comment) => format!("{}{}", " ".repeat(options.mnemonic_column), comment),
+            None => String::new(),
+        };
+    }
+    let has_label = lexed.iter().any(|l| matches!(l, LexedLine::Label { .. }));
+    let is_equ = lexed
+        .iter()
+        .any(|l| matches!(l, LexedLine::Directive { name, .. } if name == "equ"));
+    let is_unknown = lexed.iter().any(|l| matches!(l, LexedLine::Unknown { .. }));
+    let (label, rest) = match (has_label, code.split_once(':')) {
+        (true, Some((label, rest))) => (Some(format!("{}:", label.trim())), rest.trim()),
+        _ => (None, code),
+    };
+    let mut words = rest.splitn(2, char::is_whitespace);
+    let first = words.next().unwrap_or_default();
+    let remainder = words.next().unwrap_or_default().trim();
+    //the name of an equ goes in the label column, the value in the operands one
+    let (label, mnemonic, operands) = match is_equ {
+        true => {
+            let mut parts = remainder.splitn(2, char::is_whitespace);
+            let equ = parts.next().unwrap_or_default();
+            let value = parts.next().unwrap_or_default().trim();
+            (Some(first.to_string()), apply_case(equ, options.case), value.to_string())
+        }
+        false if is_unknown => (label, rest.to_string(), String::new()),
+        false => {
+            let separator = match options.space_after_comma {
+                true => ", ",
+                false => ",",
+            };
+            let operands = split_operands(remainder)
+                .iter()
+                .map(|op| format_operand(op, options.case))
+                .collect::<Vec<String>>()
+                .join(separator);
+            (label, apply_case(first, options.case), operands)
+        }
+    };
+    let mut result = match label {
+        Some(label) => format!("{}{}", " ".repeat(options.label_column), label),
+        None => String::new(),
+    };
+    if !mnemonic.is_empty() {
+        pad_to(&mut result, options.mnemonic_column);
+        result.push_str(&mnemonic);
+    }
+    if !operands.is_empty() {
+        pad_to(&mut result, options.operands_column);
+        result.push_str(&operands);
+    }
+    if let Some(comment) = comment {
+        pad_to(&mut result, options.comment_column);
+        result.push_str(comment);
+    }
+    result.trim_end().to_string()
+}
+
+pub fn format_code(code: &str, options: &FormatterOptions) -> String {
+    let mut lexer = Lexer::new();
+    let lines: Vec<&str> = code.lines().collect();
+    let mut lexed: Vec<Vec<&LexedLine>> = vec![vec![]; lines.len()];
+    for line in lexer.lex_lossy(code) {
+        lexed[line.line_index].push(&line.parsed);
+    }
+    let formatted: Vec<String> = lines
+        .iter()
+        .zip(lexed.iter())
+        .map(|(line, parsed)| format_line(line, parsed, options))
+        .collect();
+    let mut result = formatted.join("\n");
+    if code.ends_with('\n') {
+        result.push('\n');
+    }
+    result
+}
@@ -0,0 +1,318 @@
+use alloc::{format, string::ToString, vec::Vec};
+use core::any::Any;
+
+use serde::Serialize;
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::wasm_bindgen;
+
+use crate::{
+    coprocessor::{Coprocessor, CoprocessorBus, FPU_COPROCESSOR_ID},
+    instructions::{FloatCondition, FloatFormat, FloatOperand, Instruction},
+    interpreter::{RuntimeError, RuntimeResult},
+    math::sign_extend_to_long,
+};
+
+/*
+    Subset of the 68881/68882 FPU, the registers are stored as doubles instead of the 80 bit
+    extended precision, values are converted when they are read or written as extended in memory.
+    Only the condition code byte of the FPSR is emulated, exceptions are not raised.
+*/
+const FPSR_NAN: u32 = 1 << 24;
+const FPSR_INFINITY: u32 = 1 << 25;
+const FPSR_ZERO: u32 = 1 << 26;
+const FPSR_NEGATIVE: u32 = 1 << 27;
+const FPSR_CONDITION_MASK: u32 = 0x0F000000;
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Fpu {
+    fp_reg: [f64; 8],
+    fpcr: u32,
+    fpsr: u32,
+}
+
+impl Default for Fpu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Fpu {
+    pub fn new() -> Self {
+        Self {
+            //the registers are initialized to NaN on reset
+            fp_reg: [f64::NAN; 8],
+            fpcr: 0,
+            fpsr: 0,
+        }
+    }
+    pub fn get_fp_reg(&self, register: u8) -> f64 {
+        self.fp_reg[register as usize]
+    }
+    pub fn set_fp_reg(&mut self, register: u8, value: f64) {
+        self.fp_reg[register as usize] = value;
+    }
+    pub fn get_fpsr(&self) -> u32 {
+        self.fpsr
+    }
+    pub fn set_fpsr(&mut self, value: u32) {
+        self.fpsr = value;
+    }
+    pub fn get_fpcr(&self) -> u32 {
+        self.fpcr
+    }
+    pub fn set_condition_codes(&mut self, value: f64) {
+        let mut codes = 0;
+        if value.is_sign_negative() {
+            codes |= FPSR_NEGATIVE;
+        }
+        if value == 0.0 {
+            codes |= FPSR_ZERO;
+        }
+        if value.is_infinite() {
+            codes |= FPSR_INFINITY;
+        }
+        if value.is_nan() {
+            codes |= FPSR_NAN;
+        }
+        self.fpsr = (self.fpsr & !FPSR_CONDITION_MASK) | codes;
+    }
+    pub fn get_condition_value(&self, condition: &FloatCondition) -> bool {
+        let nan = self.fpsr & FPSR_NAN != 0;
+        let zero = self.fpsr & FPSR_ZERO != 0;
+        let negative = self.fpsr & FPSR_NEGATIVE != 0;
+        match condition {
+            FloatCondition::True => true,
+            FloatCondition::False => false,
+            FloatCondition::Equal => zero,
+            FloatCondition::NotEqual => !zero,
+            FloatCondition::GreaterThan => !(nan || zero || negative),
+            FloatCondition::NotGreaterThan => nan || zero || negative,
+            FloatCondition::GreaterThanOrEqual => zero || !(nan || negative),
+            FloatCondition::NotGreaterThanOrEqual => nan || (negative && !zero),
+            FloatCondition::LessThan => negative && !(nan || zero),
+            FloatCondition::NotLessThan => nan || zero || !negative,
+            FloatCondition::LessThanOrEqual => zero || (negative && !nan),
+            FloatCondition::NotLessThanOrEqual => nan || !(negative || zero),
+            FloatCondition::GreaterOrLess => !(nan || zero),
+            FloatCondition::NotGreaterOrLess => nan || zero,
+            FloatCondition::GreaterLessOrEqual => !nan,
+            FloatCondition::NotGreaterLessOrEqual => nan,
+            FloatCondition::Ordered => !nan,
+            FloatCondition::Unordered => nan,
+        }
+    }
+}
+
+impl Fpu {
+    fn read_float_operand(
+        &self,
+        op: &FloatOperand,
+        format: FloatFormat,
+        bus: &mut dyn CoprocessorBus,
+    ) -> RuntimeResult<f64> {
+        match op {
+            FloatOperand::Register(register) => Ok(self.get_fp_reg(*register)),
+            FloatOperand::Immediate(value) => Ok(*value),
+            FloatOperand::Effective(op) => match format.to_size() {
+                Some(size) => {
+                    let value = bus.read_operand(op, size)?;
+                    Ok(match format {
+                        FloatFormat::Single => f32::from_bits(value) as f64,
+                        _ => sign_extend_to_long(value, size) as f64,
+                    })
+                }
+                None => {
+                    let address = bus.get_operand_address(op, format.to_bytes() as u32)?;
+                    let bytes = bus.read_bytes(address, format.to_bytes())?;
+                    Ok(match format {
+                        FloatFormat::Double => f64::from_be_bytes(bytes.try_into().unwrap()),
+                        _ => extended_to_f64(&bytes.try_into().unwrap()),
+                    })
+                }
+            },
+        }
+    }
+    fn write_float_operand(
+        &mut self,
+        op: &FloatOperand,
+        value: f64,
+        format: FloatFormat,
+        bus: &mut dyn CoprocessorBus,
+    ) -> RuntimeResult<()> {
+        match op {
+            FloatOperand::Register(register) => {
+                self.set_fp_reg(*register, value);
+                Ok(())
+            }
+            FloatOperand::Immediate(_) => Err(RuntimeError::IncorrectAddressingMode(
+                "Attempted to store to immediate value".to_string(),
+            )),
+            FloatOperand::Effective(op) => match format.to_size() {
+                Some(size) => {
+                    let converted = match format {
+                        FloatFormat::Single => (value as f32).to_bits(),
+                        _ => f64_to_integer(value, size.to_bytes()),
+                    };
+                    bus.write_operand(op, converted, size)
+                }
+                None => {
+                    let address = bus.get_operand_address(op, format.to_bytes() as u32)?;
+                    match format {
+                        FloatFormat::Double => bus.write_bytes(address, &value.to_be_bytes()),
+                        _ => bus.write_bytes(address, &f64_to_extended(value)),
+                    }
+                }
+            },
+        }
+    }
+}
+
+const FPU_STATE_SIZE: usize = 8 * 8 + 4 + 4;
+
+impl Coprocessor for Fpu {
+    fn get_id(&self) -> u8 {
+        FPU_COPROCESSOR_ID
+    }
+    fn get_name(&self) -> &str {
+        "68881 FPU"
+    }
+    fn execute(&mut self, ins: &Instruction, bus: &mut dyn CoprocessorBus) -> RuntimeResult<()> {
+        match ins {
+            Instruction::FMOVE(source, dest, format) => {
+                let value = self.read_float_operand(source, *format, bus)?;
+                self.write_float_operand(dest, value, *format, bus)?;
+                //moving to memory does not change the condition codes
+                if let FloatOperand::Register(_) = dest {
+                    self.set_condition_codes(value)
+                }
+            }
+            Instruction::FADD(source, dest, format)
+            | Instruction::FSUB(source, dest, format)
+            | Instruction::FMUL(source, dest, format)
+            | Instruction::FDIV(source, dest, format)
+            | Instruction::FCMP(source, dest, format) => {
+                let source_value = self.read_float_operand(source, *format, bus)?;
+                let dest_value = self.get_fp_reg(*dest);
+                let result = match ins {
+                    Instruction::FADD(..) => dest_value + source_value,
+                    Instruction::FMUL(..) => dest_value * source_value,
+                    Instruction::FDIV(..) => dest_value / source_value,
+                    _ => dest_value - source_value,
+                };
+                match ins {
+                    Instruction::FCMP(..) => {}
+                    _ => self.set_fp_reg(*dest, result),
+                }
+                self.set_condition_codes(result);
+            }
+            Instruction::FBcc(address, condition) => {
+                if self.get_condition_value(condition) {
+                    bus.set_pc(*address as usize);
+                }
+            }
+            _ => {
+                return Err(RuntimeError::UnsupportedInstruction(format!(
+                    "The FPU does not implement the {} instruction",
+                    ins.get_instruction_name()
+                )))
+            }
+        }
+        Ok(())
+    }
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = Vec::with_capacity(FPU_STATE_SIZE);
+        for value in self.fp_reg.iter() {
+            state.extend_from_slice(&value.to_be_bytes());
+        }
+        state.extend_from_slice(&self.fpcr.to_be_bytes());
+        state.extend_from_slice(&self.fpsr.to_be_bytes());
+        state
+    }
+    fn restore_state(&mut self, state: &[u8]) -> RuntimeResult<()> {
+        if state.len() != FPU_STATE_SIZE {
+            return Err(RuntimeError::Raw(format!(
+                "Invalid FPU state, expected {} bytes, received {}",
+                FPU_STATE_SIZE,
+                state.len()
+            )));
+        }
+        for (i, chunk) in state[..64].chunks(8).enumerate() {
+            self.fp_reg[i] = f64::from_be_bytes(chunk.try_into().unwrap());
+        }
+        self.fpcr = u32::from_be_bytes(state[64..68].try_into().unwrap());
+        self.fpsr = u32::from_be_bytes(state[68..72].try_into().unwrap());
+        Ok(())
+    }
+    fn reset(&mut self) {
+        *self = Self::new();
+    }
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl Fpu {
+    pub fn wasm_get_fp_reg(&self, register: u8) -> f64 {
+        self.get_fp_reg(register)
+    }
+    pub fn wasm_get_fpsr(&self) -> u32 {
+        self.fpsr
+    }
+    pub fn wasm_get_fpcr(&self) -> u32 {
+        self.fpcr
+    }
+}
+
+
+/// Converts a double to the 96 bit memory format of the extended precision
+pub fn f64_to_extended(value: f64) -> [u8; 12] {
+    let bits = value.to_bits();
+    let sign = ((bits >> 63) as u16) << 15;
+    let exponent = ((bits >> 52) & 0x7FF) as i32;
+    let fraction = bits & 0x000F_FFFF_FFFF_FFFF;
+    let (exponent, mantissa) = match exponent {
+        0 if fraction == 0 => (0, 0),
+        0 => {
+            //denormals can be normalized since the extended exponent is wider
+            let position = 63 - fraction.leading_zeros() as i32;
+            let mantissa = fraction << (63 - position);
+            ((position - 1074 + 16383) as u16, mantissa)
+        }
+        0x7FF => (0x7FFF, fraction << 11),
+        _ => ((exponent - 1023 + 16383) as u16, (1 << 63) | (fraction << 11)),
+    };
+    let mut bytes = [0u8; 12];
+    bytes[0..2].copy_from_slice(&(sign | exponent).to_be_bytes());
+    bytes[4..12].copy_from_slice(&mantissa.to_be_bytes());
+    bytes
+}
+
+pub fn extended_to_f64(bytes: &[u8; 12]) -> f64 {
+    let head = u16::from_be_bytes([bytes[0], bytes[1]]);
+    let mantissa = u64::from_be_bytes(bytes[4..12].try_into().unwrap());
+    let negative = head & 0x8000 != 0;
+    let exponent = (head & 0x7FFF) as i32;
+    let value = match exponent {
+        0x7FFF if mantissa << 1 == 0 => f64::INFINITY,
+        0x7FFF => f64::NAN,
+        //libm so that the conversions also build without std
+        _ => libm::ldexp(mantissa as f64, exponent - 16383 - 63),
+    };
+    if negative {
+        -value
+    } else {
+        value
+    }
+}
+
+/// Converts to an integer using round to nearest, saturating like the FPU does on overflow
+pub fn f64_to_integer(value: f64, bytes: usize) -> u32 {
+    let rounded = libm::rint(value);
+    match bytes {
+        1 => rounded as i8 as u32,
+        2 => rounded as i16 as u32,
+        _ => rounded as i32 as u32,
+    }
+}
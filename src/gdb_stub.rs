@@ -0,0 +1,392 @@
+use std::io::{Read, Write};
+
+use crate::{
+    host_io::ScriptedIo,
+    instructions::{RegisterOperand, Size},
+    interpreter::{Interpreter, InterpreterStatus},
+    scripted_input::{ExhaustedPolicy, ScriptedInput},
+};
+
+/*
+    Stub of the GDB remote serial protocol, so m68k-elf-gdb (or any frontend that talks to gdb) can debug
+    a program running in the interpreter with "target remote host:port".
+    The supported packets are:
+        ?                           reason of the last stop
+        g, G, p, P                  d0-d7, a0-a7, sr and pc, in the order gdb uses for the 68k
+        m, M                        memory
+        Z0, z0, Z1, z1              breakpoints, software and hardware ones are the same
+        c, s                        continue and single step, with an optional address to resume at
+        qRcmd                       "monitor symbols" lists the labels, "monitor symbol <name>" finds one
+        qSupported, qAttached, qC, qfThreadInfo, qsThreadInfo, qSymbol, H, T, D and k
+    Everything else gets the empty reply, which tells gdb that the packet is not supported.
+    The text written by the program is sent to gdb as console output, the traps that read take the
+    input the stub was given. There is a single thread, and a continue stops with SIGINT after
+    the limit of instructions so an endless loop gives the control back to gdb
+*/
+
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+const SIGSEGV: u8 = 11;
+const DEFAULT_LIMIT: usize = 1_000_000;
+/// d0-d7, a0-a7, sr and pc
+const REGISTER_COUNT: usize = 18;
+
+pub struct GdbStub {
+    interpreter: Interpreter,
+    breakpoints: Vec<usize>,
+    input: ScriptedInput,
+    limit: usize,
+    last_stop: String,
+    ended: bool,
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| hex.get(i..i + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok()))
+        .collect()
+}
+
+fn parse_hex(hex: &str) -> Option<usize> {
+    usize::from_str_radix(hex, 16).ok()
+}
+
+/// "addr,length" of the memory and breakpoint packets
+fn parse_address_and_length(args: &str) -> Option<(usize, usize)> {
+    let (address, length) = args.split_once(',')?;
+    Some((parse_hex(address)?, parse_hex(length)?))
+}
+
+impl GdbStub {
+    pub fn new(interpreter: Interpreter) -> Self {
+        Self {
+            interpreter,
+            breakpoints: vec![],
+            input: ScriptedInput::new(ExhaustedPolicy::Eof(0)),
+            limit: DEFAULT_LIMIT,
+            last_stop: format!("S{:02x}", SIGTRAP),
+            ended: false,
+        }
+    }
+    /// Text read by the input traps, once it is over they read empty strings and zeros
+    pub fn set_input(&mut self, input: impl Into<String>) {
+        self.input = ScriptedInput::from_text(&input.into(), ExhaustedPolicy::Eof(0));
+    }
+    pub fn set_scripted_input(&mut self, input: ScriptedInput) {
+        self.input = input;
+    }
+    /// Instructions run by a continue before it stops
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+    }
+    pub fn get_interpreter(&self) -> &Interpreter {
+        &self.interpreter
+    }
+    /// If gdb killed the program or detached
+    pub fn has_ended(&self) -> bool {
+        self.ended
+    }
+    fn get_register(&self, index: usize) -> Option<u32> {
+        let cpu = self.interpreter.get_cpu();
+        match index {
+            0..=7 => Some(cpu.wasm_get_d_regs_value()[index]),
+            8..=15 => Some(cpu.wasm_get_a_regs_value()[index - 8]),
+            16 => Some(self.interpreter.get_sr() as u32),
+            17 => Some(self.interpreter.get_pc() as u32),
+            _ => None,
+        }
+    }
+    fn set_register(&mut self, index: usize, value: u32) -> bool {
+        match index {
+            0..=7 => self
+                .interpreter
+                .set_register_value(&RegisterOperand::Data(index as u8), value, Size::Long),
+            8..=15 => self
+                .interpreter
+                .set_register_value(&RegisterOperand::Address(index as u8 - 8), value, Size::Long),
+            16 => self.interpreter.set_sr(value as u16),
+            17 => self.interpreter.set_pc(value as usize),
+            _ => return false,
+        }
+        true
+    }
+    fn read_registers(&self) -> String {
+        (0..REGISTER_COUNT)
+            .filter_map(|i| self.get_register(i))
+            .map(|value| to_hex(&value.to_be_bytes()))
+            .collect()
+    }
+    fn write_registers(&mut self, hex: &str) -> String {
+        let bytes = match from_hex(hex) {
+            Some(bytes) => bytes,
+            None => return "E01".to_string(),
+        };
+        //gdb can send the fpu registers after the ones of the cpu, they are ignored
+        for (i, value) in bytes.chunks_exact(4).take(REGISTER_COUNT).enumerate() {
+            self.set_register(i, u32::from_be_bytes([value[0], value[1], value[2], value[3]]));
+        }
+        "OK".to_string()
+    }
+    fn read_memory(&self, args: &str) -> String {
+        match parse_address_and_length(args) {
+            Some((address, length)) => match self.interpreter.get_memory().read_bytes(address, length) {
+                Ok(bytes) => to_hex(&bytes),
+                Err(_) => "E01".to_string(),
+            },
+            None => "E01".to_string(),
+        }
+    }
+    fn write_memory(&mut self, args: &str) -> String {
+        let (range, data) = match args.split_once(':') {
+            Some(split) => split,
+            None => return "E01".to_string(),
+        };
+        match (parse_address_and_length(range), from_hex(data)) {
+            (Some((address, length)), Some(bytes)) if bytes.len() == length => {
+                match self.interpreter.set_memory_bytes(address, &bytes) {
+                    Ok(_) => "OK".to_string(),
+                    Err(_) => "E01".to_string(),
+                }
+            }
+            _ => "E01".to_string(),
+        }
+    }
+    /// The args of Z and z, only breakpoints are supported, not watchpoints
+    fn set_breakpoint(&mut self, args: &str, insert: bool) -> String {
+        let (kind, rest) = match args.split_once(',') {
+            Some(split) => split,
+            None => return "E01".to_string(),
+        };
+        if kind != "0" && kind != "1" {
+            return String::new();
+        }
+        let address = match parse_address_and_length(rest) {
+            Some((address, _)) => address,
+            None => return "E01".to_string(),
+        };
+        self.breakpoints.retain(|a| *a != address);
+        if insert {
+            self.breakpoints.push(address);
+        }
+        "OK".to_string()
+    }
+    /// Answers the interrupt, returning the text written by the program
+    fn answer_interrupt(&mut self) -> Result<String, String> {
+        let mut output = String::new();
+        self.interpreter
+            .answer_interrupt_with(&mut ScriptedIo::new(&mut self.input, &mut output))
+            .map_err(|e| e.get_message())?;
+        Ok(output)
+    }
+    /// Runs until a breakpoint, the end of the program or after one instruction when stepping.
+    /// Returns the console output packets followed by the stop reply
+    fn resume(&mut self, args: &str, single_step: bool) -> Vec<String> {
+        if let Some(address) = parse_hex(args) {
+            self.interpreter.set_pc(address);
+        }
+        let mut packets = vec![];
+        let mut output = |text: String| {
+            if !text.is_empty() {
+                packets.push(format!("O{}", to_hex(text.as_bytes())));
+            }
+        };
+        let limit = if single_step { 1 } else { self.limit };
+        let mut stop = format!("S{:02x}", SIGINT);
+        let mut executed = 0;
+        while executed < limit {
+            match *self.interpreter.get_status() {
+                InterpreterStatus::Interrupt => {
+                    match self.answer_interrupt() {
+                        Ok(text) => output(text),
+                        Err(e) => {
+                            output(format!("{}\n", e));
+                            stop = format!("S{:02x}", SIGSEGV);
+                            break;
+                        }
+                    }
+                    continue;
+                }
+                InterpreterStatus::Terminated => {
+                    stop = "W00".to_string();
+                    break;
+                }
+                InterpreterStatus::TerminatedWithException => {
+                    stop = format!("X{:02x}", SIGSEGV);
+                    break;
+                }
+                InterpreterStatus::Running => {}
+            }
+            if let Err(e) = self.interpreter.step() {
+                output(format!("{}\n", e.get_message()));
+                stop = format!("S{:02x}", SIGSEGV);
+                break;
+            }
+            executed += 1;
+            if self.breakpoints.contains(&self.interpreter.get_pc()) || single_step {
+                stop = format!("S{:02x}", SIGTRAP);
+                break;
+            }
+        }
+        //a step that ends the program reports the end, not the trap
+        if self.interpreter.has_terminated() && !stop.starts_with('X') {
+            stop = "W00".to_string();
+        }
+        self.last_stop = stop.clone();
+        packets.push(stop);
+        packets
+    }
+    fn monitor(&self, hex: &str) -> String {
+        let command = from_hex(hex)
+            .map(|bytes| String::from_utf8_lossy(&bytes).trim().to_string())
+            .unwrap_or_default();
+        let mut labels: Vec<(&usize, &String)> = self
+            .interpreter
+            .get_labels()
+            .values()
+            .map(|label| (&label.address, &label.name))
+            .collect();
+        labels.sort();
+        let text = match command.split_once(' ') {
+            None if command == "symbols" => labels
+                .iter()
+                .map(|(address, name)| format!("{} = 0x{:x}\n", name, address))
+                .collect(),
+            Some(("symbol", name)) => match labels.iter().find(|(_, label)| label.as_str() == name.trim()) {
+                Some((address, name)) => format!("{} = 0x{:x}\n", name, address),
+                None => format!("No symbol \"{}\"\n", name.trim()),
+            },
+            _ => "Commands: symbols, symbol <name>\n".to_string(),
+        };
+        to_hex(text.as_bytes())
+    }
+    /// Handles the data of a packet, returning the data of the packets to send back in order.
+    /// A packet that has no reply, like k, returns nothing
+    pub fn handle_packet(&mut self, packet: &str) -> Vec<String> {
+        let (command, args) = packet.split_at(packet.chars().next().map(char::len_utf8).unwrap_or(0));
+        let reply = match command {
+            "?" => self.last_stop.clone(),
+            "g" => self.read_registers(),
+            "G" => self.write_registers(args),
+            "p" => match parse_hex(args).and_then(|i| self.get_register(i)) {
+                Some(value) => to_hex(&value.to_be_bytes()),
+                None => String::new(),
+            },
+            "P" => {
+                let parsed = args
+                    .split_once('=')
+                    .and_then(|(index, value)| Some((parse_hex(index)?, from_hex(value)?)));
+                match parsed {
+                    Some((index, bytes)) if bytes.len() == 4 => {
+                        let value = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+                        match self.set_register(index, value) {
+                            true => "OK".to_string(),
+                            false => "E01".to_string(),
+                        }
+                    }
+                    _ => "E01".to_string(),
+                }
+            }
+            "m" => self.read_memory(args),
+            "M" => self.write_memory(args),
+            "Z" => self.set_breakpoint(args, true),
+            "z" => self.set_breakpoint(args, false),
+            "c" => return self.resume(args, false),
+            "s" => return self.resume(args, true),
+            "H" | "T" => "OK".to_string(),
+            "D" => {
+                self.ended = true;
+                "OK".to_string()
+            }
+            "k" => {
+                self.ended = true;
+                return vec![];
+            }
+            "q" => match args.split_once([':', ',']).unwrap_or((args, "")) {
+                ("Supported", _) => "PacketSize=1000".to_string(),
+                ("Attached", _) => "1".to_string(),
+                ("C", _) => "QC1".to_string(),
+                ("fThreadInfo", _) => "m1".to_string(),
+                ("sThreadInfo", _) => "l".to_string(),
+                //the stub knows the labels, it doesn't need gdb to look up any symbol
+                ("Symbol", _) => "OK".to_string(),
+                ("Rcmd", hex) => self.monitor(hex),
+                _ => String::new(),
+            },
+            _ => String::new(),
+        };
+        vec![reply]
+    }
+}
+
+fn write_packet(stream: &mut impl Write, data: &str) -> Result<(), String> {
+    let checksum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+    write!(stream, "${}#{:02x}", data, checksum).map_err(|e| e.to_string())?;
+    stream.flush().map_err(|e| e.to_string())
+}
+
+fn read_byte(stream: &mut impl Read) -> Result<Option<u8>, String> {
+    let mut byte = [0u8];
+    match stream.read(&mut byte).map_err(|e| e.to_string())? {
+        0 => Ok(None),
+        _ => Ok(Some(byte[0])),
+    }
+}
+
+/// Reads the next packet, answering its acknowledgment. None when the connection is closed
+fn read_packet(stream: &mut (impl Read + Write)) -> Result<Option<String>, String> {
+    loop {
+        match read_byte(stream)? {
+            None => return Ok(None),
+            //ctrl-c while the program is stopped, it is answered as a stop
+            Some(0x03) => return Ok(Some("?".to_string())),
+            Some(b'$') => {}
+            //acknowledgments of the packets that were sent
+            Some(_) => continue,
+        }
+        //the escaped characters are sent xored with 0x20, so the raw data never has a #
+        let mut raw = vec![];
+        loop {
+            match read_byte(stream)? {
+                None => return Ok(None),
+                Some(b'#') => break,
+                Some(b) => raw.push(b),
+            }
+        }
+        let mut checksum = [0u8; 2];
+        stream.read_exact(&mut checksum).map_err(|e| e.to_string())?;
+        let expected = std::str::from_utf8(&checksum).ok().and_then(|c| u8::from_str_radix(c, 16).ok());
+        if expected == Some(raw.iter().fold(0u8, |sum, b| sum.wrapping_add(*b))) {
+            stream.write_all(b"+").map_err(|e| e.to_string())?;
+            let mut data = vec![];
+            let mut bytes = raw.into_iter();
+            while let Some(b) = bytes.next() {
+                match b {
+                    b'}' => data.extend(bytes.next().map(|b| b ^ 0x20)),
+                    _ => data.push(b),
+                }
+            }
+            return Ok(Some(String::from_utf8_lossy(&data).to_string()));
+        }
+        stream.write_all(b"-").map_err(|e| e.to_string())?;
+    }
+}
+
+/// Serves gdb on the stream until it kills the program, detaches or closes the connection
+pub fn serve(mut stream: impl Read + Write, stub: &mut GdbStub) -> Result<(), String> {
+    while let Some(packet) = read_packet(&mut stream)? {
+        for reply in stub.handle_packet(&packet) {
+            write_packet(&mut stream, &reply)?;
+        }
+        if stub.has_ended() {
+            break;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,451 @@
+use alloc::{format, string::{String, ToString}, vec, vec::Vec};
+use crate::collections::HashMap;
+
+use serde::Serialize;
+
+use crate::{
+    assertions::{parse_assertions, AssertionResult},
+    compiler::Compiler,
+    cpu_model::CpuModel,
+    host_io::ScriptedIo,
+    instructions::{RegisterOperand, Size},
+    interpreter::{Flags, Interpreter, InterpreterOptions, InterpreterStatus},
+    sandbox::SandboxConfig,
+    scaffold::{check_scaffold, get_protected_ranges, ScaffoldViolation},
+    scripted_input::{ExhaustedPolicy, ScriptedInput},
+    S68k,
+};
+
+/*
+    Grading of programs by their final state, for courses that check the exercises of the students.
+    A grader has the expectations on the registers, memory, flags and output of the program when it ends,
+    the input that is given to the read traps and the limit of instructions. Grading assembles the code, runs it
+    and checks every expectation, the report says for each one if it passed and what was found instead:
+
+    let report = Grader::new()
+        .expect_register(RegisterOperand::Data(0), 55)
+        .expect_output("55")
+        .with_input("10")
+        .grade(code);
+
+    A program that doesn't assemble, fails at runtime or doesn't end within the limit doesn't pass,
+    the expectations are still checked against the state it stopped in.
+    The ;ASSERT comments of the source are checked too, see the assertions module.
+    With a scaffold the protected regions of the template must be unchanged, see the scaffold module
+*/
+
+const DEFAULT_LIMIT: usize = 1_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "serialize", derive(serde::Deserialize))]
+pub enum ConditionFlag {
+    Extend,
+    Negative,
+    Zero,
+    Overflow,
+    Carry,
+}
+
+impl ConditionFlag {
+    pub fn to_flags(self) -> Flags {
+        match self {
+            ConditionFlag::Extend => Flags::Extend,
+            ConditionFlag::Negative => Flags::Negative,
+            ConditionFlag::Zero => Flags::Zero,
+            ConditionFlag::Overflow => Flags::Overflow,
+            ConditionFlag::Carry => Flags::Carry,
+        }
+    }
+    pub(crate) fn get_name(self) -> &'static str {
+        match self {
+            ConditionFlag::Extend => "X",
+            ConditionFlag::Negative => "N",
+            ConditionFlag::Zero => "Z",
+            ConditionFlag::Overflow => "V",
+            ConditionFlag::Carry => "C",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[cfg_attr(feature = "serialize", derive(serde::Deserialize))]
+#[serde(tag = "type", content = "value")]
+pub enum Expectation {
+    Register { register: RegisterOperand, value: u32 },
+    Memory { address: usize, bytes: Vec<u8> },
+    Flag { flag: ConditionFlag, value: bool },
+    /// All the text the program wrote
+    Output(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", content = "value")]
+pub enum RunOutcome {
+    Terminated,
+    AssemblyFailed(Vec<String>),
+    RuntimeError(String),
+    LimitReached(usize),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CheckResult {
+    pub expectation: Expectation,
+    pub passed: bool,
+    /// What was found, in the same shape as the expectation. Missing if the program didn't assemble
+    /// or the memory could not be read
+    pub actual: Option<Expectation>,
+    /// Explains the difference, missing when the check passed
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct GradingReport {
+    /// If the program ended and every check passed
+    pub passed: bool,
+    pub outcome: RunOutcome,
+    pub checks: Vec<CheckResult>,
+    /// Results of the assertions written in the source
+    pub assertions: Vec<AssertionResult>,
+    /// Changes to the protected regions of the scaffold
+    pub scaffold_violations: Vec<ScaffoldViolation>,
+    pub output: String,
+    pub instructions: usize,
+    pub cycles: u64,
+}
+
+impl GradingReport {
+    pub fn get_failed_checks(&self) -> Vec<&CheckResult> {
+        self.checks.iter().filter(|check| !check.passed).collect()
+    }
+    pub fn get_failed_assertions(&self) -> Vec<&AssertionResult> {
+        self.assertions.iter().filter(|assertion| !assertion.passed).collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Grader {
+    expectations: Vec<Expectation>,
+    input: ScriptedInput,
+    limit: usize,
+    cpu_model: CpuModel,
+    sandbox: SandboxConfig,
+    scaffold: Option<String>,
+    protected: Vec<(usize, usize)>,
+}
+
+impl Default for Grader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub(crate) fn get_register_name(register: &RegisterOperand) -> String {
+    match register {
+        RegisterOperand::Data(index) => format!("d{}", index),
+        RegisterOperand::Address(index) => format!("a{}", index),
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>().join(" ")
+}
+
+/// Answers the interrupt from the input, the text written is added to the output
+fn answer_interrupt(interpreter: &mut Interpreter, input: &mut ScriptedInput, output: &mut String) -> Result<(), String> {
+    interpreter
+        .answer_interrupt_with(&mut ScriptedIo::new(input, output))
+        .map_err(|e| e.get_message())
+}
+
+/// Checks the expectation against the current state of the interpreter
+pub(crate) fn check_expectation(expectation: &Expectation, interpreter: &Interpreter, output: &str) -> CheckResult {
+    let (actual, message) = match expectation {
+        Expectation::Register { register, value } => {
+            let actual = interpreter.get_register_value(register, Size::Long);
+            let message = format!(
+                "{} is ${:08X}, expected ${:08X}",
+                get_register_name(register),
+                actual,
+                value
+            );
+            (
+                Some(Expectation::Register {
+                    register: *register,
+                    value: actual,
+                }),
+                Some(message).filter(|_| actual != *value),
+            )
+        }
+        Expectation::Memory { address, bytes } => {
+            match interpreter.get_memory().read_bytes(*address, bytes.len()) {
+                Ok(actual) => {
+                    let different: Vec<String> = (0..bytes.len())
+                        .filter(|i| actual[*i] != bytes[*i])
+                        .map(|i| format!("${:X}", address + i))
+                        .collect();
+                    let message = format!(
+                        "Memory at ${:X} is {}, expected {}, different at {}",
+                        address,
+                        to_hex(&actual),
+                        to_hex(bytes),
+                        different.join(", ")
+                    );
+                    (
+                        Some(Expectation::Memory {
+                            address: *address,
+                            bytes: actual.to_vec(),
+                        }),
+                        Some(message).filter(|_| !different.is_empty()),
+                    )
+                }
+                Err(e) => (None, Some(e.get_message())),
+            }
+        }
+        Expectation::Flag { flag, value } => {
+            let actual = interpreter.get_flag(flag.to_flags());
+            let message = format!("{} is {}, expected {}", flag.get_name(), actual as u8, *value as u8);
+            (
+                Some(Expectation::Flag {
+                    flag: *flag,
+                    value: actual,
+                }),
+                Some(message).filter(|_| actual != *value),
+            )
+        }
+        Expectation::Output(expected) => {
+            let position = expected
+                .chars()
+                .zip(output.chars())
+                .position(|(a, b)| a != b)
+                .unwrap_or(expected.chars().count().min(output.chars().count()));
+            let message = format!(
+                "The output is {:?}, expected {:?}, different from character {}",
+                output, expected, position
+            );
+            (
+                Some(Expectation::Output(output.to_string())),
+                Some(message).filter(|_| output != expected),
+            )
+        }
+    };
+    CheckResult {
+        expectation: expectation.clone(),
+        passed: message.is_none(),
+        actual,
+        message,
+    }
+}
+
+/// Checks and compiles the code, the errors are the messages with their line
+pub(crate) fn assemble(code: &str, cpu_model: CpuModel) -> Result<(S68k, Compiler), Vec<String>> {
+    let mut s68k = S68k::new(code);
+    s68k.set_cpu_model(cpu_model);
+    let errors = s68k.semantic_check();
+    if !errors.is_empty() {
+        return Err(errors.iter().map(|e| e.get_message_with_line()).collect());
+    }
+    let compiled = s68k.compile().map_err(|e| vec![e.to_string()])?;
+    Ok((s68k, compiled))
+}
+
+/// Runs the program until it ends, fails or reaches the limit, returning how it stopped and the instructions it ran.
+/// before_step is called with the output so far before every instruction
+pub(crate) fn run_to_end(
+    interpreter: &mut Interpreter,
+    input: &mut ScriptedInput,
+    output: &mut String,
+    limit: usize,
+    mut before_step: impl FnMut(&Interpreter, &str),
+) -> (RunOutcome, usize) {
+    let mut instructions = 0;
+    let outcome = loop {
+        match interpreter.get_status() {
+            InterpreterStatus::Interrupt => {
+                if let Err(e) = answer_interrupt(interpreter, input, output) {
+                    break RunOutcome::RuntimeError(e);
+                }
+            }
+            InterpreterStatus::Terminated => break RunOutcome::Terminated,
+            InterpreterStatus::TerminatedWithException => match interpreter.get_sandbox_stop() {
+                Some(limit) => break RunOutcome::RuntimeError(limit.to_string()),
+                None => break RunOutcome::RuntimeError("The program ended with an exception".to_string()),
+            },
+            InterpreterStatus::Running if instructions >= limit => break RunOutcome::LimitReached(limit),
+            InterpreterStatus::Running => {
+                before_step(interpreter, output);
+                if let Err(e) = interpreter.step() {
+                    break RunOutcome::RuntimeError(e.get_message());
+                }
+                instructions += 1;
+            }
+        }
+    };
+    (outcome, instructions)
+}
+
+impl Grader {
+    pub fn new() -> Self {
+        Self {
+            expectations: vec![],
+            input: ScriptedInput::default(),
+            limit: DEFAULT_LIMIT,
+            cpu_model: CpuModel::default(),
+            sandbox: SandboxConfig::default(),
+            scaffold: None,
+            protected: vec![],
+        }
+    }
+    pub fn expect(mut self, expectation: Expectation) -> Self {
+        self.expectations.push(expectation);
+        self
+    }
+    pub fn expect_register(self, register: RegisterOperand, value: u32) -> Self {
+        self.expect(Expectation::Register { register, value })
+    }
+    pub fn expect_memory(self, address: usize, bytes: &[u8]) -> Self {
+        self.expect(Expectation::Memory {
+            address,
+            bytes: bytes.to_vec(),
+        })
+    }
+    pub fn expect_flag(self, flag: ConditionFlag, value: bool) -> Self {
+        self.expect(Expectation::Flag { flag, value })
+    }
+    pub fn expect_output(self, output: &str) -> Self {
+        self.expect(Expectation::Output(output.to_string()))
+    }
+    /// Text read by the input traps, a line for every string or number that is read.
+    /// Reading past the end fails unless the policy is changed with with_exhausted_input
+    pub fn with_input(mut self, input: &str) -> Self {
+        self.input = ScriptedInput::from_text(input, self.input.get_policy());
+        self
+    }
+    pub fn with_scripted_input(mut self, input: ScriptedInput) -> Self {
+        self.input = input;
+        self
+    }
+    /// What the read traps get once the input is over
+    pub fn with_exhausted_input(mut self, policy: ExhaustedPolicy) -> Self {
+        self.input.set_policy(policy);
+        self
+    }
+    /// Instructions the program can run before it is stopped
+    pub fn with_limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+    pub fn with_cpu_model(mut self, cpu_model: CpuModel) -> Self {
+        self.cpu_model = cpu_model;
+        self
+    }
+    /// Limits for running code from students, a program stopped by one ends with a runtime error
+    pub fn with_sandbox(mut self, sandbox: SandboxConfig) -> Self {
+        self.sandbox = sandbox;
+        self
+    }
+    /// The template given to the students, its protected regions must be in the graded program unchanged
+    pub fn with_scaffold(mut self, template: &str) -> Self {
+        self.scaffold = Some(template.to_string());
+        self
+    }
+    /// Makes the memory from start to end read only while the program runs, start included and end excluded
+    pub fn protect_memory(mut self, start: usize, end: usize) -> Self {
+        self.protected.push((start, end));
+        self
+    }
+    pub fn get_expectations(&self) -> &[Expectation] {
+        &self.expectations
+    }
+    /// Assembles and runs the code, then checks the expectations on the final state
+    pub fn grade(&self, code: &str) -> GradingReport {
+        let assembled = assemble(code, self.cpu_model).and_then(|(s68k, compiled)| {
+            let protected = get_protected_ranges(&s68k, &compiled).map_err(|e| vec![String::from(e)])?;
+            match parse_assertions(&s68k, compiled.get_labels_map()) {
+                Ok(assertions) => Ok((s68k, compiled, assertions, protected)),
+                Err(errors) => Err(errors.into_iter().map(String::from).collect()),
+            }
+        });
+        let (s68k, compiled, assertions, protected) = match assembled {
+            Ok(assembled) => assembled,
+            Err(errors) => {
+                let checks = self
+                    .expectations
+                    .iter()
+                    .map(|expectation| CheckResult {
+                        expectation: expectation.clone(),
+                        passed: false,
+                        actual: None,
+                        message: Some("The program didn't assemble".to_string()),
+                    })
+                    .collect();
+                return GradingReport {
+                    passed: false,
+                    outcome: RunOutcome::AssemblyFailed(errors),
+                    checks,
+                    assertions: vec![],
+                    scaffold_violations: vec![],
+                    output: String::new(),
+                    instructions: 0,
+                    cycles: 0,
+                };
+            }
+        };
+        let options = InterpreterOptions {
+            cpu_model: self.cpu_model,
+            sandbox: self.sandbox,
+            ..Default::default()
+        };
+        //assertions of a checkpoint are checked before the instruction of its label runs
+        let mut checkpoints: HashMap<usize, Vec<usize>> = HashMap::new();
+        for (i, assertion) in assertions.iter().enumerate() {
+            if let Some(label) = assertion.checkpoint.as_ref().and_then(|name| compiled.get_labels_map().get(name)) {
+                checkpoints.entry(label.address).or_default().push(i);
+            }
+        }
+        let mut assertions: Vec<AssertionResult> = assertions.into_iter().map(AssertionResult::new).collect();
+        let scaffold_violations = match &self.scaffold {
+            Some(template) => check_scaffold(&S68k::new(template.as_str()), &s68k).unwrap_or_else(|error| {
+                vec![ScaffoldViolation {
+                    region: 0,
+                    line_index: None,
+                    message: format!("The template is wrong: {}", error),
+                }]
+            }),
+            None => vec![],
+        };
+        let mut interpreter = s68k.create_interpreter(compiled, Some(options));
+        for (start, end) in protected.iter().chain(self.protected.iter()) {
+            interpreter.protect_memory(*start, *end);
+        }
+        let mut input = self.input.clone();
+        let mut output = String::new();
+        let (outcome, instructions) = run_to_end(&mut interpreter, &mut input, &mut output, self.limit, |interpreter, output| {
+            for i in checkpoints.get(&interpreter.get_pc()).into_iter().flatten() {
+                let check = check_expectation(&assertions[*i].assertion.expectation, interpreter, output);
+                assertions[*i].add_check(check);
+            }
+        });
+        let checks: Vec<CheckResult> = self
+            .expectations
+            .iter()
+            .map(|expectation| check_expectation(expectation, &interpreter, &output))
+            .collect();
+        for assertion in assertions.iter_mut().filter(|assertion| assertion.assertion.checkpoint.is_none()) {
+            let check = check_expectation(&assertion.assertion.expectation, &interpreter, &output);
+            assertion.add_check(check);
+        }
+        GradingReport {
+            passed: outcome == RunOutcome::Terminated
+                && checks.iter().all(|check| check.passed)
+                && assertions.iter().all(|assertion| assertion.passed)
+                && scaffold_violations.is_empty(),
+            outcome,
+            checks,
+            assertions,
+            scaffold_violations,
+            output,
+            instructions,
+            cycles: interpreter.get_cycles(),
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use alloc::{format, string::String};
+use crate::scripted_input::ScriptedInput;
+
+/*
+    The console of the program, the tasks of TRAP #15 are the ones of Easy68K so the programs written for it run
+    unmodified. The interpreter stops on every trap with an interrupt, a frontend can answer it by itself or give
+    a host to `Interpreter::answer_interrupt_with` and `Interpreter::run_with_io`, which does the task with it:
+        0, 1        display the string at A1 of length D1.W, with and without CRLF
+        2           read a string into A1, its length in D1.W
+        3           display D1.L as a signed number
+        4, 5        read a number into D1.L, a char into D1.B
+        6           display the char in D1.B
+        7           D1.B is 1 if there is input pending
+        8           hundredths of a second since midnight in D1.L
+        9           terminate the program
+        11          clear the screen when D1.W is $FF00, else move the cursor to the column and row in D1.W
+        12          keyboard echo, off when D1.B is 0
+        13, 14      display the null terminated string at A1, with and without CRLF
+        15          display D1.L unsigned in the base in D2.B
+        17          the string at A1 without CRLF followed by D1.L
+        18          the string at A1 without CRLF, then read a number into D1.L
+        20          display D1.L signed, right aligned in D2.B columns
+    The CRLF is written as '\n'
+*/
+
+pub trait HostIo {
+    fn write(&mut self, text: &str);
+    fn read_line(&mut self) -> Result<String, String>;
+    fn read_char(&mut self) -> Result<char, String>;
+    fn read_number(&mut self) -> Result<i32, String> {
+        let line = self.read_line()?;
+        line.trim()
+            .parse()
+            .map_err(|_| format!("Expected a number as input, received \"{}\"", line))
+    }
+    /// If a read would return without waiting
+    fn has_input(&mut self) -> bool {
+        true
+    }
+    /// Hundredths of a second since midnight
+    fn get_time(&mut self) -> u32 {
+        0
+    }
+    fn clear_screen(&mut self) {}
+    fn set_cursor(&mut self, _column: u8, _row: u8) {}
+    fn set_echo(&mut self, _echo: bool) {}
+}
+
+/// Reads the input given in advance and collects the output, for the batch runs and the tests
+pub struct ScriptedIo<'a> {
+    pub input: &'a mut ScriptedInput,
+    pub output: &'a mut String,
+}
+
+impl<'a> ScriptedIo<'a> {
+    pub fn new(input: &'a mut ScriptedInput, output: &'a mut String) -> Self {
+        Self { input, output }
+    }
+}
+
+impl HostIo for ScriptedIo<'_> {
+    fn write(&mut self, text: &str) {
+        self.output.push_str(text);
+    }
+    fn read_line(&mut self) -> Result<String, String> {
+        Ok(self.input.read_line()?)
+    }
+    fn read_char(&mut self) -> Result<char, String> {
+        Ok(self.input.read_char()?)
+    }
+    fn read_number(&mut self) -> Result<i32, String> {
+        Ok(self.input.read_number()?)
+    }
+    fn has_input(&mut self) -> bool {
+        !self.input.is_exhausted()
+    }
+}
+
+/// The terminal the program runs in, for the console apps
+#[cfg(feature = "std")]
+pub struct TerminalIo;
+
+#[cfg(feature = "std")]
+impl HostIo for TerminalIo {
+    fn write(&mut self, text: &str) {
+        use std::io::Write;
+        print!("{}", text);
+        let _ = std::io::stdout().flush();
+    }
+    fn read_line(&mut self) -> Result<String, String> {
+        console::Term::stdout().read_line().map_err(|e| e.to_string())
+    }
+    fn read_char(&mut self) -> Result<char, String> {
+        console::Term::stdout().read_char().map_err(|e| e.to_string())
+    }
+    fn get_time(&mut self) -> u32 {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| ((d.as_millis() % 86_400_000) / 10) as u32)
+            .unwrap_or(0)
+    }
+    fn clear_screen(&mut self) {
+        let _ = console::Term::stdout().clear_screen();
+    }
+    fn set_cursor(&mut self, column: u8, row: u8) {
+        let _ = console::Term::stdout().move_cursor_to(column as usize, row as usize);
+    }
+}
@@ -0,0 +1,140 @@
+use alloc::{format, string::String, vec, vec::Vec};
+use crate::collections::HashMap;
+
+use serde::Serialize;
+
+use crate::{
+    compiler::Compiler,
+    lexer::{LexedLine, ParsedLine},
+    semantic_tokens::{SemanticTokenType, DECLARATION_MODIFIER},
+    timing::get_timing_table,
+    utils::{parse_absolute_expression, split_comment},
+    S68k,
+    symbol::LabelTable,
+};
+
+/*
+    Inlay hints for editors, so the cost and layout of the code is visible without a listing.
+    Every instruction and data directive gets its address, size in bytes and, for instructions, the cycles
+    it takes on the cpu model of the program, a conditional branch shows the cycles when taken and not taken.
+    These are put at the end of the code, before the comment, and are missing if the program doesn't check or assemble.
+    Every use of an equ gets its value right after the name, the value of an equ that depends on a label
+    is only known when the program assembles. Columns are in UTF-16 code units like the semantic tokens
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum InlayHintKind {
+    Address,
+    Size,
+    Cycles,
+    Value,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct InlayHint {
+    pub line: usize,
+    pub column: usize,
+    pub label: String,
+    pub kind: InlayHintKind,
+}
+
+fn utf16_len(text: &str) -> usize {
+    text.encode_utf16().count()
+}
+
+/// End of the code of the line without the comment and the trailing whitespace
+fn get_code_end(line: &str) -> usize {
+    utf16_len(split_comment(line).0.trim_end())
+}
+
+fn get_equ_values(lines: &[ParsedLine], labels: &LabelTable) -> HashMap<String, i64> {
+    lines
+        .iter()
+        .filter_map(|line| match &line.parsed {
+            LexedLine::Directive { name, args, .. } if name == "equ" && args.len() >= 3 => {
+                let value = parse_absolute_expression(&args[2..].join(" "), labels).ok()?;
+                Some((args[0].clone(), value))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+fn get_layout_hints(s68k: &S68k, compiler: &Compiler, hints: &mut Vec<InlayHint>) {
+    let lines = s68k.get_lexed_lines();
+    let addresses = compiler.get_line_addresses();
+    let timing = get_timing_table(s68k.get_cpu_model());
+    let mut instructions = compiler.get_instructions().iter();
+    for (i, line) in lines.iter().enumerate() {
+        let is_instruction = matches!(line.parsed, LexedLine::Instruction { .. });
+        let is_data = matches!(&line.parsed, LexedLine::Directive { name, .. } if matches!(name.as_str(), "dc" | "ds" | "dcb"));
+        if !is_instruction && !is_data {
+            continue;
+        }
+        let address = addresses[i];
+        let next_address = addresses.get(i + 1).copied().unwrap_or(compiler.get_end_address());
+        let column = get_code_end(&line.line);
+        let mut push = |label: String, kind: InlayHintKind| {
+            hints.push(InlayHint {
+                line: line.line_index,
+                column,
+                label,
+                kind,
+            })
+        };
+        push(format!("${:X}", address), InlayHintKind::Address);
+        push(format!("{} bytes", next_address - address), InlayHintKind::Size);
+        if !is_instruction {
+            continue;
+        }
+        //the instructions are compiled in the same order as the lines
+        if let Some(instruction) = instructions.next() {
+            let taken = timing.get_instruction_cycles(&instruction.instruction, true);
+            let not_taken = timing.get_instruction_cycles(&instruction.instruction, false);
+            let cycles = match taken == not_taken {
+                true => format!("{} cycles", taken),
+                false => format!("{}/{} cycles", taken, not_taken),
+            };
+            push(cycles, InlayHintKind::Cycles);
+        }
+    }
+}
+
+fn get_value_hints(s68k: &S68k, values: &HashMap<String, i64>, hints: &mut Vec<InlayHint>) {
+    let code_lines: Vec<&str> = s68k.get_code().lines().collect();
+    for token in s68k.get_semantic_tokens() {
+        if token.token_type != SemanticTokenType::Constant || token.modifiers & DECLARATION_MODIFIER != 0 {
+            continue;
+        }
+        let line: Vec<u16> = code_lines.get(token.line).unwrap_or(&"").encode_utf16().collect();
+        let end = (token.start + token.length).min(line.len());
+        let name = String::from_utf16_lossy(&line[token.start.min(end)..end]);
+        if let Some(value) = values.get(&name) {
+            hints.push(InlayHint {
+                line: token.line,
+                column: end,
+                label: format!("= {}", value),
+                kind: InlayHintKind::Value,
+            });
+        }
+    }
+}
+
+/// The hints sorted by position
+pub fn get_inlay_hints(s68k: &S68k) -> Vec<InlayHint> {
+    //only a checked program can be compiled
+    let compiler = match s68k.semantic_check().is_empty() {
+        true => s68k.compile().ok(),
+        false => None,
+    };
+    let no_labels = LabelTable::new();
+    let labels = compiler.as_ref().map(|c| c.get_labels_map()).unwrap_or(&no_labels);
+    let mut hints = vec![];
+    if let Some(compiler) = &compiler {
+        get_layout_hints(s68k, compiler, &mut hints);
+    }
+    get_value_hints(s68k, &get_equ_values(s68k.get_lexed_lines(), labels), &mut hints);
+    //the sort is stable so the hints at the end of a line stay in order
+    hints.sort_by_key(|hint| (hint.line, hint.column));
+    hints
+}
@@ -0,0 +1,291 @@
+use alloc::{format, string::{String, ToString}, vec, vec::Vec};
+use crate::scripted_input::{ExhaustedPolicy, ScriptedInput};
+
+/*
+    Random inputs for the scripted input that are the same for the same seed, so that a grader can run many
+    cases and any failing one can be run again from its seed and index:
+
+    let generator = InputGenerator::new(42)
+        .with_number(-100, 100)
+        .with_text("[a-z]{3,8}")
+        .with_list(0, 5, 1, 9);
+    let input = generator.generate(3)?;
+
+    Every field is a line, a list is a line with its length and then a line for every number.
+    Text patterns are made of literal chars, classes like [a-z0-9_], the escapes \d, \w, \s and \ before a
+    special char, and the quantifiers {n}, {n,m}, ?, * and +, the last two repeat up to 8 times
+*/
+
+/// Repetitions of the * and + quantifiers
+const MAX_REPETITIONS: usize = 8;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InputField {
+    /// A number between min and max, both included
+    Number { min: i32, max: i32 },
+    /// A string made from a pattern
+    Text(String),
+    /// One of the strings
+    Choice(Vec<String>),
+    /// The length of the list and then its numbers
+    List {
+        min_length: usize,
+        max_length: usize,
+        min: i32,
+        max: i32,
+    },
+}
+
+/// splitmix64, small and with no dependencies, the sequence must never change to keep the seeds reproducible
+#[derive(Debug, Clone)]
+struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+    fn next(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+    /// A number between min and max, both included
+    fn range(&mut self, min: i64, max: i64) -> i64 {
+        let span = (max - min) as u64 + 1;
+        min + (self.next() % span) as i64
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum PatternAtom {
+    Literal(char),
+    Class(Vec<char>),
+}
+
+fn get_escape_class(c: char) -> Option<Vec<char>> {
+    match c {
+        'd' => Some(('0'..='9').collect()),
+        'w' => Some(('a'..='z').chain('A'..='Z').chain('0'..='9').chain(['_']).collect()),
+        's' => Some(vec![' ']),
+        _ => None,
+    }
+}
+
+fn parse_class(chars: &mut core::iter::Peekable<core::str::Chars>) -> Result<Vec<char>, String> {
+    let mut class = vec![];
+    loop {
+        let c = match chars.next() {
+            Some(']') => break,
+            Some('\\') => match chars.next() {
+                Some(c) => match get_escape_class(c) {
+                    Some(escaped) => {
+                        class.extend(escaped);
+                        continue;
+                    }
+                    None => c,
+                },
+                None => return Err("The pattern ends with a \\".to_string()),
+            },
+            Some(c) => c,
+            None => return Err("Missing ] at the end of a class".to_string()),
+        };
+        let mut ahead = chars.clone();
+        match (ahead.next(), ahead.next()) {
+            (Some('-'), Some(end)) if end != ']' => {
+                chars.next();
+                chars.next();
+                if end < c {
+                    return Err(format!("Invalid range {}-{}", c, end));
+                }
+                class.extend(c..=end);
+            }
+            _ => class.push(c),
+        }
+    }
+    match class.is_empty() {
+        true => Err("Empty class []".to_string()),
+        false => Ok(class),
+    }
+}
+
+fn parse_quantifier(chars: &mut core::iter::Peekable<core::str::Chars>) -> Result<(usize, usize), String> {
+    let quantifier = match chars.peek() {
+        Some('?') => (0, 1),
+        Some('*') => (0, MAX_REPETITIONS),
+        Some('+') => (1, MAX_REPETITIONS),
+        Some('{') => {
+            chars.next();
+            let inner: String = chars.by_ref().take_while(|c| *c != '}').collect();
+            let parse = |value: &str| {
+                value
+                    .trim()
+                    .parse::<usize>()
+                    .map_err(|_| format!("Invalid quantifier {{{}}}", inner))
+            };
+            let (min, max) = match inner.split_once(',') {
+                Some((min, max)) => (parse(min)?, parse(max)?),
+                None => (parse(&inner)?, parse(&inner)?),
+            };
+            if max < min {
+                return Err(format!("Invalid quantifier {{{}}}", inner));
+            }
+            return Ok((min, max));
+        }
+        _ => return Ok((1, 1)),
+    };
+    chars.next();
+    Ok(quantifier)
+}
+
+fn parse_pattern(pattern: &str) -> Result<Vec<(PatternAtom, usize, usize)>, String> {
+    let mut atoms = vec![];
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        let atom = match c {
+            '[' => PatternAtom::Class(parse_class(&mut chars)?),
+            '\\' => match chars.next() {
+                Some(c) => match get_escape_class(c) {
+                    Some(class) => PatternAtom::Class(class),
+                    None => PatternAtom::Literal(c),
+                },
+                None => return Err("The pattern ends with a \\".to_string()),
+            },
+            '?' | '*' | '+' | '{' => return Err(format!("Quantifier {} without anything to repeat", c)),
+            c => PatternAtom::Literal(c),
+        };
+        let (min, max) = parse_quantifier(&mut chars)?;
+        atoms.push((atom, min, max));
+    }
+    Ok(atoms)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputGenerator {
+    seed: u64,
+    fields: Vec<InputField>,
+    policy: ExhaustedPolicy,
+}
+
+impl InputGenerator {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            fields: vec![],
+            policy: ExhaustedPolicy::default(),
+        }
+    }
+    pub fn with_field(mut self, field: InputField) -> Self {
+        self.fields.push(field);
+        self
+    }
+    pub fn with_number(self, min: i32, max: i32) -> Self {
+        self.with_field(InputField::Number { min, max })
+    }
+    pub fn with_text(self, pattern: &str) -> Self {
+        self.with_field(InputField::Text(pattern.to_string()))
+    }
+    pub fn with_choice(self, choices: &[&str]) -> Self {
+        self.with_field(InputField::Choice(choices.iter().map(|c| c.to_string()).collect()))
+    }
+    pub fn with_list(self, min_length: usize, max_length: usize, min: i32, max: i32) -> Self {
+        self.with_field(InputField::List {
+            min_length,
+            max_length,
+            min,
+            max,
+        })
+    }
+    /// What the program reads after the generated lines
+    pub fn with_policy(mut self, policy: ExhaustedPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+    pub fn get_seed(&self) -> u64 {
+        self.seed
+    }
+    pub fn get_fields(&self) -> &Vec<InputField> {
+        &self.fields
+    }
+    /// Checks the fields, a range or pattern that can't generate anything is an error
+    pub fn validate(&self) -> Result<(), String> {
+        for field in &self.fields {
+            match field {
+                InputField::Number { min, max } | InputField::List { min, max, .. } if min > max => {
+                    return Err(format!("Invalid range of numbers {}..{}", min, max))
+                }
+                InputField::List {
+                    min_length, max_length, ..
+                } if min_length > max_length => {
+                    return Err(format!("Invalid range of lengths {}..{}", min_length, max_length))
+                }
+                InputField::Text(pattern) => {
+                    parse_pattern(pattern).map_err(|e| format!("Invalid pattern \"{}\": {}", pattern, e))?;
+                }
+                InputField::Choice(choices) if choices.is_empty() => {
+                    return Err("A choice needs at least one string".to_string())
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+    /// The lines of a case, the same case of the same seed always has the same lines
+    pub fn generate_lines(&self, case: usize) -> Result<Vec<String>, String> {
+        self.validate()?;
+        //every case has its own sequence, so a case can be made again without the ones before it
+        let mut rng = Rng::new(self.seed);
+        rng.state ^= Rng::new(case as u64).next();
+        let mut lines = vec![];
+        for field in &self.fields {
+            match field {
+                InputField::Number { min, max } => lines.push(rng.range(*min as i64, *max as i64).to_string()),
+                InputField::Text(pattern) => {
+                    let mut text = String::new();
+                    for (atom, min, max) in parse_pattern(pattern)? {
+                        let repetitions = rng.range(min as i64, max as i64);
+                        for _ in 0..repetitions {
+                            match &atom {
+                                PatternAtom::Literal(c) => text.push(*c),
+                                PatternAtom::Class(class) => {
+                                    text.push(class[rng.range(0, class.len() as i64 - 1) as usize])
+                                }
+                            }
+                        }
+                    }
+                    lines.push(text);
+                }
+                InputField::Choice(choices) => {
+                    lines.push(choices[rng.range(0, choices.len() as i64 - 1) as usize].clone())
+                }
+                InputField::List {
+                    min_length,
+                    max_length,
+                    min,
+                    max,
+                } => {
+                    let length = rng.range(*min_length as i64, *max_length as i64);
+                    lines.push(length.to_string());
+                    for _ in 0..length {
+                        lines.push(rng.range(*min as i64, *max as i64).to_string());
+                    }
+                }
+            }
+        }
+        Ok(lines)
+    }
+    pub fn generate(&self, case: usize) -> Result<ScriptedInput, String> {
+        let mut input = ScriptedInput::new(self.policy);
+        for line in self.generate_lines(case)? {
+            input.push_line(line);
+        }
+        Ok(input)
+    }
+    /// The first count cases
+    pub fn generate_cases(&self, count: usize) -> Result<Vec<ScriptedInput>, String> {
+        (0..count).map(|case| self.generate(case)).collect()
+    }
+}
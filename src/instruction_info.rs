@@ -0,0 +1,494 @@
+use alloc::{format, string::{String, ToString}, vec, vec::Vec};
+use serde::Serialize;
+
+use crate::{
+    completion::{get_addressing_modes, get_valid_sizes},
+    constants::{BRANCHES, DECREMENT_BRANCHES, INSTRUCTIONS, SETS},
+    cpu_model::{CpuFeature, CpuModel},
+    semantic::{get_instruction_rule, Form, Modes, Sizes},
+    timing::get_timing_table,
+};
+
+/*
+    Documentation of every mnemonic for hovers and teaching tools: what it does, how it changes the flags,
+    the sizes and addressing modes it takes, how its first word is encoded and how many cycles it needs
+    with each mode. Descriptions, flags and encodings are kept in the tables below, condition code families
+    like Bcc share one entry. Sizes and addressing modes are the ones of the forms of the legality tables
+    the model has, the mnemonics that are not in the tables are probed on the semantic checker instead,
+    and the cycles come from the timing table of the model, so they agree with what the assembler and
+    interpreter do
+*/
+
+/// Effect of an instruction on one flag of the CCR
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum FlagEffect {
+    Unaffected,
+    /// Set or cleared depending on the result
+    Affected,
+    Cleared,
+    Set,
+    Undefined,
+}
+
+impl FlagEffect {
+    fn from_char(c: char) -> Self {
+        match c {
+            '*' => FlagEffect::Affected,
+            '0' => FlagEffect::Cleared,
+            '1' => FlagEffect::Set,
+            'U' => FlagEffect::Undefined,
+            _ => FlagEffect::Unaffected,
+        }
+    }
+    /// Symbol used by the user manuals, "-" unaffected, "*" affected, "0", "1" and "U" undefined
+    pub fn get_symbol(&self) -> char {
+        match self {
+            FlagEffect::Unaffected => '-',
+            FlagEffect::Affected => '*',
+            FlagEffect::Cleared => '0',
+            FlagEffect::Set => '1',
+            FlagEffect::Undefined => 'U',
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct FlagEffects {
+    pub x: FlagEffect,
+    pub n: FlagEffect,
+    pub z: FlagEffect,
+    pub v: FlagEffect,
+    pub c: FlagEffect,
+}
+
+impl FlagEffects {
+    /// Parses the XNZVC columns of the user manuals, like "-**00"
+    fn new(flags: &str) -> Self {
+        let flags: Vec<FlagEffect> = flags.chars().map(FlagEffect::from_char).collect();
+        Self {
+            x: flags[0],
+            n: flags[1],
+            z: flags[2],
+            v: flags[3],
+            c: flags[4],
+        }
+    }
+    pub fn get_symbols(&self) -> String {
+        [self.x, self.n, self.z, self.v, self.c]
+            .iter()
+            .map(|f| f.get_symbol())
+            .collect()
+    }
+}
+
+/// Cycles of the instruction when an operand uses the addressing mode
+#[derive(Debug, Clone, Serialize)]
+pub struct ModeCycles {
+    pub mode: &'static str,
+    pub byte_word: u32,
+    pub long: u32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct InstructionInfo {
+    pub mnemonic: String,
+    /// Name of the family in the user manuals, like "Bcc" for BEQ
+    pub family: &'static str,
+    pub description: &'static str,
+    pub operation: &'static str,
+    pub flags: FlagEffects,
+    pub sizes: Vec<&'static str>,
+    /// Addressing modes accepted by every operand, in order
+    pub operands: Vec<Vec<&'static str>>,
+    /// Bits of the first word of every form like in the user manuals, the letters are the fields set by
+    /// the operands and the size: s size, r register, m mode, c condition, d data or displacement,
+    /// o opmode, n shift count and x the other fields
+    pub encodings: &'static [&'static str],
+    pub cycles: Vec<ModeCycles>,
+    pub cpu_model: CpuModel,
+    /// Oldest model that has the instruction
+    pub minimum_model: CpuModel,
+    /// If the model of the info has the instruction
+    pub available: bool,
+}
+
+const FPU_BRANCHES: &[&str] = &[
+    "fbeq", "fbne", "fbgt", "fbngt", "fbge", "fbnge", "fblt", "fbnlt", "fble", "fbnle", "fbgl", "fbngl", "fbgle",
+    "fbngle", "fbor", "fbun", "fbt", "fbf",
+];
+const BITFIELDS: &[&str] = &["bftst", "bfextu", "bfexts", "bfffo", "bfchg", "bfclr", "bfset", "bfins"];
+
+/// Family, description, operation and XNZVC flags
+pub(crate) fn get_entry(name: &str) -> Option<(&'static str, &'static str, &'static str, &'static str)> {
+    let entry = match name {
+        _ if BRANCHES.contains(&name) => ("Bcc", "Branch if the condition is true", "If cc then PC + d -> PC", "-----"),
+        _ if DECREMENT_BRANCHES.contains(&name) => (
+            "DBcc",
+            "Test the condition, decrement and branch, used for loops",
+            "If not cc then (Dn - 1 -> Dn; if Dn != -1 then PC + d -> PC)",
+            "-----",
+        ),
+        _ if SETS.contains(&name) => ("Scc", "Set a byte to ones if the condition is true, else to zeros", "If cc then 1s -> Destination else 0s -> Destination", "-----"),
+        _ if FPU_BRANCHES.contains(&name) => ("FBcc", "Branch if the floating point condition is true", "If cc then PC + d -> PC", "-----"),
+        "bftst" => ("BFTST", "Test a bit field", "Test bit field", "-**00"),
+        "bfextu" => ("BFEXTU", "Extract an unsigned bit field into a data register", "Bit field -> Dn", "-**00"),
+        "bfexts" => ("BFEXTS", "Extract a sign extended bit field into a data register", "Bit field -> Dn", "-**00"),
+        "bfffo" => ("BFFFO", "Find the offset of the first set bit of a bit field", "Offset of first one -> Dn", "-**00"),
+        "bfchg" => ("BFCHG", "Invert a bit field", "~Bit field -> Bit field", "-**00"),
+        "bfclr" => ("BFCLR", "Clear a bit field", "0s -> Bit field", "-**00"),
+        "bfset" => ("BFSET", "Set a bit field", "1s -> Bit field", "-**00"),
+        "bfins" => ("BFINS", "Insert the low bits of a data register into a bit field", "Dn -> Bit field", "-**00"),
+        "add" => ("ADD", "Add binary", "Source + Destination -> Destination", "*****"),
+        "adda" => ("ADDA", "Add to an address register, the flags are not changed", "Source + An -> An", "-----"),
+        "addi" => ("ADDI", "Add an immediate value", "#data + Destination -> Destination", "*****"),
+        "addq" => ("ADDQ", "Add a value from 1 to 8, the flags are not changed for address registers", "#data + Destination -> Destination", "*****"),
+        "sub" => ("SUB", "Subtract binary", "Destination - Source -> Destination", "*****"),
+        "suba" => ("SUBA", "Subtract from an address register, the flags are not changed", "An - Source -> An", "-----"),
+        "subi" => ("SUBI", "Subtract an immediate value", "Destination - #data -> Destination", "*****"),
+        "subq" => ("SUBQ", "Subtract a value from 1 to 8, the flags are not changed for address registers", "Destination - #data -> Destination", "*****"),
+        "and" => ("AND", "Logical and", "Source & Destination -> Destination", "-**00"),
+        "andi" => ("ANDI", "Logical and with an immediate value", "#data & Destination -> Destination", "-**00"),
+        "or" => ("OR", "Logical or", "Source | Destination -> Destination", "-**00"),
+        "ori" => ("ORI", "Logical or with an immediate value", "#data | Destination -> Destination", "-**00"),
+        "eor" => ("EOR", "Logical exclusive or", "Dn ^ Destination -> Destination", "-**00"),
+        "eori" => ("EORI", "Logical exclusive or with an immediate value", "#data ^ Destination -> Destination", "-**00"),
+        "not" => ("NOT", "Logical complement", "~Destination -> Destination", "-**00"),
+        "neg" => ("NEG", "Negate", "0 - Destination -> Destination", "*****"),
+        "clr" => ("CLR", "Clear an operand", "0 -> Destination", "-0100"),
+        "tst" => ("TST", "Test an operand, comparing it with zero", "Destination - 0, sets the flags", "-**00"),
+        "cmp" => ("CMP", "Compare", "Destination - Source, sets the flags", "-****"),
+        "cmpa" => ("CMPA", "Compare with an address register", "An - Source, sets the flags", "-****"),
+        "cmpi" => ("CMPI", "Compare with an immediate value", "Destination - #data, sets the flags", "-****"),
+        "cmpm" => ("CMPM", "Compare memory, both operands are post incremented", "(Ay)+ - (Ax)+, sets the flags", "-****"),
+        "move" => ("MOVE", "Move data from the source to the destination", "Source -> Destination", "-**00"),
+        "movea" => ("MOVEA", "Move to an address register, words are sign extended", "Source -> An", "-----"),
+        "moveq" => ("MOVEQ", "Move a sign extended 8 bit value to a data register", "#data -> Dn", "-**00"),
+        "movem" => ("MOVEM", "Move multiple registers to or from memory", "Registers -> Destination or Source -> Registers", "-----"),
+        "movec" => ("MOVEC", "Move to or from a control register", "Rc -> Rn or Rn -> Rc", "-----"),
+        "moves" => ("MOVES", "Move to or from the address space of the function code registers", "Rn -> Destination or Source -> Rn", "-----"),
+        "lea" => ("LEA", "Load the effective address into an address register", "<ea> -> An", "-----"),
+        "pea" => ("PEA", "Push the effective address on the stack", "SP - 4 -> SP; <ea> -> (SP)", "-----"),
+        "exg" => ("EXG", "Exchange two registers", "Rx <-> Ry", "-----"),
+        "swap" => ("SWAP", "Swap the two words of a data register", "Dn[31:16] <-> Dn[15:0]", "-**00"),
+        "ext" => ("EXT", "Sign extend a byte to a word or a word to a long", "Dn sign extended -> Dn", "-**00"),
+        "extb" => ("EXTB", "Sign extend a byte to a long", "Dn sign extended -> Dn", "-**00"),
+        "muls" => ("MULS", "Signed multiply of two words into a long", "Source * Dn -> Dn", "-***0"),
+        "mulu" => ("MULU", "Unsigned multiply of two words into a long", "Source * Dn -> Dn", "-***0"),
+        "divs" => ("DIVS", "Signed divide, the quotient goes in the low word and the remainder in the high word", "Dn / Source -> Dn", "-***0"),
+        "divu" => ("DIVU", "Unsigned divide, the quotient goes in the low word and the remainder in the high word", "Dn / Source -> Dn", "-***0"),
+        "divsl" => ("DIVSL", "Signed 32 bit divide with a 32 bit remainder", "Dl / Source -> Dl, remainder -> Dr", "-***0"),
+        "divul" => ("DIVUL", "Unsigned 32 bit divide with a 32 bit remainder", "Dl / Source -> Dl, remainder -> Dr", "-***0"),
+        "asl" => ("ASL", "Arithmetic shift left", "Destination << count -> Destination", "*****"),
+        "asr" => ("ASR", "Arithmetic shift right, the sign is kept", "Destination >> count -> Destination", "*****"),
+        "lsl" => ("LSL", "Logical shift left", "Destination << count -> Destination", "***0*"),
+        "lsr" => ("LSR", "Logical shift right", "Destination >> count -> Destination", "***0*"),
+        "rol" => ("ROL", "Rotate left", "Destination rotated by count -> Destination", "-**0*"),
+        "ror" => ("ROR", "Rotate right", "Destination rotated by count -> Destination", "-**0*"),
+        "btst" => ("BTST", "Test a bit", "~(bit of Destination) -> Z", "--*--"),
+        "bchg" => ("BCHG", "Test a bit and invert it", "~(bit of Destination) -> Z and -> bit of Destination", "--*--"),
+        "bclr" => ("BCLR", "Test a bit and clear it", "~(bit of Destination) -> Z; 0 -> bit of Destination", "--*--"),
+        "bset" => ("BSET", "Test a bit and set it", "~(bit of Destination) -> Z; 1 -> bit of Destination", "--*--"),
+        "bra" => ("BRA", "Branch always", "PC + d -> PC", "-----"),
+        "bsr" => ("BSR", "Branch to a subroutine", "SP - 4 -> SP; PC -> (SP); PC + d -> PC", "-----"),
+        "jmp" => ("JMP", "Jump to an address", "<ea> -> PC", "-----"),
+        "jsr" => ("JSR", "Jump to a subroutine", "SP - 4 -> SP; PC -> (SP); <ea> -> PC", "-----"),
+        "rts" => ("RTS", "Return from a subroutine", "(SP) -> PC; SP + 4 -> SP", "-----"),
+        "rte" => ("RTE", "Return from an exception, the status register is restored", "(SP) -> SR; (SP) -> PC", "*****"),
+        "rtr" => ("RTR", "Return from a subroutine and restore the condition codes", "(SP) -> CCR; (SP) -> PC", "*****"),
+        "rtd" => ("RTD", "Return and deallocate parameters", "(SP) -> PC; SP + 4 + d -> SP", "-----"),
+        "link" => ("LINK", "Create a stack frame", "SP - 4 -> SP; An -> (SP); SP -> An; SP + d -> SP", "-----"),
+        "unlk" => ("UNLK", "Remove a stack frame", "An -> SP; (SP) -> An; SP + 4 -> SP", "-----"),
+        "trap" => ("TRAP", "Call a trap handler, TRAP #15 is used for the input and output of the simulator", "Exception of vector 32 + n", "-----"),
+        "trapv" => ("TRAPV", "Trap on overflow", "If V then exception of vector 7", "-----"),
+        "chk" => ("CHK", "Check a register against bounds, traps when it is negative or greater than the bound", "If Dn < 0 or Dn > Source then exception of vector 6", "-*UUU"),
+        "illegal" => ("ILLEGAL", "Raise the illegal instruction exception", "Exception of vector 4", "-----"),
+        "nop" => ("NOP", "No operation", "None", "-----"),
+        "simhalt" => ("SIMHALT", "Stop the program, the halt of the Easy68K simulator", "Halt", "-----"),
+        "linef" => ("LINEF", "Emit a line F opcode for a coprocessor", "Coprocessor instruction", "-----"),
+        "fmove" => ("FMOVE", "Move a floating point value, the FPU condition codes are set instead of the CCR", "Source -> Destination", "-----"),
+        "fadd" => ("FADD", "Floating point add", "Source + FPn -> FPn", "-----"),
+        "fsub" => ("FSUB", "Floating point subtract", "FPn - Source -> FPn", "-----"),
+        "fmul" => ("FMUL", "Floating point multiply", "Source * FPn -> FPn", "-----"),
+        "fdiv" => ("FDIV", "Floating point divide", "FPn / Source -> FPn", "-----"),
+        "fcmp" => ("FCMP", "Floating point compare, sets the FPU condition codes", "FPn - Source", "-----"),
+        _ => return None,
+    };
+    Some(entry)
+}
+
+/// First word of every form, the register forms come before the immediate and memory ones
+fn get_encodings(family: &str) -> &'static [&'static str] {
+    match family {
+        "Bcc" => &["0110 cccc dddd dddd"],
+        "DBcc" => &["0101 cccc 1100 1rrr"],
+        "Scc" => &["0101 cccc 11mm mrrr"],
+        "FBcc" => &["1111 0010 1scc cccc"],
+        "BFTST" => &["1110 1000 11mm mrrr"],
+        "BFEXTU" => &["1110 1001 11mm mrrr"],
+        "BFCHG" => &["1110 1010 11mm mrrr"],
+        "BFEXTS" => &["1110 1011 11mm mrrr"],
+        "BFCLR" => &["1110 1100 11mm mrrr"],
+        "BFFFO" => &["1110 1101 11mm mrrr"],
+        "BFSET" => &["1110 1110 11mm mrrr"],
+        "BFINS" => &["1110 1111 11mm mrrr"],
+        "ADD" => &["1101 rrro ssmm mrrr"],
+        "ADDA" => &["1101 rrrs 11mm mrrr"],
+        "ADDI" => &["0000 0110 ssmm mrrr"],
+        "ADDQ" => &["0101 ddd0 ssmm mrrr"],
+        "SUB" => &["1001 rrro ssmm mrrr"],
+        "SUBA" => &["1001 rrrs 11mm mrrr"],
+        "SUBI" => &["0000 0100 ssmm mrrr"],
+        "SUBQ" => &["0101 ddd1 ssmm mrrr"],
+        "AND" => &["1100 rrro ssmm mrrr"],
+        "ANDI" => &["0000 0010 ssmm mrrr"],
+        "OR" => &["1000 rrro ssmm mrrr"],
+        "ORI" => &["0000 0000 ssmm mrrr"],
+        "EOR" => &["1011 rrr1 ssmm mrrr"],
+        "EORI" => &["0000 1010 ssmm mrrr"],
+        "NOT" => &["0100 0110 ssmm mrrr"],
+        "NEG" => &["0100 0100 ssmm mrrr"],
+        "CLR" => &["0100 0010 ssmm mrrr"],
+        "TST" => &["0100 1010 ssmm mrrr"],
+        "CMP" => &["1011 rrr0 ssmm mrrr"],
+        "CMPA" => &["1011 rrrs 11mm mrrr"],
+        "CMPI" => &["0000 1100 ssmm mrrr"],
+        "CMPM" => &["1011 rrr1 ss00 1rrr"],
+        "MOVE" => &["00ss rrrm mmmm mrrr"],
+        "MOVEA" => &["00ss rrr0 01mm mrrr"],
+        "MOVEQ" => &["0111 rrr0 dddd dddd"],
+        "MOVEM" => &["0100 1x00 1smm mrrr"],
+        "MOVEC" => &["0100 1110 0111 101x"],
+        "MOVES" => &["0000 1110 ssmm mrrr"],
+        "LEA" => &["0100 rrr1 11mm mrrr"],
+        "PEA" => &["0100 1000 01mm mrrr"],
+        "EXG" => &["1100 rrr1 oooo orrr"],
+        "SWAP" => &["0100 1000 0100 0rrr"],
+        "EXT" => &["0100 1000 1s00 0rrr"],
+        "EXTB" => &["0100 1001 1100 0rrr"],
+        "MULS" => &["1100 rrr1 11mm mrrr", "0100 1100 00mm mrrr"],
+        "MULU" => &["1100 rrr0 11mm mrrr", "0100 1100 00mm mrrr"],
+        "DIVS" => &["1000 rrr1 11mm mrrr", "0100 1100 01mm mrrr"],
+        "DIVU" => &["1000 rrr0 11mm mrrr", "0100 1100 01mm mrrr"],
+        "DIVSL" | "DIVUL" => &["0100 1100 01mm mrrr"],
+        "ASL" => &["1110 nnn1 ssx0 0rrr"],
+        "ASR" => &["1110 nnn0 ssx0 0rrr"],
+        "LSL" => &["1110 nnn1 ssx0 1rrr"],
+        "LSR" => &["1110 nnn0 ssx0 1rrr"],
+        "ROL" => &["1110 nnn1 ssx1 1rrr"],
+        "ROR" => &["1110 nnn0 ssx1 1rrr"],
+        "BTST" => &["0000 rrr1 00mm mrrr", "0000 1000 00mm mrrr"],
+        "BCHG" => &["0000 rrr1 01mm mrrr", "0000 1000 01mm mrrr"],
+        "BCLR" => &["0000 rrr1 10mm mrrr", "0000 1000 10mm mrrr"],
+        "BSET" => &["0000 rrr1 11mm mrrr", "0000 1000 11mm mrrr"],
+        "BRA" => &["0110 0000 dddd dddd"],
+        "BSR" => &["0110 0001 dddd dddd"],
+        "JMP" => &["0100 1110 11mm mrrr"],
+        "JSR" => &["0100 1110 10mm mrrr"],
+        "RTS" => &["0100 1110 0111 0101"],
+        "RTE" => &["0100 1110 0111 0011"],
+        "RTR" => &["0100 1110 0111 0111"],
+        "RTD" => &["0100 1110 0111 0100"],
+        "LINK" => &["0100 1110 0101 0rrr"],
+        "UNLK" => &["0100 1110 0101 1rrr"],
+        "TRAP" => &["0100 1110 0100 dddd"],
+        "TRAPV" => &["0100 1110 0111 0110"],
+        "CHK" => &["0100 rrr1 s0mm mrrr"],
+        "ILLEGAL" => &["0100 1010 1111 1100"],
+        "NOP" => &["0100 1110 0111 0001"],
+        "SIMHALT" => &["1111 1111 1111 1111"],
+        "LINEF" => &["1111 dddd dddd dddd"],
+        "FMOVE" | "FADD" | "FSUB" | "FMUL" | "FDIV" | "FCMP" => &["1111 0010 00mm mrrr"],
+        _ => &[],
+    }
+}
+
+/// Name of the addressing mode in the completions and the effective address timings
+fn get_mode_label(mode: Modes) -> &'static str {
+    match mode {
+        Modes::DN => "Dn",
+        Modes::AN => "An",
+        Modes::INDIRECT => "(An)",
+        Modes::POST_INCREMENT => "(An)+",
+        Modes::PRE_DECREMENT => "-(An)",
+        Modes::DISPLACEMENT => "d(An)",
+        Modes::INDEX => "d(An,Xn)",
+        Modes::MEMORY_INDIRECT => "([bd,An],Xn,od)",
+        Modes::ABSOLUTE => "abs",
+        Modes::IMMEDIATE => "#imm",
+        Modes::REG_LIST => "reg list",
+        Modes::REG_PAIR => "Dh:Dl",
+        Modes::FP_REG => "FPn",
+        _ => "bit field",
+    }
+}
+
+/// Sizes and addressing modes of the forms of the legality tables the model has
+fn get_form_signature(forms: &[&Form], cpu_model: CpuModel) -> (Vec<&'static str>, Vec<Vec<&'static str>>) {
+    let sizes = forms.iter().fold(Sizes::NONE, |sizes, form| sizes | form.sizes);
+    let sizes = [(Sizes::BYTE, "b"), (Sizes::WORD, "w"), (Sizes::LONG, "l")]
+        .iter()
+        .filter(|(size, _)| sizes.contains(*size))
+        .map(|(_, name)| *name)
+        .collect();
+    let count = forms.iter().map(|form| form.operands.len()).max().unwrap_or(0);
+    let operands = (0..count)
+        .map(|position| {
+            let mut modes = forms
+                .iter()
+                .filter_map(|form| form.operands.get(position))
+                .fold(Modes::empty(), |modes, form_modes| modes | *form_modes);
+            if !cpu_model.supports(CpuFeature::MemoryIndirect) {
+                modes.remove(Modes::MEMORY_INDIRECT);
+            }
+            modes.iter().map(get_mode_label).collect()
+        })
+        .collect();
+    (sizes, operands)
+}
+
+/// Name of the entry of the timing table
+fn get_timing_name(name: &str, family: &'static str) -> String {
+    match name {
+        "muls" | "mulu" => "MULx".to_string(),
+        "divs" => "DIVS".to_string(),
+        "divu" => "DIVU".to_string(),
+        "divsl" | "divul" => "DIVxL".to_string(),
+        "asl" | "asr" => "ASd".to_string(),
+        "lsl" | "lsr" => "LSd".to_string(),
+        "rol" | "ror" => "ROd".to_string(),
+        _ if BITFIELDS.contains(&name) => "BFx".to_string(),
+        _ => family.to_string(),
+    }
+}
+
+/// Name of the addressing mode in the effective address timings, absolute addresses take the long one
+fn get_timing_mode(mode: &str) -> &str {
+    match mode {
+        "abs" => "Abs.L",
+        "#imm" => "Immediate",
+        _ => mode,
+    }
+}
+
+impl InstructionInfo {
+    /// Information of the mnemonic with the timings of the 68000, the size suffix is ignored
+    pub fn lookup(name: &str) -> Option<InstructionInfo> {
+        Self::lookup_for_model(name, CpuModel::M68000)
+    }
+    /// Information of the mnemonic with the timings of the model, addressing modes that the model
+    /// does not have are left out, but the instruction is returned even if the model does not have it
+    pub fn lookup_for_model(name: &str, cpu_model: CpuModel) -> Option<InstructionInfo> {
+        let name = name.split('.').next().unwrap_or_default().to_lowercase();
+        if !INSTRUCTIONS.contains(&name.as_str()) {
+            return None;
+        }
+        let (family, description, operation, flags) = get_entry(&name)?;
+        let minimum_model = match CpuModel::get_instruction_feature(&name) {
+            Some(feature) => feature.get_minimum_model(),
+            None => CpuModel::M68000,
+        };
+        //the modes are checked on a model that has the instruction
+        let check_model = match cpu_model.verify_instruction(&name) {
+            Ok(_) => cpu_model,
+            Err(_) => minimum_model,
+        };
+        let (sizes, operands) = match get_instruction_rule(&name) {
+            Some(rule) => {
+                let forms: Vec<&Form> = rule.forms.iter().filter(|form| form.is_supported_by(check_model)).collect();
+                get_form_signature(&forms, check_model)
+            }
+            None => {
+                let operands: Vec<Vec<&'static str>> = (0..2)
+                    .map(|position| get_addressing_modes(&name, position, check_model))
+                    .take_while(|modes| !modes.is_empty())
+                    .collect();
+                (get_valid_sizes(&name), operands)
+            }
+        };
+        let table = get_timing_table(cpu_model);
+        let cycles = match table.get_entry(&get_timing_name(&name, family)) {
+            Some(entry) => {
+                let mut modes: Vec<&'static str> = vec![];
+                for mode in operands.iter().flatten() {
+                    if !modes.contains(mode) {
+                        modes.push(mode);
+                    }
+                }
+                modes
+                    .into_iter()
+                    .map(|mode| {
+                        let (byte_word, long) = table
+                            .get_effective_address_entries()
+                            .iter()
+                            .find(|ea| ea.instruction == get_timing_mode(mode))
+                            .map(|ea| (ea.byte_word, ea.long))
+                            .unwrap_or((0, 0));
+                        ModeCycles {
+                            mode,
+                            byte_word: entry.byte_word + byte_word,
+                            long: entry.long + long,
+                        }
+                    })
+                    .collect()
+            }
+            None => vec![],
+        };
+        Some(InstructionInfo {
+            sizes,
+            encodings: get_encodings(family),
+            available: cpu_model.verify_instruction(&name).is_ok(),
+            mnemonic: name,
+            family,
+            description,
+            operation,
+            flags: FlagEffects::new(flags),
+            operands,
+            cycles,
+            cpu_model,
+            minimum_model,
+        })
+    }
+    /// Every mnemonic accepted by the semantic checker, condition codes are listed one by one
+    pub fn get_mnemonics() -> &'static [&'static str] {
+        INSTRUCTIONS
+    }
+    /// Information of every mnemonic with the timings of the model, also the ones the model does not have
+    pub fn all(cpu_model: CpuModel) -> Vec<InstructionInfo> {
+        INSTRUCTIONS
+            .iter()
+            .filter_map(|name| Self::lookup_for_model(name, cpu_model))
+            .collect()
+    }
+    /// Markdown description for hovers
+    pub fn to_markdown(&self) -> String {
+        let mut lines = vec![
+            format!("`{}` ({}) {}", self.mnemonic, self.family, self.description),
+            format!("Operation: `{}`", self.operation),
+            format!("Flags XNZVC: `{}`", self.flags.get_symbols()),
+        ];
+        if !self.sizes.is_empty() {
+            lines.push(format!("Sizes: {}", self.sizes.join(", ")));
+        }
+        for (i, modes) in self.operands.iter().enumerate() {
+            lines.push(format!("Operand {}: {}", i + 1, modes.join(" ")));
+        }
+        if !self.encodings.is_empty() {
+            lines.push(format!("Encoding: `{}`", self.encodings.join("` or `")));
+        }
+        if !self.cycles.is_empty() {
+            let cycles: Vec<String> = self
+                .cycles
+                .iter()
+                .map(|c| match c.byte_word == c.long {
+                    true => format!("{} {}", c.mode, c.byte_word),
+                    false => format!("{} {}/{}", c.mode, c.byte_word, c.long),
+                })
+                .collect();
+            lines.push(format!("Cycles on the {} (b,w/l): {}", self.cpu_model.get_name(), cycles.join(", ")));
+        }
+        if self.minimum_model != CpuModel::M68000 {
+            lines.push(format!("Needs the {} or newer", self.minimum_model.get_name()));
+        }
+        lines.join("\n\n")
+    }
+}
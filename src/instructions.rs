@@ -1,21 +1,25 @@
-use std::{
-    fmt::Debug,
-    str::FromStr,
-};
+use alloc::{format, string::{String, ToString}};
+use core::{fmt::Debug, str::FromStr};
 
 use serde::{Deserialize, Serialize};
+#[cfg(feature = "wasm")]
 use wasm_bindgen::prelude::wasm_bindgen;
 
-#[wasm_bindgen]
+/// Id of the 68881 FPU in the coprocessor interface
+pub const FPU_COPROCESSOR_ID: u8 = 1;
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
 #[derive(Debug, Clone, Copy, Serialize, Eq, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Deserialize))]
 pub enum Size {
     Byte = 1,
     Word = 2,
     Long = 4,
 }
 
-#[wasm_bindgen]
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
 #[derive(Debug, Clone, Copy, Serialize, Eq, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Deserialize))]
 pub enum TargetDirection {
     ToMemory,
     FromMemory,
@@ -32,7 +36,7 @@ impl Size {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, Copy)]
+#[derive(Debug, Clone, Serialize, Deserialize, Copy, PartialEq, Eq)]
 #[serde(tag = "type", content = "value")]
 pub enum RegisterOperand {
     Address(u8),
@@ -48,13 +52,15 @@ impl RegisterOperand{
 }
 
 #[derive(Debug, Clone, Serialize, Copy)]
+#[cfg_attr(feature = "serialize", derive(Deserialize))]
 pub struct IndexRegister {
     pub register: RegisterOperand,
-    //pub scale: u8,
+    pub scale: u8,
     pub size: Size,
 }
 
 #[derive(Debug, Clone, Serialize, Copy)]
+#[cfg_attr(feature = "serialize", derive(Deserialize))]
 pub enum Operand {
     Immediate(u32),
     Register(RegisterOperand),
@@ -70,8 +76,21 @@ pub enum Operand {
         offset: i32,
         index: IndexRegister,
     },
+    /*
+        68020 memory indirect, the base and index can be suppressed, when post_indexed is true
+        the index is added after reading the intermediate address: ([bd,An],Xn,od)
+     */
+    MemoryIndirect {
+        base: Option<RegisterOperand>,
+        base_displacement: i32,
+        index: Option<IndexRegister>,
+        post_indexed: bool,
+        outer_displacement: i32,
+    },
 
     Absolute(usize),
+    /// Registers of a MOVEM like d0-d3/a0, bit 0 is d0 and bit 15 is a7
+    RegisterList(u16),
 }
 /*
 Thanks to:  https://github.com/transistorfet/moa/blob/main/emulator/cpus/m68k/src/instructions.rs
@@ -80,14 +99,16 @@ for the Conditions and inspiration
 
 
 #[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "serialize", derive(Deserialize))]
 pub struct Label {
     pub name: String,
     pub address: usize,
     pub line: usize,
 }
 
-#[wasm_bindgen]
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
 #[derive(Copy, Clone, Debug, Serialize)]
+#[cfg_attr(feature = "serialize", derive(Deserialize))]
 pub enum Condition {
     True,
     False,
@@ -133,19 +154,202 @@ impl FromStr for Condition {
     }
 }
 
+/*
+    Control registers accessible with MOVEC, the 68010 added them together with the vector base register
+ */
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ControlRegister {
+    SFC,
+    DFC,
+    USP,
+    VBR,
+}
+
+impl FromStr for ControlRegister {
+    type Err = String;
+    fn from_str(s: &str) -> Result<ControlRegister, Self::Err> {
+        Ok(match s.to_lowercase().as_str() {
+            "sfc" => ControlRegister::SFC,
+            "dfc" => ControlRegister::DFC,
+            "usp" => ControlRegister::USP,
+            "vbr" => ControlRegister::VBR,
+            _ => return Err(format!("Invalid control register: {}", s)),
+        })
+    }
+}
+
+/*
+    The status register and its low byte, the condition code register, as the operand of MOVE and of
+    ANDI/ORI/EORI. Writing the whole status register is privileged, the condition codes can be written in user mode
+ */
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum StatusRegister {
+    SR,
+    CCR,
+}
+
+impl FromStr for StatusRegister {
+    type Err = String;
+    fn from_str(s: &str) -> Result<StatusRegister, Self::Err> {
+        Ok(match s.to_lowercase().as_str() {
+            "sr" => StatusRegister::SR,
+            "ccr" => StatusRegister::CCR,
+            _ => return Err(format!("Invalid status register: {}", s)),
+        })
+    }
+}
+
+/*
+    Data formats of the FPU, extended precision values are kept as doubles once loaded in a register
+ */
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Copy, Clone, Debug, Serialize, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize", derive(Deserialize))]
+pub enum FloatFormat {
+    Byte,
+    Word,
+    Long,
+    Single,
+    Double,
+    Extended,
+}
+
+impl FloatFormat {
+    pub fn to_bytes(&self) -> usize {
+        match self {
+            FloatFormat::Byte => 1,
+            FloatFormat::Word => 2,
+            FloatFormat::Long | FloatFormat::Single => 4,
+            FloatFormat::Double => 8,
+            FloatFormat::Extended => 12,
+        }
+    }
+    /// The integer size used to access the operand, if it fits in a normal operand
+    pub fn to_size(&self) -> Option<Size> {
+        match self {
+            FloatFormat::Byte => Some(Size::Byte),
+            FloatFormat::Word => Some(Size::Word),
+            FloatFormat::Long | FloatFormat::Single => Some(Size::Long),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Serialize)]
+#[cfg_attr(feature = "serialize", derive(Deserialize))]
+pub enum FloatOperand {
+    Register(u8),
+    Immediate(f64),
+    Effective(Operand),
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Copy, Clone, Debug, Serialize, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize", derive(Deserialize))]
+pub enum FloatCondition {
+    True,
+    False,
+    Equal,
+    NotEqual,
+    GreaterThan,
+    NotGreaterThan,
+    GreaterThanOrEqual,
+    NotGreaterThanOrEqual,
+    LessThan,
+    NotLessThan,
+    LessThanOrEqual,
+    NotLessThanOrEqual,
+    GreaterOrLess,
+    NotGreaterOrLess,
+    GreaterLessOrEqual,
+    NotGreaterLessOrEqual,
+    Ordered,
+    Unordered,
+}
+
+impl FromStr for FloatCondition {
+    type Err = String;
+    fn from_str(s: &str) -> Result<FloatCondition, Self::Err> {
+        Ok(match s.to_lowercase().as_str() {
+            "t" => FloatCondition::True,
+            "f" => FloatCondition::False,
+            "eq" => FloatCondition::Equal,
+            "ne" => FloatCondition::NotEqual,
+            "gt" => FloatCondition::GreaterThan,
+            "ngt" => FloatCondition::NotGreaterThan,
+            "ge" => FloatCondition::GreaterThanOrEqual,
+            "nge" => FloatCondition::NotGreaterThanOrEqual,
+            "lt" => FloatCondition::LessThan,
+            "nlt" => FloatCondition::NotLessThan,
+            "le" => FloatCondition::LessThanOrEqual,
+            "nle" => FloatCondition::NotLessThanOrEqual,
+            "gl" => FloatCondition::GreaterOrLess,
+            "ngl" => FloatCondition::NotGreaterOrLess,
+            "gle" => FloatCondition::GreaterLessOrEqual,
+            "ngle" => FloatCondition::NotGreaterLessOrEqual,
+            "or" => FloatCondition::Ordered,
+            "un" => FloatCondition::Unordered,
+            _ => return Err(format!("Invalid floating point condition: {}", s)),
+        })
+    }
+}
+
+#[derive(Copy, Clone, Debug, Serialize, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize", derive(Deserialize))]
+pub enum BitfieldOperation {
+    Test,
+    ExtractUnsigned,
+    ExtractSigned,
+    FindFirstOne,
+    Change,
+    Clear,
+    Set,
+    Insert,
+}
+
+impl FromStr for BitfieldOperation {
+    type Err = String;
+    fn from_str(s: &str) -> Result<BitfieldOperation, Self::Err> {
+        Ok(match s.to_lowercase().as_str() {
+            "bftst" => BitfieldOperation::Test,
+            "bfextu" => BitfieldOperation::ExtractUnsigned,
+            "bfexts" => BitfieldOperation::ExtractSigned,
+            "bfffo" => BitfieldOperation::FindFirstOne,
+            "bfchg" => BitfieldOperation::Change,
+            "bfclr" => BitfieldOperation::Clear,
+            "bfset" => BitfieldOperation::Set,
+            "bfins" => BitfieldOperation::Insert,
+            _ => return Err(format!("Invalid bitfield instruction: {}", s)),
+        })
+    }
+}
+
+/// Offset or width of a bitfield, either a constant or the value of a data register
+#[derive(Copy, Clone, Debug, Serialize)]
+#[cfg_attr(feature = "serialize", derive(Deserialize))]
+pub enum BitfieldValue {
+    Immediate(u32),
+    Register(RegisterOperand),
+}
+
 #[derive(Copy, Clone, Debug, Serialize)]
+#[cfg_attr(feature = "serialize", derive(Deserialize))]
 pub enum ShiftDirection {
     Right,
     Left,
 }
 
 #[derive(Copy, Clone, Debug, Serialize)]
+#[cfg_attr(feature = "serialize", derive(Deserialize))]
 pub enum Sign {
     Signed,
     Unsigned,
 }
 
 #[derive(Clone, Debug, Serialize, Copy)]
+#[cfg_attr(feature = "serialize", derive(Deserialize))]
 pub enum Instruction {
     ADDA(Operand, RegisterOperand, Size),
     SUBA(Operand, RegisterOperand, Size),
@@ -172,6 +376,32 @@ pub enum Instruction {
     CMPM(Operand, Operand, Size),
     DIVx(Operand, RegisterOperand, Sign),
     MULx(Operand, RegisterOperand, Sign),
+    /*
+        32 bit multiply, when high is present the 64 bit result is stored in high:low
+     */
+    MULxL {
+        source: Operand,
+        low: RegisterOperand,
+        high: Option<RegisterOperand>,
+        sign: Sign,
+    },
+    /*
+        32 bit divide, the dividend is remainder:quotient when long_dividend is true
+     */
+    DIVxL {
+        source: Operand,
+        quotient: RegisterOperand,
+        remainder: Option<RegisterOperand>,
+        sign: Sign,
+        long_dividend: bool,
+    },
+    BFx {
+        operation: BitfieldOperation,
+        target: Operand,
+        offset: BitfieldValue,
+        width: BitfieldValue,
+        register: Option<RegisterOperand>,
+    },
     SWAP(RegisterOperand),
     CLR(Operand, Size),
     EXG(RegisterOperand, RegisterOperand),
@@ -202,7 +432,45 @@ pub enum Instruction {
     JMP(Operand),
     BSR(u32),
     TRAP(u8),
+    /// Traps with the CHK vector when the word of the register is negative or greater than the bound
+    CHK(Operand, RegisterOperand),
+    /// Traps with the TRAPV vector when the overflow flag is set
+    TRAPV,
+    ILLEGAL,
+    NOP,
+    /// Stops the simulator like the one of Easy68K, encoded as $FFFFFFFF
+    SIMHALT,
     RTS,
+    FMOVE(FloatOperand, FloatOperand, FloatFormat),
+    FADD(FloatOperand, u8, FloatFormat),
+    FSUB(FloatOperand, u8, FloatFormat),
+    FMUL(FloatOperand, u8, FloatFormat),
+    FDIV(FloatOperand, u8, FloatFormat),
+    FCMP(FloatOperand, u8, FloatFormat),
+    FBcc(u32, FloatCondition),
+    /*
+        Generic coprocessor instruction, the opcode word selects the coprocessor with bits 9-11
+     */
+    LINEF(u16, Option<Operand>),
+    MOVEC {
+        register: RegisterOperand,
+        control_register: ControlRegister,
+        to_control: bool,
+    },
+    MOVES(Operand, Operand, Size),
+    RTD(i16),
+    RTE,
+    /// Pops the condition codes and then the PC
+    RTR,
+    MOVEtoSR(Operand, StatusRegister),
+    MOVEfromSR(Operand, StatusRegister),
+    ANDItoSR(u16, StatusRegister),
+    ORItoSR(u16, StatusRegister),
+    EORItoSR(u16, StatusRegister),
+    MOVEUSP {
+        register: RegisterOperand,
+        to_usp: bool,
+    },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -211,12 +479,19 @@ pub enum Interrupt {
     DisplayStringWithCRLF(String),
     DisplayStringWithoutCRLF(String),
     ReadKeyboardString,
-    DisplayNumber(u32),
+    DisplayNumber(i32),
     ReadNumber,
     ReadChar,
     DisplayChar(char),
     GetTime,
     Terminate,
+    IsInputPending,
+    ClearScreen,
+    /// Column and row, starting from 0
+    PositionCursor(u8, u8),
+    SetEcho(bool),
+    /// Displays the string without CRLF, then reads a number
+    ReadNumberWithPrompt(String),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -231,12 +506,31 @@ pub enum InterruptResult {
     DisplayChar,
     GetTime(u32),
     Terminate,
+    IsInputPending(bool),
+    ClearScreen,
+    PositionCursor,
+    SetEcho,
+    ReadNumberWithPrompt(i32),
 }
 
 impl Instruction {
     pub fn get_instruction_name(&self) -> String {
         let string = format!("{:?}", self);
-        let mut string = string.split('(');
+        let mut string = string.split(['(', ' ']);
         string.next().unwrap().to_string()
     }
+    /// Id of the coprocessor that executes the instruction, for the instructions in the line-F opcode space
+    pub fn get_coprocessor_id(&self) -> Option<u8> {
+        match self {
+            Instruction::FMOVE(..)
+            | Instruction::FADD(..)
+            | Instruction::FSUB(..)
+            | Instruction::FMUL(..)
+            | Instruction::FDIV(..)
+            | Instruction::FCMP(..)
+            | Instruction::FBcc(..) => Some(FPU_COPROCESSOR_ID),
+            Instruction::LINEF(opcode, _) => Some(((opcode >> 9) & 7) as u8),
+            _ => None,
+        }
+    }
 }
@@ -10,23 +10,58 @@
     There needs to be added a way to only apply the side effect once, and then store the result to the register.
 */
 use core::panic;
-use std::{collections::HashMap, hash::Hash};
+use core::{cell::Cell, error::Error, fmt, hash::Hash};
+use alloc::{borrow::Cow, boxed::Box, format, string::{String, ToString}, vec, vec::Vec};
+use crate::collections::HashMap;
 
 use bitflags::bitflags;
 use serde::{Deserialize, Serialize};
+#[cfg(feature = "wasm")]
 use wasm_bindgen::{JsValue, prelude::wasm_bindgen};
 
 use crate::{
+    assembler::encode_instruction,
+    breakpoints::{BreakHit, Breakpoint, BreakpointLocation, Watchpoint},
     compiler::{Compiler, Directive, InstructionLine},
-    debugger::{Debugger, ExecutionStep, MutationOperation},
+    cost_report::get_encoded_size,
+    cpu_model::{CpuFeature, CpuModel},
+    debugger::{Debugger, ExecutionStep, MutationOperation, StateChange, StepMode, StepResult},
+    exceptions::Exception,
+    coprocessor::{Coprocessor, CoprocessorBus, FPU_COPROCESSOR_ID},
+    fpu::Fpu,
+    timing::{get_timing_table, LineCycles, RuntimeOperands, TimingTable},
     instructions::{
-        Condition, Instruction, Interrupt, InterruptResult, Label, Operand, RegisterOperand,
-        ShiftDirection, Sign, Size,
+        BitfieldOperation, BitfieldValue, Condition, ControlRegister,
+        IndexRegister, Instruction, Interrupt, InterruptResult, Label, Operand,
+        RegisterOperand, ShiftDirection, Sign, Size, StatusRegister,
     },
+    lesson_profile::LessonProfile,
+    lexer::LexedLine,
+    host_io::HostIo,
+    interrupt_source::{InterruptSource, PeriodicInterrupt},
     math::*,
+    memory_region::{Access, MemoryRegion, Permissions},
+    mmio::{MappedDevice, MmioDevice},
+    sandbox::{SandboxConfig, SandboxLimit, DEFAULT_MEMORY_SIZE},
+    source_map::SourceMap,
+    symbol::LabelTable,
+    utils::to_radix,
 };
+#[cfg(feature = "wasm")]
+use crate::stack_frames::get_stack_view;
 use crate::instructions::TargetDirection;
 
+/// Supervisor bit of the status register, when clear A7 is the user stack pointer
+pub const SR_SUPERVISOR: u16 = 0x2000;
+/// Trace bit of the status register, cleared when an exception is taken
+const SR_TRACE: u16 = 0x8000;
+/// The interrupt priority mask, raised to the level of the interrupt being handled
+const SR_INTERRUPT_MASK: u16 = 0x0700;
+/// The bits of the status register that exist: trace, supervisor, interrupt mask and condition codes
+const SR_SYSTEM_MASK: u16 = 0xA700;
+/// Bytes of the longest instruction, a MOVE between two memory indirect operands of the 68020
+const MAX_INSTRUCTION_SIZE: usize = 22;
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 enum Used {
     Once,
@@ -34,7 +69,7 @@ enum Used {
 }
 
 bitflags! {
-    #[wasm_bindgen]
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
     #[derive(Serialize, Copy, Clone, Debug)]
     pub struct Flags: u16 {
         const Carry    = 1<<1;
@@ -57,6 +92,13 @@ impl Flags {
     pub fn clear(&mut self) {
         *self = Flags::empty();
     }
+    /// Converts the flags to the bit layout of the real CCR register (XNZVC)
+    pub fn to_ccr(self) -> u8 {
+        (self.bits() >> 1) as u8
+    }
+    pub fn from_ccr(ccr: u8) -> Self {
+        Flags::from_bits_truncate(((ccr & 0x1F) as u16) << 1)
+    }
     pub fn get_status(&self) -> String {
         format!(
             "X:{} N:{} Z:{} V:{} C:{}",
@@ -100,38 +142,186 @@ impl MemoryCell {
 }
 
 #[derive(Debug)]
-#[wasm_bindgen]
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
 pub struct Memory {
     data: Vec<u8>,
+    //ranges that can't be written, start included and end excluded
+    protected: Vec<(usize, usize)>,
+    //when empty every address can be accessed, see the memory_region module
+    regions: Vec<MemoryRegion>,
+    //address and length of the writes, only while the debugging steps track them
+    written: Option<Vec<(usize, usize)>>,
+    watchpoints: Vec<Watchpoint>,
+    //the first watchpoint hit since it was taken, the reads don't borrow the memory mutably
+    watch_hit: Cell<Option<BreakHit>>,
+    devices: Vec<MappedDevice>,
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Memory {
     pub fn new() -> Self {
+        Self::with_size(DEFAULT_MEMORY_SIZE) //16mb
+    }
+    pub fn with_size(size: usize) -> Self {
         Self {
-            data: vec![255; 0x01000000], //16mb
+            data: vec![255; size],
+            protected: vec![],
+            regions: vec![],
+            written: None,
+            watchpoints: vec![],
+            watch_hit: Cell::new(None),
+            devices: vec![],
+        }
+    }
+    pub fn get_size(&self) -> usize {
+        self.data.len()
+    }
+    pub fn add_region(&mut self, region: MemoryRegion) {
+        self.regions.push(region);
+    }
+    pub fn get_regions(&self) -> &[MemoryRegion] {
+        &self.regions
+    }
+    pub fn get_region_at(&self, address: usize) -> Option<&MemoryRegion> {
+        self.regions.iter().find(|region| region.contains(address))
+    }
+    /// Checks that every byte of the access is in a region that allows it
+    pub fn verify_access(&self, address: usize, length: usize, access: Access) -> RuntimeResult<()> {
+        if !self.watchpoints.is_empty() && self.watch_hit.get().is_none() {
+            let watchpoint = self.watchpoints.iter().find(|w| w.matches(address, length, access));
+            if let Some(watchpoint) = watchpoint {
+                self.watch_hit.set(Some(BreakHit::Watchpoint {
+                    id: watchpoint.id,
+                    address,
+                    length,
+                    access,
+                }));
+            }
+        }
+        if self.regions.is_empty() {
+            return Ok(());
+        }
+        let end = address + length.max(1);
+        let mut position = address;
+        while position < end {
+            let region = self.get_region_at(position);
+            match region {
+                Some(region) if region.permissions.contains(access.get_permission()) => position = region.end,
+                _ => {
+                    return Err(RuntimeError::AccessViolation {
+                        address: position,
+                        access,
+                        region: region.map(|region| region.name.clone()),
+                    })
+                }
+            }
+        }
+        Ok(())
+    }
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.push(watchpoint);
+    }
+    pub fn remove_watchpoint(&mut self, id: usize) -> bool {
+        let length = self.watchpoints.len();
+        self.watchpoints.retain(|w| w.id != id);
+        self.watchpoints.len() != length
+    }
+    pub fn get_watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+    /// Routes the accesses from start to end to the device, the range can't overlap another device
+    pub fn map_device(&mut self, start: usize, end: usize, device: Box<dyn MmioDevice>) -> RuntimeResult<()> {
+        self.verify_address_bounds(start, end.saturating_sub(start))?;
+        if let Some(mapped) = self.devices.iter().find(|mapped| mapped.overlaps(start, end)) {
+            return Err(RuntimeError::Raw(format!(
+                "The range ${:X}-${:X} overlaps the device \"{}\" at ${:X}-${:X}",
+                start,
+                end,
+                mapped.get_name(),
+                mapped.start,
+                mapped.end
+            )));
+        }
+        self.devices.push(MappedDevice::new(start, end, device));
+        Ok(())
+    }
+    /// Removes the device that starts at the address
+    pub fn unmap_device(&mut self, start: usize) -> bool {
+        let length = self.devices.len();
+        self.devices.retain(|mapped| mapped.start != start);
+        self.devices.len() != length
+    }
+    pub fn get_devices(&self) -> &[MappedDevice] {
+        &self.devices
+    }
+    #[inline(always)]
+    fn get_device_at(&self, address: usize) -> Option<&MappedDevice> {
+        match self.devices.is_empty() {
+            true => None,
+            false => self.devices.iter().find(|mapped| mapped.contains(address)),
+        }
+    }
+    /// If a byte of the range belongs to a device
+    pub fn is_mapped(&self, address: usize, length: usize) -> bool {
+        self.devices.iter().any(|mapped| mapped.overlaps(address, address + length))
+    }
+    /// The first watchpoint hit since the last time it was taken
+    pub fn take_watch_hit(&self) -> Option<BreakHit> {
+        self.watch_hit.take()
+    }
+    /// Makes the memory from start to end read only, start included and end excluded
+    pub fn protect(&mut self, start: usize, end: usize) {
+        if start < end {
+            self.protected.push((start, end));
+        }
+    }
+    pub fn get_protected_ranges(&self) -> &Vec<(usize, usize)> {
+        &self.protected
+    }
+    #[inline(always)]
+    fn verify_writable(&self, address: usize, length: usize) -> RuntimeResult<()> {
+        match self
+            .protected
+            .iter()
+            .find(|(start, end)| address < *end && address + length > *start)
+        {
+            Some((start, _)) => Err(RuntimeError::ProtectedWrite(address.max(*start))),
+            None => Ok(()),
         }
     }
 
-    pub fn push(&mut self, data: &MemoryCell, mut sp: usize) -> RuntimeResult<usize> {
-        match data {
+    /// The address a push of the size writes to, a stack pointer that goes below 0 is an address error, not a wrap around
+    pub fn get_push_address(sp: usize, size: Size) -> RuntimeResult<usize> {
+        sp.checked_sub(size.to_bytes()).ok_or(RuntimeError::AddressError(sp, size))
+    }
+    pub fn push(&mut self, data: &MemoryCell, sp: usize) -> RuntimeResult<usize> {
+        let below = |size: Size| Self::get_push_address(sp, size);
+        let sp = match data {
             MemoryCell::Byte(byte) => {
-                sp -= 1;
-                self.write_byte(sp, *byte)?
+                let sp = below(Size::Byte)?;
+                self.write_byte(sp, *byte)?;
+                sp
             }
             MemoryCell::Word(word) => {
-                sp -= 2;
-                self.write_word(sp, *word)?
+                let sp = below(Size::Word)?;
+                self.write_word(sp, *word)?;
+                sp
             }
             MemoryCell::Long(long) => {
-                sp -= 4;
-                self.write_long(sp, *long)?
+                let sp = below(Size::Long)?;
+                self.write_long(sp, *long)?;
+                sp
             }
-        }
+        };
         Ok(sp)
     }
-    pub fn pop_empty_long(&self, mut sp: usize) -> RuntimeResult<usize> {
-        sp += 4;
-        Ok(sp)
+    pub fn pop_empty_long(&self, sp: usize) -> RuntimeResult<usize> {
+        sp.checked_add(4).ok_or(RuntimeError::AddressError(sp, Size::Long))
     }
     pub fn pop(&mut self, size: Size, mut sp: usize) -> RuntimeResult<(MemoryCell, usize)> {
         let result = match size {
@@ -154,19 +344,30 @@ impl Memory {
     }
     pub fn read_long(&self, address: usize) -> RuntimeResult<u32> {
         let address = self.verify_address(address, Size::Long)?;
-
+        self.verify_access(address, 4, Access::Read)?;
+        if let Some(device) = self.get_device_at(address) {
+            return Ok(device.read(address, Size::Long));
+        }
         Ok(u32::from_be_bytes(
             self.data[address..address + 4].try_into().unwrap(),
         ))
     }
     pub fn read_word(&self, address: usize) -> RuntimeResult<u16> {
         let address = self.verify_address(address, Size::Word)?;
+        self.verify_access(address, 2, Access::Read)?;
+        if let Some(device) = self.get_device_at(address) {
+            return Ok(device.read(address, Size::Word) as u16);
+        }
         Ok(u16::from_be_bytes(
             self.data[address..address + 2].try_into().unwrap(),
         ))
     }
     pub fn read_byte(&self, address: usize) -> RuntimeResult<u8> {
         let address = self.verify_address(address, Size::Byte)?;
+        self.verify_access(address, 1, Access::Read)?;
+        if let Some(device) = self.get_device_at(address) {
+            return Ok(device.read(address, Size::Byte) as u8);
+        }
         Ok(u8::from_be_bytes(
             self.data[address..address + 1].try_into().unwrap(),
         ))
@@ -223,31 +424,114 @@ impl Memory {
     }
     pub fn write_long(&mut self, address: usize, value: u32) -> RuntimeResult<()> {
         let address = self.verify_address(address, Size::Long)?;
-        self.data[address..address + 4].copy_from_slice(&value.to_be_bytes());
+        self.verify_writable(address, 4)?;
+        self.verify_access(address, 4, Access::Write)?;
+        match self.get_device_at(address) {
+            Some(device) => device.write(address, Size::Long, value),
+            None => self.data[address..address + 4].copy_from_slice(&value.to_be_bytes()),
+        }
+        self.track_write(address, 4);
         Ok(())
     }
     pub fn write_word(&mut self, address: usize, value: u16) -> RuntimeResult<()> {
         let address = self.verify_address(address, Size::Word)?;
-        self.data[address..address + 2].copy_from_slice(&value.to_be_bytes());
+        self.verify_writable(address, 2)?;
+        self.verify_access(address, 2, Access::Write)?;
+        match self.get_device_at(address) {
+            Some(device) => device.write(address, Size::Word, value as u32),
+            None => self.data[address..address + 2].copy_from_slice(&value.to_be_bytes()),
+        }
+        self.track_write(address, 2);
         Ok(())
     }
     pub fn write_byte(&mut self, address: usize, value: u8) -> RuntimeResult<()> {
         let address = self.verify_address(address, Size::Byte)?;
-        self.data[address] = value;
+        self.verify_writable(address, 1)?;
+        self.verify_access(address, 1, Access::Write)?;
+        match self.get_device_at(address) {
+            Some(device) => device.write(address, Size::Byte, value as u32),
+            None => self.data[address] = value,
+        }
+        self.track_write(address, 1);
         Ok(())
     }
     pub fn write_bytes(&mut self, address: usize, bytes: &[u8]) -> RuntimeResult<()> {
+        let address = self.verify_address_bounds(address, bytes.len())?;
+        self.verify_writable(address, bytes.len())?;
+        self.verify_access(address, bytes.len(), Access::Write)?;
+        if self.is_mapped(address, bytes.len()) {
+            for (i, byte) in bytes.iter().enumerate() {
+                match self.get_device_at(address + i) {
+                    Some(device) => device.write(address + i, Size::Byte, *byte as u32),
+                    None => self.data[address + i] = *byte,
+                }
+            }
+        } else {
+            self.data[address..address + bytes.len()].copy_from_slice(bytes);
+        }
+        self.track_write(address, bytes.len());
+        Ok(())
+    }
+    pub fn start_tracking_writes(&mut self) {
+        self.written = Some(vec![]);
+    }
+    /// The ranges written since the tracking started, sorted and merged when they overlap or touch
+    pub fn stop_tracking_writes(&mut self) -> Vec<(usize, usize)> {
+        let mut written = self.written.take().unwrap_or_default();
+        written.sort_unstable();
+        let mut merged: Vec<(usize, usize)> = vec![];
+        for (address, length) in written {
+            match merged.last_mut() {
+                Some((start, merged_length)) if address <= *start + *merged_length => {
+                    *merged_length = (*merged_length).max(address + length - *start);
+                }
+                _ => merged.push((address, length)),
+            }
+        }
+        merged
+    }
+    #[inline(always)]
+    fn track_write(&mut self, address: usize, length: usize) {
+        if let Some(written) = &mut self.written {
+            written.push((address, length));
+        }
+    }
+    /// Writes back the bytes of an undone step, the regions allowed the write that is undone
+    pub fn restore_bytes(&mut self, address: usize, bytes: &[u8]) -> RuntimeResult<()> {
         let address = self.verify_address_bounds(address, bytes.len())?;
         self.data[address..address + bytes.len()].copy_from_slice(bytes);
+        self.track_write(address, bytes.len());
         Ok(())
     }
-    pub fn read_bytes(&self, address: usize, length: usize) -> RuntimeResult<&[u8]> {
+    /// Writes the bytes without checking the regions, to load the program in a read only memory
+    pub fn load_bytes(&mut self, address: usize, bytes: &[u8]) -> RuntimeResult<()> {
+        let address = self.verify_address_bounds(address, bytes.len())?;
+        self.verify_writable(address, bytes.len())?;
+        self.data[address..address + bytes.len()].copy_from_slice(bytes);
+        Ok(())
+    }
+    pub fn read_bytes(&self, address: usize, length: usize) -> RuntimeResult<Cow<'_, [u8]>> {
+        let address = self.verify_address_bounds(address, length)?;
+        self.verify_access(address, length, Access::Read)?;
+        if !self.is_mapped(address, length) {
+            return Ok(Cow::Borrowed(&self.data[address..address + length]));
+        }
+        let bytes = (address..address + length)
+            .map(|address| match self.get_device_at(address) {
+                Some(device) => device.read(address, Size::Byte) as u8,
+                None => self.data[address],
+            })
+            .collect();
+        Ok(Cow::Owned(bytes))
+    }
+    /// Reads the bytes without checking the regions, for the debuggers
+    pub fn peek_bytes(&self, address: usize, length: usize) -> RuntimeResult<&[u8]> {
         let address = self.verify_address_bounds(address, length)?;
         Ok(&self.data[address..address + length])
     }
 }
 
-#[wasm_bindgen]
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
 impl Memory {
     pub fn wasm_read_bytes(&self, address: usize, size: usize) -> Vec<u8> {
         match self.read_bytes(address, size) {
@@ -257,7 +541,7 @@ impl Memory {
     }
 }
 
-#[wasm_bindgen]
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
 #[derive(Debug, Clone, Copy)]
 pub struct Register {
     data: u32,
@@ -311,7 +595,7 @@ impl Register {
     }
 }
 
-#[wasm_bindgen]
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
 impl Register {
     pub fn wasm_get_long(&self) -> u32 {
         self.get_long()
@@ -325,11 +609,19 @@ impl Register {
 }
 
 #[derive(Debug, Clone, Copy)]
-#[wasm_bindgen]
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
 pub struct Cpu {
     d_reg: [Register; 8],
     a_reg: [Register; 8],
     ccr: Flags,
+    /// High byte of the status register: trace, supervisor and interrupt mask
+    system: u8,
+    vbr: u32,
+    /// The stack pointers that are not in A7, the user one in supervisor mode and the supervisor one in user mode
+    usp: u32,
+    ssp: u32,
+    sfc: u8,
+    dfc: u8,
 }
 
 impl Default for Cpu {
@@ -344,11 +636,17 @@ impl Cpu {
             d_reg: [Register::new(); 8],
             a_reg: [Register::new(); 8],
             ccr: Flags::new(),
+            system: (SR_SUPERVISOR >> 8) as u8,
+            vbr: 0,
+            usp: 0,
+            ssp: 0,
+            sfc: 0,
+            dfc: 0,
         }
     }
 }
 
-#[wasm_bindgen]
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
 impl Cpu {
     pub fn wasm_get_d_reg(&self, index: usize) -> Register {
         self.d_reg[index]
@@ -366,6 +664,9 @@ impl Cpu {
     pub fn wasm_get_ccr(&self) -> Flags {
         self.ccr
     }
+    pub fn wasm_get_vbr(&self) -> u32 {
+        self.vbr
+    }
 }
 
 #[derive(Debug, Serialize)]
@@ -373,17 +674,81 @@ impl Cpu {
 pub enum RuntimeError {
     Raw(String),
     ExecutionLimit(usize),
+    SandboxLimit(SandboxLimit),
     OutOfBounds(String),
     AddressError(usize, Size),
+    /// Write to an address made read only
+    ProtectedWrite(usize),
+    /// A privileged instruction executed in user mode
+    PrivilegeViolation(String),
+    /// An access outside of the memory regions or one the region doesn't allow
+    AccessViolation {
+        address: usize,
+        access: Access,
+        region: Option<String>,
+    },
     DivisionByZero,
+    /// An exception without a handler in its vector
+    Exception(Exception),
     IncorrectAddressingMode(String),
+    UnsupportedInstruction(String),
     Unimplemented,
 }
 
+impl RuntimeError {
+    pub fn get_message(&self) -> String {
+        match self {
+            RuntimeError::Raw(message)
+            | RuntimeError::OutOfBounds(message)
+            | RuntimeError::IncorrectAddressingMode(message)
+            | RuntimeError::UnsupportedInstruction(message) => message.clone(),
+            RuntimeError::ExecutionLimit(limit) => format!("Execution limit of {} instructions reached", limit),
+            RuntimeError::SandboxLimit(limit) => limit.to_string(),
+            RuntimeError::AddressError(address, size) if address & 1 != 0 => {
+                format!("Address error, {:?} access at odd address {}", size, address)
+            }
+            RuntimeError::AddressError(address, size) => {
+                format!("Address error, {:?} access past the stack at address {}", size, address)
+            }
+            RuntimeError::ProtectedWrite(address) => format!("Write to protected memory at ${:X}", address),
+            RuntimeError::AccessViolation { address, access, region: Some(region) } => {
+                format!("Access violation, the region \"{}\" can't be used to {} at ${:X}", region, access, address)
+            }
+            RuntimeError::AccessViolation { address, access, region: None } => {
+                format!("Access violation, {} at ${:X} is outside of every memory region", access, address)
+            }
+            RuntimeError::PrivilegeViolation(name) => {
+                format!("Privilege violation, {} can only be executed in supervisor mode", name)
+            }
+            RuntimeError::DivisionByZero => "Division by zero".to_string(),
+            RuntimeError::Exception(exception) => format!(
+                "Unhandled {} exception, there is no handler in vector {}",
+                exception,
+                exception.get_vector()
+            ),
+            RuntimeError::Unimplemented => "Unimplemented".to_string(),
+        }
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.get_message())
+    }
+}
+
+impl Error for RuntimeError {}
+
+impl From<RuntimeError> for String {
+    fn from(error: RuntimeError) -> Self {
+        error.to_string()
+    }
+}
+
 pub type RuntimeResult<T> = Result<T, RuntimeError>;
 
 #[derive(Debug, Clone, PartialEq, Serialize, Copy)]
-#[wasm_bindgen]
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
 pub enum InterpreterStatus {
     Running,
     Interrupt,
@@ -395,6 +760,20 @@ pub enum InterpreterStatus {
 pub struct InterpreterOptions {
     pub keep_history: bool,
     pub history_size: usize,
+    #[serde(default)]
+    pub cpu_model: CpuModel,
+    /// Attaches a 68881/68882 FPU
+    #[serde(default)]
+    pub fpu: bool,
+    /// Limits enforced while running, see the sandbox module
+    #[serde(default)]
+    pub sandbox: SandboxConfig,
+    /// Instructions that can be run, see the lesson_profile module
+    #[serde(default)]
+    pub lesson_profile: Option<LessonProfile>,
+    /// The regions of the memory and their permissions, see the memory_region module
+    #[serde(default)]
+    pub memory_regions: Vec<MemoryRegion>,
 }
 
 impl InterpreterOptions {
@@ -402,6 +781,11 @@ impl InterpreterOptions {
         Self {
             keep_history: false,
             history_size: 100,
+            cpu_model: CpuModel::default(),
+            fpu: false,
+            sandbox: SandboxConfig::default(),
+            lesson_profile: None,
+            memory_regions: vec![],
         }
     }
 }
@@ -412,7 +796,7 @@ impl Default for InterpreterOptions {
     }
 }
 
-#[wasm_bindgen]
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
 pub struct Interpreter {
     memory: Memory,
     cpu: Cpu,
@@ -427,6 +811,32 @@ pub struct Interpreter {
     final_instruction_address: usize,
     current_interrupt: Option<Interrupt>,
     status: InterpreterStatus,
+    cpu_model: CpuModel,
+    coprocessors: [Option<Box<dyn Coprocessor>>; 8],
+    interrupt_sources: Vec<Box<dyn InterruptSource>>,
+    //bit n is set while the level n is pending
+    pending_interrupts: u8,
+    timing: &'static TimingTable,
+    cycles: u64,
+    line_cycles: HashMap<usize, LineCycles>,
+    last_cycles: u32,
+    //address of the looped instruction while a one instruction DBcc loop runs in loop mode
+    loop_address: Option<usize>,
+    //set by the branch that ran last, a branch to the next instruction is still taken
+    branch_taken: bool,
+    sandbox: SandboxConfig,
+    sandbox_stop: Option<SandboxLimit>,
+    lesson_profile: Option<LessonProfile>,
+    executed: usize,
+    traps: usize,
+    output_bytes: usize,
+    breakpoints: Vec<Breakpoint>,
+    //shared by the breakpoints and the watchpoints
+    next_break_id: usize,
+    last_break: Option<BreakHit>,
+    //there is no clock without std or on wasm without the browser, the wall time is not checked there
+    #[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+    started: Option<std::time::Instant>,
 }
 
 impl Interpreter {
@@ -434,23 +844,23 @@ impl Interpreter {
         compiled_program: Compiler,
         options: Option<InterpreterOptions>,
     ) -> Self {
-        let sp = 0x01000000;
+        let options = options.unwrap_or_default();
         let start = compiled_program.get_start_address();
         let end = compiled_program.get_final_instruction_address();
         let program = compiled_program.get_instructions().clone();
         let length = program.len();
-        let options = options.unwrap_or(InterpreterOptions {
-            keep_history: false,
-            history_size: 100,
-        });
         let max_address = program.iter().map(|i| i.address).max().unwrap_or(0);
         let mut instruction_map = vec![usize::MAX; max_address + 1];
         for (index, ins) in program.iter().enumerate() {
             //no need to check if the array is big enough because i already checked the max address
             instruction_map[ins.address] = index;
         }
+        let mut memory = Memory::with_size(options.sandbox.memory_size);
+        for region in options.memory_regions {
+            memory.add_region(region);
+        }
         let mut interpreter = Self {
-            memory: Memory::new(),
+            memory,
             instruction_map,
             cpu: Cpu::new(),
             pc: start,
@@ -460,28 +870,90 @@ impl Interpreter {
             last_line_address: 0,
             debugger: Debugger::new(options.history_size, compiled_program.get_labels_map()),
             current_interrupt: None,
+            cpu_model: options.cpu_model,
+            coprocessors: Default::default(),
+            interrupt_sources: vec![],
+            pending_interrupts: 0,
+            timing: get_timing_table(options.cpu_model),
+            cycles: 0,
+            line_cycles: HashMap::new(),
+            last_cycles: 0,
+            loop_address: None,
+            branch_taken: false,
+            sandbox: options.sandbox,
+            sandbox_stop: None,
+            lesson_profile: options.lesson_profile,
+            executed: 0,
+            traps: 0,
+            output_bytes: 0,
+            breakpoints: vec![],
+            next_break_id: 0,
+            last_break: None,
+            #[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+            started: None,
             status: if start <= end && length > 0 {
                 InterpreterStatus::Running
             } else {
                 InterpreterStatus::Terminated
             },
         };
-        interpreter.cpu.a_reg[7].store_long(sp as u32);
-        match interpreter.prepare_memory(compiled_program.get_directives()) {
+        //user mode starts from the same stack as supervisor mode, like after a MOVE to USP of the initial A7
+        interpreter.cpu.a_reg[7].store_long(interpreter.get_stack_top() as u32);
+        interpreter.cpu.usp = interpreter.get_stack_top() as u32;
+        if options.fpu {
+            interpreter.coprocessors[FPU_COPROCESSOR_ID as usize] = Some(Box::new(Fpu::new()));
+        }
+        match interpreter.prepare_memory(&compiled_program) {
             Ok(_) => interpreter,
+            //the data of the program can be past the end of a smaller memory
+            Err(_) if interpreter.sandbox.memory_size < DEFAULT_MEMORY_SIZE => {
+                let size = interpreter.sandbox.memory_size;
+                interpreter.sandbox_stop = Some(SandboxLimit::Memory(size));
+                interpreter.status = InterpreterStatus::TerminatedWithException;
+                interpreter
+            }
             Err(e) => panic!("Error preparing memory: {:?}", e),
         }
     }
 
 
+    /// Adds the instructions and data of another compiled program, replacing the instructions at the same addresses.
+    /// A program that terminated runs again, used to feed code to a running machine like in the REPL
+    pub fn load_program(&mut self, compiled_program: &Compiler) -> RuntimeResult<()> {
+        for ins in compiled_program.get_instructions() {
+            if ins.address >= self.instruction_map.len() {
+                self.instruction_map.resize(ins.address + 1, usize::MAX);
+            }
+            match self.instruction_map[ins.address] {
+                usize::MAX => {
+                    self.instruction_map[ins.address] = self.program.len();
+                    self.program.push(ins.clone());
+                }
+                index => self.program[index] = ins.clone(),
+            }
+            self.final_instruction_address = self.final_instruction_address.max(ins.address);
+        }
+        self.prepare_memory(compiled_program)?;
+        if self.status == InterpreterStatus::Terminated {
+            self.status = InterpreterStatus::Running;
+        }
+        Ok(())
+    }
+
     //TODO could make this an external function and pass the memory in
-    fn prepare_memory(&mut self, directives: &Vec<Directive>) -> RuntimeResult<()> {
-        for directive in directives {
+    fn prepare_memory(&mut self, compiled_program: &Compiler) -> RuntimeResult<()> {
+        //the machine code of the instructions, the ones that can't be encoded leave their bytes as they are
+        for ins in compiled_program.get_instructions() {
+            if let Ok(bytes) = encode_instruction(&ins.instruction, ins.address, ins.parsed_line.line_index) {
+                self.memory.load_bytes(ins.address, &bytes)?;
+            }
+        }
+        for directive in compiled_program.get_directives() {
             match &directive {
                 Directive::DC { data, address }
                 | Directive::DS { data, address }
                 | Directive::DCB { data, address } => {
-                    self.memory.write_bytes(*address, data)?;
+                    self.memory.load_bytes(*address, data)?;
                 }
                 Directive::Other => {}
             };
@@ -500,9 +972,45 @@ impl Interpreter {
     }
 
     #[inline(always)]
+    pub fn get_program(&self) -> &Vec<InstructionLine> {
+        &self.program
+    }
+    /// The lines of the instructions and the addresses of the lines, see the source_map module
+    pub fn get_source_map(&self) -> SourceMap {
+        SourceMap::new(&self.program)
+    }
     pub fn get_pc(&self) -> usize {
         self.pc
     }
+    pub fn set_pc(&mut self, address: usize) {
+        self.pc = address;
+    }
+
+    pub fn get_cpu_model(&self) -> CpuModel {
+        self.cpu_model
+    }
+
+    pub fn get_timing_table(&self) -> &'static TimingTable {
+        self.timing
+    }
+
+    #[inline(always)]
+    pub fn get_cycles(&self) -> u64 {
+        self.cycles
+    }
+    /// Cycles of the last instruction that ran
+    pub fn get_last_instruction_cycles(&self) -> u32 {
+        self.last_cycles
+    }
+    /// The cycles spent on every line that ran, sorted by line
+    pub fn get_line_cycles(&self) -> Vec<LineCycles> {
+        let mut lines: Vec<LineCycles> = self.line_cycles.values().copied().collect();
+        lines.sort_by_key(|line| line.line_index);
+        lines
+    }
+    pub fn get_line_cycles_at(&self, line_index: usize) -> Option<LineCycles> {
+        self.line_cycles.get(&line_index).copied()
+    }
 
     #[inline(always)]
     pub fn get_status(&self) -> &InterpreterStatus {
@@ -536,9 +1044,8 @@ impl Interpreter {
     }
 
     pub fn step(&mut self) -> RuntimeResult<InterpreterStatus> {
-        if self.keep_history {
-            self.debugger
-                .add_step(ExecutionStep::new(self.pc, self.cpu.ccr));
+        if self.status == InterpreterStatus::Running {
+            self.check_sandbox_limits()?;
         }
         self.last_line_address = self.pc;
         let instruction = self
@@ -557,14 +1064,32 @@ impl Interpreter {
             )),
 
             Some((index, ins)) => {
+                //only the instructions that run are in the history, undoing one makes the program run again
                 if self.keep_history {
+                    self.debugger
+                        .add_step(ExecutionStep::new(self.pc, self.cpu.ccr, self.cycles));
                     self.debugger.set_line(index);
                 }
-                self.increment_pc(4);
-                self.execute_instruction(&ins)?;
+                self.check_lesson_profile(self.pc)?;
+                self.memory.verify_access(self.pc, 2, Access::Execute)?;
+                let address = self.pc;
+                #[cfg(feature = "tracing")]
+                tracing::trace!(pc = address, line_index = index, instruction = ?ins, "step");
+                let operands = self.get_timing_operands(&ins);
+                self.increment_pc(get_encoded_size(&ins));
+                let next_address = self.pc;
+                self.branch_taken = false;
+                if let Err(error) = self.execute_instruction(&ins) {
+                    self.take_exception(error, address, next_address)?;
+                }
+                self.add_cycles(&ins, address, index, operands);
+                self.executed += 1;
+                if self.status == InterpreterStatus::Running {
+                    self.check_interrupts(index)?;
+                }
                 let status = self.get_status();
                 //TODO not sure if doing this before or after running the instruction
-                if self.has_reached_bottom() && *status != InterpreterStatus::Interrupt {
+                if self.has_reached_bottom() && *status == InterpreterStatus::Running {
                     self.set_status(InterpreterStatus::Terminated);
                 }
                 if self.keep_history {
@@ -585,14 +1110,354 @@ impl Interpreter {
             }
         }
     }
+    /// Runs one instruction, entering the subroutines
+    pub fn step_into(&mut self) -> RuntimeResult<StepResult> {
+        self.step_until(StepMode::Into)
+    }
+    /// Runs one instruction, a BSR or JSR runs until its subroutine returns
+    pub fn step_over(&mut self) -> RuntimeResult<StepResult> {
+        self.step_until(StepMode::Over)
+    }
+    /// Runs until the current subroutine returns, until the end outside of one
+    pub fn step_out(&mut self) -> RuntimeResult<StepResult> {
+        self.step_until(StepMode::Out)
+    }
+    /// Runs at least one instruction, then stops before the instruction of the line
+    pub fn run_until_line(&mut self, line_index: usize) -> RuntimeResult<StepResult> {
+        self.step_until(StepMode::Line(line_index))
+    }
+    /// Runs until the stop of the mode, a breakpoint or watchpoint, an interrupt or the end of the program, and reports what changed.
+    /// An interrupt interrupts the step, once answered the step can be made again
+    pub fn step_until(&mut self, mode: StepMode) -> RuntimeResult<StepResult> {
+        self.verify_can_run()?;
+        let before = self.get_register_snapshot();
+        let depth = self.get_call_depth();
+        let mut executed = 0;
+        let watching = self.start_break_checks();
+        self.memory.start_tracking_writes();
+        let stepped = loop {
+            if let Err(e) = self.step() {
+                break Err(e);
+            }
+            executed += 1;
+            if self.status != InterpreterStatus::Running || (watching && self.check_break()) {
+                break Ok(());
+            }
+            let done = match mode {
+                StepMode::Into => true,
+                StepMode::Over => self.get_call_depth() <= depth,
+                StepMode::Out => self.get_call_depth() < depth,
+                StepMode::Line(line_index) => self
+                    .get_next_instruction()
+                    .is_some_and(|line| line.parsed_line.line_index == line_index),
+            };
+            if done {
+                break Ok(());
+            }
+        };
+        let written = self.memory.stop_tracking_writes();
+        stepped?;
+        Ok(StepResult {
+            line_index: self.get_last_line_index(),
+            pc: self.pc,
+            status: self.status,
+            executed,
+            changes: self.get_state_changes(before, written)?,
+            break_hit: self.last_break,
+        })
+    }
+    /// Undoes the last instruction, restoring the registers, flags, memory and call stack it changed.
+    /// The history keeps the last history_size instructions, the changes reported are the ones undone
+    pub fn step_back(&mut self) -> RuntimeResult<StepResult> {
+        if !self.keep_history {
+            return Err(RuntimeError::Raw(
+                "The history is not kept, enable keep_history to step back".to_string(),
+            ));
+        }
+        let before = self.get_register_snapshot();
+        self.memory.start_tracking_writes();
+        let undone = self.undo();
+        let written = self.memory.stop_tracking_writes();
+        let step = undone?;
+        self.last_line_address = self.pc;
+        Ok(StepResult {
+            line_index: Some(step.get_line()),
+            pc: self.pc,
+            status: self.status,
+            executed: 1,
+            changes: self.get_state_changes(before, written)?,
+            break_hit: None,
+        })
+    }
+    /// How many instructions can be undone, zero stops keeping the history
+    pub fn set_history_size(&mut self, history_size: usize) {
+        self.keep_history = history_size > 0;
+        self.debugger.set_history_size(history_size);
+    }
+    /// The data registers, address registers and status register, to find what a debugging step changed
+    fn get_register_snapshot(&self) -> ([u32; 8], [u32; 8], u16) {
+        (
+            self.cpu.d_reg.map(|r| r.get_long()),
+            self.cpu.a_reg.map(|r| r.get_long()),
+            self.get_sr(),
+        )
+    }
+    fn get_state_changes(
+        &self,
+        (d_regs, a_regs, sr): ([u32; 8], [u32; 8], u16),
+        written: Vec<(usize, usize)>,
+    ) -> RuntimeResult<Vec<StateChange>> {
+        let mut changes = vec![];
+        for (i, old) in d_regs.into_iter().enumerate() {
+            let new = self.cpu.d_reg[i].get_long();
+            if new != old {
+                changes.push(StateChange::Register { register: RegisterOperand::Data(i as u8), old, new });
+            }
+        }
+        for (i, old) in a_regs.into_iter().enumerate() {
+            let new = self.cpu.a_reg[i].get_long();
+            if new != old {
+                changes.push(StateChange::Register { register: RegisterOperand::Address(i as u8), old, new });
+            }
+        }
+        if self.get_sr() != sr {
+            changes.push(StateChange::StatusRegister { old: sr, new: self.get_sr() });
+        }
+        for (address, length) in written {
+            let bytes = self.memory.peek_bytes(address, length)?.to_vec();
+            changes.push(StateChange::Memory { address, bytes });
+        }
+        Ok(changes)
+    }
+    /// Line of the last instruction that was run, the one that failed when a step returns an error
+    pub fn get_last_line_index(&self) -> Option<usize> {
+        self.get_instruction_at(self.last_line_address)
+            .map(|line| line.parsed_line.line_index)
+    }
+    /// The message of a runtime error with the line of the instruction that caused it
+    pub fn get_error_message(&self, error: &RuntimeError) -> String {
+        match self.get_instruction_at(self.last_line_address) {
+            Some(line) => format!(
+                "Error on line {}, \"{}\": {}",
+                line.parsed_line.line_index + 1,
+                line.parsed_line.line.trim(),
+                error
+            ),
+            None => error.get_message(),
+        }
+    }
+    pub fn get_sandbox(&self) -> &SandboxConfig {
+        &self.sandbox
+    }
+    /// The sandbox limit that stopped the program, if one did
+    pub fn get_sandbox_stop(&self) -> Option<SandboxLimit> {
+        self.sandbox_stop
+    }
+    /// Instructions run since the start
+    pub fn get_executed_instructions(&self) -> usize {
+        self.executed
+    }
+    pub fn get_lesson_profile(&self) -> Option<&LessonProfile> {
+        self.lesson_profile.as_ref()
+    }
+    fn check_lesson_profile(&self, address: usize) -> RuntimeResult<()> {
+        let (Some(profile), Some(line)) = (&self.lesson_profile, self.get_instruction_at(address)) else {
+            return Ok(());
+        };
+        match &line.parsed_line.parsed {
+            LexedLine::Instruction { name, .. } => profile
+                .verify_instruction(name)
+                .map_err(RuntimeError::UnsupportedInstruction),
+            _ => Ok(()),
+        }
+    }
+    fn stop_sandbox(&mut self, limit: SandboxLimit) -> RuntimeResult<()> {
+        self.sandbox_stop = Some(limit);
+        self.set_status(InterpreterStatus::TerminatedWithException);
+        Err(RuntimeError::SandboxLimit(limit))
+    }
+    fn check_sandbox_limits(&mut self) -> RuntimeResult<()> {
+        let sandbox = self.sandbox;
+        if let Some(max) = sandbox.max_instructions {
+            if self.executed >= max {
+                return self.stop_sandbox(SandboxLimit::Instructions(max));
+            }
+        }
+        if let Some(max) = sandbox.max_cycles {
+            if self.cycles >= max {
+                return self.stop_sandbox(SandboxLimit::Cycles(max));
+            }
+        }
+        #[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+        if let Some(max) = sandbox.max_wall_time_ms {
+            //reading the clock every step is too slow
+            if self.executed.is_multiple_of(256) {
+                let started = *self.started.get_or_insert_with(std::time::Instant::now);
+                if started.elapsed().as_millis() >= max as u128 {
+                    return self.stop_sandbox(SandboxLimit::WallTime(max));
+                }
+            }
+        }
+        Ok(())
+    }
+    fn count_trap(&mut self) -> RuntimeResult<()> {
+        self.traps += 1;
+        match self.sandbox.max_traps {
+            Some(max) if self.traps > max => self.stop_sandbox(SandboxLimit::Traps(max)),
+            _ => Ok(()),
+        }
+    }
+    fn count_output(&mut self, interrupt: &Interrupt) -> RuntimeResult<()> {
+        self.output_bytes += match interrupt {
+            Interrupt::DisplayStringWithCRLF(string) => string.len() + 1,
+            Interrupt::DisplayStringWithoutCRLF(string) => string.len(),
+            Interrupt::DisplayNumber(number) => number.to_string().len(),
+            Interrupt::DisplayChar(char) => char.len_utf8(),
+            Interrupt::ReadNumberWithPrompt(prompt) => prompt.len(),
+            _ => 0,
+        };
+        match self.sandbox.max_output_bytes {
+            Some(max) if self.output_bytes > max => self.stop_sandbox(SandboxLimit::Output(max)),
+            _ => Ok(()),
+        }
+    }
+    /// Reads the data that changes the timing of the instruction before it runs, without side effects
+    fn get_timing_operands(&self, ins: &Instruction) -> RuntimeOperands {
+        match ins {
+            Instruction::ASd(Operand::Register(register), ..)
+            | Instruction::LSd(Operand::Register(register), ..)
+            | Instruction::ROd(Operand::Register(register), ..) => RuntimeOperands {
+                shift_count: Some(self.get_register_value(register, Size::Long) % 64),
+                ..Default::default()
+            },
+            Instruction::MULx(source, ..) => {
+                let address = match source {
+                    Operand::Immediate(value) => {
+                        return RuntimeOperands {
+                            multiplier: Some(*value as u16),
+                            ..Default::default()
+                        }
+                    }
+                    Operand::Register(register) => {
+                        return RuntimeOperands {
+                            multiplier: Some(self.get_register_value(register, Size::Word) as u16),
+                            ..Default::default()
+                        }
+                    }
+                    Operand::Indirect(reg) | Operand::PostIndirect(reg) => {
+                        Some(self.cpu.a_reg[*reg as usize].get_long() as usize)
+                    }
+                    Operand::PreIndirect(reg) => {
+                        Some((self.cpu.a_reg[*reg as usize].get_long() as usize).wrapping_sub(2))
+                    }
+                    Operand::IndirectDisplacement { offset, base } => Some(
+                        (self.get_register_value(base, Size::Long) as i64 + *offset as i64) as usize,
+                    ),
+                    Operand::Absolute(address) => Some(*address),
+                    _ => None,
+                };
+                RuntimeOperands {
+                    multiplier: address
+                        .and_then(|address| self.memory.peek_bytes(address, 2).ok())
+                        .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]])),
+                    ..Default::default()
+                }
+            }
+            _ => RuntimeOperands::default(),
+        }
+    }
+    fn add_cycles(&mut self, ins: &Instruction, address: usize, line_index: usize, operands: RuntimeOperands) {
+        let branch_taken = self.branch_taken;
+        let mut cycles = self.timing.get_executed_cycles(ins, branch_taken, operands);
+        //the loop is a one word instruction followed by the DBcc that branches back to it
+        let in_loop = match self.loop_address {
+            Some(body) => address == body || address == body + 2,
+            None => false,
+        };
+        if let Some(saving) = self.timing.get_loop_mode_saving() {
+            if in_loop {
+                cycles = cycles.saturating_sub(saving);
+            }
+            self.loop_address = match ins {
+                Instruction::DBcc(_, target, _)
+                    if branch_taken && *target as usize + 2 == address =>
+                {
+                    Some(*target as usize)
+                }
+                _ if in_loop && self.loop_address == Some(address) => self.loop_address,
+                _ => None,
+            };
+        }
+        self.cycles += cycles as u64;
+        self.last_cycles = cycles;
+        let line = self.line_cycles.entry(line_index).or_insert(LineCycles {
+            line_index,
+            ..Default::default()
+        });
+        line.executions += 1;
+        line.cycles += cycles as u64;
+    }
+    /// Makes the memory from start to end read only, writing to it fails with RuntimeError::ProtectedWrite
+    pub fn protect_memory(&mut self, start: usize, end: usize) {
+        self.memory.protect(start, end);
+    }
+    /// Routes the reads and writes of the program from start to end to the device
+    pub fn map_device(&mut self, start: usize, end: usize, device: Box<dyn MmioDevice>) -> RuntimeResult<()> {
+        self.memory.map_device(start, end, device)
+    }
+    /// Removes the device that starts at the address, returns false if there is none
+    pub fn unmap_device(&mut self, start: usize) -> bool {
+        self.memory.unmap_device(start)
+    }
+    /// Where the stack pointer starts, the end of the memory
+    pub fn get_stack_top(&self) -> usize {
+        self.sandbox.memory_size.min(DEFAULT_MEMORY_SIZE) & !1
+    }
     pub fn get_pretty_call_stack(&self) -> Vec<Label> {
         self.debugger.to_call_stack()
     }
+    /// Labels of the program by their name
+    pub fn get_labels(&self) -> &LabelTable {
+        self.debugger.get_labels()
+    }
+    pub fn get_label_at(&self, address: usize) -> Option<&Label> {
+        self.debugger.get_label_at(address)
+    }
+    pub fn get_call_depth(&self) -> usize {
+        self.debugger.get_call_depth()
+    }
+    /// Addresses of the BSR/JSR instructions of the active calls, from the outermost one
+    pub fn get_call_sites(&self) -> &Vec<usize> {
+        self.debugger.get_call_sites()
+    }
+    /// Changes made by the last step, only recorded when the interpreter keeps the history
+    pub fn get_previous_mutations(&self) -> Option<&Vec<MutationOperation>> {
+        match self.keep_history {
+            true => self.debugger.get_previous_mutations(),
+            false => None,
+        }
+    }
     pub fn undo(&mut self) -> RuntimeResult<ExecutionStep> {
         match self.debugger.pop_step() {
             Some(step) => {
                 self.pc = step.get_pc();
                 self.cpu.ccr = step.get_ccr();
+                //an instruction that failed has no cycles added
+                let undone = self.cycles - step.get_cycles();
+                match self.line_cycles.get_mut(&step.get_line()) {
+                    Some(line) if undone > 0 => {
+                        line.cycles -= undone;
+                        line.executions -= 1;
+                    }
+                    _ => {}
+                }
+                self.cycles = step.get_cycles();
+                self.last_cycles = 0;
+                self.executed = self.executed.saturating_sub(1);
+                self.status = InterpreterStatus::Running;
+                self.current_interrupt = None;
+                //the loop mode state is not part of the history, it is entered again on the next DBcc
+                self.loop_address = None;
                 //doing from right to left because mutations are added from left to right
                 for mutation in step.get_mutations().iter().rev() {
                     match mutation {
@@ -609,14 +1474,25 @@ impl Interpreter {
                             }
                         },
                         MutationOperation::WriteMemory { address, old, size } => {
-                            self.memory.write_size(*address, *size, *old)?;
+                            let bytes = old.to_be_bytes();
+                            self.memory.restore_bytes(*address, &bytes[4 - size.to_bytes()..])?;
                         }
                         MutationOperation::WriteMemoryBytes { address, old } => {
-                            self.memory.write_bytes(*address, old)?;
+                            self.memory.restore_bytes(*address, old)?;
+                        }
+                        MutationOperation::WriteControlRegister { register, old } => {
+                            self.store_control_register(register, *old);
+                        }
+                        MutationOperation::WriteStatusRegister { old } => {
+                            self.store_sr(*old);
+                        }
+                        MutationOperation::WriteCoprocessorState { id, old } => {
+                            self.get_coprocessor_mut(*id)?.restore_state(old)?;
                         }
                         MutationOperation::PopCall { to, from: _ } => {
                             //try to get the address of the function that popped the call
-                            let ins = self.get_instruction_at(*to - 4);
+                            let ins = self.get_instruction_before(*to);
+                            let call_site = ins.map(|ins| ins.address).unwrap_or(*to);
                             let callee_address = match ins {
                                 Some(ins) => match &ins.instruction {
                                     Instruction::BSR(address) => *address as usize,
@@ -627,7 +1503,7 @@ impl Interpreter {
                                 },
                                 None => 0,
                             };
-                            self.debugger.push_call(callee_address);
+                            self.debugger.push_call(callee_address, call_site);
                         }
                         MutationOperation::PushCall { to: _, from: _ } => {
                             self.debugger.pop_call();
@@ -669,6 +1545,13 @@ impl Interpreter {
             InterruptResult::Terminate => {
                 self.set_status(InterpreterStatus::Terminated);
             }
+            InterruptResult::IsInputPending(pending) => {
+                self.set_register_value(&RegisterOperand::Data(1), pending as u32, Size::Byte);
+            }
+            InterruptResult::ReadNumberWithPrompt(num) => {
+                self.set_register_value(&RegisterOperand::Data(1), num as u32, Size::Long);
+            }
+            InterruptResult::ClearScreen | InterruptResult::PositionCursor | InterruptResult::SetEcho => {}
         };
         self.current_interrupt = None;
         //edge case if the last instruction is an interrupt
@@ -679,6 +1562,59 @@ impl Interpreter {
         };
         Ok(())
     }
+    /// Answers the pending interrupt with the host, the text is written to it and the reads wait for it
+    pub fn answer_interrupt_with(&mut self, io: &mut dyn HostIo) -> RuntimeResult<()> {
+        let result = match self.get_current_interrupt()? {
+            Interrupt::DisplayStringWithCRLF(string) => {
+                io.write(&format!("{}\n", string));
+                InterruptResult::DisplayStringWithCRLF
+            }
+            Interrupt::DisplayStringWithoutCRLF(string) => {
+                io.write(&string);
+                InterruptResult::DisplayStringWithoutCRLF
+            }
+            Interrupt::DisplayNumber(number) => {
+                io.write(&number.to_string());
+                InterruptResult::DisplayNumber
+            }
+            Interrupt::DisplayChar(char) => {
+                io.write(&char.to_string());
+                InterruptResult::DisplayChar
+            }
+            Interrupt::ReadKeyboardString => InterruptResult::ReadKeyboardString(io.read_line().map_err(RuntimeError::Raw)?),
+            Interrupt::ReadNumber => InterruptResult::ReadNumber(io.read_number().map_err(RuntimeError::Raw)?),
+            Interrupt::ReadChar => InterruptResult::ReadChar(io.read_char().map_err(RuntimeError::Raw)?),
+            Interrupt::GetTime => InterruptResult::GetTime(io.get_time()),
+            Interrupt::Terminate => InterruptResult::Terminate,
+            Interrupt::IsInputPending => InterruptResult::IsInputPending(io.has_input()),
+            Interrupt::ClearScreen => {
+                io.clear_screen();
+                InterruptResult::ClearScreen
+            }
+            Interrupt::PositionCursor(column, row) => {
+                io.set_cursor(column, row);
+                InterruptResult::PositionCursor
+            }
+            Interrupt::SetEcho(echo) => {
+                io.set_echo(echo);
+                InterruptResult::SetEcho
+            }
+            Interrupt::ReadNumberWithPrompt(prompt) => {
+                io.write(&prompt);
+                InterruptResult::ReadNumberWithPrompt(io.read_number().map_err(RuntimeError::Raw)?)
+            }
+        };
+        self.answer_interrupt(result)
+    }
+    /// Runs the program answering the interrupts with the host, until it ends or fails
+    pub fn run_with_io(&mut self, io: &mut dyn HostIo) -> RuntimeResult<InterpreterStatus> {
+        loop {
+            match self.run()? {
+                InterpreterStatus::Interrupt => self.answer_interrupt_with(io)?,
+                status => return Ok(status),
+            }
+        }
+    }
     #[inline(always)]
     fn increment_pc(&mut self, amount: usize) {
         self.pc += amount;
@@ -700,6 +1636,14 @@ impl Interpreter {
             None => None,
         }
     }
+    /// The instruction that ends at the address, like the call before a return address
+    pub fn get_instruction_before(&self, address: usize) -> Option<&InstructionLine> {
+        (2..=MAX_INSTRUCTION_SIZE)
+            .step_by(2)
+            .filter_map(|size| address.checked_sub(size))
+            .filter_map(|start| self.get_instruction_at(start))
+            .find(|ins| ins.address + get_encoded_size(&ins.instruction) == address)
+    }
     pub fn get_current_interrupt(&self) -> RuntimeResult<Interrupt> {
         match &self.current_interrupt {
             Some(interrupt) => Ok(interrupt.clone()),
@@ -895,6 +1839,119 @@ impl Interpreter {
                 self.set_compare_flags(result as u32, Size::Long, false, false);
                 self.set_register_value(dest, result as u32, Size::Long);
             }
+            Instruction::MULxL { source, low, high, sign } => {
+                let source_value = self.get_operand_value(source, Size::Long, Used::Once)?;
+                let dest_value = self.get_register_value(low, Size::Long);
+                let (result, has_overflowed) = match sign {
+                    Sign::Signed => {
+                        let result = (dest_value as i32 as i64) * (source_value as i32 as i64);
+                        (result as u64, result != result as i32 as i64)
+                    }
+                    Sign::Unsigned => {
+                        let result = dest_value as u64 * source_value as u64;
+                        (result, result > u32::MAX as u64)
+                    }
+                };
+                match high {
+                    Some(high) => {
+                        self.set_compare_flags((result >> 32) as u32, Size::Long, false, false);
+                        self.set_flag(Flags::Zero, result == 0);
+                        self.set_register_value(high, (result >> 32) as u32, Size::Long);
+                    }
+                    None => self.set_compare_flags(result as u32, Size::Long, false, has_overflowed),
+                }
+                self.set_register_value(low, result as u32, Size::Long);
+            }
+            Instruction::DIVxL { source, quotient, remainder, sign, long_dividend } => {
+                let source_value = self.get_operand_value(source, Size::Long, Used::Once)?;
+                if source_value == 0 {
+                    return Err(RuntimeError::DivisionByZero);
+                }
+                let low = self.get_register_value(quotient, Size::Long);
+                let high = match (remainder, long_dividend) {
+                    (Some(remainder), true) => Some(self.get_register_value(remainder, Size::Long)),
+                    _ => None,
+                };
+                let result = match sign {
+                    Sign::Signed => {
+                        let dividend = match high {
+                            Some(high) => (((high as u64) << 32) | low as u64) as i64,
+                            None => low as i32 as i64,
+                        };
+                        let divisor = source_value as i32 as i64;
+                        let quotient = dividend.wrapping_div(divisor);
+                        if quotient != quotient as i32 as i64 {
+                            None
+                        } else {
+                            Some((quotient as u32, dividend.wrapping_rem(divisor) as u32))
+                        }
+                    }
+                    Sign::Unsigned => {
+                        let dividend = match high {
+                            Some(high) => ((high as u64) << 32) | low as u64,
+                            None => low as u64,
+                        };
+                        let quotient = dividend / source_value as u64;
+                        if quotient > u32::MAX as u64 {
+                            None
+                        } else {
+                            Some((quotient as u32, (dividend % source_value as u64) as u32))
+                        }
+                    }
+                };
+                match result {
+                    Some((quotient_value, remainder_value)) => {
+                        self.set_compare_flags(quotient_value, Size::Long, false, false);
+                        if let Some(remainder) = remainder {
+                            self.set_register_value(remainder, remainder_value, Size::Long);
+                        }
+                        self.set_register_value(quotient, quotient_value, Size::Long);
+                    }
+                    None => {
+                        self.set_flag(Flags::Carry, false);
+                        self.set_flag(Flags::Overflow, true);
+                    }
+                }
+            }
+            Instruction::BFx { operation, target, offset, width, register } => {
+                let offset = self.get_bitfield_value(offset) as i32;
+                //a width of 0 means 32 bits
+                let width = match self.get_bitfield_value(width) % 32 {
+                    0 => 32,
+                    width => width,
+                };
+                let mask = u32::MAX >> (32 - width);
+                let field = self.read_bitfield(target, offset, width)?;
+                let flag_value = match (operation, register) {
+                    (BitfieldOperation::Insert, Some(register)) => {
+                        self.get_register_value(register, Size::Long) & mask
+                    }
+                    _ => field,
+                };
+                self.set_compare_flags(flag_value << (32 - width), Size::Long, false, false);
+                match (operation, register) {
+                    (BitfieldOperation::ExtractUnsigned, Some(register)) => {
+                        self.set_register_value(register, field, Size::Long);
+                    }
+                    (BitfieldOperation::ExtractSigned, Some(register)) => {
+                        let value = ((field << (32 - width)) as i32) >> (32 - width);
+                        self.set_register_value(register, value as u32, Size::Long);
+                    }
+                    (BitfieldOperation::FindFirstOne, Some(register)) => {
+                        let leading = (field << (32 - width)).leading_zeros().min(width);
+                        self.set_register_value(register, (offset as u32).wrapping_add(leading), Size::Long);
+                    }
+                    (BitfieldOperation::Change, _) => {
+                        self.write_bitfield(target, offset, width, !field & mask)?
+                    }
+                    (BitfieldOperation::Clear, _) => self.write_bitfield(target, offset, width, 0)?,
+                    (BitfieldOperation::Set, _) => self.write_bitfield(target, offset, width, mask)?,
+                    (BitfieldOperation::Insert, _) => {
+                        self.write_bitfield(target, offset, width, flag_value)?
+                    }
+                    _ => {}
+                }
+            }
 
             Instruction::BRA(address) => {
                 //instead of using the absolute address, the original language uses pc + 2 + offset
@@ -902,7 +1959,7 @@ impl Interpreter {
             }
             Instruction::BSR(address) => {
                 if self.keep_history {
-                    let old_address = self.get_sp() - 4;
+                    let old_address = Memory::get_push_address(self.get_sp(), Size::Long)?;
                     let old_value = self.memory.read_long(old_address)?;
                     self.debugger.add_mutation(MutationOperation::WriteMemory {
                         address: old_address,
@@ -911,20 +1968,21 @@ impl Interpreter {
                     });
                     self.debugger.add_mutation(MutationOperation::PushCall {
                         to: *address as usize,
-                        from: self.get_pc() - 4, //the pc is incremented before the instruction is executed
+                        from: self.last_line_address,
                     });
                 }
                 let new_sp = self
                     .memory
                     .push(&MemoryCell::Long(self.pc as u32), self.get_sp())?;
                 self.set_sp(new_sp);
+                let call_site = self.last_line_address;
                 self.pc = *address as usize;
-                self.debugger.push_call(self.pc);
+                self.debugger.push_call(self.pc, call_site);
             }
             Instruction::JSR(source) => {
                 let address = self.get_operand_address(source)?;
                 if self.keep_history {
-                    let old_address = self.get_sp() - 4;
+                    let old_address = Memory::get_push_address(self.get_sp(), Size::Long)?;
                     let old_value = self.memory.read_long(old_address)?;
                     self.debugger.add_mutation(MutationOperation::WriteMemory {
                         address: old_address,
@@ -933,15 +1991,16 @@ impl Interpreter {
                     });
                     self.debugger.add_mutation(MutationOperation::PushCall {
                         to: address as usize,
-                        from: self.get_pc() - 4, //pc is incremented before the instruction is executed
+                        from: self.last_line_address,
                     });
                 }
                 let new_sp = self
                     .memory
                     .push(&MemoryCell::Long(self.pc as u32), self.get_sp())?;
                 self.set_sp(new_sp);
+                let call_site = self.last_line_address;
                 self.pc = address as usize;
-                self.debugger.push_call(self.pc);
+                self.debugger.push_call(self.pc, call_site);
             }
             Instruction::JMP(op) => {
                 let addr = self.get_operand_address(op)?;
@@ -954,9 +2013,10 @@ impl Interpreter {
             Instruction::PEA(source) => {
                 let addr = self.get_operand_address(source)?;
                 if self.keep_history {
-                    let old_value = self.memory.read_long(self.get_sp())?;
+                    let address = Memory::get_push_address(self.get_sp(), Size::Long)?;
+                    let old_value = self.memory.read_long(address)?;
                     self.debugger.add_mutation(MutationOperation::WriteMemory {
-                        address: self.get_sp() - 4,
+                        address,
                         old: old_value,
                         size: Size::Long,
                     })
@@ -1176,6 +2236,11 @@ impl Interpreter {
                 self.set_register_value(reg2, reg1_value, Size::Long);
             }
             Instruction::EXT(reg, from, to) => {
+                if *from == Size::Byte && *to == Size::Long {
+                    self.cpu_model
+                        .verify_feature(CpuFeature::ExtendByteToLong, "extb")
+                        .map_err(RuntimeError::UnsupportedInstruction)?;
+                }
                 let input = get_value_sized(self.get_register_value(reg, Size::Long), *from);
                 let result = match (from, to) {
                     (Size::Byte, Size::Word) => ((((input as u8) as i8) as i16) as u16) as u32,
@@ -1230,6 +2295,7 @@ impl Interpreter {
             Instruction::Bcc(address, condition) => {
                 if self.get_condition_value(condition) {
                     self.pc = *address as usize;
+                    self.branch_taken = true;
                 }
             }
             Instruction::CLR(dest, size) => {
@@ -1252,14 +2318,15 @@ impl Interpreter {
                     self.set_register_value(reg, next as u32, Size::Word);
                     if next != -1 {
                         self.pc = *address as usize;
+                        self.branch_taken = true;
                     }
                 }
             }
             Instruction::LINK(reg, offset) => {
-                let sp = self.get_sp() - 4; //TODO convert to push
+                let sp = Memory::get_push_address(self.get_sp(), Size::Long)?; //TODO convert to push
                 self.set_sp(sp);
                 let value = self.get_register_value(reg, Size::Long);
-                self.set_memory_value(value as usize, Size::Long, sp as u32)?;
+                self.set_memory_value(sp, Size::Long, value)?;
                 self.set_register_value(reg, sp as u32, Size::Long);
                 self.set_sp((sp as i32).wrapping_add(*offset as i32) as usize)
             }
@@ -1274,30 +2341,165 @@ impl Interpreter {
                 if self.keep_history {
                     self.debugger.add_mutation(MutationOperation::PopCall {
                         to: value.get_long() as usize,
-                        from: self.get_pc() - 4, //pc is incremented before execution
+                        from: self.last_line_address,
                     })
                 }
                 self.set_sp(new_sp);
                 self.pc = value.get_long() as usize;
                 self.debugger.pop_call();
             }
+            Instruction::RTD(displacement) => {
+                let (value, new_sp) = self.memory.pop(Size::Long, self.get_sp())?;
+                if self.keep_history {
+                    self.debugger.add_mutation(MutationOperation::PopCall {
+                        to: value.get_long() as usize,
+                        from: self.last_line_address,
+                    })
+                }
+                self.set_sp((new_sp as i32).wrapping_add(*displacement as i32) as usize);
+                self.pc = value.get_long() as usize;
+                self.debugger.pop_call();
+            }
+            Instruction::RTE => {
+                self.verify_supervisor("RTE")?;
+                let (sr, sp) = self.memory.pop(Size::Word, self.get_sp())?;
+                let (pc, mut sp) = self.memory.pop(Size::Long, sp)?;
+                //since the 68010 every frame has a format/vector word after the PC
+                if self.cpu_model != CpuModel::M68000 {
+                    let (format, new_sp) = self.memory.pop(Size::Word, sp)?;
+                    sp = match format.get_word() >> 12 {
+                        0 => new_sp,
+                        //the address of the instruction that caused it
+                        2 => new_sp + 4,
+                        format => {
+                            return Err(RuntimeError::Raw(format!(
+                                "Unsupported exception stack frame format: {}",
+                                format
+                            )));
+                        }
+                    };
+                }
+                self.set_sp(sp);
+                //the stack is switched after the frame is popped from the supervisor one
+                self.set_sr(sr.get_word());
+                self.pc = pc.get_long() as usize;
+            }
+            Instruction::RTR => {
+                let (ccr, sp) = self.memory.pop(Size::Word, self.get_sp())?;
+                let (pc, sp) = self.memory.pop(Size::Long, sp)?;
+                if self.keep_history {
+                    self.debugger.add_mutation(MutationOperation::PopCall {
+                        to: pc.get_long() as usize,
+                        from: self.last_line_address,
+                    })
+                }
+                self.set_sp(sp);
+                self.cpu.ccr = Flags::from_ccr(ccr.get_byte());
+                self.pc = pc.get_long() as usize;
+                self.debugger.pop_call();
+            }
+            Instruction::MOVEtoSR(source, target) => {
+                let value = self.get_operand_value(source, Size::Word, Used::Once)? as u16;
+                match target {
+                    StatusRegister::SR => {
+                        self.verify_supervisor("MOVE to SR")?;
+                        self.set_sr(value);
+                    }
+                    StatusRegister::CCR => self.cpu.ccr = Flags::from_ccr(value as u8),
+                }
+            }
+            Instruction::MOVEfromSR(dest, target) => {
+                let value = match target {
+                    //only the 68000 lets the user mode read the whole status register
+                    StatusRegister::SR if self.cpu_model != CpuModel::M68000 => {
+                        self.verify_supervisor("MOVE from SR")?;
+                        self.get_sr()
+                    }
+                    StatusRegister::SR => self.get_sr(),
+                    StatusRegister::CCR => self.cpu.ccr.to_ccr() as u16,
+                };
+                self.store_operand_value(dest, value as u32, Size::Word, Used::Once)?;
+            }
+            Instruction::ANDItoSR(value, target) => {
+                self.apply_to_status_register(*value, target, "ANDI to SR", |sr, value| sr & value)?
+            }
+            Instruction::ORItoSR(value, target) => {
+                self.apply_to_status_register(*value, target, "ORI to SR", |sr, value| sr | value)?
+            }
+            Instruction::EORItoSR(value, target) => {
+                self.apply_to_status_register(*value, target, "EORI to SR", |sr, value| sr ^ value)?
+            }
+            Instruction::MOVEUSP { register, to_usp } => {
+                self.verify_supervisor("MOVE USP")?;
+                if *to_usp {
+                    let value = self.get_register_value(register, Size::Long);
+                    self.set_control_register(&ControlRegister::USP, value);
+                } else {
+                    let value = self.get_control_register(&ControlRegister::USP);
+                    self.set_register_value(register, value, Size::Long);
+                }
+            }
+            Instruction::MOVEC { register, control_register, to_control } => {
+                self.verify_supervisor("MOVEC")?;
+                if *to_control {
+                    let value = self.get_register_value(register, Size::Long);
+                    self.set_control_register(control_register, value);
+                } else {
+                    let value = self.get_control_register(control_register);
+                    self.set_register_value(register, value, Size::Long);
+                }
+            }
+            Instruction::MOVES(source, dest, size) => {
+                self.verify_supervisor("MOVES")?;
+                //there is no MMU, so the function codes in SFC/DFC don't change the address space
+                let value = self.get_operand_value(source, *size, Used::Once)?;
+                self.store_operand_value(dest, value, *size, Used::Once)?;
+            }
+            Instruction::FMOVE(..)
+            | Instruction::FADD(..)
+            | Instruction::FSUB(..)
+            | Instruction::FMUL(..)
+            | Instruction::FDIV(..)
+            | Instruction::FCMP(..)
+            | Instruction::FBcc(..)
+            | Instruction::LINEF(..) => self.execute_coprocessor_instruction(ins)?,
             Instruction::TRAP(value) => match value {
                 15 => {
+                    self.count_trap()?;
                     let task = self.cpu.d_reg[0].get_byte();
                     let interrupt = self.get_trap(task)?;
-                    self.current_interrupt = Some(interrupt);
-                    self.set_status(InterpreterStatus::Interrupt);
+                    self.count_output(&interrupt)?;
+                    //the terminate task ends the program without waiting for an answer
+                    if self.status != InterpreterStatus::Terminated {
+                        self.current_interrupt = Some(interrupt);
+                        self.set_status(InterpreterStatus::Interrupt);
+                    }
                 }
                 _ => {
-                    return Err(RuntimeError::Raw(format!(
-                        "Unknown trap: {}, only IO with #15 allowed",
-                        value
-                    )));
+                    self.count_trap()?;
+                    return Err(RuntimeError::Exception(Exception::Trap(*value)));
                 }
             },
+            Instruction::TRAPV => {
+                if self.get_flag(Flags::Overflow) {
+                    return Err(RuntimeError::Exception(Exception::TrapV));
+                }
+            }
+            Instruction::CHK(source, register) => {
+                let bound = self.get_operand_value(source, Size::Word, Used::Once)? as i16;
+                let value = self.get_register_value(register, Size::Word) as i16;
+                if value < 0 || value > bound {
+                    self.set_flag(Flags::Negative, value < 0);
+                    return Err(RuntimeError::Exception(Exception::Chk));
+                }
+            }
+            Instruction::ILLEGAL => return Err(RuntimeError::Exception(Exception::IllegalInstruction)),
+            Instruction::SIMHALT => self.set_status(InterpreterStatus::Terminated),
+            Instruction::NOP => {}
         };
         Ok(())
     }
+    #[cfg(feature = "std")]
     #[rustfmt::skip]
     pub fn debug_status(&self) {
         println!("\n-----INTERPRETER DEBUG-----\n");
@@ -1322,6 +2524,240 @@ impl Interpreter {
         println!("{}", ccr);
     }
 
+    pub fn get_control_register(&self, register: &ControlRegister) -> u32 {
+        match register {
+            ControlRegister::SFC => self.cpu.sfc as u32,
+            ControlRegister::DFC => self.cpu.dfc as u32,
+            ControlRegister::USP => match self.is_supervisor() {
+                true => self.cpu.usp,
+                false => self.cpu.a_reg[7].get_long(),
+            },
+            ControlRegister::VBR => self.cpu.vbr,
+        }
+    }
+    pub fn set_control_register(&mut self, register: &ControlRegister, value: u32) {
+        if self.keep_history {
+            self.debugger
+                .add_mutation(MutationOperation::WriteControlRegister {
+                    register: *register,
+                    old: self.get_control_register(register),
+                });
+        }
+        self.store_control_register(register, value);
+    }
+    fn store_control_register(&mut self, register: &ControlRegister, value: u32) {
+        match register {
+            //only the function code bits are kept
+            ControlRegister::SFC => self.cpu.sfc = (value & 0x7) as u8,
+            ControlRegister::DFC => self.cpu.dfc = (value & 0x7) as u8,
+            ControlRegister::USP if self.is_supervisor() => self.cpu.usp = value,
+            ControlRegister::USP => self.cpu.a_reg[7].store_long(value),
+            ControlRegister::VBR => self.cpu.vbr = value,
+        }
+    }
+    /// The status register, the program starts in supervisor mode with the interrupts enabled
+    pub fn get_sr(&self) -> u16 {
+        ((self.cpu.system as u16) << 8) | self.cpu.ccr.to_ccr() as u16
+    }
+    pub fn set_sr(&mut self, sr: u16) {
+        if self.keep_history {
+            self.debugger
+                .add_mutation(MutationOperation::WriteStatusRegister { old: self.get_sr() });
+        }
+        self.store_sr(sr);
+    }
+    /// Entering or leaving supervisor mode swaps A7 with the stack pointer of the other mode
+    fn store_sr(&mut self, sr: u16) {
+        let supervisor = sr & SR_SUPERVISOR != 0;
+        if supervisor != self.is_supervisor() {
+            let sp = self.cpu.a_reg[7].get_long();
+            let sp = match supervisor {
+                true => {
+                    self.cpu.usp = sp;
+                    self.cpu.ssp
+                }
+                false => {
+                    self.cpu.ssp = sp;
+                    self.cpu.usp
+                }
+            };
+            self.cpu.a_reg[7].store_long(sp);
+        }
+        self.cpu.system = ((sr & SR_SYSTEM_MASK) >> 8) as u8;
+        self.cpu.ccr = Flags::from_ccr(sr as u8);
+    }
+    pub fn is_supervisor(&self) -> bool {
+        self.get_sr() & SR_SUPERVISOR != 0
+    }
+    /// The interrupt priority mask, interrupts of this level or lower are ignored
+    pub fn get_interrupt_mask(&self) -> u8 {
+        self.cpu.system & 0x7
+    }
+    /// ANDI/ORI/EORI to the status register, the one to the condition codes can run in user mode
+    fn apply_to_status_register(
+        &mut self,
+        value: u16,
+        target: &StatusRegister,
+        name: &str,
+        operation: fn(u16, u16) -> u16,
+    ) -> RuntimeResult<()> {
+        match target {
+            StatusRegister::SR => {
+                self.verify_supervisor(name)?;
+                self.set_sr(operation(self.get_sr(), value));
+            }
+            StatusRegister::CCR => {
+                let ccr = operation(self.cpu.ccr.to_ccr() as u16, value & 0xFF);
+                self.cpu.ccr = Flags::from_ccr(ccr as u8);
+            }
+        }
+        Ok(())
+    }
+    fn verify_supervisor(&self, name: &str) -> RuntimeResult<()> {
+        match self.is_supervisor() {
+            true => Ok(()),
+            false => Err(RuntimeError::PrivilegeViolation(name.to_string())),
+        }
+    }
+    /// Address of the exception vector, relative to the VBR
+    pub fn get_exception_vector_address(&self, vector: u8) -> usize {
+        self.cpu.vbr as usize + vector as usize * 4
+    }
+    /// Polled after every instruction, it can raise interrupts with the cycles that ran
+    pub fn add_interrupt_source(&mut self, source: Box<dyn InterruptSource>) {
+        self.interrupt_sources.push(source);
+    }
+    pub fn clear_interrupt_sources(&mut self) {
+        self.interrupt_sources.clear();
+    }
+    /// Makes the level pending, it is taken after the current instruction if the mask allows it
+    pub fn raise_interrupt(&mut self, level: u8) -> RuntimeResult<()> {
+        match level {
+            1..=7 => {
+                self.pending_interrupts |= 1 << level;
+                Ok(())
+            }
+            _ => Err(RuntimeError::Raw(format!(
+                "Invalid interrupt level {}, must be between 1 and 7",
+                level
+            ))),
+        }
+    }
+    /// Bit n is set while the level n is pending
+    pub fn get_pending_interrupts(&self) -> u8 {
+        self.pending_interrupts
+    }
+    /// Polls the sources and takes the highest pending level if the mask allows it, level 7 can't be masked
+    fn check_interrupts(&mut self, line_index: usize) -> RuntimeResult<()> {
+        for source in self.interrupt_sources.iter_mut() {
+            if let Some(level @ 1..=7) = source.poll(self.cycles) {
+                self.pending_interrupts |= 1 << level;
+            }
+        }
+        if self.pending_interrupts == 0 {
+            return Ok(());
+        }
+        let level = 7 - self.pending_interrupts.leading_zeros() as u8;
+        if level <= self.get_interrupt_mask() && level != 7 {
+            return Ok(());
+        }
+        self.pending_interrupts &= !(1 << level);
+        let exception = Exception::Autovector(level);
+        let Some(handler) = self.get_exception_handler(exception) else {
+            return Err(RuntimeError::Exception(exception));
+        };
+        let sr = self.get_sr();
+        self.set_sr(((sr | SR_SUPERVISOR) & !SR_TRACE & !SR_INTERRUPT_MASK) | ((level as u16) << 8));
+        self.push_exception_frame(exception, self.pc as u32, sr, self.pc as u32)?;
+        self.pc = handler;
+        let cycles = self.timing.get_cycles("interrupt", Size::Long);
+        self.cycles += cycles as u64;
+        if let Some(line) = self.line_cycles.get_mut(&line_index) {
+            line.cycles += cycles as u64;
+        }
+        Ok(())
+    }
+    /// Address of the handler of the exception, None when its vector doesn't point to an instruction
+    pub fn get_exception_handler(&self, exception: Exception) -> Option<usize> {
+        let address = self.get_exception_vector_address(exception.get_vector());
+        let bytes = self.memory.peek_bytes(address, 4).ok()?;
+        let handler = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize;
+        self.get_instruction_at(handler).map(|_| handler)
+    }
+    /**
+    Pushes the exception stack frame with the SR before the exception, the 68000 pushes only the PC and SR while
+    the 68010 and later add a format/vector offset word, the 68020 and CPU32 use format 2 with the address of the
+    instruction for the exceptions that have it
+     */
+    pub fn push_exception_frame(
+        &mut self,
+        exception: Exception,
+        pc: u32,
+        sr: u16,
+        instruction_address: u32,
+    ) -> RuntimeResult<()> {
+        let mut sp = self.get_sp();
+        let vector = exception.get_vector();
+        if self.cpu_model != CpuModel::M68000 {
+            let format = match self.cpu_model != CpuModel::M68010 && exception.has_instruction_address() {
+                true => {
+                    sp = Memory::get_push_address(sp, Size::Long)?;
+                    self.set_memory_value(sp, Size::Long, instruction_address)?;
+                    0x2000
+                }
+                false => 0,
+            };
+            sp = Memory::get_push_address(sp, Size::Word)?;
+            self.set_memory_value(sp, Size::Word, format | ((vector as u32 * 4) & 0x0FFF))?;
+        }
+        sp = Memory::get_push_address(sp, Size::Long)?;
+        self.set_memory_value(sp, Size::Long, pc)?;
+        sp = Memory::get_push_address(sp, Size::Word)?;
+        self.set_memory_value(sp, Size::Word, sr as u32)?;
+        self.set_sp(sp);
+        Ok(())
+    }
+    /**
+    Takes the exception of the error when its vector has a handler, otherwise the error is returned.
+    The address error of the 68000 pushes the group 0 frame on top of the normal one
+     */
+    fn take_exception(&mut self, error: RuntimeError, address: usize, next_address: usize) -> RuntimeResult<()> {
+        let exception = match &error {
+            RuntimeError::Exception(exception) => *exception,
+            RuntimeError::DivisionByZero => Exception::DivideByZero,
+            RuntimeError::PrivilegeViolation(_) => Exception::PrivilegeViolation,
+            RuntimeError::AddressError(..) if self.cpu_model == CpuModel::M68000 => Exception::AddressError,
+            _ => return Err(error),
+        };
+        let Some(handler) = self.get_exception_handler(exception) else {
+            return Err(error);
+        };
+        let pc = match exception.restarts_instruction() {
+            true => address,
+            false => next_address,
+        };
+        let sr = self.get_sr();
+        let supervisor = match sr & SR_SUPERVISOR != 0 {
+            true => 0b101,
+            false => 0b001,
+        };
+        self.set_sr((sr | SR_SUPERVISOR) & !SR_TRACE);
+        self.push_exception_frame(exception, pc as u32, sr, address as u32)?;
+        if let RuntimeError::AddressError(access_address, _) = error {
+            let mut sp = self.get_sp();
+            sp = Memory::get_push_address(sp, Size::Word)?;
+            self.set_memory_value(sp, Size::Word, 0)?;
+            sp = Memory::get_push_address(sp, Size::Long)?;
+            self.set_memory_value(sp, Size::Long, access_address as u32)?;
+            //a data read, the access kind is not known
+            sp = Memory::get_push_address(sp, Size::Word)?;
+            self.set_memory_value(sp, Size::Word, 0x10 | supervisor)?;
+            self.set_sp(sp);
+        }
+        self.pc = handler;
+        Ok(())
+    }
+
     #[inline]
     pub fn get_register_value(&self, register: &RegisterOperand, size: Size) -> u32 {
         match register {
@@ -1361,7 +2797,8 @@ impl Interpreter {
         size: Size,
         value: u32,
     ) -> RuntimeResult<()> {
-        if self.keep_history {
+        //reading a device can have side effects, its writes are not undone
+        if self.keep_history && !self.memory.is_mapped(address, size.to_bytes()) {
             let old_value = self.memory.read_size(address, size)?;
             self.debugger.add_mutation(MutationOperation::WriteMemory {
                 address,
@@ -1433,7 +2870,7 @@ impl Interpreter {
 
 
     pub fn set_memory_bytes(&mut self, address: usize, bytes: &[u8]) -> RuntimeResult<()> {
-        if self.keep_history {
+        if self.keep_history && !self.memory.is_mapped(address, bytes.len()) {
             let old_bytes = self.memory.read_bytes(address, bytes.len())?;
             self.debugger
                 .add_mutation(MutationOperation::WriteMemoryBytes {
@@ -1446,6 +2883,31 @@ impl Interpreter {
     pub fn get_next_instruction(&self) -> Option<&InstructionLine> {
         self.get_instruction_at(self.pc)
     }
+    /// The string at A1 up to the null char
+    fn read_null_terminated(&self) -> RuntimeResult<String> {
+        let max = 16384; //to prevent infinite loop
+        let address = self.cpu.a_reg[1].get_long() as usize;
+        let mut bytes = Vec::new();
+        loop {
+            let byte = self.memory.read_byte(address + bytes.len())?;
+            if byte == 0x00 {
+                break;
+            }
+            bytes.push(byte);
+            if bytes.len() > max {
+                return Err(RuntimeError::Raw(format!(
+                    "Invalid String read, reached max length of {} bytes",
+                    max
+                )));
+            }
+        }
+        String::from_utf8(bytes).map_err(|e| {
+            RuntimeError::Raw(format!(
+                "Invalid String read, received: {:?}, expected UTF-8",
+                e.into_bytes()
+            ))
+        })
+    }
     fn get_trap(&mut self, value: u8) -> RuntimeResult<Interrupt> {
         match value {
             0 | 1 => {
@@ -1462,9 +2924,8 @@ impl Interpreter {
                         .to_vec();
                     if value == 0 {
                         //get all bytes until 0x00
-                        match bytes.iter().position(|&x| x == 0x00) {
-                            Some(pos) => bytes = bytes[..pos].to_vec(),
-                            None => {}
+                        if let Some(pos) = bytes.iter().position(|&x| x == 0x00) {
+                            bytes = bytes[..pos].to_vec()
                         }
                     }
                     //TODO implement call to interrupt handler
@@ -1480,7 +2941,7 @@ impl Interpreter {
             }
             2 => Ok(Interrupt::ReadKeyboardString),
             3 => {
-                let value = self.cpu.d_reg[1].get_long();
+                let value = self.cpu.d_reg[1].get_long() as i32;
                 Ok(Interrupt::DisplayNumber(value))
             }
             4 => Ok(Interrupt::ReadNumber),
@@ -1489,40 +2950,41 @@ impl Interpreter {
                 let value = self.cpu.d_reg[1].get_byte();
                 Ok(Interrupt::DisplayChar(value as char))
             }
+            7 => Ok(Interrupt::IsInputPending),
             8 => Ok(Interrupt::GetTime),
             9 => {
                 self.status = InterpreterStatus::Terminated;
                 Ok(Interrupt::Terminate)
             }
-            13 | 14 => {
-                //read untill null char
-                let max = 16384; //to prevent infinite loop
-                let address = self.cpu.a_reg[1].get_long() as usize;
-                let mut bytes = Vec::new();
-                let mut i = 0;
-                loop {
-                    let byte = self.memory.read_byte(address + i)?;
-                    if byte == 0x00 {
-                        break;
-                    }
-                    bytes.push(byte);
-                    i += 1;
-                    if i > max {
-                        return Err(RuntimeError::Raw(format!(
-                            "Invalid String read, reached max length of {} bytes",
-                            max
-                        )));
-                    }
-                }
-                match String::from_utf8(bytes.to_vec()) {
-                    Ok(str) if value == 13 => Ok(Interrupt::DisplayStringWithCRLF(str)),
-                    Ok(str) if value == 14 => Ok(Interrupt::DisplayStringWithoutCRLF(str)),
-                    Err(_) | Ok(_) => Err(RuntimeError::Raw(format!(
-                        "Invalid String read, received: {:?}, expected UTF-8",
-                        bytes
+            11 => match self.cpu.d_reg[1].get_word() {
+                0xFF00 => Ok(Interrupt::ClearScreen),
+                position => Ok(Interrupt::PositionCursor((position >> 8) as u8, position as u8)),
+            },
+            12 => Ok(Interrupt::SetEcho(self.cpu.d_reg[1].get_byte() != 0)),
+            13 => Ok(Interrupt::DisplayStringWithCRLF(self.read_null_terminated()?)),
+            14 => Ok(Interrupt::DisplayStringWithoutCRLF(self.read_null_terminated()?)),
+            15 => {
+                let number = self.cpu.d_reg[1].get_long();
+                let base = self.cpu.d_reg[2].get_byte() as u32;
+                match to_radix(number, base) {
+                    Some(digits) => Ok(Interrupt::DisplayStringWithoutCRLF(digits)),
+                    None => Err(RuntimeError::Raw(format!(
+                        "Invalid number base in d2 register: {}, expected between 2 and 36",
+                        base
                     ))),
                 }
             }
+            17 => {
+                let string = self.read_null_terminated()?;
+                let number = self.cpu.d_reg[1].get_long() as i32;
+                Ok(Interrupt::DisplayStringWithoutCRLF(format!("{}{}", string, number)))
+            }
+            18 => Ok(Interrupt::ReadNumberWithPrompt(self.read_null_terminated()?)),
+            20 => {
+                let number = self.cpu.d_reg[1].get_long() as i32;
+                let width = self.cpu.d_reg[2].get_byte() as usize;
+                Ok(Interrupt::DisplayStringWithoutCRLF(format!("{:>1$}", number, width)))
+            }
             _ => Err(RuntimeError::Raw(format!("Unknown interrupt: {}", value))),
         }
     }
@@ -1555,6 +3017,9 @@ impl Interpreter {
         match op {
             Operand::Immediate(v) => Ok(*v),
             Operand::Register(op) => Ok(self.get_register_value(op, size)),
+            Operand::RegisterList(_) => Err(RuntimeError::IncorrectAddressingMode(
+                "Attempted to read a register list as a value".to_string(),
+            )),
             Operand::Absolute(address) => Ok(self.memory.read_size(*address, size)?),
 
             Operand::Indirect(reg) => {
@@ -1587,13 +3052,16 @@ impl Interpreter {
                 //TODO not sure if this is how it should work
                 //TODO should this be i32?
                 let base_value = self.get_register_value(base, Size::Long) as i32;
-                let index_value = self.get_register_value(&index.register, index.size);
-                let index_value = sign_extend_to_long(index_value, index.size);
+                let index_value = self.get_index_value(index);
                 let final_address = base_value
                     .wrapping_add(*offset)
                     .wrapping_add(index_value);
                 Ok(self.memory.read_size(final_address as usize, size)?)
             }
+            Operand::MemoryIndirect { .. } => {
+                let address = self.get_operand_address(op)?;
+                Ok(self.memory.read_size(address as usize, size)?)
+            }
         }
     }
     fn get_operand_address(&mut self, op: &Operand) -> RuntimeResult<u32> {
@@ -1611,13 +3079,37 @@ impl Interpreter {
             Operand::IndirectIndex { offset, base, index } => {
                 //TODO not sure if this is how it should work
                 let base_value = self.get_register_value(base, Size::Long) as i32;
-                let index_value = self.get_register_value(&index.register, index.size);
-                let index_value = sign_extend_to_long(index_value, index.size);
+                let index_value = self.get_index_value(index);
                 let final_address = base_value
                     .wrapping_add(*offset)
                     .wrapping_add(index_value);
                 Ok(final_address as u32)
             }
+            Operand::MemoryIndirect {
+                base,
+                base_displacement,
+                index,
+                post_indexed,
+                outer_displacement,
+            } => {
+                let index_value = match index {
+                    Some(index) => self.get_index_value(index),
+                    None => 0,
+                };
+                let base_value = match base {
+                    Some(base) => self.get_register_value(base, Size::Long) as i32,
+                    None => 0,
+                };
+                let mut intermediate = base_value.wrapping_add(*base_displacement);
+                if !post_indexed {
+                    intermediate = intermediate.wrapping_add(index_value);
+                }
+                let mut address = self.memory.read_long(intermediate as u32 as usize)? as i32;
+                if *post_indexed {
+                    address = address.wrapping_add(index_value);
+                }
+                Ok(address.wrapping_add(*outer_displacement) as u32)
+            }
             Operand::Absolute(address) => Ok(*address as u32),
             _ => Err(RuntimeError::IncorrectAddressingMode(
                 "Attempted to get address of non address addressing mode".to_string(),
@@ -1635,6 +3127,9 @@ impl Interpreter {
             Operand::Immediate(_) => Err(RuntimeError::IncorrectAddressingMode(
                 "Attempted to store to immediate value".to_string(),
             )),
+            Operand::RegisterList(_) => Err(RuntimeError::IncorrectAddressingMode(
+                "Attempted to store to a register list".to_string(),
+            )),
             Operand::Register(op) => {
                 self.set_register_value(op, value, size);
                 Ok(())
@@ -1669,15 +3164,171 @@ impl Interpreter {
             }
             Operand::IndirectIndex { offset, index, base } => {
                 let base_value = self.get_register_value(base, Size::Long) as i32;
-                let index_value = self.get_register_value(&index.register, index.size);
-                let index_value = sign_extend_to_long(index_value, index.size);
+                let index_value = self.get_index_value(index);
                 let final_address = base_value
                     .wrapping_add(*offset)
                     .wrapping_add(index_value);
                 Ok(self.set_memory_value(final_address as usize, size, value)?)
             }
+            Operand::MemoryIndirect { .. } => {
+                let address = self.get_operand_address(op)?;
+                Ok(self.set_memory_value(address as usize, size, value)?)
+            }
         }
     }
+    pub fn attach_coprocessor(&mut self, coprocessor: Box<dyn Coprocessor>) -> RuntimeResult<()> {
+        let id = coprocessor.get_id();
+        match self.coprocessors.get(id as usize) {
+            Some(None) => {
+                self.coprocessors[id as usize] = Some(coprocessor);
+                Ok(())
+            }
+            Some(Some(attached)) => Err(RuntimeError::Raw(format!(
+                "Coprocessor id {} is already used by \"{}\"",
+                id,
+                attached.get_name()
+            ))),
+            None => Err(RuntimeError::Raw(format!(
+                "Invalid coprocessor id {}, must be between 0 and 7",
+                id
+            ))),
+        }
+    }
+    pub fn get_coprocessor(&self, id: u8) -> RuntimeResult<&dyn Coprocessor> {
+        match self.coprocessors.get(id as usize) {
+            Some(Some(coprocessor)) => Ok(coprocessor.as_ref()),
+            _ => Err(RuntimeError::UnsupportedInstruction(format!(
+                "No coprocessor is attached with id {}",
+                id
+            ))),
+        }
+    }
+    fn get_coprocessor_mut(&mut self, id: u8) -> RuntimeResult<&mut Box<dyn Coprocessor>> {
+        match self.coprocessors.get_mut(id as usize) {
+            Some(Some(coprocessor)) => Ok(coprocessor),
+            _ => Err(RuntimeError::UnsupportedInstruction(format!(
+                "No coprocessor is attached with id {}",
+                id
+            ))),
+        }
+    }
+    pub fn get_fpu(&self) -> RuntimeResult<&Fpu> {
+        match self.get_coprocessor(FPU_COPROCESSOR_ID)?.as_any().downcast_ref::<Fpu>() {
+            Some(fpu) => Ok(fpu),
+            None => Err(RuntimeError::Raw(format!(
+                "The coprocessor with id {} is not an FPU",
+                FPU_COPROCESSOR_ID
+            ))),
+        }
+    }
+    pub fn save_coprocessor_state(&self, id: u8) -> RuntimeResult<Vec<u8>> {
+        Ok(self.get_coprocessor(id)?.save_state())
+    }
+    pub fn restore_coprocessor_state(&mut self, id: u8, state: &[u8]) -> RuntimeResult<()> {
+        let keep_history = self.keep_history;
+        let coprocessor = self.get_coprocessor_mut(id)?;
+        let old = coprocessor.save_state();
+        coprocessor.restore_state(state)?;
+        if keep_history {
+            self.debugger
+                .add_mutation(MutationOperation::WriteCoprocessorState { id, old });
+        }
+        Ok(())
+    }
+    /*
+        The coprocessor is taken out while it runs so that it can borrow the interpreter as its bus
+     */
+    fn execute_coprocessor_instruction(&mut self, ins: &Instruction) -> RuntimeResult<()> {
+        let id = ins.get_coprocessor_id().unwrap_or(0);
+        let mut coprocessor = match self.coprocessors[id as usize].take() {
+            Some(coprocessor) => coprocessor,
+            None => {
+                return Err(RuntimeError::UnsupportedInstruction(format!(
+                    "The {} instruction needs a coprocessor attached with id {}",
+                    ins.get_instruction_name(),
+                    id
+                )))
+            }
+        };
+        if self.keep_history {
+            self.debugger.add_mutation(MutationOperation::WriteCoprocessorState {
+                id,
+                old: coprocessor.save_state(),
+            });
+        }
+        let result = coprocessor.execute(ins, self);
+        self.coprocessors[id as usize] = Some(coprocessor);
+        result
+    }
+    fn get_bitfield_value(&self, value: &BitfieldValue) -> u32 {
+        match value {
+            BitfieldValue::Immediate(value) => *value,
+            BitfieldValue::Register(register) => self.get_register_value(register, Size::Long),
+        }
+    }
+    /*
+        In a register the field wraps around the 32 bits, in memory the offset is signed and relative
+        to the most significant bit of the byte at the effective address, so a field can span 5 bytes
+     */
+    fn get_bitfield_location(&mut self, target: &Operand, offset: i32) -> RuntimeResult<(usize, u32)> {
+        let address = self.get_operand_address(target)? as i64 + (offset >> 3) as i64;
+        Ok((address as usize, (offset & 7) as u32))
+    }
+    fn read_bitfield(&mut self, target: &Operand, offset: i32, width: u32) -> RuntimeResult<u32> {
+        match target {
+            Operand::Register(register) => {
+                let value = self.get_register_value(register, Size::Long);
+                Ok(value.rotate_left(offset as u32 % 32) >> (32 - width))
+            }
+            _ => {
+                let (address, bit_offset) = self.get_bitfield_location(target, offset)?;
+                let length = (bit_offset + width).div_ceil(8);
+                let mut data = 0u64;
+                for i in 0..5 {
+                    let byte = match i < length {
+                        true => self.memory.read_byte(address + i as usize)?,
+                        false => 0,
+                    };
+                    data = (data << 8) | byte as u64;
+                }
+                Ok(((data >> (40 - bit_offset - width)) as u32) & (u32::MAX >> (32 - width)))
+            }
+        }
+    }
+    fn write_bitfield(&mut self, target: &Operand, offset: i32, width: u32, value: u32) -> RuntimeResult<()> {
+        let mask = u32::MAX >> (32 - width);
+        match target {
+            Operand::Register(register) => {
+                let shift = offset as u32 % 32;
+                let field_mask = (mask << (32 - width)).rotate_right(shift);
+                let field = (value << (32 - width)).rotate_right(shift);
+                let old = self.get_register_value(register, Size::Long);
+                self.set_register_value(register, (old & !field_mask) | field, Size::Long);
+                Ok(())
+            }
+            _ => {
+                let (address, bit_offset) = self.get_bitfield_location(target, offset)?;
+                let length = (bit_offset + width).div_ceil(8);
+                let shift = 40 - bit_offset - width;
+                let field_mask = (mask as u64) << shift;
+                let field = ((value & mask) as u64) << shift;
+                for i in 0..length {
+                    let byte_shift = 32 - i * 8;
+                    let byte_mask = ((field_mask >> byte_shift) & 0xFF) as u32;
+                    let byte_value = ((field >> byte_shift) & 0xFF) as u32;
+                    let address = address + i as usize;
+                    let old = self.memory.read_byte(address)? as u32;
+                    self.set_memory_value(address, Size::Byte, (old & !byte_mask) | byte_value)?;
+                }
+                Ok(())
+            }
+        }
+    }
+    fn get_index_value(&self, index: &IndexRegister) -> i32 {
+        let index_value = self.get_register_value(&index.register, index.size);
+        let index_value = sign_extend_to_long(index_value, index.size);
+        index_value.wrapping_mul(index.scale as i32)
+    }
     pub fn verify_can_run(&mut self) -> RuntimeResult<()> {
         if self.status == InterpreterStatus::Terminated
             || self.status == InterpreterStatus::TerminatedWithException
@@ -1693,15 +3344,82 @@ impl Interpreter {
         }
         Ok(())
     }
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, err(Debug)))]
     pub fn run(&mut self) -> RuntimeResult<InterpreterStatus> {
         self.verify_can_run()?;
+        let watching = self.start_break_checks();
         while self.status == InterpreterStatus::Running {
             self.step()?;
+            if watching && self.check_break() {
+                break;
+            }
         }
         Ok(self.status)
     }
+    /// Stops before the instruction of the line, label or address runs, returns the id of the breakpoint
+    pub fn add_breakpoint(&mut self, location: BreakpointLocation) -> RuntimeResult<usize> {
+        let address = match &location {
+            BreakpointLocation::Line(line_index) => self
+                .get_source_map()
+                .get_first_address_of_line(*line_index)
+                .ok_or_else(|| RuntimeError::Raw(format!("There is no instruction on line {}", line_index + 1)))?,
+            BreakpointLocation::Label(name) => self
+                .debugger
+                .get_labels()
+                .get(name)
+                .map(|label| label.address)
+                .ok_or_else(|| RuntimeError::Raw(format!("Unknown label \"{}\"", name)))?,
+            BreakpointLocation::Address(address) => *address,
+        };
+        let id = self.next_break_id;
+        self.next_break_id += 1;
+        self.breakpoints.push(Breakpoint { id, location, address });
+        Ok(id)
+    }
+    pub fn remove_breakpoint(&mut self, id: usize) -> bool {
+        let length = self.breakpoints.len();
+        self.breakpoints.retain(|b| b.id != id);
+        self.breakpoints.len() != length
+    }
+    pub fn get_breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+    /// Stops after an instruction reads or writes the range, as chosen by the accesses, returns the id of the watchpoint
+    pub fn add_watchpoint(&mut self, address: usize, length: usize, accesses: Permissions) -> usize {
+        let id = self.next_break_id;
+        self.next_break_id += 1;
+        self.memory.add_watchpoint(Watchpoint { id, address, length, accesses });
+        id
+    }
+    pub fn remove_watchpoint(&mut self, id: usize) -> bool {
+        self.memory.remove_watchpoint(id)
+    }
+    pub fn get_watchpoints(&self) -> &[Watchpoint] {
+        self.memory.get_watchpoints()
+    }
+    /// The breakpoint or watchpoint that stopped the last run
+    pub fn get_last_break(&self) -> Option<BreakHit> {
+        self.last_break
+    }
+    /// Forgets the last hit and the accesses made outside of the run, returns if there is something to check
+    fn start_break_checks(&mut self) -> bool {
+        self.last_break = None;
+        self.memory.take_watch_hit();
+        !self.breakpoints.is_empty() || !self.memory.get_watchpoints().is_empty()
+    }
+    /// Checks the watchpoints hit by the last instruction and the breakpoints of the next one
+    fn check_break(&mut self) -> bool {
+        let hit = self.memory.take_watch_hit().or_else(|| {
+            self.breakpoints
+                .iter()
+                .find(|b| b.address == self.pc)
+                .map(|b| BreakHit::Breakpoint { id: b.id, address: b.address })
+        });
+        self.last_break = hit;
+        hit.is_some()
+    }
 
-    pub fn generate_breakpoints_map(&self, breakpoint_lines: &Vec<usize>) -> Vec<bool> {
+    pub fn generate_breakpoints_map(&self, breakpoint_lines: &[usize]) -> Vec<bool> {
         let breakpoints_lines_map = breakpoint_lines
             .iter()
             .map(|l| (l, true))
@@ -1719,9 +3437,10 @@ impl Interpreter {
         }
         breakpoints_addresses_map
     }
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, err(Debug)))]
     pub fn run_with_breakpoints(
         &mut self,
-        breakpoint_lines: &Vec<usize>,
+        breakpoint_lines: &[usize],
         limit: Option<usize>,
     ) -> RuntimeResult<InterpreterStatus> {
         self.verify_can_run()?;
@@ -1729,6 +3448,7 @@ impl Interpreter {
         let mut iterations = 0;
         let limit = limit.unwrap_or(usize::MAX);
         let mut limit_counter = limit;
+        let watching = self.start_break_checks();
         while self.status == InterpreterStatus::Running && limit_counter > 0 {
             match breakpoints_map.get(self.pc) {
                 //skip the first iteration if the pc is in a breakpoint
@@ -1740,6 +3460,9 @@ impl Interpreter {
                     self.step()?;
                 }
             }
+            if watching && self.check_break() {
+                break;
+            }
             limit_counter -= 1;
             iterations += 1;
         }
@@ -1750,11 +3473,16 @@ impl Interpreter {
         //convert the line numbers to their corresponding addresses, to then save it in a vector to check if the current pc is in it
     }
 
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(limit), err(Debug)))]
     pub fn run_with_limit(&mut self, limit: usize) -> RuntimeResult<InterpreterStatus> {
         let mut limit_counter = limit;
         self.verify_can_run()?;
+        let watching = self.start_break_checks();
         while self.status == InterpreterStatus::Running && limit_counter > 0 {
             self.step()?;
+            if watching && self.check_break() {
+                break;
+            }
             limit_counter -= 1;
         }
         if limit_counter == 0 {
@@ -1847,7 +3575,40 @@ impl Interpreter {
     }
 }
 
-#[wasm_bindgen]
+impl CoprocessorBus for Interpreter {
+    fn read_operand(&mut self, op: &Operand, size: Size) -> RuntimeResult<u32> {
+        self.get_operand_value(op, size, Used::Once)
+    }
+    fn write_operand(&mut self, op: &Operand, value: u32, size: Size) -> RuntimeResult<()> {
+        self.store_operand_value(op, value, size, Used::Once)
+    }
+    fn get_operand_address(&mut self, op: &Operand, bytes: u32) -> RuntimeResult<usize> {
+        match op {
+            Operand::PreIndirect(reg) => {
+                let address = self.get_a_reg_sized(*reg, Size::Long).wrapping_sub(bytes);
+                self.set_a_reg_sized(*reg, address, Size::Long);
+                Ok(address as usize)
+            }
+            Operand::PostIndirect(reg) => {
+                let address = self.get_a_reg_sized(*reg, Size::Long);
+                self.set_a_reg_sized(*reg, address.wrapping_add(bytes), Size::Long);
+                Ok(address as usize)
+            }
+            _ => Ok(Interpreter::get_operand_address(self, op)? as usize),
+        }
+    }
+    fn read_bytes(&mut self, address: usize, length: usize) -> RuntimeResult<Vec<u8>> {
+        Ok(self.memory.read_bytes(address, length)?.to_vec())
+    }
+    fn write_bytes(&mut self, address: usize, bytes: &[u8]) -> RuntimeResult<()> {
+        self.set_memory_bytes(address, bytes)
+    }
+    fn set_pc(&mut self, address: usize) {
+        self.pc = address;
+    }
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
 impl Interpreter {
     pub fn wasm_read_memory_bytes(&self, address: usize, size: usize) -> Vec<u8> {
         match self.memory.read_bytes(address, size) {
@@ -1855,6 +3616,7 @@ impl Interpreter {
             Err(_) => vec![],
         }
     }
+    #[cfg(feature = "wasm")]
     pub fn wasm_write_memory_bytes(&mut self, address: usize, bytes: Vec<u8>) -> Result<(), JsValue> {
         match self.memory.write_bytes(address, &bytes) {
             Ok(_) => Ok(()),
@@ -1867,9 +3629,46 @@ impl Interpreter {
     pub fn wasm_get_pc(&self) -> usize {
         self.get_pc()
     }
+    pub fn wasm_get_cycles(&self) -> u64 {
+        self.cycles
+    }
+    #[cfg(feature = "wasm")]
+    pub fn wasm_get_timing_table(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(self.timing).unwrap()
+    }
+    pub fn wasm_get_last_instruction_cycles(&self) -> u32 {
+        self.last_cycles
+    }
+    #[cfg(feature = "wasm")]
+    pub fn wasm_get_line_cycles(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.get_line_cycles()).unwrap()
+    }
+    #[cfg(feature = "wasm")]
+    pub fn wasm_get_source_map(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(self.get_source_map().get_entries()).unwrap()
+    }
+    pub fn wasm_get_line_at(&self, address: usize) -> Option<usize> {
+        self.get_source_map().get_line_at(address)
+    }
+    pub fn wasm_get_addresses_of_line(&self, line_index: usize) -> Vec<usize> {
+        self.get_source_map().get_addresses_of_line(line_index)
+    }
+    #[cfg(feature = "wasm")]
+    pub fn wasm_raise_interrupt(&mut self, level: u8) -> Result<(), JsValue> {
+        self.raise_interrupt(level)
+            .map_err(|e| serde_wasm_bindgen::to_value(&e).unwrap())
+    }
+    /// Raises the level every period of cycles
+    pub fn wasm_add_periodic_interrupt(&mut self, level: u8, period: u64) {
+        self.add_interrupt_source(Box::new(PeriodicInterrupt::new(level, period)));
+    }
+    pub fn wasm_get_pending_interrupts(&self) -> u8 {
+        self.pending_interrupts
+    }
     pub fn wasm_get_sp(&self) -> usize {
         self.get_sp()
     }
+    #[cfg(feature = "wasm")]
     pub fn wasm_get_instruction_at(&self, address: usize) -> JsValue {
         match self.get_instruction_at(address) {
             Some(ins) => serde_wasm_bindgen::to_value(ins).unwrap(),
@@ -1879,24 +3678,28 @@ impl Interpreter {
     pub fn wasm_can_undo(&self) -> bool {
         self.debugger.can_undo()
     }
+    #[cfg(feature = "wasm")]
     pub fn wasm_step(&mut self) -> Result<JsValue, JsValue> {
         match self.step() {
             Ok(step) => Ok(serde_wasm_bindgen::to_value(&step).unwrap()),
             Err(e) => Err(serde_wasm_bindgen::to_value(&e).unwrap()),
         }
     }
+    #[cfg(feature = "wasm")]
     pub fn wasm_step_only_status(&mut self) -> Result<InterpreterStatus, JsValue> {
         match self.step() {
             Ok(status) => Ok(status),
             Err(e) => Err(serde_wasm_bindgen::to_value(&e).unwrap()),
         }
     }
+    #[cfg(feature = "wasm")]
     pub fn wasm_run(&mut self) -> Result<InterpreterStatus, JsValue> {
         match self.run() {
             Ok(status) => Ok(status),
             Err(e) => Err(serde_wasm_bindgen::to_value(&e).unwrap()),
         }
     }
+    #[cfg(feature = "wasm")]
     pub fn wasm_run_with_breakpoints(
         &mut self,
         breakpoint_lines: Vec<usize>,
@@ -1907,27 +3710,36 @@ impl Interpreter {
             Err(e) => Err(serde_wasm_bindgen::to_value(&e).unwrap()),
         }
     }
+    #[cfg(feature = "wasm")]
     pub fn wasm_get_call_stack(&self) -> JsValue {
         serde_wasm_bindgen::to_value(&self.get_pretty_call_stack()).unwrap()
     }
+    #[cfg(feature = "wasm")]
+    pub fn wasm_get_stack_view(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&get_stack_view(self)).unwrap()
+    }
+    #[cfg(feature = "wasm")]
     pub fn wasm_run_with_limit(&mut self, limit: usize) -> Result<InterpreterStatus, JsValue> {
         match self.run_with_limit(limit) {
             Ok(status) => Ok(status),
             Err(e) => Err(serde_wasm_bindgen::to_value(&e).unwrap()),
         }
     }
+    #[cfg(feature = "wasm")]
     pub fn wasm_get_next_instruction(&self) -> JsValue {
         match self.get_next_instruction() {
             Some(ins) => serde_wasm_bindgen::to_value(ins).unwrap(),
             None => JsValue::NULL,
         }
     }
+    #[cfg(feature = "wasm")]
     pub fn wasm_get_previous_mutations(&self) -> JsValue {
         match self.debugger.get_previous_mutations() {
             Some(m) => serde_wasm_bindgen::to_value(&m).unwrap(),
             None => JsValue::NULL,
         }
     }
+    #[cfg(feature = "wasm")]
     pub fn wasm_get_undo_history(&self, count: usize) -> JsValue {
         serde_wasm_bindgen::to_value(&self.debugger.get_last_steps(count)).unwrap()
     }
@@ -1940,12 +3752,14 @@ impl Interpreter {
     pub fn wasm_get_flags_as_number(&self) -> u16 {
         self.cpu.ccr.bits()
     }
+    #[cfg(feature = "wasm")]
     pub fn wasm_undo(&mut self) -> Result<JsValue, JsValue> {
         match self.undo() {
             Ok(step) => Ok(serde_wasm_bindgen::to_value(&step).unwrap()),
             Err(e) => Err(serde_wasm_bindgen::to_value(&e).unwrap()),
         }
     }
+    #[cfg(feature = "wasm")]
     pub fn wasm_get_last_step(&self) -> JsValue {
         match self.debugger.get_last_step() {
             Some(step) => serde_wasm_bindgen::to_value(step).unwrap(),
@@ -1961,9 +3775,11 @@ impl Interpreter {
     pub fn wasm_get_last_line_address(&self) -> usize {
         self.last_line_address
     }
+    #[cfg(feature = "wasm")]
     pub fn wasm_get_last_instruction(&self) -> JsValue {
         self.wasm_get_instruction_at(self.last_line_address)
     }
+    #[cfg(feature = "wasm")]
     pub fn wasm_get_register_value(&self, reg: JsValue, size: Size) -> Result<u32, String> {
         match serde_wasm_bindgen::from_value(reg.clone()) {
             Ok(reg) => Ok(self.get_register_value(&reg, size)),
@@ -1973,6 +3789,7 @@ impl Interpreter {
             )),
         }
     }
+    #[cfg(feature = "wasm")]
     pub fn wasm_set_register_value(
         &mut self,
         reg: JsValue,
@@ -1996,6 +3813,7 @@ impl Interpreter {
     pub fn wasm_has_terminated(&self) -> bool {
         self.has_terminated()
     }
+    #[cfg(feature = "wasm")]
     pub fn wasm_get_current_interrupt(&self) -> Result<JsValue, String> {
         match &self.get_current_interrupt() {
             Ok(interrupt) => match serde_wasm_bindgen::to_value(interrupt) {
@@ -2005,6 +3823,7 @@ impl Interpreter {
             Err(_) => Ok(JsValue::NULL),
         }
     }
+    #[cfg(feature = "wasm")]
     pub fn wasm_answer_interrupt(&mut self, value: JsValue) -> Result<(), String> {
         match serde_wasm_bindgen::from_value(value.clone()) {
             Ok(answer) => self.answer_interrupt(answer).unwrap(),
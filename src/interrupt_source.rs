@@ -0,0 +1,48 @@
+/*
+    Hardware interrupts, raised by the devices of the board on the IPL lines with a level from 1 to 7.
+    A source is polled after every instruction with the cycles run since the start, the levels it requests stay
+    pending until the cpu takes them, hosts can also raise a level directly with Interpreter::raise_interrupt.
+    Between two instructions the highest pending level is taken when it is above the interrupt mask of the SR,
+    level 7 is not maskable. The interrupt uses the autovector of its level, vector 24 + level, the frame is the
+    one of the other exceptions with the PC of the next instruction, then the mask is raised to the level so
+    only higher levels can interrupt the handler until its RTE restores the SR.
+    Undoing the instruction after which an interrupt was taken undoes the interrupt too, it isn't raised again
+*/
+
+pub trait InterruptSource {
+    /// The level to raise, from 1 to 7, given the cycles run since the start of the program
+    fn poll(&mut self, cycles: u64) -> Option<u8>;
+}
+
+/// Raises the level every period of cycles, like the vertical blank of a display or a timer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeriodicInterrupt {
+    pub level: u8,
+    pub period: u64,
+    next: u64,
+}
+
+impl PeriodicInterrupt {
+    pub fn new(level: u8, period: u64) -> PeriodicInterrupt {
+        PeriodicInterrupt {
+            level,
+            period,
+            next: period,
+        }
+    }
+    /// A vertical blank of a 50Hz display on a 68000 clocked at the frequency, in Hz
+    pub fn vertical_blank(level: u8, frequency: u64) -> PeriodicInterrupt {
+        PeriodicInterrupt::new(level, frequency / 50)
+    }
+}
+
+impl InterruptSource for PeriodicInterrupt {
+    fn poll(&mut self, cycles: u64) -> Option<u8> {
+        if self.period == 0 || cycles < self.next {
+            return None;
+        }
+        //a long instruction can skip several periods, they are raised once like on the real line
+        self.next += (cycles - self.next) / self.period * self.period + self.period;
+        Some(self.level)
+    }
+}
@@ -0,0 +1,120 @@
+use serde::{Deserialize, Serialize};
+
+use crate::lexer::{LexedLine, Lexer, ParsedLine};
+
+/*
+    Stable JSON representation of a lexed program, for tools that don't link the crate.
+    The document looks like:
+    {
+        "version": 1,
+        "lines": [{
+            "parsed": LexedLine,        same shape as the ParsedLine type in ts_types
+            "line": "    move.l d0, d1",
+            "line_index": 0,
+            "span": { "line": 0, "start": 4, "end": 17 }
+        }],
+        "symbols": [{ "name": "loop", "kind": "Label", "line_index": 3, "value": null }]
+    }
+    Span columns are in characters, "start" is the first non whitespace character and "end" is exclusive.
+    The version is increased whenever a field is renamed or removed, new fields can be added in the same version
+*/
+pub const PROGRAM_JSON_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProgramJson {
+    pub version: u32,
+    pub lines: Vec<JsonLine>,
+    pub symbols: Vec<JsonSymbol>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JsonLine {
+    #[serde(flatten)]
+    pub line: ParsedLine,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Span {
+    pub line: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum SymbolKind {
+    Label,
+    Equ,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JsonSymbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub line_index: usize,
+    /// The replacement of an equ, labels don't have an address until the program is compiled
+    pub value: Option<String>,
+}
+
+fn get_line_span(line: &ParsedLine) -> Span {
+    let start = line.line.chars().take_while(|c| c.is_whitespace()).count();
+    let end = line.line.trim_end().chars().count();
+    Span {
+        line: line.line_index,
+        start: start.min(end),
+        end,
+    }
+}
+
+impl ProgramJson {
+    pub fn new(lines: &[ParsedLine]) -> Self {
+        let symbols = lines
+            .iter()
+            .filter_map(|line| match &line.parsed {
+                LexedLine::Label { name, .. } => Some(JsonSymbol {
+                    name: name.clone(),
+                    kind: SymbolKind::Label,
+                    line_index: line.line_index,
+                    value: None,
+                }),
+                //the equ replacement is also applied to the args, so the name is taken from the source line
+                LexedLine::Directive { name, args, .. } if name == "equ" => Some(JsonSymbol {
+                    name: line.line.split_whitespace().next().unwrap_or_default().to_string(),
+                    kind: SymbolKind::Equ,
+                    line_index: line.line_index,
+                    value: args.get(2..).map(|value| value.join(" ")),
+                }),
+                _ => None,
+            })
+            .collect();
+        Self {
+            version: PROGRAM_JSON_VERSION,
+            lines: lines
+                .iter()
+                .map(|line| JsonLine {
+                    line: line.clone(),
+                    span: get_line_span(line),
+                })
+                .collect(),
+            symbols,
+        }
+    }
+}
+
+impl Lexer {
+    pub fn to_json(&self) -> Result<String, String> {
+        serde_json::to_string(&ProgramJson::new(self.lines())).map_err(|e| e.to_string())
+    }
+    pub fn from_json(json: &str) -> Result<Lexer, String> {
+        let program: ProgramJson = serde_json::from_str(json).map_err(|e| e.to_string())?;
+        if program.version != PROGRAM_JSON_VERSION {
+            return Err(format!(
+                "Unsupported program version {}, expected {}",
+                program.version, PROGRAM_JSON_VERSION
+            ));
+        }
+        let mut lexer = Lexer::new();
+        lexer.set_lines(program.lines.into_iter().map(|l| l.line).collect());
+        Ok(lexer)
+    }
+}
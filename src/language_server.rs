@@ -0,0 +1,627 @@
+use std::{collections::HashMap, error::Error};
+
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::{
+    notification::{
+        DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, Notification as _,
+        PublishDiagnostics,
+    },
+    request::{
+        CodeActionRequest, Completion, DocumentSymbolRequest, FoldingRangeRequest, GotoDefinition, HoverRequest, InlayHintRequest, References, SignatureHelpRequest, Request as _,
+        SemanticTokensFullDeltaRequest, SemanticTokensFullRequest,
+    },
+    CodeAction, CodeActionKind as LspCodeActionKind, CodeActionOrCommand, CodeActionProviderCapability,
+    CompletionItem, CompletionItemKind, CompletionOptions, Diagnostic, DiagnosticRelatedInformation, DiagnosticSeverity,
+    Documentation, DocumentSymbol, NumberOrString, FoldingRange, FoldingRangeKind,
+    FoldingRangeProviderCapability, GotoDefinitionResponse, Hover, HoverContents, HoverProviderCapability,
+    InitializeParams, InlayHint, InlayHintKind as LspInlayHintKind, InlayHintLabel, InsertTextFormat, Location, MarkupContent, MarkupKind, OneOf, Position,
+    PublishDiagnosticsParams, Range, SemanticToken, SemanticTokenModifier, SemanticTokenType,
+    SemanticTokens, SemanticTokensDelta, SemanticTokensEdit, SemanticTokensFullDeltaResult,
+    SemanticTokensFullOptions, SemanticTokensLegend, SemanticTokensOptions,
+    SemanticTokensServerCapabilities, ServerCapabilities, SignatureHelp, SignatureHelpOptions, SignatureInformation,
+    ParameterInformation, ParameterLabel, SymbolKind as LspSymbolKind,
+    TextDocumentSyncCapability, TextDocumentSyncKind, TextEdit, Url, WorkspaceEdit,
+};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{
+    code_actions::{get_code_actions, CodeActionKind},
+    completion::{get_completions, get_register_description, CompletionKind},
+    constants::{DIRECTIVE_DESCRIPTIONS, INSTRUCTIONS},
+    cpu_model::CpuModel,
+    diagnostic::Severity,
+    folding::{get_folding_ranges, FoldingKind},
+    inlay_hints::{get_inlay_hints, InlayHintKind},
+    instruction_info::InstructionInfo,
+    json::{JsonSymbol, ProgramJson, SymbolKind},
+    outline::{get_outline, OutlineKind, OutlineSymbol},
+    references::{find_definition, find_references},
+    signature_help::get_signature_help,
+    semantic_tokens::{
+        encode_semantic_tokens, get_semantic_tokens_edit, SEMANTIC_TOKEN_MODIFIERS, SEMANTIC_TOKEN_TYPES,
+    },
+    S68k,
+};
+
+/*
+    Language server for editors, it keeps every open document lexed and answers with
+    the diagnostics of the semantic checker, hovers, go to definition, references, document symbols, completions,
+    folding ranges, inlay hints, signature help, code actions and semantic tokens, the last tokens sent for every document are kept to answer the delta requests.
+    The analysis is done by LanguageServer, run() only moves the messages between it and the client.
+    The CPU model can be chosen by the client with the initialization options: { "cpuModel": "68020" }
+    Only compiled with the "lsp" feature, the s68k-lsp binary talks over stdio
+*/
+
+pub struct LanguageServer {
+    documents: HashMap<Url, S68k>,
+    cpu_model: CpuModel,
+    /// Result id and encoded data of the last semantic tokens sent for a document
+    semantic_tokens: HashMap<Url, (String, Vec<u32>)>,
+    next_result_id: usize,
+}
+
+fn line_range(line_index: usize, start: usize, end: usize) -> Range {
+    Range::new(
+        Position::new(line_index as u32, start as u32),
+        Position::new(line_index as u32, end as u32),
+    )
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '.' || c == '$'
+}
+
+/// Returns the word under the cursor, columns are counted in characters
+fn get_word_at(line: &str, column: usize) -> Option<String> {
+    let chars: Vec<char> = line.chars().collect();
+    let column = column.min(chars.len());
+    let start = chars[..column]
+        .iter()
+        .rposition(|c| !is_word_char(*c))
+        .map(|i| i + 1)
+        .unwrap_or(0);
+    let end = chars[column..]
+        .iter()
+        .position(|c| !is_word_char(*c))
+        .map(|i| i + column)
+        .unwrap_or(chars.len());
+    match start < end {
+        true => Some(chars[start..end].iter().collect()),
+        false => None,
+    }
+}
+
+impl LanguageServer {
+    pub fn new(cpu_model: CpuModel) -> Self {
+        Self {
+            documents: HashMap::new(),
+            cpu_model,
+            semantic_tokens: HashMap::new(),
+            next_result_id: 0,
+        }
+    }
+    pub fn set_document(&mut self, uri: Url, code: String) {
+        let mut s68k = S68k::new(code);
+        s68k.set_cpu_model(self.cpu_model);
+        self.documents.insert(uri, s68k);
+    }
+    pub fn remove_document(&mut self, uri: &Url) {
+        self.documents.remove(uri);
+        self.semantic_tokens.remove(uri);
+    }
+    fn get_symbols(&self, uri: &Url) -> Vec<JsonSymbol> {
+        match self.documents.get(uri) {
+            Some(s68k) => ProgramJson::new(s68k.get_lexed_lines()).symbols,
+            None => vec![],
+        }
+    }
+    fn get_line(&self, uri: &Url, line_index: usize) -> Option<&str> {
+        self.documents
+            .get(uri)
+            .and_then(|s68k| s68k.get_code().lines().nth(line_index))
+    }
+    fn find_symbol(&self, uri: &Url, position: Position) -> Option<JsonSymbol> {
+        let line = self.get_line(uri, position.line as usize)?;
+        let word = get_word_at(line, position.character as usize)?;
+        let symbols = self.get_symbols(uri);
+        //the word can include a size suffix, like "label.w" in an absolute address
+        let base = word.split('.').next().unwrap_or_default().to_string();
+        symbols
+            .iter()
+            .find(|s| s.name == word)
+            .or_else(|| symbols.iter().find(|s| s.name == base))
+            .cloned()
+    }
+    pub fn get_diagnostics(&self, uri: &Url) -> Vec<Diagnostic> {
+        let s68k = match self.documents.get(uri) {
+            Some(s68k) => s68k,
+            None => return vec![],
+        };
+        s68k.get_diagnostics()
+            .iter()
+            .map(|d| {
+                let span = d.span.unwrap_or_default();
+                Diagnostic {
+                    range: line_range(span.line_index, span.start, span.end),
+                    severity: Some(match d.severity {
+                        Severity::Error => DiagnosticSeverity::ERROR,
+                        Severity::Warning => DiagnosticSeverity::WARNING,
+                        Severity::Info => DiagnosticSeverity::INFORMATION,
+                    }),
+                    code: Some(NumberOrString::String(d.get_code().to_string())),
+                    source: Some("s68k".to_string()),
+                    message: d.message.clone(),
+                    related_information: match &d.related[..] {
+                        [] => None,
+                        related => Some(
+                            related
+                                .iter()
+                                .map(|span| DiagnosticRelatedInformation {
+                                    location: Location::new(
+                                        uri.clone(),
+                                        line_range(span.line_index, span.start, span.end),
+                                    ),
+                                    message: span.message.clone(),
+                                })
+                                .collect(),
+                        ),
+                    },
+                    ..Default::default()
+                }
+            })
+            .collect()
+    }
+    pub fn hover(&self, uri: &Url, position: Position) -> Option<Hover> {
+        let line = self.get_line(uri, position.line as usize)?;
+        let word = get_word_at(line, position.character as usize)?;
+        let name = word.split('.').next().unwrap_or_default().to_lowercase();
+        let content = match self.find_symbol(uri, position) {
+            Some(symbol) => match symbol.kind {
+                SymbolKind::Label => format!(
+                    "label `{}`, defined on line {}",
+                    symbol.name,
+                    symbol.line_index + 1
+                ),
+                SymbolKind::Equ => format!(
+                    "`{}` equ `{}`",
+                    symbol.name,
+                    symbol.value.unwrap_or_default()
+                ),
+            },
+            None => match DIRECTIVE_DESCRIPTIONS.iter().find(|(d, _)| *d == name) {
+                Some((directive, description)) => format!("`{}` directive\n\n{}", directive, description),
+                None => match (INSTRUCTIONS.contains(&name.as_str()), get_register_description(&name)) {
+                    (true, _) => self.get_instruction_hover(&name),
+                    (false, Some(description)) => description,
+                    (false, None) => return None,
+                },
+            },
+        };
+        Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: content,
+            }),
+            range: None,
+        })
+    }
+    fn get_instruction_hover(&self, name: &str) -> String {
+        let mut content = match InstructionInfo::lookup_for_model(name, self.cpu_model) {
+            Some(info) => info.to_markdown(),
+            None => format!("`{}` instruction", name),
+        };
+        if let Err(e) = self.cpu_model.verify_instruction(name) {
+            content.push_str(&format!("\n\n{}", e));
+        }
+        content
+    }
+    pub fn goto_definition(&self, uri: &Url, position: Position) -> Option<Location> {
+        let span = find_definition(self.documents.get(uri)?, position.line as usize, position.character as usize)?;
+        Some(Location::new(uri.clone(), line_range(span.line, span.start, span.end)))
+    }
+    pub fn references(&self, uri: &Url, position: Position, include_declaration: bool) -> Vec<Location> {
+        let s68k = match self.documents.get(uri) {
+            Some(s68k) => s68k,
+            None => return vec![],
+        };
+        let symbol = match find_references(s68k, position.line as usize, position.character as usize) {
+            Some(symbol) => symbol,
+            None => return vec![],
+        };
+        let definition = symbol.definition.filter(|_| include_declaration);
+        definition
+            .iter()
+            .chain(symbol.references.iter())
+            .map(|span| Location::new(uri.clone(), line_range(span.line, span.start, span.end)))
+            .collect()
+    }
+    pub fn document_symbols(&self, uri: &Url) -> Vec<DocumentSymbol> {
+        match self.documents.get(uri) {
+            Some(s68k) => get_outline(s68k).iter().map(to_document_symbol).collect(),
+            None => vec![],
+        }
+    }
+    pub fn folding_ranges(&self, uri: &Url) -> Vec<FoldingRange> {
+        let s68k = match self.documents.get(uri) {
+            Some(s68k) => s68k,
+            None => return vec![],
+        };
+        get_folding_ranges(s68k)
+            .into_iter()
+            .map(|range| FoldingRange {
+                start_line: range.start_line as u32,
+                end_line: range.end_line as u32,
+                kind: Some(FoldingRangeKind::Region),
+                collapsed_text: match range.kind {
+                    FoldingKind::Subroutine => None,
+                    FoldingKind::Data => Some("data".to_string()),
+                    FoldingKind::Macro => Some("macro".to_string()),
+                    FoldingKind::Repeat => Some("rept".to_string()),
+                    FoldingKind::Conditional => Some("if".to_string()),
+                },
+                ..Default::default()
+            })
+            .collect()
+    }
+    /// Hints on the lines of the range, the end line included
+    pub fn inlay_hints(&self, uri: &Url, range: Range) -> Vec<InlayHint> {
+        let s68k = match self.documents.get(uri) {
+            Some(s68k) => s68k,
+            None => return vec![],
+        };
+        get_inlay_hints(s68k)
+            .into_iter()
+            .filter(|hint| (range.start.line..=range.end.line).contains(&(hint.line as u32)))
+            .map(|hint| InlayHint {
+                position: Position::new(hint.line as u32, hint.column as u32),
+                label: InlayHintLabel::String(hint.label),
+                kind: match hint.kind {
+                    InlayHintKind::Value => Some(LspInlayHintKind::PARAMETER),
+                    _ => Some(LspInlayHintKind::TYPE),
+                },
+                text_edits: None,
+                tooltip: None,
+                padding_left: Some(true),
+                padding_right: None,
+                data: None,
+            })
+            .collect()
+    }
+    pub fn signature_help(&self, uri: &Url, position: Position) -> Option<SignatureHelp> {
+        let s68k = self.documents.get(uri)?;
+        let help = get_signature_help(s68k, position.line as usize, position.character as usize)?;
+        let parameters = help
+            .parameters
+            .iter()
+            .map(|parameter| ParameterInformation {
+                label: ParameterLabel::LabelOffsets([parameter.start as u32, parameter.end as u32]),
+                documentation: Some(Documentation::String(parameter.modes.join(" "))),
+            })
+            .collect();
+        Some(SignatureHelp {
+            signatures: vec![SignatureInformation {
+                label: help.label,
+                documentation: help.description.map(|d| Documentation::String(d.to_string())),
+                parameters: Some(parameters),
+                active_parameter: help.active_parameter.map(|p| p as u32),
+            }],
+            active_signature: Some(0),
+            active_parameter: help.active_parameter.map(|p| p as u32),
+        })
+    }
+    /// Fixes and refactors of the lines of the range, a fix is tied to the diagnostic of the client it fixes
+    pub fn code_actions(&self, uri: &Url, range: Range, diagnostics: &[Diagnostic]) -> Vec<CodeActionOrCommand> {
+        let s68k = match self.documents.get(uri) {
+            Some(s68k) => s68k,
+            None => return vec![],
+        };
+        (range.start.line..=range.end.line)
+            .flat_map(|line| get_code_actions(s68k, line as usize))
+            .map(|action| {
+                let edits = action
+                    .edits
+                    .iter()
+                    .map(|edit| TextEdit::new(line_range(edit.line, edit.start, edit.end), edit.new_text.clone()))
+                    .collect();
+                let fixed: Vec<Diagnostic> = diagnostics
+                    .iter()
+                    .filter(|d| {
+                        Some(&d.message) == action.diagnostic.as_ref()
+                            && action.edits.iter().any(|edit| edit.line as u32 == d.range.start.line)
+                    })
+                    .cloned()
+                    .collect();
+                CodeActionOrCommand::CodeAction(CodeAction {
+                    title: action.title,
+                    kind: Some(match action.kind {
+                        CodeActionKind::QuickFix => LspCodeActionKind::QUICKFIX,
+                        CodeActionKind::Refactor => LspCodeActionKind::REFACTOR_REWRITE,
+                    }),
+                    diagnostics: Some(fixed).filter(|fixed| !fixed.is_empty()),
+                    edit: Some(WorkspaceEdit {
+                        changes: Some(HashMap::from([(uri.clone(), edits)])),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                })
+            })
+            .collect()
+    }
+    /// Mnemonics and directives at the start of a line, legal addressing modes, registers, labels and equs in the operands
+    pub fn completion(&self, uri: &Url, position: Position) -> Vec<CompletionItem> {
+        let s68k = match self.documents.get(uri) {
+            Some(s68k) => s68k,
+            None => return vec![],
+        };
+        get_completions(s68k, position.line as usize, position.character as usize)
+            .into_iter()
+            .map(|candidate| CompletionItem {
+                label: candidate.label,
+                kind: Some(match candidate.kind {
+                    CompletionKind::Instruction | CompletionKind::Directive => CompletionItemKind::KEYWORD,
+                    CompletionKind::AddressingMode => CompletionItemKind::SNIPPET,
+                    CompletionKind::Register => CompletionItemKind::VARIABLE,
+                    CompletionKind::Label => CompletionItemKind::REFERENCE,
+                    CompletionKind::Constant => CompletionItemKind::CONSTANT,
+                }),
+                detail: candidate.detail,
+                documentation: candidate.documentation.map(|value| {
+                    Documentation::MarkupContent(MarkupContent {
+                        kind: MarkupKind::Markdown,
+                        value,
+                    })
+                }),
+                insert_text_format: candidate.snippet.as_ref().map(|_| InsertTextFormat::SNIPPET),
+                insert_text: candidate.snippet,
+                ..Default::default()
+            })
+            .collect()
+    }
+    /// Encodes the tokens of the document and remembers them under a new result id, returning the previous ones
+    fn store_semantic_tokens(&mut self, uri: &Url) -> Option<(String, Vec<u32>)> {
+        let data = encode_semantic_tokens(&self.documents.get(uri)?.get_semantic_tokens());
+        self.next_result_id += 1;
+        let result_id = self.next_result_id.to_string();
+        self.semantic_tokens.insert(uri.clone(), (result_id, data))
+    }
+    pub fn semantic_tokens_full(&mut self, uri: &Url) -> Option<SemanticTokens> {
+        self.store_semantic_tokens(uri);
+        let (result_id, data) = self.semantic_tokens.get(uri)?;
+        Some(SemanticTokens {
+            result_id: Some(result_id.clone()),
+            data: to_lsp_tokens(data),
+        })
+    }
+    /// Answers with the edits from the previous result, or with all the tokens if it is not the last one sent
+    pub fn semantic_tokens_delta(&mut self, uri: &Url, previous_result_id: &str) -> Option<SemanticTokensFullDeltaResult> {
+        let previous = self.store_semantic_tokens(uri);
+        let (result_id, data) = self.semantic_tokens.get(uri)?;
+        match previous {
+            Some((previous_id, previous_data)) if previous_id == previous_result_id => {
+                let edits = get_semantic_tokens_edit(&previous_data, data)
+                    .map(|edit| SemanticTokensEdit {
+                        start: edit.start as u32,
+                        delete_count: edit.delete_count as u32,
+                        data: Some(to_lsp_tokens(&edit.data)),
+                    })
+                    .into_iter()
+                    .collect();
+                Some(SemanticTokensFullDeltaResult::TokensDelta(SemanticTokensDelta {
+                    result_id: Some(result_id.clone()),
+                    edits,
+                }))
+            }
+            _ => Some(SemanticTokensFullDeltaResult::Tokens(SemanticTokens {
+                result_id: Some(result_id.clone()),
+                data: to_lsp_tokens(data),
+            })),
+        }
+    }
+    fn handle_request(&mut self, request: Request) -> Response {
+        let id = request.id.clone();
+        let result = match request.method.as_str() {
+            HoverRequest::METHOD => parse_params::<lsp_types::HoverParams>(request.params).and_then(|p| {
+                let position = p.text_document_position_params;
+                to_json(&self.hover(&position.text_document.uri, position.position))
+            }),
+            GotoDefinition::METHOD => parse_params::<lsp_types::GotoDefinitionParams>(request.params).and_then(|p| {
+                let position = p.text_document_position_params;
+                to_json(
+                    &self
+                        .goto_definition(&position.text_document.uri, position.position)
+                        .map(GotoDefinitionResponse::Scalar),
+                )
+            }),
+            References::METHOD => parse_params::<lsp_types::ReferenceParams>(request.params).and_then(|p| {
+                let position = p.text_document_position;
+                to_json(&self.references(
+                    &position.text_document.uri,
+                    position.position,
+                    p.context.include_declaration,
+                ))
+            }),
+            DocumentSymbolRequest::METHOD => parse_params::<lsp_types::DocumentSymbolParams>(request.params)
+                .and_then(|p| to_json(&self.document_symbols(&p.text_document.uri))),
+            FoldingRangeRequest::METHOD => parse_params::<lsp_types::FoldingRangeParams>(request.params)
+                .and_then(|p| to_json(&self.folding_ranges(&p.text_document.uri))),
+            InlayHintRequest::METHOD => parse_params::<lsp_types::InlayHintParams>(request.params)
+                .and_then(|p| to_json(&self.inlay_hints(&p.text_document.uri, p.range))),
+            SignatureHelpRequest::METHOD => parse_params::<lsp_types::SignatureHelpParams>(request.params).and_then(|p| {
+                let position = p.text_document_position_params;
+                to_json(&self.signature_help(&position.text_document.uri, position.position))
+            }),
+            CodeActionRequest::METHOD => parse_params::<lsp_types::CodeActionParams>(request.params).and_then(|p| {
+                to_json(&self.code_actions(&p.text_document.uri, p.range, &p.context.diagnostics))
+            }),
+            Completion::METHOD => parse_params::<lsp_types::CompletionParams>(request.params).and_then(|p| {
+                let position = p.text_document_position;
+                to_json(&self.completion(&position.text_document.uri, position.position))
+            }),
+            SemanticTokensFullRequest::METHOD => parse_params::<lsp_types::SemanticTokensParams>(request.params)
+                .and_then(|p| to_json(&self.semantic_tokens_full(&p.text_document.uri))),
+            SemanticTokensFullDeltaRequest::METHOD => parse_params::<lsp_types::SemanticTokensDeltaParams>(request.params)
+                .and_then(|p| to_json(&self.semantic_tokens_delta(&p.text_document.uri, &p.previous_result_id))),
+            method => {
+                return Response::new_err(
+                    id,
+                    ErrorCode::MethodNotFound as i32,
+                    format!("Unknown method {}", method),
+                )
+            }
+        };
+        match result {
+            Ok(result) => Response::new_ok(id, result),
+            Err(e) => Response::new_err(id, ErrorCode::InvalidParams as i32, e),
+        }
+    }
+    /// Returns the document whose diagnostics need to be published again
+    fn handle_notification(&mut self, notification: Notification) -> Result<Option<Url>, String> {
+        match notification.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let params: lsp_types::DidOpenTextDocumentParams = parse_params(notification.params)?;
+                let uri = params.text_document.uri;
+                self.set_document(uri.clone(), params.text_document.text);
+                Ok(Some(uri))
+            }
+            DidChangeTextDocument::METHOD => {
+                //the sync is full, so the last change is the whole document
+                let params: lsp_types::DidChangeTextDocumentParams = parse_params(notification.params)?;
+                let uri = params.text_document.uri;
+                match params.content_changes.into_iter().last() {
+                    Some(change) => {
+                        self.set_document(uri.clone(), change.text);
+                        Ok(Some(uri))
+                    }
+                    None => Ok(None),
+                }
+            }
+            DidCloseTextDocument::METHOD => {
+                let params: lsp_types::DidCloseTextDocumentParams = parse_params(notification.params)?;
+                self.remove_document(&params.text_document.uri);
+                Ok(Some(params.text_document.uri))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+fn parse_params<T: serde::de::DeserializeOwned>(params: Value) -> Result<T, String> {
+    serde_json::from_value(params).map_err(|e| e.to_string())
+}
+
+/// Splits the encoded tokens in groups of 5, which lsp_types serializes back as a flat array
+fn to_lsp_tokens(data: &[u32]) -> Vec<SemanticToken> {
+    data.chunks_exact(5)
+        .map(|token| SemanticToken {
+            delta_line: token[0],
+            delta_start: token[1],
+            length: token[2],
+            token_type: token[3],
+            token_modifiers_bitset: token[4],
+        })
+        .collect()
+}
+
+#[allow(deprecated)]
+fn to_document_symbol(symbol: &OutlineSymbol) -> DocumentSymbol {
+    let range = &symbol.range;
+    let selection = &symbol.selection_range;
+    DocumentSymbol {
+        name: symbol.name.clone(),
+        detail: symbol.detail.clone(),
+        kind: match symbol.kind {
+            OutlineKind::Label => LspSymbolKind::FUNCTION,
+            OutlineKind::Constant => LspSymbolKind::CONSTANT,
+            OutlineKind::Macro => LspSymbolKind::OPERATOR,
+            OutlineKind::Section => LspSymbolKind::NAMESPACE,
+        },
+        tags: None,
+        deprecated: None,
+        range: Range::new(
+            Position::new(range.start_line as u32, range.start as u32),
+            Position::new(range.end_line as u32, range.end as u32),
+        ),
+        selection_range: line_range(selection.line, selection.start, selection.end),
+        children: match symbol.children.is_empty() {
+            true => None,
+            false => Some(symbol.children.iter().map(to_document_symbol).collect()),
+        },
+    }
+}
+
+fn to_json<T: Serialize>(value: &T) -> Result<Value, String> {
+    serde_json::to_value(value).map_err(|e| e.to_string())
+}
+
+pub fn get_server_capabilities() -> ServerCapabilities {
+    ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        definition_provider: Some(OneOf::Left(true)),
+        references_provider: Some(OneOf::Left(true)),
+        document_symbol_provider: Some(OneOf::Left(true)),
+        completion_provider: Some(CompletionOptions::default()),
+        folding_range_provider: Some(FoldingRangeProviderCapability::Simple(true)),
+        inlay_hint_provider: Some(OneOf::Left(true)),
+        code_action_provider: Some(CodeActionProviderCapability::Simple(true)),
+        signature_help_provider: Some(SignatureHelpOptions {
+            trigger_characters: Some(vec![" ".to_string(), ",".to_string()]),
+            ..Default::default()
+        }),
+        semantic_tokens_provider: Some(SemanticTokensServerCapabilities::SemanticTokensOptions(
+            SemanticTokensOptions {
+                legend: SemanticTokensLegend {
+                    token_types: SEMANTIC_TOKEN_TYPES
+                        .iter()
+                        .map(|t| SemanticTokenType::new(t.get_name()))
+                        .collect(),
+                    token_modifiers: SEMANTIC_TOKEN_MODIFIERS
+                        .iter()
+                        .map(|m| SemanticTokenModifier::new(m))
+                        .collect(),
+                },
+                full: Some(SemanticTokensFullOptions::Delta { delta: Some(true) }),
+                ..Default::default()
+            },
+        )),
+        ..Default::default()
+    }
+}
+
+/// Initializes the connection and answers the client until it shuts down
+pub fn run(connection: &Connection) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let params = connection.initialize(serde_json::to_value(get_server_capabilities())?)?;
+    let params: InitializeParams = serde_json::from_value(params)?;
+    let cpu_model = match params
+        .initialization_options
+        .as_ref()
+        .and_then(|options| options.get("cpuModel"))
+        .and_then(|model| model.as_str())
+    {
+        Some(model) => model.parse()?,
+        None => CpuModel::M68000,
+    };
+    let mut server = LanguageServer::new(cpu_model);
+    for message in &connection.receiver {
+        match message {
+            Message::Request(request) => {
+                if connection.handle_shutdown(&request)? {
+                    return Ok(());
+                }
+                connection.sender.send(Message::Response(server.handle_request(request)))?;
+            }
+            Message::Notification(notification) => {
+                if let Some(uri) = server.handle_notification(notification)? {
+                    let params = PublishDiagnosticsParams::new(uri.clone(), server.get_diagnostics(&uri), None);
+                    connection.sender.send(Message::Notification(Notification::new(
+                        PublishDiagnostics::METHOD.to_string(),
+                        params,
+                    )))?;
+                }
+            }
+            Message::Response(_) => {}
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,93 @@
+use alloc::{format, string::{String, ToString}, vec, vec::Vec};
+use core::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::constants::{BRANCHES, DECREMENT_BRANCHES, INSTRUCTIONS, SETS};
+
+/*
+    The instructions a lesson lets the students use, like "week 3: move, add, sub, branches".
+    The semantic checker reports every other instruction and the interpreter refuses to run them with
+    RuntimeError::UnsupportedInstruction, so an assignment can't be solved with what was not taught yet.
+    Directives are always allowed. Besides the mnemonics a profile can have the groups:
+        branches        bra and the Bcc family
+        loops           the DBcc family, dbra included
+        sets            the Scc family
+        subroutines     bsr, jsr and rts
+*/
+
+const GROUPS: &[&str] = &["branches", "loops", "sets", "subroutines"];
+
+fn get_group(name: &str) -> Option<Vec<&'static str>> {
+    match name {
+        "branches" => Some(BRANCHES.iter().copied().chain(["bra"]).collect()),
+        "loops" => Some(DECREMENT_BRANCHES.to_vec()),
+        "sets" => Some(SETS.to_vec()),
+        "subroutines" => Some(vec!["bsr", "jsr", "rts"]),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LessonProfile {
+    pub name: String,
+    /// Mnemonics and groups, lowercase
+    pub instructions: Vec<String>,
+}
+
+impl LessonProfile {
+    /// Fails if one of the instructions is not a mnemonic or a group
+    pub fn new(name: &str, instructions: &[&str]) -> Result<Self, String> {
+        let instructions: Vec<String> = instructions.iter().map(|i| i.trim().to_lowercase()).collect();
+        for instruction in &instructions {
+            if !INSTRUCTIONS.contains(&instruction.as_str()) && !GROUPS.contains(&instruction.as_str()) {
+                return Err(format!(
+                    "Unknown instruction \"{}\" in the lesson profile, expected a mnemonic or one of {}",
+                    instruction,
+                    GROUPS.join(", ")
+                ));
+            }
+        }
+        Ok(Self {
+            name: name.trim().to_string(),
+            instructions,
+        })
+    }
+    pub fn is_allowed(&self, mnemonic: &str) -> bool {
+        let mnemonic = mnemonic.to_lowercase();
+        self.instructions.iter().any(|instruction| match get_group(instruction) {
+            Some(group) => group.contains(&mnemonic.as_str()),
+            None => *instruction == mnemonic,
+        })
+    }
+    pub fn verify_instruction(&self, mnemonic: &str) -> Result<(), String> {
+        match self.is_allowed(mnemonic) {
+            true => Ok(()),
+            false => Err(format!(
+                "Instruction \"{}\" is not allowed in {}, the allowed instructions are {}",
+                mnemonic.to_lowercase(),
+                self.name,
+                self.instructions.join(", ")
+            )),
+        }
+    }
+}
+
+impl FromStr for LessonProfile {
+    type Err = String;
+    /// Parses "name: move, add, branches", without a name the profile is called "this lesson"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, list) = match s.split_once(':') {
+            Some((name, list)) => (name, list),
+            None => ("this lesson", s),
+        };
+        let instructions: Vec<&str> = list
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|i| !i.is_empty())
+            .collect();
+        if instructions.is_empty() {
+            return Err("The lesson profile doesn't allow any instruction".to_string());
+        }
+        LessonProfile::new(name, &instructions)
+    }
+}
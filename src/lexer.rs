@@ -1,40 +1,124 @@
 
 
-use bitflags::bitflags;
-use regex::Regex;
+use alloc::{borrow::ToOwned, boxed::Box, format, string::{String, ToString}, vec, vec::Vec};
+#[cfg(feature = "std")]
+use alloc::borrow::Cow;
+#[cfg(feature = "std")]
+use std::io::{self, BufRead};
+use core::{error::Error, fmt, ops::Range};
+
 use serde::{Deserialize, Serialize};
+#[cfg(feature = "wasm")]
 use wasm_bindgen::prelude::wasm_bindgen;
 
-//TODO remake everything with an actual lexer
-use crate::constants::{COMMENT_1, COMMENT_2, EQU};
+use crate::constants::{COMMENT_1, COMMENT_2, EQU, INSTRUCTIONS};
+use crate::cpu_model::CpuModel;
+use crate::expression::{evaluate, evaluate_constant, is_literal, parse_literal, ExpressionError};
+use crate::local_labels::{is_local_label, scope_local_labels, scope_local_labels_with_owner};
+use crate::conditional::{apply_conditionals, ConditionalError};
+use crate::macros::{expand_macros, ExpandedLine, MacroError, MacroOrigin};
+use crate::repeat::{expand_repetitions, RepeatError};
+use crate::utils::{is_identifier, is_register};
+use crate::symbol::{intern_lines, Interner};
+
+/// Longest an expression can get while the equs are replaced in it
+pub const MAX_EXPRESSION_LENGTH: usize = 1 << 16;
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
-#[wasm_bindgen]
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
 pub enum LexedRegisterType {
     Address,
     Data,
     SP,
+    Float,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "value")]
+pub enum LexError {
+    InvalidRegister(String),
+    InvalidRegisterRange(String),
+    InvalidMemoryIndirect(String),
+    /// The size after a register, like the .q of d0.q
+    InvalidSize(String),
+    /// The scale of an index register, like the 3 of d0*3
+    InvalidScale(String),
+    InvalidIndirect(String),
+    InvalidBitfield(String),
+    InvalidMacro(MacroError),
+    InvalidConditional(ConditionalError),
+    InvalidRepeat(RepeatError),
+    /// Where the error was found in the source, the span is the one of the operand
+    OnLine { span: TextSpan, error: Box<LexError> },
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LexError::InvalidRegister(register) => write!(f, "Invalid register type '{}'", register),
+            LexError::InvalidRegisterRange(range) => write!(f, "Invalid register range '{}'", range),
+            LexError::InvalidMemoryIndirect(message) => write!(f, "{}", message),
+            LexError::InvalidSize(operand) => write!(f, "Invalid size in '{}', expected .b, .w or .l", operand),
+            LexError::InvalidScale(operand) => write!(f, "Invalid scale in '{}'", operand),
+            LexError::InvalidIndirect(operand) => write!(f, "Invalid indirect operand '{}'", operand),
+            LexError::InvalidBitfield(operand) => write!(f, "Invalid bitfield '{}', expected <ea>{{offset:width}}", operand),
+            LexError::InvalidMacro(error) => write!(f, "{}", error),
+            LexError::InvalidConditional(error) => write!(f, "{}", error),
+            LexError::InvalidRepeat(error) => write!(f, "{}", error),
+            LexError::OnLine { span, error } => {
+                write!(f, "Error on line {}, column {}: {}", span.line_index + 1, span.start + 1, error)
+            }
+        }
+    }
+}
+
+impl Error for LexError {}
+
+impl LexError {
+    /// The error without the position in the source
+    pub fn get_cause(&self) -> &LexError {
+        match self {
+            LexError::OnLine { error, .. } => error.get_cause(),
+            _ => self,
+        }
+    }
+    pub fn get_span(&self) -> Option<TextSpan> {
+        match self {
+            LexError::OnLine { span, .. } => Some(*span),
+            _ => None,
+        }
+    }
+}
+
+impl From<LexError> for String {
+    fn from(error: LexError) -> Self {
+        error.to_string()
+    }
 }
 
 impl LexedRegisterType {
-    pub fn from_string(string: &str) -> Result<LexedRegisterType, String> {
+    pub fn from_string(string: &str) -> Result<LexedRegisterType, LexError> {
         match string.chars().collect::<Vec<char>>().as_slice() {
             ['d' | 'D', num] if num.is_ascii_digit() => Ok(LexedRegisterType::Data),
             ['a' | 'A', num] if num.is_ascii_digit() => Ok(LexedRegisterType::Address),
             ['s' | 'S', 'p' | 'P'] => Ok(LexedRegisterType::SP),
-            _ => Err(format!("Invalid register type '{}'", string)),
+            ['f' | 'F', 'p' | 'P', num] if num.is_ascii_digit() => Ok(LexedRegisterType::Float),
+            _ => Err(LexError::InvalidRegister(string.to_string())),
         }
     }
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
-#[wasm_bindgen]
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
 pub enum LexedSize {
     Byte,
     Word,
     Long,
     Unspecified,
     Unknown,
+    Single,
+    Double,
+    Extended,
 }
 
 impl LexedSize {
@@ -43,6 +127,9 @@ impl LexedSize {
             LexedSize::Byte => 1,
             LexedSize::Word => 2,
             LexedSize::Long => 4,
+            LexedSize::Single => 4,
+            LexedSize::Double => 8,
+            LexedSize::Extended => 12,
             LexedSize::Unspecified => default.to_bytes(LexedSize::Unknown),
             _ => 0,
         }
@@ -58,7 +145,7 @@ impl LexedSize {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(tag = "type", content = "value")]
 pub enum LexedOperand {
     Immediate(String),
@@ -67,6 +154,15 @@ pub enum LexedOperand {
     },
     Register(LexedRegisterType, String),
     RegisterWithSize(LexedRegisterType, String, LexedSize),
+    ScaledRegister(LexedRegisterType, String, LexedSize, u8),
+    /// Dh:Dl pair used by the 64 bit multiply and divide
+    RegisterPair(String, String),
+    /// <ea>{offset:width}
+    Bitfield {
+        operand: Box<LexedOperand>,
+        offset: String,
+        width: String,
+    },
     Indirect(Box<LexedOperand>),
     IndirectDisplacement {
         offset: String,
@@ -76,6 +172,11 @@ pub enum LexedOperand {
         offset: String,
         operands: Vec<LexedOperand>,
     },
+    /// ([bd,An,Xn],od) or ([bd,An],Xn,od), every element can be omitted
+    MemoryIndirect {
+        inner: Vec<LexedOperand>,
+        outer: Vec<LexedOperand>,
+    },
     PostIndirect(Box<LexedOperand>),
     PreIndirect(Box<LexedOperand>),
     Absolute(String),
@@ -84,25 +185,135 @@ pub enum LexedOperand {
 }
 
 impl LexedOperand {
-    pub fn affects_memory(&self) -> bool {
+    /// The value of an immediate or absolute that is a single literal, like #$FF or @17, None for anything else
+    pub fn value(&self) -> Option<Result<i64, ExpressionError>> {
+        let text = match self {
+            LexedOperand::Immediate(value) => &value[1..],
+            LexedOperand::Absolute(value) => value.as_str(),
+            _ => return None,
+        };
+        match is_literal(text) {
+            true => Some(parse_literal(text).map(|literal| literal.value)),
+            false => None,
+        }
+    }
+    /// The value of an immediate that doesn't use labels, the equs are already replaced by the lexer
+    pub fn get_immediate_value(&self) -> Option<i64> {
         match self {
-            LexedOperand::Indirect(_) => true,
-            LexedOperand::IndirectDisplacement { .. } => true,
-            LexedOperand::IndirectIndex { .. } => true,
-            LexedOperand::PostIndirect(_) => true,
-            LexedOperand::PreIndirect(_) => true,
-            LexedOperand::Absolute(_) => true,
-
-            _ => false,
+            LexedOperand::Immediate(value) => evaluate_constant(value).ok(),
+            _ => None,
+        }
+    }
+    /// SR, CCR or USP, they are lexed as absolutes since they are only operands of a few instructions
+    pub fn get_special_register(&self) -> Option<String> {
+        match self {
+            LexedOperand::Absolute(name) => match name.to_lowercase().as_str() {
+                name @ ("sr" | "ccr" | "usp") => Some(name.to_string()),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+    pub fn affects_memory(&self) -> bool {
+        matches!(
+            self,
+            LexedOperand::Indirect(_)
+                | LexedOperand::IndirectDisplacement { .. }
+                | LexedOperand::IndirectIndex { .. }
+                | LexedOperand::MemoryIndirect { .. }
+                | LexedOperand::PostIndirect(_)
+                | LexedOperand::PreIndirect(_)
+                | LexedOperand::Absolute(_)
+        )
+    }
+    /**
+    Splits the elements of a memory indirect operand into its parts, the first address register
+    inside the brackets is the base, any other register is the index
+     */
+    pub fn split_memory_indirect<'a>(
+        inner: &'a [LexedOperand],
+        outer: &'a [LexedOperand],
+    ) -> Result<MemoryIndirectParts<'a>, LexError> {
+        let mut parts = MemoryIndirectParts::default();
+        for operand in inner {
+            match operand {
+                LexedOperand::Absolute(displacement) | LexedOperand::Label(displacement) => {
+                    if parts.base_displacement.is_some() || parts.base.is_some() || parts.index.is_some() {
+                        return Err(LexError::InvalidMemoryIndirect(
+                            "The base displacement must be the first element".to_string(),
+                        ));
+                    }
+                    parts.base_displacement = Some(displacement);
+                }
+                LexedOperand::Register(LexedRegisterType::Address | LexedRegisterType::SP, _)
+                    if parts.base.is_none() && parts.index.is_none() =>
+                {
+                    parts.base = Some(operand);
+                }
+                LexedOperand::Register(_, _)
+                | LexedOperand::RegisterWithSize(_, _, _)
+                | LexedOperand::ScaledRegister(_, _, _, _)
+                    if parts.index.is_none() =>
+                {
+                    parts.index = Some(operand);
+                }
+                _ => {
+                    return Err(LexError::InvalidMemoryIndirect(format!(
+                        "Invalid element in memory indirect: {:?}",
+                        operand
+                    )))
+                }
+            }
+        }
+        for operand in outer {
+            match operand {
+                LexedOperand::Absolute(displacement) | LexedOperand::Label(displacement) => {
+                    if parts.outer_displacement.is_some() {
+                        return Err(LexError::InvalidMemoryIndirect(
+                            "Only one outer displacement is allowed".to_string(),
+                        ));
+                    }
+                    parts.outer_displacement = Some(displacement);
+                }
+                LexedOperand::Register(_, _)
+                | LexedOperand::RegisterWithSize(_, _, _)
+                | LexedOperand::ScaledRegister(_, _, _, _)
+                    if parts.index.is_none() && parts.outer_displacement.is_none() =>
+                {
+                    parts.index = Some(operand);
+                    parts.post_indexed = true;
+                }
+                _ => {
+                    return Err(LexError::InvalidMemoryIndirect(format!(
+                        "Invalid element in memory indirect: {:?}",
+                        operand
+                    )))
+                }
+            }
         }
+        Ok(parts)
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Default)]
+pub struct MemoryIndirectParts<'a> {
+    pub base_displacement: Option<&'a String>,
+    pub base: Option<&'a LexedOperand>,
+    pub index: Option<&'a LexedOperand>,
+    pub post_indexed: bool,
+    pub outer_displacement: Option<&'a String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(tag = "type", content = "value")]
 pub enum LexedLine {
     Label {
         name: String,
+        /// Local labels like .loop or 1$ belong to the last global label, see local_labels
+        #[serde(default)]
+        local: bool,
+        #[serde(default)]
+        owner: Option<String>,
     },
     Directive {
         name: String,
@@ -121,18 +332,39 @@ pub enum LexedLine {
     Unknown {
         content: String,
     },
+    /// An instruction with malformed operands, only made by a lexer with recovery
+    Error {
+        content: String,
+        diagnostics: Vec<LexError>,
+    },
+}
+
+impl LexedLine {
+    /// A label without owner, the owner is set when the lines are scoped
+    pub fn new_label(name: impl Into<String>) -> Self {
+        let name = name.into();
+        LexedLine::Label {
+            local: is_local_label(&name),
+            owner: None,
+            name,
+        }
+    }
 }
 
 #[derive(Debug)]
-#[wasm_bindgen]
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
 pub enum OperandKind {
     Register,
     RegisterList,
     RegisterWithSize,
+    ScaledRegister,
+    RegisterPair,
+    Bitfield,
     Immediate,
     Indirect,
     IndirectDisplacement,
     IndirectIndex,
+    MemoryIndirect,
     PostIndirect,
     PreIndirect,
     Absolute,
@@ -168,279 +400,365 @@ should be the same thing.
 At the same time, the directive should have dc/ds/dcb/etc and not just org
 */
 
-enum Grammar {
-    Directive,
-    Register,
-    RegisterWithSize,
-    Indirect,
-    RegisterRange,
-    IndirectDisplacement,
-    IndirectIndex,
-    PostIndirect,
-    PreIndirect,
-    Immediate,
-    Comment,
-    CommentLine,
-    Label,
-    LabelInclusive,
-    Operand,
-    OperandArg,
-    Absolute,
+#[derive(Debug)]
+enum LexLineResult {
+    Line(LexedLine),
+    Multiple(Vec<LexedLine>),
 }
 
-bitflags! {
-    struct GrammarOptions:u32{
-        const NONE = 0;
-        const IS_LINE = 1;
-        const IGNORE_CASE = 2;
+/*
+    Hand-written classifier of the lines and of the operands, every check is a single walk of the text that
+    doesn't allocate. The rules are the ones the lexer always had, tried in the order of get_operand_kind:
+        bitfield                anything followed by {offset:width}
+        memory indirect         ([...]...)
+        post/pre indirect       (x)+ and -(x), where x is a word
+        indirect                (register)
+        indirect index          displacement(x,y...) or (displacement,x,y...)
+        indirect displacement   displacement(register), the register is lowercase
+        registers               d0, a0.w, d0*4, d0.w*4, d0:d1, d0-d3/a0
+        immediate               #value or #'string'
+    anything else is an absolute value or label.
+    The registers are d0-d9, a0-a9, sp and fp0-fp9, the number is checked when the operand is parsed
+*/
+
+fn is_register_name(name: &str, ignore_case: bool) -> bool {
+    let letter = |b: &u8| match ignore_case {
+        true => b.to_ascii_lowercase(),
+        false => *b,
+    };
+    match name.as_bytes() {
+        [kind, b'0'..=b'9'] => matches!(letter(kind), b'd' | b'a'),
+        [s, p] => letter(s) == b's' && letter(p) == b'p',
+        [f, p, b'0'..=b'9'] => letter(f) == b'f' && letter(p) == b'p',
+        _ => false,
     }
 }
 
-#[derive(Debug)]
-enum LexLineResult {
-    Line(LexedLine),
-    Multiple(Vec<LexedLine>),
+fn is_word(text: &str) -> bool {
+    !text.is_empty() && text.chars().all(|c| c.is_alphanumeric() || c == '_')
 }
 
-impl Grammar {
-    fn get_regex(&self) -> String {
-        match &self {
-            Grammar::Directive => r"(.+\s+equ\s+.+)|((org|dc|dcb|ds)\s*.*)".to_string(),
-            Grammar::Register => r"(d\d|a\d|sp)".to_string(),
-            Grammar::RegisterRange => {
-                let r = Grammar::Register.get_regex();
-                //this accepts strings like: "d0-d5/a0-a6/a0/a4"
-                format!("(((({})-({}))|({}))\\/)*((({})-({}))|({}))", r, r, r, r, r, r)
-            }
-            Grammar::RegisterWithSize => format!(
-                r"({})\.(b|w|l)",
-                Grammar::Register.get_regex()
-            ),
-            Grammar::Indirect => format!(r"\({}\)", Grammar::Register.get_regex()),
-            Grammar::IndirectDisplacement => format!(r"([^\r\n\t\f\v,])*\({}\)", Grammar::Register.get_regex()),
-            Grammar::IndirectIndex => r"([^\r\n\t\f\v,])*\((.+,)+.+\)".to_string(),
-            Grammar::PostIndirect => r"\(\w+\)\+".to_string(), //TODO should i include registers in here or leave it?
-            Grammar::PreIndirect => r"-\(\w+\)".to_string(),
-            Grammar::Immediate => r"#(('.+')|(\S+))".to_string(), //TODO could #add absolute here but it wouldn't change the end result
-            Grammar::Comment => r"\s+((;|\*).*)|^(;.*|\*.*)".to_string(),
-            Grammar::CommentLine => r"^((;|\*).*)$".to_string(),
-            Grammar::Label => r"\w+:$".to_string(),
-            Grammar::LabelInclusive => r"\w+:.*".to_string(),
-            Grammar::Absolute => r"((%|@|$|)\w+)|('.')|(\d+)".to_string(), //TODO this does not include labels
-            Grammar::OperandArg => r"(\w*\((?:.+,)+.+\)\w*)|(\w+)|(#\S+)".to_string(),
-            Grammar::Operand => format!(
-                r"(({})|({})|({})|({})|({})|({})|({})|({}))",
-                Grammar::Register.get_regex(),
-                Grammar::Indirect.get_regex(),
-                Grammar::IndirectIndex.get_regex(),
-                Grammar::IndirectDisplacement.get_regex(),
-                Grammar::PostIndirect.get_regex(),
-                Grammar::PreIndirect.get_regex(),
-                Grammar::Immediate.get_regex(),
-                Grammar::Absolute.get_regex()
-            ),
-        }
-    }
-    fn get_opt(&self, options: GrammarOptions) -> String {
-        let mut regex = self.get_regex();
-        if options.contains(GrammarOptions::IGNORE_CASE) {
-            regex = format!(r"(?i){}", regex);
-        }
-        if options.contains(GrammarOptions::IS_LINE) {
-            regex = format!(r"^{}$", regex);
-        }
-
-        regex
+/// What can come before the parenthesis of an indirect, it can't span more arguments
+fn is_displacement(text: &str) -> bool {
+    !text.contains([',', '\r', '\n', '\t', '\x0B', '\x0C'])
+}
+
+fn is_bitfield(operand: &str) -> bool {
+    let body = match operand.strip_suffix('}') {
+        Some(body) => body,
+        None => return false,
+    };
+    match body.rfind('{') {
+        Some(start) if start > 0 => match body[start + 1..].split_once(':') {
+            Some((offset, width)) => [offset, width]
+                .iter()
+                .all(|part| !part.is_empty() && !part.contains([':', '{', '}'])),
+            None => false,
+        },
+        _ => false,
     }
 }
-/*
 
-indirect_displacement
-*/
+fn is_memory_indirect(operand: &str) -> bool {
+    operand.starts_with("([") && operand.ends_with(')') && operand[2..operand.len() - 1].contains(']')
+}
 
-struct AsmRegex {
-    register_only: Regex,
-    register_list_only: Regex,
-    register_with_size_only: Regex,
-    immediate_only: Regex,
-    indirect_only: Regex,
-    indirect_displacement_only: Regex,
-    indirect_index_only: Regex,
-    post_indirect_only: Regex,
-    pre_indirect_only: Regex,
-    label_line: Regex,
-    directive: Regex,
-    operand_arg: Regex,
-    comment_line: Regex,
-    comment: Regex,
-}
-
-impl AsmRegex {
-    pub fn new() -> Self {
-        AsmRegex {
-            register_only: Regex::new(&Grammar::Register.get_opt(GrammarOptions::IGNORE_CASE | GrammarOptions::IS_LINE)).unwrap(),
-            register_list_only: Regex::new(&Grammar::RegisterRange.get_opt(GrammarOptions::IGNORE_CASE | GrammarOptions::IS_LINE)).unwrap(),
-            register_with_size_only: Regex::new(&Grammar::RegisterWithSize.get_opt(GrammarOptions::IGNORE_CASE | GrammarOptions::IS_LINE)).unwrap(),
-            immediate_only: Regex::new(&Grammar::Immediate.get_opt(GrammarOptions::IS_LINE)).unwrap(),
-            indirect_only: Regex::new(&Grammar::Indirect.get_opt(GrammarOptions::IGNORE_CASE | GrammarOptions::IS_LINE)).unwrap(),
-            indirect_displacement_only: Regex::new(&Grammar::IndirectDisplacement.get_opt(GrammarOptions::IS_LINE)).unwrap(),
-            indirect_index_only: Regex::new(&Grammar::IndirectIndex.get_opt(GrammarOptions::IS_LINE)).unwrap(),
-            post_indirect_only: Regex::new(&Grammar::PostIndirect.get_opt(GrammarOptions::IS_LINE)).unwrap(),
-            pre_indirect_only: Regex::new(&Grammar::PreIndirect.get_opt(GrammarOptions::IS_LINE)).unwrap(),
-            label_line: Regex::new(r"^\S+:.*").unwrap(),
-            directive: Regex::new(&format!(r"^\s*({})", Grammar::Directive.get_opt(GrammarOptions::IGNORE_CASE)))
-                .unwrap(),
-            operand_arg: Regex::new(&Grammar::OperandArg.get_regex()).unwrap(),
-            comment_line: Regex::new(&Grammar::CommentLine.get_regex()).unwrap(),
-            comment: Regex::new(&Grammar::Comment.get_regex()).unwrap(),
-        }
-    }
-    pub fn get_operand_kind(&self, operand: &String) -> OperandKind {
-        match operand {
-            //TODO order is important
-            _ if self.post_indirect_only.is_match(operand) => OperandKind::PostIndirect,
-            _ if self.pre_indirect_only.is_match(operand) => OperandKind::PreIndirect,
-            _ if self.indirect_only.is_match(operand) => OperandKind::Indirect,
-            _ if self.indirect_index_only.is_match(operand) => OperandKind::IndirectIndex,
-            _ if self.indirect_displacement_only.is_match(operand) => OperandKind::IndirectDisplacement,
-            _ if self.register_with_size_only.is_match(operand) => OperandKind::RegisterWithSize,
-            _ if self.register_only.is_match(operand) => OperandKind::Register,
-            _ if self.register_list_only.is_match(operand) => OperandKind::RegisterList,
-            _ if self.immediate_only.is_match(operand) => OperandKind::Immediate,
-            //_ if self.absolute.is_match(operand) => OperandKind::Absolute,
-            _ => OperandKind::Absolute,
-        }
-    }
-    pub fn split_at_size(&self, data: &String) -> (String, LexedSize) {
-        let data = data.to_string();
-        let split = data.split('.').collect::<Vec<&str>>();
-        match split[..] {
-            [first] => (first.to_string(), LexedSize::Unspecified),
-            [first, size] => {
-                let size = match size {
-                    "b" | "B" => LexedSize::Byte,
-                    "w" | "W" => LexedSize::Word,
-                    "l" | "L" => LexedSize::Long,
-                    _ => LexedSize::Unknown,
-                };
-                (first.to_string(), size)
-            }
-            _ => (data, LexedSize::Unspecified),
+fn is_post_indirect(operand: &str) -> bool {
+    match operand.strip_prefix('(').and_then(|rest| rest.strip_suffix(")+")) {
+        Some(inner) => is_word(inner),
+        None => false,
+    }
+}
+
+fn is_pre_indirect(operand: &str) -> bool {
+    match operand.strip_prefix("-(").and_then(|rest| rest.strip_suffix(')')) {
+        Some(inner) => is_word(inner),
+        None => false,
+    }
+}
+
+fn is_indirect(operand: &str) -> bool {
+    match operand.strip_prefix('(').and_then(|rest| rest.strip_suffix(')')) {
+        Some(inner) => is_register_name(inner, true),
+        None => false,
+    }
+}
+
+fn is_indirect_index(operand: &str) -> bool {
+    let body = match operand.strip_suffix(')') {
+        Some(body) => body,
+        None => return false,
+    };
+    //the arguments need at least a comma with something on both sides of it
+    let has_arguments = |inner: &str| {
+        inner
+            .char_indices()
+            .any(|(i, c)| c == ',' && i > 0 && i + 1 < inner.len())
+    };
+    for (i, c) in body.char_indices() {
+        match c {
+            '(' if has_arguments(&body[i + 1..]) => return true,
+            ',' | '\r' | '\n' | '\t' | '\x0B' | '\x0C' => return false,
+            _ => {}
+        }
+    }
+    false
+}
+
+fn is_indirect_displacement(operand: &str) -> bool {
+    let body = match operand.strip_suffix(')') {
+        Some(body) => body,
+        None => return false,
+    };
+    match body.rfind('(') {
+        Some(start) => is_register_name(&body[start + 1..], false) && is_displacement(&body[..start]),
+        None => false,
+    }
+}
+
+fn is_register_with_size(operand: &str) -> bool {
+    match operand.split_once('.') {
+        Some((register, size)) => {
+            is_register_name(register, true) && matches!(size, "b" | "w" | "l" | "B" | "W" | "L")
         }
+        None => false,
     }
-    pub fn split_into_separated_args(&self, line: &str, ignore_space: bool) -> Vec<String> {
-        let mut args = vec![];
-        let mut current_arg = String::new();
-        let mut in_parenthesis = false;
-        let mut in_quotes = false;
-        let mut last_char = ' ';
-        let mut last_separator = ' ';
-        if line.is_empty() {
-            return args;
+}
+
+fn is_scaled_register(operand: &str) -> bool {
+    match operand.split_once('*') {
+        Some((register, scale)) => {
+            (is_register_name(register, true) || is_register_with_size(register))
+                && !scale.is_empty()
+                && scale.bytes().all(|b| b.is_ascii_digit())
         }
-        for c in line.chars() {
-            match c {
-                '(' if !in_quotes => {
-                    in_parenthesis = true;
-                    current_arg.push(c);
-                }
-                ')' if !in_quotes => {
-                    in_parenthesis = false;
+        None => false,
+    }
+}
+
+fn is_register_pair(operand: &str) -> bool {
+    let is_data = |register: &str| matches!(register.as_bytes(), [b'd' | b'D', b'0'..=b'9']);
+    match operand.split_once(':') {
+        Some((first, second)) => is_data(first) && is_data(second),
+        None => false,
+    }
+}
+
+fn is_register_list(operand: &str) -> bool {
+    operand.split('/').all(|group| match group.split_once('-') {
+        Some((from, to)) => is_register_name(from, true) && is_register_name(to, true),
+        None => is_register_name(group, true),
+    })
+}
+
+fn is_immediate(operand: &str) -> bool {
+    match operand.strip_prefix('#') {
+        Some(value) => {
+            let is_string = value.len() >= 3 && value.starts_with('\'') && value.ends_with('\'');
+            is_string || (!value.is_empty() && !value.contains(char::is_whitespace))
+        }
+        None => false,
+    }
+}
+
+fn get_operand_kind(operand: &str) -> OperandKind {
+    match operand {
+        //the order is important, the first that matches wins
+        _ if is_bitfield(operand) => OperandKind::Bitfield,
+        _ if is_memory_indirect(operand) => OperandKind::MemoryIndirect,
+        _ if is_post_indirect(operand) => OperandKind::PostIndirect,
+        _ if is_pre_indirect(operand) => OperandKind::PreIndirect,
+        _ if is_indirect(operand) => OperandKind::Indirect,
+        _ if is_indirect_index(operand) => OperandKind::IndirectIndex,
+        _ if is_indirect_displacement(operand) => OperandKind::IndirectDisplacement,
+        _ if is_register_with_size(operand) => OperandKind::RegisterWithSize,
+        _ if is_scaled_register(operand) => OperandKind::ScaledRegister,
+        _ if is_register_pair(operand) => OperandKind::RegisterPair,
+        _ if is_register_name(operand, true) => OperandKind::Register,
+        _ if is_register_list(operand) => OperandKind::RegisterList,
+        _ if is_immediate(operand) => OperandKind::Immediate,
+        _ => OperandKind::Absolute,
+    }
+}
+
+fn split_at_size(data: &str) -> (String, LexedSize) {
+    let split = data.split('.').collect::<Vec<&str>>();
+    match split[..] {
+        [first] => (first.to_string(), LexedSize::Unspecified),
+        [first, size] => {
+            let size = match size {
+                "b" | "B" => LexedSize::Byte,
+                "w" | "W" => LexedSize::Word,
+                "l" | "L" => LexedSize::Long,
+                "s" | "S" => LexedSize::Single,
+                "d" | "D" => LexedSize::Double,
+                "x" | "X" => LexedSize::Extended,
+                _ => LexedSize::Unknown,
+            };
+            (first.to_string(), size)
+        }
+        _ => (data.to_string(), LexedSize::Unspecified),
+    }
+}
+
+fn split_into_separated_args(line: &str, ignore_space: bool) -> Vec<String> {
+    let mut args = vec![];
+    let mut current_arg = String::new();
+    let mut in_parenthesis = false;
+    let mut in_quotes = false;
+    let mut last_char = ' ';
+    if line.is_empty() {
+        return args;
+    }
+    for c in line.chars() {
+        match c {
+            '(' if !in_quotes => {
+                in_parenthesis = true;
+                current_arg.push(c);
+            }
+            ')' if !in_quotes => {
+                in_parenthesis = false;
+                current_arg.push(c);
+            }
+            '\'' if !in_parenthesis => {
+                in_quotes = !in_quotes;
+                current_arg.push(c);
+            }
+            ',' => {
+                if in_parenthesis || in_quotes {
+                    //ignore if in parenthesis or in quotes
                     current_arg.push(c);
+                } else {
+                    args.push(current_arg.trim().to_string());
+                    current_arg = String::new();
                 }
-                '\'' if !in_parenthesis => {
-                    in_quotes = !in_quotes;
-                    current_arg.push(c);
+            }
+            COMMENT_1 | COMMENT_2 => {
+                if last_char == ' ' {
+                    break;
                 }
-                ',' => {
-                    if in_parenthesis || in_quotes {
-                        //ignore if in parenthesis or in quotes
-                        current_arg.push(c);
-                    } else {
-                        args.push(current_arg.trim().to_string());
-                        current_arg = String::new();
-                        last_separator = c;
-                    }
+                current_arg.push(c);
+            } //if it reaches the end where there is a comment
+            ' ' => {
+                // last_char == ',' ||
+                if ignore_space && !in_quotes {
+                    continue;
                 }
-                COMMENT_1 | COMMENT_2 => {
-                    if last_char == ' ' {
-                        break;
-                    }
+                if in_parenthesis || in_quotes {
+                    //ignore if in parenthesis or if it's a char
                     current_arg.push(c);
-                } //if it reaches the end where there is a comment
-                ' ' => {
-                    // last_char == ',' ||
-                    if ignore_space && !in_quotes {
+                } else {
+                    if current_arg.is_empty() {
                         continue;
                     }
-                    if in_parenthesis || in_quotes {
-                        //ignore if in parenthesis or if it's a char
-                        current_arg.push(c);
-                    } else {
-                        if current_arg.is_empty() {
-                            continue;
-                        }
-                        args.push(current_arg.trim().to_string());
-                        current_arg = String::new();
-                        last_separator = c;
-                    }
-                }
-                _ => {
-                    current_arg.push(c);
+                    args.push(current_arg.trim().to_string());
+                    current_arg = String::new();
                 }
             }
-            last_char = c;
-        }
-        match current_arg.trim() {
-            "" => args,
             _ => {
-                args.push(current_arg.trim().to_string());
-                args
-            },
+                current_arg.push(c);
+            }
         }
+        last_char = c;
     }
-    pub fn split_at_whitespace(&self, line: &str) -> Vec<String> {
-        line.replace('\t', " ")
-            .trim()
-            .split(' ')
-            .map(|x| x.to_string())
-            .filter(|x| !x.is_empty())
-            .collect::<Vec<String>>()
+    match current_arg.trim() {
+        "" => args,
+        _ => {
+            args.push(current_arg.trim().to_string());
+            args
+        },
     }
-    pub fn split_at_comment<'a>(&self, string: &'a str) -> Vec<&'a str> {
-        self.comment.split(string).collect()
+}
+fn split_at_whitespace(line: &str) -> Vec<String> {
+    line.replace('\t', " ")
+        .trim()
+        .split(' ')
+        .map(|x| x.to_string())
+        .filter(|x| !x.is_empty())
+        .collect::<Vec<String>>()
+}
+
+/// The code of the line without the comment, a comment starts with ; or * at the start or after a whitespace
+fn strip_comment(line: &str) -> &str {
+    if line.starts_with([COMMENT_1, COMMENT_2]) {
+        return "";
     }
-    pub fn get_line_kind(&self, line: &String) -> LineKind {
-        let line = line.trim();
-        let args = line
-            .split_whitespace()
-            .map(|x| x.trim().to_string())
-            .collect::<Vec<String>>();
-        match args[..] {
-            [] => LineKind::Empty,
-            _ if self.comment_line.is_match(line) => LineKind::Comment,
-            _ if self.label_line.is_match(line) => {
-                let split = line.splitn(2, ':');
-                match split.collect::<Vec<&str>>()[..] {
-                    [name, inclusive] => LineKind::Label {
-                        name: name.to_string(),
-                        inner: Some(inclusive.to_string()),
-                    },
-                    [name] => LineKind::Label {
-                        name: name.to_string(),
-                        inner: None,
-                    },
-                    _ => LineKind::Unknown,
-                }
+    let mut whitespace_start = None;
+    for (i, c) in line.char_indices() {
+        match (c, whitespace_start) {
+            (COMMENT_1 | COMMENT_2, Some(start)) => return &line[..start],
+            (c, None) if c.is_whitespace() => whitespace_start = Some(i),
+            (c, _) if c.is_whitespace() => {}
+            _ => whitespace_start = None,
+        }
+    }
+    line
+}
+
+fn starts_with_ignore_case(line: &str, prefix: &str) -> bool {
+    line.len() >= prefix.len() && line.as_bytes()[..prefix.len()].eq_ignore_ascii_case(prefix.as_bytes())
+}
+
+fn is_label_line(line: &str) -> bool {
+    match line.split(char::is_whitespace).next() {
+        Some(word) => word.chars().skip(1).any(|c| c == ':'),
+        None => false,
+    }
+}
+
+fn is_directive_line(line: &str) -> bool {
+    if ["org", "dc", "ds"].iter().any(|name| starts_with_ignore_case(line, name)) {
+        return true;
+    }
+    let is_export = ["xdef", "xref", "public"]
+        .iter()
+        .any(|name| starts_with_ignore_case(line, name) && line[name.len()..].starts_with(char::is_whitespace));
+    //end alone or with the label the program starts from
+    let is_end = starts_with_ignore_case(line, "end") && line[3..].chars().next().is_none_or(char::is_whitespace);
+    let words = line.split_whitespace().collect::<Vec<&str>>();
+    //name equ value
+    let is_equ = words.len() >= 3 && words[1..words.len() - 1].iter().any(|word| word.eq_ignore_ascii_case(EQU));
+    is_export || is_end || is_equ
+}
+
+/// The label in the first column of a line and the code after it, Easy68K doesn't need the colon there
+fn split_colonless_label(line: &str) -> Option<(&str, &str)> {
+    let (name, rest) = line.split_at(line.find(char::is_whitespace).unwrap_or(line.len()));
+    let (mnemonic, _) = split_at_size(&name.to_lowercase());
+    let is_instruction = INSTRUCTIONS.contains(&mnemonic.as_str())
+        || CpuModel::get_unimplemented_instruction_model(&mnemonic).is_some();
+    match is_identifier(name) && !is_instruction && !is_directive_line(line) {
+        true => Some((name, rest)),
+        false => None,
+    }
+}
+
+fn get_line_kind(line: &str) -> LineKind {
+    let line = line.trim();
+    let args = line.split_whitespace().collect::<Vec<&str>>();
+    match args[..] {
+        [] => LineKind::Empty,
+        _ if line.starts_with([COMMENT_1, COMMENT_2]) => LineKind::Comment,
+        _ if is_label_line(line) => {
+            let split = line.splitn(2, ':');
+            match split.collect::<Vec<&str>>()[..] {
+                [name, inclusive] => LineKind::Label {
+                    name: name.to_string(),
+                    inner: Some(inclusive.to_string()),
+                },
+                [name] => LineKind::Label {
+                    name: name.to_string(),
+                    inner: None,
+                },
+                _ => LineKind::Unknown,
             }
-            _ if self.directive.is_match(line) => LineKind::Directive,
-            //TODO why this distinction?
-            [_, _, ..] | [_] => {
-                let (instruction, size) = self.split_at_size(&args[0].to_lowercase());
-                LineKind::Instruction {
-                    size,
-                    name: instruction,
-                }
+        }
+        _ if is_directive_line(line) => LineKind::Directive,
+        [first, ..] => {
+            let (instruction, size) = split_at_size(&first.to_lowercase());
+            LineKind::Instruction {
+                size,
+                name: instruction,
             }
         }
     }
@@ -458,16 +776,193 @@ impl EquValue {
     }
 }
 
+/// Part of a line of the source, the columns are in UTF-16 code units like the LSP positions and the end is excluded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct TextSpan {
+    pub line_index: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ParsedLine {
     pub parsed: LexedLine,
     pub line: String,
     pub line_index: usize,
+    /// The statement without the label before it and the comment after it, the name for a label
+    #[serde(default)]
+    pub code_span: TextSpan,
+    /// One for every operand of an instruction or argument of a directive, in the same order
+    #[serde(default)]
+    pub operand_spans: Vec<TextSpan>,
+    /// The macro the line was expanded from, the line and the spans are the ones of the invocation
+    #[serde(default)]
+    pub macro_origin: Option<MacroOrigin>,
+}
+
+impl ParsedLine {
+    /// Finds the spans of the lexed line in the source line
+    pub fn new(parsed: LexedLine, line: String, line_index: usize) -> Self {
+        let (code_span, operand_spans) = get_spans(&parsed, &line, line_index);
+        ParsedLine {
+            parsed,
+            line,
+            line_index,
+            code_span,
+            operand_spans,
+            macro_origin: None,
+        }
+    }
+    /// Moves the line and its spans to another line of the source
+    pub fn move_to(&mut self, line_index: usize) {
+        self.line_index = line_index;
+        self.code_span.line_index = line_index;
+        for span in self.operand_spans.iter_mut() {
+            span.line_index = line_index;
+        }
+        if let LexedLine::Error { diagnostics, .. } = &mut self.parsed {
+            for error in diagnostics.iter_mut() {
+                if let LexError::OnLine { span, .. } = error {
+                    span.line_index = line_index;
+                }
+            }
+        }
+    }
+    pub fn get_operand_span(&self, index: usize) -> Option<TextSpan> {
+        self.operand_spans.get(index).copied()
+    }
+    /// Text of a span of this line
+    pub fn get_span_text(&self, span: TextSpan) -> String {
+        let line: Vec<u16> = self.line.encode_utf16().collect();
+        let end = span.end.min(line.len());
+        String::from_utf16_lossy(&line[span.start.min(end)..end])
+    }
+}
+
+/// Splits the text like split_into_separated_args, but keeps the byte range of every part
+fn split_with_ranges(text: &str, offset: usize, split_at_space: bool) -> Vec<(usize, usize)> {
+    let mut ranges = vec![];
+    let mut start: Option<usize> = None;
+    let mut end = 0;
+    let mut depth = 0;
+    let mut in_quotes = false;
+    for (i, c) in text.char_indices() {
+        let separator = match c {
+            '(' if !in_quotes => {
+                depth += 1;
+                false
+            }
+            ')' if !in_quotes => {
+                depth -= 1;
+                false
+            }
+            '\'' if depth == 0 => {
+                in_quotes = !in_quotes;
+                false
+            }
+            ',' => depth == 0 && !in_quotes,
+            //spaces only end a part, they are never the start or end of one
+            c if c.is_whitespace() => {
+                if split_at_space && depth == 0 && !in_quotes {
+                    if let Some(first) = start.take() {
+                        ranges.push((first, end));
+                    }
+                }
+                continue;
+            }
+            _ => false,
+        };
+        if separator {
+            if let Some(first) = start.take() {
+                ranges.push((first, end));
+            }
+            continue;
+        }
+        start.get_or_insert(offset + i);
+        end = offset + i + c.len_utf8();
+    }
+    if let Some(first) = start {
+        ranges.push((first, end));
+    }
+    ranges
+}
+
+/// The code after the label, the lines expanded from a macro have the span of the invocation
+fn get_statement_span(line: &str, line_index: usize) -> TextSpan {
+    let statement = LexedLine::Instruction {
+        name: String::new(),
+        operands: vec![],
+        size: LexedSize::Unspecified,
+    };
+    get_spans(&statement, line, line_index).0
+}
+
+fn get_spans(parsed: &LexedLine, line: &str, line_index: usize) -> (TextSpan, Vec<TextSpan>) {
+    let to_span = |(start, end): (usize, usize)| TextSpan {
+        line_index,
+        start: line[..start].encode_utf16().count(),
+        end: line[..end].encode_utf16().count(),
+    };
+    let code = crate::utils::split_comment(line).0.trim_end();
+    //the code after the label, if the line starts with one
+    let body_start = match code.split_once(':') {
+        Some((label, _)) if !matches!(parsed, LexedLine::Label { .. }) && !label.trim().contains([' ', '\t', '\'']) => {
+            label.len() + 1
+        }
+        _ => match split_colonless_label(code) {
+            Some((label, _)) if !matches!(parsed, LexedLine::Label { .. }) => label.len(),
+            _ => 0,
+        },
+    };
+    let body = &code[body_start..];
+    let trimmed = body.trim_start();
+    let start = body_start + body.len() - trimmed.len();
+    match parsed {
+        LexedLine::Label { name, .. } => {
+            let start = code.find(name.as_str()).unwrap_or(0);
+            (to_span((start, start + name.len())), vec![])
+        }
+        LexedLine::Instruction { .. } => {
+            let mnemonic_end = start + trimmed.find(char::is_whitespace).unwrap_or(trimmed.len());
+            let operands = split_with_ranges(&code[mnemonic_end..], mnemonic_end, false);
+            (to_span((start, code.len())), operands.into_iter().map(to_span).collect())
+        }
+        LexedLine::Directive { .. } => {
+            let args = split_with_ranges(trimmed, start, true);
+            (to_span((start, code.len())), args.into_iter().map(to_span).collect())
+        }
+        LexedLine::Comment { .. } => {
+            let start = line.len() - line.trim_start().len();
+            (to_span((start, line.trim_end().len())), vec![])
+        }
+        LexedLine::Unknown { .. } | LexedLine::Error { .. } => (to_span((start, code.len())), vec![]),
+        LexedLine::Empty => (to_span((code.len(), code.len())), vec![]),
+    }
+}
+
+/// Problem found while reading the source, the line is still lexed
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReadDiagnostic {
+    pub line_index: usize,
+    pub message: String,
+}
+
+/// The source of the last lex, kept so that update lexes again only the lines that change
+#[derive(Debug, Default)]
+struct LexedSource {
+    lines: Vec<String>,
+    equ_map: Vec<(String, String)>,
+    /// Macros, REPT or conditional assembly changed the lines, a line can then depend on any other
+    preprocessed: bool,
 }
 
 pub struct Lexer {
     lines: Vec<ParsedLine>,
-    regex: AsmRegex,
+    read_diagnostics: Vec<ReadDiagnostic>,
+    diagnostics: Vec<LexError>,
+    recover: bool,
+    source: LexedSource,
+    symbols: Interner,
 }
 
 impl Default for Lexer {
@@ -480,49 +975,103 @@ impl Lexer {
     pub fn new() -> Self {
         Lexer {
             lines: Vec::new(),
-            regex: AsmRegex::new(),
+            read_diagnostics: Vec::new(),
+            diagnostics: Vec::new(),
+            recover: false,
+            source: LexedSource::default(),
+            symbols: Interner::new(),
         }
     }
-    pub fn parse_operands(&self, operands: Vec<String>) -> Vec<LexedOperand> {
+    pub fn parse_operands(&self, operands: &[String]) -> Result<Vec<LexedOperand>, LexError> {
         operands
             .iter()
             .map(|o| self.parse_operand(o))
             .collect()
     }
-    pub fn parse_operand(&self, operand: &String) -> LexedOperand {
+    pub fn parse_operand(&self, operand: &str) -> Result<LexedOperand, LexError> {
         let operand = operand.to_string();
-        match self.regex.get_operand_kind(&operand) {
+        let parsed = match get_operand_kind(&operand) {
             OperandKind::Immediate => LexedOperand::Immediate(operand),
             OperandKind::RegisterWithSize => {
                 let split = operand.split('.').collect::<Vec<&str>>();
                 match split[..] {
                     [register, size] => {
-                        let register = self.parse_operand(&register.to_string());
+                        let register = self.parse_operand(register)?;
                         let size = match size {
                             "b" => LexedSize::Byte,
                             "w" => LexedSize::Word,
                             "l" => LexedSize::Long,
-                            _ => return LexedOperand::Other(split.join(".")),
+                            _ => return Err(LexError::InvalidSize(operand)),
                         };
                         match register {
                             LexedOperand::Register(reg, name) => {
                                 LexedOperand::RegisterWithSize(reg, name, size)
                             }
-                            _ => LexedOperand::Other(operand),
+                            _ => return Err(LexError::InvalidRegister(operand)),
                         }
                     }
-                    _ => LexedOperand::Other(operand),
+                    _ => return Err(LexError::InvalidSize(operand)),
                 }
             }
-            OperandKind::Register => {
+            OperandKind::ScaledRegister => {
+                let split = operand.split('*').collect::<Vec<&str>>();
+                let scale = match split[1].parse::<u8>() {
+                    Ok(scale) => scale,
+                    Err(_) => return Err(LexError::InvalidScale(operand)),
+                };
+                match self.parse_operand(split[0])? {
+                    LexedOperand::Register(reg, name) => {
+                        LexedOperand::ScaledRegister(reg, name, LexedSize::Unspecified, scale)
+                    }
+                    LexedOperand::RegisterWithSize(reg, name, size) => {
+                        LexedOperand::ScaledRegister(reg, name, size, scale)
+                    }
+                    _ => return Err(LexError::InvalidRegister(operand)),
+                }
+            }
+            OperandKind::RegisterPair => {
                 let operand = operand.to_lowercase();
-                let register_type = match operand.chars().nth(0).expect("Missing register") {
-                    'd' => LexedRegisterType::Data,
-                    'a' => LexedRegisterType::Address,
-                    's' => LexedRegisterType::SP, //TODO this might fail
-                    _ => panic!("Invalid register type '{}'", operand),
+                match operand.split_once(':') {
+                    Some((high, low)) => LexedOperand::RegisterPair(high.to_string(), low.to_string()),
+                    None => return Err(LexError::InvalidRegister(operand)),
+                }
+            }
+            OperandKind::Bitfield => {
+                let (target, field) = match operand.rsplit_once('{') {
+                    Some(split) => split,
+                    None => return Err(LexError::InvalidBitfield(operand)),
                 };
-                LexedOperand::Register(register_type, operand)
+                match field.trim_end_matches('}').split_once(':') {
+                    Some((offset, width)) => LexedOperand::Bitfield {
+                        operand: Box::new(self.parse_operand(target.trim())?),
+                        offset: offset.trim().to_string(),
+                        width: width.trim().to_string(),
+                    },
+                    None => return Err(LexError::InvalidBitfield(operand)),
+                }
+            }
+            OperandKind::MemoryIndirect => {
+                let (start, end) = match (operand.find('['), operand.find(']')) {
+                    (Some(start), Some(end)) if start < end => (start, end),
+                    _ => {
+                        return Err(LexError::InvalidMemoryIndirect(format!(
+                            "Mismatched brackets in memory indirect '{}'",
+                            operand
+                        )))
+                    }
+                };
+                let inner = split_into_separated_args(operand[start + 1..end].trim(), true);
+                let outer = operand[end + 1..].trim_end_matches(')').trim();
+                let outer = outer.strip_prefix(',').unwrap_or(outer);
+                let outer = split_into_separated_args(outer.trim(), true);
+                LexedOperand::MemoryIndirect {
+                    inner: self.parse_operands(&inner)?,
+                    outer: self.parse_operands(&outer)?,
+                }
+            }
+            OperandKind::Register => {
+                let operand = operand.to_lowercase();
+                LexedOperand::Register(LexedRegisterType::from_string(&operand)?, operand)
             }
             OperandKind::RegisterList => {
                 let groups = operand.split('/').collect::<Vec<&str>>();
@@ -531,74 +1080,74 @@ impl Lexer {
                     let split = group.split('-').collect::<Vec<&str>>();
                     match split[..] {
                         [start, end] => {
-                            let start = parse_register_range(start);
-                            let end = parse_register_range(end);
-                            if start.is_err() || end.is_err() {
-                                return LexedOperand::Other(operand);
-                            }
-                            let (start_reg, start_num) = start.unwrap();
-                            let (end_reg, end_num) = end.unwrap();
-                            if start_reg != end_reg {
-                                return LexedOperand::Other(operand);
+                            let (start_reg, start_num) = parse_register_range(start)?;
+                            let (end_reg, end_num) = parse_register_range(end)?;
+                            if start_reg != end_reg || start_num > end_num {
+                                return Err(LexError::InvalidRegisterRange(group.to_string()));
                             }
                             let base = match start_reg {
                                 LexedRegisterType::Data => 0,
                                 LexedRegisterType::Address => 8,
                                 LexedRegisterType::SP => 15,
+                                LexedRegisterType::Float => return Err(LexError::InvalidRegisterRange(group.to_string())),
                             };
                             for i in start_num..=end_num {
                                 mask |= 1 << (base + i);
                             }
                         }
                         [single] => {
-                            let single = parse_register_range(single);
-                            if single.is_err() {
-                                return LexedOperand::Other(operand);
-                            }
-                            let (reg, num) = single.unwrap();
+                            let (reg, num) = parse_register_range(single)?;
                             let base = match reg {
                                 LexedRegisterType::Data => 0,
                                 LexedRegisterType::Address => 8,
                                 LexedRegisterType::SP => 15,
+                                LexedRegisterType::Float => return Err(LexError::InvalidRegisterRange(group.to_string())),
                             };
                             mask |= 1 << (base + num);
                         }
-                        _ => return LexedOperand::Other(operand),
+                        _ => return Err(LexError::InvalidRegisterRange(group.to_string())),
                     }
                 }
                 LexedOperand::RegisterRange { mask }
             }
             OperandKind::Indirect => {
                 let operand = operand.replace(['(', ')'], "");
-                let operand = self.parse_operand(&operand);
+                let operand = self.parse_operand(&operand)?;
                 LexedOperand::Indirect(Box::new(operand))
             }
             OperandKind::IndirectIndex
             => {
                 let split = operand.split('(').collect::<Vec<&str>>();
                 if split.len() != 2 {
-                    return LexedOperand::Other(operand);
+                    return Err(LexError::InvalidIndirect(operand));
                 }
-                let offset = split[0].trim().to_string();
+                let mut offset = split[0].trim().to_string();
                 let args = split[1].replace(')', "");
-                let args = self.regex.split_into_separated_args(args.trim(), true);
-                let operands = self.parse_operands(args);
-                LexedOperand::IndirectIndex {
-                    offset,
-                    operands,
+                let mut args = split_into_separated_args(args.trim(), true);
+                //the displacement can also be the first argument, like (8,a0,d1.w*4)
+                if offset.is_empty() && args.len() > 1 && matches!(get_operand_kind(&args[0]), OperandKind::Absolute) {
+                    offset = args.remove(0);
+                }
+                let operands = self.parse_operands(&args)?;
+                match &operands[..] {
+                    [operand] => LexedOperand::IndirectDisplacement {
+                        offset,
+                        operand: Box::new(operand.to_owned()),
+                    },
+                    _ => LexedOperand::IndirectIndex { offset, operands },
                 }
             }
             OperandKind::IndirectDisplacement => {
                 let split = operand.split('(').collect::<Vec<&str>>();
                 if split.len() != 2 {
-                    return LexedOperand::Other(operand);
+                    return Err(LexError::InvalidIndirect(operand));
                 }
                 let offset = split[0].trim().to_string();
                 let args = split[1].replace(')', "");
-                let args = self.regex.split_into_separated_args(args.trim(), true);
-                let operands = self.parse_operands(args);
+                let args = split_into_separated_args(args.trim(), true);
+                let operands = self.parse_operands(&args)?;
                 if operands.len() != 1 {
-                    return LexedOperand::Other(operand);
+                    return Err(LexError::InvalidIndirect(operand));
                 }
                 LexedOperand::IndirectDisplacement {
                     offset,
@@ -608,61 +1157,371 @@ impl Lexer {
             OperandKind::Absolute => LexedOperand::Absolute(operand),
             OperandKind::PostIndirect => {
                 let parsed_operand = operand.replace('(', "").replace(")+", "");
-                let arg = self.parse_operand(&parsed_operand);
+                let arg = self.parse_operand(&parsed_operand)?;
                 LexedOperand::PostIndirect(Box::new(arg))
             }
             OperandKind::PreIndirect => {
                 let parsed_operand = operand.replace("-(", "").replace(')', "");
-                let arg = self.parse_operand(&parsed_operand);
+                let arg = self.parse_operand(&parsed_operand)?;
                 LexedOperand::PreIndirect(Box::new(arg))
             }
-        }
+        };
+        Ok(parsed)
     }
 
-    pub fn make_equ_map(&self, lines: &Vec<String>) -> Vec<(String, String)> {
-        let mut equs: Vec<(String, String)> = vec![];
-        lines
+    fn get_equ(&self, line: &str) -> Option<(String, String)> {
+        let code = strip_comment(line).trim();
+        let args = split_at_whitespace(code);
+        match args.len() >= 3 && args[1] == EQU {
+            true => Some((args[0].to_string(), group_equ_value(args[2..].join(" ")))),
+            false => None,
+        }
+    }
+    pub fn make_equ_map(&self, lines: &[String]) -> Vec<(String, String)> {
+        let mut equs: Vec<(String, String)> = lines.iter().filter_map(|line| self.get_equ(line)).collect();
+        //sort by length so that the longest ones are replaced first
+        equs.sort_by_key(|e| core::cmp::Reverse(e.0.len()));
+        equs
+    }
+    /**
+    Lex never aborts once recovery is enabled: an instruction with malformed operands is lexed as
+    LexedLine::Error with its diagnostics, and every problem of the file is in diagnostics
+    */
+    pub fn with_recovery(mut self) -> Self {
+        self.recover = true;
+        self
+    }
+    /// Fails with the first malformed operand unless recovery is enabled, the lines lexed before are not kept
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(bytes = code.len())))]
+    pub fn lex(&mut self, code: &str) -> Result<&Vec<ParsedLine>, LexError> {
+        let mut errors = vec![];
+        let (parsed, source) = self.lex_all(code, self.recover, &mut errors);
+        self.diagnostics = errors;
+        match self.diagnostics.first() {
+            Some(error) if !self.recover => Err(error.clone()),
+            _ => {
+                self.source = source;
+                self.set_lines(parsed);
+                Ok(&self.lines)
+            }
+        }
+    }
+    /**
+    Same as lex, but malformed operands are kept as LexedOperand::Other instead of failing,
+    they are reported by the semantic checker like any other invalid operand
+    */
+    pub fn lex_lossy(&mut self, code: &str) -> &Vec<ParsedLine> {
+        let mut errors = vec![];
+        let (parsed, source) = self.lex_all(code, false, &mut errors);
+        self.diagnostics = errors;
+        self.source = source;
+        self.set_lines(parsed);
+        &self.lines
+    }
+    /// Every problem found by the last lex, lex_lossy or lex_parallel
+    pub fn diagnostics(&self) -> &[LexError] {
+        &self.diagnostics
+    }
+    /**
+    Replaces the lines in range of the last lexed source with the lines of new_text, and lexes again only
+    the lines that changed, for editors that can't lex the whole source on every keystroke.
+    The lexed lines and the diagnostics are patched, malformed operands are kept like in lex_lossy unless
+    recovery is enabled. Returns the lines of the new source that were lexed again, the ones after them
+    are moved by the lines added or removed. A changed global label also lexes again the local labels
+    after it, while changing an equ, or a source with macros, REPT or conditional assembly, lexes the whole source
+    */
+    pub fn update(&mut self, range: Range<usize>, new_text: &str) -> Range<usize> {
+        let end = range.end.min(self.source.lines.len());
+        let start = range.start.min(end);
+        let new_lines = new_text.lines().map(String::from).collect::<Vec<String>>();
+        let changes_equ = self.source.lines[start..end]
             .iter()
-            .map(|line| {
-                let split_at_comments = self.regex.split_at_comment(line);
-                let code = split_at_comments[0].trim();
-                self.regex.split_at_whitespace(code)
-            })
-            .for_each(|args| {
-                if args.len() >= 3 && args[1] == EQU {
-                    equs.push((args[0].to_string(), args[2..].join(" ")));
+            .chain(&new_lines)
+            .any(|line| self.get_equ(line).is_some());
+        if self.source.preprocessed || changes_equ || !is_plain(&new_lines, &preprocess(&new_lines)) {
+            let mut lines = core::mem::take(&mut self.source.lines);
+            lines.splice(start..end, new_lines);
+            let mut errors = vec![];
+            let (parsed, source) = self.lex_lines(lines, self.recover, &mut errors);
+            self.diagnostics = errors;
+            self.source = source;
+            self.set_lines(parsed);
+            return 0..self.source.lines.len();
+        }
+        let added = new_lines.len();
+        let first = self.lines.partition_point(|line| line.line_index < start);
+        let mut last = self.lines.partition_point(|line| line.line_index < end);
+        let mut errors = vec![];
+        let mut parsed = self.lex_plain_lines(&new_lines, start, &mut errors);
+        let mut lexed_end = end;
+        if self.lines[first..last].iter().chain(&parsed).any(is_global_label) {
+            lexed_end = self.lines[last..]
+                .iter()
+                .find(|line| is_global_label(line))
+                .map_or(self.source.lines.len(), |line| line.line_index);
+            last = self.lines.partition_point(|line| line.line_index < lexed_end);
+            parsed.extend(self.lex_plain_lines(&self.source.lines[end..lexed_end], start + added, &mut errors));
+        }
+        let owner = self.lines[..first].iter().rev().find_map(|line| match &line.parsed {
+            LexedLine::Label { name, local: false, .. } => Some(name.clone()),
+            _ => None,
+        });
+        scope_local_labels_with_owner(&mut parsed, owner);
+        //the lines after the lexed ones only move
+        let shift = |line_index: usize| line_index - (end - start) + added;
+        for line in self.lines[last..].iter_mut() {
+            line.move_to(shift(line.line_index));
+        }
+        self.diagnostics.retain(|error| match error.get_span() {
+            Some(span) => span.line_index < start || span.line_index >= lexed_end,
+            None => true,
+        });
+        for error in self.diagnostics.iter_mut() {
+            if let LexError::OnLine { span, .. } = error {
+                if span.line_index >= lexed_end {
+                    span.line_index = shift(span.line_index);
                 }
+            }
+        }
+        let position = self
+            .diagnostics
+            .partition_point(|error| error.get_span().is_none_or(|span| span.line_index < start));
+        self.diagnostics.splice(position..position, errors);
+        intern_lines(&mut self.symbols, &parsed);
+        self.lines.splice(first..last, parsed);
+        self.source.lines.splice(start..end, new_lines);
+        start..start + added + (lexed_end - end)
+    }
+    /// Lexes lines that don't depend on the others, like the ones of a source without preprocessing
+    fn lex_plain_lines(&self, lines: &[String], line_index: usize, errors: &mut Vec<LexError>) -> Vec<ParsedLine> {
+        let mut parsed = vec![];
+        for (i, text) in lines.iter().enumerate() {
+            let line = ExpandedLine {
+                text: text.clone(),
+                line_index: line_index + i,
+                origin: None,
+                error: None,
+            };
+            parsed.extend(self.lex_recovering_line(&line, text, &self.source.equ_map, self.recover, errors));
+        }
+        parsed
+    }
+    fn lex_all(&self, code: &str, recover: bool, errors: &mut Vec<LexError>) -> (Vec<ParsedLine>, LexedSource) {
+        let lines = code.lines().map(String::from).collect::<Vec<String>>();
+        self.lex_lines(lines, recover, errors)
+    }
+    /// The macros, REPT and the conditional assembly are applied first, the equs can then be defined by a macro
+    fn lex_lines(&self, lines: Vec<String>, recover: bool, errors: &mut Vec<LexError>) -> (Vec<ParsedLine>, LexedSource) {
+        let expanded = preprocess(&lines);
+        let equ_map = self.make_equ_map(&get_texts(&expanded));
+        let mut parsed = vec![];
+        for line in &expanded {
+            parsed.extend(self.lex_recovering_line(line, &lines[line.line_index], &equ_map, recover, errors));
+        }
+        let source = LexedSource {
+            preprocessed: !is_plain(&lines, &expanded),
+            lines,
+            equ_map,
+        };
+        (parsed, source)
+    }
+    /// With recovery the instruction of a line with errors is replaced by LexedLine::Error, its label is kept
+    fn lex_recovering_line(
+        &self,
+        line: &ExpandedLine,
+        source: &str,
+        equ_map: &[(String, String)],
+        recover: bool,
+        errors: &mut Vec<LexError>,
+    ) -> Vec<ParsedLine> {
+        let mut line_errors = vec![];
+        let mut parsed = self.lex_source_line(&line.text, line.line_index, equ_map, &mut line_errors);
+        if let Some(error) = &line.error {
+            let span = parsed.last().map(|parsed| parsed.code_span).unwrap_or_default();
+            line_errors.push(LexError::OnLine {
+                span,
+                error: Box::new(error.clone()),
             });
-        //sort by length so that the longest ones are replaced first
-        equs.sort_by(|a, b| b.0.len().cmp(&a.0.len()));
-        equs
+        }
+        if line.text != source {
+            for parsed_line in parsed.iter_mut() {
+                parsed_line.line = source.to_string();
+            }
+        }
+        //the lines of a macro point at the invocation
+        if let Some(origin) = &line.origin {
+            let span = get_statement_span(source, line.line_index);
+            for parsed_line in parsed.iter_mut() {
+                parsed_line.macro_origin = Some(origin.clone());
+                parsed_line.code_span = span;
+                parsed_line.operand_spans.clear();
+            }
+            for error in line_errors.iter_mut() {
+                if let LexError::OnLine { span: error_span, .. } = error {
+                    *error_span = span;
+                }
+            }
+        }
+        if recover && !line_errors.is_empty() {
+            for parsed_line in parsed.iter_mut() {
+                if let LexedLine::Instruction { .. } = parsed_line.parsed {
+                    parsed_line.parsed = LexedLine::Error {
+                        content: parsed_line.get_span_text(parsed_line.code_span),
+                        diagnostics: line_errors.clone(),
+                    };
+                    parsed_line.operand_spans.clear();
+                }
+            }
+        }
+        errors.extend(line_errors);
+        parsed
     }
-    pub fn lex(&mut self, code: &String) -> &Vec<ParsedLine> {
+    /**
+    Same as lex, but the lines are lexed in parallel once the macros are expanded and the equs are collected, a line does not depend
+    on the others after that. Worth it for large generated sources, the result is the same as lex
+    */
+    #[cfg(feature = "parallel")]
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(bytes = code.len())))]
+    pub fn lex_parallel(&mut self, code: &str) -> Result<&Vec<ParsedLine>, LexError> {
+        use rayon::prelude::*;
         let lines = code.lines().map(String::from).collect::<Vec<String>>();
-        let equ_map = self.make_equ_map(&lines);
+        let expanded = preprocess(&lines);
+        let equ_map = self.make_equ_map(&get_texts(&expanded));
+        let this = &*self;
+        let lexed: Vec<(Vec<ParsedLine>, Vec<LexError>)> = expanded
+            .par_iter()
+            .map(|line| {
+                let mut errors = vec![];
+                let source = &lines[line.line_index];
+                (this.lex_recovering_line(line, source, &equ_map, this.recover, &mut errors), errors)
+            })
+            .collect();
         let mut parsed = vec![];
-        for (i, line) in lines.iter().enumerate() {
-            match self.lex_line(line) {
-                LexLineResult::Line(parsed_line) => parsed.push(ParsedLine {
-                    parsed: self.apply_equ_to_line(parsed_line, &equ_map),
-                    line: line.to_string(),
-                    line_index: i,
-                }),
-                LexLineResult::Multiple(parsed_lines) => {
-                    for parsed_line in parsed_lines {
-                        parsed.push(ParsedLine {
-                            parsed: self.apply_equ_to_line(parsed_line, &equ_map),
-                            line: line.to_string(),
-                            line_index: i,
-                        })
-                    }
+        self.diagnostics.clear();
+        for (lines, errors) in lexed {
+            parsed.extend(lines);
+            self.diagnostics.extend(errors);
+        }
+        match self.diagnostics.first() {
+            Some(error) if !self.recover => Err(error.clone()),
+            _ => {
+                self.source = LexedSource {
+                    preprocessed: !is_plain(&lines, &expanded),
+                    lines,
+                    equ_map,
+                };
+                self.set_lines(parsed);
+                Ok(&self.lines)
+            }
+        }
+    }
+    /// Lexes one line of the source, a line with a label and code gives more than one parsed line
+    pub(crate) fn lex_source_line(
+        &self,
+        line: &str,
+        line_index: usize,
+        equ_map: &[(String, String)],
+        errors: &mut Vec<LexError>,
+    ) -> Vec<ParsedLine> {
+        let mut operand_errors = vec![];
+        let parsed = match self.lex_line(line, &mut operand_errors) {
+            LexLineResult::Line(parsed_line) => vec![parsed_line],
+            LexLineResult::Multiple(parsed_lines) => parsed_lines,
+        };
+        let parsed: Vec<ParsedLine> = parsed
+            .into_iter()
+            .map(|parsed_line| ParsedLine::new(self.apply_equ_to_line(parsed_line, equ_map), line.to_string(), line_index))
+            .collect();
+        //a source line has at most one instruction, the operand errors are all from it
+        if let Some(instruction) = parsed.iter().find(|l| matches!(l.parsed, LexedLine::Instruction { .. })) {
+            for (index, error) in operand_errors {
+                errors.push(LexError::OnLine {
+                    span: instruction.get_operand_span(index).unwrap_or(instruction.code_span),
+                    error: Box::new(error),
+                });
+            }
+        }
+        parsed
+    }
+    /**
+    Lexes the lines of a reader, without keeping the whole source in memory as one string.
+    Both LF and CRLF line endings are accepted, a line that is not valid UTF-8 is lexed with the invalid
+    bytes replaced and reported in the read diagnostics. The lines are lexed as they are read, once a line
+    needs the preprocessing or an equ is defined after its use, the whole source is lexed again at the
+    end so that the result is the same as lex
+     */
+    #[cfg(feature = "std")]
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
+    pub fn lex_reader<R: BufRead>(&mut self, mut reader: R) -> io::Result<&Vec<ParsedLine>> {
+        self.read_diagnostics.clear();
+        let mut lines: Vec<String> = vec![];
+        let mut parsed = vec![];
+        let mut equ_map: Vec<(String, String)> = vec![];
+        let mut relex = false;
+        let mut buffer = vec![];
+        let mut line_index = 0;
+        while reader.read_until(b'\n', &mut buffer)? > 0 {
+            if buffer.ends_with(b"\n") {
+                buffer.pop();
+                if buffer.ends_with(b"\r") {
+                    buffer.pop();
                 }
             }
+            let line = match String::from_utf8_lossy(&buffer) {
+                Cow::Borrowed(line) => line.to_string(),
+                Cow::Owned(line) => {
+                    self.read_diagnostics.push(ReadDiagnostic {
+                        line_index,
+                        message: "The line is not valid UTF-8, the invalid bytes were replaced".to_string(),
+                    });
+                    line
+                }
+            };
+            if !relex {
+                if let Some(equ) = self.get_equ(&line) {
+                    //the lines already lexed would have been replaced by lex
+                    relex = lines.iter().any(|previous| previous.contains(&equ.0));
+                    let position = equ_map.partition_point(|(key, _)| key.len() >= equ.0.len());
+                    equ_map.insert(position, equ);
+                }
+                let single = core::slice::from_ref(&line);
+                relex = relex || !is_plain(single, &preprocess(single));
+                if relex {
+                    parsed.clear();
+                } else {
+                    //malformed operands are kept like in lex_lossy
+                    let expanded = ExpandedLine {
+                        text: line.clone(),
+                        line_index,
+                        origin: None,
+                        error: None,
+                    };
+                    parsed.extend(self.lex_recovering_line(&expanded, &line, &equ_map, false, &mut vec![]));
+                }
+            }
+            lines.push(line);
+            buffer.clear();
+            line_index += 1;
         }
-        self.lines = parsed;
-        &self.lines
+        if relex {
+            let (lexed, source) = self.lex_lines(lines, false, &mut vec![]);
+            parsed = lexed;
+            self.source = source;
+        } else {
+            self.source = LexedSource {
+                lines,
+                equ_map,
+                preprocessed: false,
+            };
+        }
+        self.set_lines(parsed);
+        Ok(&self.lines)
     }
-    fn apply_equ_to_line(&self, line: LexedLine, equ_map: &Vec<(String, String)>) -> LexedLine {
+    /// Problems found by the last lex_reader
+    pub fn get_read_diagnostics(&self) -> &Vec<ReadDiagnostic> {
+        &self.read_diagnostics
+    }
+    pub(crate) fn apply_equ_to_line(&self, line: LexedLine, equ_map: &[(String, String)]) -> LexedLine {
         match line {
             LexedLine::Instruction { name, operands, size } => LexedLine::Instruction {
                 name,
@@ -672,27 +1531,34 @@ impl Lexer {
                     .collect(),
                 size,
             },
+            //the name of the constant of an equ and the names of the symbols of a module are kept
             LexedLine::Directive { name, args, size } => LexedLine::Directive {
-                name,
                 args: args
                     .into_iter()
-                    .map(|arg| {
-                        self.apply_equ_to_expression_string(arg, equ_map)
+                    .enumerate()
+                    .map(|(i, arg)| match (name.as_str(), i) {
+                        ("equ", 0) | ("xdef" | "xref" | "public" | "end", _) => arg,
+                        _ => self.apply_equ_to_expression_string(arg, equ_map),
                     })
                     .collect(),
+                name,
                 size,
             },
             _ => line,
         }
     }
 
-    fn apply_equ_to_expression_string(&self, mut expression: String, equ_map: &Vec<(String, String)>) -> String {
+    pub(crate) fn apply_equ_to_expression_string(&self, mut expression: String, equ_map: &[(String, String)]) -> String {
         for (key, value) in equ_map.iter() {
+            //equs that expand to other equs can grow exponentially, past the limit the expression is left as is
+            if expression.len() > MAX_EXPRESSION_LENGTH {
+                break;
+            }
             expression = expression.replace(key, value);
         }
         expression
     }
-    fn apply_equ_to_operand(&self, op: LexedOperand, equ_map: &Vec<(String, String)>) -> LexedOperand {
+    fn apply_equ_to_operand(&self, op: LexedOperand, equ_map: &[(String, String)]) -> LexedOperand {
         match op {
             LexedOperand::Register(_, _)
             | LexedOperand::RegisterRange { .. }
@@ -705,11 +1571,11 @@ impl Lexer {
             LexedOperand::Absolute(abs) => {
                 let string = self.apply_equ_to_expression_string(abs, equ_map);
                 //TODO this is a bit of a hack, after applying the equ, it could change the operand type
-                self.parse_operand(&string)
+                self.parse_operand(&string).unwrap_or(LexedOperand::Other(string))
             }
             LexedOperand::Label(label) => {
                 let string = self.apply_equ_to_expression_string(label, equ_map);
-                self.parse_operand(&string)
+                self.parse_operand(&string).unwrap_or(LexedOperand::Other(string))
             }
             LexedOperand::Indirect(operand) => {
                 let operand = self.apply_equ_to_operand(*operand, equ_map);
@@ -729,30 +1595,58 @@ impl Lexer {
                 let offset = self.apply_equ_to_expression_string(offset, equ_map);
                 LexedOperand::IndirectIndex { offset, operands }
             }
+            LexedOperand::MemoryIndirect { inner, outer } => {
+                let inner = inner
+                    .into_iter()
+                    .map(|op| self.apply_equ_to_operand(op, equ_map))
+                    .collect();
+                let outer = outer
+                    .into_iter()
+                    .map(|op| self.apply_equ_to_operand(op, equ_map))
+                    .collect();
+                LexedOperand::MemoryIndirect { inner, outer }
+            }
             LexedOperand::RegisterWithSize(reg, name, size) => {
                 LexedOperand::RegisterWithSize(reg, name, size)
             }
+            LexedOperand::ScaledRegister(reg, name, size, scale) => {
+                LexedOperand::ScaledRegister(reg, name, size, scale)
+            }
+            LexedOperand::RegisterPair(high, low) => LexedOperand::RegisterPair(high, low),
+            LexedOperand::Bitfield { operand, offset, width } => {
+                let operand = self.apply_equ_to_operand(*operand, equ_map);
+                let offset = self.apply_equ_to_expression_string(offset, equ_map);
+                let width = self.apply_equ_to_expression_string(width, equ_map);
+                LexedOperand::Bitfield { operand: Box::new(operand), offset, width }
+            }
         }
     }
 
-    fn lex_line(&mut self, line: &String) -> LexLineResult {
-        let line = line.trim();
-        let split_at_comments = self.regex.split_at_comment(line);
-        let code = split_at_comments[0].trim();
-        /*
-                let comment = match split_at_comments[..] {
-            [_, ..] => split_at_comments[1..].join(&COMMENT_1.to_string()),
-            _ => "".to_string(),
+    /// Malformed operands are kept as LexedOperand::Other, the error and the index of the operand are added to the errors
+    fn lex_line(&self, line: &str, errors: &mut Vec<(usize, LexError)>) -> LexLineResult {
+        let kind = match split_colonless_label(line) {
+            Some((name, inner)) => LineKind::Label {
+                name: name.to_string(),
+                inner: Some(inner.to_string()),
+            },
+            None => get_line_kind(strip_comment(line.trim()).trim()),
         };
-         */
-        let kind = self.regex.get_line_kind(&code.to_string());
-        let args = self.regex.split_at_whitespace(code);
+        let line = line.trim();
+        let code = strip_comment(line).trim();
+        let args = split_at_whitespace(code);
         match kind {
             LineKind::Instruction { size, name } => {
-                let operands = self
-                    .regex
-                    .split_into_separated_args(args[1..].join(" ").as_str(), true);
-                let operands = self.parse_operands(operands);
+                let operands = split_into_separated_args(args[1..].join(" ").as_str(), true);
+                let operands = operands
+                    .iter()
+                    .enumerate()
+                    .map(|(i, operand)| {
+                        self.parse_operand(operand).unwrap_or_else(|error| {
+                            errors.push((i, error));
+                            LexedOperand::Other(operand.clone())
+                        })
+                    })
+                    .collect();
                 LexLineResult::Line(LexedLine::Instruction {
                     name,
                     size,
@@ -764,21 +1658,22 @@ impl Lexer {
             }),
             LineKind::Label { name, inner } => match &inner {
                 Some(inn) => {
-                    let mut parsed_inner = match self.lex_line(inn) {
+                    let mut parsed_inner = match self.lex_line(inn, errors) {
                         LexLineResult::Line(l) => vec![l],
                         LexLineResult::Multiple(l) => l,
                     };
-                    parsed_inner.insert(0, LexedLine::Label { name });
+                    parsed_inner.insert(0, LexedLine::new_label(name));
                     LexLineResult::Multiple(parsed_inner)
                 }
-                None => LexLineResult::Line(LexedLine::Label { name }),
+                None => LexLineResult::Line(LexedLine::new_label(name)),
             },
             LineKind::Directive => {
                 let mut parsed_args: Vec<String> =
-                    self.regex.split_into_separated_args(&code.replace('\t', " "), false);
+                    split_into_separated_args(&code.replace('\t', " "), false);
                 //lowercase the first arg
-                let first = parsed_args.first().expect("Missing first argument").to_lowercase();
-                parsed_args[0] = first;
+                if let Some(first) = parsed_args.first_mut() {
+                    *first = first.to_lowercase();
+                }
                 let line = match &parsed_args[..] {
                     [_, equ, ..] if equ.to_lowercase() == "equ" => LexedLine::Directive {
                         name: equ.to_lowercase(),
@@ -786,7 +1681,7 @@ impl Lexer {
                         args: parsed_args,
                     },
                     [first, ..] => {
-                        let (name, size) = self.regex.split_at_size(&first.to_lowercase());
+                        let (name, size) = split_at_size(&first.to_lowercase());
                         LexedLine::Directive {
                             name,
                             size,
@@ -805,23 +1700,86 @@ impl Lexer {
             LineKind::Empty => LexLineResult::Line(LexedLine::Empty),
         }
     }
+    pub fn lines(&self) -> &[ParsedLine] {
+        &self.lines
+    }
+    /// Takes the lexed lines out of the lexer without copying them
+    pub fn into_lines(self) -> Vec<ParsedLine> {
+        self.lines
+    }
+    /// Kept for compatibility, prefer lines
     pub fn get_lines(&self) -> &Vec<ParsedLine> {
         &self.lines
     }
+    pub fn set_lines(&mut self, mut lines: Vec<ParsedLine>) {
+        scope_local_labels(&mut lines);
+        intern_lines(&mut self.symbols, &lines);
+        self.lines = lines;
+    }
+    /// Labels, mnemonics and registers of every program lexed by this lexer
+    pub fn get_symbols(&self) -> &Interner {
+        &self.symbols
+    }
+}
+
+
+/// The lines up to the END directive included, the ones after it are not assembled
+pub fn get_assembled_lines(lines: &[ParsedLine]) -> &[ParsedLine] {
+    let end = lines
+        .iter()
+        .position(|line| matches!(&line.parsed, LexedLine::Directive { name, .. } if name == "end"));
+    match end {
+        Some(end) => &lines[..=end],
+        None => lines,
+    }
+}
+
+/// Expands the macros, copies the REPT blocks and removes the inactive conditional blocks, before the lines are lexed
+fn preprocess(lines: &[String]) -> Vec<ExpandedLine> {
+    let mut expanded = expand_repetitions(expand_macros(lines).lines);
+    apply_conditionals(&mut expanded);
+    expanded
 }
 
+fn is_global_label(line: &ParsedLine) -> bool {
+    matches!(line.parsed, LexedLine::Label { local: false, .. })
+}
+
+/// Whether the preprocessing left every line as it is
+fn is_plain(lines: &[String], expanded: &[ExpandedLine]) -> bool {
+    expanded.len() == lines.len()
+        && expanded.iter().zip(lines).enumerate().all(|(i, (line, source))| {
+            line.line_index == i && line.origin.is_none() && line.error.is_none() && &line.text == source
+        })
+}
+
+fn get_texts(lines: &[ExpandedLine]) -> Vec<String> {
+    lines.iter().map(|line| line.text.clone()).collect()
+}
+
+/// Arithmetic values are put in parentheses, so BUFLEN*2 with BUFLEN equ 4+1 is (4+1)*2 and not 4+1*2
+fn group_equ_value(value: String) -> String {
+    let is_arithmetic = !value.starts_with('\'')
+        && value.contains(|c: char| "+-*/\\&|^~<>".contains(c))
+        && evaluate(&value, &|symbol| (!is_register(symbol)).then_some(0)).is_ok();
+    match is_arithmetic {
+        true => format!("({})", value),
+        false => value,
+    }
+}
 
-fn parse_register_range(range: &str) -> Result<(LexedRegisterType, u32), String>{
+fn parse_register_range(range: &str) -> Result<(LexedRegisterType, u32), LexError>{
     let reg_type = match LexedRegisterType::from_string(range) {
         Ok(reg) => reg,
-        Err(e) => return Err(format!("Invalid register range '{}': {}", range, e))
+        Err(_) => return Err(LexError::InvalidRegisterRange(range.to_string()))
     };
+    //sp is a7, so a0-sp is a valid range
     if reg_type == LexedRegisterType::SP {
-        return Ok((reg_type, 0));
+        return Ok((LexedRegisterType::Address, 7));
     }
     let num = range.chars().nth(1).and_then(|x| x.to_digit(10));
     match num {
         Some(num) => Ok((reg_type, num)),
-        None => Err(format!("Invalid register range '{}'", range))
+        None => Err(LexError::InvalidRegisterRange(range.to_string()))
     }
 }
\ No newline at end of file
@@ -1,117 +1,431 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+extern crate alloc;
+#[cfg(feature = "interpreter")]
 use interpreter::{Interpreter, InterpreterOptions};
+#[cfg(feature = "assembler")]
+use assembler::{Assembler, EncodeError};
+#[cfg(feature = "assembler")]
 use compiler::Compiler;
+#[cfg(feature = "assembler")]
+use elf::{ElfError, ObjectFile};
+use cpu_model::CpuModel;
+use lesson_profile::LessonProfile;
+#[cfg(feature = "wasm")]
 use wasm_bindgen::prelude::*;
+#[cfg(feature = "capi")]
+pub mod capi;
+#[cfg(feature = "assembler")]
+pub mod code_actions;
+pub mod arena;
+pub mod collections;
+#[cfg(feature = "assembler")]
+pub mod assembler;
+#[cfg(feature = "interpreter")]
+pub mod assertions;
+#[cfg(feature = "interpreter")]
+pub mod breakpoints;
+pub mod builder;
+pub mod conditional;
 mod constants;
+#[cfg(feature = "interpreter")]
+pub mod coprocessor;
+#[cfg(feature = "assembler")]
+pub mod cost_report;
+pub mod cpu_model;
+pub mod diff;
+#[cfg(feature = "assembler")]
+pub mod elf;
+#[cfg(feature = "interpreter")]
+pub mod exceptions;
+#[cfg(feature = "interpreter")]
+pub mod differential;
+#[cfg(feature = "assembler")]
+pub mod diagnostic;
+#[cfg(feature = "interpreter")]
+pub mod explain;
+pub mod expression;
+#[cfg(feature = "dap")]
+pub mod debug_adapter;
+#[cfg(feature = "interpreter")]
+pub mod flag_explanation;
+pub mod folding;
+#[cfg(feature = "interpreter")]
+pub mod fpu;
+pub mod formatter;
+#[cfg(feature = "interpreter")]
+pub mod grading;
+#[cfg(feature = "gdb")]
+pub mod gdb_stub;
+#[cfg(feature = "interpreter")]
+pub mod host_io;
+#[cfg(feature = "assembler")]
+pub mod timing;
+#[cfg(feature = "assembler")]
 pub mod instructions;
+#[cfg(feature = "assembler")]
+pub mod instruction_info;
+#[cfg(feature = "assembler")]
+pub mod inlay_hints;
+#[cfg(feature = "serialize")]
+pub mod json;
+#[cfg(feature = "assembler")]
+pub mod memory_image;
+#[cfg(feature = "interpreter")]
+pub mod memory_region;
+#[cfg(feature = "interpreter")]
+pub mod mmio;
+#[cfg(feature = "interpreter")]
+pub mod input_generator;
+#[cfg(feature = "interpreter")]
 pub mod interpreter;
+#[cfg(feature = "interpreter")]
+pub mod interrupt_source;
+#[cfg(feature = "lsp")]
+pub mod language_server;
+pub mod lesson_profile;
 pub mod lexer;
+#[cfg(feature = "assembler")]
+pub mod linker;
+#[cfg(feature = "assembler")]
+pub mod listing;
+pub mod local_labels;
+pub mod macros;
+pub mod outline;
+pub mod printer;
+#[cfg(feature = "interpreter")]
+pub mod quiz;
+#[cfg(feature = "assembler")]
 pub mod compiler;
+#[cfg(feature = "assembler")]
+pub mod completion;
+pub mod references;
+pub mod repeat;
+#[cfg(feature = "assembler")]
+pub mod semantic;
+#[cfg(feature = "assembler")]
+pub mod symbol_table;
+#[cfg(feature = "assembler")]
+pub mod symbol_map;
+#[cfg(feature = "remote")]
+pub mod remote_debug;
+#[cfg(feature = "interpreter")]
+pub mod repl;
+#[cfg(feature = "interpreter")]
+pub mod sandbox;
+#[cfg(feature = "assembler")]
+pub mod scaffold;
+#[cfg(feature = "interpreter")]
+pub mod scripted_input;
+pub mod semantic_tokens;
+#[cfg(feature = "assembler")]
+pub mod signature_help;
+pub mod similarity;
+#[cfg(feature = "assembler")]
+pub mod source_map;
+#[cfg(feature = "assembler")]
+pub mod srec;
+#[cfg(feature = "interpreter")]
+pub mod stack_frames;
+pub mod symbol;
+pub mod template;
+#[cfg(feature = "interpreter")]
+pub mod trace;
+#[cfg(feature = "assembler")]
+pub mod untrusted;
+pub mod visitor;
+#[cfg(feature = "assembler")]
 mod semantic_checker;
+#[cfg(feature = "python")]
+pub(crate) mod python;
+//the expressions are only evaluated by the assembler, the radix and the plain absolutes by the repl
+#[cfg_attr(not(feature = "interpreter"), allow(dead_code))]
 mod utils;
 
+#[cfg(all(test, feature = "interpreter", feature = "std"))]
 mod test;
+//the compiler only uses the sign extension, the rest is for the interpreter
+#[cfg(feature = "assembler")]
+#[cfg_attr(not(feature = "interpreter"), allow(dead_code))]
 mod math;
+#[cfg(feature = "wasm")]
 mod ts_types;
+//the history is only read by the wasm exports
+#[cfg(feature = "interpreter")]
+#[cfg_attr(not(feature = "wasm"), allow(dead_code))]
 mod debugger;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "interpreter")]
+pub use crate::interpreter::RuntimeError;
+pub use crate::lexer::LexError;
+#[cfg(feature = "assembler")]
+pub use crate::{
+    compiler::{AssembleError, CompilationError},
+    semantic_checker::{RelatedSpan, SemanticError},
+};
+#[cfg(feature = "assembler")]
+use crate::collections::HashMap;
+#[cfg(feature = "assembler")]
+use crate::{
+    code_actions::{get_code_actions, CodeAction},
+    diagnostic::{Diagnostic, Suggestion},
+    semantic_checker::SemanticChecker,
+};
+use alloc::{string::{String, ToString}, vec, vec::Vec};
 use crate::{
     lexer::{Lexer, ParsedLine},
-    semantic_checker::{SemanticChecker, SemanticError},
+    utils::set_panic_hook,
+    semantic_tokens::{get_semantic_tokens, SemanticToken},
+    symbol::{intern_lines, Interner},
 };
 
-#[wasm_bindgen]
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
 pub struct S68k {
     code: String,
     lines: Vec<ParsedLine>,
-    
+    //the operands the lexer couldn't parse, the lines keep them as they are written
+    lex_errors: Vec<LexError>,
+    //the names of the lines, the label tables start from them
+    symbols: Interner,
+    cpu_model: CpuModel,
+    lesson_profile: Option<LessonProfile>,
 }
 impl S68k {
-    pub fn new(code: String) -> S68k {
+    /// Takes a String without copying it, or copies a borrowed &str
+    pub fn new(code: impl Into<String>) -> S68k {
+        let code = code.into();
         let mut lexer = Lexer::new();
-        lexer.lex(&code);
+        lexer.lex_lossy(&code);
         S68k {
             code,
-            lines: lexer.get_lines().clone(),
+            lex_errors: lexer.diagnostics().to_vec(),
+            symbols: lexer.get_symbols().clone(),
+            lines: lexer.into_lines(),
+            cpu_model: CpuModel::default(),
+            lesson_profile: None,
         }
     }
-    pub fn semantic_check(&self) -> Vec<SemanticError> {
-        let semantic_checker = SemanticChecker::new(&self.lines);
-        semantic_checker.get_errors()
+    /// Takes lines that were built instead of lexed, the code is made of the text of the lines
+    pub fn from_lines(mut lines: Vec<ParsedLine>) -> S68k {
+        local_labels::scope_local_labels(&mut lines);
+        let count = lines.iter().map(|line| line.line_index + 1).max().unwrap_or(0);
+        let mut code = vec![""; count];
+        for line in &lines {
+            code[line.line_index] = &line.line;
+        }
+        let mut symbols = Interner::new();
+        intern_lines(&mut symbols, &lines);
+        S68k {
+            code: code.join("\n"),
+            lines,
+            lex_errors: vec![],
+            symbols,
+            cpu_model: CpuModel::default(),
+            lesson_profile: None,
+        }
+    }
+    pub fn set_cpu_model(&mut self, cpu_model: CpuModel) {
+        self.cpu_model = cpu_model;
     }
-    pub fn compile(&self) -> Result<Compiler, String> {
-        Compiler::new(&self.lines)
+    pub fn get_cpu_model(&self) -> CpuModel {
+        self.cpu_model
+    }
+    /// Restricts the instructions the program can use, see the lesson_profile module
+    pub fn set_lesson_profile(&mut self, lesson_profile: Option<LessonProfile>) {
+        self.lesson_profile = lesson_profile;
+    }
+    pub fn get_lesson_profile(&self) -> Option<&LessonProfile> {
+        self.lesson_profile.as_ref()
     }
     pub fn get_lexed_lines(&self) -> &Vec<ParsedLine> {
         &self.lines
     }
-    pub fn get_code(&self) -> &String {
+    /// The problems the lexer found in the operands
+    pub fn get_lex_errors(&self) -> &[LexError] {
+        &self.lex_errors
+    }
+    pub fn get_code(&self) -> &str {
         &self.code
     }
+    /// Labels, mnemonics and registers of the program
+    pub fn get_symbols(&self) -> &Interner {
+        &self.symbols
+    }
+    pub fn get_semantic_tokens(&self) -> Vec<SemanticToken> {
+        get_semantic_tokens(&self.code, &self.lines)
+    }
+    /// The lexed program as the JSON document of the json module
+    #[cfg(feature = "serialize")]
+    pub fn to_json(&self) -> Result<String, String> {
+        serde_json::to_string(&json::ProgramJson::new(&self.lines)).map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(feature = "assembler")]
+impl S68k {
+    pub fn semantic_check(&self) -> Vec<SemanticError> {
+        let semantic_checker = SemanticChecker::new_with_symbols(
+            &self.lines,
+            self.cpu_model,
+            self.lesson_profile.clone(),
+            self.symbols.clone(),
+        );
+        semantic_checker.get_errors()
+    }
+    pub fn compile(&self) -> Result<Compiler, AssembleError> {
+        Compiler::new_with_model(&self.lines, self.cpu_model)
+    }
+    /// Encodes the program to machine code, see the assembler module
+    pub fn assemble(&self) -> Result<Assembler, EncodeError> {
+        Assembler::new_with_model(&self.lines, self.cpu_model)
+    }
+    /// Assembles the program as a relocatable object, see the elf module
+    pub fn assemble_object(&self) -> Result<ObjectFile, ElfError> {
+        ObjectFile::new(&self.lines, self.cpu_model)
+    }
+    /// The problems of the program with their code and fix, see the diagnostic module.
+    /// The errors of the semantic checker on a line with a lexer error are left out as they come from the
+    /// operand that couldn't be parsed, the program is assembled only when there is no other error
+    pub fn get_diagnostics(&self) -> Vec<Diagnostic> {
+        let mut diagnostics: Vec<Diagnostic> = self.lex_errors.iter().map(Diagnostic::from).collect();
+        let lex_lines: Vec<Option<usize>> = diagnostics.iter().map(|d| d.get_line_index()).collect();
+        diagnostics.extend(
+            self.semantic_check()
+                .iter()
+                .filter(|error| !lex_lines.contains(&Some(error.get_line_index())))
+                .map(Diagnostic::from),
+        );
+        if diagnostics.is_empty() {
+            match self.assemble() {
+                Ok(_) => return vec![],
+                Err(error) => diagnostics.push(error.to_diagnostic(&self.code)),
+            }
+        }
+        let mut actions: HashMap<usize, Vec<CodeAction>> = HashMap::new();
+        diagnostics
+            .into_iter()
+            .map(|diagnostic| {
+                let line_index = match diagnostic.get_line_index() {
+                    Some(line_index) => line_index,
+                    None => return diagnostic,
+                };
+                let actions = actions
+                    .entry(line_index)
+                    .or_insert_with(|| get_code_actions(self, line_index));
+                let fixes: Vec<&CodeAction> = actions
+                    .iter()
+                    .filter(|action| action.diagnostic.as_ref() == Some(&diagnostic.message))
+                    .collect();
+                //a suggestion is applied without asking, so there must be only one way to fix it
+                match fixes[..] {
+                    [fix] => diagnostic.with_suggestion(Suggestion {
+                        message: fix.title.clone(),
+                        edits: fix.edits.clone(),
+                    }),
+                    _ => diagnostic,
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(feature = "interpreter")]
+impl S68k {
     pub fn create_interpreter(
         &self,
         pre_processed_program: Compiler,
         options: Option<InterpreterOptions>,
     ) -> Interpreter {
-        Interpreter::new(pre_processed_program, options)
+        let mut options = options.unwrap_or_default();
+        options.cpu_model = self.cpu_model;
+        options.lesson_profile = self.lesson_profile.clone();
+        Interpreter::new(pre_processed_program, Some(options))
     }
 }
 
-#[wasm_bindgen]
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
 impl S68k {
-    #[wasm_bindgen(constructor)]
+    #[cfg_attr(feature = "wasm", wasm_bindgen(constructor))]
     pub fn wasm_new(code: String) -> S68k {
-        console_error_panic_hook::set_once();
-        let mut lexer = Lexer::new();
-        lexer.lex(&code);
-        S68k {
-            code,
-            lines: lexer.get_lines().clone(),
-        }
+        set_panic_hook();
+        S68k::new(code)
+    }
+    pub fn wasm_set_cpu_model(&mut self, cpu_model: CpuModel) {
+        self.set_cpu_model(cpu_model);
+    }
+    pub fn wasm_get_cpu_model(&self) -> CpuModel {
+        self.get_cpu_model()
     }
+    /// Sets the lesson profile from a text like "week 3: move, add, branches", an empty one removes it
+    pub fn wasm_set_lesson_profile(&mut self, profile: String) -> Result<(), String> {
+        let profile = match profile.trim().is_empty() {
+            true => None,
+            false => Some(profile.parse()?),
+        };
+        self.set_lesson_profile(profile);
+        Ok(())
+    }
+    #[cfg(feature = "wasm")]
     pub fn wasm_get_lexed_lines(&self) -> Result<JsValue, JsValue> {
-        console_error_panic_hook::set_once();
+        set_panic_hook();
         match serde_wasm_bindgen::to_value(&self.get_lexed_lines()) {
             Ok(v) => Ok(v),
             Err(e) => Err(JsValue::from_str(&e.to_string())),
         }
     }
-    pub fn wasm_compile(&self) -> Result<Compiler, String>{
-        console_error_panic_hook::set_once();
-        self.compile()
-    }
     pub fn wasm_get_code(&self) -> String {
-        console_error_panic_hook::set_once();
-        self.get_code().clone()
+        set_panic_hook();
+        self.get_code().to_string()
+    }
+}
+
+#[cfg(feature = "assembler")]
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl S68k {
+    pub fn wasm_compile(&self) -> Result<Compiler, String>{
+        set_panic_hook();
+        Ok(self.compile()?)
     }
     pub fn wasm_semantic_check(&self) -> WasmSemanticErrors {
-        console_error_panic_hook::set_once();
+        set_panic_hook();
         WasmSemanticErrors::new(self.semantic_check())
     }
+}
+
+#[cfg(feature = "interpreter")]
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl S68k {
+    #[cfg(feature = "wasm")]
     pub fn wasm_create_interpreter(
         &self,
         pre_processed_program: Compiler,
         interpreter_options: JsValue,
     ) -> Interpreter {
-        console_error_panic_hook::set_once();
+        set_panic_hook();
         let interpreter_options: InterpreterOptions = serde_wasm_bindgen::from_value(interpreter_options).unwrap();
         self.create_interpreter(pre_processed_program, Some(interpreter_options))
     }
 }
 
-#[wasm_bindgen]
+#[cfg(feature = "assembler")]
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
 pub struct WasmSemanticErrors {
     errors: Vec<SemanticError>,
 }
+#[cfg(feature = "assembler")]
 impl WasmSemanticErrors {
     pub fn new(errors: Vec<SemanticError>) -> Self {
         Self { errors }
     }
 }
 
-#[wasm_bindgen]
+#[cfg(feature = "assembler")]
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
 impl WasmSemanticErrors {
     pub fn get_length(&self) -> usize {
         self.errors.len()
     }
+    #[cfg(feature = "wasm")]
     pub fn get_errors(&self) -> Vec<JsValue> {
         self.errors
             .iter()
@@ -0,0 +1,228 @@
+use alloc::{string::{String, ToString}, vec, vec::Vec};
+use core::{error::Error, fmt};
+
+use crate::{
+    assembler::{EncodedLine, MachineCode},
+    elf::{ObjectFile, RelocationKind, RelocationTarget, SymbolSection},
+};
+
+/*
+    Joins the objects of several modules into a single program. Every module exports its symbols with XDEF or
+    PUBLIC and imports the ones of the others with XREF, an object keeps a relocation for every field that holds
+    the address of its code or of an import.
+    The modules are placed one after the other from the origin, in the order they are added and aligned to a word,
+    then the exported symbols are collected and the relocations are applied:
+        absolute        the address of the target plus the addend
+        relative        the same minus the address of the field, like the displacement of a branch
+    The program starts at the global START when a module exports it, at the origin otherwise
+*/
+
+/// Where the first module is placed unless configured, the start of a program without ORG
+pub const DEFAULT_LINK_ORIGIN: usize = 0x1000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LinkError {
+    /// Two modules export the same symbol
+    DuplicateSymbol { name: String, first: String, second: String },
+    /// A module imports a symbol that no module exports
+    UndefinedSymbol { name: String, module: String },
+    /// The relocated value doesn't fit the field
+    OutOfRange { module: String, offset: usize, value: i64 },
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LinkError::DuplicateSymbol { name, first, second } => write!(
+                f,
+                "The symbol \"{}\" is exported by both \"{}\" and \"{}\"",
+                name, first, second
+            ),
+            LinkError::UndefinedSymbol { name, module } => write!(
+                f,
+                "The symbol \"{}\" imported by \"{}\" is not exported by any module",
+                name, module
+            ),
+            LinkError::OutOfRange { module, offset, value } => write!(
+                f,
+                "The value {} at offset ${:X} of \"{}\" doesn't fit its field",
+                value, offset, module
+            ),
+        }
+    }
+}
+
+impl Error for LinkError {}
+
+impl From<LinkError> for String {
+    fn from(error: LinkError) -> Self {
+        error.to_string()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkedModule {
+    pub name: String,
+    pub address: usize,
+    pub length: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkedSymbol {
+    pub name: String,
+    pub value: i64,
+    /// Index of the module that exports it
+    pub module: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct LinkedImage {
+    /// A line for every module, its line index is the index of the module
+    pub machine_code: MachineCode,
+    pub modules: Vec<LinkedModule>,
+    pub symbols: Vec<LinkedSymbol>,
+}
+
+impl LinkedImage {
+    pub fn get_symbol(&self, name: &str) -> Option<&LinkedSymbol> {
+        self.symbols.iter().find(|symbol| symbol.name == name)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Linker {
+    modules: Vec<(String, ObjectFile)>,
+    origin: usize,
+}
+
+impl Default for Linker {
+    fn default() -> Self {
+        Self {
+            modules: vec![],
+            origin: DEFAULT_LINK_ORIGIN,
+        }
+    }
+}
+
+/// Checks that the value fits the field, signed or unsigned
+fn fits(value: i64, kind: RelocationKind) -> bool {
+    let bits = kind.get_width() as u32 * 8;
+    match kind {
+        RelocationKind::Relative16 => value >= -(1 << (bits - 1)) && value < 1 << (bits - 1),
+        _ => value >= -(1 << (bits - 1)) && value < 1 << bits,
+    }
+}
+
+impl Linker {
+    pub fn new() -> Linker {
+        Linker::default()
+    }
+    pub fn with_origin(mut self, origin: usize) -> Self {
+        self.origin = origin;
+        self
+    }
+    pub fn add_module(&mut self, name: impl Into<String>, object: ObjectFile) {
+        self.modules.push((name.into(), object));
+    }
+    pub fn get_modules(&self) -> &[(String, ObjectFile)] {
+        &self.modules
+    }
+    pub fn link(&self) -> Result<LinkedImage, LinkError> {
+        let mut modules: Vec<LinkedModule> = vec![];
+        let mut address = self.origin;
+        for (name, object) in &self.modules {
+            modules.push(LinkedModule {
+                name: name.clone(),
+                address,
+                length: object.code.len(),
+            });
+            address = (address + object.code.len() + 1) & !1;
+        }
+        let mut symbols: Vec<LinkedSymbol> = vec![];
+        for (i, (name, object)) in self.modules.iter().enumerate() {
+            let exported = object
+                .symbols
+                .iter()
+                .filter(|symbol| symbol.global && symbol.section != SymbolSection::Undefined);
+            for symbol in exported {
+                if let Some(existing) = symbols.iter().find(|existing| existing.name == symbol.name) {
+                    return Err(LinkError::DuplicateSymbol {
+                        name: symbol.name.clone(),
+                        first: modules[existing.module].name.clone(),
+                        second: name.clone(),
+                    });
+                }
+                let value = match symbol.section {
+                    SymbolSection::Text => symbol.value + modules[i].address as i64,
+                    _ => symbol.value,
+                };
+                symbols.push(LinkedSymbol {
+                    name: symbol.name.clone(),
+                    value,
+                    module: i,
+                });
+            }
+        }
+        let end = modules.last().map(|module| module.address + module.length).unwrap_or(self.origin);
+        let mut bytes = vec![0; end - self.origin];
+        for (i, (name, object)) in self.modules.iter().enumerate() {
+            let start = modules[i].address - self.origin;
+            let code = &mut bytes[start..start + object.code.len()];
+            code.copy_from_slice(&object.code);
+            for relocation in &object.relocations {
+                let target = match &relocation.target {
+                    RelocationTarget::Section => modules[i].address as i64,
+                    RelocationTarget::External(external) => {
+                        match symbols.iter().find(|symbol| symbol.name == *external) {
+                            Some(symbol) => symbol.value,
+                            None => {
+                                return Err(LinkError::UndefinedSymbol {
+                                    name: external.clone(),
+                                    module: name.clone(),
+                                })
+                            }
+                        }
+                    }
+                };
+                let field = (modules[i].address + relocation.offset) as i64;
+                let value = match relocation.kind {
+                    RelocationKind::Relative16 => target + relocation.addend - field,
+                    _ => target + relocation.addend,
+                };
+                if !fits(value, relocation.kind) {
+                    return Err(LinkError::OutOfRange {
+                        module: name.clone(),
+                        offset: relocation.offset,
+                        value,
+                    });
+                }
+                let width = relocation.kind.get_width();
+                let field = &mut code[relocation.offset..relocation.offset + width];
+                field.copy_from_slice(&(value as u32).to_be_bytes()[4 - width..]);
+            }
+        }
+        let start_address = match symbols.iter().find(|symbol| symbol.name == "START") {
+            Some(symbol) => symbol.value as usize,
+            None => self.origin,
+        };
+        let lines = modules
+            .iter()
+            .enumerate()
+            .map(|(i, module)| EncodedLine {
+                line_index: i,
+                address: module.address,
+                length: module.length,
+            })
+            .collect();
+        Ok(LinkedImage {
+            machine_code: MachineCode {
+                origin: self.origin,
+                bytes,
+                start_address,
+                lines,
+            },
+            modules,
+            symbols,
+        })
+    }
+}
@@ -0,0 +1,168 @@
+use alloc::{format, string::String, vec, vec::Vec};
+use serde::Serialize;
+
+use crate::{
+    assembler::MachineCode,
+    compiler::Compiler,
+    lexer::{LexedLine, ParsedLine},
+    printer::print_source_line,
+};
+
+/*
+    The listing of an assembled program, every line of the source with its address and the bytes it was encoded to.
+    The lines of a macro come after its invocation, printed from the lexed code since they are not in the source,
+    the copies of a REPT come after its ENDR with the source of the body. In the text a row has the line number,
+    a + for the lines of a macro, the address, up to 8 bytes and the source, the rest of the bytes of a long
+    line like a dc are on the rows after it.
+*/
+
+/// Bytes on a row of the text
+const BYTES_PER_ROW: usize = 8;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ListingRow {
+    /// Line of the source, the invocation for the lines of a macro
+    pub line_index: usize,
+    /// Missing for the lines that don't take space, like comments and equs
+    pub address: Option<usize>,
+    pub bytes: Vec<u8>,
+    pub source: String,
+    /// The macro the line was expanded from
+    pub macro_name: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Listing {
+    pub rows: Vec<ListingRow>,
+}
+
+fn has_address(line: &LexedLine) -> bool {
+    match line {
+        LexedLine::Empty | LexedLine::Comment { .. } => false,
+        LexedLine::Directive { name, .. } => name != "equ",
+        _ => true,
+    }
+}
+
+/// The nodes of a row, the labels and the statement after them
+fn group_rows(lines: &[ParsedLine]) -> Vec<(usize, usize)> {
+    let mut groups: Vec<(usize, usize)> = vec![];
+    for (i, line) in lines.iter().enumerate() {
+        let key = |line: &ParsedLine| {
+            let origin = line.macro_origin.as_ref();
+            (line.line_index, origin.map(|origin| (origin.line_index, origin.expansion)))
+        };
+        match groups.last_mut() {
+            //the copies of a REPT have the same line, a row has a single statement
+            Some((start, end))
+                if key(&lines[*start]) == key(line)
+                    && lines[*start..*end]
+                        .iter()
+                        .all(|line| matches!(line.parsed, LexedLine::Label { .. })) =>
+            {
+                *end = i + 1
+            }
+            _ => groups.push((i, i + 1)),
+        }
+    }
+    groups
+}
+
+pub fn generate_listing(lines: &[ParsedLine], compiler: &Compiler, machine_code: &MachineCode) -> Listing {
+    let addresses = compiler.get_line_addresses();
+    let mut encoded = machine_code.lines.iter().peekable();
+    let mut rows = vec![];
+    let mut last_invocation = None;
+    for (start, end) in group_rows(lines) {
+        let first = &lines[start];
+        let mut bytes = vec![];
+        for (i, line) in lines.iter().enumerate().take(end).skip(start) {
+            //the encoded lines are in the same order as the lexed ones
+            match encoded.peek() {
+                Some(next) if next.line_index == line.line_index && next.address == addresses[i] => {
+                    match &line.parsed {
+                        LexedLine::Instruction { .. } | LexedLine::Directive { .. } => {
+                            bytes.extend_from_slice(machine_code.get_line_bytes(next));
+                            encoded.next();
+                        }
+                        _ => {}
+                    }
+                }
+                _ => {}
+            }
+        }
+        let nodes: Vec<&LexedLine> = lines[start..end].iter().map(|line| &line.parsed).collect();
+        let address = match nodes.iter().any(|node| has_address(node)) {
+            true => Some(addresses[start]),
+            false => None,
+        };
+        let (source, macro_name) = match &first.macro_origin {
+            Some(origin) => {
+                let invocation = Some((first.line_index, origin.expansion));
+                if last_invocation != invocation {
+                    last_invocation = invocation;
+                    rows.push(ListingRow {
+                        line_index: first.line_index,
+                        address: None,
+                        bytes: vec![],
+                        source: first.line.clone(),
+                        macro_name: None,
+                    });
+                }
+                //the empty lines of the body are left out
+                if nodes.iter().all(|node| matches!(node, LexedLine::Empty)) {
+                    continue;
+                }
+                (print_source_line(&nodes, "    "), Some(origin.name.clone()))
+            }
+            None => (first.line.clone(), None),
+        };
+        rows.push(ListingRow {
+            line_index: first.line_index,
+            address,
+            bytes,
+            source,
+            macro_name,
+        });
+    }
+    Listing { rows }
+}
+
+fn format_bytes(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02X}", byte)).collect::<Vec<String>>().join(" ")
+}
+
+impl Listing {
+    pub fn to_text(&self) -> String {
+        let width = BYTES_PER_ROW * 3 - 1;
+        let mut text = String::new();
+        for row in &self.rows {
+            let marker = match row.macro_name {
+                Some(_) => '+',
+                None => ' ',
+            };
+            let address = match row.address {
+                Some(address) => format!("{:08X}", address),
+                None => " ".repeat(8),
+            };
+            let mut chunks = row.bytes.chunks(BYTES_PER_ROW);
+            let first = chunks.next().map(format_bytes).unwrap_or_default();
+            let line = format!(
+                "{:>5}{} {}  {:<width$}  {}",
+                row.line_index + 1,
+                marker,
+                address,
+                first,
+                row.source,
+                width = width
+            );
+            text.push_str(line.trim_end());
+            text.push('\n');
+            for (i, chunk) in chunks.enumerate() {
+                let address = row.address.unwrap_or_default() + (i + 1) * BYTES_PER_ROW;
+                text.push_str(&format!("{:>6} {:08X}  {}\n", "", address, format_bytes(chunk)));
+            }
+        }
+        text
+    }
+}
@@ -0,0 +1,115 @@
+use alloc::{format, string::{String, ToString}, vec::Vec};
+use crate::{
+    lexer::{LexedLine, LexedOperand, LexedSize, ParsedLine},
+    visitor::{walk_lexed_operand_mut, VisitorMut},
+};
+
+/*
+    Local labels, they belong to the last global label before them so the same name can be used
+    in every subroutine. Two forms are supported:
+        .loop   the name starts with a dot, its key is the owner followed by the name, main.loop
+        1$      digits followed by a dollar, its key is the owner, a dot and the name, main.1$
+    The label lines keep the name as written and record if they are local and their owner, while
+    the references to local labels in the operands and directives are replaced by their key, so the
+    compiler and the semantic checker resolve them like any other label.
+    A local label before the first global label has no owner and its key is the name itself
+*/
+
+/// Whether the label is local, like .loop or 1$
+pub fn is_local_label(name: &str) -> bool {
+    match name.strip_suffix('$') {
+        Some(digits) => !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit()),
+        //.5 is a number
+        None => match name.strip_prefix('.') {
+            Some(rest) => rest.starts_with(|c: char| c.is_alphabetic() || c == '_'),
+            None => false,
+        },
+    }
+}
+
+/// The name a label is known by in the symbol table
+pub fn get_label_key(name: &str, owner: Option<&str>) -> String {
+    match owner {
+        Some(owner) if name.starts_with('.') => format!("{}{}", owner, name),
+        Some(owner) if is_local_label(name) => format!("{}.{}", owner, name),
+        _ => name.to_string(),
+    }
+}
+
+struct LocalScope {
+    owner: Option<String>,
+}
+
+impl LocalScope {
+    fn scope_expression(&self, expression: &mut String) {
+        let mut result = String::new();
+        let mut word = String::new();
+        let mut in_string = false;
+        for c in expression.chars().chain(core::iter::once('\0')) {
+            if !in_string && (c.is_alphanumeric() || c == '_' || c == '.' || c == '$') {
+                word.push(c);
+                continue;
+            }
+            match is_local_label(&word) {
+                true => result.push_str(&get_label_key(&word, self.owner.as_deref())),
+                false => result.push_str(&word),
+            }
+            word.clear();
+            if c == '\'' {
+                in_string = !in_string;
+            }
+            if c != '\0' {
+                result.push(c);
+            }
+        }
+        *expression = result;
+    }
+}
+
+impl VisitorMut for LocalScope {
+    fn visit_directive_mut(&mut self, _name: &mut String, _size: &mut LexedSize, args: &mut Vec<String>) {
+        //the first arg is the directive as written, or the name of the constant for equ
+        for arg in args.iter_mut().skip(1) {
+            self.scope_expression(arg);
+        }
+    }
+    fn visit_lexed_operand_mut(&mut self, operand: &mut LexedOperand) {
+        match operand {
+            LexedOperand::Immediate(expression)
+            | LexedOperand::Absolute(expression)
+            | LexedOperand::Label(expression)
+            | LexedOperand::IndirectDisplacement { offset: expression, .. }
+            | LexedOperand::IndirectIndex { offset: expression, .. } => self.scope_expression(expression),
+            _ => {}
+        }
+        walk_lexed_operand_mut(self, operand)
+    }
+}
+
+/**
+    Records the owner of the local labels and replaces the references to them with their key.
+    The references are scoped to the owner of the line they are in, running it again changes nothing
+*/
+pub fn scope_local_labels(lines: &mut [ParsedLine]) {
+    scope_local_labels_with_owner(lines, None)
+}
+
+/// Same as scope_local_labels for lines in the middle of a program, owner is the last global label before them
+pub fn scope_local_labels_with_owner(lines: &mut [ParsedLine], owner: Option<String>) {
+    let mut scope = LocalScope { owner };
+    for line in lines {
+        match &mut line.parsed {
+            LexedLine::Label { name, local, owner } => {
+                *local = is_local_label(name);
+                match *local {
+                    true => *owner = scope.owner.clone(),
+                    false => {
+                        *owner = None;
+                        scope.owner = Some(name.clone());
+                    }
+                }
+            }
+            parsed => scope.visit_lexed_line_mut(parsed),
+        }
+    }
+}
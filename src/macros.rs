@@ -0,0 +1,269 @@
+use alloc::{format, string::{String, ToString}, vec, vec::Vec};
+use core::{error::Error, fmt};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    lexer::LexError,
+    utils::{split_comment, split_operands},
+};
+
+/*
+    Macros, expanded on the text of the source before it is lexed:
+        name MACRO      starts a definition, the lines until ENDM are its body
+        \1, \2 ...      are replaced by the arguments of the invocation, a missing one is empty
+        \@              is replaced by a suffix that is unique to every expansion, like _3, to make labels
+                        that don't clash when the macro is used more than once
+        name a, b       invokes the macro, the line is replaced by the body with the arguments
+    The lines of a definition are lexed as empty lines. The expanded lines have the line index of the
+    invocation and remember the macro and the line of the body they come from, so errors point at the
+    invocation and can say where in the macro they are. A macro can invoke other macros, a macro that
+    invokes itself stops at the depth limit
+*/
+
+/// How many macros can be expanded inside each other
+const MAX_DEPTH: usize = 32;
+/// How many lines all the expansions can add, so that a macro invoking itself twice doesn't explode
+const MAX_EXPANDED_LINES: usize = 1 << 16;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "value")]
+pub enum MacroError {
+    Unterminated(String),
+    UnexpectedEndm,
+    NestedDefinition(String),
+    Duplicate(String),
+    TooDeep(String),
+    TooLarge(String),
+}
+
+impl fmt::Display for MacroError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MacroError::Unterminated(name) => write!(f, "Macro '{}' is missing its ENDM", name),
+            MacroError::UnexpectedEndm => write!(f, "ENDM without a MACRO"),
+            MacroError::NestedDefinition(name) => {
+                write!(f, "Macro '{}' can't be defined inside another macro", name)
+            }
+            MacroError::Duplicate(name) => write!(f, "Macro '{}' is already defined", name),
+            MacroError::TooDeep(name) => write!(
+                f,
+                "Macro '{}' is expanded more than {} times inside itself",
+                name, MAX_DEPTH
+            ),
+            MacroError::TooLarge(name) => write!(
+                f,
+                "Expanding macro '{}' makes more than {} lines",
+                name, MAX_EXPANDED_LINES
+            ),
+        }
+    }
+}
+
+impl Error for MacroError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MacroDefinition {
+    pub name: String,
+    pub body: Vec<String>,
+    /// Line of the MACRO
+    pub line_index: usize,
+    /// Line of the ENDM
+    pub end_line_index: usize,
+}
+
+/// Where an expanded line comes from
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MacroOrigin {
+    pub name: String,
+    /// Line of the body in the source
+    pub line_index: usize,
+    /// Number of the expansion, the same as the suffix of \@
+    pub expansion: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExpandedLine {
+    /// The text that is lexed, empty for the lines of a definition
+    pub text: String,
+    /// The line of the source, the invocation for the lines of a macro
+    pub line_index: usize,
+    pub origin: Option<MacroOrigin>,
+    /// A line with an error is lexed as it is written
+    pub error: Option<LexError>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MacroExpansion {
+    pub lines: Vec<ExpandedLine>,
+    pub macros: Vec<MacroDefinition>,
+}
+
+/// The label and the statement of a line, without the comment
+pub(crate) fn split_statement(line: &str) -> (Option<&str>, &str) {
+    let code = split_comment(line).0;
+    //like the lexer, a label starts in the first column and ends with :
+    match code.split_once(':') {
+        Some((label, rest)) if !label.is_empty() && !label.contains(char::is_whitespace) => {
+            (Some(label), rest.trim())
+        }
+        _ => (None, code.trim()),
+    }
+}
+
+/// The name of the macro if the line starts a definition, name MACRO or name: MACRO
+fn get_macro_header(line: &str) -> Option<String> {
+    let (label, statement) = split_statement(line);
+    let words = statement.split_whitespace().collect::<Vec<&str>>();
+    match (label, &words[..]) {
+        (None, &[name, keyword]) | (Some(name), &[keyword]) if keyword.eq_ignore_ascii_case("macro") => {
+            Some(name.to_string())
+        }
+        _ => None,
+    }
+}
+
+fn is_endm(line: &str) -> bool {
+    split_statement(line).1.eq_ignore_ascii_case("endm")
+}
+
+/// Replaces the parameters of the body with the arguments
+fn substitute(line: &str, args: &[String], expansion: usize) -> String {
+    let mut result = String::new();
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('\\', Some('@')) => {
+                chars.next();
+                result.push_str(&format!("_{}", expansion));
+            }
+            ('\\', Some('1'..='9')) => {
+                let mut digits = String::new();
+                while let Some(digit) = chars.next_if(|c| c.is_ascii_digit()) {
+                    digits.push(digit);
+                }
+                let index = digits.parse::<usize>().unwrap_or(usize::MAX);
+                if let Some(arg) = index.checked_sub(1).and_then(|index| args.get(index)) {
+                    result.push_str(arg);
+                }
+            }
+            _ => result.push(c),
+        }
+    }
+    result
+}
+
+struct Expander<'a> {
+    macros: &'a [MacroDefinition],
+    lines: Vec<ExpandedLine>,
+    expansions: usize,
+    expanded_lines: usize,
+}
+
+impl Expander<'_> {
+    fn push(&mut self, text: String, line_index: usize, origin: Option<MacroOrigin>, error: Option<MacroError>) {
+        self.lines.push(ExpandedLine {
+            text,
+            line_index,
+            origin,
+            error: error.map(LexError::InvalidMacro),
+        });
+    }
+    fn expand_line(&mut self, line: &str, line_index: usize, origin: Option<MacroOrigin>, depth: usize) {
+        let (label, statement) = split_statement(line);
+        let mnemonic = statement.split_whitespace().next().unwrap_or_default();
+        let macros = self.macros;
+        let definition = match macros.iter().find(|m| m.name.eq_ignore_ascii_case(mnemonic)) {
+            Some(definition) => definition,
+            None => return self.push(line.to_string(), line_index, origin, None),
+        };
+        let error = match () {
+            _ if depth >= MAX_DEPTH => Some(MacroError::TooDeep(definition.name.clone())),
+            _ if self.expanded_lines + definition.body.len() > MAX_EXPANDED_LINES => {
+                Some(MacroError::TooLarge(definition.name.clone()))
+            }
+            _ => None,
+        };
+        if error.is_some() {
+            return self.push(line.to_string(), line_index, origin, error);
+        }
+        if let Some(label) = label {
+            self.push(format!("{}:", label), line_index, origin, None);
+        }
+        self.expansions += 1;
+        self.expanded_lines += definition.body.len();
+        let expansion = self.expansions;
+        let args = split_operands(statement[mnemonic.len()..].trim());
+        for (i, body_line) in definition.body.iter().enumerate() {
+            let origin = MacroOrigin {
+                name: definition.name.clone(),
+                line_index: definition.line_index + 1 + i,
+                expansion,
+            };
+            self.expand_line(&substitute(body_line, &args, expansion), line_index, Some(origin), depth + 1);
+        }
+    }
+}
+
+/// Collects the definitions and expands the invocations, every line of the source gives at least one line
+pub fn expand_macros(lines: &[String]) -> MacroExpansion {
+    let mut macros: Vec<MacroDefinition> = vec![];
+    let mut in_definition = vec![false; lines.len()];
+    let mut errors: Vec<Option<MacroError>> = vec![None; lines.len()];
+    let mut current: Option<MacroDefinition> = None;
+    for (i, line) in lines.iter().enumerate() {
+        match (get_macro_header(line), &mut current) {
+            (Some(name), Some(_)) => {
+                errors[i] = Some(MacroError::NestedDefinition(name));
+                in_definition[i] = true;
+            }
+            (Some(name), None) => {
+                current = Some(MacroDefinition {
+                    name,
+                    body: vec![],
+                    line_index: i,
+                    end_line_index: i,
+                });
+                in_definition[i] = true;
+            }
+            (None, Some(definition)) if is_endm(line) => {
+                definition.end_line_index = i;
+                in_definition[i] = true;
+                let definition = current.take().unwrap();
+                match macros.iter().any(|m| m.name.eq_ignore_ascii_case(&definition.name)) {
+                    true => errors[definition.line_index] = Some(MacroError::Duplicate(definition.name)),
+                    false => macros.push(definition),
+                }
+            }
+            (None, None) if is_endm(line) => errors[i] = Some(MacroError::UnexpectedEndm),
+            (None, Some(definition)) => {
+                definition.body.push(line.clone());
+                in_definition[i] = true;
+            }
+            (None, None) => {}
+        }
+    }
+    //without its ENDM the lines are not part of a macro
+    if let Some(definition) = current {
+        for in_definition in in_definition.iter_mut().skip(definition.line_index) {
+            *in_definition = false;
+        }
+        errors[definition.line_index] = Some(MacroError::Unterminated(definition.name));
+    }
+    let mut expander = Expander {
+        macros: &macros,
+        lines: vec![],
+        expansions: 0,
+        expanded_lines: 0,
+    };
+    for (i, line) in lines.iter().enumerate() {
+        match (in_definition[i], errors[i].take()) {
+            //the lines with errors are lexed as they are, so they are reported like unknown code
+            (_, Some(error)) => expander.push(line.clone(), i, None, Some(error)),
+            (true, None) => expander.push(String::new(), i, None, None),
+            (false, None) => expander.expand_line(line, i, None, 0),
+        }
+    }
+    let lines = expander.lines;
+    MacroExpansion { lines, macros }
+}
@@ -1,8 +1,8 @@
 use console::Term;
 use core::panic;
 use s68k::{
-    instructions::{Interrupt, InterruptResult},
-    interpreter::{Interpreter, InterpreterOptions, InterpreterStatus},
+    host_io::TerminalIo,
+    interpreter::{InterpreterOptions, InterpreterStatus},
     S68k,
 };
 use std::env;
@@ -71,8 +71,7 @@ fn main() {
             let status = interpreter.run().unwrap();
             match status {
                 InterpreterStatus::Interrupt => {
-                    let interrupt = interpreter.get_current_interrupt().unwrap();
-                    handle_interrupt(&mut interpreter, &interrupt);
+                    interpreter.answer_interrupt_with(&mut TerminalIo).unwrap();
                 }
                 InterpreterStatus::TerminatedWithException => {
                     println!("Program Terminated with exception");
@@ -108,8 +107,7 @@ fn main() {
             let status = interpreter.get_status();
             match status {
                 InterpreterStatus::Interrupt => {
-                    let interrupt = interpreter.get_current_interrupt().unwrap();
-                    handle_interrupt(&mut interpreter, &interrupt);
+                    interpreter.answer_interrupt_with(&mut TerminalIo).unwrap();
                 }
                 InterpreterStatus::TerminatedWithException => {
                     println!("Program Terminated with exception");
@@ -125,14 +123,14 @@ fn main() {
         let options = InterpreterOptions {
             keep_history: false,
             history_size: 0,
+            ..Default::default()
         };
         let mut interpreter = s68k.create_interpreter(compiled_program, Some(options));
         while !interpreter.has_terminated() {
             let status = interpreter.run().unwrap();
             match status {
                 InterpreterStatus::Interrupt => {
-                    let interrupt = interpreter.get_current_interrupt().unwrap();
-                    handle_interrupt(&mut interpreter, &interrupt);
+                    interpreter.answer_interrupt_with(&mut TerminalIo).unwrap();
                 }
                 InterpreterStatus::TerminatedWithException => {
                     println!("Program Terminated with exception");
@@ -165,61 +163,3 @@ fn ask_step_kind() -> StepKind {
         _ => panic!("Invalid step kind"),
     }
 }
-
-fn handle_interrupt(interpreter: &mut Interpreter, interrupt: &Interrupt) {
-    match interrupt {
-        Interrupt::DisplayNumber(number) => {
-            print!("{}", number);
-            interpreter
-                .answer_interrupt(InterruptResult::DisplayNumber)
-                .unwrap();
-        }
-        Interrupt::DisplayStringWithCRLF(string) => {
-            println!("{}", string);
-            interpreter
-                .answer_interrupt(InterruptResult::DisplayStringWithCRLF)
-                .unwrap();
-        }
-        Interrupt::DisplayStringWithoutCRLF(string) => {
-            print!("{}", string);
-            interpreter
-                .answer_interrupt(InterruptResult::DisplayStringWithoutCRLF)
-                .unwrap();
-        }
-        Interrupt::GetTime => {
-            interpreter
-                .answer_interrupt(InterruptResult::GetTime(0))
-                .unwrap();
-        }
-        Interrupt::DisplayChar(char) => {
-            print!("{}", char);
-            interpreter
-                .answer_interrupt(InterruptResult::DisplayChar)
-                .unwrap();
-        }
-        Interrupt::ReadChar => {
-            let char = Term::stdout().read_char().expect("Unable to read char");
-            interpreter
-                .answer_interrupt(InterruptResult::ReadChar(char))
-                .unwrap();
-        }
-        Interrupt::ReadNumber => {
-            let num = Term::stdout().read_line().expect("Unable to read line");
-            let num = num.trim().parse::<i32>().expect("Unable to parse number");
-            interpreter
-                .answer_interrupt(InterruptResult::ReadNumber(num))
-                .unwrap();
-        }
-        Interrupt::ReadKeyboardString => {
-            let string = Term::stdout().read_line().expect("Unable to read line");
-            interpreter
-                .answer_interrupt(InterruptResult::ReadKeyboardString(string))
-                .unwrap();
-        }
-        Interrupt::Terminate => {
-            interpreter
-                .answer_interrupt(InterruptResult::Terminate)
-                .unwrap();
-        }
-    }
-}
@@ -0,0 +1,164 @@
+use alloc::{format, string::{String, ToString}, vec, vec::Vec};
+use core::{error::Error, fmt, str::FromStr};
+
+use crate::assembler::MachineCode;
+
+/*
+    Memory images for FPGA block RAM and ROM programmers. The image is the machine code of the program,
+    instructions and data, from the lowest to the highest address it uses, the gaps between them are zero.
+    The image is split in words of the configured width, big endian like the 68k, and padded with zeros
+    up to the depth, the number of words of the memory. Word 0 is the first address of the image.
+    The formats are:
+        mif     Memory Initialization File of Intel/Altera Quartus
+        hex     text read by $readmemh in Verilog, one word per line
+        coe     coefficient file of the Xilinx block memory generator
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryImageFormat {
+    Mif,
+    VerilogHex,
+    Coe,
+}
+
+impl FromStr for MemoryImageFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "mif" => Ok(MemoryImageFormat::Mif),
+            "hex" => Ok(MemoryImageFormat::VerilogHex),
+            "coe" => Ok(MemoryImageFormat::Coe),
+            _ => Err(format!("Unknown memory image format \"{}\", expected mif, hex or coe", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryImageOptions {
+    /// Bits of a word, a multiple of 8 up to 64
+    pub word_width: usize,
+    /// Words of the memory, the image is padded up to it. When missing the memory is as big as the image
+    pub depth: Option<usize>,
+}
+
+impl Default for MemoryImageOptions {
+    fn default() -> Self {
+        Self {
+            word_width: 8,
+            depth: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MemoryImageError {
+    InvalidWordWidth(usize),
+    TooLarge { words: usize, depth: usize },
+}
+
+impl fmt::Display for MemoryImageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MemoryImageError::InvalidWordWidth(width) => write!(
+                f,
+                "Invalid word width {}, it must be a multiple of 8 between 8 and 64",
+                width
+            ),
+            MemoryImageError::TooLarge { words, depth } => {
+                write!(f, "The image is {} words long, it doesn't fit a depth of {}", words, depth)
+            }
+        }
+    }
+}
+
+impl Error for MemoryImageError {}
+
+impl From<MemoryImageError> for String {
+    fn from(error: MemoryImageError) -> Self {
+        error.to_string()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryImage {
+    pub start_address: usize,
+    pub bytes: Vec<u8>,
+}
+
+impl MemoryImage {
+    pub fn new(start_address: usize, bytes: Vec<u8>) -> Self {
+        Self { start_address, bytes }
+    }
+    pub fn from_machine_code(machine_code: &MachineCode) -> Self {
+        Self::new(machine_code.origin, machine_code.bytes.clone())
+    }
+    /// The words of the memory, the last word of the image is padded with zeros if it is not complete
+    pub fn get_words(&self, options: &MemoryImageOptions) -> Result<Vec<u64>, MemoryImageError> {
+        let width = options.word_width;
+        if width == 0 || width > 64 || !width.is_multiple_of(8) {
+            return Err(MemoryImageError::InvalidWordWidth(width));
+        }
+        let mut words: Vec<u64> = self
+            .bytes
+            .chunks(width / 8)
+            .map(|chunk| {
+                (0..width / 8).fold(0, |word, i| (word << 8) | *chunk.get(i).unwrap_or(&0) as u64)
+            })
+            .collect();
+        let depth = options.depth.unwrap_or(words.len());
+        if words.len() > depth {
+            return Err(MemoryImageError::TooLarge {
+                words: words.len(),
+                depth,
+            });
+        }
+        words.resize(depth, 0);
+        Ok(words)
+    }
+    pub fn write(&self, format: MemoryImageFormat, options: &MemoryImageOptions) -> Result<String, MemoryImageError> {
+        let words = self.get_words(options)?;
+        let digits = options.word_width / 4;
+        let hex = |word: &u64| format!("{:0width$X}", word, width = digits);
+        let image = match format {
+            MemoryImageFormat::Mif => {
+                let address_digits = format!("{:X}", words.len().saturating_sub(1)).len();
+                let mut mif = format!(
+                    "-- Starts at address ${:X}\nWIDTH={};\nDEPTH={};\n\nADDRESS_RADIX=HEX;\nDATA_RADIX=HEX;\n\nCONTENT BEGIN\n",
+                    self.start_address,
+                    options.word_width,
+                    words.len()
+                );
+                //the zeros at the end are written as a single range
+                let used = words.iter().rposition(|word| *word != 0).map(|i| i + 1).unwrap_or(0);
+                for (i, word) in words[..used].iter().enumerate() {
+                    mif.push_str(&format!("\t{:0width$X} : {};\n", i, hex(word), width = address_digits));
+                }
+                if used < words.len() {
+                    mif.push_str(&format!(
+                        "\t[{:0width$X}..{:0width$X}] : {};\n",
+                        used,
+                        words.len() - 1,
+                        hex(&0),
+                        width = address_digits
+                    ));
+                }
+                mif.push_str("END;\n");
+                mif
+            }
+            MemoryImageFormat::VerilogHex => {
+                let mut lines = vec![format!("// Starts at address ${:X}", self.start_address)];
+                lines.extend(words.iter().map(hex));
+                lines.join("\n") + "\n"
+            }
+            MemoryImageFormat::Coe => {
+                let words: Vec<String> = words.iter().map(hex).collect();
+                format!(
+                    "; Starts at address ${:X}\nmemory_initialization_radix=16;\nmemory_initialization_vector=\n{};\n",
+                    self.start_address,
+                    words.join(",\n")
+                )
+            }
+        };
+        Ok(image)
+    }
+}
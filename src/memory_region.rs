@@ -0,0 +1,84 @@
+use alloc::string::String;
+use core::fmt;
+
+use bitflags::bitflags;
+use serde::{Deserialize, Serialize};
+
+/*
+    Named ranges of the memory with the accesses they allow, like the ROM, RAM and stack of a board.
+    Without regions every address of the memory can be read, written and executed, once one is added the
+    addresses outside of every region can't be accessed at all. When regions overlap the first one added wins.
+    The program and its data are loaded before the permissions are checked, so a ROM can hold the code and
+    its constants, only the writes of the running program are refused
+*/
+
+bitflags! {
+    #[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+    pub struct Permissions: u8 {
+        const READ = 1<<0;
+        const WRITE = 1<<1;
+        const EXECUTE = 1<<2;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Access {
+    Read,
+    Write,
+    Execute,
+}
+
+impl Access {
+    pub fn get_permission(&self) -> Permissions {
+        match self {
+            Access::Read => Permissions::READ,
+            Access::Write => Permissions::WRITE,
+            Access::Execute => Permissions::EXECUTE,
+        }
+    }
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Access::Read => write!(f, "read"),
+            Access::Write => write!(f, "write"),
+            Access::Execute => write!(f, "execute"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MemoryRegion {
+    pub name: String,
+    pub start: usize,
+    /// Excluded from the region
+    pub end: usize,
+    pub permissions: Permissions,
+}
+
+impl MemoryRegion {
+    pub fn new(name: impl Into<String>, start: usize, end: usize, permissions: Permissions) -> MemoryRegion {
+        MemoryRegion {
+            name: name.into(),
+            start,
+            end,
+            permissions,
+        }
+    }
+    /// Code and constants, it can be read and executed
+    pub fn rom(start: usize, end: usize) -> MemoryRegion {
+        MemoryRegion::new("rom", start, end, Permissions::READ | Permissions::EXECUTE)
+    }
+    /// Everything is allowed, the code can also be in RAM
+    pub fn ram(start: usize, end: usize) -> MemoryRegion {
+        MemoryRegion::new("ram", start, end, Permissions::all())
+    }
+    /// Can be read and written but not executed
+    pub fn stack(start: usize, end: usize) -> MemoryRegion {
+        MemoryRegion::new("stack", start, end, Permissions::READ | Permissions::WRITE)
+    }
+    pub fn contains(&self, address: usize) -> bool {
+        self.start <= address && address < self.end
+    }
+}
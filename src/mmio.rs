@@ -0,0 +1,60 @@
+use alloc::{boxed::Box, string::{String, ToString}};
+use core::{cell::RefCell, fmt};
+
+use crate::instructions::Size;
+
+/*
+    Peripherals mapped in the address space, like UARTs, timers or framebuffers, implemented by the host.
+    Every read and write of the program inside the range of a device goes to the device instead of the memory,
+    with the offset from the start of the range and the size of the access, a long access is a single call.
+    The region permissions, protected ranges and watchpoints still apply to the range.
+    Reading a device can have side effects, like taking a byte from a receive buffer, so the debuggers that
+    peek the memory and the history don't read it: they see the memory under the device, and undo doesn't
+    restore what was written to a device
+*/
+
+pub trait MmioDevice {
+    fn get_name(&self) -> &str;
+    /// Value of the access at the offset from the start of the range
+    fn read(&mut self, offset: usize, size: Size) -> u32;
+    fn write(&mut self, offset: usize, size: Size, value: u32);
+}
+
+pub struct MappedDevice {
+    pub start: usize,
+    /// Excluded from the range
+    pub end: usize,
+    //reads borrow the memory immutably and the device can change when it is read
+    device: RefCell<Box<dyn MmioDevice>>,
+}
+
+impl MappedDevice {
+    pub fn new(start: usize, end: usize, device: Box<dyn MmioDevice>) -> MappedDevice {
+        MappedDevice {
+            start,
+            end,
+            device: RefCell::new(device),
+        }
+    }
+    pub fn contains(&self, address: usize) -> bool {
+        self.start <= address && address < self.end
+    }
+    pub fn overlaps(&self, start: usize, end: usize) -> bool {
+        start < self.end && self.start < end
+    }
+    pub fn get_name(&self) -> String {
+        self.device.borrow().get_name().to_string()
+    }
+    pub fn read(&self, address: usize, size: Size) -> u32 {
+        self.device.borrow_mut().read(address - self.start, size)
+    }
+    pub fn write(&self, address: usize, size: Size, value: u32) {
+        self.device.borrow_mut().write(address - self.start, size, value)
+    }
+}
+
+impl fmt::Debug for MappedDevice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "MappedDevice({} ${:X}-${:X})", self.get_name(), self.start, self.end)
+    }
+}
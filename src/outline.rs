@@ -0,0 +1,166 @@
+use alloc::{string::{String, ToString}, vec, vec::Vec};
+use serde::Serialize;
+
+use crate::{
+    lexer::{LexedLine, ParsedLine},
+    macros::expand_macros,
+    references::SourceSpan,
+    S68k,
+};
+
+/*
+    Outline of a file for the symbols sidebar of editors, in the shape of the LSP document symbols.
+    Labels and equs are at the top level, a label that starts with a dot is a local label and is a child
+    of the label before it. A label covers the lines up to the next label of the same level.
+    The value of an equ is evaluated when the assembler is enabled and the expression only has numbers.
+    Macros are at the top level and cover their definition. Sections are not part of the language yet,
+    they will be listed here once the lexer has them.
+    Columns are in UTF-16 code units like the semantic tokens
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum OutlineKind {
+    Label,
+    Constant,
+    Macro,
+    Section,
+}
+
+/// Range over many lines, the end is exclusive
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct SourceRange {
+    pub start_line: usize,
+    pub start: usize,
+    pub end_line: usize,
+    pub end: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct OutlineSymbol {
+    pub name: String,
+    pub kind: OutlineKind,
+    /// The expression of an equ
+    pub detail: Option<String>,
+    /// The value of an equ, missing if it could not be evaluated
+    pub value: Option<i64>,
+    /// The whole symbol, a label includes the lines under it
+    pub range: SourceRange,
+    /// The name in the definition
+    pub selection_range: SourceSpan,
+    pub children: Vec<OutlineSymbol>,
+}
+
+fn utf16_len(text: &str) -> usize {
+    text.encode_utf16().count()
+}
+
+fn get_name_span(line: &ParsedLine, name: &str) -> SourceSpan {
+    let start = line.line.find(name).map(|i| utf16_len(&line.line[..i])).unwrap_or(0);
+    SourceSpan {
+        line: line.line_index,
+        start,
+        end: start + utf16_len(name),
+    }
+}
+
+fn evaluate(expression: &str) -> Option<i64> {
+    crate::expression::evaluate_constant(expression).ok()
+}
+
+fn new_symbol(line: &ParsedLine, name: &str, kind: OutlineKind, detail: Option<String>) -> OutlineSymbol {
+    let selection_range = get_name_span(line, name);
+    OutlineSymbol {
+        name: name.to_string(),
+        kind,
+        value: detail.as_deref().and_then(evaluate),
+        detail,
+        range: SourceRange {
+            start_line: line.line_index,
+            start: 0,
+            end_line: line.line_index,
+            end: utf16_len(&line.line),
+        },
+        selection_range,
+        children: vec![],
+    }
+}
+
+/// Extends the label to the end of the line before the next one
+fn close_label(symbol: &mut OutlineSymbol, next_line: usize, code_lines: &[&str]) {
+    let end_line = next_line.saturating_sub(1).max(symbol.range.start_line);
+    symbol.range.end_line = end_line;
+    symbol.range.end = code_lines.get(end_line).map(|line| utf16_len(line)).unwrap_or(0);
+}
+
+pub fn get_outline(s68k: &S68k) -> Vec<OutlineSymbol> {
+    let code_lines: Vec<&str> = s68k.get_code().lines().collect();
+    let mut outline: Vec<OutlineSymbol> = vec![];
+    //index in the outline of the label that gets the local labels
+    let mut parent: Option<usize> = None;
+    for line in s68k.get_lexed_lines() {
+        match &line.parsed {
+            LexedLine::Label { name, local: true, .. } => {
+                let symbol = new_symbol(line, name, OutlineKind::Label, None);
+                match parent {
+                    Some(parent) => {
+                        let children = &mut outline[parent].children;
+                        if let Some(previous) = children.last_mut() {
+                            close_label(previous, line.line_index, &code_lines);
+                        }
+                        children.push(symbol);
+                    }
+                    None => outline.push(symbol),
+                }
+            }
+            LexedLine::Label { name, .. } => {
+                if let Some(parent) = parent {
+                    let parent = &mut outline[parent];
+                    close_label(parent, line.line_index, &code_lines);
+                    if let Some(child) = parent.children.last_mut() {
+                        close_label(child, line.line_index, &code_lines);
+                    }
+                }
+                parent = Some(outline.len());
+                outline.push(new_symbol(line, name, OutlineKind::Label, None));
+            }
+            LexedLine::Directive { name, args, .. } if name == "equ" && args.len() >= 3 => {
+                let detail = Some(args[2..].join(" "));
+                outline.push(new_symbol(line, &args[0], OutlineKind::Constant, detail));
+            }
+            _ => {}
+        }
+    }
+    if let Some(parent) = parent {
+        let parent = &mut outline[parent];
+        close_label(parent, code_lines.len(), &code_lines);
+        if let Some(child) = parent.children.last_mut() {
+            close_label(child, code_lines.len(), &code_lines);
+        }
+    }
+    let lines = code_lines.iter().map(|line| line.to_string()).collect::<Vec<String>>();
+    for definition in expand_macros(&lines).macros {
+        let header = code_lines[definition.line_index];
+        let start = header.find(&definition.name).map(|i| utf16_len(&header[..i])).unwrap_or(0);
+        let symbol = OutlineSymbol {
+            kind: OutlineKind::Macro,
+            detail: None,
+            value: None,
+            range: SourceRange {
+                start_line: definition.line_index,
+                start: 0,
+                end_line: definition.end_line_index,
+                end: utf16_len(code_lines[definition.end_line_index]),
+            },
+            selection_range: SourceSpan {
+                line: definition.line_index,
+                start,
+                end: start + utf16_len(&definition.name),
+            },
+            children: vec![],
+            name: definition.name,
+        };
+        let position = outline.partition_point(|symbol| symbol.range.start_line < definition.line_index);
+        outline.insert(position, symbol);
+    }
+    outline
+}
@@ -0,0 +1,203 @@
+use alloc::{collections::BTreeMap, format, string::{String, ToString}, vec, vec::Vec};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    lexer::{LexedLine, LexedOperand, LexedSize, Lexer, ParsedLine},
+    utils::split_comment,
+};
+
+/*
+    Prints the lexed lines back to assembly, so a pass can rewrite the AST and get source out of it.
+    Without trivia every line is regenerated from its nodes, with trivia the comments of the source are kept
+    and the lines whose nodes were not changed are copied from the source as they are, so printing an
+    unchanged program gives back the same text. The equ replacement is already applied to the lexed lines,
+    a regenerated line has the values in place of the constants
+*/
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PrinterOptions {
+    /// Indentation of the lines without a label
+    pub indent: String,
+    /// Keep comments and the unchanged lines of the source
+    pub keep_trivia: bool,
+}
+
+impl Default for PrinterOptions {
+    fn default() -> Self {
+        Self {
+            indent: "    ".to_string(),
+            keep_trivia: true,
+        }
+    }
+}
+
+pub(crate) fn get_size_suffix(size: &LexedSize) -> &'static str {
+    match size {
+        LexedSize::Byte => ".b",
+        LexedSize::Word => ".w",
+        LexedSize::Long => ".l",
+        LexedSize::Single => ".s",
+        LexedSize::Double => ".d",
+        LexedSize::Extended => ".x",
+        LexedSize::Unspecified | LexedSize::Unknown => "",
+    }
+}
+
+/// Prints the movem mask as ranges, like d0-d2/a0
+pub fn print_register_mask(mask: u16) -> String {
+    let mut groups = vec![];
+    for (prefix, base) in [("d", 0), ("a", 8)] {
+        let mut i = 0;
+        while i < 8 {
+            if mask & (1 << (base + i)) == 0 {
+                i += 1;
+                continue;
+            }
+            let start = i;
+            while i < 8 && mask & (1 << (base + i)) != 0 {
+                i += 1;
+            }
+            match i - start {
+                1 => groups.push(format!("{}{}", prefix, start)),
+                _ => groups.push(format!("{}{}-{}{}", prefix, start, prefix, i - 1)),
+            }
+        }
+    }
+    groups.join("/")
+}
+
+fn print_operands(operands: &[LexedOperand]) -> String {
+    operands.iter().map(print_operand).collect::<Vec<String>>().join(",")
+}
+
+pub fn print_operand(operand: &LexedOperand) -> String {
+    match operand {
+        LexedOperand::Immediate(value)
+        | LexedOperand::Absolute(value)
+        | LexedOperand::Label(value)
+        | LexedOperand::Other(value) => value.clone(),
+        LexedOperand::RegisterRange { mask } => print_register_mask(*mask),
+        LexedOperand::Register(_, name) => name.clone(),
+        LexedOperand::RegisterWithSize(_, name, size) => format!("{}{}", name, get_size_suffix(size)),
+        LexedOperand::ScaledRegister(_, name, size, scale) => {
+            format!("{}{}*{}", name, get_size_suffix(size), scale)
+        }
+        LexedOperand::RegisterPair(high, low) => format!("{}:{}", high, low),
+        LexedOperand::Bitfield { operand, offset, width } => {
+            format!("{}{{{}:{}}}", print_operand(operand), offset, width)
+        }
+        LexedOperand::Indirect(operand) => format!("({})", print_operand(operand)),
+        LexedOperand::IndirectDisplacement { offset, operand } => {
+            format!("{}({})", offset, print_operand(operand))
+        }
+        LexedOperand::IndirectIndex { offset, operands } => format!("{}({})", offset, print_operands(operands)),
+        LexedOperand::MemoryIndirect { inner, outer } => match outer.is_empty() {
+            true => format!("([{}])", print_operands(inner)),
+            false => format!("([{}],{})", print_operands(inner), print_operands(outer)),
+        },
+        LexedOperand::PostIndirect(operand) => format!("({})+", print_operand(operand)),
+        LexedOperand::PreIndirect(operand) => format!("-({})", print_operand(operand)),
+    }
+}
+
+/// Prints the code of the line, without indentation
+pub fn print_lexed_line(line: &LexedLine) -> String {
+    match line {
+        LexedLine::Label { name, .. } => format!("{}:", name),
+        LexedLine::Instruction { name, operands, size } => {
+            let mnemonic = format!("{}{}", name, get_size_suffix(size));
+            match operands.is_empty() {
+                true => mnemonic,
+                false => {
+                    let operands = operands.iter().map(print_operand).collect::<Vec<String>>();
+                    format!("{} {}", mnemonic, operands.join(", "))
+                }
+            }
+        }
+        //the first arg is the directive as written, or the name of the constant for equ
+        LexedLine::Directive { name, size, args } => match (name.as_str(), &args[..]) {
+            ("equ", [constant, _, values @ ..]) => format!("{} equ {}", constant, values.join(", ")),
+            (_, [directive, values @ ..]) if !values.is_empty() => format!("{} {}", directive, values.join(", ")),
+            (_, [directive]) => directive.clone(),
+            _ => format!("{}{}", name, get_size_suffix(size)),
+        },
+        LexedLine::Comment { content } | LexedLine::Unknown { content } | LexedLine::Error { content, .. } => {
+            content.clone()
+        }
+        LexedLine::Empty => String::new(),
+    }
+}
+
+/// Prints the nodes of one source line, the label is in the first column and the rest is indented
+pub(crate) fn print_source_line(nodes: &[&LexedLine], indent: &str) -> String {
+    let mut labels = vec![];
+    let mut code = vec![];
+    for node in nodes {
+        match node {
+            LexedLine::Label { .. } => labels.push(print_lexed_line(node)),
+            LexedLine::Empty => {}
+            _ => code.push(print_lexed_line(node)),
+        }
+    }
+    //the name of an equ is in the first column like a label
+    let is_equ = nodes
+        .iter()
+        .any(|node| matches!(node, LexedLine::Directive { name, .. } if name == "equ"));
+    match (labels.is_empty(), code.is_empty()) {
+        (_, true) => labels.join(" "),
+        (true, false) if is_equ => code.join(" "),
+        (true, false) => format!("{}{}", indent, code.join(" ")),
+        (false, false) => format!("{} {}", labels.join(" "), code.join(" ")),
+    }
+}
+
+fn group_by_source_line(lines: &[ParsedLine]) -> BTreeMap<usize, Vec<&ParsedLine>> {
+    let mut grouped: BTreeMap<usize, Vec<&ParsedLine>> = BTreeMap::new();
+    for line in lines {
+        grouped.entry(line.line_index).or_default().push(line);
+    }
+    grouped
+}
+
+/// Prints the program, a line of the source is printed once even if it was lexed into many lines
+pub fn print_program(lines: &[ParsedLine], options: &PrinterOptions) -> String {
+    let grouped = group_by_source_line(lines);
+    //the source is lexed again to find which lines were changed
+    let original = match options.keep_trivia {
+        true => {
+            let source = grouped
+                .values()
+                .map(|nodes| nodes[0].line.as_str())
+                .collect::<Vec<&str>>()
+                .join("\n");
+            let mut lexer = Lexer::new();
+            let lexed = lexer.lex_lossy(&source).clone();
+            let mut original: BTreeMap<usize, String> = BTreeMap::new();
+            for (index, nodes) in group_by_source_line(&lexed) {
+                let nodes = nodes.iter().map(|line| &line.parsed).collect::<Vec<&LexedLine>>();
+                original.insert(index, print_source_line(&nodes, &options.indent));
+            }
+            Some(original)
+        }
+        false => None,
+    };
+    let mut result = vec![];
+    for (position, nodes) in grouped.values().enumerate() {
+        let source = nodes[0].line.as_str();
+        let nodes = nodes.iter().map(|line| &line.parsed).collect::<Vec<&LexedLine>>();
+        let printed = print_source_line(&nodes, &options.indent);
+        let line = match &original {
+            Some(original) if original.get(&position) == Some(&printed) => source.to_string(),
+            Some(_) => match split_comment(source) {
+                (_, Some(comment)) if printed.is_empty() => comment.trim().to_string(),
+                (_, Some(comment)) => format!("{} {}", printed, comment.trim()),
+                (_, None) => printed,
+            },
+            None => printed,
+        };
+        result.push(line);
+    }
+    result.join("\n")
+}
@@ -0,0 +1,157 @@
+//the pyo3 macros expand to conversions that clippy reports on the function signatures
+#![allow(clippy::useless_conversion)]
+
+use pyo3::{exceptions::PyValueError, prelude::*, types::PyBytes};
+
+use crate::{
+    cpu_model::CpuModel,
+    interpreter::{Interpreter, InterpreterOptions, InterpreterStatus, RuntimeError},
+    S68k,
+};
+
+/*
+    Python module for autograders and notebooks:
+
+        import s68k
+        errors = s68k.check(code)               # list of (line_index, message)
+        interpreter = s68k.Interpreter(code, model="68000")
+        interpreter.add_breakpoint(3)
+        status = interpreter.run()              # "Running" when stopped at a breakpoint
+        interpreter.d_reg(0)
+
+    Assembling errors are raised as ValueError with one diagnostic per line
+*/
+
+fn runtime_error(e: RuntimeError) -> PyErr {
+    PyValueError::new_err(e.get_message())
+}
+
+fn status_name(status: InterpreterStatus) -> &'static str {
+    match status {
+        InterpreterStatus::Running => "Running",
+        InterpreterStatus::Interrupt => "Interrupt",
+        InterpreterStatus::Terminated => "Terminated",
+        InterpreterStatus::TerminatedWithException => "TerminatedWithException",
+    }
+}
+
+fn create_s68k(code: &str, model: &str) -> PyResult<S68k> {
+    let model: CpuModel = model.parse().map_err(PyValueError::new_err)?;
+    let mut s68k = S68k::new(code.to_string());
+    s68k.set_cpu_model(model);
+    Ok(s68k)
+}
+
+#[pyfunction]
+#[pyo3(signature = (code, model = "68000"))]
+fn check(code: &str, model: &str) -> PyResult<Vec<(usize, String)>> {
+    let s68k = create_s68k(code, model)?;
+    Ok(s68k
+        .semantic_check()
+        .iter()
+        .map(|e| (e.get_line_index(), e.get_message()))
+        .collect())
+}
+
+#[pyclass(unsendable, name = "Interpreter")]
+pub(crate) struct PyInterpreter {
+    interpreter: Interpreter,
+    breakpoints: Vec<usize>,
+}
+
+#[pymethods]
+impl PyInterpreter {
+    #[new]
+    #[pyo3(signature = (code, model = "68000", fpu = false))]
+    pub(crate) fn new(code: &str, model: &str, fpu: bool) -> PyResult<Self> {
+        let s68k = create_s68k(code, model)?;
+        let errors = s68k.semantic_check();
+        if !errors.is_empty() {
+            let messages: Vec<String> = errors.iter().map(|e| e.get_message()).collect();
+            return Err(PyValueError::new_err(messages.join("\n")));
+        }
+        let compiled = s68k.compile().map_err(|e| PyValueError::new_err(e.to_string()))?;
+        let options = InterpreterOptions {
+            fpu,
+            ..Default::default()
+        };
+        Ok(Self {
+            interpreter: s68k.create_interpreter(compiled, Some(options)),
+            breakpoints: vec![],
+        })
+    }
+    pub(crate) fn step(&mut self) -> PyResult<&'static str> {
+        self.interpreter.step().map(status_name).map_err(runtime_error)
+    }
+    /// Runs until a breakpoint, an interrupt or the end of the program
+    #[pyo3(signature = (limit = None))]
+    pub(crate) fn run(&mut self, limit: Option<usize>) -> PyResult<&'static str> {
+        self.interpreter
+            .run_with_breakpoints(&self.breakpoints, limit)
+            .map(status_name)
+            .map_err(runtime_error)
+    }
+    fn undo(&mut self) -> PyResult<()> {
+        self.interpreter.undo().map(|_| ()).map_err(runtime_error)
+    }
+    pub(crate) fn add_breakpoint(&mut self, line: usize) {
+        if !self.breakpoints.contains(&line) {
+            self.breakpoints.push(line);
+        }
+    }
+    fn remove_breakpoint(&mut self, line: usize) {
+        self.breakpoints.retain(|l| *l != line);
+    }
+    #[getter]
+    fn breakpoints(&self) -> Vec<usize> {
+        self.breakpoints.clone()
+    }
+    #[getter]
+    fn status(&self) -> &'static str {
+        status_name(*self.interpreter.get_status())
+    }
+    #[getter]
+    fn pc(&self) -> usize {
+        self.interpreter.get_pc()
+    }
+    #[getter]
+    fn ccr(&self) -> u8 {
+        self.interpreter.get_cpu().wasm_get_ccr().to_ccr()
+    }
+    #[getter]
+    fn cycles(&self) -> u64 {
+        self.interpreter.get_cycles()
+    }
+    pub(crate) fn d_reg(&self, index: usize) -> PyResult<u32> {
+        match index {
+            0..=7 => Ok(self.interpreter.get_cpu().wasm_get_d_reg(index).get_long()),
+            _ => Err(PyValueError::new_err("Register index must be between 0 and 7")),
+        }
+    }
+    fn a_reg(&self, index: usize) -> PyResult<u32> {
+        match index {
+            0..=7 => Ok(self.interpreter.get_cpu().wasm_get_a_reg(index).get_long()),
+            _ => Err(PyValueError::new_err("Register index must be between 0 and 7")),
+        }
+    }
+    fn read_memory<'py>(&self, py: Python<'py>, address: usize, length: usize) -> PyResult<Bound<'py, PyBytes>> {
+        let bytes = self
+            .interpreter
+            .get_memory()
+            .read_bytes(address, length)
+            .map_err(runtime_error)?;
+        Ok(PyBytes::new_bound(py, &bytes))
+    }
+    fn write_memory(&mut self, address: usize, bytes: Vec<u8>) -> PyResult<()> {
+        self.interpreter
+            .set_memory_bytes(address, &bytes)
+            .map_err(runtime_error)
+    }
+}
+
+#[pymodule]
+fn s68k(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_function(wrap_pyfunction!(check, module)?)?;
+    module.add_class::<PyInterpreter>()?;
+    Ok(())
+}
@@ -0,0 +1,217 @@
+use alloc::{format, string::{String, ToString}, vec, vec::Vec};
+use serde::Serialize;
+
+use crate::{
+    grading::get_register_name,
+    instructions::{RegisterOperand, Size},
+    interpreter::InterpreterStatus,
+    lexer::{LexedLine, LexedOperand, LexedRegisterType, LexedSize, ParsedLine},
+    visitor::VisitorMut,
+    S68k,
+};
+
+/*
+    "Predict the result" quizzes: the snippet is run and the value of a register at the end is the answer,
+    the wrong choices come from the mistakes students make the most:
+        ignoring the size       the snippet run again with every .b and .w instruction made .l
+        decimal read as hex     the snippet run again with #10 read as #$10
+        swapped operands        the snippet run again with sub between registers computing source - destination
+        sign extension          the answer not sign extended if it was, or sign extended if it was not
+        off by one              the answer plus or minus one, only used when the others are not enough
+    A mistake that gives the right answer, or the same value as another mistake, is not used.
+    Registers start at 0, the snippet must set what it uses
+*/
+
+/// Instructions the snippet can run before it is considered stuck in a loop
+const STEP_LIMIT: usize = 10_000;
+const DISTRACTORS: usize = 3;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Distractor {
+    pub value: u32,
+    /// The mistake that gives this value
+    pub mistake: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct QuizItem {
+    pub snippet: String,
+    pub question: String,
+    pub answer: u32,
+    pub distractors: Vec<Distractor>,
+}
+
+impl QuizItem {
+    /// The answer and the distractors sorted, so the position of the answer doesn't give it away
+    pub fn get_choices(&self) -> Vec<u32> {
+        let mut choices: Vec<u32> = self.distractors.iter().map(|d| d.value).collect();
+        choices.push(self.answer);
+        choices.sort_unstable();
+        choices
+    }
+}
+
+/// Runs the lines and reads the register at the end
+fn run_lines(lines: Vec<ParsedLine>, register: &RegisterOperand) -> Result<u32, String> {
+    let s68k = S68k::from_lines(lines);
+    if let Some(error) = s68k.semantic_check().first() {
+        return Err(error.get_message());
+    }
+    let mut interpreter = s68k.create_interpreter(s68k.compile()?, None);
+    match interpreter.run_with_limit(STEP_LIMIT) {
+        Ok(InterpreterStatus::Terminated) => Ok(interpreter.get_register_value(register, Size::Long)),
+        Ok(_) => Err("The snippet stopped with an exception".to_string()),
+        Err(error) => Err(error.to_string()),
+    }
+}
+
+/// The mistakes that are made by rewriting the snippet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mistake {
+    IgnoredSize,
+    DecimalAsHex,
+    SwappedOperands,
+}
+
+impl Mistake {
+    fn get_description(self) -> &'static str {
+        match self {
+            Mistake::IgnoredSize => "ignored the .b and .w sizes and worked on long words",
+            Mistake::DecimalAsHex => "read decimal immediates like #10 as hexadecimal",
+            Mistake::SwappedOperands => "subtracted the destination from the source",
+        }
+    }
+}
+
+struct Rewriter {
+    mistake: Mistake,
+    changed: bool,
+}
+
+impl VisitorMut for Rewriter {
+    fn visit_instruction_mut(&mut self, _name: &mut String, size: &mut LexedSize, operands: &mut Vec<LexedOperand>) {
+        match self.mistake {
+            Mistake::IgnoredSize => {
+                if matches!(size, LexedSize::Byte | LexedSize::Word) {
+                    *size = LexedSize::Long;
+                    self.changed = true;
+                }
+            }
+            Mistake::DecimalAsHex => {
+                for operand in operands {
+                    if let LexedOperand::Immediate(value) = operand {
+                        let digits = &value[1..];
+                        if digits.len() > 1 && digits.chars().all(|c| c.is_ascii_digit()) {
+                            *value = format!("#${}", digits);
+                            self.changed = true;
+                        }
+                    }
+                }
+            }
+            //inserts lines instead, see negate_subtractions
+            Mistake::SwappedOperands => {}
+        }
+    }
+}
+
+/// Adds a neg after every sub between data registers, destination - source becomes source - destination
+fn negate_subtractions(lines: &[ParsedLine]) -> Option<Vec<ParsedLine>> {
+    let mut result = vec![];
+    let mut changed = false;
+    for line in lines {
+        result.push(line.clone());
+        if let LexedLine::Instruction { name, size, operands } = &line.parsed {
+            if let [LexedOperand::Register(LexedRegisterType::Data, _), destination @ LexedOperand::Register(LexedRegisterType::Data, _)] =
+                &operands[..]
+            {
+                if name == "sub" {
+                    let neg = LexedLine::Instruction {
+                        name: "neg".to_string(),
+                        size: size.clone(),
+                        operands: vec![destination.clone()],
+                    };
+                    result.push(ParsedLine::new(neg, line.line.clone(), line.line_index));
+                    changed = true;
+                }
+            }
+        }
+    }
+    match changed {
+        true => Some(result),
+        false => None,
+    }
+}
+
+fn run_with_mistake(lines: &[ParsedLine], register: &RegisterOperand, mistake: Mistake) -> Option<u32> {
+    if mistake == Mistake::SwappedOperands {
+        return run_lines(negate_subtractions(lines)?, register).ok();
+    }
+    let mut rewriter = Rewriter {
+        mistake,
+        changed: false,
+    };
+    let mut lines = lines.to_vec();
+    for line in &mut lines {
+        rewriter.visit_parsed_line_mut(line);
+    }
+    match rewriter.changed {
+        true => run_lines(lines, register).ok(),
+        false => None,
+    }
+}
+
+/// The sign extension mistakes of the answer
+fn get_extension_mistakes(answer: u32) -> Vec<(u32, &'static str)> {
+    let mut mistakes = vec![];
+    for (size, bits) in [(Size::Byte, 8), (Size::Word, 16)] {
+        let mask = (1u32 << bits) - 1;
+        let sign = 1u32 << (bits - 1);
+        let low = answer & mask;
+        let extended = match size {
+            Size::Byte => low as u8 as i8 as i32 as u32,
+            _ => low as u16 as i16 as i32 as u32,
+        };
+        if low & sign == 0 {
+            continue;
+        }
+        if answer == extended {
+            mistakes.push((low, "forgot the sign extension"));
+        } else if answer == low {
+            mistakes.push((extended, "sign extended a value that is not"));
+        }
+    }
+    mistakes
+}
+
+/// Runs the snippet and makes a quiz on the value of the register after it
+pub fn generate_quiz(snippet: &str, register: RegisterOperand) -> Result<QuizItem, String> {
+    let s68k = S68k::new(snippet);
+    let lines = s68k.get_lexed_lines();
+    let answer = run_lines(lines.clone(), &register)?;
+    let mut candidates: Vec<(u32, String)> = vec![];
+    for mistake in [Mistake::IgnoredSize, Mistake::DecimalAsHex, Mistake::SwappedOperands] {
+        if let Some(value) = run_with_mistake(lines, &register, mistake) {
+            candidates.push((value, mistake.get_description().to_string()));
+        }
+    }
+    for (value, mistake) in get_extension_mistakes(answer) {
+        candidates.push((value, mistake.to_string()));
+    }
+    candidates.push((answer.wrapping_add(1), "off by one".to_string()));
+    candidates.push((answer.wrapping_sub(1), "off by one".to_string()));
+    let mut distractors: Vec<Distractor> = vec![];
+    for (value, mistake) in candidates {
+        if distractors.len() == DISTRACTORS {
+            break;
+        }
+        if value != answer && !distractors.iter().any(|d| d.value == value) {
+            distractors.push(Distractor { value, mistake });
+        }
+    }
+    Ok(QuizItem {
+        snippet: snippet.to_string(),
+        question: format!("What is the value of {} after the code runs?", get_register_name(&register).to_uppercase()),
+        answer,
+        distractors,
+    })
+}
@@ -0,0 +1,87 @@
+use alloc::{string::String, vec::Vec};
+use serde::Serialize;
+
+use crate::{
+    semantic_tokens::{SemanticToken, SemanticTokenType, DECLARATION_MODIFIER},
+    S68k,
+};
+
+/*
+    Go to definition and find all references, built on the semantic tokens so a word is only a symbol
+    where the classification says it is one. Labels, equs and macros are symbols, the spans use the same
+    UTF-16 columns as the semantic tokens. SET symbols and register aliases are not part of the language yet,
+    they will be found here once the semantic tokens classify them
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct SourceSpan {
+    pub line: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SymbolReferences {
+    pub name: String,
+    pub token_type: SemanticTokenType,
+    /// Missing when the symbol is used but never defined
+    pub definition: Option<SourceSpan>,
+    /// Every use of the symbol, without the definition
+    pub references: Vec<SourceSpan>,
+}
+
+fn is_symbol(token_type: SemanticTokenType) -> bool {
+    matches!(
+        token_type,
+        SemanticTokenType::Label | SemanticTokenType::Constant | SemanticTokenType::Macro
+    )
+}
+
+fn get_span(token: &SemanticToken) -> SourceSpan {
+    SourceSpan {
+        line: token.line,
+        start: token.start,
+        end: token.start + token.length,
+    }
+}
+
+/// Text of the token, the columns of the tokens are in UTF-16 code units
+fn get_token_text(code: &str, token: &SemanticToken) -> String {
+    let line: Vec<u16> = code.lines().nth(token.line).unwrap_or_default().encode_utf16().collect();
+    let end = (token.start + token.length).min(line.len());
+    String::from_utf16_lossy(&line[token.start.min(end)..end])
+}
+
+/// Finds the symbol under the position and all of its uses, the column is in UTF-16 code units
+pub fn find_references(s68k: &S68k, line: usize, column: usize) -> Option<SymbolReferences> {
+    let code = s68k.get_code();
+    let tokens: Vec<SemanticToken> = s68k
+        .get_semantic_tokens()
+        .into_iter()
+        .filter(|token| is_symbol(token.token_type))
+        .collect();
+    //the cursor can also be right after the symbol
+    let target = tokens
+        .iter()
+        .find(|token| token.line == line && token.start <= column && column <= token.start + token.length)?;
+    let name = get_token_text(code, target);
+    let same_symbol: Vec<&SemanticToken> = tokens
+        .iter()
+        .filter(|token| token.token_type == target.token_type && get_token_text(code, token) == name)
+        .collect();
+    let is_definition = |token: &&&SemanticToken| token.modifiers & DECLARATION_MODIFIER != 0;
+    Some(SymbolReferences {
+        definition: same_symbol.iter().find(is_definition).map(|token| get_span(token)),
+        references: same_symbol
+            .iter()
+            .filter(|token| !is_definition(token))
+            .map(|token| get_span(token))
+            .collect(),
+        token_type: target.token_type,
+        name,
+    })
+}
+
+pub fn find_definition(s68k: &S68k, line: usize, column: usize) -> Option<SourceSpan> {
+    find_references(s68k, line, column)?.definition
+}
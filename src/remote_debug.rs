@@ -0,0 +1,469 @@
+use std::io::{BufRead, Write};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{
+    cpu_model::CpuModel,
+    instructions::{Interrupt, InterruptResult, Label},
+    interpreter::{Interpreter, InterpreterOptions, InterpreterStatus},
+    S68k,
+};
+
+/*
+    Remote debugging protocol, it lets a UI control an interpreter that runs somewhere else, like a server
+    or a web worker. Every message is a JSON object, the server doesn't know about the transport: it takes
+    the text of a request and gives back the texts to send, so a WebSocket sends each one as a text frame,
+    a worker with postMessage and stdio one per line (see run).
+    A request has an id and a command, the response has the same id and either a result or an error:
+        { "id": 1, "command": "load", "code": "...", "cpuModel": "68000" }
+        { "id": 1, "result": { ...state } }
+        { "id": 2, "command": "step", "count": 2 }
+        { "id": 2, "error": "The program was not loaded" }
+    The commands are:
+        load { code, cpuModel? }            assembles the code and attaches to a new interpreter
+        attach                              attaches to the interpreter the server was made with
+        detach
+        setBreakpoints { lines }            result is the lines that have an instruction
+        step { count? }, stepOver, stepOut, stepBack
+        continue { limit? }                 runs until a breakpoint, the end or the limit of instructions
+        getState                            registers, flags, cycles, line and call stack
+        readMemory { address, length }      result is the bytes
+        answerInterrupt { result }          answers an input interrupt and resumes what was running
+    After the response the server pushes the events of the command, they have an "event" instead of an id:
+        { "event": "stopped", "reason": "breakpoint", "pc": 4096, "line": 3 }
+        { "event": "output", "text": "hello\n" }
+        { "event": "interrupt", "interrupt": { "type": "ReadNumber" } }
+        { "event": "terminated" }
+    Output traps are answered by the server, the traps that read input or the time are sent to the client
+    and execution waits for its answerInterrupt. Lines start at 0 like everywhere else in the crate
+*/
+
+const DEFAULT_LIMIT: usize = 1_000_000;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "command", rename_all = "camelCase")]
+pub enum RemoteCommand {
+    #[serde(rename_all = "camelCase")]
+    Load {
+        code: String,
+        cpu_model: Option<String>,
+    },
+    Attach,
+    Detach,
+    SetBreakpoints {
+        lines: Vec<usize>,
+    },
+    Step {
+        count: Option<usize>,
+    },
+    StepOver,
+    StepOut,
+    StepBack,
+    Continue {
+        limit: Option<usize>,
+    },
+    GetState,
+    ReadMemory {
+        address: usize,
+        length: usize,
+    },
+    AnswerInterrupt {
+        result: InterruptResult,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum StopReason {
+    Step,
+    Breakpoint,
+    Limit,
+    Exception,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "camelCase")]
+pub enum RemoteEvent {
+    Stopped {
+        reason: StopReason,
+        pc: usize,
+        line: Option<usize>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        message: Option<String>,
+    },
+    Output {
+        text: String,
+    },
+    Interrupt {
+        interrupt: Interrupt,
+    },
+    Terminated,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RemoteState {
+    pub status: InterpreterStatus,
+    pub pc: usize,
+    pub line: Option<usize>,
+    pub d: Vec<u32>,
+    pub a: Vec<u32>,
+    pub sr: u16,
+    pub cycles: u64,
+    pub call_stack: Vec<Label>,
+    pub can_step_back: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum StepMode {
+    Continue,
+    StepIn,
+    /// Stops once the call depth is back to the one when the step started
+    StepOver(usize),
+    StepOut(usize),
+}
+
+pub struct RemoteDebugServer {
+    interpreter: Option<Interpreter>,
+    breakpoints: Vec<usize>,
+    attached: bool,
+    /// What was running when an input interrupt was sent to the client, with its limit
+    pending: Option<(StepMode, usize)>,
+}
+
+impl Default for RemoteDebugServer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RemoteDebugServer {
+    pub fn new() -> Self {
+        Self {
+            interpreter: None,
+            breakpoints: vec![],
+            attached: false,
+            pending: None,
+        }
+    }
+    /// Serves an interpreter made by the host, stepping back needs the interpreter to keep its history
+    pub fn with_interpreter(interpreter: Interpreter) -> Self {
+        Self {
+            interpreter: Some(interpreter),
+            ..Self::new()
+        }
+    }
+    pub fn get_interpreter(&self) -> Option<&Interpreter> {
+        self.interpreter.as_ref()
+    }
+    pub fn is_attached(&self) -> bool {
+        self.attached
+    }
+    fn get_interpreter_ref(&self) -> Result<&Interpreter, String> {
+        match (&self.interpreter, self.attached) {
+            (Some(interpreter), true) => Ok(interpreter),
+            (None, _) => Err("The program was not loaded".to_string()),
+            (Some(_), false) => Err("The client is not attached".to_string()),
+        }
+    }
+    fn get_interpreter_mut(&mut self) -> Result<&mut Interpreter, String> {
+        match (&mut self.interpreter, self.attached) {
+            (Some(interpreter), true) => Ok(interpreter),
+            (None, _) => Err("The program was not loaded".to_string()),
+            (Some(_), false) => Err("The client is not attached".to_string()),
+        }
+    }
+    fn get_line_at(&self, address: usize) -> Option<usize> {
+        self.interpreter
+            .as_ref()
+            .and_then(|i| i.get_instruction_at(address))
+            .map(|i| i.parsed_line.line_index)
+    }
+    pub fn get_state(&self) -> Result<RemoteState, String> {
+        let interpreter = self.get_interpreter_ref()?;
+        let cpu = interpreter.get_cpu();
+        Ok(RemoteState {
+            status: *interpreter.get_status(),
+            pc: interpreter.get_pc(),
+            line: self.get_line_at(interpreter.get_pc()),
+            d: cpu.wasm_get_d_regs_value(),
+            a: cpu.wasm_get_a_regs_value(),
+            sr: interpreter.get_sr(),
+            cycles: interpreter.get_cycles(),
+            call_stack: interpreter.get_pretty_call_stack(),
+            can_step_back: interpreter.wasm_can_undo(),
+        })
+    }
+    /// Runs a command, returning its result and the events to push after the response
+    pub fn handle(&mut self, command: RemoteCommand) -> (Result<Value, String>, Vec<RemoteEvent>) {
+        let mut events = vec![];
+        let result = self.run_command(command, &mut events);
+        (result, events)
+    }
+    fn run_command(&mut self, command: RemoteCommand, events: &mut Vec<RemoteEvent>) -> Result<Value, String> {
+        match command {
+            RemoteCommand::Load { code, cpu_model } => {
+                self.load(&code, cpu_model.as_deref())?;
+                self.to_value(self.get_state()?)
+            }
+            RemoteCommand::Attach => {
+                if self.interpreter.is_none() {
+                    return Err("There is no interpreter to attach to, load a program".to_string());
+                }
+                self.attached = true;
+                self.to_value(self.get_state()?)
+            }
+            RemoteCommand::Detach => {
+                self.attached = false;
+                self.pending = None;
+                Ok(json!({}))
+            }
+            RemoteCommand::SetBreakpoints { lines } => {
+                let verified: Vec<usize> = match &self.interpreter {
+                    Some(interpreter) => lines
+                        .iter()
+                        .copied()
+                        .filter(|line| interpreter.get_program().iter().any(|i| i.parsed_line.line_index == *line))
+                        .collect(),
+                    None => lines.clone(),
+                };
+                self.breakpoints = lines;
+                Ok(json!({ "lines": verified }))
+            }
+            RemoteCommand::Step { count } => {
+                for _ in 0..count.unwrap_or(1) {
+                    let step = self.resume(StepMode::StepIn, DEFAULT_LIMIT)?;
+                    let stepped = matches!(step.as_slice(), [RemoteEvent::Stopped { reason: StopReason::Step, .. }]);
+                    events.clear();
+                    events.extend(step);
+                    if !stepped {
+                        break;
+                    }
+                }
+                Ok(json!({}))
+            }
+            RemoteCommand::StepOver => {
+                let depth = self.get_interpreter_mut()?.get_call_depth();
+                events.extend(self.resume(StepMode::StepOver(depth), DEFAULT_LIMIT)?);
+                Ok(json!({}))
+            }
+            RemoteCommand::StepOut => {
+                let depth = self.get_interpreter_mut()?.get_call_depth();
+                events.extend(self.resume(StepMode::StepOut(depth), DEFAULT_LIMIT)?);
+                Ok(json!({}))
+            }
+            RemoteCommand::StepBack => {
+                let interpreter = self.get_interpreter_mut()?;
+                interpreter.undo().map_err(|e| e.get_message())?;
+                let pc = interpreter.get_pc();
+                events.push(self.stopped(StopReason::Step, pc, None));
+                Ok(json!({}))
+            }
+            RemoteCommand::Continue { limit } => {
+                events.extend(self.resume(StepMode::Continue, limit.unwrap_or(DEFAULT_LIMIT))?);
+                Ok(json!({}))
+            }
+            RemoteCommand::GetState => self.to_value(self.get_state()?),
+            RemoteCommand::ReadMemory { address, length } => {
+                let bytes = self
+                    .get_interpreter_ref()?
+                    .get_memory()
+                    .read_bytes(address, length)
+                    .map_err(|e| e.get_message())?;
+                Ok(json!({ "bytes": bytes }))
+            }
+            RemoteCommand::AnswerInterrupt { result } => {
+                self.get_interpreter_mut()?
+                    .answer_interrupt(result)
+                    .map_err(|e| e.get_message())?;
+                if let Some((mode, limit)) = self.pending.take() {
+                    events.extend(self.resume(mode, limit)?);
+                }
+                Ok(json!({}))
+            }
+        }
+    }
+    fn to_value(&self, value: impl Serialize) -> Result<Value, String> {
+        serde_json::to_value(value).map_err(|e| e.to_string())
+    }
+    fn load(&mut self, code: &str, cpu_model: Option<&str>) -> Result<(), String> {
+        let cpu_model: CpuModel = match cpu_model {
+            Some(model) => model.parse()?,
+            None => CpuModel::M68000,
+        };
+        let mut s68k = S68k::new(code);
+        s68k.set_cpu_model(cpu_model);
+        let errors = s68k.semantic_check();
+        if !errors.is_empty() {
+            let messages: Vec<String> = errors.iter().map(|e| e.get_message()).collect();
+            return Err(messages.join("\n"));
+        }
+        let compiled = s68k.compile()?;
+        let options = InterpreterOptions {
+            keep_history: true,
+            history_size: 1000,
+            ..Default::default()
+        };
+        self.interpreter = Some(s68k.create_interpreter(compiled, Some(options)));
+        self.attached = true;
+        self.pending = None;
+        Ok(())
+    }
+    fn stopped(&self, reason: StopReason, pc: usize, message: Option<String>) -> RemoteEvent {
+        RemoteEvent::Stopped {
+            reason,
+            pc,
+            line: self.get_line_at(pc),
+            message,
+        }
+    }
+    /// Answers the interrupts that only write, the others are for the client
+    fn answer_output(&mut self, interrupt: &Interrupt) -> Result<Option<RemoteEvent>, String> {
+        let (result, text) = match interrupt {
+            Interrupt::DisplayStringWithCRLF(string) => (InterruptResult::DisplayStringWithCRLF, format!("{}\n", string)),
+            Interrupt::DisplayStringWithoutCRLF(string) => (InterruptResult::DisplayStringWithoutCRLF, string.clone()),
+            Interrupt::DisplayNumber(number) => (InterruptResult::DisplayNumber, number.to_string()),
+            Interrupt::DisplayChar(char) => (InterruptResult::DisplayChar, char.to_string()),
+            Interrupt::Terminate => (InterruptResult::Terminate, String::new()),
+            Interrupt::ClearScreen => (InterruptResult::ClearScreen, String::new()),
+            Interrupt::PositionCursor(..) => (InterruptResult::PositionCursor, String::new()),
+            Interrupt::SetEcho(_) => (InterruptResult::SetEcho, String::new()),
+            Interrupt::ReadKeyboardString
+            | Interrupt::ReadNumber
+            | Interrupt::ReadChar
+            | Interrupt::GetTime
+            | Interrupt::IsInputPending
+            | Interrupt::ReadNumberWithPrompt(_) => return Ok(None),
+        };
+        self.get_interpreter_mut()?
+            .answer_interrupt(result)
+            .map_err(|e| e.get_message())?;
+        Ok(Some(text).filter(|text| !text.is_empty()).map(|text| RemoteEvent::Output { text }))
+    }
+    fn resume(&mut self, mode: StepMode, limit: usize) -> Result<Vec<RemoteEvent>, String> {
+        let mut events = vec![];
+        self.pending = None;
+        for _ in 0..limit {
+            let interpreter = self.get_interpreter_mut()?;
+            match *interpreter.get_status() {
+                InterpreterStatus::Interrupt => {
+                    let interrupt = interpreter.get_current_interrupt().map_err(|e| e.get_message())?;
+                    events.extend(self.answer_output(&interrupt)?);
+                    if matches!(self.get_interpreter_ref()?.get_status(), InterpreterStatus::Interrupt) {
+                        self.pending = Some((mode, limit));
+                        events.push(RemoteEvent::Interrupt { interrupt });
+                        return Ok(events);
+                    }
+                    continue;
+                }
+                InterpreterStatus::Terminated | InterpreterStatus::TerminatedWithException => {
+                    events.push(RemoteEvent::Terminated);
+                    return Ok(events);
+                }
+                InterpreterStatus::Running => {}
+            }
+            match interpreter.step() {
+                Ok(InterpreterStatus::Interrupt) => continue,
+                Ok(_) => {}
+                Err(e) => {
+                    let pc = interpreter.get_pc();
+                    events.push(self.stopped(StopReason::Exception, pc, Some(e.get_message())));
+                    return Ok(events);
+                }
+            }
+            if interpreter.has_terminated() {
+                events.push(RemoteEvent::Terminated);
+                return Ok(events);
+            }
+            let depth = interpreter.get_call_depth();
+            let pc = interpreter.get_pc();
+            let done = match mode {
+                StepMode::Continue => false,
+                StepMode::StepIn => true,
+                StepMode::StepOver(start) => depth <= start,
+                StepMode::StepOut(start) => depth < start,
+            };
+            let on_breakpoint = match self.get_line_at(pc) {
+                Some(line) => self.breakpoints.contains(&line),
+                None => false,
+            };
+            if on_breakpoint {
+                events.push(self.stopped(StopReason::Breakpoint, pc, None));
+                return Ok(events);
+            }
+            if done {
+                events.push(self.stopped(StopReason::Step, pc, None));
+                return Ok(events);
+            }
+        }
+        let pc = self.get_interpreter_ref()?.get_pc();
+        events.push(self.stopped(StopReason::Limit, pc, Some(format!("Paused after {} instructions", limit))));
+        Ok(events)
+    }
+    /// Handles the text of a request, returning the texts of the response and of the events, in order
+    pub fn handle_message(&mut self, message: &str) -> Vec<String> {
+        let request: Value = match serde_json::from_str(message) {
+            Ok(request) => request,
+            Err(e) => return vec![json!({ "id": null, "error": e.to_string() }).to_string()],
+        };
+        let id = request["id"].clone();
+        let (result, events) = match serde_json::from_value::<RemoteCommand>(request) {
+            Ok(command) => self.handle(command),
+            Err(e) => (Err(e.to_string()), vec![]),
+        };
+        let response = match result {
+            Ok(result) => json!({ "id": id, "result": result }),
+            Err(error) => json!({ "id": id, "error": error }),
+        };
+        let mut messages = vec![response.to_string()];
+        messages.extend(events.iter().filter_map(|event| serde_json::to_string(event).ok()));
+        messages
+    }
+}
+
+/// Serves a client that sends one request per line and reads one message per line, until the end of the input
+pub fn run(reader: impl BufRead, mut writer: impl Write) -> Result<(), String> {
+    let mut server = RemoteDebugServer::new();
+    for line in reader.lines() {
+        let line = line.map_err(|e| e.to_string())?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        for message in server.handle_message(&line) {
+            writeln!(writer, "{}", message).map_err(|e| e.to_string())?;
+        }
+        writer.flush().map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+/// Serves the clients that connect to the address one at a time, each one gets a new server.
+/// Every text frame is a request and every message is sent back as a text frame
+#[cfg(feature = "websocket")]
+pub fn serve_websocket(address: &str) -> Result<(), String> {
+    use tungstenite::Message;
+    let listener = std::net::TcpListener::bind(address).map_err(|e| e.to_string())?;
+    for stream in listener.incoming() {
+        let stream = stream.map_err(|e| e.to_string())?;
+        let mut socket = match tungstenite::accept(stream) {
+            Ok(socket) => socket,
+            //a client that fails the handshake doesn't stop the others
+            Err(_) => continue,
+        };
+        let mut server = RemoteDebugServer::new();
+        while let Ok(message) = socket.read() {
+            let text = match message {
+                Message::Text(text) => text,
+                Message::Close(_) => break,
+                _ => continue,
+            };
+            for message in server.handle_message(&text) {
+                if socket.send(Message::Text(message)).is_err() {
+                    break;
+                }
+            }
+        }
+    }
+    Ok(())
+}
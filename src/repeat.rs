@@ -0,0 +1,212 @@
+use alloc::{string::{String, ToString}, vec, vec::Vec};
+use core::{error::Error, fmt};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    conditional::{define_symbols, evaluate_with_symbols, Symbols},
+    expression::ExpressionError,
+    lexer::LexError,
+    macros::{split_statement, ExpandedLine},
+};
+
+/*
+    Repetition blocks, the body between REPT count and ENDR is copied count times before the lines are lexed.
+    Inside the body the word REPTN is replaced by the number of the copy, starting from 0, so tables and
+    unrolled loops can use it in their expressions. Blocks can be nested, REPTN is the counter of the
+    innermost block and the count of an inner block can use the REPTN of the outer one.
+    The count can use numbers and the equs defined before the block. The copies have the line index of
+    the body they come from, the REPT, the ENDR and the body itself are lexed as empty lines, so a count
+    of 0 removes the body. A label in the body is defined by every copy, use REPTN in an equ or local labels
+    under different global labels to tell them apart
+*/
+
+/// How many lines all the copies can add
+const MAX_REPEATED_LINES: usize = 1 << 16;
+/// Replaced by the number of the copy in the body
+pub const REPEAT_COUNTER: &str = "REPTN";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "value")]
+pub enum RepeatError {
+    Unterminated,
+    UnexpectedEndr,
+    MissingCount,
+    InvalidCount(ExpressionError),
+    NegativeCount(i64),
+    TooLarge,
+}
+
+impl fmt::Display for RepeatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RepeatError::Unterminated => write!(f, "REPT is missing its ENDR"),
+            RepeatError::UnexpectedEndr => write!(f, "ENDR without a REPT"),
+            RepeatError::MissingCount => write!(f, "REPT is missing its count"),
+            RepeatError::InvalidCount(error) => write!(f, "Invalid count: {}", error),
+            RepeatError::NegativeCount(count) => write!(f, "The count {} is negative", count),
+            RepeatError::TooLarge => write!(f, "The copies make more than {} lines", MAX_REPEATED_LINES),
+        }
+    }
+}
+
+impl Error for RepeatError {}
+
+fn get_directive(line: &str) -> (String, &str) {
+    let statement = split_statement(line).1;
+    let word = statement.split_whitespace().next().unwrap_or_default();
+    (word.to_lowercase(), statement[word.len()..].trim())
+}
+
+/// Replaces the counter when it is a whole word, strings are left as they are
+fn replace_counter(line: &str, iteration: usize) -> String {
+    let mut result = String::new();
+    let mut word = String::new();
+    let mut in_string = false;
+    for c in line.chars().chain(core::iter::once('\0')) {
+        if !in_string && (c.is_alphanumeric() || c == '_' || c == '.') {
+            word.push(c);
+            continue;
+        }
+        match word == REPEAT_COUNTER {
+            true => result.push_str(&iteration.to_string()),
+            false => result.push_str(&word),
+        }
+        word.clear();
+        if c == '\'' {
+            in_string = !in_string;
+        }
+        if c != '\0' {
+            result.push(c);
+        }
+    }
+    result
+}
+
+/// Index of the ENDR of the REPT at start
+fn find_end(lines: &[ExpandedLine], start: usize) -> Option<usize> {
+    let mut depth = 0;
+    for (i, line) in lines.iter().enumerate().skip(start) {
+        match get_directive(&line.text).0.as_str() {
+            "rept" => depth += 1,
+            "endr" => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+fn with_error(line: &ExpandedLine, error: RepeatError) -> ExpandedLine {
+    ExpandedLine {
+        error: Some(LexError::InvalidRepeat(error)),
+        ..line.clone()
+    }
+}
+
+fn emptied(line: &ExpandedLine) -> ExpandedLine {
+    ExpandedLine {
+        text: String::new(),
+        ..line.clone()
+    }
+}
+
+struct Repeater {
+    symbols: Symbols,
+    repeated_lines: usize,
+}
+
+impl Repeater {
+    fn get_count(&self, argument: &str, body_length: usize) -> Result<usize, RepeatError> {
+        if argument.is_empty() {
+            return Err(RepeatError::MissingCount);
+        }
+        let count = evaluate_with_symbols(argument, &self.symbols).map_err(RepeatError::InvalidCount)?;
+        if count < 0 {
+            return Err(RepeatError::NegativeCount(count));
+        }
+        match (count as usize).checked_mul(body_length) {
+            Some(lines) if self.repeated_lines + lines <= MAX_REPEATED_LINES => Ok(count as usize),
+            _ => Err(RepeatError::TooLarge),
+        }
+    }
+    fn expand(&mut self, lines: &[ExpandedLine], output: &mut Vec<ExpandedLine>) {
+        let mut i = 0;
+        while i < lines.len() {
+            let line = &lines[i];
+            let (directive, argument) = get_directive(&line.text);
+            let end = match directive.as_str() {
+                "rept" => find_end(lines, i),
+                "endr" => {
+                    output.push(with_error(line, RepeatError::UnexpectedEndr));
+                    i += 1;
+                    continue;
+                }
+                _ => {
+                    define_symbols(&line.text, &mut self.symbols);
+                    output.push(line.clone());
+                    i += 1;
+                    continue;
+                }
+            };
+            //without its ENDR the lines are not part of the block
+            let end = match end {
+                Some(end) => end,
+                None => {
+                    output.push(with_error(line, RepeatError::Unterminated));
+                    i += 1;
+                    continue;
+                }
+            };
+            let body = &lines[i + 1..end];
+            let count = match self.get_count(argument, body.len()) {
+                Ok(count) => {
+                    output.push(emptied(line));
+                    count
+                }
+                Err(error) => {
+                    output.push(with_error(line, error));
+                    0
+                }
+            };
+            output.extend(lines[i + 1..=end].iter().map(emptied));
+            self.repeated_lines += count * body.len();
+            for iteration in 0..count {
+                //the counter of the nested blocks is replaced when they are copied
+                let mut depth = 0;
+                let copy: Vec<ExpandedLine> = body
+                    .iter()
+                    .map(|line| {
+                        let text = match depth {
+                            0 => replace_counter(&line.text, iteration),
+                            _ => line.text.clone(),
+                        };
+                        match get_directive(&line.text).0.as_str() {
+                            "rept" => depth += 1,
+                            "endr" => depth -= 1,
+                            _ => {}
+                        }
+                        ExpandedLine { text, ..line.clone() }
+                    })
+                    .collect();
+                self.expand(&copy, output);
+            }
+            i = end + 1;
+        }
+    }
+}
+
+/// Copies the bodies of the REPT blocks, the lines outside of them are kept as they are
+pub fn expand_repetitions(lines: Vec<ExpandedLine>) -> Vec<ExpandedLine> {
+    let mut repeater = Repeater {
+        symbols: Symbols::new(),
+        repeated_lines: 0,
+    };
+    let mut output = vec![];
+    repeater.expand(&lines, &mut output);
+    output
+}
@@ -0,0 +1,255 @@
+use alloc::{format, string::{String, ToString}, vec, vec::Vec};
+
+use crate::{
+    compiler::{Compiler, Directive},
+    cpu_model::CpuModel,
+    interpreter::{Interpreter, InterpreterOptions, InterpreterStatus},
+    lexer::LexedLine,
+    symbol::LabelTable,
+    utils::parse_absolute,
+    S68k,
+};
+
+/*
+    Read-eval-print loop over a persistent machine: every instruction is assembled at the pc and executed
+    right away, labels and equs are remembered for the following lines and data directives are placed
+    in a separate data area so they are not executed. Lines starting with ":" are commands to inspect the machine.
+    The caller answers the interrupts of TRAP #15 like with a normal interpreter
+*/
+pub const REPL_CODE_ADDRESS: usize = 0x1000;
+pub const REPL_DATA_ADDRESS: usize = 0x8000;
+
+const REPL_HELP: &str = ":regs            show the registers and flags
+:mem <addr> [n]  show n bytes of memory starting at the address
+:symbols         show the labels and equs
+:undo            undo the last instruction
+:reset           start again with a new machine
+:help            show this message";
+
+pub struct Repl {
+    interpreter: Interpreter,
+    cpu_model: CpuModel,
+    /// Labels and equs, in the order they were defined, given to every line as equs
+    symbols: Vec<(String, String)>,
+    data_address: usize,
+}
+
+fn create_interpreter(cpu_model: CpuModel) -> Result<Interpreter, String> {
+    let mut s68k = S68k::new(format!("org ${:X}", REPL_CODE_ADDRESS));
+    s68k.set_cpu_model(cpu_model);
+    let options = InterpreterOptions {
+        keep_history: true,
+        history_size: 1000,
+        ..Default::default()
+    };
+    let compiled = s68k.compile()?;
+    let mut interpreter = s68k.create_interpreter(compiled, Some(options));
+    interpreter.set_pc(REPL_CODE_ADDRESS);
+    Ok(interpreter)
+}
+
+impl Repl {
+    pub fn new(cpu_model: CpuModel) -> Result<Self, String> {
+        Ok(Self {
+            interpreter: create_interpreter(cpu_model)?,
+            cpu_model,
+            symbols: vec![],
+            data_address: REPL_DATA_ADDRESS,
+        })
+    }
+    pub fn get_interpreter(&self) -> &Interpreter {
+        &self.interpreter
+    }
+    pub fn get_interpreter_mut(&mut self) -> &mut Interpreter {
+        &mut self.interpreter
+    }
+    pub fn get_symbols(&self) -> &Vec<(String, String)> {
+        &self.symbols
+    }
+    fn define_symbol(&mut self, name: &str, value: String) {
+        self.symbols.retain(|(n, _)| n != name);
+        self.symbols.push((name.to_string(), value));
+    }
+    /// Checks and compiles the line with the known symbols, placing it at the address
+    fn compile(&self, line: &str, address: usize) -> Result<Compiler, String> {
+        let mut code: Vec<String> = self
+            .symbols
+            .iter()
+            .map(|(name, value)| format!("{} equ {}", name, value))
+            .collect();
+        code.push(format!("org ${:X}", address));
+        code.push(line.to_string());
+        let mut s68k = S68k::new(code.join("\n"));
+        s68k.set_cpu_model(self.cpu_model);
+        let errors = s68k.semantic_check();
+        if !errors.is_empty() {
+            let messages: Vec<&str> = errors.iter().map(|e| e.get_error()).collect();
+            return Err(messages.join("\n"));
+        }
+        Ok(s68k.compile()?)
+    }
+    /// Evaluates a line, returning the text to show to the user
+    pub fn eval(&mut self, input: &str) -> Result<String, String> {
+        let input = input.trim();
+        if let Some(command) = input.strip_prefix(':') {
+            return self.eval_command(command);
+        }
+        let s68k = S68k::new(input.to_string());
+        let mut labels = vec![];
+        let mut content = None;
+        for line in s68k.get_lexed_lines() {
+            match &line.parsed {
+                LexedLine::Label { name, .. } => labels.push(name.clone()),
+                LexedLine::Instruction { .. } | LexedLine::Directive { .. } => content = Some(line.parsed.clone()),
+                LexedLine::Unknown { content } => return Err(format!("Unknown line \"{}\"", content)),
+                LexedLine::Error { diagnostics, .. } => {
+                    return Err(diagnostics.first().map(|e| e.get_cause().to_string()).unwrap_or_default())
+                }
+                LexedLine::Comment { .. } | LexedLine::Empty => {}
+            }
+        }
+        let pc = self.interpreter.get_pc();
+        match content {
+            None => {
+                for label in &labels {
+                    self.define_symbol(label, format!("${:X}", pc));
+                }
+                Ok(String::new())
+            }
+            Some(LexedLine::Directive { name, args, .. }) => match name.as_str() {
+                "equ" => {
+                    //the equ replacement is also applied to the args, so the name is taken from the source line
+                    let name = input.split_whitespace().next().unwrap_or_default().to_string();
+                    let value = args.get(2..).map(|v| v.join(" ")).unwrap_or_default();
+                    self.define_symbol(&name, value.clone());
+                    Ok(format!("{} = {}", name, value))
+                }
+                "org" => {
+                    let address = match args.get(1) {
+                        Some(address) => parse_absolute(address, &LabelTable::new())? as usize,
+                        None => return Err("Missing address of ORG".to_string()),
+                    };
+                    self.interpreter.set_pc(address);
+                    Ok(format!("pc = ${:X}", address))
+                }
+                _ => {
+                    let address = self.data_address;
+                    let compiled = self.compile(input, address)?;
+                    let length: usize = compiled
+                        .get_directives()
+                        .iter()
+                        .map(|d| match d {
+                            Directive::DC { data, .. } | Directive::DS { data, .. } | Directive::DCB { data, .. } => data.len(),
+                            Directive::Other => 0,
+                        })
+                        .sum();
+                    self.interpreter.load_program(&compiled).map_err(|e| e.get_message())?;
+                    //keep the next data aligned to a word
+                    self.data_address += length + (length & 1);
+                    for label in &labels {
+                        self.define_symbol(label, format!("${:X}", address));
+                    }
+                    Ok(format!("{} bytes at ${:X}", length, address))
+                }
+            },
+            Some(_) => {
+                let compiled = self.compile(input, pc)?;
+                for label in &labels {
+                    self.define_symbol(label, format!("${:X}", pc));
+                }
+                self.interpreter.load_program(&compiled).map_err(|e| e.get_message())?;
+                let d_regs = self.interpreter.get_cpu().wasm_get_d_regs_value();
+                let a_regs = self.interpreter.get_cpu().wasm_get_a_regs_value();
+                let ccr = self.interpreter.get_cpu().wasm_get_ccr();
+                self.interpreter.step().map_err(|e| e.get_message())?;
+                Ok(self.describe_changes(&d_regs, &a_regs, ccr.get_status()))
+            }
+        }
+    }
+    /// Lists the registers and flags that changed after an instruction
+    fn describe_changes(&self, d_regs: &[u32], a_regs: &[u32], ccr: String) -> String {
+        let cpu = self.interpreter.get_cpu();
+        let mut changes: Vec<String> = vec![];
+        for (i, (old, new)) in d_regs.iter().zip(cpu.wasm_get_d_regs_value()).enumerate() {
+            if *old != new {
+                changes.push(format!("d{} = ${:08X}", i, new));
+            }
+        }
+        for (i, (old, new)) in a_regs.iter().zip(cpu.wasm_get_a_regs_value()).enumerate() {
+            if *old != new {
+                changes.push(format!("a{} = ${:08X}", i, new));
+            }
+        }
+        let new_ccr = cpu.wasm_get_ccr().get_status();
+        if new_ccr != ccr {
+            changes.push(new_ccr);
+        }
+        changes.push(format!("pc = ${:X}", self.interpreter.get_pc()));
+        if *self.interpreter.get_status() == InterpreterStatus::TerminatedWithException {
+            changes.push("terminated with an exception".to_string());
+        }
+        changes.join("  ")
+    }
+    fn eval_command(&mut self, command: &str) -> Result<String, String> {
+        let args: Vec<&str> = command.split_whitespace().collect();
+        match args[..] {
+            ["regs"] | ["r"] => {
+                let cpu = self.interpreter.get_cpu();
+                let mut lines: Vec<String> = (0..8)
+                    .map(|i| {
+                        format!(
+                            "d{}: ${:08X}    a{}: ${:08X}",
+                            i,
+                            cpu.wasm_get_d_reg(i).get_long(),
+                            i,
+                            cpu.wasm_get_a_reg(i).get_long()
+                        )
+                    })
+                    .collect();
+                lines.push(format!("pc: ${:X}    {}", self.interpreter.get_pc(), cpu.wasm_get_ccr().get_status()));
+                Ok(lines.join("\n"))
+            }
+            ["mem", address] | ["m", address] => self.dump_memory(address, "16"),
+            ["mem", address, length] | ["m", address, length] => self.dump_memory(address, length),
+            ["symbols"] => Ok(self
+                .symbols
+                .iter()
+                .map(|(name, value)| format!("{} = {}", name, value))
+                .collect::<Vec<String>>()
+                .join("\n")),
+            ["undo"] | ["u"] => {
+                self.interpreter.undo().map_err(|e| e.get_message())?;
+                Ok(format!("pc = ${:X}", self.interpreter.get_pc()))
+            }
+            ["reset"] => {
+                *self = Repl::new(self.cpu_model)?;
+                Ok(String::new())
+            }
+            ["help"] | ["h"] => Ok(REPL_HELP.to_string()),
+            _ => Err(format!("Unknown command \":{}\", use :help to list the commands", command)),
+        }
+    }
+    fn dump_memory(&self, address: &str, length: &str) -> Result<String, String> {
+        //symbols can be used as the address, like ":mem msg"
+        let address = match self.symbols.iter().find(|(name, _)| name == address) {
+            Some((_, value)) => value.as_str(),
+            None => address,
+        };
+        let address = parse_absolute(address, &LabelTable::new())? as usize;
+        let length = parse_absolute(length, &LabelTable::new())? as usize;
+        let bytes = self
+            .interpreter
+            .get_memory()
+            .read_bytes(address, length)
+            .map_err(|e| e.get_message())?;
+        Ok(bytes
+            .chunks(16)
+            .enumerate()
+            .map(|(i, chunk)| {
+                let hex: Vec<String> = chunk.iter().map(|b| format!("{:02X}", b)).collect();
+                format!("${:08X}  {}", address + i * 16, hex.join(" "))
+            })
+            .collect::<Vec<String>>()
+            .join("\n"))
+    }
+}
@@ -0,0 +1,119 @@
+use alloc::string::{String, ToString};
+use core::{error::Error, fmt};
+
+use serde::{Deserialize, Serialize};
+
+/*
+    Limits for running untrusted programs, enforced by the interpreter. A missing limit is not checked.
+    When a program goes past one it stops with RuntimeError::SandboxLimit and the status TerminatedWithException,
+    the limit that stopped it stays in Interpreter::get_sandbox_stop.
+        max_instructions    instructions that can be run
+        max_cycles          clock cycles of the cpu model
+        max_wall_time_ms    real time from the first step, not checked without std or on wasm where there is no clock
+        memory_size         bytes of memory, accesses past it fail and the stack starts at its end.
+                            A program with data past it doesn't start
+        max_output_bytes    bytes written by the display traps
+        max_traps           TRAP instructions that can be run
+*/
+
+pub const DEFAULT_MEMORY_SIZE: usize = 0x01000000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SandboxConfig {
+    pub max_instructions: Option<usize>,
+    pub max_cycles: Option<u64>,
+    pub max_wall_time_ms: Option<u64>,
+    pub memory_size: usize,
+    pub max_output_bytes: Option<usize>,
+    pub max_traps: Option<usize>,
+}
+
+impl Default for SandboxConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SandboxConfig {
+    /// No limits and the full 16MB of memory
+    pub fn new() -> Self {
+        Self {
+            max_instructions: None,
+            max_cycles: None,
+            max_wall_time_ms: None,
+            memory_size: DEFAULT_MEMORY_SIZE,
+            max_output_bytes: None,
+            max_traps: None,
+        }
+    }
+    /// Limits for code from anyone: 10M instructions, 5 seconds, 1MB of memory, 64KB of output and 100K traps
+    pub fn untrusted() -> Self {
+        Self {
+            max_instructions: Some(10_000_000),
+            max_cycles: None,
+            max_wall_time_ms: Some(5_000),
+            memory_size: 0x100000,
+            max_output_bytes: Some(0x10000),
+            max_traps: Some(100_000),
+        }
+    }
+    pub fn with_max_instructions(mut self, limit: usize) -> Self {
+        self.max_instructions = Some(limit);
+        self
+    }
+    pub fn with_max_cycles(mut self, limit: u64) -> Self {
+        self.max_cycles = Some(limit);
+        self
+    }
+    pub fn with_max_wall_time_ms(mut self, limit: u64) -> Self {
+        self.max_wall_time_ms = Some(limit);
+        self
+    }
+    pub fn with_memory_size(mut self, size: usize) -> Self {
+        self.memory_size = size;
+        self
+    }
+    pub fn with_max_output_bytes(mut self, limit: usize) -> Self {
+        self.max_output_bytes = Some(limit);
+        self
+    }
+    pub fn with_max_traps(mut self, limit: usize) -> Self {
+        self.max_traps = Some(limit);
+        self
+    }
+}
+
+/// The limit a program went past, with its value
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "value")]
+pub enum SandboxLimit {
+    Instructions(usize),
+    Cycles(u64),
+    WallTime(u64),
+    Output(usize),
+    Traps(usize),
+    /// The data of the program doesn't fit the memory
+    Memory(usize),
+}
+
+impl fmt::Display for SandboxLimit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SandboxLimit::Instructions(limit) => write!(f, "Sandbox limit of {} instructions reached", limit),
+            SandboxLimit::Cycles(limit) => write!(f, "Sandbox limit of {} cycles reached", limit),
+            SandboxLimit::WallTime(limit) => write!(f, "Sandbox limit of {}ms of running time reached", limit),
+            SandboxLimit::Output(limit) => write!(f, "Sandbox limit of {} bytes of output reached", limit),
+            SandboxLimit::Traps(limit) => write!(f, "Sandbox limit of {} traps reached", limit),
+            SandboxLimit::Memory(size) => write!(f, "The program doesn't fit the sandbox memory of {} bytes", size),
+        }
+    }
+}
+
+impl Error for SandboxLimit {}
+
+impl From<SandboxLimit> for String {
+    fn from(limit: SandboxLimit) -> Self {
+        limit.to_string()
+    }
+}
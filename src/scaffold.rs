@@ -0,0 +1,219 @@
+use alloc::{format, string::{String, ToString}, vec, vec::Vec};
+use core::{error::Error, fmt};
+
+use serde::Serialize;
+
+use crate::{
+    compiler::Compiler,
+    lexer::{LexedLine, ParsedLine},
+    utils::split_comment,
+    S68k,
+};
+
+/*
+    Regions of an exercise written by the instructor that the student must not change:
+
+        ;PROTECTED
+        print:
+            move.l #13, d0
+            trap #15
+            rts
+        table: dc.w 1, 2, 3
+        ;END_PROTECTED
+
+    The grader compares the protected regions of the program of the student with the ones of the template,
+    comments and spacing can change but the statements must be the same and in the same order.
+    When the program runs the memory of its protected regions is read only, so the data of the scaffold
+    can't be overwritten. Any other address range can be protected with Interpreter::protect_memory
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ScaffoldRegion {
+    /// First line after the ;PROTECTED marker
+    pub start_line: usize,
+    /// Line of the ;END_PROTECTED marker, excluded from the region
+    pub end_line: usize,
+}
+
+impl ScaffoldRegion {
+    pub fn contains(&self, line_index: usize) -> bool {
+        line_index >= self.start_line && line_index < self.end_line
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScaffoldError {
+    pub line_index: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ScaffoldError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid protected region at line {}: {}", self.line_index + 1, self.message)
+    }
+}
+
+impl Error for ScaffoldError {}
+
+impl From<ScaffoldError> for String {
+    fn from(error: ScaffoldError) -> Self {
+        error.to_string()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ScaffoldViolation {
+    /// Index of the protected region in the template
+    pub region: usize,
+    /// Line of the program of the student where it is different, missing when the whole region is
+    pub line_index: Option<usize>,
+    pub message: String,
+}
+
+enum Marker {
+    Start,
+    End,
+}
+
+fn get_marker(line: &str) -> Option<Marker> {
+    let (_, comment) = split_comment(line);
+    let text = comment?.trim_start_matches([';', '*']).trim();
+    let keyword = text.split(|c: char| !c.is_alphanumeric() && c != '_').next()?;
+    match keyword.to_uppercase().as_str() {
+        "PROTECTED" => Some(Marker::Start),
+        "END_PROTECTED" => Some(Marker::End),
+        _ => None,
+    }
+}
+
+/// Finds the regions between the ;PROTECTED and ;END_PROTECTED markers
+pub fn get_scaffold_regions(code: &str) -> Result<Vec<ScaffoldRegion>, ScaffoldError> {
+    let mut regions = vec![];
+    let mut start: Option<usize> = None;
+    for (line_index, line) in code.lines().enumerate() {
+        match (get_marker(line), start) {
+            (Some(Marker::Start), None) => start = Some(line_index + 1),
+            (Some(Marker::Start), Some(_)) => {
+                return Err(ScaffoldError {
+                    line_index,
+                    message: "A protected region can't start inside another one".to_string(),
+                })
+            }
+            (Some(Marker::End), Some(start_line)) => {
+                regions.push(ScaffoldRegion {
+                    start_line,
+                    end_line: line_index,
+                });
+                start = None;
+            }
+            (Some(Marker::End), None) => {
+                return Err(ScaffoldError {
+                    line_index,
+                    message: "END_PROTECTED without a PROTECTED before it".to_string(),
+                })
+            }
+            (None, _) => {}
+        }
+    }
+    match start {
+        Some(start_line) => Err(ScaffoldError {
+            line_index: start_line - 1,
+            message: "The protected region is never closed with END_PROTECTED".to_string(),
+        }),
+        None => Ok(regions),
+    }
+}
+
+fn get_region_statements<'a>(lines: &'a [ParsedLine], region: &ScaffoldRegion) -> Vec<&'a ParsedLine> {
+    lines
+        .iter()
+        .filter(|line| region.contains(line.line_index))
+        .filter(|line| !matches!(line.parsed, LexedLine::Comment { .. } | LexedLine::Empty))
+        .collect()
+}
+
+/// Checks that the protected regions of the template are in the program of the student and were not changed.
+/// Fails if the markers of the template are wrong, the ones of the student are reported as violations
+pub fn check_scaffold(template: &S68k, student: &S68k) -> Result<Vec<ScaffoldViolation>, ScaffoldError> {
+    let template_regions = get_scaffold_regions(template.get_code())?;
+    let student_regions = match get_scaffold_regions(student.get_code()) {
+        Ok(regions) => regions,
+        Err(error) => {
+            return Ok(vec![ScaffoldViolation {
+                region: 0,
+                line_index: Some(error.line_index),
+                message: error.message,
+            }])
+        }
+    };
+    let mut violations = vec![];
+    for (region, template_region) in template_regions.iter().enumerate() {
+        let student_region = match student_regions.get(region) {
+            Some(student_region) => student_region,
+            None => {
+                violations.push(ScaffoldViolation {
+                    region,
+                    line_index: None,
+                    message: format!("The protected region {} was removed", region + 1),
+                });
+                continue;
+            }
+        };
+        let expected = get_region_statements(template.get_lexed_lines(), template_region);
+        let found = get_region_statements(student.get_lexed_lines(), student_region);
+        let difference = (0..expected.len().max(found.len()))
+            .find(|i| expected.get(*i).map(|line| &line.parsed) != found.get(*i).map(|line| &line.parsed));
+        if let Some(i) = difference {
+            let (line_index, message) = match (expected.get(i), found.get(i)) {
+                (Some(expected), Some(found)) => (
+                    found.line_index,
+                    format!("\"{}\" was changed, it should be \"{}\"", found.line.trim(), expected.line.trim()),
+                ),
+                (Some(expected), None) => (
+                    student_region.end_line,
+                    format!("\"{}\" was removed", expected.line.trim()),
+                ),
+                (None, Some(found)) => (found.line_index, format!("\"{}\" was added", found.line.trim())),
+                (None, None) => continue,
+            };
+            violations.push(ScaffoldViolation {
+                region,
+                line_index: Some(line_index),
+                message: format!("In the protected region {}: {}", region + 1, message),
+            });
+        }
+    }
+    if student_regions.len() > template_regions.len() {
+        violations.push(ScaffoldViolation {
+            region: template_regions.len(),
+            line_index: Some(student_regions[template_regions.len()].start_line.saturating_sub(1)),
+            message: "There are more protected regions than in the template".to_string(),
+        });
+    }
+    Ok(violations)
+}
+
+/// The address ranges of the protected regions of the compiled program, start included and end excluded
+pub fn get_protected_ranges(s68k: &S68k, compiled: &Compiler) -> Result<Vec<(usize, usize)>, ScaffoldError> {
+    let regions = get_scaffold_regions(s68k.get_code())?;
+    let lines = s68k.get_lexed_lines();
+    let addresses = compiled.get_line_addresses();
+    let mut ranges: Vec<(usize, usize)> = vec![];
+    for (i, line) in lines.iter().enumerate() {
+        if !regions.iter().any(|region| region.contains(line.line_index)) {
+            continue;
+        }
+        let Some(&start) = addresses.get(i) else {
+            continue;
+        };
+        let end = addresses.get(i + 1).copied().unwrap_or(compiled.get_end_address());
+        if end <= start {
+            continue;
+        }
+        match ranges.last_mut() {
+            Some(last) if last.1 == start => last.1 = end,
+            _ => ranges.push((start, end)),
+        }
+    }
+    Ok(ranges)
+}
@@ -0,0 +1,139 @@
+use core::{error::Error, fmt, str::FromStr};
+use alloc::{collections::VecDeque, format, string::{String, ToString}};
+
+/*
+    Input given in advance to the traps that read, so that interactive programs can run in batch mode.
+    The input is a queue of lines: reading a string or a number takes the next line, reading a char takes the
+    next char of the line, and the newline once the line is over.
+    What happens when the program reads more than it was given depends on the policy:
+        fail        the read fails with an error
+        repeat      the last line or char is read again
+        eof=<n>     strings are empty, chars are '\0' and numbers are n
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExhaustedPolicy {
+    #[default]
+    Fail,
+    RepeatLast,
+    /// The value read as a number, strings are empty and chars are '\0'
+    Eof(i32),
+}
+
+impl FromStr for ExhaustedPolicy {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "fail" => Ok(ExhaustedPolicy::Fail),
+            "repeat" => Ok(ExhaustedPolicy::RepeatLast),
+            "eof" => Ok(ExhaustedPolicy::Eof(0)),
+            policy => match policy.strip_prefix("eof=").map(|value| value.parse::<i32>()) {
+                Some(Ok(value)) => Ok(ExhaustedPolicy::Eof(value)),
+                _ => Err(format!("Unknown input policy \"{}\", expected fail, repeat, eof or eof=<number>", s)),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InputError {
+    Exhausted,
+    NotANumber(String),
+}
+
+impl fmt::Display for InputError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InputError::Exhausted => write!(f, "The program read more input than it was given"),
+            InputError::NotANumber(line) => write!(f, "Expected a number as input, received \"{}\"", line),
+        }
+    }
+}
+
+impl Error for InputError {}
+
+impl From<InputError> for String {
+    fn from(error: InputError) -> Self {
+        error.to_string()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ScriptedInput {
+    lines: VecDeque<String>,
+    policy: ExhaustedPolicy,
+    last_line: Option<String>,
+    last_char: Option<char>,
+}
+
+impl ScriptedInput {
+    pub fn new(policy: ExhaustedPolicy) -> Self {
+        Self {
+            policy,
+            ..Default::default()
+        }
+    }
+    /// A line of the text for every read, the \r of windows line endings is removed
+    pub fn from_text(text: &str, policy: ExhaustedPolicy) -> Self {
+        let mut input = Self::new(policy);
+        for line in text.lines() {
+            input.push_line(line.trim_end_matches('\r'));
+        }
+        input
+    }
+    pub fn push_line(&mut self, line: impl Into<String>) {
+        self.lines.push_back(line.into());
+    }
+    pub fn push_number(&mut self, number: i32) {
+        self.push_line(number.to_string());
+    }
+    pub fn set_policy(&mut self, policy: ExhaustedPolicy) {
+        self.policy = policy;
+    }
+    pub fn get_policy(&self) -> ExhaustedPolicy {
+        self.policy
+    }
+    /// Lines that were not read yet, a line read in part counts
+    pub fn get_remaining(&self) -> usize {
+        self.lines.len()
+    }
+    pub fn is_exhausted(&self) -> bool {
+        self.lines.is_empty()
+    }
+    pub fn read_line(&mut self) -> Result<String, InputError> {
+        let line = match (self.lines.pop_front(), self.policy) {
+            (Some(line), _) => line,
+            (None, ExhaustedPolicy::RepeatLast) => self.last_line.clone().ok_or(InputError::Exhausted)?,
+            (None, ExhaustedPolicy::Eof(_)) => return Ok(String::new()),
+            (None, ExhaustedPolicy::Fail) => return Err(InputError::Exhausted),
+        };
+        self.last_line = Some(line.clone());
+        Ok(line)
+    }
+    pub fn read_number(&mut self) -> Result<i32, InputError> {
+        if let (true, ExhaustedPolicy::Eof(value)) = (self.lines.is_empty(), self.policy) {
+            return Ok(value);
+        }
+        let line = self.read_line()?;
+        line.trim().parse().map_err(|_| InputError::NotANumber(line))
+    }
+    pub fn read_char(&mut self) -> Result<char, InputError> {
+        //none once the line is over, then the newline is read
+        let next = self.lines.front_mut().map(|line| match line.is_empty() {
+            true => None,
+            false => Some(line.remove(0)),
+        });
+        let char = match (next, self.policy) {
+            (Some(Some(char)), _) => char,
+            (Some(None), _) => {
+                self.lines.pop_front();
+                '\n'
+            }
+            (None, ExhaustedPolicy::RepeatLast) => self.last_char.ok_or(InputError::Exhausted)?,
+            (None, ExhaustedPolicy::Eof(_)) => return Ok('\0'),
+            (None, ExhaustedPolicy::Fail) => return Err(InputError::Exhausted),
+        };
+        self.last_char = Some(char);
+        Ok(char)
+    }
+}
@@ -0,0 +1,492 @@
+use alloc::{format, string::{String, ToString}, vec::Vec};
+use bitflags::bitflags;
+
+use crate::{
+    constants::{BRANCHES, DECREMENT_BRANCHES, SETS},
+    cpu_model::{CpuFeature, CpuModel},
+    diagnostic::{Diagnostic, DiagnosticCode},
+    lexer::{LexedLine, LexedOperand, LexedRegisterType, LexedSize, ParsedLine},
+    semantic_checker::SemanticError,
+};
+
+/*
+    Legality tables of the 68000 instructions, every instruction has the forms it can be written in,
+    each with the sizes it accepts and the addressing modes of its operands, like the tables of the
+    programmer's reference manual. validate checks the lexed lines against them, the semantic checker
+    validates every instruction after its own checks found nothing, so an illegal size, addressing mode
+    or number of operands they let through is still reported, and the assembler refuses to encode an
+    instruction they reject. A missing size is always accepted, it is the default size of the instruction.
+    The instructions of the later models, the FPU and the directives are not in the tables and are left
+    to the rest of the semantic checker, which also checks the values of the operands and the features
+    of the cpu model
+*/
+
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Modes: u16 {
+        const DN = 1<<0;
+        const AN = 1<<1;
+        const INDIRECT = 1<<2;
+        const POST_INCREMENT = 1<<3;
+        const PRE_DECREMENT = 1<<4;
+        const DISPLACEMENT = 1<<5;
+        const INDEX = 1<<6;
+        const MEMORY_INDIRECT = 1<<7;
+        const ABSOLUTE = 1<<8;
+        const IMMEDIATE = 1<<9;
+        const REG_LIST = 1<<10;
+        const REG_PAIR = 1<<11;
+        const FP_REG = 1<<12;
+        const BITFIELD = 1<<13;
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Sizes: u8 {
+        const BYTE = 1<<0;
+        const WORD = 1<<1;
+        const LONG = 1<<2;
+    }
+}
+
+//the categories of the manual, they are not flags so that iterating a set only gives single modes
+impl Modes {
+    pub const EA: Modes = Modes::DN
+        .union(Modes::AN)
+        .union(Modes::INDIRECT)
+        .union(Modes::POST_INCREMENT)
+        .union(Modes::PRE_DECREMENT)
+        .union(Modes::DISPLACEMENT)
+        .union(Modes::INDEX)
+        .union(Modes::MEMORY_INDIRECT)
+        .union(Modes::ABSOLUTE)
+        .union(Modes::IMMEDIATE);
+    pub const DATA: Modes = Modes::EA.difference(Modes::AN);
+    pub const MEMORY: Modes = Modes::DATA.difference(Modes::DN);
+    pub const CONTROL: Modes = Modes::INDIRECT
+        .union(Modes::DISPLACEMENT)
+        .union(Modes::INDEX)
+        .union(Modes::MEMORY_INDIRECT)
+        .union(Modes::ABSOLUTE);
+    pub const ALTERABLE: Modes = Modes::EA.difference(Modes::IMMEDIATE);
+    pub const DATA_ALTERABLE: Modes = Modes::DATA.intersection(Modes::ALTERABLE);
+    pub const MEMORY_ALTERABLE: Modes = Modes::MEMORY.intersection(Modes::ALTERABLE);
+    pub const REGISTER: Modes = Modes::DN.union(Modes::AN);
+}
+
+impl Sizes {
+    pub const NONE: Sizes = Sizes::empty();
+    pub const WORD_OR_LONG: Sizes = Sizes::WORD.union(Sizes::LONG);
+    pub const ANY: Sizes = Sizes::BYTE.union(Sizes::WORD_OR_LONG);
+}
+
+/// One way to write an instruction, an instruction without sizes is not sized
+pub struct Form {
+    pub sizes: Sizes,
+    pub operands: &'static [Modes],
+    /// The feature of the later models the form needs, the semantic checker verifies it
+    pub feature: Option<CpuFeature>,
+}
+
+impl Form {
+    pub fn is_supported_by(&self, cpu_model: CpuModel) -> bool {
+        match self.feature {
+            Some(feature) => cpu_model.supports(feature),
+            None => true,
+        }
+    }
+}
+
+pub struct InstructionRule {
+    pub names: &'static [&'static str],
+    pub forms: &'static [Form],
+}
+
+const fn form(sizes: Sizes, operands: &'static [Modes]) -> Form {
+    Form { sizes, operands, feature: None }
+}
+
+const fn model_form(feature: CpuFeature, sizes: Sizes, operands: &'static [Modes]) -> Form {
+    Form { sizes, operands, feature: Some(feature) }
+}
+
+const EA_NO_AN: Modes = Modes::EA.difference(Modes::AN);
+const BIT_DESTINATION: Modes = Modes::DATA.difference(Modes::DN);
+const BIT_ALTERABLE: Modes = Modes::DATA_ALTERABLE.difference(Modes::DN);
+const MOVEM_SOURCE: Modes = Modes::CONTROL.union(Modes::POST_INCREMENT);
+const MOVEM_DESTINATION: Modes = Modes::CONTROL.union(Modes::PRE_DECREMENT);
+const MOVEM_LIST: Modes = Modes::REG_LIST.union(Modes::REGISTER);
+
+pub static INSTRUCTION_RULES: &[InstructionRule] = &[
+    InstructionRule {
+        names: &["move"],
+        forms: &[
+            form(Sizes::WORD_OR_LONG, &[Modes::EA, Modes::DATA_ALTERABLE]),
+            form(Sizes::BYTE, &[EA_NO_AN, Modes::DATA_ALTERABLE]),
+            form(Sizes::WORD_OR_LONG, &[Modes::EA, Modes::AN]),
+        ],
+    },
+    InstructionRule {
+        names: &["movea", "adda", "suba", "cmpa"],
+        forms: &[form(Sizes::WORD_OR_LONG, &[Modes::EA, Modes::AN])],
+    },
+    InstructionRule {
+        names: &["moveq"],
+        forms: &[form(Sizes::LONG, &[Modes::IMMEDIATE, Modes::DN])],
+    },
+    InstructionRule {
+        names: &["add", "sub"],
+        forms: &[
+            form(Sizes::WORD_OR_LONG, &[Modes::EA, Modes::DN]),
+            form(Sizes::BYTE, &[EA_NO_AN, Modes::DN]),
+            form(Sizes::ANY, &[Modes::DN, Modes::MEMORY_ALTERABLE]),
+            form(Sizes::WORD_OR_LONG, &[Modes::EA, Modes::AN]),
+        ],
+    },
+    InstructionRule {
+        names: &["cmp"],
+        forms: &[
+            form(Sizes::WORD_OR_LONG, &[Modes::EA, Modes::DN]),
+            form(Sizes::BYTE, &[EA_NO_AN, Modes::DN]),
+            form(Sizes::WORD_OR_LONG, &[Modes::EA, Modes::AN]),
+            form(Sizes::ANY, &[Modes::IMMEDIATE, Modes::DATA_ALTERABLE]),
+        ],
+    },
+    InstructionRule {
+        names: &["addi", "subi", "andi", "ori", "eori", "cmpi"],
+        forms: &[form(Sizes::ANY, &[Modes::IMMEDIATE, Modes::DATA_ALTERABLE])],
+    },
+    InstructionRule {
+        names: &["addq", "subq"],
+        forms: &[
+            form(Sizes::ANY, &[Modes::IMMEDIATE, Modes::DATA_ALTERABLE]),
+            form(Sizes::WORD_OR_LONG, &[Modes::IMMEDIATE, Modes::AN]),
+        ],
+    },
+    InstructionRule {
+        names: &["and", "or"],
+        forms: &[
+            form(Sizes::ANY, &[Modes::DATA, Modes::DN]),
+            form(Sizes::ANY, &[Modes::DN, Modes::MEMORY_ALTERABLE]),
+            form(Sizes::ANY, &[Modes::IMMEDIATE, Modes::DATA_ALTERABLE]),
+        ],
+    },
+    InstructionRule {
+        names: &["eor"],
+        forms: &[
+            form(Sizes::ANY, &[Modes::DN, Modes::DATA_ALTERABLE]),
+            form(Sizes::ANY, &[Modes::IMMEDIATE, Modes::DATA_ALTERABLE]),
+        ],
+    },
+    InstructionRule {
+        names: &["cmpm"],
+        forms: &[form(Sizes::ANY, &[Modes::POST_INCREMENT, Modes::POST_INCREMENT])],
+    },
+    InstructionRule {
+        names: &["mulu", "muls", "divu", "divs"],
+        forms: &[
+            form(Sizes::WORD, &[Modes::DATA, Modes::DN]),
+            model_form(CpuFeature::LongMultiplyDivide, Sizes::LONG, &[Modes::DATA, Modes::DN.union(Modes::REG_PAIR)]),
+        ],
+    },
+    InstructionRule {
+        names: &["clr", "neg", "not", "tst"],
+        forms: &[form(Sizes::ANY, &[Modes::DATA_ALTERABLE])],
+    },
+    InstructionRule {
+        names: &["ext"],
+        forms: &[form(Sizes::WORD_OR_LONG, &[Modes::DN])],
+    },
+    InstructionRule {
+        names: &["swap"],
+        forms: &[form(Sizes::WORD, &[Modes::DN])],
+    },
+    InstructionRule {
+        names: &["exg"],
+        forms: &[form(Sizes::LONG, &[Modes::REGISTER, Modes::REGISTER])],
+    },
+    InstructionRule {
+        names: &["lea"],
+        forms: &[form(Sizes::LONG, &[Modes::CONTROL, Modes::AN])],
+    },
+    InstructionRule {
+        names: &["pea"],
+        forms: &[form(Sizes::LONG, &[Modes::CONTROL])],
+    },
+    InstructionRule {
+        names: &["jmp", "jsr"],
+        forms: &[form(Sizes::NONE, &[Modes::CONTROL])],
+    },
+    InstructionRule {
+        names: BRANCHES,
+        forms: &[form(Sizes::BYTE.union(Sizes::WORD), &[Modes::ABSOLUTE])],
+    },
+    InstructionRule {
+        names: &["bra", "bsr"],
+        forms: &[form(Sizes::BYTE.union(Sizes::WORD), &[Modes::ABSOLUTE])],
+    },
+    InstructionRule {
+        names: DECREMENT_BRANCHES,
+        forms: &[form(Sizes::WORD, &[Modes::DN, Modes::ABSOLUTE])],
+    },
+    InstructionRule {
+        names: SETS,
+        forms: &[form(Sizes::BYTE, &[Modes::DATA_ALTERABLE])],
+    },
+    InstructionRule {
+        names: &["link"],
+        forms: &[form(Sizes::WORD, &[Modes::AN, Modes::IMMEDIATE])],
+    },
+    InstructionRule {
+        names: &["unlk"],
+        forms: &[form(Sizes::NONE, &[Modes::AN])],
+    },
+    InstructionRule {
+        names: &["trap"],
+        forms: &[form(Sizes::NONE, &[Modes::IMMEDIATE])],
+    },
+    InstructionRule {
+        names: &["rts", "rte", "rtr", "trapv", "illegal", "simhalt", "nop"],
+        forms: &[form(Sizes::NONE, &[])],
+    },
+    InstructionRule {
+        names: &["chk"],
+        forms: &[form(Sizes::WORD, &[Modes::DATA, Modes::DN])],
+    },
+    InstructionRule {
+        names: &["movem"],
+        forms: &[
+            form(Sizes::WORD_OR_LONG, &[MOVEM_LIST, MOVEM_DESTINATION]),
+            form(Sizes::WORD_OR_LONG, &[MOVEM_SOURCE, MOVEM_LIST]),
+        ],
+    },
+    InstructionRule {
+        names: &["lsl", "lsr", "asl", "asr", "rol", "ror"],
+        forms: &[
+            form(Sizes::ANY, &[Modes::DN.union(Modes::IMMEDIATE), Modes::DN]),
+            //memory is shifted by one bit, written with or without the #1 count
+            form(Sizes::WORD, &[Modes::IMMEDIATE, Modes::MEMORY_ALTERABLE]),
+            form(Sizes::WORD, &[Modes::MEMORY_ALTERABLE]),
+        ],
+    },
+    //the register forms are long, the memory forms are byte
+    InstructionRule {
+        names: &["btst"],
+        forms: &[
+            form(Sizes::LONG, &[Modes::DN.union(Modes::IMMEDIATE), Modes::DN]),
+            form(Sizes::BYTE, &[Modes::DN, BIT_DESTINATION]),
+            form(Sizes::BYTE, &[Modes::IMMEDIATE, BIT_DESTINATION.difference(Modes::IMMEDIATE)]),
+        ],
+    },
+    InstructionRule {
+        names: &["bchg", "bclr", "bset"],
+        forms: &[
+            form(Sizes::LONG, &[Modes::DN.union(Modes::IMMEDIATE), Modes::DN]),
+            form(Sizes::BYTE, &[Modes::DN.union(Modes::IMMEDIATE), BIT_ALTERABLE]),
+        ],
+    },
+];
+
+pub fn get_instruction_rule(name: &str) -> Option<&'static InstructionRule> {
+    INSTRUCTION_RULES.iter().find(|rule| rule.names.contains(&name))
+}
+
+/// The addressing mode of an operand, None for the ones that are not operands on their own
+pub fn get_operand_mode(operand: &LexedOperand) -> Option<Modes> {
+    match operand {
+        LexedOperand::Register(register_type, _) | LexedOperand::RegisterWithSize(register_type, _, _) => {
+            match register_type {
+                LexedRegisterType::Data => Some(Modes::DN),
+                LexedRegisterType::Address | LexedRegisterType::SP => Some(Modes::AN),
+                LexedRegisterType::Float => Some(Modes::FP_REG),
+            }
+        }
+        LexedOperand::RegisterRange { .. } => Some(Modes::REG_LIST),
+        LexedOperand::RegisterPair(_, _) => Some(Modes::REG_PAIR),
+        LexedOperand::Bitfield { .. } => Some(Modes::BITFIELD),
+        LexedOperand::Immediate(_) => Some(Modes::IMMEDIATE),
+        LexedOperand::Indirect(_) => Some(Modes::INDIRECT),
+        LexedOperand::PostIndirect(_) => Some(Modes::POST_INCREMENT),
+        LexedOperand::PreIndirect(_) => Some(Modes::PRE_DECREMENT),
+        LexedOperand::IndirectDisplacement { .. } => Some(Modes::DISPLACEMENT),
+        LexedOperand::IndirectIndex { .. } => Some(Modes::INDEX),
+        LexedOperand::MemoryIndirect { .. } => Some(Modes::MEMORY_INDIRECT),
+        LexedOperand::Absolute(_) | LexedOperand::Label(_) => Some(Modes::ABSOLUTE),
+        LexedOperand::ScaledRegister(_, _, _, _) | LexedOperand::Other(_) => None,
+    }
+}
+
+fn get_mode_name(mode: Modes) -> &'static str {
+    match mode {
+        Modes::DN => "Dn",
+        Modes::AN => "An",
+        Modes::INDIRECT => "(An)",
+        Modes::POST_INCREMENT => "(An)+",
+        Modes::PRE_DECREMENT => "-(An)",
+        Modes::DISPLACEMENT => "d16(An)",
+        Modes::INDEX => "d8(An,Xn)",
+        Modes::MEMORY_INDIRECT => "([bd,An],Xn,od)",
+        Modes::ABSOLUTE => "absolute address",
+        Modes::IMMEDIATE => "#immediate",
+        Modes::REG_LIST => "register list",
+        Modes::REG_PAIR => "register pair",
+        Modes::FP_REG => "FPn",
+        _ => "bitfield",
+    }
+}
+
+fn get_size_name(size: &LexedSize) -> &'static str {
+    match size {
+        LexedSize::Byte => ".b",
+        LexedSize::Word => ".w",
+        LexedSize::Long => ".l",
+        LexedSize::Single => ".s",
+        LexedSize::Double => ".d",
+        LexedSize::Extended => ".x",
+        LexedSize::Unspecified | LexedSize::Unknown => "",
+    }
+}
+
+fn to_sizes(size: &LexedSize) -> Sizes {
+    match size {
+        LexedSize::Byte => Sizes::BYTE,
+        LexedSize::Word => Sizes::WORD,
+        LexedSize::Long => Sizes::LONG,
+        _ => Sizes::NONE,
+    }
+}
+
+fn join_sizes(sizes: Sizes) -> String {
+    let names: Vec<&str> = sizes
+        .iter()
+        .map(|size| match size {
+            Sizes::BYTE => ".b",
+            Sizes::WORD => ".w",
+            _ => ".l",
+        })
+        .collect();
+    names.join(" or ")
+}
+
+fn get_operand_role(index: usize, count: usize) -> &'static str {
+    match (index, count) {
+        (_, 1) => "operand",
+        (0, _) => "source",
+        _ => "destination",
+    }
+}
+
+/// The first operand that none of the forms accept after the operands before it, its mode and the modes accepted there
+fn find_illegal_operand(forms: &[&Form], modes: &[Option<Modes>]) -> Option<(usize, Modes, Modes)> {
+    let mut forms = forms.to_vec();
+    for (i, mode) in modes.iter().enumerate() {
+        //the lexer reports the operands it doesn't understand
+        let mode = match mode {
+            Some(mode) => *mode,
+            None => continue,
+        };
+        let allowed = forms.iter().fold(Modes::empty(), |allowed, form| allowed | form.operands[i]);
+        forms.retain(|form| form.operands[i].contains(mode));
+        if forms.is_empty() {
+            return Some((i, mode, allowed));
+        }
+    }
+    None
+}
+
+fn get_operand_counts(rule: &InstructionRule) -> String {
+    let mut counts: Vec<usize> = rule.forms.iter().map(|form| form.operands.len()).collect();
+    counts.sort();
+    counts.dedup();
+    let counts: Vec<String> = counts.iter().map(|count| count.to_string()).collect();
+    let plural = match counts[..] {
+        [ref count] if count == "1" => "",
+        _ => "s",
+    };
+    format!("{} operand{}", counts.join(" or "), plural)
+}
+
+/// Checks the size, the addressing modes and the number of operands of an instruction in the tables
+pub fn validate_instruction(line: &ParsedLine) -> Option<SemanticError> {
+    let (name, operands, size) = match &line.parsed {
+        LexedLine::Instruction { name, operands, size } => (name.as_str(), operands, size),
+        _ => return None,
+    };
+    let rule = get_instruction_rule(name)?;
+    let statement_error = |message: String, code: DiagnosticCode| SemanticError::new(line.clone(), message).with_code(code);
+    let forms: Vec<&Form> = rule.forms.iter().filter(|form| form.operands.len() == operands.len()).collect();
+    if forms.is_empty() {
+        return Some(statement_error(
+            format!("{} expects {}, found {}", name, get_operand_counts(rule), operands.len()),
+            DiagnosticCode::OperandCount,
+        ));
+    }
+    let modes: Vec<Option<Modes>> = operands.iter().map(get_operand_mode).collect();
+    let operand_error = |index: usize, message: String, code: DiagnosticCode| {
+        let error = SemanticError::new(line.clone(), message).with_code(code);
+        //the operands of the lines expanded from a macro don't have their own spans
+        match line.operand_spans.get(index) {
+            Some(span) => error.with_span(*span),
+            None => error,
+        }
+    };
+    let sizes = forms.iter().fold(Sizes::NONE, |sizes, form| sizes | form.sizes);
+    let sized: Vec<&Form> = match size {
+        LexedSize::Unspecified | LexedSize::Unknown => forms.clone(),
+        _ => {
+            let size = to_sizes(size);
+            forms.iter().copied().filter(|form| !size.is_empty() && form.sizes.contains(size)).collect()
+        }
+    };
+    match find_illegal_operand(&forms, &modes) {
+        Some((index, mode, allowed)) => {
+            let allowed: Vec<&str> = allowed.iter().map(get_mode_name).collect();
+            Some(operand_error(
+                index,
+                format!(
+                    "{} is not allowed as the {} of {}, expected {}",
+                    get_mode_name(mode),
+                    get_operand_role(index, operands.len()),
+                    name,
+                    allowed.join(", ")
+                ),
+                DiagnosticCode::InvalidAddressingMode,
+            ))
+        }
+        None if sized.is_empty() && sizes.is_empty() => Some(statement_error(
+            format!("{} is not sized, found {}", name, get_size_name(size)),
+            DiagnosticCode::InvalidSize,
+        )),
+        None if sized.is_empty() => Some(statement_error(
+            format!(
+                "Invalid size {} for {}, expected {}",
+                get_size_name(size),
+                name,
+                join_sizes(sizes)
+            ),
+            DiagnosticCode::InvalidSize,
+        )),
+        //the size is legal, but not with these operands
+        None => find_illegal_operand(&sized, &modes).map(|(index, mode, _)| {
+            operand_error(
+                index,
+                format!(
+                    "Size {} is not allowed with {} as the {} of {}",
+                    get_size_name(size),
+                    get_mode_name(mode),
+                    get_operand_role(index, operands.len()),
+                    name
+                ),
+                DiagnosticCode::InvalidSize,
+            )
+        }),
+    }
+}
+
+/// The diagnostics of the instructions of the lines that the tables reject
+pub fn validate(lines: &[ParsedLine]) -> Vec<Diagnostic> {
+    lines
+        .iter()
+        .filter_map(validate_instruction)
+        .map(|error| Diagnostic::from(&error))
+        .collect()
+}
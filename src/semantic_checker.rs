@@ -1,26 +1,86 @@
 //TODO some instructions might accept indirect and also displacement, check that
 
-use std::collections::HashMap;
+use alloc::{format, string::{String, ToString}, vec, vec::Vec};
+use core::{error::Error, fmt};
 
 use bitflags::bitflags;
 use serde::{Deserialize, Serialize};
+#[cfg(feature = "wasm")]
 use wasm_bindgen::prelude::*;
 
 use crate::{
-    instructions::Label,
-    lexer::{LexedLine, LexedOperand, LexedRegisterType, LexedSize, ParsedLine}, utils::{num_to_signed_base, parse_absolute_expression},
+    completion::get_valid_sizes,
+    cpu_model::{CpuFeature, CpuModel},
+    diagnostic::DiagnosticCode,
+    instructions::{ControlRegister, Label},
+    lesson_profile::LessonProfile,
+    lexer::{get_assembled_lines, LexedLine, LexedOperand, LexedRegisterType, LexedSize, ParsedLine, TextSpan},
+    local_labels::get_label_key,
+    semantic::validate_instruction,
+    symbol::{Interner, LabelTable},
+    utils::{is_identifier, num_to_signed_base, parse_absolute_expression},
 };
 
+/// Secondary place of an error with a label, like where a label was first defined.
+/// The columns are in UTF-16 code units
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RelatedSpan {
+    pub line_index: usize,
+    pub start: usize,
+    pub end: usize,
+    pub message: String,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
-#[wasm_bindgen]
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
 pub struct SemanticError {
     line: ParsedLine,
     error: String,
+    /// The part of the line the error is about, like an operand, the whole statement when missing
+    #[serde(default)]
+    span: Option<TextSpan>,
+    #[serde(default)]
+    related: Vec<RelatedSpan>,
+    #[serde(default = "get_default_code")]
+    code: DiagnosticCode,
+}
+
+fn get_default_code() -> DiagnosticCode {
+    DiagnosticCode::InvalidStatement
 }
 
 impl SemanticError {
     pub fn new(line: ParsedLine, error: String) -> Self {
-        Self { line, error }
+        Self {
+            line,
+            error,
+            span: None,
+            related: vec![],
+            code: DiagnosticCode::InvalidStatement,
+        }
+    }
+    pub fn with_code(mut self, code: DiagnosticCode) -> Self {
+        self.code = code;
+        self
+    }
+    /// The stable code of the error, see the diagnostic module
+    pub fn get_code(&self) -> DiagnosticCode {
+        self.code
+    }
+    pub fn with_span(mut self, span: TextSpan) -> Self {
+        self.span = Some(span);
+        self
+    }
+    pub fn get_span(&self) -> Option<TextSpan> {
+        self.span
+    }
+    /// Adds a labeled span in another place of the code
+    pub fn with_related(mut self, span: RelatedSpan) -> Self {
+        self.related.push(span);
+        self
+    }
+    pub fn get_related(&self) -> &[RelatedSpan] {
+        &self.related
     }
     pub fn get_line(&self) -> &ParsedLine {
         &self.line
@@ -28,24 +88,54 @@ impl SemanticError {
     pub fn get_line_index(&self) -> usize {
         self.line.line_index
     }
+    pub fn get_error(&self) -> &str {
+        &self.error
+    }
+    /// The macro the line was expanded from, the line index is the one of the invocation
+    fn get_macro_location(&self) -> String {
+        match &self.line.macro_origin {
+            Some(origin) => format!(" (in macro '{}', line {})", origin.name, origin.line_index + 1),
+            None => String::new(),
+        }
+    }
     pub fn get_message(&self) -> String {
-        format!("Error on line {}: {}", self.line.line_index + 1, self.error)
+        format!(
+            "Error on line {}{}: {}",
+            self.line.line_index + 1,
+            self.get_macro_location(),
+            self.error
+        )
     }
+    /// The message with the code of the line, followed by the related spans on their own lines
     pub fn get_message_with_line(&self) -> String {
-        format!(
-            "Error on line {}, \"{}\": {}",
+        let mut message = format!(
+            "Error on line {}, \"{}\"{}: {}",
             self.line.line_index + 1,
             self.line.line,
+            self.get_macro_location(),
             self.error
-        )
+        );
+        for span in &self.related {
+            message.push_str(&format!("\n    line {}: {}", span.line_index + 1, span.message));
+        }
+        message
     }
 }
 
-#[wasm_bindgen]
+impl fmt::Display for SemanticError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.get_message())
+    }
+}
+
+impl Error for SemanticError {}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
 impl SemanticError {
     pub fn wasm_get_message(&self) -> String {
         self.get_message()
     }
+    #[cfg(feature = "wasm")]
     pub fn wasm_get_line(&self) -> JsValue {
         serde_wasm_bindgen::to_value(&self.line).unwrap()
     }
@@ -73,6 +163,9 @@ bitflags! {
         const INDIRECT_PRE_DECREMENT = 1<<7;
         const ADDRESS = 1<<8;
         const REG_LIST = 1<<9;
+        const MEMORY_INDIRECT = 1<<10;
+        const REG_PAIR = 1<<11;
+        const FP_REG = 1<<12;
     }
     
     #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -86,6 +179,7 @@ bitflags! {
         const NO_INDIRECT = AdrMode::INDIRECT.bits()
             | AdrMode::INDIRECT_DISPLACEMENT.bits()
             | AdrMode::INDIRECT_INDEX.bits()
+            | AdrMode::MEMORY_INDIRECT.bits()
             | AdrMode::INDIRECT_POST_INCREMENT.bits()
             | AdrMode::INDIRECT_PRE_DECREMENT.bits();
 
@@ -96,6 +190,7 @@ bitflags! {
         const ONLY_INDIRECT = !(AdrMode::INDIRECT.bits()
             | AdrMode::INDIRECT_DISPLACEMENT.bits()
             | AdrMode::INDIRECT_INDEX.bits()
+            | AdrMode::MEMORY_INDIRECT.bits()
             | AdrMode::INDIRECT_POST_INCREMENT.bits()
             | AdrMode::INDIRECT_PRE_DECREMENT.bits()
         );
@@ -103,6 +198,7 @@ bitflags! {
             | AdrMode::INDIRECT.bits()
             | AdrMode::INDIRECT_DISPLACEMENT.bits()
             | AdrMode::INDIRECT_INDEX.bits()
+            | AdrMode::MEMORY_INDIRECT.bits()
             | AdrMode::INDIRECT_POST_INCREMENT.bits()
             | AdrMode::INDIRECT_PRE_DECREMENT.bits()
         );
@@ -111,6 +207,7 @@ bitflags! {
             | AdrMode::INDIRECT.bits()
             | AdrMode::INDIRECT_DISPLACEMENT.bits()
             | AdrMode::INDIRECT_INDEX.bits()
+            | AdrMode::MEMORY_INDIRECT.bits()
             | AdrMode::INDIRECT_POST_INCREMENT.bits()
             | AdrMode::INDIRECT_PRE_DECREMENT.bits()
         );
@@ -119,11 +216,21 @@ bitflags! {
         const ONLY_INDIRECT_OR_ABSOLUTE = !(AdrMode::INDIRECT.bits()
             | AdrMode::INDIRECT_DISPLACEMENT.bits()
             | AdrMode::INDIRECT_INDEX.bits()
+            | AdrMode::MEMORY_INDIRECT.bits()
             | AdrMode::INDIRECT_POST_INCREMENT.bits()
             | AdrMode::INDIRECT_PRE_DECREMENT.bits()
             | AdrMode::ADDRESS.bits()
         );
         const ONLY_REG_LIST = !AdrMode::REG_LIST.bits();
+        const ONLY_FP_REG = !AdrMode::FP_REG.bits();
+        const ONLY_D_REG_OR_PAIR = !(AdrMode::D_REG.bits() | AdrMode::REG_PAIR.bits());
+        const ONLY_D_REG_OR_CONTROL = !(AdrMode::D_REG.bits()
+            | AdrMode::ADDRESS.bits()
+            | AdrMode::INDIRECT.bits()
+            | AdrMode::INDIRECT_DISPLACEMENT.bits()
+            | AdrMode::INDIRECT_INDEX.bits()
+            | AdrMode::MEMORY_INDIRECT.bits()
+        );
         const NO_A_REG_OR_IMMEDIATE = AdrMode::A_REG.bits() | AdrMode::IMMEDIATE.bits();
     }
 }
@@ -138,6 +245,9 @@ impl AdrMode {
             AdrMode::INDIRECT_POST_INCREMENT => "(An)+",
             AdrMode::INDIRECT_PRE_DECREMENT => "-(An)",
             AdrMode::INDIRECT_INDEX => "(An, Dn)",
+            AdrMode::MEMORY_INDIRECT => "([bd,An],Xn,od)",
+            AdrMode::REG_PAIR => "Dh:Dl",
+            AdrMode::FP_REG => "FPn",
             AdrMode::IMMEDIATE => "Im",
             AdrMode::ADDRESS => "Ea/<LABEL>",
 
@@ -167,6 +277,9 @@ impl Rules {
             Rules::ONLY_INDIRECT_OR_ABSOLUTE => "(An)/Ea/<label>",
             Rules::ONLY_POST_INCREMENT => "(An)",
             Rules::ONLY_REG_LIST => "<reg list>",
+            Rules::ONLY_D_REG_OR_PAIR => "Dn/Dh:Dl",
+            Rules::ONLY_FP_REG => "FPn",
+            Rules::ONLY_D_REG_OR_CONTROL => "Dn/(An)/Ea",
             Rules::NO_A_REG_OR_IMMEDIATE => "Dn/(An)/(An)/Ea/<label>",
             _ => "UNKNOWN",
         }
@@ -178,6 +291,9 @@ pub enum SizeRules {
     NoSize,
     AnySize,
     OnlyLongOrWord,
+    OnlyLong,
+    OnlyWord,
+    FloatSize,
 }
 
 impl SizeRules {
@@ -186,50 +302,99 @@ impl SizeRules {
             SizeRules::NoSize => "",
             SizeRules::AnySize => "b, w, l",
             SizeRules::OnlyLongOrWord => "w, l",
+            SizeRules::OnlyLong => "l",
+            SizeRules::OnlyWord => "w",
+            SizeRules::FloatSize => "b, w, l, s, d, x",
         }
             .to_string()
     }
 }
 
 pub struct SemanticChecker {
-    labels: HashMap<String, Label>,
+    labels: LabelTable,
     errors: Vec<SemanticError>,
     lines: Vec<ParsedLine>,
+    cpu_model: CpuModel,
+    lesson_profile: Option<LessonProfile>,
 }
 
 
 impl SemanticChecker {
-    pub fn new(lines: &[ParsedLine]) -> SemanticChecker {
+    pub fn new(lines: &[ParsedLine], cpu_model: CpuModel) -> SemanticChecker {
+        SemanticChecker::new_with_profile(lines, cpu_model, None)
+    }
+    /// Also reports the instructions that the lesson profile doesn't allow
+    pub fn new_with_profile(
+        lines: &[ParsedLine],
+        cpu_model: CpuModel,
+        lesson_profile: Option<LessonProfile>,
+    ) -> SemanticChecker {
+        SemanticChecker::new_with_symbols(lines, cpu_model, lesson_profile, Interner::new())
+    }
+    /// Starts the label table from the names the lexer interned
+    pub fn new_with_symbols(
+        lines: &[ParsedLine],
+        cpu_model: CpuModel,
+        lesson_profile: Option<LessonProfile>,
+        symbols: Interner,
+    ) -> SemanticChecker {
         let mut syntax_checker = SemanticChecker {
             errors: Vec::new(),
             lines: Vec::new(),
-            labels: HashMap::new(),
+            labels: LabelTable::with_symbols(symbols),
+            cpu_model,
+            lesson_profile,
         };
         syntax_checker.check(lines);
         syntax_checker
     }
 
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(lines = lines.len())))]
     pub fn check(&mut self, lines: &[ParsedLine]) {
+        let lines = get_assembled_lines(lines);
         self.lines = lines.to_vec();
         for line in lines.iter() {
             match &line.parsed {
-                LexedLine::Label { name } => {
-                    if self.labels.contains_key(name) {
-                        self.errors.push(SemanticError::new(
-                            line.clone(),
-                            format!("Label \"{}\" already exists", name),
-                        ));
+                LexedLine::Label { name, owner, .. } => {
+                    let key = get_label_key(name, owner.as_deref());
+                    if let Some(label) = self.labels.get(&key) {
+                        let first = lines.iter().find(|l| l.line_index == label.line).map(|l| l.line.as_str());
+                        let start = first.and_then(|first| first.find(name.as_str())).unwrap_or(0);
+                        let prefix = first.map(|first| &first[..start]).unwrap_or_default();
+                        let span = RelatedSpan {
+                            line_index: label.line,
+                            start: prefix.encode_utf16().count(),
+                            end: prefix.encode_utf16().count() + name.encode_utf16().count(),
+                            message: "first defined here".to_string(),
+                        };
+                        self.errors.push(
+                            SemanticError::new(line.clone(), format!("Label \"{}\" already exists", name))
+                                .with_code(DiagnosticCode::DuplicateLabel)
+                                .with_related(span),
+                        );
                     } else {
                         self.labels.insert(
-                            name.to_string(),
+                            &key,
                             Label {
-                                name: name.to_string(),
+                                name: key.clone(),
                                 address: 1 << 31usize, //placeholder value,
                                 line: line.line_index,
                             },
                         );
                     }
                 }
+                //the imported labels are resolved by the linker
+                LexedLine::Directive { name, args, .. } if name == "xref" => {
+                    for symbol in &args[1..] {
+                        if !self.labels.contains_key(symbol) {
+                            self.labels.insert(symbol, Label {
+                                name: symbol.clone(),
+                                address: 1 << 31usize,
+                                line: line.line_index,
+                            });
+                        }
+                    }
+                }
                 _ => {}
             }
         }
@@ -248,6 +413,14 @@ impl SemanticChecker {
             LexedLine::Instruction { .. } => {
                 self.check_instruction(line);
             }
+            LexedLine::Error { diagnostics, .. } => {
+                for diagnostic in diagnostics {
+                    self.errors.push(
+                        SemanticError::new(line.clone(), diagnostic.get_cause().to_string())
+                            .with_code(DiagnosticCode::from(diagnostic)),
+                    );
+                }
+            }
             _ => self.errors.push(SemanticError::new(
                 line.clone(),
                 format!("Unknown line: \"{}\"", line.line),
@@ -266,6 +439,24 @@ impl SemanticChecker {
                 size,
             } => {
                 let name = name.as_str();
+                if let Err(e) = self.cpu_model.verify_instruction(name) {
+                    self.errors
+                        .push(SemanticError::new(line.clone(), e).with_code(DiagnosticCode::UnsupportedByModel));
+                    return;
+                }
+                if let Some(Err(e)) = self.lesson_profile.as_ref().map(|profile| profile.verify_instruction(name)) {
+                    self.errors.push(SemanticError::new(line.clone(), e).with_code(DiagnosticCode::NotInLesson));
+                    return;
+                }
+                if *size == LexedSize::Unknown {
+                    //refused before anything computes the bounds of the size
+                    self.errors.push(SemanticError::new(
+                        line.clone(),
+                        format!("Unknown size, expected any of \"{}\"", get_valid_sizes(name).join(", ")),
+                    ).with_code(DiagnosticCode::InvalidSize));
+                    return;
+                }
+                let errors = self.errors.len();
                 match name {
                     "add" | "sub" => {
                         self.verify_two_args(operands, Rules::NONE, Rules::NO_IMMEDIATE, line);
@@ -275,22 +466,26 @@ impl SemanticChecker {
                                 self.errors.push(SemanticError::new(
                                     line.clone(),
                                     "Invalid operands addressing mode, at most one of the two operands can read/write to memory.".to_string(),
-                                ));
+                                ).with_code(DiagnosticCode::InvalidAddressingMode));
                             }
                             _ => {}
                         };
-                        match (self.get_size_of_instruction(line), operands.first(), operands.get(1)) {
-                            (Some(LexedSize::Byte), _, Some(LexedOperand::Register(LexedRegisterType::Address | LexedRegisterType::SP, _))) => {
-                                self.errors.push(SemanticError::new(
-                                    line.clone(),
-                                    "Byte size not allowed when destination operand is address".to_string(),
-                                ));
-                            }
-                            _ => {} //cannot reach this
-                        };
+                        if let (Some(LexedSize::Byte), Some(LexedOperand::Register(LexedRegisterType::Address | LexedRegisterType::SP, _))) =
+                            (self.get_size_of_instruction(line), operands.get(1))
+                        {
+                            self.errors.push(SemanticError::new(
+                                line.clone(),
+                                "Byte size not allowed when destination operand is address".to_string(),
+                            ).with_code(DiagnosticCode::InvalidSize));
+                        }
                         self.verify_size(SizeRules::AnySize, line);
                         self.verify_size_if_immediate(operands, line, size, LexedSize::Word);
                     }
+                    "move" | "andi" | "ori" | "eori"
+                        if operands.iter().any(|operand| operand.get_special_register().is_some()) =>
+                    {
+                        self.verify_status_instruction(name, operands, line);
+                    }
                     "move" => {
                         self.verify_two_args(operands, Rules::NONE, Rules::NO_IMMEDIATE, line);
                         self.verify_size(SizeRules::AnySize, line);
@@ -302,11 +497,97 @@ impl SemanticChecker {
                         self.verify_size_if_immediate(operands, line, size, LexedSize::Word);
                     }
 
+                    "divs" | "divu" | "muls" | "mulu" if *size == LexedSize::Long => {
+                        let context = format!("{}.l", name);
+                        if let Err(e) = self.cpu_model.verify_feature(CpuFeature::LongMultiplyDivide, &context) {
+                            self.errors
+                                .push(SemanticError::new(line.clone(), e).with_code(DiagnosticCode::UnsupportedByModel));
+                            return;
+                        }
+                        self.verify_two_args(operands, Rules::NO_A_REG, Rules::ONLY_D_REG_OR_PAIR, line);
+                        self.verify_size_if_immediate(operands, line, size, LexedSize::Long);
+                    }
+                    "divsl" | "divul" => {
+                        self.verify_two_args(operands, Rules::NO_A_REG, Rules::ONLY_D_REG_OR_PAIR, line);
+                        self.verify_size(SizeRules::OnlyLong, line);
+                        self.verify_size_if_immediate(operands, line, size, LexedSize::Long);
+                    }
+                    "bftst" | "bfchg" | "bfclr" | "bfset" => match &operands[..] {
+                        [target] => self.verify_bitfield_arg(target, line),
+                        _ => self.errors.push(SemanticError::new(
+                            line.clone(),
+                            format!("Expected one operand, received {}", operands.len()),
+                        ).with_code(DiagnosticCode::OperandCount)),
+                    },
+                    "bfextu" | "bfexts" | "bfffo" => match &operands[..] {
+                        [target, register] => {
+                            self.verify_bitfield_arg(target, line);
+                            self.verify_arg_rule(register, Rules::ONLY_D_REG, line, 2);
+                        }
+                        _ => self.errors.push(SemanticError::new(
+                            line.clone(),
+                            format!("Expected two operands, received \"{}\"", operands.len()),
+                        ).with_code(DiagnosticCode::OperandCount)),
+                    },
+                    "bfins" => match &operands[..] {
+                        [register, target] => {
+                            self.verify_arg_rule(register, Rules::ONLY_D_REG, line, 1);
+                            self.verify_bitfield_arg(target, line);
+                        }
+                        _ => self.errors.push(SemanticError::new(
+                            line.clone(),
+                            format!("Expected two operands, received \"{}\"", operands.len()),
+                        ).with_code(DiagnosticCode::OperandCount)),
+                    },
+                    "fmove" => {
+                        match &operands[..] {
+                            [source, LexedOperand::Register(LexedRegisterType::Float, _)] => {
+                                self.verify_float_source(source, line);
+                                self.verify_arg_rule(&operands[1], Rules::ONLY_FP_REG, line, 2);
+                            }
+                            [LexedOperand::Register(LexedRegisterType::Float, _), _] => {
+                                self.verify_two_args(operands, Rules::ONLY_FP_REG, Rules::NO_A_REG_OR_IMMEDIATE, line);
+                            }
+                            [_, _] => self.errors.push(SemanticError::new(
+                                line.clone(),
+                                "One of the two operands must be a floating point register".to_string(),
+                            ).with_code(DiagnosticCode::InvalidAddressingMode)),
+                            _ => self.errors.push(SemanticError::new(
+                                line.clone(),
+                                format!("Expected two operands, received \"{}\"", operands.len()),
+                            ).with_code(DiagnosticCode::OperandCount)),
+                        }
+                        self.verify_size(SizeRules::FloatSize, line);
+                        self.verify_float_format(operands, size, line);
+                    }
+                    "fadd" | "fsub" | "fmul" | "fdiv" | "fcmp" => {
+                        match &operands[..] {
+                            [source, dest] => {
+                                self.verify_float_source(source, line);
+                                self.verify_arg_rule(dest, Rules::ONLY_FP_REG, line, 2);
+                            }
+                            _ => self.errors.push(SemanticError::new(
+                                line.clone(),
+                                format!("Expected two operands, received \"{}\"", operands.len()),
+                            ).with_code(DiagnosticCode::OperandCount)),
+                        }
+                        self.verify_size(SizeRules::FloatSize, line);
+                        self.verify_float_format(operands, size, line);
+                    }
+                    "fbeq" | "fbne" | "fbgt" | "fbngt" | "fbge" | "fbnge" | "fblt" | "fbnlt" | "fble"
+                    | "fbnle" | "fbgl" | "fbngl" | "fbgle" | "fbngle" | "fbor" | "fbun" | "fbt" | "fbf" => {
+                        self.verify_one_arg(operands, Rules::ONLY_ADDRESS, line);
+                        self.verify_size(SizeRules::NoSize, line);
+                    }
                     "divs" | "divu" | "muls" | "mulu" => {
                         self.verify_two_args(operands, Rules::NO_A_REG, Rules::ONLY_D_REG, line);
                         self.verify_size(SizeRules::NoSize, line);
                         self.verify_size_if_immediate(operands, line, size, LexedSize::Word);
                     }
+                    "chk" => {
+                        self.verify_two_args(operands, Rules::NO_A_REG, Rules::ONLY_D_REG, line);
+                        self.verify_size(SizeRules::OnlyWord, line);
+                    }
                     "swap" => {
                         self.verify_one_arg(operands, Rules::ONLY_D_REG, line);
                         self.verify_size(SizeRules::NoSize, line);
@@ -331,6 +612,10 @@ impl SemanticChecker {
                         self.verify_one_arg(operands, Rules::ONLY_D_REG, line);
                         self.verify_size(SizeRules::OnlyLongOrWord, line);
                     }
+                    "extb" => {
+                        self.verify_one_arg(operands, Rules::ONLY_D_REG, line);
+                        self.verify_size(SizeRules::OnlyLong, line);
+                    }
                     "tst" => {
                         self.verify_one_arg(operands, Rules::NO_IMMEDIATE, line);
                         self.verify_size(SizeRules::AnySize, line);
@@ -413,16 +698,13 @@ impl SemanticChecker {
                         self.verify_two_args(operands, Rules::ONLY_IMMEDIATE, Rules::NO_IMMEDIATE, line);
                         self.verify_value_bounds_if_immediate(operands, 0, line, 1, 8);
                         self.verify_size(SizeRules::AnySize, line);
-                        match operands.get(1) {
-                            Some(LexedOperand::Register(LexedRegisterType::Address, _)) => {
-                                if *size == LexedSize::Byte {
-                                    self.errors.push(SemanticError::new(
-                                        line.clone(),
-                                        "Byte size not allowed for address register".to_string(),
-                                    ))
-                                }
+                        if let Some(LexedOperand::Register(LexedRegisterType::Address, _)) = operands.get(1) {
+                            if *size == LexedSize::Byte {
+                                self.errors.push(SemanticError::new(
+                                    line.clone(),
+                                    "Byte size not allowed for address register".to_string(),
+                                ).with_code(DiagnosticCode::InvalidSize))
                             }
-                            _ => {}
                         }
                     }
                     "moveq" => {
@@ -443,7 +725,7 @@ impl SemanticChecker {
                                 self.errors.push(SemanticError::new(
                                     line.clone(),
                                     format!("Invalid operands for movem instruction, register list and memory, or memory and register list, received \"{}\" operands", operands.len()),
-                                ));
+                                ).with_code(DiagnosticCode::InvalidAddressingMode));
                             }
                         }
                     }
@@ -466,25 +748,7 @@ impl SemanticChecker {
                     "trap" => {
                         self.verify_one_arg(operands, Rules::ONLY_IMMEDIATE, line);
                         self.verify_size(SizeRules::NoSize, line);
-                        match &operands[..] {
-                            [LexedOperand::Immediate(value)] => {
-                                match self.get_immediate_value(value) {
-                                    Ok(value) => {
-                                        if value != 15 {
-                                            self.errors.push(SemanticError::new(
-                                                line.clone(),
-                                                format!(
-                                                    "Only implemented TRAP is 15 for IO, received \"{}\"",
-                                                    value
-                                                ),
-                                            ));
-                                        }
-                                    }
-                                    Err(e) => self.errors.push(SemanticError::new(line.clone(), e)),
-                                }
-                            }
-                            _ => {}
-                        }
+                        self.verify_value_bounds_if_immediate(operands, 0, line, 0, 15);
                     }
                     "rts" => {
                         self.verify_size(SizeRules::NoSize, line);
@@ -492,17 +756,110 @@ impl SemanticChecker {
                             self.errors.push(SemanticError::new(
                                 line.clone(),
                                 "RTS instruction does not accept operands".to_string(),
-                            ));
+                            ).with_code(DiagnosticCode::OperandCount));
+                        }
+                    }
+                    "rte" => {
+                        self.verify_size(SizeRules::NoSize, line);
+                        if !operands.is_empty() {
+                            self.errors.push(SemanticError::new(
+                                line.clone(),
+                                "RTE instruction does not accept operands".to_string(),
+                            ).with_code(DiagnosticCode::OperandCount));
+                        }
+                    }
+                    "rtr" => {
+                        self.verify_size(SizeRules::NoSize, line);
+                        if !operands.is_empty() {
+                            self.errors.push(SemanticError::new(
+                                line.clone(),
+                                "RTR instruction does not accept operands".to_string(),
+                            ).with_code(DiagnosticCode::OperandCount));
+                        }
+                    }
+                    "trapv" | "illegal" | "simhalt" | "nop" => {
+                        self.verify_size(SizeRules::NoSize, line);
+                        if !operands.is_empty() {
+                            self.errors.push(SemanticError::new(
+                                line.clone(),
+                                format!("{} instruction does not accept operands", name.to_uppercase()),
+                            ).with_code(DiagnosticCode::OperandCount));
+                        }
+                    }
+                    "rtd" => {
+                        self.verify_one_arg(operands, Rules::ONLY_IMMEDIATE, line);
+                        self.verify_size(SizeRules::NoSize, line);
+                        self.verify_value_bounds_if_immediate(operands, 0, line, -32768, 32767);
+                    }
+                    "movec" => {
+                        self.verify_size(SizeRules::NoSize, line);
+                        match &operands[..] {
+                            [LexedOperand::Absolute(control), op2] if control.parse::<ControlRegister>().is_ok() => {
+                                self.verify_arg_rule(op2, Rules::ONLY_REG, line, 2);
+                            }
+                            [op1, LexedOperand::Absolute(control)] if control.parse::<ControlRegister>().is_ok() => {
+                                self.verify_arg_rule(op1, Rules::ONLY_REG, line, 1);
+                            }
+                            _ => {
+                                self.errors.push(SemanticError::new(
+                                    line.clone(),
+                                    "Invalid operands for movec instruction, expected a control register (sfc, dfc, usp, vbr) and a Dn/An register".to_string(),
+                                ).with_code(DiagnosticCode::InvalidAddressingMode));
+                            }
                         }
                     }
+                    "moves" => {
+                        self.verify_size(SizeRules::AnySize, line);
+                        match &operands[..] {
+                            [LexedOperand::Register(_, _), op2] => {
+                                self.verify_arg_rule(op2, Rules::ONLY_INDIRECT_OR_ABSOLUTE, line, 2);
+                            }
+                            [op1, LexedOperand::Register(_, _)] => {
+                                self.verify_arg_rule(op1, Rules::ONLY_INDIRECT_OR_ABSOLUTE, line, 1);
+                            }
+                            _ => {
+                                self.errors.push(SemanticError::new(
+                                    line.clone(),
+                                    "Invalid operands for moves instruction, expected a Dn/An register and a memory operand".to_string(),
+                                ).with_code(DiagnosticCode::InvalidAddressingMode));
+                            }
+                        }
+                    }
+                    "linef" => {
+                        self.verify_size(SizeRules::NoSize, line);
+                        match &operands[..] {
+                            [op1] => self.verify_arg_rule(op1, Rules::ONLY_IMMEDIATE, line, 1),
+                            [op1, op2] => {
+                                self.verify_arg_rule(op1, Rules::ONLY_IMMEDIATE, line, 1);
+                                self.verify_arg_rule(op2, Rules::NO_IMMEDIATE, line, 2);
+                            }
+                            _ => {
+                                self.errors.push(SemanticError::new(
+                                    line.clone(),
+                                    format!("Expected an opcode word and an optional effective address, received \"{}\" operands", operands.len()),
+                                ).with_code(DiagnosticCode::OperandCount));
+                            }
+                        }
+                        self.verify_value_bounds_if_immediate(operands, 0, line, 0xF000, 0xFFFF);
+                    }
                     "lsl" | "lsr" | "asr" | "asl" | "rol" | "ror" => {
-                        self.verify_two_args(
-                            operands,
-                            Rules::NO_A_REG,
-                            Rules::NO_A_REG_OR_IMMEDIATE,
-                            line,
-                        );
-                        self.verify_value_bounds_if_immediate(operands, 0, line, 0, 8);
+                        match operands[..] {
+                            //memory is shifted by one bit, the tables check the addressing mode and the size
+                            [_] => self.verify_one_arg(operands, Rules::NO_A_REG_OR_IMMEDIATE, line),
+                            _ => {
+                                self.verify_two_args(
+                                    operands,
+                                    Rules::NO_A_REG,
+                                    Rules::NO_A_REG_OR_IMMEDIATE,
+                                    line,
+                                );
+                                let (min, max) = match operands.get(1) {
+                                    Some(LexedOperand::Register(LexedRegisterType::Data, _)) | None => (0, 8),
+                                    _ => (1, 1),
+                                };
+                                self.verify_value_bounds_if_immediate(operands, 0, line, min, max);
+                            }
+                        }
                         self.verify_size(SizeRules::AnySize, line);
                     }
                     "btst" | "bclr" | "bchg" | "bset" => {
@@ -516,10 +873,37 @@ impl SemanticChecker {
                         self.verify_value_bounds_if_immediate(operands, 0, line, 0, 0xFF);
                     }
 
-                    _ => self.errors.push(SemanticError::new(
-                        line.clone(),
-                        format!("Unknown instruction: \"{}\"", name),
-                    )),
+                    _ => match CpuModel::get_unimplemented_instruction_model(name) {
+                        Some(model) if self.cpu_model.is_older_than(model) => {
+                            self.errors.push(SemanticError::new(
+                                line.clone(),
+                                format!(
+                                    "\"{}\" is not available on the {}, the minimum model is the {}",
+                                    name,
+                                    self.cpu_model.get_name(),
+                                    model.get_name()
+                                ),
+                            ).with_code(DiagnosticCode::UnsupportedByModel))
+                        }
+                        Some(model) => self.errors.push(SemanticError::new(
+                            line.clone(),
+                            format!(
+                                "\"{}\" is a {} instruction that is not implemented yet",
+                                name,
+                                model.get_name()
+                            ),
+                        ).with_code(DiagnosticCode::UnsupportedByModel)),
+                        None => self.errors.push(SemanticError::new(
+                            line.clone(),
+                            format!("Unknown instruction: \"{}\"", name),
+                        ).with_code(DiagnosticCode::UnknownInstruction)),
+                    },
+                }
+                //the legality tables catch the sizes and addressing modes the checks above let through
+                if self.errors.len() == errors {
+                    if let Some(error) = validate_instruction(line) {
+                        self.errors.push(error);
+                    }
                 }
             }
             _ => self.errors.push(SemanticError::new(
@@ -537,7 +921,7 @@ impl SemanticChecker {
                         self.errors.push(SemanticError::new(
                             line.clone(),
                             "Invalid number of arguments for directive equ".to_string(),
-                        ));
+                        ).with_code(DiagnosticCode::InvalidDirectiveArguments));
                     }
                 }
                 "org" => {
@@ -545,9 +929,21 @@ impl SemanticChecker {
                         self.errors.push(SemanticError::new(
                             line.clone(),
                             "Invalid number of arguments for directive org".to_string(),
-                        ));
+                        ).with_code(DiagnosticCode::InvalidDirectiveArguments));
                     }
                 }
+                "end" => match &args[..] {
+                    [_] => {}
+                    [_, label] if self.labels.contains_key(label) => {}
+                    [_, label] => self.errors.push(SemanticError::new(
+                        line.clone(),
+                        format!("Label \"{}\" of directive end is not defined", label),
+                    ).with_code(DiagnosticCode::InvalidDirectiveArguments)),
+                    _ => self.errors.push(SemanticError::new(
+                        line.clone(),
+                        "Invalid number of arguments for directive end".to_string(),
+                    ).with_code(DiagnosticCode::InvalidDirectiveArguments)),
+                },
                 "dc" => {
                     self.verify_size(SizeRules::AnySize, line);
                     match &args[..] {
@@ -561,7 +957,7 @@ impl SemanticChecker {
                                             Err(_) => self.errors.push(SemanticError::new(
                                                 line.clone(),
                                                 format!("Invalid argument \"{}\" for directive dc at position {}", arg, i + 1),
-                                            )),
+                                            ).with_code(DiagnosticCode::InvalidDirectiveArguments)),
                                         }
                                     }
                                 }
@@ -570,7 +966,7 @@ impl SemanticChecker {
                         _ => self.errors.push(SemanticError::new(
                             line.clone(),
                             "No arguments for directive dc".to_string(),
-                        )),
+                        ).with_code(DiagnosticCode::InvalidDirectiveArguments)),
                     }
                 }
                 "ds" => {
@@ -583,13 +979,13 @@ impl SemanticChecker {
                                 "ds",
                                 args.len()
                             ),
-                        )),
+                        ).with_code(DiagnosticCode::InvalidDirectiveArguments)),
                         [_, arg] => match self.get_absolute_value(arg) {
                             Ok(_) => {}
                             Err(_) => self.errors.push(SemanticError::new(
                                 line.clone(),
                                 format!("Invalid argument for directive: \"{}\"", "ds"),
-                            )),
+                            ).with_code(DiagnosticCode::InvalidDirectiveArguments)),
                         },
                         _ => self.errors.push(SemanticError::new(
                             line.clone(),
@@ -598,7 +994,7 @@ impl SemanticChecker {
                                 "ds",
                                 args.len()
                             ),
-                        )),
+                        ).with_code(DiagnosticCode::InvalidDirectiveArguments)),
                     }
                 }
                 "dcb" => {
@@ -608,7 +1004,7 @@ impl SemanticChecker {
                             self.errors.push(SemanticError::new(
                                 line.clone(),
                                 format!("Too few arguments for label directive: \"{}\", expected 2, got {}", "ds", args.len()),
-                            ));
+                            ).with_code(DiagnosticCode::InvalidDirectiveArguments));
                         }
                         [_, first, second] => {
                             match self.get_absolute_value(first) {
@@ -617,7 +1013,7 @@ impl SemanticChecker {
                                     self.errors.push(SemanticError::new(
                                         line.clone(),
                                         "Invalid length argument for dcb directive".to_string(),
-                                    ));
+                                    ).with_code(DiagnosticCode::InvalidDirectiveArguments));
                                 }
                             }
                             let el = match self.get_absolute_value(second) {
@@ -626,7 +1022,7 @@ impl SemanticChecker {
                                     self.errors.push(SemanticError::new(
                                         line.clone(),
                                         "Invalid default value argument for dcb directive".to_string(),
-                                    ));
+                                    ).with_code(DiagnosticCode::InvalidDirectiveArguments));
                                     return;
                                 }
                             };
@@ -638,7 +1034,7 @@ impl SemanticChecker {
                                         "Value exceeds the limit of the specified size{}",
                                         max
                                     ),
-                                ));
+                                ).with_code(DiagnosticCode::ValueOutOfRange));
                             }
                         }
                         _ => {
@@ -649,7 +1045,34 @@ impl SemanticChecker {
                                     "dcb",
                                     args.len()
                                 ),
-                            ));
+                            ).with_code(DiagnosticCode::InvalidDirectiveArguments));
+                        }
+                    }
+                }
+                "xdef" | "public" | "xref" => {
+                    if args.len() < 2 {
+                        self.errors.push(SemanticError::new(
+                            line.clone(),
+                            format!("No labels for directive {}", name),
+                        ).with_code(DiagnosticCode::InvalidDirectiveArguments));
+                    }
+                    for symbol in &args[1..] {
+                        match self.labels.get(symbol) {
+                            _ if !is_identifier(symbol) => self.errors.push(SemanticError::new(
+                                line.clone(),
+                                format!("Invalid label name \"{}\" for directive {}", symbol, name),
+                            ).with_code(DiagnosticCode::InvalidDirectiveArguments)),
+                            None if name != "xref" => self.errors.push(SemanticError::new(
+                                line.clone(),
+                                format!("Label \"{}\" is exported but not defined", symbol),
+                            ).with_code(DiagnosticCode::InvalidDirectiveArguments)),
+                            Some(label) if name == "xref" && label.line != line.line_index => {
+                                self.errors.push(SemanticError::new(
+                                    line.clone(),
+                                    format!("Label \"{}\" is imported but already defined", symbol),
+                                ).with_code(DiagnosticCode::InvalidDirectiveArguments))
+                            }
+                            _ => {}
                         }
                     }
                 }
@@ -657,10 +1080,13 @@ impl SemanticChecker {
                     self.errors.push(SemanticError::new(
                         line.clone(),
                         format!("Unknown directive {}", name),
-                    ));
+                    ).with_code(DiagnosticCode::UnknownDirective));
                 }
             },
-            _ => panic!("Line is not a directive"),
+            _ => self.errors.push(SemanticError::new(
+                line.clone(),
+                "Expected a directive".to_string(),
+            ).with_code(DiagnosticCode::InvalidStatement)),
         }
     }
     fn verify_two_args(
@@ -678,7 +1104,7 @@ impl SemanticChecker {
             _ => self.errors.push(SemanticError::new(
                 line.clone(),
                 format!("Expected two operands, received \"{}\"", args.len()),
-            )),
+            ).with_code(DiagnosticCode::OperandCount)),
         }
     }
 
@@ -690,7 +1116,7 @@ impl SemanticChecker {
             _ => self.errors.push(SemanticError::new(
                 line.clone(),
                 format!("Expected one operand, received {}", args.len()),
-            )),
+            ).with_code(DiagnosticCode::OperandCount)),
         }
     }
     fn verify_size_if_immediate(
@@ -700,34 +1126,28 @@ impl SemanticChecker {
         size: &LexedSize,
         default: LexedSize,
     ) {
+        let size = match size {
+            LexedSize::Unspecified => &default,
+            size => size,
+        };
         let size_value = match size {
             //TODO do i really have to use i64?
-            LexedSize::Byte | LexedSize::Word | LexedSize::Long => {
-                size.to_bits_word_default() as i64
-            }
-            LexedSize::Unspecified => match default {
-                LexedSize::Byte | LexedSize::Word | LexedSize::Long => {
-                    default.to_bits_word_default() as i64
-                }
-                _ => panic!("Invalid default size"),
-            },
-            _ => 0,
+            LexedSize::Byte | LexedSize::Word | LexedSize::Long => size.to_bits_word_default() as i64,
+            //the other sizes are refused by verify_size, there is no bound to check the immediate against
+            _ => return,
         };
-        match args {
-            [LexedOperand::Immediate(value), ..] => match self.get_immediate_value(value) {
-                Ok(parsed) => match num_to_signed_base(parsed, size_value) {
-                    Ok(_) => {}
-                    Err(_) => self.errors.push(SemanticError::new(
+        if let [LexedOperand::Immediate(value), ..] = args {
+            if let Ok(parsed) = self.get_immediate_value(value) {
+                if num_to_signed_base(parsed, size_value).is_err() {
+                    self.errors.push(SemanticError::new(
                         line.clone(),
                         format!(
                             "Immediate value \"{}\" is not a valid {} bits number, received \"{}\"",
                             value, size_value, parsed
                         ),
-                    )),
-                },
-                Err(_) => {}
-            },
-            _ => {}
+                    ).with_code(DiagnosticCode::ValueOutOfRange))
+                }
+            }
         }
     }
 
@@ -739,21 +1159,57 @@ impl SemanticChecker {
         min: i64,
         max: i64,
     ) {
-        match args.get(arg_position) {
-            Some(LexedOperand::Immediate(value)) => {
-                match self.get_immediate_value(value.as_str()) {
-                    Ok(n) => {
-                        if n < min || n > max {
-                            self.errors.push(SemanticError::new(
-                                line.clone(),
-                                format!("Immediate value \"{}\" out of range, must be between \"{}\" and \"{}\" ", value, min, max),
-                            ));
-                        }
+        if let Some(LexedOperand::Immediate(value)) = args.get(arg_position) {
+            if let Ok(n) = self.get_immediate_value(value.as_str()) {
+                if n < min || n > max {
+                    self.errors.push(SemanticError::new(
+                        line.clone(),
+                        format!("Immediate value \"{}\" out of range, must be between \"{}\" and \"{}\" ", value, min, max),
+                    ).with_code(DiagnosticCode::ValueOutOfRange));
+                }
+            }
+        }
+    }
+    /// MOVE to and from SR, CCR and USP, ANDI/ORI/EORI to SR and CCR
+    fn verify_status_instruction(&mut self, name: &str, operands: &[LexedOperand], line: &ParsedLine) {
+        let specials: Vec<Option<String>> = operands.iter().map(|operand| operand.get_special_register()).collect();
+        match (name, &specials[..]) {
+            ("move", [None, Some(usp)]) if usp == "usp" => {
+                self.verify_arg_rule(&operands[0], Rules::ONLY_A_REG, line, 1);
+                self.verify_size(SizeRules::OnlyLong, line);
+            }
+            ("move", [Some(usp), None]) if usp == "usp" => {
+                self.verify_arg_rule(&operands[1], Rules::ONLY_A_REG, line, 2);
+                self.verify_size(SizeRules::OnlyLong, line);
+            }
+            ("move", [None, Some(_)]) => {
+                self.verify_arg_rule(&operands[0], Rules::NO_A_REG, line, 1);
+                self.verify_size(SizeRules::OnlyWord, line);
+            }
+            ("move", [Some(register), None]) => {
+                if register == "ccr" {
+                    if let Err(e) = self.cpu_model.verify_feature(CpuFeature::VectorBaseRegister, "move from ccr") {
+                        self.errors
+                            .push(SemanticError::new(line.clone(), e).with_code(DiagnosticCode::UnsupportedByModel));
                     }
-                    Err(_) => {}
                 }
+                self.verify_arg_rule(&operands[1], Rules::NO_A_REG_OR_IMMEDIATE, line, 2);
+                self.verify_size(SizeRules::OnlyWord, line);
             }
-            _ => {}
+            (_, [None, Some(register)]) if register != "usp" => {
+                self.verify_arg_rule(&operands[0], Rules::ONLY_IMMEDIATE, line, 1);
+                match register.as_str() {
+                    "ccr" => self.verify_value_bounds_if_immediate(operands, 0, line, 0, 255),
+                    _ => self.verify_value_bounds_if_immediate(operands, 0, line, 0, 65535),
+                }
+            }
+            _ => self.errors.push(SemanticError::new(
+                line.clone(),
+                format!(
+                    "Invalid operands for {}, expected a status register (sr, ccr) or usp with a single other operand",
+                    name
+                ),
+            ).with_code(DiagnosticCode::InvalidAddressingMode)),
         }
     }
     fn verify_arg_rule(
@@ -778,12 +1234,13 @@ impl SemanticChecker {
                             "Incorrect {} operand addressing mode, received \"{}\", expected \"{}\"",
                             arg_position_name, mode.get_name(), rule.get_valid_addressing_modes()
                         ),
-                    ));
+                    ).with_code(DiagnosticCode::InvalidAddressingMode));
                 }
             }
             Err(e) => {
                 let error = e.to_string();
-                self.errors.push(SemanticError::new(line.clone(), error));
+                self.errors
+                    .push(SemanticError::new(line.clone(), error).with_code(DiagnosticCode::InvalidAddressingMode));
             }
         }
     }
@@ -801,7 +1258,7 @@ impl SemanticChecker {
                     self.errors.push(SemanticError::new(
                         line.clone(),
                         format!("Unknown size, expected any of \"{}\"", rule.get_valid_sizes()),
-                    ));
+                    ).with_code(DiagnosticCode::InvalidSize));
                 }
 
                 SizeRules::NoSize => {
@@ -809,7 +1266,7 @@ impl SemanticChecker {
                         self.errors.push(SemanticError::new(
                             line.clone(),
                             "Invalid size, instruction is not sized".to_string(),
-                        ))
+                        ).with_code(DiagnosticCode::InvalidSize))
                     }
                 }
                 SizeRules::OnlyLongOrWord => {
@@ -817,33 +1274,56 @@ impl SemanticChecker {
                         self.errors.push(SemanticError::new(
                             line.clone(),
                             "Invalid size, instruction must be long or word".to_string(),
-                        ));
+                        ).with_code(DiagnosticCode::InvalidSize));
                     }
                 }
+                SizeRules::OnlyLong => {
+                    if *size != LexedSize::Long && *size != LexedSize::Unspecified {
+                        self.errors.push(SemanticError::new(
+                            line.clone(),
+                            "Invalid size, instruction must be long".to_string(),
+                        ).with_code(DiagnosticCode::InvalidSize));
+                    }
+                }
+                SizeRules::OnlyWord => {
+                    if *size != LexedSize::Word && *size != LexedSize::Unspecified {
+                        self.errors.push(SemanticError::new(
+                            line.clone(),
+                            "Invalid size, instruction must be word".to_string(),
+                        ).with_code(DiagnosticCode::InvalidSize));
+                    }
+                }
+                SizeRules::FloatSize => {}
                 SizeRules::AnySize => {
                     match *size {
+                        LexedSize::Single | LexedSize::Double | LexedSize::Extended => {
+                            self.errors.push(SemanticError::new(
+                                line.clone(),
+                                "Invalid size, floating point sizes can only be used by FPU instructions".to_string(),
+                            ).with_code(DiagnosticCode::InvalidSize));
+                        }
                         LexedSize::Byte => {
-                            match &line.parsed {
-                                LexedLine::Instruction { operands, .. } => {
-                                    //check if it has any address register
-                                    let has_address_reg = operands.iter().find(|op| {
-                                        matches!(op, LexedOperand::Register(LexedRegisterType::Address, _))
-                                    });
-                                    if let Some(_op) = has_address_reg {
-                                        self.errors.push(SemanticError::new(
-                                            line.clone(),
-                                            "Invalid size, address register cannot be used with byte size".to_string(),
-                                        ));
-                                    }
+                            if let LexedLine::Instruction { operands, .. } = &line.parsed {
+                                //check if it has any address register
+                                let has_address_reg = operands.iter().find(|op| {
+                                    matches!(op, LexedOperand::Register(LexedRegisterType::Address, _))
+                                });
+                                if let Some(_op) = has_address_reg {
+                                    self.errors.push(SemanticError::new(
+                                        line.clone(),
+                                        "Invalid size, address register cannot be used with byte size".to_string(),
+                                    ).with_code(DiagnosticCode::InvalidSize));
                                 }
-                                _ => {}
                             }
                         }
                         _ => {}
                     }
                 }
             },
-            _ => panic!("Line is not an instruction or directive"),
+            _ => self.errors.push(SemanticError::new(
+                line.clone(),
+                "Expected an instruction or a directive".to_string(),
+            ).with_code(DiagnosticCode::InvalidStatement)),
         }
     }
     fn get_addressing_mode(&mut self, operand: &LexedOperand) -> Result<AdrMode, String> {
@@ -860,6 +1340,10 @@ impl SemanticChecker {
                         _ => Err("Invalid address register".to_string()),
                     },
                     LexedRegisterType::SP => Ok(AdrMode::A_REG),
+                    LexedRegisterType::Float => match reg_name[2..].parse::<i8>() {
+                        Ok(reg) if (0..8).contains(&reg) => Ok(AdrMode::FP_REG),
+                        _ => Err("Invalid floating point register".to_string()),
+                    },
                 }
             }
             LexedOperand::RegisterRange { .. } => Ok(AdrMode::REG_LIST),
@@ -910,21 +1394,16 @@ impl SemanticChecker {
                 if !offset.is_empty() {
                     match offset.parse::<i64>() {
                         Ok(num) => {
-                            if !(-(1 << 7)..=1 << 7).contains(&num) {
-                                return Err("Invalid offset, must be between -128 and 128".to_string());
+                            if !(-(1 << 7)..(1 << 7)).contains(&num) {
+                                return Err("Invalid offset, must be between -128 and 127".to_string());
                             }
                         }
                         Err(_) => return Err("Offset is not a valid decimal number".to_string()),
                     }
                 }
                 match operands[..] {
-                    [LexedOperand::Register(LexedRegisterType::Address, _), LexedOperand::Register(_, _) | LexedOperand::RegisterWithSize(_, _, _)] => {
-                        let op2 = &operands[1];
-                        if let LexedOperand::RegisterWithSize(_, _, size) = op2 {
-                            if *size == LexedSize::Byte {
-                                return Err("Byte size in register is not allowed for index indirect".to_string());
-                            }
-                        }
+                    [LexedOperand::Register(LexedRegisterType::Address, _), LexedOperand::Register(_, _) | LexedOperand::RegisterWithSize(_, _, _) | LexedOperand::ScaledRegister(_, _, _, _)] => {
+                        self.check_index_register(&operands[1])?;
                         Ok(AdrMode::INDIRECT_INDEX)
                     }
                     _ => Err(
@@ -932,6 +1411,29 @@ impl SemanticChecker {
                     ),
                 }
             }
+            LexedOperand::MemoryIndirect { inner, outer } => {
+                self.cpu_model.verify_feature(CpuFeature::MemoryIndirect, "([bd,An],Xn,od)")?;
+                let parts = LexedOperand::split_memory_indirect(inner, outer)?;
+                for displacement in [parts.base_displacement, parts.outer_displacement].into_iter().flatten() {
+                    match parse_absolute_expression(displacement, &self.labels) {
+                        Ok(num) if (i32::MIN as i64..=u32::MAX as i64).contains(&num) => {}
+                        Ok(num) => return Err(format!("Invalid displacement \"{}\", must fit in 32 bits", num)),
+                        Err(_) => return Err(format!("Displacement \"{}\" is not a valid number", displacement)),
+                    }
+                }
+                if let Some(index) = parts.index {
+                    self.check_index_register(index)?;
+                }
+                Ok(AdrMode::MEMORY_INDIRECT)
+            }
+            LexedOperand::ScaledRegister(_, _, _, _) => Err("Scaled registers can only be used as an index".to_string()),
+            LexedOperand::RegisterPair(high, low) => {
+                match (LexedRegisterType::from_string(high), LexedRegisterType::from_string(low)) {
+                    (Ok(LexedRegisterType::Data), Ok(LexedRegisterType::Data)) if high != low => Ok(AdrMode::REG_PAIR),
+                    _ => Err("Invalid register pair, must be two different data registers".to_string()),
+                }
+            }
+            LexedOperand::Bitfield { .. } => Err("Bitfield operands can only be used by bitfield instructions".to_string()),
             LexedOperand::Label(name) => {
                 if self.labels.contains_key(name) {
                     Ok(AdrMode::ADDRESS)
@@ -947,6 +1449,59 @@ impl SemanticChecker {
             LexedOperand::Other(_) => Err("Unknown operand".to_string()),
         }
     }
+    fn verify_float_source(&mut self, arg: &LexedOperand, line: &ParsedLine) {
+        match arg {
+            //floating point literals are only valid for the FPU
+            LexedOperand::Immediate(value) if value[1..].parse::<f64>().is_ok() => {}
+            _ => self.verify_arg_rule(arg, Rules::NO_A_REG, line, 1),
+        }
+    }
+    fn verify_float_format(&mut self, args: &[LexedOperand], size: &LexedSize, line: &ParsedLine) {
+        let uses_data_register = args
+            .iter()
+            .any(|arg| matches!(arg, LexedOperand::Register(LexedRegisterType::Data, _)));
+        if uses_data_register && matches!(size, LexedSize::Double | LexedSize::Extended) {
+            self.errors.push(SemanticError::new(
+                line.clone(),
+                "Data registers can only be used with the b, w, l and s formats".to_string(),
+            ).with_code(DiagnosticCode::InvalidSize));
+        }
+    }
+    fn verify_bitfield_arg(&mut self, arg: &LexedOperand, line: &ParsedLine) {
+        match arg {
+            LexedOperand::Bitfield { operand, offset, width } => {
+                let checks = [(offset, 0, 31, "offset"), (width, 1, 32, "width")];
+                for (value, min, max, name) in checks {
+                    if LexedRegisterType::from_string(value) == Ok(LexedRegisterType::Data) {
+                        continue;
+                    }
+                    match self.get_absolute_value(value) {
+                        Ok(num) if (min..=max).contains(&num) => {}
+                        _ => self.errors.push(SemanticError::new(
+                            line.clone(),
+                            format!("Invalid bitfield {} \"{}\", must be a data register or a number between {} and {}", name, value, min, max),
+                        ).with_code(DiagnosticCode::ValueOutOfRange)),
+                    }
+                }
+                self.verify_arg_rule(operand, Rules::ONLY_D_REG_OR_CONTROL, line, 1);
+            }
+            _ => self.errors.push(SemanticError::new(
+                line.clone(),
+                "Expected a bitfield operand, like \"<ea>{offset:width}\"".to_string(),
+            ).with_code(DiagnosticCode::InvalidAddressingMode)),
+        }
+        self.verify_size(SizeRules::NoSize, line);
+    }
+    fn check_index_register(&self, index: &LexedOperand) -> Result<(), String> {
+        match index {
+            LexedOperand::RegisterWithSize(_, _, LexedSize::Byte)
+            | LexedOperand::ScaledRegister(_, _, LexedSize::Byte, _) => {
+                Err("Byte size in register is not allowed for index indirect".to_string())
+            }
+            LexedOperand::ScaledRegister(_, register, _, scale) => self.cpu_model.verify_index_scale(register, *scale),
+            _ => Ok(()),
+        }
+    }
     fn get_immediate_value(&self, num: &str) -> Result<i64, String> {
         self.get_absolute_value(&num[1..])
     }
@@ -0,0 +1,319 @@
+use alloc::{string::String, vec, vec::Vec};
+use crate::collections::HashSet;
+
+use serde::Serialize;
+
+use crate::{
+    lexer::{LexedLine, ParsedLine},
+    utils::{is_register, split_comment},
+};
+
+/*
+    Semantic classification of the source for editors, every token is classified with what it means
+    in the program instead of how it looks: a word is a label only if it is defined as one, a constant only
+    if an equ defines it. The tokens are read from the source text, the lexed lines only tell what each line is.
+    Positions are in UTF-16 code units like in the LSP, the encoded form is the LSP one of 5 integers per token
+    relative to the previous token, and edits between two encodings are used for the delta updates
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum SemanticTokenType {
+    Label,
+    /// Not produced until macros are supported by the lexer
+    Macro,
+    Register,
+    Directive,
+    Constant,
+    Instruction,
+    Number,
+    String,
+    Comment,
+    /// The size suffix of a mnemonic, like the ".l" of "move.l"
+    Size,
+}
+
+pub const SEMANTIC_TOKEN_TYPES: &[SemanticTokenType] = &[
+    SemanticTokenType::Label,
+    SemanticTokenType::Macro,
+    SemanticTokenType::Register,
+    SemanticTokenType::Directive,
+    SemanticTokenType::Constant,
+    SemanticTokenType::Instruction,
+    SemanticTokenType::Number,
+    SemanticTokenType::String,
+    SemanticTokenType::Comment,
+    SemanticTokenType::Size,
+];
+
+/// Modifier set on the label or constant that is defined in that position
+pub const DECLARATION_MODIFIER: u32 = 1;
+pub const SEMANTIC_TOKEN_MODIFIERS: &[&str] = &["declaration"];
+
+impl SemanticTokenType {
+    /// Name in the legend, the standard LSP one when there is one
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            Self::Label => "label",
+            Self::Macro => "macro",
+            Self::Register => "variable",
+            Self::Directive => "keyword",
+            Self::Constant => "enumMember",
+            Self::Instruction => "function",
+            Self::Number => "number",
+            Self::String => "string",
+            Self::Comment => "comment",
+            Self::Size => "type",
+        }
+    }
+    pub fn get_index(&self) -> u32 {
+        SEMANTIC_TOKEN_TYPES.iter().position(|t| t == self).unwrap_or_default() as u32
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SemanticToken {
+    pub line: usize,
+    pub start: usize,
+    pub length: usize,
+    pub token_type: SemanticTokenType,
+    pub modifiers: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SemanticTokensEdit {
+    pub start: usize,
+    pub delete_count: usize,
+    pub data: Vec<u32>,
+}
+
+fn utf16_length(text: &str) -> usize {
+    text.encode_utf16().count()
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+struct LineTokens<'a> {
+    line: &'a str,
+    line_index: usize,
+    tokens: Vec<SemanticToken>,
+}
+
+impl<'a> LineTokens<'a> {
+    /// Adds the token at the byte range of the line
+    fn push(&mut self, start: usize, end: usize, token_type: SemanticTokenType, modifiers: u32) {
+        if end > start {
+            self.tokens.push(SemanticToken {
+                line: self.line_index,
+                start: utf16_length(&self.line[..start]),
+                length: utf16_length(&self.line[start..end]),
+                token_type,
+                modifiers,
+            });
+        }
+    }
+}
+
+/// Classifies the words of the operands, starting at the byte offset of the line
+fn classify_operands(tokens: &mut LineTokens, offset: usize, end: usize, labels: &HashSet<String>, constants: &HashSet<String>) {
+    let operands = &tokens.line[offset..end];
+    let chars: Vec<(usize, char)> = operands.char_indices().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        let (start, c) = chars[i];
+        match c {
+            '\'' => {
+                i += 1;
+                while i < chars.len() && chars[i].1 != '\'' {
+                    i += 1;
+                }
+                i = (i + 1).min(chars.len());
+            }
+            '$' | '%' | '@' | '0'..='9' => {
+                i += 1;
+                while i < chars.len() && chars[i].1.is_ascii_alphanumeric() {
+                    i += 1;
+                }
+            }
+            c if is_word_char(c) => {
+                while i < chars.len() && is_word_char(chars[i].1) {
+                    i += 1;
+                }
+            }
+            _ => {
+                i += 1;
+                continue;
+            }
+        }
+        let stop = chars.get(i).map(|(index, _)| *index).unwrap_or(operands.len());
+        let word = &operands[start..stop];
+        let token_type = match c {
+            '\'' => Some(SemanticTokenType::String),
+            '$' | '%' | '@' | '0'..='9' => Some(SemanticTokenType::Number),
+            _ if is_register(word) => Some(SemanticTokenType::Register),
+            _ if labels.contains(word) => Some(SemanticTokenType::Label),
+            _ if constants.contains(word) => Some(SemanticTokenType::Constant),
+            _ => None,
+        };
+        if let Some(token_type) = token_type {
+            tokens.push(offset + start, offset + stop, token_type, 0);
+        }
+    }
+}
+
+/// Pushes the mnemonic and its size suffix as two tokens
+fn push_mnemonic(tokens: &mut LineTokens, start: usize, end: usize, token_type: SemanticTokenType) {
+    match tokens.line[start..end].find('.') {
+        Some(dot) => {
+            tokens.push(start, start + dot, token_type, 0);
+            tokens.push(start + dot, end, SemanticTokenType::Size, 0);
+        }
+        None => tokens.push(start, end, token_type, 0),
+    }
+}
+
+/// Returns the byte range of the first word after the offset
+fn next_word(line: &str, offset: usize) -> (usize, usize) {
+    let start = line[offset..]
+        .find(|c: char| !c.is_whitespace())
+        .map(|i| offset + i)
+        .unwrap_or(line.len());
+    let end = line[start..]
+        .find(char::is_whitespace)
+        .map(|i| start + i)
+        .unwrap_or(line.len());
+    (start, end)
+}
+
+fn classify_line(tokens: &mut LineTokens, lexed: &[&LexedLine], labels: &HashSet<String>, constants: &HashSet<String>) {
+    let line = tokens.line;
+    let (code, comment) = split_comment(line);
+    let has_label = lexed.iter().any(|l| matches!(l, LexedLine::Label { .. }));
+    let is_unknown = lexed.iter().any(|l| matches!(l, LexedLine::Unknown { .. }));
+    let directive = lexed.iter().find_map(|l| match l {
+        LexedLine::Directive { name, .. } => Some(name.as_str()),
+        _ => None,
+    });
+    let has_instruction = lexed.iter().any(|l| matches!(l, LexedLine::Instruction { .. }));
+    let mut offset = 0;
+    if has_label && !is_unknown {
+        if let Some(colon) = code.find(':') {
+            let start = code.len() - code.trim_start().len();
+            tokens.push(start, colon, SemanticTokenType::Label, DECLARATION_MODIFIER);
+            offset = colon + 1;
+        }
+    }
+    if !is_unknown {
+        match (directive, has_instruction) {
+            (Some("equ"), _) => {
+                let (name_start, name_end) = next_word(code, offset);
+                tokens.push(name_start, name_end, SemanticTokenType::Constant, DECLARATION_MODIFIER);
+                let (start, end) = next_word(code, name_end);
+                tokens.push(start, end, SemanticTokenType::Directive, 0);
+                classify_operands(tokens, end, code.len(), labels, constants);
+            }
+            (Some(_), _) | (None, true) => {
+                let (start, end) = next_word(code, offset);
+                let token_type = match directive {
+                    Some(_) => SemanticTokenType::Directive,
+                    None => SemanticTokenType::Instruction,
+                };
+                push_mnemonic(tokens, start, end, token_type);
+                classify_operands(tokens, end, code.len(), labels, constants);
+            }
+            _ => {}
+        }
+    }
+    if let Some(comment) = comment {
+        tokens.push(code.len(), code.len() + comment.trim_end().len(), SemanticTokenType::Comment, 0);
+    }
+}
+
+/// Classifies the tokens of the code, the lines must be the lexed lines of the same code
+pub fn get_semantic_tokens(code: &str, lexed_lines: &[ParsedLine]) -> Vec<SemanticToken> {
+    let lines: Vec<&str> = code.lines().collect();
+    let mut lexed: Vec<Vec<&LexedLine>> = vec![vec![]; lines.len()];
+    let mut labels = HashSet::new();
+    let mut constants = HashSet::new();
+    for line in lexed_lines {
+        if let Some(entry) = lexed.get_mut(line.line_index) {
+            entry.push(&line.parsed);
+        }
+        match &line.parsed {
+            LexedLine::Label { name, .. } => {
+                labels.insert(name.clone());
+            }
+            //the first arg of an equ is the name of the constant
+            LexedLine::Directive { name, args, .. } if name == "equ" => {
+                if let Some(constant) = args.first() {
+                    constants.insert(constant.clone());
+                }
+            }
+            _ => {}
+        }
+    }
+    let mut result = vec![];
+    for (line_index, (line, parsed)) in lines.iter().zip(lexed.iter()).enumerate() {
+        let mut tokens = LineTokens {
+            line,
+            line_index,
+            tokens: vec![],
+        };
+        classify_line(&mut tokens, parsed, &labels, &constants);
+        result.append(&mut tokens.tokens);
+    }
+    result
+}
+
+/// Encodes the tokens like the LSP, as line delta, start delta, length, type and modifiers
+pub fn encode_semantic_tokens(tokens: &[SemanticToken]) -> Vec<u32> {
+    let mut result = Vec::with_capacity(tokens.len() * 5);
+    let mut previous_line = 0;
+    let mut previous_start = 0;
+    for token in tokens {
+        let delta_line = token.line - previous_line;
+        let delta_start = match delta_line {
+            0 => token.start - previous_start,
+            _ => token.start,
+        };
+        result.extend([
+            delta_line as u32,
+            delta_start as u32,
+            token.length as u32,
+            token.token_type.get_index(),
+            token.modifiers,
+        ]);
+        previous_line = token.line;
+        previous_start = token.start;
+    }
+    result
+}
+
+/// Finds the edit that turns the previous encoded tokens into the new ones, none if they are the same.
+/// The edit replaces whole tokens, so start and delete_count are always multiples of 5
+pub fn get_semantic_tokens_edit(previous: &[u32], current: &[u32]) -> Option<SemanticTokensEdit> {
+    if previous == current {
+        return None;
+    }
+    let previous_tokens: Vec<&[u32]> = previous.chunks(5).collect();
+    let current_tokens: Vec<&[u32]> = current.chunks(5).collect();
+    let prefix = previous_tokens
+        .iter()
+        .zip(current_tokens.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let max_suffix = previous_tokens.len().min(current_tokens.len()) - prefix;
+    let suffix = previous_tokens
+        .iter()
+        .rev()
+        .zip(current_tokens.iter().rev())
+        .take(max_suffix)
+        .take_while(|(a, b)| a == b)
+        .count();
+    Some(SemanticTokensEdit {
+        start: prefix * 5,
+        delete_count: (previous_tokens.len() - prefix - suffix) * 5,
+        data: current[prefix * 5..(current_tokens.len() - suffix) * 5].to_vec(),
+    })
+}
@@ -0,0 +1,93 @@
+use alloc::{string::{String, ToString}, vec, vec::Vec};
+use serde::Serialize;
+
+use crate::{
+    completion::get_typed_addressing_modes,
+    constants::INSTRUCTIONS,
+    instruction_info::get_entry,
+    utils::{split_comment, split_operands},
+    S68k,
+};
+
+/*
+    Signature help for editors, shown while the operands of an instruction are typed.
+    The signature is the mnemonic as written followed by its operands, like "move.l src, dst", every operand
+    has the addressing modes that are legal at that position. Like the completions the modes are found with
+    the semantic checker and depend on the other operand when it is already typed, so "move.l a0, " doesn't
+    offer the modes that can't take an address register
+*/
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SignatureParameter {
+    pub name: &'static str,
+    /// Offsets of the name in the label of the signature
+    pub start: usize,
+    pub end: usize,
+    pub modes: Vec<&'static str>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SignatureHelp {
+    pub label: String,
+    pub description: Option<&'static str>,
+    pub parameters: Vec<SignatureParameter>,
+    /// Missing when the cursor is after the last operand the instruction takes
+    pub active_parameter: Option<usize>,
+}
+
+fn get_parameter_names(count: usize) -> &'static [&'static str] {
+    match count {
+        0 => &[],
+        1 => &["ea"],
+        _ => &["src", "dst"],
+    }
+}
+
+/// Signature of the instruction under the cursor, the column is counted in characters.
+/// Missing when the cursor is not in the operands of an instruction
+pub fn get_signature_help(s68k: &S68k, line_index: usize, column: usize) -> Option<SignatureHelp> {
+    let line = s68k.get_code().lines().nth(line_index)?;
+    let before: String = line.chars().take(column).collect();
+    if split_comment(&before).1.is_some() {
+        return None;
+    }
+    let (code, _) = split_comment(line);
+    let (before, code) = match before.find(':') {
+        Some(index) => (&before[index + 1..], &code[index + 1..]),
+        None => (before.as_str(), code),
+    };
+    let (written, typed_before) = before.trim_start().split_once(char::is_whitespace)?;
+    let name = written.split('.').next().unwrap_or_default().to_lowercase();
+    if !INSTRUCTIONS.contains(&name.as_str()) {
+        return None;
+    }
+    let operands = code
+        .trim_start()
+        .split_once(char::is_whitespace)
+        .map(|(_, operands)| split_operands(operands))
+        .unwrap_or_default();
+    let cpu_model = s68k.get_cpu_model();
+    let modes: Vec<Vec<&'static str>> = (0..2)
+        .map(|position| get_typed_addressing_modes(&name, position, &operands, cpu_model))
+        .take_while(|modes| !modes.is_empty())
+        .collect();
+    let mut label = written.to_string();
+    let mut parameters = vec![];
+    for (i, (name, modes)) in get_parameter_names(modes.len()).iter().zip(modes).enumerate() {
+        label.push_str(if i == 0 { " " } else { ", " });
+        parameters.push(SignatureParameter {
+            name,
+            start: label.len(),
+            end: label.len() + name.len(),
+            modes,
+        });
+        label.push_str(name);
+    }
+    let position = split_operands(typed_before).len().max(1) - 1;
+    Some(SignatureHelp {
+        label,
+        description: get_entry(&name).map(|(_, description, _, _)| description),
+        active_parameter: Some(position).filter(|position| *position < parameters.len()),
+        parameters,
+    })
+}
@@ -0,0 +1,206 @@
+use alloc::{format, string::{String, ToString}, vec, vec::Vec};
+use crate::collections::HashMap;
+
+use serde::Serialize;
+
+use crate::{
+    diff::longest_common_subsequence,
+    lexer::{LexedLine, LexedOperand, LexedRegisterType, LexedSize, ParsedLine},
+    visitor::{walk_lexed_operand_mut, VisitorMut},
+};
+
+/*
+    Structural similarity between two programs, to find copied submissions and to group solutions by strategy.
+    Both programs are normalized before they are compared, so that renaming does not hide a copy:
+        comments and empty lines are removed
+        labels are renamed by the order they first appear in, loop: and again: are both l0
+        data and address registers are renamed by the order they are first used in, sp and a7 are kept
+    The score goes from 0 to 1 and is the mean of two measures:
+        statements  the longest common subsequence of the normalized statements, 2 * common / (len a + len b)
+        opcodes     how many sequences of 3 mnemonics the programs share, which doesn't change when
+                    blocks of code are moved around
+*/
+
+/// Length of the sequences of mnemonics that are compared
+const OPCODE_GRAM: usize = 3;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SimilarityReport {
+    pub score: f64,
+    pub statements: f64,
+    pub opcodes: f64,
+    /// Pairs of line indexes of the statements that are the same once normalized
+    pub matched_lines: Vec<(usize, usize)>,
+}
+
+#[derive(Default)]
+struct Normalizer {
+    labels: Vec<String>,
+    renamed_labels: HashMap<String, String>,
+    data_registers: HashMap<String, String>,
+    address_registers: HashMap<String, String>,
+}
+
+impl Normalizer {
+    fn rename_label(&mut self, name: &str) -> String {
+        let count = self.renamed_labels.len();
+        self.renamed_labels
+            .entry(name.to_string())
+            .or_insert_with(|| format!("l{}", count))
+            .clone()
+    }
+    fn rename_register(&mut self, kind: &LexedRegisterType, name: &mut String) {
+        let lowercase = name.to_lowercase();
+        let (registers, prefix) = match kind {
+            LexedRegisterType::Data => (&mut self.data_registers, "d"),
+            LexedRegisterType::Address if lowercase != "a7" => (&mut self.address_registers, "a"),
+            _ => {
+                *name = lowercase;
+                return;
+            }
+        };
+        let count = registers.len();
+        *name = registers
+            .entry(lowercase)
+            .or_insert_with(|| format!("{}{}", prefix, count))
+            .clone();
+    }
+    /// Renames the labels in an expression, the rest of it is kept
+    fn rename_expression(&mut self, expression: &mut String) {
+        let mut result = String::new();
+        let mut word = String::new();
+        for c in expression.chars().chain(core::iter::once('\0')) {
+            if c.is_alphanumeric() || c == '_' || c == '.' {
+                word.push(c);
+                continue;
+            }
+            match self.labels.contains(&word) {
+                true => result.push_str(&self.rename_label(&word.clone())),
+                false => result.push_str(&word),
+            }
+            word.clear();
+            if c != '\0' {
+                result.push(c);
+            }
+        }
+        *expression = result;
+    }
+}
+
+impl VisitorMut for Normalizer {
+    fn visit_label_mut(&mut self, name: &mut String) {
+        *name = self.rename_label(name);
+    }
+    fn visit_directive_mut(&mut self, name: &mut String, _size: &mut LexedSize, args: &mut Vec<String>) {
+        *name = name.to_lowercase();
+        for arg in args {
+            self.rename_expression(arg);
+        }
+    }
+    fn visit_instruction_mut(&mut self, name: &mut String, _size: &mut LexedSize, operands: &mut Vec<LexedOperand>) {
+        *name = name.to_lowercase();
+        for operand in operands {
+            self.visit_lexed_operand_mut(operand);
+        }
+    }
+    fn visit_lexed_operand_mut(&mut self, operand: &mut LexedOperand) {
+        match operand {
+            LexedOperand::Register(kind, name)
+            | LexedOperand::RegisterWithSize(kind, name, _)
+            | LexedOperand::ScaledRegister(kind, name, _, _) => self.rename_register(kind, name),
+            LexedOperand::RegisterPair(high, low) => {
+                self.rename_register(&LexedRegisterType::Data, high);
+                self.rename_register(&LexedRegisterType::Data, low);
+            }
+            LexedOperand::Immediate(expression)
+            | LexedOperand::Absolute(expression)
+            | LexedOperand::Label(expression)
+            | LexedOperand::IndirectDisplacement { offset: expression, .. }
+            | LexedOperand::IndirectIndex { offset: expression, .. } => self.rename_expression(expression),
+            _ => {}
+        }
+        walk_lexed_operand_mut(self, operand)
+    }
+}
+
+/// The statements of the program with the labels and registers renamed, with their line index
+fn normalize(lines: &[ParsedLine]) -> Vec<(usize, LexedLine)> {
+    let mut normalizer = Normalizer {
+        labels: lines
+            .iter()
+            .filter_map(|line| match &line.parsed {
+                LexedLine::Label { name, .. } => Some(name.clone()),
+                _ => None,
+            })
+            .collect(),
+        ..Default::default()
+    };
+    lines
+        .iter()
+        .filter(|line| !matches!(line.parsed, LexedLine::Comment { .. } | LexedLine::Empty))
+        .map(|line| {
+            let mut parsed = line.parsed.clone();
+            normalizer.visit_lexed_line_mut(&mut parsed);
+            (line.line_index, parsed)
+        })
+        .collect()
+}
+
+fn get_opcode_grams(statements: &[(usize, LexedLine)]) -> HashMap<Vec<&str>, usize> {
+    let opcodes: Vec<&str> = statements
+        .iter()
+        .filter_map(|(_, line)| match line {
+            LexedLine::Instruction { name, .. } => Some(name.as_str()),
+            _ => None,
+        })
+        .collect();
+    let mut grams = HashMap::new();
+    //a program shorter than a gram is a gram by itself
+    for gram in opcodes.windows(OPCODE_GRAM.min(opcodes.len().max(1))) {
+        *grams.entry(gram.to_vec()).or_insert(0) += 1;
+    }
+    grams
+}
+
+/// 2 * common / total, two empty programs are the same
+fn get_ratio(common: usize, total: usize) -> f64 {
+    match total {
+        0 => 1.0,
+        _ => (2 * common) as f64 / total as f64,
+    }
+}
+
+/// Compares the lexed lines of two programs
+pub fn compare_programs(a: &[ParsedLine], b: &[ParsedLine]) -> SimilarityReport {
+    let (a, b) = (normalize(a), normalize(b));
+    let matched: Vec<(usize, usize)> = longest_common_subsequence(&a, &b, |a, b| a.1 == b.1)
+        .into_iter()
+        .map(|(i, j)| (a[i].0, b[j].0))
+        .collect();
+    let statements = get_ratio(matched.len(), a.len() + b.len());
+    let (grams_a, grams_b) = (get_opcode_grams(&a), get_opcode_grams(&b));
+    let common: usize = grams_a
+        .iter()
+        .map(|(gram, count)| (*count).min(grams_b.get(gram).copied().unwrap_or(0)))
+        .sum();
+    let opcodes = get_ratio(common, grams_a.values().sum::<usize>() + grams_b.values().sum::<usize>());
+    SimilarityReport {
+        score: (statements + opcodes) / 2.0,
+        statements,
+        opcodes,
+        matched_lines: matched,
+    }
+}
+
+/// The score of every pair of programs, to cluster them. The matrix is symmetric with 1 on the diagonal
+pub fn get_similarity_matrix(programs: &[&[ParsedLine]]) -> Vec<Vec<f64>> {
+    let mut matrix = vec![vec![1.0; programs.len()]; programs.len()];
+    for i in 0..programs.len() {
+        for j in i + 1..programs.len() {
+            let score = compare_programs(programs[i], programs[j]).score;
+            matrix[i][j] = score;
+            matrix[j][i] = score;
+        }
+    }
+    matrix
+}
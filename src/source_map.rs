@@ -0,0 +1,70 @@
+use alloc::vec::Vec;
+use serde::Serialize;
+
+use crate::compiler::InstructionLine;
+
+/*
+    Map between the addresses of the instructions and the lines of the source, for the debuggers that highlight
+    the line of the PC and set breakpoints on lines.
+    The line of an instruction expanded from a macro is the line of the invocation, so a line can have several
+    addresses, the line of the body it comes from is kept in the entry. Lines without instructions, like labels,
+    comments and data, have no address.
+    The addresses are the ones of the compiled program, the same the interpreter runs
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct SourceMapEntry {
+    pub address: usize,
+    pub line_index: usize,
+    /// Line of the macro body the instruction was expanded from
+    pub macro_line_index: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SourceMap {
+    /// Sorted by address
+    entries: Vec<SourceMapEntry>,
+}
+
+impl SourceMap {
+    pub fn new(instructions: &[InstructionLine]) -> SourceMap {
+        let mut entries: Vec<SourceMapEntry> = instructions
+            .iter()
+            .map(|instruction| SourceMapEntry {
+                address: instruction.address,
+                line_index: instruction.parsed_line.line_index,
+                macro_line_index: instruction.parsed_line.macro_origin.as_ref().map(|origin| origin.line_index),
+            })
+            .collect();
+        entries.sort_by_key(|entry| entry.address);
+        SourceMap { entries }
+    }
+    pub fn get_entries(&self) -> &[SourceMapEntry] {
+        &self.entries
+    }
+    /// The entry of the instruction that starts at the address
+    pub fn get_entry_at(&self, address: usize) -> Option<&SourceMapEntry> {
+        self.entries
+            .binary_search_by_key(&address, |entry| entry.address)
+            .ok()
+            .map(|index| &self.entries[index])
+    }
+    pub fn get_line_at(&self, address: usize) -> Option<usize> {
+        self.get_entry_at(address).map(|entry| entry.line_index)
+    }
+    /// The addresses of the instructions of the line, in order
+    pub fn get_addresses_of_line(&self, line_index: usize) -> Vec<usize> {
+        self.entries
+            .iter()
+            .filter(|entry| entry.line_index == line_index)
+            .map(|entry| entry.address)
+            .collect()
+    }
+    /// Where the line starts running, the address a breakpoint on the line stops at
+    pub fn get_first_address_of_line(&self, line_index: usize) -> Option<usize> {
+        self.entries
+            .iter()
+            .find(|entry| entry.line_index == line_index)
+            .map(|entry| entry.address)
+    }
+}
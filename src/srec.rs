@@ -0,0 +1,224 @@
+use alloc::{format, string::{String, ToString}, vec, vec::Vec};
+use core::{error::Error, fmt, str::FromStr};
+
+use crate::assembler::MachineCode;
+
+/*
+    Motorola S-records of the machine code, the text format read by EPROM programmers, monitors and emulators.
+    Every record is S, its type, the count of the bytes that follow, the address, the data and a checksum,
+    all in hex. The checksum is the ones' complement of the low byte of the sum of the count, address and data.
+        S0      header, address 0 and the name of the program as data
+        S1 S2 S3        data with an address of 16, 24 or 32 bits
+        S5 S6   count of the data records, in 16 or 24 bits
+        S9 S8 S7        entry point with an address of 16, 24 or 32 bits, ends the file
+    The format is named after the data and entry records it uses: S19, S28 or S37. When it is not given
+    the smallest one that fits the last address is picked.
+    Only the bytes of the lines are written, ranges far apart, like the ones of two ORG, are not joined by
+    records of zeros
+*/
+
+/// Bytes of data of a record unless configured
+pub const DEFAULT_RECORD_LENGTH: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SrecFormat {
+    S19,
+    S28,
+    S37,
+}
+
+impl SrecFormat {
+    /// Bytes of the address of the data and entry records
+    pub fn get_address_length(&self) -> usize {
+        match self {
+            SrecFormat::S19 => 2,
+            SrecFormat::S28 => 3,
+            SrecFormat::S37 => 4,
+        }
+    }
+    pub fn get_max_address(&self) -> u64 {
+        (1 << (self.get_address_length() * 8)) - 1
+    }
+    /// The smallest format that can address the last byte
+    pub fn fitting(last_address: usize) -> SrecFormat {
+        match last_address {
+            0..=0xFFFF => SrecFormat::S19,
+            0x10000..=0xFFFFFF => SrecFormat::S28,
+            _ => SrecFormat::S37,
+        }
+    }
+    fn get_data_type(&self) -> u8 {
+        match self {
+            SrecFormat::S19 => 1,
+            SrecFormat::S28 => 2,
+            SrecFormat::S37 => 3,
+        }
+    }
+    fn get_entry_type(&self) -> u8 {
+        10 - self.get_data_type()
+    }
+}
+
+impl FromStr for SrecFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "s19" => Ok(SrecFormat::S19),
+            "s28" => Ok(SrecFormat::S28),
+            "s37" => Ok(SrecFormat::S37),
+            _ => Err(format!("Unknown S-record format \"{}\", expected s19, s28 or s37", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SrecOptions {
+    /// When missing the smallest format that fits the program
+    pub format: Option<SrecFormat>,
+    /// Bytes of data of every record, the last one of a range can be shorter
+    pub record_length: usize,
+    /// Data of the S0 record, usually the name of the file
+    pub header: String,
+}
+
+impl Default for SrecOptions {
+    fn default() -> Self {
+        Self {
+            format: None,
+            record_length: DEFAULT_RECORD_LENGTH,
+            header: String::new(),
+        }
+    }
+}
+
+impl SrecOptions {
+    pub fn with_format(mut self, format: SrecFormat) -> Self {
+        self.format = Some(format);
+        self
+    }
+    pub fn with_record_length(mut self, record_length: usize) -> Self {
+        self.record_length = record_length;
+        self
+    }
+    pub fn with_header(mut self, header: impl Into<String>) -> Self {
+        self.header = header.into();
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SrecError {
+    /// The record length is 0 or doesn't fit the count byte with the address and checksum
+    InvalidRecordLength { length: usize, max: usize },
+    AddressTooLarge { address: usize, format: SrecFormat },
+    /// The header doesn't fit a single record
+    HeaderTooLong(usize),
+}
+
+impl fmt::Display for SrecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SrecError::InvalidRecordLength { length, max } => {
+                write!(f, "Invalid record length {}, it must be between 1 and {}", length, max)
+            }
+            SrecError::AddressTooLarge { address, format } => {
+                write!(f, "The address ${:X} doesn't fit the addresses of {:?}", address, format)
+            }
+            SrecError::HeaderTooLong(length) => write!(
+                f,
+                "The header is {} bytes long, a record holds up to {}",
+                length,
+                get_max_data_length(2)
+            ),
+        }
+    }
+}
+
+impl Error for SrecError {}
+
+impl From<SrecError> for String {
+    fn from(error: SrecError) -> Self {
+        error.to_string()
+    }
+}
+
+/// The count is a byte and includes the address and the checksum
+fn get_max_data_length(address_length: usize) -> usize {
+    255 - address_length - 1
+}
+
+/// A record with its checksum, the address is truncated to the bytes of the type
+fn make_record(record_type: u8, address: u32, address_length: usize, data: &[u8]) -> String {
+    let mut bytes = vec![(address_length + data.len() + 1) as u8];
+    bytes.extend_from_slice(&address.to_be_bytes()[4 - address_length..]);
+    bytes.extend_from_slice(data);
+    let checksum = !bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+    let hex: String = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+    format!("S{}{}{:02X}\n", record_type, hex, checksum)
+}
+
+/// The ranges of bytes that belong to lines, lines closer than a record are written together
+fn get_ranges(machine_code: &MachineCode, record_length: usize) -> Vec<(usize, usize)> {
+    let mut lines: Vec<(usize, usize)> = machine_code
+        .lines
+        .iter()
+        .map(|line| (line.address, line.address + line.length))
+        .collect();
+    lines.sort();
+    let mut ranges: Vec<(usize, usize)> = vec![];
+    for (start, end) in lines {
+        match ranges.last_mut() {
+            Some(last) if start <= last.1 + record_length => last.1 = last.1.max(end),
+            _ => ranges.push((start, end)),
+        }
+    }
+    ranges
+}
+
+pub fn write_srec(machine_code: &MachineCode, options: &SrecOptions) -> Result<String, SrecError> {
+    let last_address = machine_code
+        .get_end_address()
+        .saturating_sub(1)
+        .max(machine_code.start_address);
+    let format = options.format.unwrap_or(SrecFormat::fitting(last_address));
+    if last_address as u64 > format.get_max_address() {
+        return Err(SrecError::AddressTooLarge {
+            address: last_address,
+            format,
+        });
+    }
+    let address_length = format.get_address_length();
+    let max = get_max_data_length(address_length);
+    if options.record_length == 0 || options.record_length > max {
+        return Err(SrecError::InvalidRecordLength {
+            length: options.record_length,
+            max,
+        });
+    }
+    if options.header.len() > get_max_data_length(2) {
+        return Err(SrecError::HeaderTooLong(options.header.len()));
+    }
+    let mut srec = make_record(0, 0, 2, options.header.as_bytes());
+    let mut records = 0;
+    for (start, end) in get_ranges(machine_code, options.record_length) {
+        let bytes = &machine_code.bytes[start - machine_code.origin..end - machine_code.origin];
+        for (i, chunk) in bytes.chunks(options.record_length).enumerate() {
+            let address = (start + i * options.record_length) as u32;
+            srec.push_str(&make_record(format.get_data_type(), address, address_length, chunk));
+            records += 1;
+        }
+    }
+    //the count is optional, it is left out when it doesn't fit
+    match records {
+        0..=0xFFFF => srec.push_str(&make_record(5, records, 2, &[])),
+        0x10000..=0xFFFFFF => srec.push_str(&make_record(6, records, 3, &[])),
+        _ => {}
+    }
+    srec.push_str(&make_record(
+        format.get_entry_type(),
+        machine_code.start_address as u32,
+        address_length,
+        &[],
+    ));
+    Ok(srec)
+}
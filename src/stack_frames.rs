@@ -0,0 +1,327 @@
+use alloc::{format, string::String, vec, vec::Vec};
+use serde::Serialize;
+
+use crate::{
+    compiler::InstructionLine,
+    cost_report::get_encoded_size,
+    instructions::{Instruction, Operand, RegisterOperand, Size, TargetDirection},
+    interpreter::Interpreter,
+    lexer::{LexedLine, LexedOperand, LexedRegisterType, LexedSize},
+    utils::parse_absolute_expression,
+};
+
+/*
+    A structured view of the stack for the "stack diagram" of a debugger, one frame for every active call:
+
+        arguments       positive offsets from the frame pointer
+        return address  pushed by BSR/JSR
+        saved registers pushed by MOVEM or MOVE to -(sp) before or after the LINK
+        saved frame     the old frame pointer pushed by LINK
+        locals          negative offsets from the frame pointer
+
+    The layout is found from the prologue of the called function, the instructions at its start that
+    were already run: LINK, MOVEM to -(sp) and MOVE of a register to -(sp). Arguments and locals are
+    the displacements from the frame pointer used by the function, named after the symbol written in
+    the source, like "count(a6)" with "count equ -4".
+    The return address is searched on the stack, a frame where it was not found only has the function
+*/
+
+/// Instructions of a function that are looked at to find its prologue and variables
+const MAX_FUNCTION_LENGTH: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SavedRegister {
+    pub register: RegisterOperand,
+    pub address: usize,
+    pub value: u32,
+}
+
+/// A local or argument of a frame
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct StackVariable {
+    /// The symbol used for its offset in the source, missing when the offset is a number
+    pub name: Option<String>,
+    /// Offset from the frame pointer
+    pub offset: i32,
+    pub address: usize,
+    pub size: usize,
+    /// Missing when the memory can't be read
+    pub value: Option<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct StackFrame {
+    /// Label of the called function, or its address in hex
+    pub function: String,
+    pub function_address: usize,
+    /// Address of the BSR/JSR that made the call
+    pub call_site: usize,
+    pub return_address: usize,
+    /// Where the return address is on the stack, missing when it was not found
+    pub return_address_slot: Option<usize>,
+    /// Register used as frame pointer by the LINK of the prologue
+    pub frame_register: Option<RegisterOperand>,
+    /// The value of the frame pointer, where the old one was saved
+    pub frame_pointer: Option<usize>,
+    /// Bytes reserved for the locals by the LINK
+    pub locals_size: usize,
+    pub saved_registers: Vec<SavedRegister>,
+    pub locals: Vec<StackVariable>,
+    pub arguments: Vec<StackVariable>,
+    /// The frame is the memory from bottom to top, top excluded. It starts at the return address
+    /// and ends where the next frame starts or at the stack pointer
+    pub top: usize,
+    pub bottom: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct StackView {
+    pub stack_pointer: usize,
+    /// Where the stack started, the frames are between it and the stack pointer
+    pub stack_top: usize,
+    /// From the outermost call
+    pub frames: Vec<StackFrame>,
+}
+
+/// Instructions of the function in order, until its first return
+fn get_function_body(interpreter: &Interpreter, address: usize) -> Vec<&InstructionLine> {
+    let mut body = vec![];
+    let mut address = address;
+    while let Some(line) = interpreter.get_instruction_at(address) {
+        body.push(line);
+        if body.len() >= MAX_FUNCTION_LENGTH {
+            break;
+        }
+        match line.instruction {
+            Instruction::RTS | Instruction::RTD(_) | Instruction::RTE | Instruction::RTR => break,
+            _ => address += get_encoded_size(&line.instruction),
+        }
+    }
+    body
+}
+
+/// Registers pushed by a MOVEM to -(sp), from the lowest address
+fn get_pushed_registers(mask: u16) -> Vec<RegisterOperand> {
+    //the mask of the predecrement mode is reversed, bit 0 is a7 and bit 15 is d0
+    let data = (0..8).filter(|i| mask & (0x8000 >> i) != 0).map(RegisterOperand::Data);
+    let address = (0..8).filter(|i| mask & (0x80 >> i) != 0).map(RegisterOperand::Address);
+    data.chain(address).collect()
+}
+
+fn get_size_bytes(size: &LexedSize) -> usize {
+    match size {
+        LexedSize::Byte => 1,
+        LexedSize::Long => 4,
+        _ => 2,
+    }
+}
+
+fn is_register(operand: &LexedOperand, register: &RegisterOperand) -> bool {
+    match (operand, register) {
+        (LexedOperand::Register(LexedRegisterType::Address, name), RegisterOperand::Address(index)) => {
+            name.to_lowercase() == format!("a{}", index)
+        }
+        (LexedOperand::Register(LexedRegisterType::SP, _), RegisterOperand::Address(7)) => true,
+        _ => false,
+    }
+}
+
+/// The symbol written before "(register)" in the source line, if it is a name and not a number or expression
+fn get_offset_name(line: &str, register: &RegisterOperand) -> Option<String> {
+    let RegisterOperand::Address(index) = register else {
+        return None;
+    };
+    let lowercase = line.to_lowercase();
+    let position = lowercase.find(&format!("(a{})", index))?;
+    let name: String = line[..position]
+        .chars()
+        .rev()
+        .take_while(|c| !matches!(c, ',' | ' ' | '\t'))
+        .collect::<Vec<char>>()
+        .into_iter()
+        .rev()
+        .collect();
+    let is_name = name.starts_with(|c: char| c.is_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '.');
+    is_name.then_some(name)
+}
+
+/// The displacements from the frame pointer used by the function, with the size they are used with
+fn get_frame_variables(body: &[&InstructionLine], register: &RegisterOperand) -> Vec<(i32, usize, Option<String>)> {
+    let mut variables: Vec<(i32, usize, Option<String>)> = vec![];
+    for line in body {
+        let LexedLine::Instruction { operands, size, .. } = &line.parsed_line.parsed else {
+            continue;
+        };
+        for operand in operands {
+            let LexedOperand::IndirectDisplacement { offset, operand } = operand else {
+                continue;
+            };
+            if !is_register(operand, register) {
+                continue;
+            }
+            let offset = match parse_absolute_expression(offset, &Default::default()) {
+                Ok(offset) => offset as i32,
+                Err(_) => continue,
+            };
+            let name = get_offset_name(&line.parsed_line.line, register);
+            match variables.iter_mut().find(|variable| variable.0 == offset) {
+                //keeps the first use, but a name is better than a number
+                Some(variable) => {
+                    if variable.2.is_none() {
+                        variable.2 = name;
+                    }
+                }
+                None => variables.push((offset, get_size_bytes(size), name)),
+            }
+        }
+    }
+    variables.sort_by_key(|variable| variable.0);
+    variables
+}
+
+/// Finds the return address between the top and the bottom of the stack, searching from the top
+fn find_return_slot(interpreter: &Interpreter, top: usize, bottom: usize, return_address: usize) -> Option<usize> {
+    let memory = interpreter.get_memory();
+    (bottom..top.saturating_sub(3))
+        .rev()
+        .filter(|address| address % 2 == 0)
+        .find(|address| memory.read_long(*address).ok() == Some(return_address as u32))
+}
+
+fn build_frame(
+    interpreter: &Interpreter,
+    function_address: usize,
+    call_site: usize,
+    top: usize,
+    bottom: usize,
+    //address reached in the function, its prologue is what was run before it
+    position: usize,
+) -> StackFrame {
+    let memory = interpreter.get_memory();
+    let function = match interpreter.get_label_at(function_address) {
+        Some(label) => label.name.clone(),
+        None => format!("${:X}", function_address),
+    };
+    let return_address = match interpreter.get_instruction_at(call_site) {
+        Some(line) => call_site + get_encoded_size(&line.instruction),
+        None => call_site,
+    };
+    let mut frame = StackFrame {
+        function,
+        function_address,
+        call_site,
+        return_address,
+        return_address_slot: find_return_slot(interpreter, top, bottom, return_address),
+        frame_register: None,
+        frame_pointer: None,
+        locals_size: 0,
+        saved_registers: vec![],
+        locals: vec![],
+        arguments: vec![],
+        top,
+        bottom,
+    };
+    let Some(slot) = frame.return_address_slot else {
+        return frame;
+    };
+    frame.top = slot + 4;
+    let body = get_function_body(interpreter, function_address);
+    //runs the prologue again on the addresses, starting from the return address
+    let mut sp = slot;
+    let mut saved = vec![];
+    for line in body.iter().take_while(|line| line.address < position) {
+        match line.instruction {
+            Instruction::LINK(register, displacement) => {
+                sp -= 4;
+                frame.frame_register = Some(register);
+                frame.frame_pointer = Some(sp);
+                frame.locals_size = (displacement as i32).unsigned_abs() as usize;
+                sp = sp.wrapping_sub(frame.locals_size);
+            }
+            Instruction::MOVEM {
+                direction: TargetDirection::ToMemory,
+                size,
+                registers_mask,
+                target: Operand::PreIndirect(7),
+            } => {
+                let registers = get_pushed_registers(registers_mask);
+                sp -= registers.len() * size.to_bytes();
+                for (index, register) in registers.into_iter().enumerate() {
+                    saved.push((register, sp + index * size.to_bytes(), size));
+                }
+            }
+            Instruction::MOVE(Operand::Register(register), Operand::PreIndirect(7), size) => {
+                sp -= size.to_bytes();
+                saved.push((register, sp, size));
+            }
+            _ => break,
+        }
+    }
+    frame.saved_registers = saved
+        .into_iter()
+        .filter(|(_, address, _)| *address >= bottom)
+        .filter_map(|(register, address, size)| {
+            let value = memory.read_size(address, size).ok()?;
+            Some(SavedRegister {
+                register,
+                address,
+                value,
+            })
+        })
+        .collect();
+    if let (Some(register), Some(frame_pointer)) = (frame.frame_register, frame.frame_pointer) {
+        for (offset, size, name) in get_frame_variables(&body, &register) {
+            let address = (frame_pointer as i64 + offset as i64) as usize;
+            let value = match size {
+                1 => memory.read_size(address, Size::Byte),
+                2 => memory.read_size(address, Size::Word),
+                _ => memory.read_size(address, Size::Long),
+            };
+            let variable = StackVariable {
+                name,
+                offset,
+                address,
+                size,
+                value: value.ok(),
+            };
+            match offset < 0 {
+                true => frame.locals.push(variable),
+                //the saved frame pointer and the return address are not arguments
+                false if offset >= 8 => frame.arguments.push(variable),
+                false => {}
+            }
+        }
+    }
+    frame
+}
+
+/// Builds the frames of the active calls of the interpreter
+pub fn get_stack_view(interpreter: &Interpreter) -> StackView {
+    let stack_pointer = interpreter.get_sp();
+    let stack_top = interpreter.get_stack_top();
+    let functions = interpreter.get_pretty_call_stack();
+    let call_sites = interpreter.get_call_sites();
+    let mut frames: Vec<StackFrame> = vec![];
+    let mut top = stack_top;
+    for (index, (function, call_site)) in functions.iter().zip(call_sites.iter()).enumerate() {
+        let position = match call_sites.get(index + 1) {
+            Some(next_call) => *next_call,
+            None => interpreter.get_pc(),
+        };
+        let frame = build_frame(interpreter, function.address, *call_site, top, stack_pointer, position);
+        //the next return address is searched below this one, the arguments between them are of the caller
+        if let Some(slot) = frame.return_address_slot {
+            top = slot;
+            if let Some(previous) = frames.last_mut() {
+                previous.bottom = frame.top;
+            }
+        }
+        frames.push(frame);
+    }
+    StackView {
+        stack_pointer,
+        stack_top,
+        frames,
+    }
+}
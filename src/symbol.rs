@@ -0,0 +1,204 @@
+use alloc::{sync::Arc, vec::Vec};
+#[cfg(feature = "assembler")]
+use core::ops::Index;
+
+#[cfg(feature = "assembler")]
+use serde::{ser::SerializeMap, Serializer};
+use serde::Serialize;
+
+#[cfg(feature = "assembler")]
+use crate::instructions::Label;
+use crate::{
+    collections::HashMap,
+    lexer::{LexedLine, LexedOperand, ParsedLine},
+    local_labels::get_label_key,
+    utils::is_identifier,
+    visitor::{walk_lexed_operand, Visitor},
+};
+
+/*
+    Interner for the names of a program, labels, mnemonics and registers are stored once and the
+    symbols are compared by id. The ids of a name never change for the life of the interner, so
+    a program that is lexed again keeps the same symbols for the names it already had.
+    The names are shared, cloning an interner doesn't copy them: the lexer interns the program, the
+    semantic checker starts its label table from the interner of the lexer, and the interpreter
+    starts its one from the table of the compiler
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+pub struct Symbol(u32);
+
+impl Symbol {
+    pub fn get_id(&self) -> u32 {
+        self.0
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Interner {
+    ids: HashMap<Arc<str>, Symbol>,
+    names: Vec<Arc<str>>,
+}
+
+impl Interner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Returns the symbol of the name, adding it if it was never seen
+    pub fn intern(&mut self, name: &str) -> Symbol {
+        match self.ids.get(name) {
+            Some(symbol) => *symbol,
+            None => {
+                let symbol = Symbol(self.names.len() as u32);
+                let name: Arc<str> = Arc::from(name);
+                self.names.push(name.clone());
+                self.ids.insert(name, symbol);
+                symbol
+            }
+        }
+    }
+    /// Returns the symbol of the name without adding it
+    pub fn get(&self, name: &str) -> Option<Symbol> {
+        self.ids.get(name).copied()
+    }
+    pub fn resolve(&self, symbol: Symbol) -> &str {
+        &self.names[symbol.0 as usize]
+    }
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+}
+
+struct SymbolCollector<'a> {
+    interner: &'a mut Interner,
+}
+
+impl Visitor for SymbolCollector<'_> {
+    fn visit_lexed_line(&mut self, line: &LexedLine) {
+        match line {
+            //the local labels are found by the key that has their owner
+            LexedLine::Label { name, owner, .. } => {
+                self.interner.intern(&get_label_key(name, owner.as_deref()));
+            }
+            LexedLine::Instruction { name, operands, .. } => {
+                self.interner.intern(name);
+                for operand in operands {
+                    self.visit_lexed_operand(operand);
+                }
+            }
+            _ => {}
+        }
+    }
+    fn visit_lexed_operand(&mut self, operand: &LexedOperand) {
+        match operand {
+            LexedOperand::Label(name)
+            | LexedOperand::Register(_, name)
+            | LexedOperand::RegisterWithSize(_, name, _)
+            | LexedOperand::ScaledRegister(_, name, _, _) => {
+                self.interner.intern(name);
+            }
+            //labels used as operands are lexed as absolute values
+            LexedOperand::Absolute(name) if is_identifier(name) => {
+                self.interner.intern(name);
+            }
+            _ => {}
+        }
+        walk_lexed_operand(self, operand)
+    }
+}
+
+/// Interns the labels, mnemonics and registers of the lines
+pub fn intern_lines(interner: &mut Interner, lines: &[ParsedLine]) {
+    let mut collector = SymbolCollector { interner };
+    for line in lines {
+        collector.visit_parsed_line(line);
+    }
+}
+
+/// Labels keyed by the symbol of their name
+#[cfg(feature = "assembler")]
+#[derive(Debug, Clone, Default)]
+pub struct LabelTable {
+    symbols: Interner,
+    labels: HashMap<Symbol, Label>,
+}
+
+#[cfg(feature = "assembler")]
+impl LabelTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Starts from the names of an interner, the names it already has are not copied
+    pub fn with_symbols(symbols: Interner) -> Self {
+        Self {
+            symbols,
+            labels: HashMap::new(),
+        }
+    }
+    pub fn insert(&mut self, name: &str, label: Label) -> Option<Label> {
+        let symbol = self.symbols.intern(name);
+        self.labels.insert(symbol, label)
+    }
+    pub fn get(&self, name: &str) -> Option<&Label> {
+        self.symbols.get(name).and_then(|symbol| self.labels.get(&symbol))
+    }
+    pub fn get_by_symbol(&self, symbol: Symbol) -> Option<&Label> {
+        self.labels.get(&symbol)
+    }
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Label)> {
+        self.labels.iter().map(|(symbol, label)| (self.symbols.resolve(*symbol), label))
+    }
+    pub fn values(&self) -> impl Iterator<Item = &Label> {
+        self.labels.values()
+    }
+    pub fn len(&self) -> usize {
+        self.labels.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty()
+    }
+    /// The names of the table, which can be more than the labels it has
+    pub fn get_symbols(&self) -> &Interner {
+        &self.symbols
+    }
+}
+
+#[cfg(feature = "assembler")]
+impl Index<&str> for LabelTable {
+    type Output = Label;
+    fn index(&self, name: &str) -> &Label {
+        match self.get(name) {
+            Some(label) => label,
+            None => panic!("Unknown label \"{}\"", name),
+        }
+    }
+}
+
+#[cfg(feature = "assembler")]
+impl<'a> FromIterator<(&'a str, Label)> for LabelTable {
+    fn from_iter<I: IntoIterator<Item = (&'a str, Label)>>(iter: I) -> Self {
+        let mut table = LabelTable::new();
+        for (name, label) in iter {
+            table.insert(name, label);
+        }
+        table
+    }
+}
+
+//serialized like the map of names it replaced
+#[cfg(feature = "assembler")]
+impl Serialize for LabelTable {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.labels.len()))?;
+        for (name, label) in self.iter() {
+            map.serialize_entry(name, label)?;
+        }
+        map.end()
+    }
+}
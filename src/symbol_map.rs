@@ -0,0 +1,105 @@
+use alloc::{format, string::String, vec::Vec};
+use serde::Serialize;
+
+use crate::{
+    assembler::Assembler,
+    symbol_table::{SymbolEntry, SymbolKind},
+};
+
+/*
+    The map of an assembled program, every label with the address it has in the machine code and the equs with
+    their value, for debuggers and tools that only have the binary.
+    The size of a label is the distance to the next label, a global label ends at the next global one so its
+    local labels are inside of it, the last label ends at the end of the machine code. Equs have no size.
+    The labels are sorted by address, the equs follow in the order they are defined
+*/
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MapSymbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    /// The address of a label, the value of an equ
+    pub value: i64,
+    pub size: usize,
+    pub line_index: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SymbolMap {
+    pub symbols: Vec<MapSymbol>,
+}
+
+fn is_local(symbol: &SymbolEntry) -> bool {
+    symbol.name.contains('.')
+}
+
+impl SymbolMap {
+    pub fn new(assembler: &Assembler) -> SymbolMap {
+        let table = assembler.get_symbol_table();
+        let end = assembler.get_machine_code().get_end_address() as i64;
+        let mut labels: Vec<&SymbolEntry> = table.get_labels().collect();
+        labels.sort_by_key(|label| (label.value, label.line_index));
+        let mut symbols: Vec<MapSymbol> = labels
+            .iter()
+            .map(|label| {
+                let next = labels
+                    .iter()
+                    .filter(|next| next.value > label.value && (is_local(label) || !is_local(next)))
+                    .map(|next| next.value)
+                    .next()
+                    .unwrap_or(end);
+                MapSymbol {
+                    name: label.name.clone(),
+                    kind: SymbolKind::Label,
+                    value: label.value,
+                    size: (next - label.value).max(0) as usize,
+                    line_index: label.line_index,
+                }
+            })
+            .collect();
+        symbols.extend(
+            table
+                .iter()
+                .filter(|symbol| symbol.kind == SymbolKind::Equ)
+                .map(|equ| MapSymbol {
+                    name: equ.name.clone(),
+                    kind: SymbolKind::Equ,
+                    value: equ.value,
+                    size: 0,
+                    line_index: equ.line_index,
+                }),
+        );
+        SymbolMap { symbols }
+    }
+    pub fn get(&self, name: &str) -> Option<&MapSymbol> {
+        self.symbols.iter().find(|symbol| symbol.name == name)
+    }
+    /// The label the address is in, the local one if it is also in a global one
+    pub fn get_label_at(&self, address: usize) -> Option<&MapSymbol> {
+        let address = address as i64;
+        self.symbols.iter().rev().find(|symbol| {
+            symbol.kind == SymbolKind::Label && symbol.value <= address && address < symbol.value + symbol.size as i64
+        })
+    }
+    /// A row for every symbol with its value, size, kind and name
+    pub fn to_text(&self) -> String {
+        let mut text = String::from("value     size      kind   name\n");
+        for symbol in &self.symbols {
+            let kind = match symbol.kind {
+                SymbolKind::Label => "label",
+                SymbolKind::Equ => "equ",
+                SymbolKind::External => "extern",
+            };
+            let size = match symbol.kind {
+                SymbolKind::Label => format!("{:08X}", symbol.size),
+                SymbolKind::Equ | SymbolKind::External => String::new(),
+            };
+            text.push_str(&format!("{:08X}  {:<8}  {:<5}  {}\n", symbol.value as u32, size, kind, symbol.name));
+        }
+        text
+    }
+    #[cfg(feature = "serialize")]
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap_or_default()
+    }
+}
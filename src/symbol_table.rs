@@ -0,0 +1,89 @@
+use alloc::{string::String, vec::Vec};
+use crate::collections::HashMap;
+
+use serde::Serialize;
+
+use crate::expression::{evaluate, ExpressionError};
+
+/*
+    The names of a program and their values, filled by the first pass of the compiler once every line is placed,
+    so the second pass can resolve labels that are used before they are defined.
+    Labels have the address of the line after them, ORG included, local labels are stored with their
+    owner like owner.loop. The externals imported with XREF have the address the layout gives them.
+    Equs are defined after the labels and can use them and the equs before them, an equ that can't be
+    evaluated, like one that is only a register or a string, is not in the table.
+    The lexer already replaces the equs in the source, the table keeps them so tools can query the values
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "serialize", derive(serde::Deserialize))]
+pub enum SymbolKind {
+    Label,
+    Equ,
+    /// Imported with XREF, its address is the one given by the layout
+    External,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "serialize", derive(serde::Deserialize))]
+pub struct SymbolEntry {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub value: i64,
+    /// Index of the line that defines it
+    pub line_index: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SymbolTable {
+    symbols: Vec<SymbolEntry>,
+    indexes: HashMap<String, usize>,
+}
+
+impl SymbolTable {
+    pub fn new() -> SymbolTable {
+        SymbolTable::default()
+    }
+    /// Adds the symbol, returns the one with the same name if it was already defined
+    pub fn define(&mut self, symbol: SymbolEntry) -> Result<(), &SymbolEntry> {
+        if let Some(index) = self.indexes.get(&symbol.name) {
+            return Err(&self.symbols[*index]);
+        }
+        self.indexes.insert(symbol.name.clone(), self.symbols.len());
+        self.symbols.push(symbol);
+        Ok(())
+    }
+    pub fn get(&self, name: &str) -> Option<&SymbolEntry> {
+        self.indexes.get(name).map(|index| &self.symbols[*index])
+    }
+    pub fn get_value(&self, name: &str) -> Option<i64> {
+        self.get(name).map(|symbol| symbol.value)
+    }
+    /// The address of a label or external, equs are not addresses
+    pub fn get_address(&self, name: &str) -> Option<usize> {
+        match self.get(name) {
+            Some(symbol) if symbol.kind != SymbolKind::Equ => Some(symbol.value as usize),
+            _ => None,
+        }
+    }
+    pub fn contains(&self, name: &str) -> bool {
+        self.indexes.contains_key(name)
+    }
+    /// The symbols in the order they are defined
+    pub fn iter(&self) -> impl Iterator<Item = &SymbolEntry> {
+        self.symbols.iter()
+    }
+    pub fn get_labels(&self) -> impl Iterator<Item = &SymbolEntry> {
+        self.symbols.iter().filter(|symbol| symbol.kind == SymbolKind::Label)
+    }
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+    /// Evaluates a constant expression, the names in it are looked up in the table
+    pub fn resolve(&self, expression: &str) -> Result<i64, ExpressionError> {
+        evaluate(expression, &|name| self.get_value(name))
+    }
+}
@@ -0,0 +1,143 @@
+use alloc::{string::{String, ToString}, vec, vec::Vec};
+use core::{error::Error, fmt};
+
+use crate::lexer::{Lexer, ParsedLine};
+
+/*
+    Parameterized snippets of assembly, a parameter is written as \name and is replaced by the value
+    bound to it, \\ is a backslash. The values are checked before they are placed in the code, a value
+    can't start a new line, a comment or another parameter, so a binding only ever changes the text
+    where its parameter is. Every parameter must be bound and every binding must be a parameter
+*/
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TemplateError {
+    MissingBinding(String),
+    UnknownBinding(String),
+    InvalidValue { name: String, value: String },
+    InvalidParameter(usize),
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TemplateError::MissingBinding(name) => write!(f, "Missing value for parameter '{}'", name),
+            TemplateError::UnknownBinding(name) => write!(f, "Unknown parameter '{}'", name),
+            TemplateError::InvalidValue { name, value } => {
+                write!(f, "Invalid value '{}' for parameter '{}'", value, name)
+            }
+            TemplateError::InvalidParameter(position) => {
+                write!(f, "Expected a parameter name after '\\' at position {}", position)
+            }
+        }
+    }
+}
+
+impl Error for TemplateError {}
+
+impl From<TemplateError> for String {
+    fn from(error: TemplateError) -> Self {
+        error.to_string()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TemplatePart {
+    Text(String),
+    Parameter(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct Template {
+    parts: Vec<TemplatePart>,
+    parameters: Vec<String>,
+}
+
+fn is_parameter_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// A value can't add lines, comments or parameters to the code
+fn is_safe_value(value: &str) -> bool {
+    let mut previous_is_space = true;
+    for c in value.chars() {
+        match c {
+            '\n' | '\r' | ';' | '\\' => return false,
+            '*' if previous_is_space => return false,
+            _ => {}
+        }
+        previous_is_space = c.is_whitespace();
+    }
+    true
+}
+
+impl Template {
+    pub fn new(source: &str) -> Result<Template, TemplateError> {
+        let mut parts = vec![];
+        let mut parameters: Vec<String> = vec![];
+        let mut text = String::new();
+        let mut chars = source.char_indices().peekable();
+        while let Some((position, c)) = chars.next() {
+            if c != '\\' {
+                text.push(c);
+                continue;
+            }
+            if let Some((_, '\\')) = chars.peek() {
+                chars.next();
+                text.push('\\');
+                continue;
+            }
+            let mut name = String::new();
+            while let Some((_, c)) = chars.peek().copied().filter(|(_, c)| is_parameter_char(*c)) {
+                name.push(c);
+                chars.next();
+            }
+            if name.is_empty() {
+                return Err(TemplateError::InvalidParameter(position));
+            }
+            parts.push(TemplatePart::Text(core::mem::take(&mut text)));
+            if !parameters.contains(&name) {
+                parameters.push(name.clone());
+            }
+            parts.push(TemplatePart::Parameter(name));
+        }
+        parts.push(TemplatePart::Text(text));
+        Ok(Template { parts, parameters })
+    }
+    /// The parameters in the order they first appear
+    pub fn get_parameters(&self) -> &[String] {
+        &self.parameters
+    }
+    /// Replaces the parameters with the values bound to them
+    pub fn expand(&self, bindings: &[(&str, &str)]) -> Result<String, TemplateError> {
+        for (name, value) in bindings {
+            if !self.parameters.iter().any(|parameter| parameter == name) {
+                return Err(TemplateError::UnknownBinding(name.to_string()));
+            }
+            if !is_safe_value(value) {
+                return Err(TemplateError::InvalidValue {
+                    name: name.to_string(),
+                    value: value.to_string(),
+                });
+            }
+        }
+        let mut code = String::new();
+        for part in &self.parts {
+            match part {
+                TemplatePart::Text(text) => code.push_str(text),
+                TemplatePart::Parameter(name) => match bindings.iter().find(|(bound, _)| bound == name) {
+                    Some((_, value)) => code.push_str(value),
+                    None => return Err(TemplateError::MissingBinding(name.clone())),
+                },
+            }
+        }
+        Ok(code)
+    }
+    /// Expands the template and lexes it
+    pub fn instantiate(&self, bindings: &[(&str, &str)]) -> Result<Vec<ParsedLine>, TemplateError> {
+        let code = self.expand(bindings)?;
+        let mut lexer = Lexer::new();
+        lexer.lex_lossy(&code);
+        Ok(lexer.into_lines())
+    }
+}
@@ -1 +1,2 @@
-mod test;
\ No newline at end of file
+#[allow(clippy::module_inception)]
+mod test;
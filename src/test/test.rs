@@ -1,14 +1,3882 @@
-use console::Term;
-
 use crate::compiler::Compiler;
-use crate::instructions::{Interrupt, InterruptResult};
+use crate::host_io::TerminalIo;
 use crate::interpreter::{Interpreter, InterpreterOptions, InterpreterStatus};
 use crate::S68k;
 
 //TODO add better tests for all cases and if i find bugs etc
-#[cfg(test)]
 mod tests {
-    use crate::test::test::lex_and_run;
+    use crate::cpu_model::CpuModel;
+    use crate::interpreter::InterpreterOptions;
+    use crate::test::test::{lex_and_run, lex_only};
+    use crate::timing::get_timing_table;
+    use crate::coprocessor::{Coprocessor, CoprocessorBus};
+    use crate::instructions::{Instruction, Size};
+    use crate::interpreter::{RuntimeError, RuntimeResult};
+    use std::any::Any;
+    use crate::interpreter::InterpreterStatus;
+    #[cfg(feature = "wasm")]
+    use crate::wasm::InterpreterHandle;
+    use crate::S68k;
+
+    #[test]
+    fn cpu_model_gates_instructions() {
+        let code = "move.l #$80, d0
+    extb.l d0";
+        let mut s68k = S68k::new(code.to_string());
+        assert_eq!(s68k.semantic_check().len(), 1);
+        assert!(s68k.compile().is_err());
+        s68k.set_cpu_model(CpuModel::M68020);
+        assert!(s68k.semantic_check().is_empty());
+        let compiled = s68k.compile().expect("To compile correctly");
+        let mut interpreter = s68k.create_interpreter(compiled, None);
+        interpreter.run().unwrap();
+        assert_eq!(interpreter.get_cpu().wasm_get_d_reg(0).get_long(), 0xFFFFFF80);
+    }
+
+    #[test]
+    fn movec_and_rtd_on_68010() {
+        let code = "
+    move.l #$4000, d0
+    movec d0, vbr
+    move.l #1, -(sp)
+    bsr sub
+    bra end
+sub:
+    rtd #4
+end:
+    movec vbr, d1";
+        let mut s68k = S68k::new(code.to_string());
+        assert!(!s68k.semantic_check().is_empty());
+        s68k.set_cpu_model(CpuModel::M68010);
+        assert!(s68k.semantic_check().is_empty());
+        let compiled = s68k.compile().expect("To compile correctly");
+        let mut interpreter = s68k.create_interpreter(compiled, None);
+        let sp = interpreter.get_sp();
+        interpreter.run().unwrap();
+        assert_eq!(interpreter.get_cpu().wasm_get_d_reg(1).get_long(), 0x4000);
+        assert_eq!(interpreter.get_sp(), sp);
+        assert_eq!(interpreter.get_exception_vector_address(2), 0x4008);
+    }
+
+    #[test]
+    fn memory_indirect_on_68020() {
+        let code = "
+    move.l #$3000, a0
+    move.l #$2000, $3004
+    move.l #7, $2010
+    move.l #2, d0
+    move.l ([4,a0],d0.l*8), d1
+    move.l ([4,a0],$10), d2
+    lea ([-4,a0,d0.w*4]), a2
+    lea (a0,d0.w*2), a1";
+        let mut s68k = S68k::new(code.to_string());
+        assert_eq!(s68k.semantic_check().len(), 4);
+        s68k.set_cpu_model(CpuModel::M68020);
+        assert!(s68k.semantic_check().is_empty());
+        let compiled = s68k.compile().expect("To compile correctly");
+        let mut interpreter = s68k.create_interpreter(compiled, None);
+        interpreter.run().unwrap();
+        let cpu = interpreter.get_cpu();
+        assert_eq!(cpu.wasm_get_d_reg(1).get_long(), 7);
+        assert_eq!(cpu.wasm_get_d_reg(2).get_long(), 7);
+        assert_eq!(cpu.wasm_get_a_reg(2).get_long(), 0x2000);
+        assert_eq!(cpu.wasm_get_a_reg(1).get_long(), 0x3004);
+    }
+
+    #[test]
+    fn scaled_index_in_both_forms_on_68020() {
+        let code = "
+    move.l #$3000, a0
+    move.l #2, d0
+    move.l #7, $3010
+    move.l #9, $3004
+    move.l (8,a0,d0.w*4), d1
+    move.l 8(a0,d0.w*4), d2
+    move.l (4,a0), d3
+    lea (-8,a0,d0.l*4), a1";
+        let mut s68k = S68k::new(code.to_string());
+        //the checker and the compiler refuse the scale with the same message
+        let expected = "\"d0*4\" uses scaled index, which is not available on the 68000, the minimum model is the 68020";
+        let messages: Vec<String> = s68k.semantic_check().iter().map(|e| e.get_error().to_string()).collect();
+        assert_eq!(messages, vec![expected; 3]);
+        assert!(s68k.compile().err().unwrap().to_string().contains(expected));
+        s68k.set_cpu_model(CpuModel::M68020);
+        assert!(s68k.semantic_check().is_empty());
+        let mut interpreter = s68k.create_interpreter(s68k.compile().unwrap(), None);
+        interpreter.run().unwrap();
+        let cpu = interpreter.get_cpu();
+        assert_eq!(cpu.wasm_get_d_reg(1).get_long(), 7);
+        assert_eq!(cpu.wasm_get_d_reg(2).get_long(), 7);
+        assert_eq!(cpu.wasm_get_d_reg(3).get_long(), 9);
+        assert_eq!(cpu.wasm_get_a_reg(1).get_long(), 0x3000);
+    }
+
+    #[test]
+    fn bitfield_and_long_mul_div_on_68020() {
+        let code = "
+    move.l #$12345678, d0
+    bfextu d0{4:8}, d1
+    bfffo d0{8:8}, d4
+    bfins d1, d0{0:8}
+    move.l #0, $2000
+    bfset $2000{4:12}
+    move.l $2000, d5
+    move.l #100000, d6
+    muls.l #100000, d7:d6
+    move.l d6, d2
+    divu.l #100000, d7:d2";
+        let mut s68k = S68k::new(code.to_string());
+        assert_eq!(s68k.semantic_check().len(), 6);
+        s68k.set_cpu_model(CpuModel::M68020);
+        assert!(s68k.semantic_check().is_empty());
+        let compiled = s68k.compile().expect("To compile correctly");
+        let mut interpreter = s68k.create_interpreter(compiled, None);
+        interpreter.run().unwrap();
+        let cpu = interpreter.get_cpu();
+        assert_eq!(cpu.wasm_get_d_reg(0).get_long(), 0x23345678);
+        assert_eq!(cpu.wasm_get_d_reg(1).get_long(), 0x23);
+        assert_eq!(cpu.wasm_get_d_reg(4).get_long(), 10);
+        assert_eq!(cpu.wasm_get_d_reg(5).get_long(), 0x0FFF0000);
+        assert_eq!(cpu.wasm_get_d_reg(6).get_long(), 0x540BE400);
+        assert_eq!(cpu.wasm_get_d_reg(7).get_long(), 0);
+        assert_eq!(cpu.wasm_get_d_reg(2).get_long(), 100000);
+    }
+
+    #[test]
+    fn fpu_arithmetic_and_branches() {
+        let code = "
+    fmove.l #3, fp0
+    fmove.d #0.5, fp1
+    fadd.x fp1, fp0
+    fmul.s #2.0, fp0
+    fmove.l fp0, d0
+    fmove.x fp0, $2000
+    fmove.x $2000, fp2
+    fdiv.l #2, fp2
+    fmove.d fp2, -(sp)
+    fmove.d (sp)+, fp3
+    fcmp.l #4, fp3
+    fblt less
+    move.l #1, d1
+less:
+    fmove.l fp3, d2";
+        let s68k = S68k::new(code.to_string());
+        assert!(s68k.semantic_check().is_empty());
+        let options = InterpreterOptions {
+            fpu: true,
+            ..Default::default()
+        };
+        let mut interpreter = s68k.create_interpreter(s68k.compile().unwrap(), Some(options));
+        interpreter.run().unwrap();
+        let cpu = interpreter.get_cpu();
+        assert_eq!(cpu.wasm_get_d_reg(0).get_long(), 7);
+        assert_eq!(cpu.wasm_get_d_reg(1).get_long(), 0);
+        assert_eq!(cpu.wasm_get_d_reg(2).get_long(), 4);
+        assert_eq!(interpreter.get_fpu().unwrap().get_fp_reg(3), 3.5);
+        let mut interpreter = s68k.create_interpreter(s68k.compile().unwrap(), None);
+        assert!(interpreter.run().is_err());
+    }
+
+    #[test]
+    fn cycle_count_follows_model_timing() {
+        let code = "
+    move.l #2, d0
+loop:
+    add.l d1, d2
+    dbra d0, loop";
+        let mut s68k = S68k::new(code.to_string());
+        let options = InterpreterOptions {
+            keep_history: true,
+            ..Default::default()
+        };
+        let mut interpreter = s68k.create_interpreter(s68k.compile().unwrap(), Some(options));
+        interpreter.run().unwrap();
+        assert_eq!(interpreter.get_cycles(), 70);
+        interpreter.undo().unwrap();
+        assert_eq!(interpreter.get_cycles(), 56);
+        s68k.set_cpu_model(CpuModel::M68010);
+        let mut interpreter = s68k.create_interpreter(s68k.compile().unwrap(), None);
+        interpreter.run().unwrap();
+        //the body and the DBcc run in loop mode after the first iteration
+        assert_eq!(interpreter.get_cycles(), 56);
+        let table = interpreter.get_timing_table();
+        assert_eq!(table.get_model(), CpuModel::M68010);
+        assert_eq!(table.get_entry("MULx").unwrap().byte_word, 42);
+        assert_eq!(get_timing_table(CpuModel::M68000).get_entry("MULx").unwrap().byte_word, 70);
+    }
+
+    struct CounterCoprocessor {
+        count: u32,
+    }
+    impl Coprocessor for CounterCoprocessor {
+        fn get_id(&self) -> u8 {
+            2
+        }
+        fn get_name(&self) -> &str {
+            "counter"
+        }
+        fn execute(&mut self, ins: &Instruction, bus: &mut dyn CoprocessorBus) -> RuntimeResult<()> {
+            match ins {
+                Instruction::LINEF(opcode, operand) => {
+                    self.count += (opcode & 0xFF) as u32;
+                    match operand {
+                        Some(operand) => bus.write_operand(operand, self.count, Size::Long),
+                        None => Ok(()),
+                    }
+                }
+                _ => Err(RuntimeError::Unimplemented),
+            }
+        }
+        fn save_state(&self) -> Vec<u8> {
+            self.count.to_be_bytes().to_vec()
+        }
+        fn restore_state(&mut self, state: &[u8]) -> RuntimeResult<()> {
+            self.count = u32::from_be_bytes(state.try_into().unwrap());
+            Ok(())
+        }
+        fn reset(&mut self) {
+            self.count = 0;
+        }
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+    }
+
+    #[test]
+    fn custom_coprocessor_through_line_f() {
+        let code = "
+    linef #$F405
+    linef #$F403, d0
+    linef #$F401, $2000";
+        let s68k = S68k::new(code.to_string());
+        assert!(s68k.semantic_check().is_empty());
+        let options = InterpreterOptions {
+            keep_history: true,
+            ..Default::default()
+        };
+        let mut interpreter = s68k.create_interpreter(s68k.compile().unwrap(), Some(options));
+        assert!(interpreter.attach_coprocessor(Box::new(CounterCoprocessor { count: 0 })).is_ok());
+        interpreter.run().unwrap();
+        assert_eq!(interpreter.get_cpu().wasm_get_d_reg(0).get_long(), 8);
+        assert_eq!(interpreter.get_memory().read_long(0x2000).unwrap(), 9);
+        interpreter.undo().unwrap();
+        assert_eq!(interpreter.save_coprocessor_state(2).unwrap(), vec![0, 0, 0, 8]);
+        //without the coprocessor attached the line-F instruction fails
+        let mut interpreter = s68k.create_interpreter(s68k.compile().unwrap(), None);
+        assert!(interpreter.run().is_err());
+    }
+
+    #[test]
+    fn diagnostics_name_the_minimum_model() {
+        let errors = S68k::new("
+    extb.l d0
+    movec vbr, d0
+    move.l (a0,d0.w*4), d1
+    cas.l d0, d1, (a0)".to_string())
+        .semantic_check();
+        let messages: Vec<String> = errors.iter().map(|e| e.get_message()).collect();
+        assert_eq!(messages.len(), 4);
+        assert!(messages[0].contains("the minimum model is the 68020"));
+        assert!(messages[1].contains("the minimum model is the 68010"));
+        assert!(messages[2].contains("\"d0*4\"") && messages[2].contains("68020"));
+        assert!(messages[3].contains("\"cas\" is not available on the 68000"));
+    }
+
+    #[test]
+    #[cfg(feature = "wasm")]
+    fn interpreter_handle_stops_at_breakpoints() {
+        assert_eq!(S68k::new("move.l d0".to_string()).get_diagnostics().len(), 1);
+        let s68k = S68k::new("
+    move.l #1, d0
+    move.l #2, d0
+    move.l #3, d0".to_string());
+        assert!(s68k.get_diagnostics().is_empty());
+        let mut handle = InterpreterHandle::new(s68k.compile().unwrap(), None);
+        handle.add_breakpoint(2);
+        handle.add_breakpoint(3);
+        handle.remove_breakpoint(3);
+        assert_eq!(handle.run(None).unwrap(), InterpreterStatus::Running);
+        assert_eq!(handle.get_d_regs()[0], 1);
+        assert_eq!(handle.run(None).unwrap(), InterpreterStatus::Terminated);
+        assert_eq!(handle.get_interpreter().get_cpu().wasm_get_d_reg(0).get_long(), 3);
+    }
+
+    #[test]
+    #[cfg(feature = "serialize")]
+    fn ast_and_program_round_trip_through_serde() {
+        use crate::compiler::InstructionLine;
+        use crate::instructions::{Label, Operand};
+        use crate::lexer::ParsedLine;
+        use std::collections::HashMap;
+        let s68k = S68k::new("
+start:
+    move.l 4(a0,d1.w), -(sp)
+    bra start".to_string());
+        let json = serde_json::to_string(s68k.get_lexed_lines()).unwrap();
+        let lines: Vec<ParsedLine> = serde_json::from_str(&json).unwrap();
+        assert_eq!(serde_json::to_string(&lines).unwrap(), json);
+        let compiled = s68k.compile().unwrap();
+        let json = serde_json::to_string(compiled.get_labels_map()).unwrap();
+        let labels: HashMap<String, Label> = serde_json::from_str(&json).unwrap();
+        assert_eq!(labels["start"].address, 0x1000);
+        let json = serde_json::to_string(compiled.get_instructions()).unwrap();
+        let program: Vec<InstructionLine> = serde_json::from_str(&json).unwrap();
+        match program[0].instruction {
+            Instruction::MOVE(Operand::IndirectIndex { offset: 4, .. }, Operand::PreIndirect(7), Size::Long) => {}
+            _ => panic!("Unexpected instruction {:?}", program[0].instruction),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "serialize")]
+    fn lexer_json_export_and_import() {
+        use crate::lexer::Lexer;
+        let code = "ten equ 10
+loop:
+    move.l #ten, d0 ; comment
+    bra loop";
+        let mut lexer = Lexer::new();
+        lexer.lex(code).unwrap();
+        let json = lexer.to_json().unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["version"], 1);
+        assert_eq!(value["symbols"][0]["name"], "ten");
+        assert_eq!(value["symbols"][0]["value"], "10");
+        assert_eq!(value["symbols"][1]["kind"], "Label");
+        //a label line is lexed as the label followed by an empty line
+        assert_eq!(value["lines"][3]["span"]["start"], 4);
+        assert_eq!(value["lines"][3]["parsed"]["type"], "Instruction");
+        let imported = Lexer::from_json(&json).unwrap();
+        assert_eq!(imported.to_json().unwrap(), json);
+        assert!(Lexer::from_json(&json.replacen("\"version\":1", "\"version\":2", 1)).is_err());
+        assert_eq!(S68k::new(code).to_json().unwrap(), json);
+    }
+
+    #[test]
+    #[cfg(feature = "capi")]
+    fn c_api_assembles_and_runs() {
+        use crate::capi::*;
+        use std::ffi::CStr;
+        unsafe {
+            let code = "move.l #5, d0\n    move.l d0, $2000";
+            let program = s68k_assemble(code.as_ptr(), code.len(), 0);
+            assert_eq!(s68k_program_diagnostic_count(program), 0);
+            let interpreter = s68k_interpreter_new(program);
+            assert!(s68k_interpreter_new(program).is_null());
+            s68k_program_free(program);
+            assert_eq!(s68k_interpreter_run(interpreter, 0), 2);
+            assert_eq!(s68k_interpreter_get_d_reg(interpreter, 0), 5);
+            let mut buffer = [0u8; 4];
+            assert_eq!(s68k_interpreter_read_memory(interpreter, 0x2000, buffer.as_mut_ptr(), 4), 0);
+            assert_eq!(buffer, [0, 0, 0, 5]);
+            assert_eq!(s68k_interpreter_step(interpreter), S68K_STATUS_ERROR);
+            assert!(!s68k_interpreter_last_error(interpreter).is_null());
+            s68k_interpreter_free(interpreter);
+            let code = "move.l d0";
+            let program = s68k_assemble(code.as_ptr(), code.len(), 0);
+            assert_eq!(s68k_program_diagnostic_count(program), 1);
+            assert_eq!(s68k_program_diagnostic_line(program, 0), 0);
+            let message = CStr::from_ptr(s68k_program_diagnostic_message(program, 0));
+            assert!(message.to_str().unwrap().starts_with("Error on line 1"));
+            assert!(s68k_interpreter_new(program).is_null());
+            s68k_program_free(program);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "python")]
+    fn python_interpreter_runs_to_breakpoint() {
+        use crate::python::PyInterpreter;
+        assert!(PyInterpreter::new("move.l d0", "68000", false).is_err());
+        assert!(PyInterpreter::new("rts", "68030", false).is_err());
+        let mut interpreter = PyInterpreter::new("
+    move.l #1, d0
+    move.l #2, d0", "68010", false).unwrap();
+        interpreter.add_breakpoint(2);
+        assert_eq!(interpreter.run(None).unwrap(), "Running");
+        assert_eq!(interpreter.d_reg(0).unwrap(), 1);
+        assert_eq!(interpreter.step().unwrap(), "Terminated");
+        assert_eq!(interpreter.d_reg(0).unwrap(), 2);
+    }
+
+    #[test]
+    #[cfg(feature = "lsp")]
+    fn language_server_answers_from_the_lexed_document() {
+        use crate::language_server::LanguageServer;
+        use lsp_types::{HoverContents, Position, Url};
+        let uri = Url::parse("file:///test.s").unwrap();
+        let mut server = LanguageServer::new(CpuModel::M68000);
+        server.set_document(uri.clone(), "count equ 5
+loop:
+    move.l #count, d0
+    extb.l d0
+    bra loop".to_string());
+        let diagnostics = server.get_diagnostics(&uri);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].range.start, Position::new(3, 4));
+        let definition = server.goto_definition(&uri, Position::new(4, 9)).unwrap();
+        assert_eq!(definition.range.start, Position::new(1, 0));
+        let symbols = server.document_symbols(&uri);
+        assert_eq!(symbols.len(), 2);
+        assert_eq!(symbols[0].name, "count");
+        assert_eq!(symbols[0].detail, Some("5".to_string()));
+        match server.hover(&uri, Position::new(3, 5)).unwrap().contents {
+            HoverContents::Markup(content) => assert!(content.value.contains("the minimum model is the 68020")),
+            _ => panic!("Expected markdown hover"),
+        }
+        let mnemonics = server.completion(&uri, Position::new(2, 6));
+        assert!(mnemonics.iter().any(|item| item.label == "move"));
+        assert!(!mnemonics.iter().any(|item| item.label == "extb"));
+        let operands = server.completion(&uri, Position::new(4, 8));
+        assert!(operands.iter().any(|item| item.label == "loop"));
+        //a branch only takes an address
+        assert!(!operands.iter().any(|item| item.label == "d0"));
+        assert_eq!(server.references(&uri, Position::new(1, 1), true).len(), 2);
+        assert_eq!(server.references(&uri, Position::new(1, 1), false).len(), 1);
+        let tokens = server.semantic_tokens_full(&uri).unwrap();
+        server.set_document(uri.clone(), "count equ 5\nloop:\n    bra loop".to_string());
+        match server.semantic_tokens_delta(&uri, tokens.result_id.as_deref().unwrap()).unwrap() {
+            lsp_types::SemanticTokensFullDeltaResult::TokensDelta(delta) => assert_eq!(delta.edits.len(), 1),
+            _ => panic!("Expected a delta from the previous tokens"),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "dap")]
+    fn debug_adapter_steps_through_calls() {
+        use crate::debug_adapter::{DebugEvent, DebugSession};
+        use serde_json::json;
+        let path = std::env::temp_dir().join("s68k_debug_adapter_test.s");
+        std::fs::write(&path, "main:
+    move.l #1, d0
+    bsr sub
+    move.l #3, d0
+    bra end
+sub:
+    move.l #2, d0
+    rts
+end:
+    move.l #4, d1").unwrap();
+        let mut session = DebugSession::new();
+        session.handle_request("initialize", &json!({ "linesStartAt1": true })).unwrap();
+        session.handle_request("launch", &json!({ "program": path.to_str().unwrap() })).unwrap();
+        let (body, _) = session
+            .handle_request("setBreakpoints", &json!({ "breakpoints": [{ "line": 3 }, { "line": 6 }] }))
+            .unwrap();
+        assert_eq!(body["breakpoints"][0]["verified"], json!(true));
+        assert_eq!(body["breakpoints"][1]["verified"], json!(false));
+        let (_, events) = session.handle_request("configurationDone", &json!({})).unwrap();
+        assert_eq!(events, vec![DebugEvent::Stopped { reason: "breakpoint".to_string(), description: None }]);
+        session.handle_request("stepIn", &json!({})).unwrap();
+        let (body, _) = session.handle_request("stackTrace", &json!({})).unwrap();
+        assert_eq!(body["stackFrames"][0]["name"], json!("sub"));
+        assert_eq!(body["stackFrames"][0]["line"], json!(7));
+        assert_eq!(body["stackFrames"][1]["name"], json!("main"));
+        assert_eq!(body["stackFrames"][1]["line"], json!(3));
+        session.handle_request("stepOut", &json!({})).unwrap();
+        let (body, _) = session.handle_request("stackTrace", &json!({})).unwrap();
+        assert_eq!(body["stackFrames"].as_array().unwrap().len(), 1);
+        assert_eq!(body["stackFrames"][0]["line"], json!(4));
+        session.handle_request("next", &json!({})).unwrap();
+        let (body, _) = session.handle_request("variables", &json!({ "variablesReference": 1 })).unwrap();
+        assert_eq!(body["variables"][0]["value"], json!("0x00000003"));
+        let (_, events) = session.handle_request("continue", &json!({})).unwrap();
+        assert_eq!(events, vec![DebugEvent::Terminated]);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn repl_keeps_machine_state_between_lines() {
+        use crate::repl::{Repl, REPL_CODE_ADDRESS, REPL_DATA_ADDRESS};
+        let mut repl = Repl::new(CpuModel::M68000).unwrap();
+        assert_eq!(repl.eval("count equ 3").unwrap(), "count = 3");
+        assert_eq!(repl.eval("move.l #count, d0").unwrap(), "d0 = $00000003  pc = $1006");
+        repl.eval("loop:").unwrap();
+        repl.eval("subq.l #1, d0").unwrap();
+        assert_eq!(repl.eval("bne loop").unwrap(), "pc = $1006");
+        assert_eq!(repl.eval("msg: dc.b 'hi',0").unwrap(), "3 bytes at $8000");
+        repl.eval("lea msg, a1").unwrap();
+        let interpreter = repl.get_interpreter();
+        assert_eq!(interpreter.get_cpu().wasm_get_d_reg(0).get_long(), 2);
+        assert_eq!(interpreter.get_cpu().wasm_get_a_reg(1).get_long(), REPL_DATA_ADDRESS as u32);
+        assert_eq!(repl.eval(":mem msg 2").unwrap(), "$00008000  68 69");
+        repl.eval(":undo").unwrap();
+        assert_eq!(repl.get_interpreter().get_cpu().wasm_get_a_reg(1).get_long(), 0);
+        assert!(repl.eval("move.l d0").is_err());
+        repl.eval(":reset").unwrap();
+        assert!(repl.get_symbols().is_empty());
+        assert_eq!(repl.get_interpreter().get_pc(), REPL_CODE_ADDRESS);
+    }
+
+    #[test]
+    fn formatter_aligns_columns_and_normalizes_case() {
+        use crate::formatter::{format_code, FormatterOptions, LetterCase};
+        let code = "* header
+COUNT EQU 5
+START: MOVE.L #COUNT,D0 ; load
+    lea 4(A0,D1.W),a1
+
+msg: dc.b 'a, b',$A0
+";
+        let formatted = format_code(code, &FormatterOptions::default());
+        assert_eq!(formatted, "* header
+COUNT   equ     5
+START:  move.l  #COUNT, d0              ; load
+        lea     4(a0,d1.w), a1
+
+msg:    dc.b    'a, b', $A0
+");
+        assert_eq!(format_code(&formatted, &FormatterOptions::default()), formatted);
+        let options = FormatterOptions {
+            case: LetterCase::Upper,
+            space_after_comma: false,
+            ..Default::default()
+        };
+        assert!(format_code(code, &options).contains("        LEA     4(A0,D1.W),A1"));
+        let options = FormatterOptions {
+            label_column: 1,
+            ..Default::default()
+        };
+        assert!(format_code(code, &options).contains(" START: move.l  #COUNT, d0"));
+    }
+
+    #[test]
+    fn completion_follows_the_legal_addressing_modes() {
+        use crate::completion::{get_completions, CompletionKind};
+        let code = "count equ 5
+loop: move.l #count, d0 ; load
+    lea 
+    move.b d0, 
+    add.l (a0)+, ";
+        let s68k = S68k::new(code.to_string());
+        let labels = |line: usize, column: usize| -> Vec<String> {
+            get_completions(&s68k, line, column).into_iter().map(|c| c.label).collect()
+        };
+        let mnemonics = get_completions(&s68k, 1, 8);
+        assert!(mnemonics.iter().any(|c| c.label == "move.l" && c.kind == CompletionKind::Instruction));
+        assert!(mnemonics.iter().any(|c| c.label == "dc.b" && c.kind == CompletionKind::Directive));
+        assert!(!mnemonics.iter().any(|c| c.label == "moveq.b" || c.label == "extb"));
+        let lea = labels(2, 8);
+        assert!(lea.contains(&"d(An)".to_string()) && lea.contains(&"loop".to_string()));
+        assert!(!lea.contains(&"Dn".to_string()) && !lea.contains(&"#imm".to_string()));
+        //byte moves can not write to address registers
+        let move_byte = labels(3, 15);
+        assert!(move_byte.contains(&"d0".to_string()) && !move_byte.contains(&"a0".to_string()));
+        //at most one of the operands can be in memory
+        let add = get_completions(&s68k, 4, 17);
+        assert!(add.iter().all(|c| !matches!(c.label.as_str(), "(An)" | "abs")));
+        let snippet = add.iter().find(|c| c.label == "Dn").unwrap().snippet.clone();
+        assert_eq!(snippet, Some("d${1:0}".to_string()));
+        assert!(get_completions(&s68k, 1, 28).is_empty());
+    }
+
+    #[test]
+    fn instruction_info_describes_every_mnemonic() {
+        use crate::constants::INSTRUCTIONS;
+        use crate::instruction_info::{get_entry, FlagEffect, InstructionInfo};
+        for name in INSTRUCTIONS {
+            assert!(get_entry(name).is_some(), "missing info for {}", name);
+        }
+        let movem = InstructionInfo::lookup("MOVEM.L").unwrap();
+        assert_eq!(movem.sizes, vec!["w", "l"]);
+        assert_eq!(movem.flags.get_symbols(), "-----");
+        assert!(movem.operands[0].contains(&"reg list") && movem.operands[0].contains(&"(An)+"));
+        let indirect = movem.cycles.iter().find(|c| c.mode == "(An)").unwrap();
+        assert_eq!((indirect.byte_word, indirect.long), (12, 16));
+        let add = InstructionInfo::lookup("add").unwrap();
+        assert_eq!(add.flags.x, FlagEffect::Affected);
+        assert_eq!(InstructionInfo::lookup("clr").unwrap().flags.z, FlagEffect::Set);
+        let beq = InstructionInfo::lookup_for_model("beq", CpuModel::M68020).unwrap();
+        assert_eq!(beq.family, "Bcc");
+        assert_eq!(beq.operands, vec![vec!["abs"]]);
+        assert_eq!(beq.cycles[0].byte_word, 9 + 5);
+        assert_eq!(InstructionInfo::lookup("bfins").unwrap().minimum_model, CpuModel::M68020);
+        assert!(InstructionInfo::lookup("abcd").is_none());
+        let database = InstructionInfo::all(CpuModel::M68000);
+        assert_eq!(database.len(), InstructionInfo::get_mnemonics().len());
+        let bfins = database.iter().find(|info| info.mnemonic == "bfins").unwrap();
+        assert!(!bfins.available);
+        assert!(InstructionInfo::all(CpuModel::M68020).iter().all(|info| info.available));
+        //the sizes and modes come from the forms the model has
+        assert_eq!(InstructionInfo::lookup("muls").unwrap().sizes, vec!["w"]);
+        let muls = InstructionInfo::lookup_for_model("muls", CpuModel::M68020).unwrap();
+        assert_eq!(muls.sizes, vec!["w", "l"]);
+        assert!(muls.operands[1].contains(&"Dh:Dl"));
+        assert!(!add.operands[0].contains(&"([bd,An],Xn,od)"));
+        assert_eq!(InstructionInfo::lookup("lea").unwrap().operands[1], vec!["An"]);
+    }
+
+    #[test]
+    fn instruction_encodings_match_the_assembler() {
+        use crate::instruction_info::InstructionInfo;
+        let samples = [
+            "beq.w $2000", "dbra d3, $2000", "sne (a2)", "bfins d1, d0{0:8}", "add.w d1, (a3)", "adda.l (a1), a2",
+            "addi.b #3, d4", "addq.l #5, a1", "sub.b -(a1), d2", "suba.w d0, a3", "subi.l #9, $3000", "subq.w #8, d7",
+            "and.l (a0)+, d1", "andi.w #1, d2", "or.b d1, (a0)", "ori.l #1, d3", "eor.w d2, d3", "eori.b #1, (a5)",
+            "not.l d1", "neg.w (a1)", "clr.b d2", "tst.l -(a6)", "cmp.w d1, d2", "cmpa.l d3, a4", "cmpi.b #1, d0",
+            "cmpm.w (a1)+, (a2)+", "move.w 4(a1), (a2,d1.w)", "movea.l d1, a3", "moveq #-1, d5", "movem.l d0-d2/a0, -(sp)",
+            "movec vbr, d0", "lea 8(a1), a4", "pea (a2)", "exg d1, a2", "swap d6", "ext.l d2", "extb.l d3", "muls.w d1, d2",
+            "mulu.w (a0), d3", "divs.w #3, d4", "divu.w d5, d6", "muls.l d1, d2", "divu.l d1, d2", "asl.w #3, d1",
+            "asr.l d2, d3", "lsl.b #1, d4", "lsr.w d5, d6", "rol.l #8, d7", "ror.b d0, d1", "btst d1, d2",
+            "btst #3, (a0)", "bchg d2, (a1)", "bclr #1, d3", "bset d4, $3000", "bra.w $2000", "bsr.w $2000",
+            "jmp (a1)", "jsr $2000", "rts", "rte", "rtr", "rtd #4", "link a6, #-8", "unlk a6", "trap #15", "trapv",
+            "chk.w d1, d2", "illegal", "simhalt",
+        ];
+        for sample in samples {
+            let name = sample.split(['.', ' ']).next().unwrap();
+            let info = InstructionInfo::lookup(name).unwrap();
+            let mut s68k = S68k::new(format!("    org $1000\n    {}", sample));
+            s68k.set_cpu_model(CpuModel::M68020);
+            let bytes = s68k.assemble().unwrap_or_else(|e| panic!("{}: {}", sample, e)).into_machine_code().bytes;
+            let word = u16::from_be_bytes([bytes[0], bytes[1]]);
+            let matches = |pattern: &&str| {
+                pattern.chars().filter(|c| *c != ' ').enumerate().all(|(i, c)| match c {
+                    '0' | '1' => (word >> (15 - i)) & 1 == (c == '1') as u16,
+                    _ => true,
+                })
+            };
+            assert!(info.encodings.iter().any(matches), "{} is {:016b}, expected {:?}", sample, word, info.encodings);
+        }
+    }
+
+    #[test]
+    fn references_follow_the_semantic_tokens() {
+        use crate::references::{find_definition, find_references, SourceSpan};
+        let code = "count equ 5
+loop: move.l #count, d0
+    dbra d0, loop
+    ; loop in a comment
+    bra loop";
+        let s68k = S68k::new(code.to_string());
+        let definition = Some(SourceSpan { line: 1, start: 0, end: 4 });
+        assert_eq!(find_definition(&s68k, 2, 14), definition);
+        //right after the word
+        assert_eq!(find_definition(&s68k, 4, 12), definition);
+        let loop_references = find_references(&s68k, 1, 2).unwrap();
+        assert_eq!(loop_references.name, "loop");
+        assert_eq!(
+            loop_references.references,
+            vec![SourceSpan { line: 2, start: 13, end: 17 }, SourceSpan { line: 4, start: 8, end: 12 }]
+        );
+        let count = find_references(&s68k, 1, 16).unwrap();
+        assert_eq!(count.definition, Some(SourceSpan { line: 0, start: 0, end: 5 }));
+        assert_eq!(count.references.len(), 1);
+        assert!(find_references(&s68k, 3, 8).is_none());
+        assert!(find_references(&s68k, 2, 10).is_none());
+    }
+
+    #[test]
+    fn visitors_walk_lexed_and_compiled_trees() {
+        use crate::instructions::RegisterOperand;
+        use crate::lexer::{LexedOperand, ParsedLine};
+        use crate::visitor::{Visitor, VisitorMut};
+        struct Registers {
+            lexed: usize,
+            compiled: Vec<u16>,
+        }
+        impl Visitor for Registers {
+            fn visit_lexed_operand(&mut self, operand: &LexedOperand) {
+                if let LexedOperand::Register(_, _) | LexedOperand::RegisterWithSize(_, _, _) = operand {
+                    self.lexed += 1;
+                }
+                crate::visitor::walk_lexed_operand(self, operand)
+            }
+            fn visit_register(&mut self, register: &RegisterOperand) {
+                self.compiled.push(register.to_index())
+            }
+        }
+        struct Rename;
+        impl VisitorMut for Rename {
+            fn visit_label_mut(&mut self, name: &mut String) {
+                name.insert_str(0, "renamed_")
+            }
+            fn visit_lexed_operand_mut(&mut self, operand: &mut LexedOperand) {
+                match operand {
+                    //label operands are lexed as absolute addresses
+                    LexedOperand::Absolute(name) | LexedOperand::Label(name) if name == "start" => {
+                        name.insert_str(0, "renamed_")
+                    }
+                    _ => crate::visitor::walk_lexed_operand_mut(self, operand),
+                }
+            }
+        }
+        let code = "start:
+    move.l 4(a0,d1.w), d0
+    lea (a1), a2
+    bra start";
+        let s68k = S68k::new(code.to_string());
+        let mut registers = Registers { lexed: 0, compiled: vec![] };
+        for line in s68k.get_lexed_lines() {
+            registers.visit_parsed_line(line);
+        }
+        //a0 and d1.w inside the index, d0, a1 inside the indirect and a2
+        assert_eq!(registers.lexed, 5);
+        let compiled = s68k.compile().unwrap();
+        for line in compiled.get_instructions() {
+            registers.visit_instruction_line(line);
+        }
+        //a0, d1, d0 and a2, (a1) is stored as a register number
+        assert_eq!(registers.compiled, vec![8, 1, 0, 10]);
+        let mut lines: Vec<ParsedLine> = s68k.get_lexed_lines().clone();
+        for line in lines.iter_mut() {
+            Rename.visit_parsed_line_mut(line);
+        }
+        let renamed = lines
+            .iter()
+            .filter(|line| format!("{:?}", line.parsed).contains("renamed_start"))
+            .count();
+        assert_eq!(renamed, 2);
+    }
+
+    #[test]
+    fn printer_round_trips_the_lexed_lines() {
+        use crate::lexer::LexedLine;
+        use crate::printer::{print_program, PrinterOptions};
+        let code = "x equ 5
+start:  move.l  #x,d0   ; load
+; full line comment
+
+        movem.l d0-d2/a0/a2-a3, -(sp)
+        move.w ([4,a0,d1.w*2],8), d2
+        bfextu (a0){2:d1}, d3
+    lea 4(a0,d1.w), a1
+    dc.b 1, 'ab'
+    rts";
+        let s68k = S68k::new(code.to_string());
+        let mut lines = s68k.get_lexed_lines().clone();
+        assert_eq!(print_program(&lines, &PrinterOptions::default()), code);
+        let options = PrinterOptions {
+            keep_trivia: false,
+            ..Default::default()
+        };
+        let printed = print_program(&lines, &options);
+        assert_eq!(
+            printed.lines().collect::<Vec<&str>>(),
+            vec![
+                "x equ 5",
+                "start: move.l #5, d0",
+                "",
+                "",
+                "    movem.l d0-d2/a0/a2-a3, -(sp)",
+                "    move.w ([4,a0,d1.w*2],8), d2",
+                "    bfextu (a0){2:d1}, d3",
+                "    lea 4(a0,d1.w), a1",
+                "    dc.b 1, 'ab'",
+                "    rts",
+            ]
+        );
+        //the regenerated program lexes to the same lines
+        let reprinted = S68k::new(printed.clone());
+        assert_eq!(print_program(reprinted.get_lexed_lines(), &options), printed);
+        //only the changed line is regenerated, keeping its comment
+        for line in lines.iter_mut() {
+            if let LexedLine::Instruction { name, .. } = &mut line.parsed {
+                if name == "move" && line.line_index == 1 {
+                    name.replace_range(.., "add");
+                }
+            }
+        }
+        let rewritten = print_program(&lines, &PrinterOptions::default());
+        assert_eq!(rewritten.lines().nth(1), Some("start: add.l #5, d0 ; load"));
+        assert_eq!(rewritten.lines().nth(2), Some("; full line comment"));
+    }
+
+    #[test]
+    fn errors_implement_error_with_sources() {
+        use crate::{AssembleError, CompilationError, LexError};
+        use std::error::Error;
+        let mut s68k = S68k::new("move.l ([4,d0,a0]), d1".to_string());
+        s68k.set_cpu_model(CpuModel::M68020);
+        let error = s68k.compile().err().unwrap();
+        assert_eq!(error.get_line_index(), 0);
+        let source = error.source().unwrap();
+        assert!(source.downcast_ref::<CompilationError>().is_some());
+        let lex_error = source.source().unwrap().downcast_ref::<LexError>().unwrap();
+        assert!(matches!(lex_error, LexError::InvalidMemoryIndirect(_)));
+        assert!(error.to_string().ends_with("; at line 0"));
+        let s68k = S68k::new("a:\na:".to_string());
+        match s68k.compile() {
+            Err(AssembleError::DuplicateLabel { line_index, name }) => assert_eq!((line_index, name.as_str()), (1, "a")),
+            _ => panic!("Expected a duplicate label"),
+        }
+        //every stage converts to a boxed error
+        let errors: Vec<Box<dyn Error>> = vec![
+            Box::new(LexError::InvalidRegister("x0".to_string())),
+            Box::new(S68k::new("move.l".to_string()).semantic_check().remove(0)),
+            Box::new(error),
+            Box::new(RuntimeError::DivisionByZero),
+        ];
+        assert_eq!(errors[0].to_string(), "Invalid register type 'x0'");
+        assert_eq!(errors[3].to_string(), "Division by zero");
+    }
+
+    #[test]
+    fn lex_reader_matches_lex() {
+        use crate::lexer::Lexer;
+        let code = "count equ 3\nstart: move.l #count, d0 ; load\n    dbra d0, start";
+        let mut lexer = Lexer::new();
+        let expected = format!("{:?}", lexer.lex(code).unwrap());
+        let crlf = code.replace('\n', "\r\n");
+        let lexed = format!("{:?}", lexer.lex_reader(crlf.as_bytes()).unwrap());
+        assert_eq!(lexed, expected);
+        assert!(lexer.get_read_diagnostics().is_empty());
+        //equs used before they are defined and macros make the source be lexed again at the end
+        for code in [
+            "move.l #count, d0\ncount equ 3",
+            "inc macro\n    addq.l #1, \\1\n    endm\n    inc d0",
+        ] {
+            let expected = format!("{:?}", lexer.lex(code).unwrap());
+            let lexed = format!("{:?}", lexer.lex_reader(code.as_bytes()).unwrap());
+            assert_eq!(lexed, expected);
+        }
+        let mut invalid = b"move.l #1, d0\r\n".to_vec();
+        invalid.extend(b"move.l #2, d1 ; \xFF\n");
+        let lines = lexer.lex_reader(&invalid[..]).unwrap();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1].line, "move.l #2, d1 ; \u{FFFD}");
+        let diagnostics = lexer.get_read_diagnostics();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].line_index, 1);
+    }
+
+    #[test]
+    fn borrowed_api_accepts_str() {
+        use crate::lexer::{LexedOperand, Lexer};
+        let code = "start: move.l (a0)+, d0";
+        let lexer = Lexer::new();
+        assert!(matches!(lexer.parse_operand("(a0)+"), Ok(LexedOperand::PostIndirect(_))));
+        let operands = lexer.parse_operands(&["#1".to_string(), "d0".to_string()]).unwrap();
+        assert!(matches!(operands[..], [LexedOperand::Immediate(_), LexedOperand::Register(..)]));
+        let borrowed = S68k::new(code);
+        let owned = S68k::new(code.to_string());
+        assert_eq!(borrowed.get_code(), owned.get_code());
+        assert_eq!(
+            format!("{:?}", borrowed.get_lexed_lines()),
+            format!("{:?}", owned.get_lexed_lines())
+        );
+    }
+
+    #[test]
+    fn lexer_lines_by_reference() {
+        use crate::lexer::Lexer;
+        let mut lexer = Lexer::new();
+        lexer.lex("start: move.l #1, d0\n    bra start").unwrap();
+        let lines: &[_] = lexer.lines();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines.as_ptr(), lexer.get_lines().as_ptr());
+        let expected = format!("{:?}", lines);
+        assert_eq!(format!("{:?}", lexer.into_lines()), expected);
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn lex_parallel_matches_lex() {
+        use crate::lexer::Lexer;
+        let code = "count equ 3\nstart: move.l #count, d0 ; load\n    dbra d0, start\n"
+            .repeat(200);
+        let mut lexer = Lexer::new();
+        let expected = format!("{:?}", lexer.lex(&code).unwrap());
+        assert_eq!(format!("{:?}", lexer.lex_parallel(&code).unwrap()), expected);
+    }
+
+    #[test]
+    fn arena_round_trips_the_lexed_lines() {
+        use crate::arena::{ArenaLine, ArenaOperand};
+        use crate::lexer::Lexer;
+        let code = "size equ 4
+start: move.l ([4,a0],d0.l*8), d1 ; load
+    bfextu d0{4:8}, d1
+    movem.l d0-d2/a0, -(sp)
+    dc.b 'hi', 0";
+        let mut lexer = Lexer::new();
+        let arena = lexer.lex_arena(code);
+        let expected = format!("{:?}", lexer.lex(code).unwrap());
+        assert_eq!(format!("{:?}", arena.to_parsed_lines()), expected);
+        let lines = arena.lines();
+        assert_eq!(lines[1].line, lines[2].line);
+        match &lines[2].parsed {
+            ArenaLine::Instruction { name, operands, .. } => {
+                assert_eq!(arena.get_text(*name), "move");
+                match arena.get_operands(*operands) {
+                    [ArenaOperand::MemoryIndirect { inner, .. }, ArenaOperand::Register(_, register)] => {
+                        assert_eq!(arena.get_operands(*inner).len(), 2);
+                        assert_eq!(arena.get_text(*register), "d1");
+                    }
+                    operands => panic!("Unexpected operands {:?}", operands),
+                }
+            }
+            line => panic!("Unexpected line {:?}", line),
+        }
+    }
+
+    #[test]
+    fn builder_matches_the_lexer() {
+        use crate::builder::{a, abs, d, disp, imm, label, post_inc, pre_dec, sp, BuilderError, ProgramBuilder};
+        use crate::lexer::{LexedSize, Lexer};
+        let builder = ProgramBuilder::new()
+            .equ("count", "3")
+            .inst("move", LexedSize::Long, [imm(0), d(1)])
+            .inst("lea", LexedSize::Unspecified, [abs(0x2000), a(0)])
+            .inst("move", LexedSize::Long, [imm(3), d(0)])
+            .label("loop")
+            .inst("add", LexedSize::Long, [d(0), d(1)])
+            .inst("move", LexedSize::Long, [d(1), post_inc(a(0))])
+            .inst("subq", LexedSize::Long, [imm(1), d(0)])
+            .bne("loop")
+            .inst("move", LexedSize::Long, [disp(-4, a(0)), d(2)])
+            .inst("move", LexedSize::Long, [d(2), pre_dec(sp())])
+            .bsr("done")
+            .label("done")
+            .inst("lea", LexedSize::Unspecified, [label("count"), a(1)])
+            .dc(LexedSize::Word, &["count", "2"]);
+        let mut lexer = Lexer::new();
+        let lexed = lexer.lex(&builder.to_source()).unwrap();
+        assert_eq!(format!("{:?}", builder.build().unwrap()), format!("{:?}", lexed));
+        let source = builder.to_source();
+        let s68k = builder.into_s68k().unwrap();
+        assert_eq!(s68k.get_code(), source);
+        assert!(s68k.semantic_check().is_empty());
+        let mut interpreter = s68k.create_interpreter(s68k.compile().unwrap(), None);
+        interpreter.run().unwrap();
+        assert_eq!(interpreter.get_cpu().wasm_get_d_reg(1).get_long(), 6);
+        assert_eq!(interpreter.get_cpu().wasm_get_d_reg(2).get_long(), 6);
+        assert_eq!(interpreter.get_cpu().wasm_get_a_reg(1).get_long(), 3);
+        let unknown = ProgramBuilder::new().label("start").inst("mov", LexedSize::Long, [d(0), d(1)]).build();
+        assert_eq!(unknown.unwrap_err(), BuilderError::UnknownInstruction { index: 1, name: "mov".to_string() });
+    }
+
+    #[test]
+    fn template_expands_bound_parameters() {
+        use crate::template::{Template, TemplateError};
+        let template = Template::new(
+            "\\name: move.l #\\count, \\reg ; \\\\ kept
+    add.l \\reg, d1",
+        )
+        .unwrap();
+        assert_eq!(template.get_parameters(), ["name", "count", "reg"]);
+        let bindings = [("name", "start"), ("count", "5"), ("reg", "d2")];
+        assert_eq!(
+            template.expand(&bindings).unwrap(),
+            "start: move.l #5, d2 ; \\ kept\n    add.l d2, d1"
+        );
+        let lines = template.instantiate(&bindings).unwrap();
+        assert_eq!(lines.len(), 3);
+        let s68k = S68k::new(template.expand(&bindings).unwrap());
+        let mut interpreter = s68k.create_interpreter(s68k.compile().unwrap(), None);
+        interpreter.run().unwrap();
+        assert_eq!(interpreter.get_cpu().wasm_get_d_reg(1).get_long(), 5);
+        let missing = template.expand(&[("name", "start"), ("count", "5")]);
+        assert_eq!(missing, Err(TemplateError::MissingBinding("reg".to_string())));
+        let unknown = template.expand(&[("other", "1")]);
+        assert_eq!(unknown, Err(TemplateError::UnknownBinding("other".to_string())));
+        for value in ["1\n    trap #15", "1 ; comment", "d0 *", "\\reg"] {
+            assert!(template.expand(&[("name", "a"), ("count", value), ("reg", "d0")]).is_err());
+        }
+        assert_eq!(Template::new("move.l \\ d0").err(), Some(TemplateError::InvalidParameter(7)));
+    }
+
+    #[test]
+    fn diff_reports_changed_instructions() {
+        use crate::diff::{diff, LineDiff};
+        use crate::lexer::LexedOperand;
+        let before = S68k::new(
+            "start: move.l #1, d0 ; first
+    add.l d0, d1
+    clr.l d2
+    rts",
+        );
+        let after = S68k::new(
+            "start:   move.l   #1,d0
+* a comment
+    add.l d0, d3
+    nop
+    rts",
+        );
+        let changes = diff(before.get_lexed_lines(), after.get_lexed_lines());
+        assert_eq!(changes.len(), 3);
+        match &changes[0] {
+            LineDiff::Changed { before_index, after_index, operands, .. } => {
+                assert_eq!((*before_index, *after_index), (1, 2));
+                assert_eq!(operands.len(), 1);
+                assert_eq!(operands[0].index, 1);
+                assert!(matches!(&operands[0].after, Some(LexedOperand::Register(_, name)) if name == "d3"));
+            }
+            change => panic!("Unexpected change {:?}", change),
+        }
+        assert!(matches!(changes[1], LineDiff::Removed { line_index: 2, .. }));
+        assert!(matches!(changes[2], LineDiff::Inserted { line_index: 3, .. }));
+        assert!(diff(before.get_lexed_lines(), before.get_lexed_lines()).is_empty());
+    }
+
+    #[test]
+    fn outline_nests_local_labels() {
+        use crate::outline::{get_outline, OutlineKind};
+        let code = "size equ 4
+double equ size*2
+main:
+    move.l #size, d0
+.loop:
+    dbra d0, .loop
+.done:
+    rts
+other: nop";
+        let outline = get_outline(&S68k::new(code));
+        let names: Vec<&str> = outline.iter().map(|symbol| symbol.name.as_str()).collect();
+        assert_eq!(names, ["size", "double", "main", "other"]);
+        assert_eq!(outline[1].kind, OutlineKind::Constant);
+        assert_eq!(outline[1].detail.as_deref(), Some("4*2"));
+        assert_eq!(outline[1].value, Some(8));
+        let main = &outline[2];
+        assert_eq!((main.range.start_line, main.range.end_line), (2, 7));
+        assert_eq!(main.children.len(), 2);
+        assert_eq!(main.children[0].name, ".loop");
+        assert_eq!((main.children[0].range.start_line, main.children[0].range.end_line), (4, 5));
+        assert_eq!(main.children[1].range.end_line, 7);
+        assert_eq!((outline[3].range.start_line, outline[3].range.end_line), (8, 8));
+        assert_eq!(outline[3].selection_range.end, 5);
+    }
+
+    #[test]
+    fn folding_ranges_cover_subroutines_and_data() {
+        use crate::folding::{get_folding_ranges, FoldingKind, FoldingRange};
+        let code = "start:
+    bsr sum
+    bra done
+sum:
+    moveq #0, d0
+.loop:
+    add.b (a0)+, d0
+    dbra d1, .loop
+    rts
+table:
+    dc.b 1, 2
+    ; more
+    dc.b 3, 4
+short: dc.w 5
+done:
+    nop
+    dc.l 1
+    dc.l 2";
+        let ranges = get_folding_ranges(&S68k::new(code));
+        let range = |start_line, end_line, kind| FoldingRange {
+            start_line,
+            end_line,
+            kind,
+        };
+        assert_eq!(
+            ranges,
+            [
+                range(3, 8, FoldingKind::Subroutine),
+                range(10, 13, FoldingKind::Data),
+            ]
+        );
+    }
+
+    #[test]
+    fn inlay_hints_show_layout_and_values() {
+        use crate::inlay_hints::{get_inlay_hints, InlayHintKind};
+        let code = "size equ 4
+    move.l #size, d0 ; load
+loop:
+    dbra d0, loop
+    dc.b 1, 2";
+        let hints = get_inlay_hints(&S68k::new(code));
+        let on_line = |line: usize| {
+            hints
+                .iter()
+                .filter(|hint| hint.line == line)
+                .map(|hint| (hint.column, hint.label.as_str(), hint.kind))
+                .collect::<Vec<_>>()
+        };
+        let move_hints = on_line(1);
+        assert_eq!(move_hints[0], (16, "= 4", InlayHintKind::Value));
+        assert_eq!(move_hints[1], (20, "$1000", InlayHintKind::Address));
+        assert_eq!(move_hints[2], (20, "6 bytes", InlayHintKind::Size));
+        assert_eq!(move_hints[3].2, InlayHintKind::Cycles);
+        assert!(on_line(3)[2].1.contains('/'));
+        assert_eq!(
+            on_line(4),
+            [(13, "$100A", InlayHintKind::Address), (13, "2 bytes", InlayHintKind::Size)]
+        );
+        //without a valid program only the values are known
+        let hints = get_inlay_hints(&S68k::new("size equ 4\n    move.l #size, d0\n    bra missing"));
+        assert!(hints.iter().all(|hint| hint.kind == InlayHintKind::Value));
+        assert_eq!(hints.len(), 1);
+    }
+
+    #[test]
+    fn signature_help_tracks_the_active_operand() {
+        use crate::signature_help::get_signature_help;
+        let s68k = S68k::new("loop: move.l d0, (a0) ; copy\n    lea (a0), \n    bra loop");
+        let help = get_signature_help(&s68k, 0, 14).unwrap();
+        assert_eq!(help.label, "move.l src, dst");
+        assert_eq!(help.active_parameter, Some(0));
+        let (source, dest) = (&help.parameters[0], &help.parameters[1]);
+        assert_eq!(&help.label[source.start..source.end], "src");
+        assert_eq!(&help.label[dest.start..dest.end], "dst");
+        assert!(source.modes.contains(&"#imm"));
+        assert!(!dest.modes.contains(&"#imm"));
+        assert_eq!(get_signature_help(&s68k, 0, 19).unwrap().active_parameter, Some(1));
+        //in the mnemonic, in the comment or without an instruction there is no signature
+        assert!(get_signature_help(&s68k, 0, 9).is_none());
+        assert!(get_signature_help(&s68k, 0, 27).is_none());
+        assert!(get_signature_help(&S68k::new("x equ 4"), 0, 6).is_none());
+        let help = get_signature_help(&s68k, 1, 13).unwrap();
+        assert_eq!(help.parameters[1].modes, ["An"]);
+        assert_eq!(get_signature_help(&s68k, 2, 8).unwrap().parameters.len(), 1);
+    }
+
+    #[test]
+    fn code_actions_fix_common_errors() {
+        use crate::code_actions::{get_code_actions, CodeActionKind};
+        let code = "    move.q d0, d1
+    move.l d0, #5 ; store
+    bra.w loop
+loop:
+    add d0, d1
+    adda.l d0, a0";
+        let s68k = S68k::new(code);
+        let titles = |line: usize| {
+            get_code_actions(&s68k, line)
+                .into_iter()
+                .map(|action| action.title)
+                .collect::<Vec<String>>()
+        };
+        assert_eq!(
+            titles(0),
+            ["Change the size to .b", "Change the size to .w", "Change the size to .l"]
+        );
+        let swap = &get_code_actions(&s68k, 1)[0];
+        assert_eq!(swap.kind, CodeActionKind::QuickFix);
+        assert_eq!((swap.edits[0].start, swap.edits[0].end), (11, 17));
+        assert_eq!(swap.edits[0].new_text, "#5, d0");
+        let remove = &get_code_actions(&s68k, 2)[0];
+        assert_eq!(remove.title, "Remove the size suffix");
+        assert_eq!((remove.edits[0].start, remove.edits[0].end), (7, 9));
+        let refactors = get_code_actions(&s68k, 4);
+        assert_eq!(refactors.len(), 3);
+        assert!(refactors.iter().all(|action| action.kind == CodeActionKind::Refactor && action.diagnostic.is_none()));
+        assert_eq!((refactors[0].edits[0].start, refactors[0].edits[0].end), (7, 7));
+        //only one size is valid, there is no choice
+        assert!(titles(5).is_empty());
+        let padding = get_code_actions(&S68k::new("    dc.b 1\n    rts"), 1);
+        assert_eq!(padding.len(), 1);
+        assert_eq!(padding[0].edits[0].new_text, "    dc.b 0\n");
+    }
+
+    #[test]
+    fn duplicate_label_points_to_the_first_definition() {
+        use crate::RelatedSpan;
+        let s68k = S68k::new("start:\n    rts\n  start: rts");
+        let errors = s68k.semantic_check();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].get_line_index(), 2);
+        assert_eq!(
+            errors[0].get_related(),
+            [RelatedSpan {
+                line_index: 0,
+                start: 0,
+                end: 5,
+                message: "first defined here".to_string(),
+            }]
+        );
+        assert!(errors[0].get_message_with_line().ends_with("\n    line 1: first defined here"));
+        assert_eq!(s68k.get_diagnostics()[0].related, errors[0].get_related());
+    }
+
+    #[test]
+    fn parse_untrusted_rejects_pathological_input() {
+        use crate::lexer::MAX_EXPRESSION_LENGTH;
+        use crate::untrusted::{parse_untrusted, UntrustedError, UntrustedLimits};
+        let limits = UntrustedLimits::default();
+        let commas = format!("    move {}", ",".repeat(10 << 20));
+        assert!(matches!(parse_untrusted(&commas, &limits), Err(UntrustedError::TooLarge { .. })));
+        let commas = format!("    move {}", ",".repeat(limits.max_line_length));
+        assert!(matches!(
+            parse_untrusted(&commas, &limits),
+            Err(UntrustedError::LineTooLong { line_index: 0, .. })
+        ));
+        let equs: Vec<String> = (0..=limits.max_equs).map(|i| format!("e{} equ {}", i, i)).collect();
+        assert!(matches!(
+            parse_untrusted(&equs.join("\n"), &limits),
+            Err(UntrustedError::TooManyEqus { .. })
+        ));
+        //every equ doubles the one before it
+        let mut code: Vec<String> = (1..40).map(|i| format!("x{:02} equ x{:02}+x{:02}", i, i - 1, i - 1)).collect();
+        code.push("x00 equ 1".to_string());
+        code.push("    move.l #x39, d0".to_string());
+        let (s68k, _) = parse_untrusted(&code.join("\n"), &limits).unwrap();
+        assert!(s68k.get_lexed_lines().iter().all(|line| format!("{:?}", line.parsed).len() < 2 * MAX_EXPRESSION_LENGTH));
+        let nested = format!("    move {}a0{}, d0", "(".repeat(2000), ")".repeat(2000));
+        assert!(parse_untrusted(&nested, &limits).is_ok());
+        for code in ["    dc.b '", "    org", "    ds.b", "    dcb.b"] {
+            let (s68k, _) = parse_untrusted(code, &limits).unwrap();
+            assert!(s68k.compile().is_err());
+        }
+    }
+
+    #[test]
+    fn parse_untrusted_survives_junk_sizes_and_operands() {
+        use crate::untrusted::{parse_untrusted, UntrustedLimits};
+        let limits = UntrustedLimits::default();
+        let sizes = ["", ".b", ".w", ".l", ".s", ".d", ".x", ".vbrl", ".", ".q", ".bw"];
+        let operands = [
+            "", "#2, d2", "d0", "a0", "(a0)", "#", "#, d0", "#$ffffffffff, d0", "#-99999999999, d0", "(,,)", ",",
+            "d0,", "8(a0,d1.w*4), d0", "(8,a0,d1.w*4), d0", "d0-d9/a0, -(sp)", "sr", "ccr, d0", "-(a0)+", "1000(a0,d1.w), d0",
+            "label(pc)", "#1, #2", "(a0)+, (a1)+, (a2)+", "d0:d1", "d1:d2, d0", "#1, d0, d1", "'a', d0", "%102, d0",
+        ];
+        for mnemonic in crate::constants::INSTRUCTIONS.iter().chain(crate::constants::DIRECTIVES) {
+            for size in sizes {
+                for operand in operands {
+                    let code = format!("label:\n    {}{} {}", mnemonic, size, operand);
+                    assert!(parse_untrusted(&code, &limits).is_ok(), "{}", code);
+                }
+            }
+        }
+        let (_, errors) = parse_untrusted("    add.vbrl #2, d2", &limits).unwrap();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].get_error(), "Unknown size, expected any of \"b, w, l\"");
+    }
+
+    #[test]
+    #[cfg(feature = "remote")]
+    fn remote_debug_server_answers_requests_and_pushes_events() {
+        use crate::remote_debug::RemoteDebugServer;
+        use serde_json::{json, Value};
+        let mut server = RemoteDebugServer::new();
+        let mut send = |request: Value| -> Vec<Value> {
+            server
+                .handle_message(&request.to_string())
+                .iter()
+                .map(|message| serde_json::from_str(message).unwrap())
+                .collect()
+        };
+        let messages = send(json!({ "id": 1, "command": "step" }));
+        assert_eq!(messages, [json!({ "id": 1, "error": "The program was not loaded" })]);
+        let code = "main:
+    move.l #4, d0
+    trap #15
+    bsr double
+    move.l #3, d0
+    trap #15
+    bra end
+double:
+    add.l d1, d1
+    rts
+end: move.l #0, d2";
+        let messages = send(json!({ "id": 2, "command": "load", "code": code }));
+        assert_eq!(messages[0]["result"]["line"], json!(1));
+        let messages = send(json!({ "id": 3, "command": "setBreakpoints", "lines": [7, 8] }));
+        assert_eq!(messages, [json!({ "id": 3, "result": { "lines": [8] } })]);
+        let messages = send(json!({ "id": 4, "command": "continue" }));
+        assert_eq!(messages[1], json!({ "event": "interrupt", "interrupt": { "type": "ReadNumber" } }));
+        let messages = send(json!({ "id": 5, "command": "answerInterrupt", "result": { "type": "ReadNumber", "value": 21 } }));
+        assert_eq!(messages[1]["event"], json!("stopped"));
+        assert_eq!(messages[1]["reason"], json!("breakpoint"));
+        assert_eq!(messages[1]["line"], json!(8));
+        let messages = send(json!({ "id": 6, "command": "stepOut" }));
+        assert_eq!((&messages[1]["reason"], &messages[1]["line"]), (&json!("step"), &json!(4)));
+        let messages = send(json!({ "id": 7, "command": "continue" }));
+        assert_eq!(&messages[1..], [json!({ "event": "output", "text": "42" }), json!({ "event": "terminated" })]);
+        let messages = send(json!({ "id": 8, "command": "getState" }));
+        assert_eq!(messages[0]["result"]["d"][1], json!(42));
+        let messages = send(json!({ "id": 9, "command": "detach" }));
+        assert_eq!(messages.len(), 1);
+        let messages = send(json!({ "id": 10, "command": "getState" }));
+        assert_eq!(messages[0]["error"], json!("The client is not attached"));
+    }
+
+    #[test]
+    #[cfg(feature = "gdb")]
+    fn gdb_stub_answers_the_remote_protocol() {
+        use crate::gdb_stub::{serve, GdbStub};
+        use std::io::{Cursor, Read, Write};
+        let code = "start:
+    move.l #5, d0
+    bsr sub
+    lea msg, a1
+    move.l #14, d0
+    trap #15
+    bra end
+sub:
+    add.l #1, d0
+    rts
+msg: dc.b 'hi!', 0
+end: move.l #0, d1";
+        let s68k = S68k::new(code);
+        let new_stub = || GdbStub::new(s68k.create_interpreter(s68k.compile().unwrap(), None));
+        let mut stub = new_stub();
+        let mut send = |packet: &str| stub.handle_packet(packet);
+        let registers = send("g").remove(0);
+        assert_eq!(registers.len(), 18 * 8);
+        //sr and pc are the last ones
+        assert!(registers.ends_with("0000200000001000"));
+        assert_eq!(send("Z0,101a,2"), ["OK"]);
+        assert_eq!(send("Z2,101a,4"), [""]);
+        assert_eq!(send("c"), ["S05"]);
+        assert_eq!(send("p11"), ["0000101a"]);
+        assert_eq!(send("s"), ["S05"]);
+        assert_eq!(send("p0"), ["00000006"]);
+        assert_eq!(send("P0=0000000a"), ["OK"]);
+        assert_eq!(send("p0"), ["0000000a"]);
+        assert_eq!(send("m1022,4"), ["68692100"]);
+        assert_eq!(send("M1022,1:48"), ["OK"]);
+        let symbols = send(&format!("qRcmd,{}", "symbols".bytes().map(|b| format!("{:02x}", b)).collect::<String>()));
+        assert!(symbols[0].contains(&"sub = 0x101a".bytes().map(|b| format!("{:02x}", b)).collect::<String>()));
+        assert_eq!(send("z0,101a,2"), ["OK"]);
+        assert_eq!(send("c"), ["O486921", "W00"]);
+        assert_eq!(send("?"), ["W00"]);
+        assert!(send("k").is_empty());
+        assert!(stub.has_ended());
+
+        struct Stream(Cursor<Vec<u8>>, Vec<u8>);
+        impl Read for Stream {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                self.0.read(buf)
+            }
+        }
+        impl Write for Stream {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.1.write(buf)
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+        let mut stream = Stream(Cursor::new(b"+$?#3f$?#00".to_vec()), vec![]);
+        //the second packet has a wrong checksum and is not answered
+        serve(&mut stream, &mut new_stub()).unwrap();
+        assert_eq!(String::from_utf8(stream.1).unwrap(), "+$S05#b8-");
+    }
+
+    #[test]
+    fn memory_images_split_words_and_pad_to_depth() {
+        use crate::memory_image::{MemoryImage, MemoryImageError, MemoryImageFormat, MemoryImageOptions};
+        let s68k = S68k::new("    org $2000\n    dc.b 1, 2, 3\n    dc.b 4, $AB");
+        let image = MemoryImage::from_machine_code(s68k.assemble().unwrap().get_machine_code());
+        assert_eq!(image, MemoryImage::new(0x2000, vec![1, 2, 3, 4, 0xAB]));
+        let code = S68k::new("    org $1000\n    moveq #1, d0\n    rts\n    dc.b 'hi'").assemble().unwrap();
+        let program = MemoryImage::from_machine_code(code.get_machine_code());
+        assert_eq!(program, MemoryImage::new(0x1000, vec![0x70, 0x01, 0x4E, 0x75, b'h', b'i']));
+        let options = MemoryImageOptions {
+            word_width: 16,
+            depth: Some(6),
+        };
+        assert_eq!(image.get_words(&options).unwrap(), [0x0102, 0x0304, 0xAB00, 0, 0, 0]);
+        assert_eq!(
+            image.write(MemoryImageFormat::Mif, &options).unwrap(),
+            "-- Starts at address $2000
+WIDTH=16;
+DEPTH=6;
+
+ADDRESS_RADIX=HEX;
+DATA_RADIX=HEX;
+
+CONTENT BEGIN
+\t0 : 0102;
+\t1 : 0304;
+\t2 : AB00;
+\t[3..5] : 0000;
+END;
+"
+        );
+        assert_eq!(
+            image.write(MemoryImageFormat::VerilogHex, &options).unwrap(),
+            "// Starts at address $2000\n0102\n0304\nAB00\n0000\n0000\n0000\n"
+        );
+        let options = MemoryImageOptions {
+            word_width: 32,
+            depth: None,
+        };
+        assert_eq!(
+            image.write(MemoryImageFormat::Coe, &options).unwrap(),
+            "; Starts at address $2000\nmemory_initialization_radix=16;\nmemory_initialization_vector=\n01020304,\nAB000000;\n"
+        );
+        let too_small = MemoryImageOptions {
+            word_width: 8,
+            depth: Some(4),
+        };
+        assert_eq!(image.get_words(&too_small), Err(MemoryImageError::TooLarge { words: 5, depth: 4 }));
+        let odd_width = MemoryImageOptions {
+            word_width: 12,
+            depth: None,
+        };
+        assert_eq!(image.get_words(&odd_width), Err(MemoryImageError::InvalidWordWidth(12)));
+        assert_eq!("hex".parse(), Ok(MemoryImageFormat::VerilogHex));
+    }
+
+    #[test]
+    fn grading_reports_the_differences_of_the_final_state() {
+        use crate::grading::{ConditionFlag, Expectation, Grader, RunOutcome};
+        use crate::instructions::RegisterOperand;
+        let code = "    move.l #4, d0
+    trap #15
+    move.l d1, d3
+    add.l d1, d1
+    move.l #3, d0
+    trap #15
+    move.b #$AB, $2000";
+        let report = Grader::new()
+            .expect_register(RegisterOperand::Data(1), 42)
+            .expect_memory(0x2000, &[0xAB])
+            .expect_flag(ConditionFlag::Negative, true)
+            .expect_output("42")
+            .with_input("21\n")
+            .grade(code);
+        assert_eq!(report.outcome, RunOutcome::Terminated);
+        assert!(report.passed, "{:?}", report.get_failed_checks());
+        assert_eq!(report.instructions, 7);
+        let report = Grader::new()
+            .expect_register(RegisterOperand::Data(3), 20)
+            .expect_memory(0x2000, &[0xAB, 1])
+            .expect_flag(ConditionFlag::Zero, false)
+            .expect_output("41")
+            .with_input("21")
+            .grade(code);
+        assert!(!report.passed);
+        let failed = report.get_failed_checks();
+        assert_eq!(failed.len(), 3);
+        assert_eq!(failed[0].message.as_deref(), Some("d3 is $00000015, expected $00000014"));
+        assert_eq!(
+            failed[0].actual,
+            Some(Expectation::Register {
+                register: RegisterOperand::Data(3),
+                value: 21
+            })
+        );
+        assert!(failed[1].message.as_ref().unwrap().ends_with("different at $2001"));
+        assert!(failed[2].message.as_ref().unwrap().ends_with("different from character 1"));
+        let report = Grader::new().expect_register(RegisterOperand::Data(0), 0).grade("    move.l d0");
+        assert!(matches!(report.outcome, RunOutcome::AssemblyFailed(_)));
+        assert!(!report.checks[0].passed);
+        let report = Grader::new().with_limit(100).grade("loop: bra loop");
+        assert_eq!(report.outcome, RunOutcome::LimitReached(100));
+        assert!(!report.passed);
+    }
+
+    #[test]
+    fn source_assertions_are_checked_at_checkpoints_and_at_the_end() {
+        use crate::assertions::parse_assertions;
+        use crate::grading::{Expectation, Grader, RunOutcome};
+        let code = "count equ 3
+    move.l #0, d0
+    move.l #count, d1
+loop: ;ASSERT d1 > 0
+    ;ASSERT D1 = 3
+    add.l d1, d0
+    sub.l #1, d1
+    bne loop
+    move.w d0, result
+    move.l #6, d0
+    move.b #'!', d1
+    trap #15
+    ;ASSERT word[result] = 6
+    ;ASSERT z == 0
+    ;ASSERT_OUTPUT \"!\"
+result: dc.w 0";
+        //the first assertion is not a comparison
+        let report = Grader::new().grade(code);
+        match &report.outcome {
+            RunOutcome::AssemblyFailed(errors) => assert!(errors[0].starts_with("Invalid assertion at line 4")),
+            outcome => panic!("{:?}", outcome),
+        }
+        let code = code.replace("d1 > 0", "a7 = $FFFFFFFF+count");
+        let s68k = S68k::new(code.as_str());
+        let assertions = parse_assertions(&s68k, s68k.compile().unwrap().get_labels_map()).unwrap();
+        assert_eq!(assertions.len(), 5);
+        assert_eq!(assertions[1].checkpoint.as_deref(), Some("loop"));
+        assert_eq!(assertions[2].checkpoint, None);
+        assert_eq!(
+            assertions[2].expectation,
+            Expectation::Memory {
+                address: s68k.compile().unwrap().get_labels_map()["result"].address,
+                bytes: vec![0, 6]
+            }
+        );
+        let report = Grader::new().grade(&code);
+        assert_eq!(report.outcome, RunOutcome::Terminated);
+        assert_eq!(report.assertions.iter().map(|a| a.hits).collect::<Vec<_>>(), vec![3, 3, 1, 1, 1]);
+        //the stack pointer assertion is wrong and d1 is only 3 on the first iteration
+        let failed = report.get_failed_assertions();
+        assert_eq!(failed.len(), 2);
+        assert_eq!(failed[1].assertion.line_index, 4);
+        assert_eq!(
+            failed[1].check.as_ref().unwrap().message.as_deref(),
+            Some("d1 is $00000002, expected $00000003")
+        );
+        assert!(!report.passed);
+    }
+
+    #[test]
+    fn scripted_input_applies_the_policy_once_it_is_over() {
+        use crate::grading::{Grader, RunOutcome};
+        use crate::scripted_input::{ExhaustedPolicy, InputError, ScriptedInput};
+        let mut input = ScriptedInput::from_text("ab\r\n12", ExhaustedPolicy::Fail);
+        assert_eq!(input.read_char(), Ok('a'));
+        assert_eq!(input.read_line(), Ok("b".to_string()));
+        assert_eq!(input.read_number(), Ok(12));
+        assert_eq!(input.read_line(), Err(InputError::Exhausted));
+        input.push_line("x");
+        assert_eq!(input.read_number(), Err(InputError::NotANumber("x".to_string())));
+        let mut input = ScriptedInput::from_text("7", "repeat".parse().unwrap());
+        assert_eq!((input.read_number(), input.read_number()), (Ok(7), Ok(7)));
+        let mut input = ScriptedInput::from_text("k", "eof=-1".parse().unwrap());
+        assert_eq!((input.read_char(), input.read_char(), input.read_char()), (Ok('k'), Ok('\n'), Ok('\0')));
+        assert_eq!((input.read_number(), input.read_line()), (Ok(-1), Ok(String::new())));
+        assert!("eof=x".parse::<ExhaustedPolicy>().is_err());
+        //sums the numbers read until a negative one
+        let code = "    move.l #0, d2
+loop:
+    move.l #4, d0
+    trap #15
+    tst.l d1
+    bmi end
+    add.l d1, d2
+    bra loop
+end:
+    move.l d2, d1
+    move.l #3, d0
+    trap #15";
+        let report = Grader::new().expect_output("6").with_input("1\n2\n3").grade(code);
+        assert_eq!(report.outcome, RunOutcome::RuntimeError("The program read more input than it was given".to_string()));
+        let report = Grader::new()
+            .with_exhausted_input(ExhaustedPolicy::Eof(-1))
+            .expect_output("6")
+            .with_input("1\n2\n3")
+            .grade(code);
+        assert!(report.passed, "{:?}", report);
+        let report = Grader::new().with_exhausted_input(ExhaustedPolicy::RepeatLast).with_limit(1000).with_input("1").grade(code);
+        assert_eq!(report.outcome, RunOutcome::LimitReached(1000));
+    }
+
+    #[test]
+    fn easy68k_programs_halt_and_start_from_the_end_label() {
+        use crate::grading::Grader;
+        let code = "*-----------------------------------------------------------
+* Title      : hello
+*-----------------------------------------------------------
+    ORG    $1000
+MESSAGE: DC.B 'Hello',0
+START:                  ; first instruction of program
+    LEA     MESSAGE,A1
+    MOVE.B  #14,D0
+    TRAP    #15
+    SIMHALT             ; halt simulator
+    MOVE.B  #1,D2
+    END    START        ; last line of source
+    this line is not assembled";
+        let s68k = S68k::new(code);
+        assert!(s68k.semantic_check().is_empty());
+        let assembled = s68k.assemble().unwrap();
+        let code_bytes = &assembled.get_machine_code().bytes;
+        assert_eq!(code_bytes[code_bytes.len() - 8..code_bytes.len() - 4], [0xFF, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(s68k.compile().unwrap().get_start_address(), 0x1006);
+        let report = Grader::new().expect_output("Hello").grade(code);
+        assert!(report.passed, "{:?}", report);
+        let s68k = S68k::new("    moveq #1, d0\n    simhalt\n    moveq #2, d0");
+        let mut interpreter = s68k.create_interpreter(s68k.compile().unwrap(), None);
+        assert_eq!(interpreter.run_with_limit(100).unwrap(), InterpreterStatus::Terminated);
+        assert_eq!(interpreter.get_cpu().wasm_get_d_reg(0).get_long(), 1);
+        let s68k = S68k::new("start: moveq #1, d0\n    end main");
+        assert_eq!(s68k.semantic_check().len(), 1);
+    }
+
+    #[test]
+    fn easy68k_listings_use_labels_without_colons() {
+        use crate::lexer::LexedLine;
+        let code = "*-----------------------------------------------------------
+* Title      : sum
+* Written by : a student
+*-----------------------------------------------------------
+    ORG    $1000
+START                   ; first instruction of program
+    MOVE.L  #5,D1
+    CLR.L   D0
+LOOP    ADD.L   D1,D0
+    SUBQ.L  #1,D1
+    BNE     LOOP
+    NOP
+    MOVE.L  D0,RESULT
+    LEA     MESSAGE,A1
+    MOVE.B  #14,D0
+    TRAP    #15
+    SIMHALT             ; halt simulator
+
+* Variables and Strings
+RESULT  DS.L    1
+MESSAGE DC.B    'Done',0
+COUNT   EQU     5
+
+    END    START        ; last line of source";
+        let s68k = S68k::new(code);
+        let labels: Vec<&str> = s68k
+            .get_lexed_lines()
+            .iter()
+            .filter_map(|line| match &line.parsed {
+                LexedLine::Label { name, .. } => Some(name.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(labels, vec!["START", "LOOP", "RESULT", "MESSAGE"]);
+        assert!(s68k.get_diagnostics().is_empty(), "{:?}", s68k.get_diagnostics());
+        let add = &s68k.get_lexed_lines().iter().find(|line| line.line_index == 8 && !matches!(line.parsed, LexedLine::Label { .. })).unwrap();
+        assert_eq!((add.code_span.start, add.code_span.end), (8, 21));
+        let report = crate::grading::Grader::new().expect_output("Done").grade(code);
+        assert!(report.passed, "{:?}", report);
+        let compiled = s68k.compile().unwrap();
+        let result = compiled.get_labels_map().get("RESULT").unwrap().address;
+        let mut interpreter = s68k.create_interpreter(compiled, None);
+        interpreter.run().unwrap();
+        assert_eq!(interpreter.get_memory().read_long(result).unwrap(), 15);
+        let nop = S68k::new("    nop").assemble().unwrap().into_machine_code();
+        assert_eq!(nop.bytes, vec![0x4E, 0x71]);
+        //an instruction in the first column is still an instruction
+        assert!(S68k::new("move.l #1, d0\nnop").get_lexed_lines().iter().all(|line| !matches!(line.parsed, LexedLine::Label { .. })));
+    }
+
+    #[test]
+    fn differential_testing_finds_the_first_divergence_from_the_reference() {
+        use crate::differential::{Divergence, DifferentialTester, MemoryRegion};
+        use crate::grading::RunOutcome;
+        use crate::instructions::RegisterOperand;
+        //reads two numbers and stores their absolute difference
+        let reference = "    move.l #4, d0
+    trap #15
+    move.l d1, d2
+    trap #15
+    sub.l d1, d2
+    bpl positive
+    neg.l d2
+positive:
+    move.l d2, result
+    move.l d2, d1
+    move.l #3, d0
+    trap #15
+    move.l #9, d0
+    trap #15
+result: dc.l 0";
+        //forgets the negative case and keeps the result in another register
+        let student = "    move.l #4, d0
+    trap #15
+    move.l d1, d3
+    trap #15
+    sub.l d1, d3
+    move.l d3, d1
+    move.l #3, d0
+    trap #15
+    move.l d3, diff
+    move.l #9, d0
+    trap #15
+    dc.w 0
+diff: dc.l 0";
+        let tester = DifferentialTester::new(reference)
+            .with_input("7\n3")
+            .with_input("3\n7")
+            .compare_register(RegisterOperand::Data(0));
+        let report = tester.clone().compare(student).unwrap();
+        assert!(!report.passed);
+        assert_eq!(report.cases[0].divergence, None);
+        //the number is shown signed like Easy68K, the minus is the first difference
+        assert_eq!(report.cases[1].divergence, Some(Divergence::Output { position: 0, line: 0 }));
+        assert_eq!(report.cases[1].student_output, "-4");
+        let fixed = student.replace("    move.l d3, d1", "    bpl positive\n    neg.l d3\npositive:\n    move.l d3, d1");
+        let report = tester.clone().compare_label("result", 4).compare(&fixed).unwrap();
+        assert_eq!(report.cases[0].divergence, Some(Divergence::MissingLabel("result".to_string())));
+        let report = tester.clone().compare_register(RegisterOperand::Data(2)).compare(&fixed).unwrap();
+        assert_eq!(
+            report.cases[0].divergence.as_ref().unwrap().get_message(),
+            "d2 is $00000000, the reference has $00000004"
+        );
+        let fixed = fixed.replace("diff", "result");
+        let report = tester.clone().compare_label("result", 4).compare(&fixed).unwrap();
+        assert!(report.passed, "{:?}", report.get_divergent_cases());
+        let report = tester
+            .clone()
+            .compare_label("result", 4)
+            .compare(&fixed.replace("move.l d3, result", "move.w d3, result"))
+            .unwrap();
+        assert_eq!(
+            report.cases[0].divergence,
+            Some(Divergence::Memory {
+                region: MemoryRegion::Label {
+                    name: "result".to_string(),
+                    length: 4
+                },
+                offset: 1,
+                student: Some(4),
+                reference: Some(0)
+            })
+        );
+        let report = tester.clone().compare("    move.l d0").unwrap();
+        assert!(matches!(
+            &report.cases[0].divergence,
+            Some(Divergence::Outcome { student: RunOutcome::AssemblyFailed(_), reference: RunOutcome::Terminated })
+        ));
+        assert!(DifferentialTester::new("    move.l d0").compare(student).is_err());
+    }
+
+    #[test]
+    fn sandbox_limits_stop_the_program_with_their_reason() {
+        use crate::grading::{Grader, RunOutcome};
+        use crate::instructions::{Interrupt, InterruptResult, RegisterOperand};
+        use crate::interpreter::RuntimeError;
+        use crate::sandbox::{SandboxConfig, SandboxLimit};
+        let run = |code: &str, sandbox: SandboxConfig| {
+            let s68k = S68k::new(code);
+            let options = InterpreterOptions {
+                sandbox,
+                ..Default::default()
+            };
+            let mut interpreter = s68k.create_interpreter(s68k.compile().unwrap(), Some(options));
+            let result = loop {
+                match interpreter.get_status() {
+                    InterpreterStatus::Running => match interpreter.step() {
+                        Ok(_) => {}
+                        Err(e) => break Err(e),
+                    },
+                    InterpreterStatus::Interrupt => {
+                        let result = match interpreter.get_current_interrupt().unwrap() {
+                            Interrupt::DisplayStringWithoutCRLF(_) => InterruptResult::DisplayStringWithoutCRLF,
+                            _ => InterruptResult::DisplayChar,
+                        };
+                        interpreter.answer_interrupt(result).unwrap();
+                    }
+                    status => break Ok(*status),
+                }
+            };
+            (result, interpreter)
+        };
+        let spin = "loop: bra loop";
+        let (result, interpreter) = run(spin, SandboxConfig::new().with_max_instructions(50));
+        assert!(matches!(result, Err(RuntimeError::SandboxLimit(SandboxLimit::Instructions(50)))));
+        assert_eq!(interpreter.get_executed_instructions(), 50);
+        assert_eq!(*interpreter.get_status(), InterpreterStatus::TerminatedWithException);
+        assert_eq!(interpreter.get_sandbox_stop(), Some(SandboxLimit::Instructions(50)));
+        let (result, interpreter) = run(spin, SandboxConfig::new().with_max_cycles(100));
+        assert!(matches!(result, Err(RuntimeError::SandboxLimit(SandboxLimit::Cycles(100)))));
+        assert!(interpreter.get_cycles() >= 100);
+        let (result, _) = run(spin, SandboxConfig::new().with_max_wall_time_ms(20));
+        assert!(matches!(result, Err(RuntimeError::SandboxLimit(SandboxLimit::WallTime(20)))));
+        let print = "    lea text, a1
+    move.l #14, d0
+loop:
+    trap #15
+    bra loop
+text: dc.b 'hello', 0";
+        let (result, _) = run(print, SandboxConfig::new().with_max_output_bytes(12));
+        assert!(matches!(result, Err(RuntimeError::SandboxLimit(SandboxLimit::Output(12)))));
+        let (result, _) = run(print, SandboxConfig::new().with_max_traps(4));
+        assert!(matches!(result, Err(RuntimeError::SandboxLimit(SandboxLimit::Traps(4)))));
+        //the stack starts at the end of the memory
+        let (result, interpreter) = run("    move.l #1, -(sp)", SandboxConfig::new().with_memory_size(0x2000));
+        assert!(result.is_ok());
+        assert_eq!(interpreter.get_register_value(&RegisterOperand::Address(7), Size::Long), 0x1FFC);
+        let (result, interpreter) = run("    org $4000\ndata: dc.l 1", SandboxConfig::new().with_memory_size(0x2000));
+        assert_eq!(result.unwrap(), InterpreterStatus::TerminatedWithException);
+        assert_eq!(interpreter.get_sandbox_stop(), Some(SandboxLimit::Memory(0x2000)));
+        let report = Grader::new().with_sandbox(SandboxConfig::untrusted().with_max_instructions(10)).grade(spin);
+        assert_eq!(
+            report.outcome,
+            RunOutcome::RuntimeError("Sandbox limit of 10 instructions reached".to_string())
+        );
+    }
+
+    #[test]
+    fn explain_mode_narrates_each_step() {
+        use crate::explain::explain_step;
+        let code = "    lea data, a1
+    move.l #4, d0
+    move.l d0, (a1)+
+    cmp.l #4, d0
+    bne skip
+    beq done
+skip:
+    clr.l d0
+done:
+    move.l #0, d1
+data: dc.l 0";
+        let s68k = S68k::new(code);
+        let mut interpreter = s68k.create_interpreter(s68k.compile().unwrap(), None);
+        let mut texts = vec![];
+        while interpreter.get_status() == &InterpreterStatus::Running {
+            let explanation = explain_step(&mut interpreter).unwrap();
+            texts.push((explanation.line_index, explanation.text));
+        }
+        assert_eq!(texts[0].0, 0);
+        assert!(texts[0].1.contains("data"));
+        assert!(texts[2]
+            .1
+            .starts_with("moved the long word 4 from D0 into the address held in A1, then incremented A1 by 4"));
+        assert!(texts[3].1.contains("Z set"));
+        assert_eq!(texts[4].1, "didn't jump to skip because the condition not equal was false");
+        assert_eq!(texts[5].1, "jumped to done because the condition equal was true");
+        assert_eq!(texts.len(), 7);
+    }
+
+    #[test]
+    fn stack_view_describes_linked_frames() {
+        use crate::instructions::RegisterOperand;
+        use crate::stack_frames::get_stack_view;
+        let code = "    move.l #7, -(sp)
+    bsr sum
+    addq.l #4, sp
+    move.l #9, d0
+    trap #15
+sum:
+    link a6, #-8
+    movem.l d2-d3, -(sp)
+    move.l arg(a6), d2
+    move.l d2, total(a6)
+    move.w #1, flag(a6)
+    bsr inner
+    movem.l (sp)+, d2-d3
+    unlk a6
+    rts
+inner:
+    move.l d0, d0
+    rts
+arg equ 8
+total equ -4
+flag equ -6";
+        let s68k = S68k::new(code);
+        let mut interpreter = s68k.create_interpreter(s68k.compile().unwrap(), None);
+        while interpreter.get_call_depth() < 2 {
+            interpreter.step().unwrap();
+        }
+        let view = get_stack_view(&interpreter);
+        let top = view.stack_top;
+        assert_eq!(view.frames.len(), 2);
+        let sum = &view.frames[0];
+        assert_eq!(sum.function, "sum");
+        assert_eq!(sum.return_address_slot, Some(top - 8));
+        assert_eq!(sum.frame_register, Some(RegisterOperand::Address(6)));
+        assert_eq!(sum.frame_pointer, Some(top - 12));
+        assert_eq!(sum.locals_size, 8);
+        let saved: Vec<(RegisterOperand, usize, u32)> = sum
+            .saved_registers
+            .iter()
+            .map(|saved| (saved.register, saved.address, saved.value))
+            .collect();
+        assert_eq!(
+            saved,
+            vec![
+                (RegisterOperand::Data(2), top - 28, 0),
+                (RegisterOperand::Data(3), top - 24, 0)
+            ]
+        );
+        let locals: Vec<(Option<&str>, i32, usize, Option<u32>)> = sum
+            .locals
+            .iter()
+            .map(|local| (local.name.as_deref(), local.offset, local.size, local.value))
+            .collect();
+        assert_eq!(locals, vec![(Some("flag"), -6, 2, Some(1)), (Some("total"), -4, 4, Some(7))]);
+        assert_eq!(sum.arguments.len(), 1);
+        assert_eq!(sum.arguments[0].name.as_deref(), Some("arg"));
+        assert_eq!(sum.arguments[0].value, Some(7));
+        let inner = &view.frames[1];
+        assert_eq!(inner.function, "inner");
+        assert_eq!(inner.return_address_slot, Some(top - 32));
+        assert_eq!(sum.bottom, inner.top);
+        assert_eq!(inner.bottom, view.stack_pointer);
+        assert!(inner.frame_pointer.is_none());
+    }
+
+    #[test]
+    fn trace_recorder_exports_steps_as_csv_and_json() {
+        use crate::trace::TraceRecorder;
+        let code = "    move.l #$12345678, d0
+    lea value, a0
+    move.w d0, (a0)
+value: dc.w 0";
+        let s68k = S68k::new(code);
+        let options = InterpreterOptions {
+            keep_history: true,
+            history_size: 1,
+            ..Default::default()
+        };
+        let mut interpreter = s68k.create_interpreter(s68k.compile().unwrap(), Some(options));
+        let mut trace = TraceRecorder::new();
+        while interpreter.get_status() == &InterpreterStatus::Running {
+            trace.record_step(&mut interpreter).unwrap();
+        }
+        let steps = trace.get_steps();
+        assert_eq!(steps.len(), 3);
+        assert_eq!(steps[0].registers[0], 0x12345678);
+        assert!(steps[0].memory_writes.is_empty());
+        let value = steps[1].registers[8] as usize;
+        assert_eq!(steps[2].line_index, 2);
+        assert_eq!(steps[2].memory_writes.len(), 1);
+        assert_eq!(steps[2].memory_writes[0].address, value);
+        assert_eq!(steps[2].memory_writes[0].bytes, vec![0x56, 0x78]);
+        let csv = trace.to_csv();
+        let rows: Vec<&str> = csv.lines().collect();
+        assert_eq!(rows.len(), 4);
+        assert!(rows[0].starts_with("step,pc,line,instruction,d0,d1"));
+        assert!(rows[0].ends_with("a7,ccr,cycles,writes"));
+        assert!(rows[1].starts_with("0,"));
+        assert!(rows[1].contains("\"move.l #$12345678, d0\",12345678,"));
+        assert!(rows[3].ends_with(&format!(",{:X}:5678", value)));
+        #[cfg(feature = "serialize")]
+        {
+            let json: serde_json::Value = serde_json::from_str(&trace.to_json()).unwrap();
+            assert_eq!(json[2]["memory_writes"][0]["bytes"], serde_json::json!([0x56, 0x78]));
+            assert_eq!(json[0]["instruction"], "move.l #$12345678, d0");
+        }
+    }
+
+    #[test]
+    fn input_generator_is_reproducible_from_the_seed() {
+        use crate::differential::DifferentialTester;
+        use crate::input_generator::InputGenerator;
+        let generator = InputGenerator::new(42)
+            .with_number(-5, 5)
+            .with_text("id_[a-c]{2}\\d?x+")
+            .with_choice(&["yes", "no"])
+            .with_list(1, 3, 0, 9);
+        let first = generator.generate_lines(7).unwrap();
+        assert_eq!(first, generator.generate_lines(7).unwrap());
+        let cases: Vec<Vec<String>> = (0..50).map(|case| generator.generate_lines(case).unwrap()).collect();
+        assert!(cases.iter().any(|lines| lines != &first));
+        for lines in &cases {
+            let number: i32 = lines[0].parse().unwrap();
+            assert!((-5..=5).contains(&number));
+            let text = lines[1].as_bytes();
+            assert!(lines[1].starts_with("id_"));
+            assert!(text[3..5].iter().all(|c| (b'a'..=b'c').contains(c)));
+            assert!(lines[1][5..].trim_start_matches(|c: char| c.is_ascii_digit()).chars().all(|c| c == 'x'));
+            assert!(lines[1].ends_with('x'));
+            assert!(lines[2] == "yes" || lines[2] == "no");
+            let length: usize = lines[3].parse().unwrap();
+            assert!((1..=3).contains(&length));
+            assert_eq!(lines.len(), 4 + length);
+        }
+        let numbers = |seed| InputGenerator::new(seed).with_number(0, 1000).generate_lines(0).unwrap();
+        assert_ne!(numbers(1), numbers(2));
+        assert!(InputGenerator::new(0).with_text("[a-").validate().is_err());
+        assert!(InputGenerator::new(0).with_number(3, 1).generate(0).is_err());
+        //the same program compared with itself on generated cases
+        let code = "    move.l #4, d0
+    trap #15
+    add.l d1, d1
+    move.l #3, d0
+    trap #15";
+        let report = DifferentialTester::new(code)
+            .with_generated_inputs(&InputGenerator::new(7).with_number(-1000, 1000), 20)
+            .unwrap()
+            .compare(code)
+            .unwrap();
+        assert!(report.passed);
+        assert_eq!(report.cases.len(), 20);
+    }
+
+    #[test]
+    fn similarity_ignores_renaming_and_comments() {
+        use crate::similarity::{compare_programs, get_similarity_matrix};
+        let original = "    move.l #10, d0
+    clr.l d1
+loop:
+    add.l d0, d1
+    subq.l #1, d0
+    bne loop
+    move.l d1, result
+result: dc.l 0";
+        //the same program with other names, registers and comments
+        let renamed_code = "; sum of the numbers up to 10
+    move.l #10, d3 ; counter
+    clr.l d5
+
+again:
+    add.l d3, d5
+    subq.l #1, d3
+    bne again
+    move.l d5, total
+total: dc.l 0";
+        let different = "    lea table, a0
+    move.b (a0)+, d0
+    move.b (a0)+, d1
+    cmp.b d0, d1
+    bgt done
+    exg d0, d1
+done:
+    rts
+table: dc.b 1, 2";
+        let lines = |code: &str| S68k::new(code).get_lexed_lines().clone();
+        let (original, renamed, different) = (lines(original), lines(renamed_code), lines(different));
+        let report = compare_programs(&original, &renamed);
+        assert_eq!(report.score, 1.0);
+        assert_eq!(report.matched_lines.len(), 9);
+        assert_eq!(report.matched_lines[2], (2, 4));
+        //swapping the registers of an instruction is a real change
+        let swapped = lines(&renamed_code.replace("add.l d3, d5", "add.l d5, d3"));
+        let report = compare_programs(&original, &swapped);
+        assert!(report.score < 1.0 && report.score > 0.6);
+        let report = compare_programs(&original, &different);
+        assert!(report.score < 0.4);
+        let matrix = get_similarity_matrix(&[&original, &renamed, &different]);
+        assert_eq!(matrix[0][0], 1.0);
+        assert_eq!(matrix[0][1], matrix[1][0]);
+        assert!(matrix[0][1] > matrix[0][2]);
+        assert_eq!(compare_programs(&[], &[]).score, 1.0);
+        assert_eq!(compare_programs(&original, &[]).score, 0.0);
+    }
+
+    #[test]
+    fn scaffold_regions_are_checked_and_write_protected() {
+        use crate::grading::{Grader, RunOutcome};
+        use crate::instructions::RegisterOperand;
+        use crate::scaffold::{get_scaffold_regions, ScaffoldRegion};
+        let template = "    ;write your code here
+    move.l #9, d0
+    trap #15
+;PROTECTED given by the instructor
+table: dc.w 1, 2, 3
+;END_PROTECTED";
+        assert_eq!(
+            get_scaffold_regions(template).unwrap(),
+            vec![ScaffoldRegion { start_line: 4, end_line: 5 }]
+        );
+        assert!(get_scaffold_regions(";PROTECTED\n move.l d0, d1").is_err());
+        let grader = Grader::new().with_scaffold(template).expect_register(RegisterOperand::Data(1), 6);
+        let solution = "    lea table, a0
+    move.w (a0)+, d1
+    add.w (a0)+, d1
+    add.w (a0)+, d1
+    move.l #9, d0
+    trap #15
+;protected, with other spacing and comments
+table:   dc.w 1,2,3 ; the values
+;end_protected";
+        let report = grader.grade(solution);
+        assert!(report.passed, "{:?}", report);
+        //the data of the scaffold changed in the source
+        let report = grader.grade(&solution.replace("dc.w 1,2,3", "dc.w 6,0,0"));
+        assert!(!report.passed);
+        assert_eq!(report.scaffold_violations.len(), 1);
+        assert_eq!(report.scaffold_violations[0].line_index, Some(7));
+        assert!(report.scaffold_violations[0].message.contains("was changed"));
+        //or at runtime
+        let cheating = solution.replace("    lea table, a0", "    lea table, a0\n    move.w #6, (a0)");
+        let report = grader.grade(&cheating);
+        assert!(report.scaffold_violations.is_empty());
+        match report.outcome {
+            RunOutcome::RuntimeError(message) => assert!(message.starts_with("Write to protected memory")),
+            outcome => panic!("Expected a protected write, got {:?}", outcome),
+        }
+        let report = grader.grade(&solution.replace(";end_protected", ""));
+        assert!(!report.passed);
+    }
+
+    #[test]
+    fn flag_explanation_matches_the_interpreter() {
+        use crate::flag_explanation::{explain_flags, explain_step_flags, FlagOperation};
+        use crate::grading::ConditionFlag;
+        use crate::interpreter::Flags;
+        let explanation = explain_flags(FlagOperation::Add, 1, 0x7F, Size::Byte, Flags::empty());
+        assert_eq!(explanation.result, 0x80);
+        let overflow = explanation.get_flag(ConditionFlag::Overflow).unwrap();
+        assert!(overflow.value && overflow.changed);
+        assert_eq!(
+            overflow.reason,
+            "V set because adding two positives (127 and 1) produced a negative (-128)"
+        );
+        assert!(!explanation.get_flag(ConditionFlag::Carry).unwrap().value);
+        let code = "    move.b #$7F, d0
+    add.b #1, d0
+    sub.w #5, d1
+    cmp.l #3, d1
+    neg.b d0
+    and.w #0, d0
+    moveq #-1, d2
+    tst.l d2
+    clr.b d2
+    lea $1000, a0";
+        let s68k = S68k::new(code);
+        let mut interpreter = s68k.create_interpreter(s68k.compile().unwrap(), None);
+        let mut explained = 0;
+        while interpreter.get_status() == &InterpreterStatus::Running {
+            let Some(explanation) = explain_step_flags(&mut interpreter).unwrap() else {
+                continue;
+            };
+            let flags = interpreter.get_cpu().wasm_get_ccr();
+            for reason in &explanation.flags {
+                assert_eq!(reason.value, flags.contains(reason.flag.to_flags()), "{}", reason.reason);
+            }
+            explained += 1;
+        }
+        assert_eq!(explained, 9);
+    }
+
+    #[test]
+    fn lesson_profile_restricts_the_instructions() {
+        use crate::instructions::RegisterOperand;
+        use crate::interpreter::RuntimeError;
+        use crate::lesson_profile::LessonProfile;
+        let profile: LessonProfile = "week 3: move, add, sub, branches".parse().unwrap();
+        assert_eq!(profile.name, "week 3");
+        assert!(profile.is_allowed("BEQ") && profile.is_allowed("bra") && !profile.is_allowed("dbra"));
+        assert!("week 1: move, multiply".parse::<LessonProfile>().is_err());
+        let code = "    move.l #3, d0
+    add.l d0, d0
+    muls #2, d0
+    beq done
+done:
+    dc.l 0";
+        let mut s68k = S68k::new(code);
+        assert!(s68k.semantic_check().is_empty());
+        s68k.set_lesson_profile(Some(profile.clone()));
+        let errors = s68k.semantic_check();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].get_message(),
+            "Error on line 3: Instruction \"muls\" is not allowed in week 3, the allowed instructions are move, add, sub, branches"
+        );
+        let mut interpreter = s68k.create_interpreter(s68k.compile().unwrap(), None);
+        interpreter.step().unwrap();
+        interpreter.step().unwrap();
+        assert!(matches!(interpreter.step(), Err(RuntimeError::UnsupportedInstruction(_))));
+        assert_eq!(interpreter.get_register_value(&RegisterOperand::Data(0), Size::Long), 6);
+    }
+
+    #[test]
+    fn quiz_distractors_come_from_common_mistakes() {
+        use crate::instructions::RegisterOperand;
+        use crate::quiz::generate_quiz;
+        let snippet = "    move.w #-2, d2
+    move.b #10, d1
+    add.b d1, d2";
+        let quiz = generate_quiz(snippet, RegisterOperand::Data(2)).unwrap();
+        assert_eq!(quiz.question, "What is the value of D2 after the code runs?");
+        assert_eq!(quiz.answer, 0xFF08);
+        let distractors: Vec<(u32, &str)> = quiz.distractors.iter().map(|d| (d.value, d.mistake.as_str())).collect();
+        assert_eq!(
+            distractors,
+            vec![
+                (8, "ignored the .b and .w sizes and worked on long words"),
+                (0xFF0E, "read decimal immediates like #10 as hexadecimal"),
+                (0xFFFFFF08, "sign extended a value that is not"),
+            ]
+        );
+        assert_eq!(quiz.get_choices(), vec![8, 0xFF08, 0xFF0E, 0xFFFFFF08]);
+        let quiz = generate_quiz("    moveq #5, d0\n    moveq #2, d1\n    sub.l d1, d0", RegisterOperand::Data(0)).unwrap();
+        assert_eq!(quiz.answer, 3);
+        assert_eq!(quiz.distractors[0].value, (-3i32) as u32);
+        assert!(generate_quiz("    bra.s nowhere", RegisterOperand::Data(0)).is_err());
+    }
+
+    #[test]
+    fn cost_report_annotates_lines_and_subroutines() {
+        use crate::cost_report::get_cost_report;
+        let code = "    move.l #5, d0
+    bsr sum
+    bra done
+sum:
+    move.w $1000, d1
+    dbra d0, sum
+    rts
+done:
+    dc.w 1, 2";
+        let s68k = S68k::new(code);
+        let report = get_cost_report(&s68k, &s68k.compile().unwrap());
+        let bytes: Vec<(usize, usize)> = report.lines.iter().map(|line| (line.line_index, line.bytes)).collect();
+        assert_eq!(bytes, vec![(0, 6), (1, 4), (2, 4), (4, 4), (5, 4), (6, 2), (8, 4)]);
+        assert_eq!(report.total_bytes, 28);
+        let dbra = report.get_line(5).unwrap();
+        assert!(dbra.best_cycles < dbra.worst_cycles);
+        let names: Vec<(Option<&str>, usize)> = report
+            .subroutines
+            .iter()
+            .map(|subroutine| (subroutine.name.as_deref(), subroutine.bytes))
+            .collect();
+        assert_eq!(names, vec![(None, 14), (Some("sum"), 10), (Some("done"), 4)]);
+        assert!(report.to_text(code).contains("     4   10-14      dbra d0, sum"));
+    }
+
+    #[test]
+    fn cost_report_counts_labelled_lines_once_and_every_copy() {
+        use crate::cost_report::get_cost_report;
+        let report = |code: &str| {
+            let s68k = S68k::new(code);
+            get_cost_report(&s68k, &s68k.compile().unwrap())
+        };
+        let labelled = report("main: moveq #1, d0\n    rts");
+        assert_eq!(labelled.total_bytes, 4);
+        assert_eq!(labelled.get_line(0).map(|line| line.bytes), Some(2));
+        assert_eq!(labelled.subroutines[0].bytes, 4);
+        assert_eq!((labelled.subroutines[0].best_cycles, labelled.subroutines[0].worst_cycles), (20, 20));
+        let data = report("msg: dc.b 'Hello', 0");
+        assert_eq!(data.total_bytes, 6);
+        assert_eq!(data.subroutines[0].bytes, 6);
+        let repeated = report("copy:\n    rept 3\n    move.l (a0)+, (a1)+\n    endr\n    rts");
+        assert_eq!(repeated.get_line(2).map(|line| (line.bytes, line.best_cycles)), Some((6, 60)));
+        assert_eq!(repeated.total_bytes, 8);
+        let copy = &repeated.subroutines[0];
+        assert_eq!((copy.bytes, copy.best_cycles, copy.worst_cycles), (8, 76, 76));
+    }
+
+    #[test]
+    fn lexer_tracks_spans_of_lines_and_operands() {
+        use crate::lexer::TextSpan;
+        let s68k = S68k::new("loop: move.l #1, (a0, d1) ; comment\n    dc.b 'a, b', 2\n  dc.b 'é', 1");
+        let lines = s68k.get_lexed_lines();
+        let span = |start, end| TextSpan { line_index: 0, start, end };
+        assert_eq!(lines[0].code_span, span(0, 4));
+        assert_eq!(lines[1].code_span, span(6, 25));
+        assert_eq!(lines[1].operand_spans, vec![span(13, 15), span(17, 25)]);
+        assert_eq!(lines[1].get_operand_span(1), Some(span(17, 25)));
+        let directive = TextSpan { line_index: 1, start: 4, end: 18 };
+        assert_eq!(lines[2].code_span, directive);
+        let args: Vec<(usize, usize)> = lines[2].operand_spans.iter().map(|s| (s.start, s.end)).collect();
+        assert_eq!(args, vec![(4, 8), (9, 15), (17, 18)]);
+        //columns count UTF-16 code units, not bytes
+        assert_eq!(lines[3].get_operand_span(2), Some(TextSpan { line_index: 2, start: 12, end: 13 }));
+    }
+
+    #[test]
+    fn lexer_reports_malformed_operands() {
+        use crate::lexer::{LexError, LexedOperand, Lexer};
+        let mut lexer = Lexer::new();
+        assert_eq!(lexer.parse_operand("d0*300"), Err(LexError::InvalidScale("d0*300".to_string())));
+        assert!(matches!(lexer.parse_operand("d0-a2"), Err(LexError::InvalidRegisterRange(_))));
+        let code = "start: move.l d0, d1\n    movem.l d0-a2, -(sp)";
+        match lexer.lex(code) {
+            Err(LexError::OnLine { span, error }) => {
+                assert_eq!((span.line_index, span.start, span.end), (1, 12, 17));
+                assert_eq!(*error, LexError::InvalidRegisterRange("d0-a2".to_string()));
+            }
+            result => panic!("Expected an error, got {:?}", result),
+        }
+        let lines = lexer.lex_lossy(code);
+        assert!(matches!(&lines[2].parsed, crate::lexer::LexedLine::Instruction { operands, .. }
+            if operands[0] == LexedOperand::Other("d0-a2".to_string())));
+        assert!(!S68k::new(code).semantic_check().is_empty());
+    }
+
+    #[test]
+    fn recovering_lexer_collects_every_diagnostic() {
+        use crate::lexer::{LexError, LexedLine, Lexer};
+        use crate::semantic_checker::SemanticChecker;
+        let code = "start: movem.l d0-a2, -(sp)\n    move.l d0, d1\n    lea (d0*300, a0), a1";
+        let mut lexer = Lexer::new().with_recovery();
+        let lines = lexer.lex(code).unwrap().clone();
+        assert!(matches!(&lines[0].parsed, LexedLine::Label { name, .. } if name == "start"));
+        match &lines[1].parsed {
+            LexedLine::Error { content, diagnostics } => {
+                assert_eq!(content, "movem.l d0-a2, -(sp)");
+                assert_eq!(diagnostics.len(), 1);
+            }
+            line => panic!("Expected an error line, got {:?}", line),
+        }
+        assert!(matches!(lines[2].parsed, LexedLine::Instruction { .. }));
+        assert!(matches!(lines[3].parsed, LexedLine::Error { .. }));
+        let diagnostics = lexer.diagnostics();
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[1].get_span().map(|s| s.line_index), Some(2));
+        assert_eq!(diagnostics[1].get_cause(), &LexError::InvalidScale("d0*300".to_string()));
+        let errors = SemanticChecker::new(&lines, Default::default()).get_errors();
+        assert_eq!(errors.len(), 2);
+        //without recovery the first problem aborts, but all of them are still collected
+        let mut lexer = Lexer::new();
+        assert!(lexer.lex(code).is_err());
+        assert_eq!(lexer.diagnostics().len(), 2);
+    }
+
+    #[test]
+    fn expressions_are_evaluated_with_equs_and_labels() {
+        use crate::expression::{evaluate, evaluate_constant, ExpressionError};
+        use crate::instructions::RegisterOperand;
+        use crate::lexer::LexedLine;
+        assert_eq!(evaluate_constant("#(4*2)+1"), Ok(9));
+        assert_eq!(evaluate_constant("1<<3 | 1"), Ok(9));
+        assert_eq!(evaluate_constant("~$0F & $FF"), Ok(0xF0));
+        assert_eq!(evaluate_constant("2**3**2"), Ok(512));
+        assert_eq!(evaluate_constant("%101 + @17 - 'A'"), Ok(5 + 15 - 65));
+        assert_eq!(evaluate_constant("7 \\ 0"), Err(ExpressionError::DivisionByZero));
+        assert_eq!(evaluate_constant("(1"), Err(ExpressionError::UnexpectedEnd));
+        assert_eq!(evaluate("MASK + 1", &|s| (s == "MASK").then_some(2)), Ok(3));
+        let code = "BUFLEN equ 4+1
+MASK equ $0F
+    move.l #(BUFLEN*2)+1, d0
+    move.l #~MASK & $FF, d1
+    move.l #end-start, d2
+    bra end
+start:
+    dc.w 1<<3
+end:";
+        let s68k = S68k::new(code);
+        match &s68k.get_lexed_lines()[2].parsed {
+            LexedLine::Instruction { operands, .. } => assert_eq!(operands[0].get_immediate_value(), Some(11)),
+            line => panic!("Expected an instruction, got {:?}", line),
+        }
+        let interpreter = lex_and_run(code);
+        let value = |register| interpreter.get_register_value(&RegisterOperand::Data(register), Size::Long);
+        assert_eq!((value(0), value(1), value(2)), (11, 0xF0, 2));
+    }
+
+    #[test]
+    fn literals_are_parsed_into_typed_values() {
+        use crate::expression::{parse_literal, ExpressionError, Literal, LiteralBase};
+        use crate::lexer::LexedOperand;
+        let literal = |base, value| Ok(Literal { base, value });
+        assert_eq!(parse_literal("$FF"), literal(LiteralBase::Hexadecimal, 255));
+        assert_eq!(parse_literal("%1010"), literal(LiteralBase::Binary, 10));
+        assert_eq!(parse_literal("@17"), literal(LiteralBase::Octal, 15));
+        assert_eq!(parse_literal("'A'"), literal(LiteralBase::Character, 65));
+        assert_eq!(parse_literal("$FFFFFFFF"), literal(LiteralBase::Hexadecimal, 0xFFFFFFFF));
+        assert_eq!(parse_literal("$100000000"), Err(ExpressionError::Overflow("$100000000".to_string())));
+        assert_eq!(parse_literal("'abcde'"), Err(ExpressionError::Overflow("'abcde'".to_string())));
+        assert_eq!(parse_literal("%102"), Err(ExpressionError::InvalidNumber("%102".to_string())));
+        assert_eq!(LexedOperand::Immediate("#@17".to_string()).value(), Some(Ok(15)));
+        assert_eq!(LexedOperand::Absolute("$1000".to_string()).value(), Some(Ok(0x1000)));
+        assert_eq!(LexedOperand::Immediate("#$FF+1".to_string()).value(), None);
+        assert_eq!(LexedOperand::Absolute("start".to_string()).value(), None);
+        let errors = S68k::new("    move.l #$1FFFFFFFF, d0").semantic_check();
+        assert!(errors[0].get_message().contains("doesn't fit in 32 bits"));
+    }
+
+    #[test]
+    fn register_lists_round_trip_and_run() {
+        use crate::instructions::{Instruction, Operand, RegisterOperand};
+        use crate::lexer::{LexError, LexedOperand, Lexer};
+        use crate::printer::print_operand;
+        let lexer = Lexer::new();
+        let operands = lexer.parse_operands(&["D0-D3/A0-A2".to_string(), "-(sp)".to_string()]).unwrap();
+        assert_eq!(operands[0], LexedOperand::RegisterRange { mask: 0x070F });
+        let printed: Vec<String> = operands.iter().map(print_operand).collect();
+        assert_eq!(printed, vec!["d0-d3/a0-a2", "-(sp)"]);
+        assert_eq!(lexer.parse_operands(&printed).unwrap(), operands);
+        assert_eq!(lexer.parse_operand("a6-sp"), Ok(LexedOperand::RegisterRange { mask: 0xC000 }));
+        assert_eq!(lexer.parse_operand("d3-d1"), Err(LexError::InvalidRegisterRange("d3-d1".to_string())));
+        let code = "    move.l #1, d0
+    move.l #2, d3
+    move.l #3, a2
+    movem.l d0-d3/a0-a2,-(sp)
+    clr.l d0
+    clr.l d3
+    movem.l (sp)+, d0-d3/a0-a2";
+        let s68k = S68k::new(code);
+        let compiled = s68k.compile().unwrap();
+        assert!(compiled.get_instructions().iter().any(|line| matches!(
+            line.instruction,
+            Instruction::MOVEM { target: Operand::PostIndirect(7), registers_mask: 0x070F, .. }
+        )));
+        let interpreter = lex_and_run(code);
+        let value = |register| interpreter.get_register_value(&register, Size::Long);
+        assert_eq!(value(RegisterOperand::Data(0)), 1);
+        assert_eq!(value(RegisterOperand::Data(3)), 2);
+        assert_eq!(value(RegisterOperand::Address(2)), 3);
+    }
+
+    #[test]
+    fn local_labels_are_scoped_to_their_global_label() {
+        use crate::lexer::{LexedLine, LexedOperand};
+        use crate::instructions::RegisterOperand;
+        use crate::local_labels::{get_label_key, is_local_label};
+        assert!(is_local_label(".loop") && is_local_label("12$"));
+        assert!(!is_local_label("loop") && !is_local_label(".5") && !is_local_label("$10"));
+        assert_eq!(get_label_key(".loop", Some("main")), "main.loop");
+        assert_eq!(get_label_key("1$", Some("main")), "main.1$");
+        assert_eq!(get_label_key("1$", None), "1$");
+        let code = "    move.l #0, d0
+    bsr first
+    bsr second
+    bra done
+first:
+    move.l #3, d1
+.loop:
+    add.l #1, d0
+    sub.l #1, d1
+    bne .loop
+    move.l #1, d1
+1$: add.l #1000, d0
+    sub.l #1, d1
+    bne 1$
+    rts
+second:
+    move.l #2, d1
+.loop:
+    add.l #10, d0
+    sub.l #1, d1
+    bne .loop
+    move.l #2, d1
+1$: add.l #100, d0
+    sub.l #1, d1
+    bne 1$
+    lea .loop, a0
+    rts
+done:";
+        let s68k = S68k::new(code);
+        let labels: Vec<&LexedLine> = s68k
+            .get_lexed_lines()
+            .iter()
+            .map(|line| &line.parsed)
+            .filter(|line| matches!(line, LexedLine::Label { .. }))
+            .collect();
+        assert_eq!(
+            labels[1],
+            &LexedLine::Label { name: ".loop".to_string(), local: true, owner: Some("first".to_string()) }
+        );
+        assert_eq!(
+            labels[5],
+            &LexedLine::Label { name: "1$".to_string(), local: true, owner: Some("second".to_string()) }
+        );
+        assert!(s68k.get_lexed_lines().iter().any(|line| matches!(
+            &line.parsed,
+            LexedLine::Instruction { operands, .. } if operands.first() == Some(&LexedOperand::Absolute("second.1$".to_string()))
+        )));
+        let compiled = s68k.compile().unwrap();
+        assert_ne!(compiled.get_labels_map()["first.loop"].address, compiled.get_labels_map()["second.loop"].address);
+        let interpreter = lex_and_run(code);
+        assert_eq!(interpreter.get_register_value(&RegisterOperand::Data(0), Size::Long), 1223);
+        assert_eq!(
+            interpreter.get_register_value(&RegisterOperand::Address(0), Size::Long),
+            compiled.get_labels_map()["second.loop"].address as u32
+        );
+    }
+
+    #[test]
+    fn macros_are_expanded_with_arguments_and_unique_labels() {
+        use crate::instructions::RegisterOperand;
+        use crate::lexer::{LexError, LexedLine, Lexer};
+        use crate::folding::{get_folding_ranges, FoldingKind, FoldingRange};
+        use crate::macros::MacroError;
+        use crate::outline::{get_outline, OutlineKind};
+        let code = "push macro
+    move.l \\1, -(sp)
+    endm
+swap_regs macro
+    push \\1
+    move.l \\2, \\1
+    move.l (sp)+, \\2
+    endm
+count_down macro
+    move.l #\\1, d7
+loop\\@:
+    sub.l #1, d7
+    bne loop\\@
+    endm
+start:
+    move.l #1, d0
+    move.l #2, d1
+    swap_regs d0, d1
+    count_down 3
+    count_down 2";
+        let s68k = S68k::new(code);
+        let lines = s68k.get_lexed_lines();
+        assert!(lines.iter().filter(|line| line.line_index < 14).all(|line| line.parsed == LexedLine::Empty));
+        let pushed = lines.iter().find(|line| line.line_index == 17).unwrap();
+        assert_eq!(pushed.line, "    swap_regs d0, d1");
+        let origin = pushed.macro_origin.as_ref().unwrap();
+        assert_eq!((origin.name.as_str(), origin.line_index, origin.expansion), ("push", 1, 2));
+        assert!(lines.iter().any(|line| line.parsed == LexedLine::new_label("loop_4")));
+        let folded = get_folding_ranges(&s68k);
+        assert!(folded.contains(&FoldingRange { start_line: 3, end_line: 7, kind: FoldingKind::Macro }));
+        let outline = get_outline(&s68k);
+        assert_eq!((outline[0].name.as_str(), outline[0].kind), ("push", OutlineKind::Macro));
+        let interpreter = lex_and_run(code);
+        let value = |register| interpreter.get_register_value(&register, Size::Long);
+        assert_eq!(value(RegisterOperand::Data(0)), 2);
+        assert_eq!(value(RegisterOperand::Data(1)), 1);
+        assert_eq!(value(RegisterOperand::Data(7)), 0);
+
+        let errors = S68k::new("bad macro\n    movx d0, d1\n    endm\n    bad").semantic_check();
+        assert_eq!(errors[0].get_line().line_index, 3);
+        assert!(errors[0].get_message().starts_with("Error on line 4 (in macro 'bad', line 2)"));
+        let mut lexer = Lexer::new().with_recovery();
+        lexer.lex("open macro\n    nop\n    endm\n    endm").unwrap();
+        assert_eq!(lexer.diagnostics()[0].get_cause(), &LexError::InvalidMacro(MacroError::UnexpectedEndm));
+        assert_eq!(lexer.diagnostics()[0].get_span().unwrap().line_index, 3);
+        let looping = "again macro\n    again\n    endm\n    again";
+        assert!(Lexer::new().lex(looping).is_err());
+    }
+
+    #[test]
+    fn conditional_assembly_keeps_only_the_active_blocks() {
+        use crate::conditional::ConditionalError;
+        use crate::folding::{get_folding_ranges, FoldingKind, FoldingRange};
+        use crate::instructions::RegisterOperand;
+        use crate::lexer::{LexError, LexedLine, Lexer};
+        let code = "DEBUG equ 1
+LEVEL equ DEBUG+1
+set_d3 macro
+    ifeq \\1
+    clr.l d3
+    else
+    move.l #\\1, d3
+    endc
+    endm
+    ifne DEBUG
+    move.l #1, d0
+    else
+    move.l #2, d0
+    endc
+    ifeq LEVEL-2
+    ifd MISSING
+    move.l #9, d1
+    else
+    move.l #3, d1
+    endc
+    endc
+    ifnd DEBUG
+    move.l #7, d2
+    endc
+    set_d3 5";
+        let s68k = S68k::new(code);
+        let lines = s68k.get_lexed_lines();
+        let at = |line_index| lines.iter().find(|line| line.line_index == line_index).unwrap();
+        assert_eq!(at(12).parsed, LexedLine::Empty);
+        assert_eq!(at(12).line, "    move.l #2, d0");
+        assert!(matches!(at(10).parsed, LexedLine::Instruction { .. }));
+        let interpreter = lex_and_run(code);
+        let value = |register| interpreter.get_register_value(&register, Size::Long);
+        assert_eq!(value(RegisterOperand::Data(0)), 1);
+        assert_eq!(value(RegisterOperand::Data(1)), 3);
+        assert_eq!(value(RegisterOperand::Data(2)), 0);
+        assert_eq!(value(RegisterOperand::Data(3)), 5);
+
+        let mut lexer = Lexer::new().with_recovery();
+        lexer.lex("    ifeq 0\n    nop\n    else\n    else\n    endc\n    endc\n    ifne\n    endc\n    ifne 1").unwrap();
+        let causes: Vec<&LexError> = lexer.diagnostics().iter().map(|error| error.get_cause()).collect();
+        assert_eq!(
+            causes,
+            vec![
+                &LexError::InvalidConditional(ConditionalError::DuplicateElse),
+                &LexError::InvalidConditional(ConditionalError::UnexpectedEndc),
+                &LexError::InvalidConditional(ConditionalError::MissingArgument("ifne".to_string())),
+                &LexError::InvalidConditional(ConditionalError::Unterminated("ifne".to_string())),
+            ]
+        );
+        assert_eq!(lexer.diagnostics()[3].get_span().unwrap().line_index, 8);
+        assert!(Lexer::new().lex("    ifeq UNKNOWN\n    endc").is_err());
+        let folded = get_folding_ranges(&s68k);
+        assert!(folded.contains(&FoldingRange { start_line: 14, end_line: 20, kind: FoldingKind::Conditional }));
+        assert!(folded.contains(&FoldingRange { start_line: 15, end_line: 19, kind: FoldingKind::Conditional }));
+    }
+
+    #[test]
+    fn rept_blocks_are_copied_with_their_counter() {
+        use crate::folding::{get_folding_ranges, FoldingKind, FoldingRange};
+        use crate::instructions::RegisterOperand;
+        use crate::lexer::{LexError, LexedLine, Lexer};
+        use crate::repeat::RepeatError;
+        let code = "COUNT equ 3
+    rept COUNT
+    add.l #REPTN, d0
+    endr
+    rept 2
+    rept REPTN+1
+    add.l #10, d1
+    endr
+    endr
+    rept 0
+    move.l #9, d2
+    endr";
+        let s68k = S68k::new(code);
+        let copies = s68k
+            .get_lexed_lines()
+            .iter()
+            .filter(|line| line.line_index == 2 && matches!(line.parsed, LexedLine::Instruction { .. }))
+            .count();
+        assert_eq!(copies, 3);
+        assert!(s68k.get_lexed_lines().iter().all(|line| line.line_index != 10 || line.parsed == LexedLine::Empty));
+        let interpreter = lex_and_run(code);
+        let value = |register| interpreter.get_register_value(&register, Size::Long);
+        assert_eq!(value(RegisterOperand::Data(0)), 3);
+        assert_eq!(value(RegisterOperand::Data(1)), 30);
+        assert_eq!(value(RegisterOperand::Data(2)), 0);
+        let table = S68k::new("    rept 3\n    dc.b REPTN*2, 'REPTN'\n    endr");
+        let args: Vec<&String> = table
+            .get_lexed_lines()
+            .iter()
+            .filter_map(|line| match &line.parsed {
+                LexedLine::Directive { args, .. } => args.get(1),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(args, vec!["0*2", "1*2", "2*2"]);
+        assert!(table.get_lexed_lines().iter().any(|line| matches!(
+            &line.parsed,
+            LexedLine::Directive { args, .. } if args.get(2).map(String::as_str) == Some("'REPTN'")
+        )));
+        let folded = get_folding_ranges(&s68k);
+        assert!(folded.contains(&FoldingRange { start_line: 4, end_line: 8, kind: FoldingKind::Repeat }));
+
+        let mut lexer = Lexer::new().with_recovery();
+        lexer.lex("    rept\n    nop\n    endr\n    endr\n    rept -1\n    endr\n    rept 2").unwrap();
+        let causes: Vec<&LexError> = lexer.diagnostics().iter().map(|error| error.get_cause()).collect();
+        assert_eq!(
+            causes,
+            vec![
+                &LexError::InvalidRepeat(RepeatError::MissingCount),
+                &LexError::InvalidRepeat(RepeatError::UnexpectedEndr),
+                &LexError::InvalidRepeat(RepeatError::NegativeCount(-1)),
+                &LexError::InvalidRepeat(RepeatError::Unterminated),
+            ]
+        );
+        assert!(Lexer::new().lex("    rept 100000\n    rept 100000\n    nop\n    endr\n    endr").is_err());
+    }
+
+    #[test]
+    fn legality_tables_reject_illegal_operands() {
+        use crate::{
+            diagnostic::DiagnosticCode,
+            semantic::{validate, validate_instruction},
+        };
+        let messages = |code: &str| -> Vec<String> {
+            validate(S68k::new(code).get_lexed_lines()).into_iter().map(|diagnostic| diagnostic.message).collect()
+        };
+        let legal = "    move.l a0, d0
+    move.w (a0)+, -(a1)
+    add.b d0, (a0)
+    addq.w #1, a0
+    lea 4(a0), a1
+    movem.l d0-d2/a0, -(sp)
+    btst #3, d0
+    lsl.w #1, d0
+    lsl.w #1, (a0)
+    asr.w -(a1)
+    rts";
+        assert!(messages(legal).is_empty(), "{:?}", messages(legal));
+        assert_eq!(messages("    move.b a0, d0"), vec!["Size .b is not allowed with An as the source of move"]);
+        assert_eq!(messages("    addq.b #1, a0"), vec!["Size .b is not allowed with An as the destination of addq"]);
+        assert_eq!(
+            messages("    move.l d0, #1"),
+            vec!["#immediate is not allowed as the destination of move, expected Dn, An, (An), (An)+, -(An), d16(An), d8(An,Xn), ([bd,An],Xn,od), absolute address"]
+        );
+        assert_eq!(
+            messages("    lea (a0)+, a1"),
+            vec!["(An)+ is not allowed as the source of lea, expected (An), d16(An), d8(An,Xn), ([bd,An],Xn,od), absolute address"]
+        );
+        assert_eq!(messages("    swap.l d0"), vec!["Invalid size .l for swap, expected .w"]);
+        assert_eq!(messages("    rts.l"), vec!["rts is not sized, found .l"]);
+        assert_eq!(messages("    clr d0, d1"), vec!["clr expects 1 operand, found 2"]);
+        assert_eq!(messages("    lsl"), vec!["lsl expects 1 or 2 operands, found 0"]);
+        assert_eq!(messages("    lsl.l (a0)"), vec!["Invalid size .l for lsl, expected .w"]);
+        assert_eq!(messages("    lsl.w d0"), vec!["Dn is not allowed as the operand of lsl, expected (An), (An)+, -(An), d16(An), d8(An,Xn), ([bd,An],Xn,od), absolute address"]);
+        let diagnostics = S68k::new("    add.l (a0), (a1)").get_lexed_lines().iter().filter_map(validate_instruction).collect::<Vec<_>>();
+        assert_eq!(diagnostics[0].get_error(), "(An) is not allowed as the destination of add, expected Dn, An");
+        let span = diagnostics[0].get_span().unwrap();
+        assert_eq!((span.start, span.end), (16, 20));
+        //the checker reports what the tables find after its own checks
+        let diagnostics = S68k::new("    eor.l (a0), d0\n    lsl.l #1, (a0)").get_diagnostics();
+        assert_eq!(diagnostics[0].message, "(An) is not allowed as the source of eor, expected Dn, #immediate");
+        assert_eq!(diagnostics[1].code, DiagnosticCode::InvalidSize);
+        assert_eq!(diagnostics[1].message, "Size .l is not allowed with (An) as the destination of lsl");
+        //memory is only shifted by one bit
+        assert!(S68k::new("    lsl.w #1, (a0)\n    lsl.w (a0)").get_diagnostics().is_empty());
+        assert_eq!(S68k::new("    lsl.w #2, (a0)").get_diagnostics()[0].code, DiagnosticCode::ValueOutOfRange);
+    }
+
+    #[test]
+    fn assembler_refuses_what_the_legality_tables_reject() {
+        use crate::assembler::{Assembler, EncodeError};
+        for code in [
+            "    move.w 1000(a0,d1.w), d0",
+            "    neg.b a0",
+            "    move.l #1, #2",
+            "    lea (a0)+, a1",
+            "    clr.w a0",
+        ] {
+            let lines = S68k::new(code).get_lexed_lines().to_vec();
+            let result = Assembler::new(&lines);
+            assert!(
+                matches!(result, Err(EncodeError::Assemble(_) | EncodeError::InvalidOperand { .. })),
+                "{} was assembled",
+                code
+            );
+        }
+        match Assembler::new(S68k::new("    neg.b a0").get_lexed_lines()) {
+            Err(EncodeError::InvalidOperand { message, .. }) => {
+                assert_eq!(message, "An is not allowed as the operand of neg, expected Dn, (An), (An)+, -(An), d16(An), d8(An,Xn), ([bd,An],Xn,od), absolute address")
+            }
+            _ => panic!("neg.b a0 was assembled"),
+        }
+        let index = S68k::new("    move.w -128(a0,d1.w), d0\n    move.w 127(a0,d1.w), d0").assemble().unwrap();
+        assert_eq!(index.get_machine_code().bytes, vec![0x30, 0x30, 0x10, 0x80, 0x30, 0x30, 0x10, 0x7F]);
+        //both forms of the memory shift are the same instruction
+        let code = "    lsl.w #1, (a0)\n    lsl.w (a0)\n    asr.w 4(a1)";
+        let machine_code = S68k::new(code).assemble().unwrap().into_machine_code();
+        assert_eq!(machine_code.bytes, vec![0xE3, 0xD0, 0xE3, 0xD0, 0xE0, 0xE9, 0x00, 0x04]);
+        let code = "    lea value, a0
+    lsl.w (a0)
+    lsl.w #1, (a0)
+    move.w (a0), d0
+    bra end
+value: dc.w 3
+end:";
+        let s68k = S68k::new(code);
+        assert!(s68k.get_diagnostics().is_empty());
+        let mut interpreter = s68k.create_interpreter(s68k.compile().unwrap(), None);
+        interpreter.run().unwrap();
+        assert_eq!(interpreter.get_cpu().wasm_get_d_reg(0).get_word(), 12);
+    }
+
+    #[test]
+    fn assembler_encodes_machine_code() {
+        use crate::assembler::EncodeError;
+        let code = "    org $1000
+start:
+    move.l #$12345678, d0
+    moveq #5, d1
+    add.w d1, d0
+    lea data, a0
+    move.b (a0)+, d2
+    bra start
+    rts
+data:
+    dc.b 1, 2";
+        let assembler = S68k::new(code).assemble().unwrap();
+        let machine_code = assembler.get_machine_code();
+        assert_eq!(machine_code.origin, 0x1000);
+        assert_eq!(machine_code.start_address, 0x1000);
+        assert_eq!(
+            machine_code.bytes,
+            vec![
+                0x20, 0x3C, 0x12, 0x34, 0x56, 0x78, 0x72, 0x05, 0xD0, 0x41, 0x41, 0xF8, 0x10, 0x16, 0x14, 0x18, 0x60,
+                0x00, 0xFF, 0xEE, 0x4E, 0x75, 0x01, 0x02
+            ]
+        );
+        assert_eq!(machine_code.lines.len(), 8);
+        assert_eq!(machine_code.get_line_bytes(&machine_code.lines[5]), &[0x60, 0x00, 0xFF, 0xEE]);
+        //forward references get the address the label has once the lines before it are encoded
+        let loop_code = "    moveq #3, d0
+loop:
+    btst #2, (a1)
+    dbra d0, loop
+    beq done
+    addq.l #1, 4(a0)
+done:
+    rts";
+        let machine_code = S68k::new(loop_code).assemble().unwrap().into_machine_code();
+        assert_eq!(
+            machine_code.bytes,
+            vec![
+                0x70, 0x03, 0x08, 0x11, 0x00, 0x02, 0x51, 0xC8, 0xFF, 0xFA, 0x67, 0x00, 0x00, 0x06, 0x52, 0xA8, 0x00,
+                0x04, 0x4E, 0x75
+            ]
+        );
+        let adda = S68k::new("    add.l #6, sp").assemble().unwrap().into_machine_code();
+        assert_eq!(adda.bytes, vec![0xDF, 0xFC, 0x00, 0x00, 0x00, 0x06]);
+        let far = S68k::new("    bra far\n    org $20000\nfar:\n    rts").assemble();
+        assert!(matches!(far, Err(EncodeError::OutOfRange { line_index: 0, .. })));
+    }
+
+    #[test]
+    fn symbol_table_resolves_forward_references() {
+        use crate::compiler::Directive;
+        use crate::symbol_table::SymbolKind;
+        let code = "SIZE equ 4
+    bra done
+    dc.l table
+    org $2000
+table:
+    ds.b SIZE*2
+.end:
+done:
+    move.l #table_end-table, d0
+table_end equ table+SIZE*2";
+        let s68k = S68k::new(code);
+        let compiler = s68k.compile().unwrap();
+        let symbols = compiler.get_symbol_table();
+        assert_eq!(symbols.get_address("table"), Some(0x2000));
+        assert_eq!(symbols.get_address("table.end"), Some(0x2008));
+        assert_eq!(symbols.get_address("done"), Some(0x2008));
+        assert_eq!(symbols.get_value("SIZE"), Some(4));
+        assert_eq!(symbols.get_address("SIZE"), None);
+        //an equ can use the labels after it
+        assert_eq!(symbols.get("table_end").map(|symbol| symbol.kind), Some(SymbolKind::Equ));
+        assert_eq!(symbols.get_value("table_end"), Some(0x2008));
+        assert_eq!(symbols.resolve("done-table").unwrap(), 8);
+        let names: Vec<&str> = symbols.iter().map(|symbol| symbol.name.as_str()).collect();
+        assert_eq!(names, vec!["table", "table.end", "done", "SIZE", "table_end"]);
+        //the forward reference of the dc is resolved in the second pass
+        match &compiler.get_directives()[1] {
+            Directive::DC { data, .. } => assert_eq!(data, &vec![0x00, 0x00, 0x20, 0x00]),
+            directive => panic!("Expected a DC, found {:?}", directive),
+        }
+    }
+
+    #[test]
+    fn srec_writes_records_with_checksums() {
+        use crate::srec::{write_srec, SrecError, SrecFormat, SrecOptions};
+        let code = "    org $1000
+start:
+    moveq #1, d0
+    rts";
+        let machine_code = S68k::new(code).assemble().unwrap().into_machine_code();
+        let options = SrecOptions::default().with_header("t");
+        assert_eq!(
+            write_srec(&machine_code, &options).unwrap(),
+            "S00400007487\nS107100070014E75B4\nS5030001FB\nS9031000EC\n"
+        );
+        let s37 = write_srec(&machine_code, &options.clone().with_format(SrecFormat::S37)).unwrap();
+        assert_eq!(s37.lines().nth(1), Some("S3090000100070014E75B2"));
+        assert_eq!(s37.lines().last(), Some("S70500001000EA"));
+        //ranges far apart are separate records, the format is picked from the last address
+        let code = "    org $1000
+start:
+    rts
+    org $20000
+    dc.b 1, 2, 3";
+        let machine_code = S68k::new(code).assemble().unwrap().into_machine_code();
+        let srec = write_srec(&machine_code, &SrecOptions::default()).unwrap();
+        let records: Vec<&str> = srec.lines().collect();
+        assert_eq!(records.len(), 5);
+        assert!(records[1].starts_with("S2060010004E75"));
+        assert!(records[2].starts_with("S207020000010203"));
+        assert!(records[4].starts_with("S8"));
+        for record in records {
+            let bytes: Vec<u8> = (2..record.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&record[i..i + 2], 16).unwrap())
+                .collect();
+            assert_eq!(bytes[0] as usize, bytes.len() - 1);
+            assert_eq!(bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)), 0xFF);
+        }
+        assert_eq!(
+            write_srec(&machine_code, &SrecOptions::default().with_format(SrecFormat::S19)),
+            Err(SrecError::AddressTooLarge {
+                address: 0x20002,
+                format: SrecFormat::S19
+            })
+        );
+        assert_eq!(
+            write_srec(&machine_code, &SrecOptions::default().with_record_length(0)),
+            Err(SrecError::InvalidRecordLength { length: 0, max: 251 })
+        );
+    }
+
+    #[test]
+    fn elf_object_relocates_label_addresses() {
+        use crate::elf::{ElfError, RelocationKind, SymbolSection};
+        let code = "    org $1000
+START:
+    lea data, a0
+    moveq #SIZE, d0
+    bra START
+data:
+    dc.w 0, data
+    dc.l START+4
+SIZE equ 7";
+        let object = S68k::new(code).assemble_object().unwrap();
+        //labels are long addresses in the object, the branch is relative so it is not relocated
+        assert_eq!(
+            object.code,
+            vec![
+                0x41, 0xF9, 0x00, 0x00, 0x00, 0x0C, 0x70, 0x07, 0x60, 0x00, 0xFF, 0xF6, 0x00, 0x00, 0x00, 0x0C, 0x00,
+                0x00, 0x00, 0x04
+            ]
+        );
+        let relocations: Vec<(usize, RelocationKind, i64)> = object
+            .relocations
+            .iter()
+            .map(|relocation| (relocation.offset, relocation.kind, relocation.addend))
+            .collect();
+        assert_eq!(
+            relocations,
+            vec![
+                (2, RelocationKind::Absolute32, 12),
+                (14, RelocationKind::Absolute16, 12),
+                (16, RelocationKind::Absolute32, 4)
+            ]
+        );
+        let symbols: Vec<(&str, i64, SymbolSection, bool)> = object
+            .symbols
+            .iter()
+            .map(|symbol| (symbol.name.as_str(), symbol.value, symbol.section, symbol.global))
+            .collect();
+        assert_eq!(
+            symbols,
+            vec![
+                ("START", 0, SymbolSection::Text, false),
+                ("data", 12, SymbolSection::Text, false),
+                ("SIZE", 7, SymbolSection::Absolute, false)
+            ]
+        );
+        let elf = object.to_elf();
+        assert_eq!(&elf[..7], &[0x7F, b'E', b'L', b'F', 1, 2, 1]);
+        assert_eq!(&elf[16..20], &[0, 1, 0, 4]); //ET_REL, EM_68K
+        assert_eq!(&elf[52..52 + object.code.len()], &object.code[..]);
+        //a value made from an address that is not an address can't be relocated
+        let code = "start:
+    move.l #start*2, d0";
+        assert!(matches!(
+            S68k::new(code).assemble_object(),
+            Err(ElfError::Unrelocatable { line_index: 1 })
+        ));
+    }
+
+    #[test]
+    fn elf_symbol_table_binds_only_the_xdef_labels_as_global() {
+        let code = "    xdef start
+start:
+    bsr helper
+    rts
+helper:
+    rts";
+        let elf = S68k::new(code).assemble_object().unwrap().to_elf();
+        let read_u32 = |offset: usize| u32::from_be_bytes(elf[offset..offset + 4].try_into().unwrap()) as usize;
+        //the section headers of .symtab and .strtab
+        let symtab = read_u32(32) + 3 * 40;
+        let strtab = read_u32(32) + 4 * 40;
+        let (offset, size, first_global) = (read_u32(symtab + 16), read_u32(symtab + 20), read_u32(symtab + 28));
+        let names = read_u32(strtab + 16);
+        let symbols: Vec<(String, u8)> = (offset..offset + size)
+            .step_by(16)
+            .skip(2)
+            .map(|symbol| {
+                let name = &elf[names + read_u32(symbol)..];
+                let name = &name[..name.iter().position(|byte| *byte == 0).unwrap()];
+                (String::from_utf8(name.to_vec()).unwrap(), elf[symbol + 12] >> 4)
+            })
+            .collect();
+        //STB_LOCAL is 0 and STB_GLOBAL is 1, the locals come first
+        assert_eq!(symbols, vec![("helper".to_string(), 0), ("start".to_string(), 1)]);
+        assert_eq!(first_global, 3);
+    }
+
+    #[test]
+    fn listing_has_addresses_bytes_and_expanded_lines() {
+        let code = "    org $1000
+push macro
+    move.l \\1, -(sp)
+    endm
+start:
+    moveq #1, d0 ; one
+    push d0
+SIZE equ 4
+data: dc.b 1, 2, 3, 4, 5, 6, 7, 8, 9, 10
+    rts";
+        let listing = S68k::new(code).assemble().unwrap().generate_listing();
+        let rows: Vec<(usize, Option<usize>, &[u8], &str)> = listing
+            .rows
+            .iter()
+            .map(|row| (row.line_index, row.address, &row.bytes[..], row.source.as_str()))
+            .collect();
+        assert_eq!(rows[4], (4, Some(0x1000), &[][..], "start:"));
+        assert_eq!(rows[5], (5, Some(0x1000), &[0x70, 0x01][..], "    moveq #1, d0 ; one"));
+        //the invocation is followed by the lines of the macro
+        assert_eq!(rows[6], (6, None, &[][..], "    push d0"));
+        assert_eq!(rows[7], (6, Some(0x1002), &[0x2F, 0x00][..], "    move.l d0, -(sp)"));
+        assert_eq!(listing.rows[7].macro_name.as_deref(), Some("push"));
+        assert_eq!(rows[8], (7, None, &[][..], "SIZE equ 4"));
+        assert_eq!(rows[9].2, &[1, 2, 3, 4, 5, 6, 7, 8, 9, 10][..]);
+        assert_eq!(rows.len(), 11);
+        let text = listing.to_text();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[5], "    6  00001000  70 01                        moveq #1, d0 ; one");
+        assert_eq!(lines[7], "    7+ 00001002  2F 00                        move.l d0, -(sp)");
+        assert_eq!(lines[9], "    9  00001004  01 02 03 04 05 06 07 08  data: dc.b 1, 2, 3, 4, 5, 6, 7, 8, 9, 10");
+        assert_eq!(lines[10], "       0000100C  09 0A");
+    }
+
+    #[test]
+    fn symbol_map_has_addresses_and_sizes() {
+        use crate::symbol_table::SymbolKind;
+        let code = "    org $2000
+start:
+    moveq #3, d0
+.loop:
+    dbra d0, .loop
+    rts
+table:
+    dc.w 1, 2, 3
+COUNT equ 3";
+        let map = S68k::new(code).assemble().unwrap().get_symbol_map();
+        let symbols: Vec<(&str, SymbolKind, i64, usize)> = map
+            .symbols
+            .iter()
+            .map(|symbol| (symbol.name.as_str(), symbol.kind, symbol.value, symbol.size))
+            .collect();
+        //the local label is inside start, which ends at table
+        assert_eq!(
+            symbols,
+            vec![
+                ("start", SymbolKind::Label, 0x2000, 8),
+                ("start.loop", SymbolKind::Label, 0x2002, 6),
+                ("table", SymbolKind::Label, 0x2008, 6),
+                ("COUNT", SymbolKind::Equ, 3, 0)
+            ]
+        );
+        assert_eq!(map.get_label_at(0x2004).map(|label| label.name.as_str()), Some("start.loop"));
+        assert_eq!(map.get_label_at(0x2000).map(|label| label.name.as_str()), Some("start"));
+        assert_eq!(map.get_label_at(0x200E), None);
+        assert_eq!(map.get("COUNT").map(|equ| equ.value), Some(3));
+        let text = map.to_text();
+        assert_eq!(text.lines().nth(2), Some("00002002  00000006  label  start.loop"));
+        assert_eq!(text.lines().nth(4), Some("00000003            equ    COUNT"));
+        #[cfg(feature = "serialize")]
+        {
+            let json: serde_json::Value = serde_json::from_str(&map.to_json()).unwrap();
+            assert_eq!(json["symbols"][2]["name"], "table");
+            assert_eq!(json["symbols"][2]["size"], 6);
+        }
+    }
+
+    #[test]
+    fn linker_resolves_cross_module_references() {
+        use crate::linker::{LinkError, Linker};
+        let main = "    xref print, message
+    xdef START
+START:
+    lea message, a0
+    bsr print
+    bra START";
+        let library = "    xdef print, message
+print:
+    move.b (a0)+, d0
+    rts
+message:
+    dc.b 'hi', 0";
+        let main = S68k::new(main).assemble_object().unwrap();
+        let library = S68k::new(library).assemble_object().unwrap();
+        assert_eq!(main.relocations.len(), 2);
+        let mut linker = Linker::new();
+        linker.add_module("main", main.clone());
+        linker.add_module("library", library);
+        let image = linker.link().unwrap();
+        //main is at $1000 and takes 14 bytes, the library follows it
+        assert_eq!(image.get_symbol("print").unwrap().value, 0x100E);
+        assert_eq!(image.get_symbol("message").unwrap().value, 0x1012);
+        assert_eq!(image.machine_code.start_address, 0x1000);
+        assert_eq!(
+            image.machine_code.bytes,
+            vec![
+                0x41, 0xF9, 0x00, 0x00, 0x10, 0x12, 0x61, 0x00, 0x00, 0x06, 0x60, 0x00, 0xFF, 0xF4, 0x10, 0x18, 0x4E,
+                0x75, b'h', b'i', 0
+            ]
+        );
+        //a module that imports a symbol no one exports
+        let mut linker = Linker::new();
+        linker.add_module("main", main);
+        assert_eq!(
+            linker.link().unwrap_err(),
+            LinkError::UndefinedSymbol {
+                name: "message".to_string(),
+                module: "main".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn status_register_switches_between_supervisor_and_user_stacks() {
+        let code = "
+    move.w sr, d0
+    ori #$0700, sr
+    move.w sr, d1
+    lea $3000, a0
+    move.l a0, usp
+    move.l sp, d2
+    bsr sub
+    move.w sr, d4
+    andi #$DFFF, sr
+    move.l sp, d3
+    move #$04, ccr
+    move #$2000, sr
+sub:
+    move.w #$1F, -(sp)
+    rtr";
+        let s68k = S68k::new(code.to_string());
+        assert!(s68k.semantic_check().is_empty());
+        let compiled = s68k.compile().expect("To compile correctly");
+        let mut interpreter = s68k.create_interpreter(compiled, None);
+        let result = interpreter.run();
+        let cpu = interpreter.get_cpu();
+        assert_eq!(cpu.wasm_get_d_reg(0).get_word(), 0x2000);
+        assert_eq!(cpu.wasm_get_d_reg(1).get_word(), 0x2700);
+        //rtr restored the condition codes pushed by the subroutine
+        assert_eq!(cpu.wasm_get_d_reg(4).get_word(), 0x271F);
+        //leaving supervisor mode made the user stack pointer A7
+        assert_eq!(cpu.wasm_get_d_reg(3).get_long(), 0x3000);
+        assert!(!interpreter.is_supervisor());
+        assert_eq!(interpreter.get_sr(), 0x0704);
+        assert!(matches!(result, Err(RuntimeError::PrivilegeViolation(_))));
+        let machine_code = s68k.assemble().unwrap().get_machine_code().bytes.clone();
+        assert_eq!(&machine_code[..6], &[0x40, 0xC0, 0x00, 0x7C, 0x07, 0x00]);
+        assert!(machine_code.windows(2).any(|word| word == [0x4E, 0x60]));
+        assert!(machine_code.ends_with(&[0x3F, 0x3C, 0x00, 0x1F, 0x4E, 0x77]));
+    }
+
+    #[test]
+    fn user_mode_starts_with_a_usable_stack() {
+        use crate::interpreter::{Memory, MemoryCell};
+        let code = "    move.l sp, d0
+    andi.w #$dfff, sr
+    move.l sp, d1
+    bsr sub
+    moveq #1, d2
+    bra end
+sub:
+    rts
+end:";
+        let s68k = S68k::new(code);
+        let mut interpreter = s68k.create_interpreter(s68k.compile().unwrap(), None);
+        assert_eq!(interpreter.run().unwrap(), InterpreterStatus::Terminated);
+        let cpu = interpreter.get_cpu();
+        assert!(!interpreter.is_supervisor());
+        assert_eq!(cpu.wasm_get_d_reg(1).get_long(), cpu.wasm_get_d_reg(0).get_long());
+        assert_eq!(cpu.wasm_get_d_reg(2).get_long(), 1);
+        let mut memory = Memory::with_size(16);
+        assert!(matches!(
+            memory.push(&MemoryCell::Long(1), 2),
+            Err(RuntimeError::AddressError(2, crate::instructions::Size::Long))
+        ));
+    }
+
+    #[test]
+    fn memory_regions_enforce_permissions() {
+        use crate::memory_region::{Access, MemoryRegion};
+        use crate::sandbox::SandboxConfig;
+        let code = "    org $1000
+    move.l value, d0
+    move.l d0, $2000
+    bsr sub
+    move.l d0, value
+sub:
+    rts
+value:
+    dc.l 7";
+        let options = || InterpreterOptions {
+            sandbox: SandboxConfig::new().with_memory_size(0x10000),
+            memory_regions: vec![
+                MemoryRegion::rom(0, 0x2000),
+                MemoryRegion::ram(0x2000, 0x3000),
+                MemoryRegion::stack(0xF000, 0x10000),
+            ],
+            ..Default::default()
+        };
+        let s68k = S68k::new(code.to_string());
+        let compiled = s68k.compile().expect("To compile correctly");
+        let value = compiled.get_symbol_table().get_address("value").unwrap();
+        let mut interpreter = s68k.create_interpreter(compiled, Some(options()));
+        let error = interpreter.run().unwrap_err();
+        //the constant was loaded in the rom and can be read, but not written
+        assert_eq!(interpreter.get_cpu().wasm_get_d_reg(0).get_long(), 7);
+        assert_eq!(interpreter.get_memory().read_long(0x2000).unwrap(), 7);
+        assert!(matches!(
+            &error,
+            RuntimeError::AccessViolation { address, access: Access::Write, region: Some(region) }
+                if *address == value && region == "rom"
+        ));
+        assert_eq!(interpreter.get_last_line_index(), Some(4));
+        assert!(interpreter.get_error_message(&error).starts_with("Error on line 5, \"move.l d0, value\""));
+        let s68k = S68k::new("    move.l $4000, d0".to_string());
+        let compiled = s68k.compile().expect("To compile correctly");
+        let mut interpreter = s68k.create_interpreter(compiled, Some(options()));
+        assert!(matches!(
+            interpreter.run(),
+            Err(RuntimeError::AccessViolation { address: 0x4000, access: Access::Read, region: None })
+        ));
+    }
+
+    #[test]
+    fn trap_15_runs_easy68k_tasks_with_a_host() {
+        use crate::host_io::ScriptedIo;
+        use crate::scripted_input::{ExhaustedPolicy, ScriptedInput};
+        let code = "
+    lea prompt, a1
+    move.l #18, d0
+    trap #15
+    move.l d1, d3
+    lea total, a1
+    move.l #17, d0
+    trap #15
+    move.l #-42, d1
+    move.l #3, d0
+    trap #15
+    move.l #255, d1
+    move.l #16, d2
+    move.l #15, d0
+    trap #15
+    move.l #7, d1
+    move.l #5, d2
+    move.l #20, d0
+    trap #15
+    move.l #7, d0
+    trap #15
+    move.b d1, d4
+    move.w #$FF00, d1
+    move.l #11, d0
+    trap #15
+    move.l #9, d0
+    trap #15
+prompt: dc.b 'n? ', 0
+total: dc.b ' got ', 0";
+        let s68k = S68k::new(code.to_string());
+        let compiled = s68k.compile().expect("To compile correctly");
+        let mut interpreter = s68k.create_interpreter(compiled, None);
+        let mut input = ScriptedInput::new(ExhaustedPolicy::Fail);
+        input.push_number(12);
+        let mut output = String::new();
+        let status = interpreter.run_with_io(&mut ScriptedIo::new(&mut input, &mut output));
+        assert_eq!(status.unwrap(), InterpreterStatus::Terminated);
+        assert_eq!(output, "n?  got 12-42FF    7");
+        assert_eq!(interpreter.get_cpu().wasm_get_d_reg(3).get_long(), 12);
+        //nothing is left to read
+        assert_eq!(interpreter.get_cpu().wasm_get_d_reg(4).get_byte(), 0);
+    }
+
+    #[test]
+    fn step_over_and_out_report_the_changed_state() {
+        use crate::debugger::StateChange;
+        use crate::instructions::RegisterOperand;
+        use crate::interpreter::Interpreter;
+        let code = "    move.l #1, d0
+    bsr double
+    move.l d0, result
+    move.l #5, d1
+    move.l #9, d0
+    trap #15
+double:
+    add.l d0, d0
+    bsr inner
+    rts
+inner:
+    add.l d0, d0
+    rts
+result: dc.l 0";
+        let s68k = S68k::new(code.to_string());
+        let compiled = s68k.compile().expect("To compile correctly");
+        let result = compiled.get_symbol_table().get_address("result").unwrap();
+        let address_of = |interpreter: &Interpreter, line: usize| {
+            interpreter.get_program().iter().find(|l| l.parsed_line.line_index == line).unwrap().address
+        };
+        let mut interpreter = s68k.create_interpreter(compiled, None);
+        let step = interpreter.step_into().unwrap();
+        assert_eq!((step.line_index, step.executed), (Some(0), 1));
+        assert_eq!(
+            step.changes,
+            vec![StateChange::Register { register: RegisterOperand::Data(0), old: 0, new: 1 }]
+        );
+        //the whole subroutine runs, the return addresses pushed on the stack are written memory
+        let step = interpreter.step_over().unwrap();
+        assert_eq!((step.line_index, step.executed), (Some(9), 6));
+        assert_eq!(step.pc, address_of(&interpreter, 2));
+        assert!(step.changes.contains(&StateChange::Register { register: RegisterOperand::Data(0), old: 1, new: 4 }));
+        assert!(step.changes.iter().any(|change| matches!(change, StateChange::Memory { .. })));
+        let step = interpreter.step_over().unwrap();
+        assert_eq!(step.changes, vec![StateChange::Memory { address: result, bytes: vec![0, 0, 0, 4] }]);
+        interpreter.step_over().unwrap();
+        interpreter.step_over().unwrap();
+        assert_eq!(interpreter.step_over().unwrap().status, InterpreterStatus::Terminated);
+
+        let mut interpreter = s68k.create_interpreter(s68k.compile().unwrap(), None);
+        let step = interpreter.run_until_line(11).unwrap();
+        assert_eq!((step.line_index, step.executed), (Some(8), 4));
+        assert_eq!(interpreter.get_call_depth(), 2);
+        let step = interpreter.step_out().unwrap();
+        assert_eq!((step.pc, step.executed), (address_of(&interpreter, 9), 2));
+        let step = interpreter.step_out().unwrap();
+        assert_eq!((step.pc, step.executed), (address_of(&interpreter, 2), 1));
+    }
+
+    #[test]
+    fn breakpoints_and_watchpoints_report_what_stopped_the_run() {
+        use crate::breakpoints::{BreakHit, BreakpointLocation};
+        use crate::memory_region::{Access, Permissions};
+        let code = "    move.l #3, d0
+loop:
+    add.l d0, total
+    subq.l #1, d0
+    bne loop
+    move.l total, d1
+done:
+    move.l #1, d2
+total: dc.l 0";
+        let s68k = S68k::new(code.to_string());
+        let compiled = s68k.compile().expect("To compile correctly");
+        let total = compiled.get_symbol_table().get_address("total").unwrap();
+        let done = compiled.get_symbol_table().get_address("done").unwrap();
+        let mut interpreter = s68k.create_interpreter(compiled, None);
+        let write = interpreter.add_watchpoint(total, 4, Permissions::WRITE);
+        assert_eq!(interpreter.run().unwrap(), InterpreterStatus::Running);
+        assert_eq!(
+            interpreter.get_last_break(),
+            Some(BreakHit::Watchpoint { id: write, address: total, length: 4, access: Access::Write })
+        );
+        assert_eq!(interpreter.get_memory().read_long(total).unwrap(), 3);
+        assert!(interpreter.remove_watchpoint(write));
+        let read = interpreter.add_watchpoint(total + 2, 1, Permissions::READ);
+        interpreter.run().unwrap();
+        assert_eq!(
+            interpreter.get_last_break(),
+            Some(BreakHit::Watchpoint { id: read, address: total, length: 4, access: Access::Read })
+        );
+        interpreter.remove_watchpoint(read);
+        assert!(interpreter.add_breakpoint(BreakpointLocation::Line(20)).is_err());
+        let breakpoint = interpreter.add_breakpoint(BreakpointLocation::Label("done".to_string())).unwrap();
+        interpreter.run().unwrap();
+        assert_eq!(interpreter.get_last_break(), Some(BreakHit::Breakpoint { id: breakpoint, address: done }));
+        assert_eq!(interpreter.get_pc(), done);
+        assert_eq!(interpreter.get_cpu().wasm_get_d_reg(1).get_long(), 6);
+        assert_eq!(interpreter.run().unwrap(), InterpreterStatus::Terminated);
+        assert_eq!(interpreter.get_last_break(), None);
+    }
+
+    #[test]
+    fn step_back_restores_the_previous_state_within_the_history() {
+        use crate::debugger::StateChange;
+        let code = "    move.l #5, d0
+    move.l d0, value
+    add.l #1, d0
+value: dc.l 7";
+        let s68k = S68k::new(code.to_string());
+        let compiled = s68k.compile().expect("To compile correctly");
+        let value = compiled.get_symbol_table().get_address("value").unwrap();
+        let options = InterpreterOptions {
+            keep_history: true,
+            history_size: 2,
+            ..Default::default()
+        };
+        let mut interpreter = s68k.create_interpreter(compiled, Some(options));
+        assert!(interpreter.step_back().is_err());
+        assert_eq!(interpreter.run().unwrap(), InterpreterStatus::Terminated);
+        //the end is undone and the program can run again
+        let step = interpreter.step_back().unwrap();
+        assert_eq!((step.line_index, step.status), (Some(2), InterpreterStatus::Running));
+        assert_eq!(interpreter.get_cpu().wasm_get_d_reg(0).get_long(), 5);
+        let step = interpreter.step_back().unwrap();
+        assert_eq!(step.changes, vec![StateChange::Memory { address: value, bytes: vec![0, 0, 0, 7] }]);
+        assert_eq!(interpreter.get_memory().read_long(value).unwrap(), 7);
+        //the first instruction is older than the history
+        assert!(interpreter.step_back().is_err());
+        assert_eq!(interpreter.get_pc(), interpreter.get_program()[1].address);
+        interpreter.set_history_size(0);
+        interpreter.step().unwrap();
+        assert!(interpreter.step_back().is_err());
+        assert_eq!(interpreter.run().unwrap(), InterpreterStatus::Terminated);
+        assert_eq!(interpreter.get_cpu().wasm_get_d_reg(0).get_long(), 6);
+    }
+
+    #[test]
+    fn cycles_follow_the_68000_manual() {
+        let code = "    lea $2000, a0
+    add.l d0, d1
+    clr.w (a0)
+    addi.w #1, (a0)
+    addq.w #1, a0
+    lea $3000, a1
+    divu #2, d2
+    divs #2, d2
+    add.l d1, d2
+    add.l d1, (a1)
+    jsr sub
+    bra end
+sub:
+    rts
+end:";
+        let s68k = S68k::new(code.to_string());
+        let mut interpreter = s68k.create_interpreter(s68k.compile().unwrap(), None);
+        assert_eq!(interpreter.run().unwrap(), InterpreterStatus::Terminated);
+        let cycles = |line| interpreter.get_line_cycles_at(line).unwrap().cycles;
+        //the addresses fit in a word and are encoded short
+        assert_eq!(cycles(0), 8);
+        assert_eq!(cycles(1), 8);
+        //read, modify and write back
+        assert_eq!(cycles(2), 12);
+        assert_eq!(cycles(3), 16);
+        //on an address register it works on the whole register
+        assert_eq!(cycles(4), 8);
+        //the worst case of each division, plus the immediate
+        assert_eq!(cycles(6), 140 + 4);
+        assert_eq!(cycles(7), 158 + 4);
+        assert_eq!(cycles(9), 12 + 8);
+        assert_eq!(cycles(10), 18);
+    }
+
+    #[test]
+    fn cycles_follow_the_encoding_and_the_branch_condition() {
+        let code = "    move.w $2000, d0
+    move.w $12000, d0
+    lea $12000, a0
+    moveq #1, d1
+    beq skip
+    bne next
+next:
+    moveq #1, d2
+    dbra d2, after
+after:
+skip:";
+        let s68k = S68k::new(code.to_string());
+        let mut interpreter = s68k.create_interpreter(s68k.compile().unwrap(), None);
+        assert_eq!(interpreter.run().unwrap(), InterpreterStatus::Terminated);
+        let cycles = |line| interpreter.get_line_cycles_at(line).unwrap().cycles;
+        assert_eq!(cycles(0), 4 + 8);
+        assert_eq!(cycles(1), 4 + 12);
+        assert_eq!(cycles(2), 12);
+        //the assembler encodes Bcc.W, which takes 12 cycles when it is not taken
+        assert_eq!(cycles(4), 12);
+        //taken even if the target is the next instruction
+        assert_eq!(cycles(5), 10);
+        assert_eq!(cycles(8), 10);
+    }
+
+    #[test]
+    fn cycles_depend_on_the_data_and_are_counted_per_line() {
+        let code = "    move.w #0, d1
+    mulu.w d1, d0
+    move.w #$ffff, d1
+    mulu.w d1, d0
+    move.w #$5555, d1
+    muls.w d1, d0
+    moveq #5, d2
+    lsl.l d2, d0
+    moveq #2, d3
+loop:
+    dbra d3, loop";
+        let s68k = S68k::new(code.to_string());
+        let compiled = s68k.compile().expect("To compile correctly");
+        let mut interpreter = s68k.create_interpreter(compiled, None);
+        assert_eq!(interpreter.run().unwrap(), InterpreterStatus::Terminated);
+        let cycles = |line| interpreter.get_line_cycles_at(line).unwrap().cycles;
+        //38 plus 2 for every 1 bit of the multiplier
+        assert_eq!(cycles(1), 38);
+        assert_eq!(cycles(3), 70);
+        //$5555 with a 0 appended changes at every bit
+        assert_eq!(cycles(5), 70);
+        //8 cycles plus 2 for every bit of the count in d2
+        assert_eq!(cycles(7), 8 + 2 * 5);
+        let lines = interpreter.get_line_cycles();
+        let dbra = lines.last().unwrap();
+        assert_eq!((dbra.line_index, dbra.executions), (10, 3));
+        let total: u64 = lines.iter().map(|line| line.cycles).sum();
+        assert_eq!(total, interpreter.get_cycles());
+        assert_eq!(interpreter.get_last_instruction_cycles(), 14);
+    }
+
+    #[test]
+    fn exceptions_jump_to_the_installed_handlers() {
+        use crate::exceptions::Exception;
+        let code = "    move.l #zero, $14
+    move.l #trap3, $8c
+    move.l #bounds, $18
+    moveq #0, d6
+    move.l #10, d0
+    divu d6, d0
+    trap #3
+after_trap:
+    move.w #20, d2
+    chk #10, d2
+    trapv
+    illegal
+zero:
+    addq.l #1, d1
+    rte
+trap3:
+    move.w (sp), d3
+    move.l 2(sp), d4
+    rte
+bounds:
+    moveq #1, d5
+    rte";
+        let s68k = S68k::new(code.to_string());
+        assert!(s68k.semantic_check().is_empty());
+        let compiled = s68k.compile().expect("To compile correctly");
+        let after_trap = compiled.get_symbol_table().get_address("after_trap").unwrap();
+        let mut interpreter = s68k.create_interpreter(compiled, None);
+        let sp = interpreter.get_sp();
+        match interpreter.run() {
+            Err(RuntimeError::Exception(Exception::IllegalInstruction)) => {}
+            result => panic!("Expected an unhandled illegal instruction, got {:?}", result),
+        }
+        let cpu = interpreter.get_cpu();
+        assert_eq!(cpu.wasm_get_d_reg(1).get_long(), 1);
+        assert_eq!(cpu.wasm_get_d_reg(0).get_long(), 10);
+        assert_eq!(cpu.wasm_get_d_reg(3).get_long(), interpreter.get_sr() as u32);
+        assert_eq!(cpu.wasm_get_d_reg(4).get_long(), after_trap as u32);
+        assert_eq!(cpu.wasm_get_d_reg(5).get_long(), 1);
+        //every handler returned and popped its frame
+        assert_eq!(interpreter.get_sp(), sp);
+    }
+
+    #[test]
+    fn interrupts_are_masked_by_the_sr_and_use_the_autovectors() {
+        use crate::interrupt_source::PeriodicInterrupt;
+        let code = "    move.l #vbl, $70
+    move.l #nmi, $7c
+    move.w #$2500, sr
+    moveq #0, d0
+    moveq #9, d7
+wait:
+    dbra d7, wait
+unmask:
+    move.w #$2000, sr
+    moveq #19, d7
+wait_more:
+    dbra d7, wait_more
+    bra end
+vbl:
+    addq.l #1, d1
+    move.w (sp), d2
+    move.w sr, d3
+    rte
+nmi:
+    addq.l #1, d4
+    rte
+end:";
+        let s68k = S68k::new(code.to_string());
+        assert!(s68k.semantic_check().is_empty());
+        let compiled = s68k.compile().expect("To compile correctly");
+        let table = compiled.get_symbol_table();
+        let (unmask, nmi) = (table.get_address("unmask").unwrap(), table.get_address("nmi").unwrap());
+        let mut interpreter = s68k.create_interpreter(compiled, None);
+        interpreter.add_interrupt_source(Box::new(PeriodicInterrupt::new(4, 150)));
+        for _ in 0..3 {
+            interpreter.step().unwrap();
+        }
+        //level 7 can't be masked
+        interpreter.raise_interrupt(7).unwrap();
+        interpreter.step().unwrap();
+        assert_eq!(interpreter.get_pc(), nmi);
+        while interpreter.get_pc() != unmask {
+            interpreter.step().unwrap();
+        }
+        assert_eq!(interpreter.get_cpu().wasm_get_d_reg(4).get_long(), 1);
+        assert_eq!(interpreter.get_cpu().wasm_get_d_reg(1).get_long(), 0);
+        assert_eq!(interpreter.get_pending_interrupts(), 1 << 4);
+        assert_eq!(interpreter.run().unwrap(), InterpreterStatus::Terminated);
+        let cpu = interpreter.get_cpu();
+        assert!(cpu.wasm_get_d_reg(1).get_long() >= 1);
+        assert_eq!(cpu.wasm_get_d_reg(2).get_word(), 0x2000);
+        assert_eq!(cpu.wasm_get_d_reg(3).get_word() & 0x0700, 0x0400);
+        assert!(interpreter.raise_interrupt(8).is_err());
+    }
+
+    #[test]
+    fn memory_mapped_devices_receive_the_accesses() {
+        use crate::mmio::MmioDevice;
+        use std::{cell::RefCell, collections::VecDeque, rc::Rc};
+        #[derive(Default)]
+        struct Uart {
+            input: VecDeque<u8>,
+            output: Rc<RefCell<Vec<u8>>>,
+            ticks: u32,
+        }
+        impl MmioDevice for Uart {
+            fn get_name(&self) -> &str {
+                "uart"
+            }
+            fn read(&mut self, offset: usize, _: Size) -> u32 {
+                match offset {
+                    0 => !self.input.is_empty() as u32,
+                    2 => self.input.pop_front().unwrap_or(0) as u32,
+                    _ => {
+                        self.ticks += 1;
+                        self.ticks
+                    }
+                }
+            }
+            fn write(&mut self, offset: usize, _: Size, value: u32) {
+                if offset == 2 {
+                    self.output.borrow_mut().push(value as u8);
+                }
+            }
+        }
+        let code = "    lea $e00000, a0
+loop:
+    btst #0, (a0)
+    beq done
+    move.b 2(a0), d0
+    addi.b #1, d0
+    move.b d0, 2(a0)
+    bra loop
+done:
+    move.l 4(a0), d1
+    move.l 4(a0), d2";
+        let s68k = S68k::new(code.to_string());
+        let compiled = s68k.compile().expect("To compile correctly");
+        let options = InterpreterOptions {
+            keep_history: true,
+            ..Default::default()
+        };
+        let mut interpreter = s68k.create_interpreter(compiled, Some(options));
+        let output = Rc::new(RefCell::new(vec![]));
+        let uart = Uart {
+            input: VecDeque::from(b"HAL".to_vec()),
+            output: output.clone(),
+            ..Default::default()
+        };
+        interpreter.map_device(0xE00000, 0xE00008, Box::new(uart)).unwrap();
+        assert!(interpreter.map_device(0xE00004, 0xE00010, Box::new(Uart::default())).is_err());
+        assert_eq!(interpreter.run().unwrap(), InterpreterStatus::Terminated);
+        assert_eq!(output.borrow().as_slice(), b"IBM");
+        let cpu = interpreter.get_cpu();
+        assert_eq!((cpu.wasm_get_d_reg(1).get_long(), cpu.wasm_get_d_reg(2).get_long()), (1, 2));
+        //the memory under the device is untouched
+        assert_eq!(interpreter.get_memory().peek_bytes(0xE00002, 1).unwrap(), &[0xFF]);
+        assert!(interpreter.unmap_device(0xE00000));
+    }
+
+    #[test]
+    #[cfg(feature = "wasm")]
+    fn interpreter_handle_exposes_cycles_and_interrupts() {
+        let s68k = S68k::new("
+    move.l #1, d0
+    move.l #2, d0".to_string());
+        let mut handle = InterpreterHandle::new(s68k.compile().unwrap(), None);
+        assert_eq!(handle.get_sr() & 0x2000, 0x2000);
+        handle.add_periodic_interrupt(2, 1000);
+        assert_eq!(handle.run(None).unwrap(), InterpreterStatus::Terminated);
+        assert!(handle.get_cycles() > 0);
+        assert_eq!(handle.get_cycles(), handle.get_interpreter().get_cycles());
+        handle.raise_interrupt(5).unwrap();
+        assert_eq!(handle.get_interpreter().get_pending_interrupts(), 1 << 5);
+    }
+
+    #[test]
+    fn source_map_links_addresses_and_lines() {
+        use crate::breakpoints::BreakpointLocation;
+        let code = "swap_regs macro
+    move.l \\1, d2
+    move.l \\2, \\1
+    endm
+start:
+    move.l #1, d0
+    swap_regs d0, d1
+    move.l #2, d0";
+        let s68k = S68k::new(code);
+        let mut interpreter = s68k.create_interpreter(s68k.compile().unwrap(), None);
+        let map = interpreter.get_source_map();
+        assert_eq!(map.get_entries().len(), 4);
+        let addresses = map.get_addresses_of_line(6);
+        assert_eq!(addresses.len(), 2);
+        assert_eq!(map.get_line_at(addresses[1]), Some(6));
+        assert_eq!(map.get_entry_at(addresses[1]).unwrap().macro_line_index, Some(2));
+        assert_eq!(map.get_line_at(addresses[1] + 1), None);
+        assert!(map.get_addresses_of_line(4).is_empty());
+        let first = map.get_first_address_of_line(5).unwrap();
+        assert_eq!(map.get_line_at(first), Some(5));
+        interpreter.add_breakpoint(BreakpointLocation::Line(6)).unwrap();
+        interpreter.run().unwrap();
+        assert_eq!(interpreter.get_pc(), addresses[0]);
+    }
+
+    #[test]
+    fn unknown_data_sizes_are_reported_without_compiling() {
+        use crate::code_actions::get_code_actions;
+        use crate::inlay_hints::get_inlay_hints;
+        use crate::utils::parse_string_into_padded_bytes;
+        assert!(parse_string_into_padded_bytes("hello", 0).is_err());
+        assert_eq!(parse_string_into_padded_bytes("abc", 2), Ok(vec![b'a', b'b', b'c', 0]));
+        for code in ["    dc. 'hello'", "    dc.bx 'a'", "    dc.q 'ab'"] {
+            let s68k = S68k::new(code);
+            assert!(!s68k.get_diagnostics().is_empty());
+            assert!(get_code_actions(&s68k, 0).is_empty());
+            assert!(get_inlay_hints(&s68k).is_empty());
+            assert!(s68k.compile().is_err());
+        }
+    }
+
+    #[test]
+    fn diagnostics_have_codes_and_suggestions() {
+        use crate::diagnostic::{DiagnosticCode, Severity};
+        let s68k = S68k::new("start:
+    mov.l d0, d1
+    rts.w
+    lea (d0*300, a0), a1
+start:");
+        let diagnostics = s68k.get_diagnostics();
+        let find = |line: usize| diagnostics.iter().find(|d| d.get_line_index() == Some(line)).unwrap();
+        assert_eq!(find(1).code, DiagnosticCode::UnknownInstruction);
+        assert_eq!(find(1).get_code(), "E0202");
+        assert_eq!(find(1).severity, Severity::Error);
+        assert_eq!(find(1).span.unwrap().start, 4);
+        let suggestion = find(1).suggestion.as_ref().unwrap();
+        assert_eq!(suggestion.message, "Replace with move");
+        assert_eq!(suggestion.edits[0].new_text, "move");
+        assert_eq!(find(2).code, DiagnosticCode::InvalidSize);
+        assert_eq!(find(2).suggestion.as_ref().unwrap().edits[0].new_text, "");
+        assert_eq!(find(3).code, DiagnosticCode::InvalidScale);
+        assert_eq!(find(4).code, DiagnosticCode::DuplicateLabel);
+        assert_eq!(find(4).related[0].line_index, 0);
+        assert!(find(4).suggestion.is_none());
+        //the assembler errors only come when the checker finds nothing
+        let odd = S68k::new("    dc.b 1\n    move.l d0, d1").get_diagnostics();
+        assert_eq!(odd.len(), 1);
+        assert_eq!(odd[0].code, DiagnosticCode::OddAddress);
+        assert_eq!(odd[0].suggestion.as_ref().unwrap().edits[0].new_text, "    dc.b 0\n");
+        assert!(S68k::new("    move.l d0, d1").get_diagnostics().is_empty());
+    }
+
+    #[test]
+    fn tokenizer_classifies_operands_and_lines() {
+        use crate::lexer::{LexedLine, LexedOperand, Lexer};
+        let lexer = Lexer::new();
+        let kinds = ["(A0)", "4(a0,d1.w)", "([8,a0],d1*4,2)", "(a0){4:8}", "d1.w*4", "d0:d1", "#'a b'", "-(sp)", "label"]
+            .iter()
+            .map(|operand| match lexer.parse_operand(operand).unwrap() {
+                LexedOperand::Indirect(_) => "indirect",
+                LexedOperand::IndirectIndex { .. } => "index",
+                LexedOperand::MemoryIndirect { .. } => "memory",
+                LexedOperand::Bitfield { .. } => "bitfield",
+                LexedOperand::ScaledRegister(..) => "scaled",
+                LexedOperand::RegisterPair(..) => "pair",
+                LexedOperand::Immediate(_) => "immediate",
+                LexedOperand::PreIndirect(_) => "pre",
+                LexedOperand::Label(_) | LexedOperand::Absolute(_) => "absolute",
+                _ => "other",
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            ["indirect", "index", "memory", "bitfield", "scaled", "pair", "immediate", "pre", "absolute"]
+        );
+        let mut lexer = Lexer::new();
+        let lines = lexer.lex("* comment\nSIZE EQU 4\nstart: rts\n\tmove.l d0,d1 *comment").unwrap();
+        let lexed = lines.iter().map(|line| &line.parsed).collect::<Vec<_>>();
+        assert!(matches!(lexed[0], LexedLine::Empty));
+        assert!(matches!(lexed[1], LexedLine::Directive { name, .. } if name == "equ"));
+        assert!(matches!(lexed[2], LexedLine::Label { name, .. } if name == "start"));
+        assert!(matches!(lexed[3], LexedLine::Instruction { name, .. } if name == "rts"));
+        assert!(matches!(lexed[4], LexedLine::Instruction { name, operands, .. } if name == "move" && operands.len() == 2));
+    }
+
+    #[test]
+    fn lexer_update_lexes_only_the_changed_lines() {
+        use crate::lexer::Lexer;
+        let mut source = "start:\n  move.l #1, d0\n.loop:\n  dbra d0, .loop\nnext:\n  bra .loop\n  lea (d0*300, a0), a1\n  rts"
+            .lines()
+            .map(String::from)
+            .collect::<Vec<_>>();
+        let mut lexer = Lexer::new().with_recovery();
+        lexer.lex(&source.join("\n")).unwrap();
+        let edits = [
+            (1..2, "  move.w #2, d1", 1..2),
+            (2..2, "  nop\n  nop", 2..4),
+            //the local labels after the renamed global label are scoped again
+            (0..1, "main:", 0..6),
+            (7..9, "  lea (d0*200, a0), a1", 7..8),
+            (3..3, "size equ 4", 0..10),
+            (5..6, "  move.l #size, d2", 5..6),
+        ];
+        for (range, text, lexed) in edits {
+            assert_eq!(lexer.update(range.clone(), text), lexed);
+            source.splice(range, text.lines().map(String::from));
+            let mut expected = Lexer::new().with_recovery();
+            expected.lex(&source.join("\n")).unwrap();
+            assert_eq!(format!("{:?}", lexer.lines()), format!("{:?}", expected.lines()));
+            assert_eq!(lexer.diagnostics(), expected.diagnostics());
+        }
+    }
+
+    #[test]
+    fn programs_run_at_the_addresses_of_their_machine_code() {
+        let s68k = S68k::new("    move.l #data, a0
+    move.w (a0), d0
+    bsr sub
+    move.l d0, d2
+    bra end
+sub:
+    add.w #5, d0
+    rts
+end:
+data: dc.w 7");
+        let compiled = s68k.compile().unwrap();
+        let assembled = s68k.assemble().unwrap();
+        for name in ["sub", "end", "data"] {
+            assert_eq!(
+                compiled.get_labels_map()[name].address,
+                assembled.get_compiler().get_labels_map()[name].address
+            );
+        }
+        let code = assembled.get_machine_code();
+        let mut interpreter = s68k.create_interpreter(compiled, None);
+        assert_eq!(
+            interpreter.get_memory().peek_bytes(code.origin, code.bytes.len()).unwrap(),
+            &code.bytes[..]
+        );
+        interpreter.run().unwrap();
+        assert_eq!(interpreter.get_cpu().wasm_get_d_reg(2).get_long(), 12);
+    }
+
+    #[test]
+    fn lexer_interns_symbols() {
+        use crate::lexer::Lexer;
+        let mut lexer = Lexer::new();
+        lexer.lex("start: move.l #1, d0\n    add.l d0, d1\n    bra start").unwrap();
+        let symbols = lexer.get_symbols();
+        let start = symbols.get("start").unwrap();
+        assert_eq!(symbols.resolve(start), "start");
+        assert!(symbols.get("d0").is_some());
+        assert!(symbols.get("move").is_some());
+        assert!(symbols.get("#1").is_none());
+        let count = symbols.len();
+        lexer.lex("bra start").unwrap();
+        assert_eq!(lexer.get_symbols().get("start"), Some(start));
+        assert_eq!(lexer.get_symbols().len(), count);
+    }
+
+    #[test]
+    fn label_tables_share_the_symbols_of_the_lexer() {
+        let code = "start:\n    bsr sub\n    bra end\nsub:\n.loop:\n    dbra d0, .loop\n    rts\nend:";
+        let s68k = S68k::new(code);
+        assert!(s68k.get_symbols().get("start").is_some());
+        assert!(s68k.get_symbols().get("sub.loop").is_some());
+        assert!(s68k.semantic_check().is_empty());
+        let compiled = s68k.compile().unwrap();
+        let labels = compiled.get_labels_map();
+        assert_eq!(labels.len(), 4);
+        assert_eq!(labels["sub"].address, 0x1008);
+        let interpreter = s68k.create_interpreter(compiled, None);
+        let table = interpreter.get_labels();
+        let sub = table.get_symbols().get("sub").unwrap();
+        assert_eq!(table.get_by_symbol(sub).map(|label| label.address), Some(0x1008));
+        assert_eq!(interpreter.get_label_at(0x1008).map(|label| label.name.as_str()), Some("sub"));
+    }
+
+    #[test]
+    fn semantic_tokens_classify_by_meaning() {
+        use crate::semantic_tokens::{
+            encode_semantic_tokens, get_semantic_tokens_edit, SemanticTokenType, DECLARATION_MODIFIER,
+        };
+        let code = "count equ 5
+loop: move.l #count, d0 ; load
+    bra loop
+    move.l unknown, d1
+msg: dc.b 'hi', $A0";
+        let tokens = S68k::new(code.to_string()).get_semantic_tokens();
+        let find = |line: usize, start: usize| tokens.iter().find(|t| t.line == line && t.start == start).unwrap();
+        assert_eq!(find(0, 0).token_type, SemanticTokenType::Constant);
+        assert_eq!(find(0, 0).modifiers, DECLARATION_MODIFIER);
+        assert_eq!(find(0, 6).token_type, SemanticTokenType::Directive);
+        assert_eq!(find(0, 10).token_type, SemanticTokenType::Number);
+        assert_eq!(find(1, 0).token_type, SemanticTokenType::Label);
+        assert_eq!(find(1, 0).length, 4);
+        assert_eq!(find(1, 6).token_type, SemanticTokenType::Instruction);
+        assert_eq!(find(1, 6).length, 4);
+        assert_eq!(find(1, 10).token_type, SemanticTokenType::Size);
+        assert_eq!(find(1, 10).length, 2);
+        assert_eq!(find(1, 14).token_type, SemanticTokenType::Constant);
+        assert_eq!(find(1, 21).token_type, SemanticTokenType::Register);
+        assert_eq!(find(1, 24).token_type, SemanticTokenType::Comment);
+        assert_eq!(find(2, 8).token_type, SemanticTokenType::Label);
+        assert_eq!(find(2, 8).modifiers, 0);
+        //words that are not defined are not classified
+        assert!(!tokens.iter().any(|t| t.line == 3 && t.start == 11));
+        assert_eq!(find(4, 5).token_type, SemanticTokenType::Directive);
+        assert_eq!(find(4, 10).token_type, SemanticTokenType::String);
+        assert_eq!(find(4, 16).token_type, SemanticTokenType::Number);
+
+        let previous = encode_semantic_tokens(&tokens);
+        assert_eq!(previous[..5], [0, 0, 5, SemanticTokenType::Constant.get_index(), DECLARATION_MODIFIER]);
+        assert_eq!(get_semantic_tokens_edit(&previous, &previous), None);
+        let changed = code.replace("bra loop", "bra d1");
+        let current = encode_semantic_tokens(&S68k::new(changed).get_semantic_tokens());
+        let edit = get_semantic_tokens_edit(&previous, &current).unwrap();
+        let mut patched = previous.clone();
+        patched.splice(edit.start..edit.start + edit.delete_count, edit.data.clone());
+        assert_eq!(patched, current);
+        assert_eq!(edit.start % 5, 0);
+        assert!(edit.data.len() < current.len());
+    }
+
+    #[test]
+    fn start_label_sets_entry_point() {
+        let compiled = lex_only("ORG $2000
+    move.l d0, d1
+START:
+    move.l d1, d2");
+        assert_eq!(compiled.get_start_address(), 0x2002);
+    }
 
     #[test]
     fn equ_substitution() {
@@ -160,8 +4028,6 @@ end:");
 }
 
 
-const TEST_LIMIT: usize = 3000000;
-
 fn lex_and_run(code: &str) -> Interpreter {
     let options = InterpreterOptions {
         keep_history: false,
@@ -178,8 +4044,7 @@ fn lex_and_run(code: &str) -> Interpreter {
         let status = interpreter.run().unwrap();
         match status {
             InterpreterStatus::Interrupt => {
-                let interrupt = interpreter.get_current_interrupt().unwrap();
-                handle_interrupt(&mut interpreter, &interrupt);
+                interpreter.answer_interrupt_with(&mut TerminalIo).unwrap();
             }
             InterpreterStatus::TerminatedWithException => {
                 panic!("Program Terminated with exception");
@@ -198,62 +4063,3 @@ fn lex_only(code: &str) -> Compiler {
     }
     s68k.compile().expect("To compile correctly")
 }
-
-
-fn handle_interrupt(interpreter: &mut Interpreter, interrupt: &Interrupt) {
-    match interrupt {
-        Interrupt::DisplayNumber(number) => {
-            print!("{}", number);
-            interpreter
-                .answer_interrupt(InterruptResult::DisplayNumber)
-                .unwrap();
-        }
-        Interrupt::DisplayStringWithCRLF(string) => {
-            println!("{}", string);
-            interpreter
-                .answer_interrupt(InterruptResult::DisplayStringWithCRLF)
-                .unwrap();
-        }
-        Interrupt::DisplayStringWithoutCRLF(string) => {
-            print!("{}", string);
-            interpreter
-                .answer_interrupt(InterruptResult::DisplayStringWithoutCRLF)
-                .unwrap();
-        }
-        Interrupt::GetTime => {
-            interpreter
-                .answer_interrupt(InterruptResult::GetTime(0))
-                .unwrap();
-        }
-        Interrupt::DisplayChar(char) => {
-            print!("{}", char);
-            interpreter
-                .answer_interrupt(InterruptResult::DisplayChar)
-                .unwrap();
-        }
-        Interrupt::ReadChar => {
-            let char = Term::stdout().read_char().expect("Unable to read char");
-            interpreter
-                .answer_interrupt(InterruptResult::ReadChar(char))
-                .unwrap();
-        }
-        Interrupt::ReadNumber => {
-            let num = Term::stdout().read_line().expect("Unable to read line");
-            let num = num.trim().parse::<i32>().expect("Unable to parse number");
-            interpreter
-                .answer_interrupt(InterruptResult::ReadNumber(num))
-                .unwrap();
-        }
-        Interrupt::ReadKeyboardString => {
-            let string = Term::stdout().read_line().expect("Unable to read line");
-            interpreter
-                .answer_interrupt(InterruptResult::ReadKeyboardString(string))
-                .unwrap();
-        }
-        Interrupt::Terminate => {
-            interpreter
-                .answer_interrupt(InterruptResult::Terminate)
-                .unwrap();
-        }
-    }
-}
\ No newline at end of file
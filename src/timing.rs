@@ -0,0 +1,625 @@
+use alloc::{format, vec::Vec};
+use crate::collections::HashMap;
+
+use lazy_static::lazy_static;
+use serde::Serialize;
+
+use crate::{
+    cost_report::fits_in_word,
+    cpu_model::CpuModel,
+    instructions::{Instruction, Operand, RegisterOperand, Sign, Size},
+};
+
+/*
+    Cycle timing tables, the values are the register to register timings from the user manuals,
+    the time to calculate the effective address of memory operands is added on top of it.
+    Some instructions have variants with their own base timing, looked up as "name variant":
+        memory              the destination is in memory and is read, modified and written back
+        register            a long ADD, SUB, AND, OR, ADDA or SUBA with a register or immediate source
+        An                  ADDQ and SUBQ to an address register, which always work on the whole register
+        (An), d(An)...      JMP, JSR, LEA and PEA, that only calculate the address of the control operand
+    Absolute addresses take the timing of the short or long form, the one the assembler encodes them in.
+    The 68020 has no exact timings because of the pipeline and cache, the table uses an approximation
+    of the cache-less worst case. The FPU timings are the ones of a 68881 and are the same for every model.
+    Some 68000 timings depend on the data, the interpreter reads it before the instruction runs:
+        shifts by a register     2 cycles for every bit of the count, the register modulo 64
+        MULU                     38 cycles plus 2 for every 1 bit of the source
+        MULS                     38 cycles plus 2 for every 01 or 10 pair of the source with a 0 appended
+    A source the interpreter can't read in advance, like an indexed one, takes the worst case of the table,
+    the divisions always take it, DIVU and DIVS have different ones
+*/
+
+/// The data an instruction reads that changes its timing, known only once it runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RuntimeOperands {
+    pub shift_count: Option<u32>,
+    pub multiplier: Option<u16>,
+}
+
+/// The cycles spent on the instructions of a line, and how many times they ran
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+pub struct LineCycles {
+    pub line_index: usize,
+    pub executions: u64,
+    pub cycles: u64,
+}
+#[derive(Debug, Clone, Serialize)]
+pub struct TimingEntry {
+    pub instruction: &'static str,
+    pub byte_word: u32,
+    pub long: u32,
+}
+
+const fn entry(instruction: &'static str, byte_word: u32, long: u32) -> TimingEntry {
+    TimingEntry {
+        instruction,
+        byte_word,
+        long,
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TimingTable {
+    model: CpuModel,
+    description: &'static str,
+    entries: Vec<TimingEntry>,
+    effective_address: &'static [TimingEntry],
+    /// Cycles saved by each instruction of a one instruction DBcc loop, only the 68010 has loop mode
+    loop_mode_saving: Option<u32>,
+    #[serde(skip)]
+    lookup: HashMap<&'static str, usize>,
+    #[serde(skip)]
+    effective_address_lookup: HashMap<&'static str, usize>,
+}
+
+const FPU_ENTRIES: &[TimingEntry] = &[
+    entry("FMOVE", 33, 33),
+    entry("FADD", 51, 51),
+    entry("FSUB", 51, 51),
+    entry("FMUL", 71, 71),
+    entry("FDIV", 103, 103),
+    entry("FCMP", 33, 33),
+    entry("FBcc", 10, 10),
+];
+
+const M68000_ENTRIES: &[TimingEntry] = &[
+    entry("MOVE", 4, 4),
+    entry("MOVEA", 4, 4),
+    entry("MOVEQ", 4, 4),
+    entry("MOVEM", 8, 8),
+    entry("MOVEM register", 4, 8),
+    entry("ADD", 4, 6),
+    entry("ADD register", 4, 8),
+    entry("ADD memory", 8, 12),
+    entry("SUB", 4, 6),
+    entry("SUB register", 4, 8),
+    entry("SUB memory", 8, 12),
+    entry("CMP", 4, 6),
+    entry("AND", 4, 6),
+    entry("AND register", 4, 8),
+    entry("AND memory", 8, 12),
+    entry("OR", 4, 6),
+    entry("OR register", 4, 8),
+    entry("OR memory", 8, 12),
+    entry("EOR", 4, 8),
+    entry("EOR memory", 8, 12),
+    entry("ADDA", 8, 6),
+    entry("ADDA register", 8, 8),
+    entry("SUBA", 8, 6),
+    entry("SUBA register", 8, 8),
+    entry("CMPA", 6, 6),
+    entry("ADDQ", 4, 8),
+    entry("ADDQ An", 8, 8),
+    entry("ADDQ memory", 8, 12),
+    entry("SUBQ", 4, 8),
+    entry("SUBQ An", 8, 8),
+    entry("SUBQ memory", 8, 12),
+    entry("ADDI", 8, 16),
+    entry("ADDI memory", 12, 20),
+    entry("SUBI", 8, 16),
+    entry("SUBI memory", 12, 20),
+    entry("ANDI", 8, 14),
+    entry("ANDI memory", 12, 20),
+    entry("ORI", 8, 16),
+    entry("ORI memory", 12, 20),
+    entry("EORI", 8, 16),
+    entry("EORI memory", 12, 20),
+    entry("CMPI", 8, 14),
+    entry("CMPI memory", 8, 12),
+    entry("CMPM", 12, 20),
+    entry("MULx", 70, 70),
+    entry("MULx base", 38, 38),
+    entry("MULx bit", 2, 2),
+    entry("DIVU", 140, 140),
+    entry("DIVS", 158, 158),
+    entry("SWAP", 4, 4),
+    entry("CLR", 4, 6),
+    entry("CLR memory", 8, 12),
+    entry("EXG", 6, 6),
+    entry("LEA", 4, 4),
+    entry("LEA (An)", 4, 4),
+    entry("LEA d(An)", 8, 8),
+    entry("LEA d(An,Xn)", 12, 12),
+    entry("LEA Abs.W", 8, 8),
+    entry("LEA Abs.L", 12, 12),
+    entry("PEA", 12, 12),
+    entry("PEA (An)", 12, 12),
+    entry("PEA d(An)", 16, 16),
+    entry("PEA d(An,Xn)", 20, 20),
+    entry("PEA Abs.W", 16, 16),
+    entry("PEA Abs.L", 20, 20),
+    entry("NEG", 4, 6),
+    entry("NEG memory", 8, 12),
+    entry("NOT", 4, 6),
+    entry("NOT memory", 8, 12),
+    entry("EXT", 4, 4),
+    entry("TST", 4, 4),
+    entry("Bcc", 10, 10),
+    //the assembler always encodes the word displacement, the byte one takes 8
+    entry("Bcc not taken", 12, 12),
+    entry("Scc", 6, 6),
+    entry("Scc memory", 8, 8),
+    entry("DBcc", 10, 10),
+    entry("DBcc not taken", 14, 14),
+    entry("BRA", 10, 10),
+    entry("BSR", 18, 18),
+    entry("JSR", 16, 16),
+    entry("JSR (An)", 16, 16),
+    entry("JSR d(An)", 18, 18),
+    entry("JSR d(An,Xn)", 22, 22),
+    entry("JSR Abs.W", 18, 18),
+    entry("JSR Abs.L", 20, 20),
+    entry("JMP", 8, 8),
+    entry("JMP (An)", 8, 8),
+    entry("JMP d(An)", 10, 10),
+    entry("JMP d(An,Xn)", 14, 14),
+    entry("JMP Abs.W", 10, 10),
+    entry("JMP Abs.L", 12, 12),
+    entry("RTS", 16, 16),
+    entry("RTE", 20, 20),
+    entry("RTR", 20, 20),
+    entry("MOVEtoSR", 12, 12),
+    entry("MOVEfromSR", 6, 6),
+    entry("ANDItoSR", 20, 20),
+    entry("ORItoSR", 20, 20),
+    entry("EORItoSR", 20, 20),
+    entry("MOVEUSP", 4, 4),
+    entry("LINK", 16, 16),
+    entry("UNLK", 12, 12),
+    entry("ASd", 6, 8),
+    entry("LSd", 6, 8),
+    entry("ROd", 6, 8),
+    entry("shift count", 2, 2),
+    entry("BTST", 4, 6),
+    entry("BCHG", 8, 8),
+    entry("BSET", 8, 8),
+    entry("BCLR", 10, 10),
+    entry("TRAP", 34, 34),
+    entry("CHK", 10, 10),
+    entry("TRAPV", 4, 4),
+    entry("ILLEGAL", 34, 34),
+    entry("NOP", 4, 4),
+    //taking an autovectored interrupt
+    entry("interrupt", 44, 44),
+    entry("ADDX", 4, 8),
+    entry("SUBX", 4, 8),
+];
+
+const M68000_EFFECTIVE_ADDRESS: &[TimingEntry] = &[
+    entry("(An)", 4, 8),
+    entry("(An)+", 4, 8),
+    entry("-(An)", 6, 10),
+    entry("d(An)", 8, 12),
+    entry("d(An,Xn)", 10, 14),
+    entry("Abs.W", 8, 12),
+    entry("Abs.L", 12, 16),
+    entry("Immediate", 4, 8),
+];
+
+const M68010_ENTRIES: &[TimingEntry] = &[
+    entry("MULx", 42, 42),
+    entry("MULx bit", 0, 0),
+    entry("DIVU", 108, 108),
+    entry("DIVS", 122, 122),
+    entry("DBcc not taken", 16, 16),
+    entry("RTE", 24, 24),
+    entry("MOVEC", 10, 12),
+    entry("MOVES", 18, 22),
+    entry("RTD", 16, 16),
+    entry("TRAP", 38, 38),
+    entry("interrupt", 46, 46),
+];
+
+const M68020_ENTRIES: &[TimingEntry] = &[
+    entry("MOVE", 4, 4),
+    entry("MOVEA", 4, 4),
+    entry("MOVEQ", 4, 4),
+    entry("MOVEM", 8, 8),
+    entry("MOVEM register", 4, 4),
+    entry("ADD", 4, 4),
+    entry("SUB", 4, 4),
+    entry("CMP", 4, 4),
+    entry("AND", 4, 4),
+    entry("OR", 4, 4),
+    entry("EOR", 4, 4),
+    entry("ADDA", 4, 4),
+    entry("SUBA", 4, 4),
+    entry("CMPA", 6, 6),
+    entry("ADDQ", 4, 4),
+    entry("SUBQ", 4, 4),
+    entry("ADDI", 6, 8),
+    entry("SUBI", 6, 8),
+    entry("ANDI", 6, 8),
+    entry("ORI", 6, 8),
+    entry("EORI", 6, 8),
+    entry("CMPI", 6, 8),
+    entry("CMPM", 10, 10),
+    entry("MULx", 28, 28),
+    entry("DIVU", 44, 44),
+    entry("DIVS", 56, 56),
+    entry("MULxL", 45, 45),
+    entry("DIVxL", 90, 90),
+    entry("SWAP", 4, 4),
+    entry("CLR", 4, 4),
+    entry("EXG", 4, 4),
+    entry("LEA", 4, 4),
+    entry("PEA", 7, 7),
+    entry("NEG", 4, 4),
+    entry("NOT", 4, 4),
+    entry("EXT", 4, 4),
+    entry("TST", 4, 4),
+    entry("Bcc", 9, 9),
+    entry("Bcc not taken", 6, 6),
+    entry("Scc", 6, 6),
+    entry("DBcc", 9, 9),
+    entry("DBcc not taken", 12, 12),
+    entry("BRA", 9, 9),
+    entry("BSR", 9, 9),
+    entry("JSR", 9, 9),
+    entry("JMP", 6, 6),
+    entry("RTS", 12, 12),
+    entry("RTE", 24, 24),
+    entry("RTR", 14, 14),
+    entry("MOVEtoSR", 8, 8),
+    entry("MOVEfromSR", 4, 4),
+    entry("ANDItoSR", 12, 12),
+    entry("ORItoSR", 12, 12),
+    entry("EORItoSR", 12, 12),
+    entry("MOVEUSP", 4, 4),
+    entry("LINK", 8, 8),
+    entry("UNLK", 9, 9),
+    entry("ASd", 8, 8),
+    entry("LSd", 8, 8),
+    entry("ROd", 8, 8),
+    //the barrel shifter takes the same time for every count
+    entry("shift count", 0, 0),
+    entry("BTST", 4, 4),
+    entry("BCHG", 6, 6),
+    entry("BSET", 6, 6),
+    entry("BCLR", 6, 6),
+    entry("TRAP", 25, 25),
+    entry("CHK", 8, 8),
+    entry("ILLEGAL", 20, 20),
+    entry("NOP", 2, 2),
+    entry("interrupt", 26, 26),
+    entry("MOVEC", 9, 9),
+    entry("MOVES", 10, 10),
+    entry("RTD", 12, 12),
+    entry("BFx", 16, 16),
+    entry("BFx memory", 24, 24),
+    entry("ADDX", 4, 4),
+    entry("SUBX", 4, 4),
+];
+
+const M68020_EFFECTIVE_ADDRESS: &[TimingEntry] = &[
+    entry("(An)", 4, 4),
+    entry("(An)+", 4, 4),
+    entry("-(An)", 5, 5),
+    entry("d(An)", 5, 5),
+    entry("d(An,Xn)", 7, 7),
+    entry("([bd,An],Xn,od)", 12, 12),
+    entry("Abs.W", 4, 4),
+    entry("Abs.L", 5, 5),
+    entry("Immediate", 2, 4),
+];
+
+lazy_static! {
+    static ref M68000_TABLE: TimingTable = TimingTable::new(
+        CpuModel::M68000,
+        "68000, register timings from the user manual",
+        [M68000_ENTRIES, FPU_ENTRIES].concat(),
+        M68000_EFFECTIVE_ADDRESS,
+        None,
+    );
+    static ref M68010_TABLE: TimingTable = TimingTable::new(
+        CpuModel::M68010,
+        "68010, like the 68000 with faster multiply/divide and loop mode for one instruction DBcc loops",
+        [M68010_ENTRIES, M68000_ENTRIES, FPU_ENTRIES].concat(),
+        M68000_EFFECTIVE_ADDRESS,
+        Some(4),
+    );
+    static ref M68020_TABLE: TimingTable = TimingTable::new(
+        CpuModel::M68020,
+        "68020, approximation of the worst case timings without the instruction cache",
+        [M68020_ENTRIES, FPU_ENTRIES].concat(),
+        M68020_EFFECTIVE_ADDRESS,
+        None,
+    );
+    static ref CPU32_TABLE: TimingTable = TimingTable::new(
+        CpuModel::CPU32,
+        "CPU32, uses the 68020 approximation",
+        [M68020_ENTRIES, FPU_ENTRIES].concat(),
+        M68020_EFFECTIVE_ADDRESS,
+        None,
+    );
+}
+
+pub fn get_timing_table(model: CpuModel) -> &'static TimingTable {
+    match model {
+        CpuModel::M68000 => &M68000_TABLE,
+        CpuModel::M68010 => &M68010_TABLE,
+        CpuModel::M68020 => &M68020_TABLE,
+        CpuModel::CPU32 => &CPU32_TABLE,
+    }
+}
+
+impl TimingTable {
+    fn new(
+        model: CpuModel,
+        description: &'static str,
+        entries: Vec<TimingEntry>,
+        effective_address: &'static [TimingEntry],
+        loop_mode_saving: Option<u32>,
+    ) -> Self {
+        //the first entry wins, so model specific entries are put before the shared ones
+        let mut lookup = HashMap::new();
+        let mut unique = Vec::new();
+        for entry in entries {
+            if !lookup.contains_key(entry.instruction) {
+                lookup.insert(entry.instruction, unique.len());
+                unique.push(entry);
+            }
+        }
+        let effective_address_lookup = effective_address
+            .iter()
+            .enumerate()
+            .map(|(i, entry)| (entry.instruction, i))
+            .collect();
+        Self {
+            model,
+            description,
+            entries: unique,
+            effective_address,
+            loop_mode_saving,
+            lookup,
+            effective_address_lookup,
+        }
+    }
+    pub fn get_model(&self) -> CpuModel {
+        self.model
+    }
+    pub fn get_description(&self) -> &'static str {
+        self.description
+    }
+    pub fn get_entries(&self) -> &[TimingEntry] {
+        &self.entries
+    }
+    pub fn get_effective_address_entries(&self) -> &'static [TimingEntry] {
+        self.effective_address
+    }
+    pub fn get_loop_mode_saving(&self) -> Option<u32> {
+        self.loop_mode_saving
+    }
+    pub fn get_entry(&self, instruction: &str) -> Option<&TimingEntry> {
+        self.lookup.get(instruction).map(|i| &self.entries[*i])
+    }
+    pub fn get_cycles(&self, instruction: &str, size: Size) -> u32 {
+        match self.get_entry(instruction) {
+            Some(entry) => match size {
+                Size::Long => entry.long,
+                _ => entry.byte_word,
+            },
+            None => 0,
+        }
+    }
+    /// Cycles of the variant of the instruction, or of the instruction when the model has no such variant
+    fn get_variant_cycles(&self, instruction: &str, variant: Option<&str>, size: Size) -> u32 {
+        let entry = variant.and_then(|variant| self.get_entry(&format!("{} {}", instruction, variant)));
+        match entry {
+            Some(entry) => match size {
+                Size::Long => entry.long,
+                _ => entry.byte_word,
+            },
+            None => self.get_cycles(instruction, size),
+        }
+    }
+    /// JMP, JSR, LEA and PEA take the time of the control mode instead of the one to read the operand
+    fn get_control_cycles(&self, instruction: &str, operand: &Operand) -> u32 {
+        let entry = get_mode_name(operand).and_then(|mode| self.get_entry(&format!("{} {}", instruction, mode)));
+        match entry {
+            Some(entry) => entry.long,
+            None => self.get_cycles(instruction, Size::Long) + self.get_operand_cycles(operand, Size::Long),
+        }
+    }
+    fn get_operand_cycles(&self, operand: &Operand, size: Size) -> u32 {
+        let Some(name) = get_mode_name(operand) else {
+            return 0;
+        };
+        match self.effective_address_lookup.get(name) {
+            Some(i) => match size {
+                Size::Long => self.effective_address[*i].long,
+                _ => self.effective_address[*i].byte_word,
+            },
+            None => 0,
+        }
+    }
+    /// Cycles taken by an instruction, the branch_taken flag selects the timing of conditional branches
+    pub fn get_instruction_cycles(&self, ins: &Instruction, branch_taken: bool) -> u32 {
+        self.get_executed_cycles(ins, branch_taken, RuntimeOperands::default())
+    }
+    /// Like get_instruction_cycles, with the timings that depend on the data when it is known
+    pub fn get_executed_cycles(&self, ins: &Instruction, branch_taken: bool, operands: RuntimeOperands) -> u32 {
+        let name = ins.get_instruction_name();
+        match ins {
+            Instruction::MOVE(source, dest, size)
+            | Instruction::CMPM(source, dest, size)
+            | Instruction::MOVES(source, dest, size) => {
+                self.get_cycles(&name, *size)
+                    + self.get_operand_cycles(source, *size)
+                    + self.get_operand_cycles(dest, *size)
+            }
+            Instruction::ADD(source, dest, size)
+            | Instruction::SUB(source, dest, size)
+            | Instruction::AND(source, dest, size)
+            | Instruction::OR(source, dest, size)
+            | Instruction::EOR(source, dest, size) => {
+                let variant = match (source, dest) {
+                    (_, Operand::Register(_)) if is_register_or_immediate(source) => Some("register"),
+                    (_, Operand::Register(_)) => None,
+                    _ => Some("memory"),
+                };
+                self.get_variant_cycles(&name, variant, *size)
+                    + self.get_operand_cycles(source, *size)
+                    + self.get_operand_cycles(dest, *size)
+            }
+            Instruction::ADDA(source, _, size) | Instruction::SUBA(source, _, size) => {
+                let variant = match is_register_or_immediate(source) {
+                    true => Some("register"),
+                    false => None,
+                };
+                self.get_variant_cycles(&name, variant, *size) + self.get_operand_cycles(source, *size)
+            }
+            Instruction::CMPA(source, _, size)
+            | Instruction::MOVEA(source, _, size)
+            | Instruction::CMP(source, _, size) => {
+                self.get_cycles(&name, *size) + self.get_operand_cycles(source, *size)
+            }
+            Instruction::ADDQ(_, dest, size)
+            | Instruction::SUBQ(_, dest, size)
+            | Instruction::ADDI(_, dest, size)
+            | Instruction::SUBI(_, dest, size)
+            | Instruction::ANDI(_, dest, size)
+            | Instruction::ORI(_, dest, size)
+            | Instruction::EORI(_, dest, size)
+            | Instruction::CMPI(_, dest, size)
+            | Instruction::CLR(dest, size)
+            | Instruction::NEG(dest, size)
+            | Instruction::NOT(dest, size)
+            | Instruction::TST(dest, size) => {
+                let variant = match dest {
+                    Operand::Register(RegisterOperand::Address(_)) => Some("An"),
+                    Operand::Register(_) => None,
+                    _ => Some("memory"),
+                };
+                self.get_variant_cycles(&name, variant, *size) + self.get_operand_cycles(dest, *size)
+            }
+            Instruction::ASd(amount, dest, _, size)
+            | Instruction::LSd(amount, dest, _, size)
+            | Instruction::ROd(amount, dest, _, size) => {
+                //the count in a register is only known once the instruction runs
+                let count = match (amount, operands.shift_count) {
+                    (Operand::Immediate(count), _) => *count,
+                    (_, Some(count)) => count,
+                    _ => 1,
+                };
+                self.get_cycles(&name, *size)
+                    + self.get_cycles("shift count", *size) * count
+                    + self.get_operand_cycles(dest, *size)
+            }
+            Instruction::MOVEM {
+                size,
+                registers_mask,
+                target,
+                ..
+            } => {
+                self.get_cycles("MOVEM", *size)
+                    + self.get_cycles("MOVEM register", *size) * registers_mask.count_ones()
+                    + self.get_operand_cycles(target, *size)
+            }
+            Instruction::MULx(source, _, sign) => {
+                let bit = self.get_cycles("MULx bit", Size::Word);
+                let cycles = match operands.multiplier {
+                    Some(multiplier) if bit > 0 => {
+                        let bits = match sign {
+                            Sign::Unsigned => multiplier.count_ones(),
+                            Sign::Signed => {
+                                let appended = (multiplier as u32) << 1;
+                                ((appended ^ (appended >> 1)) & 0xFFFF).count_ones()
+                            }
+                        };
+                        self.get_cycles("MULx base", Size::Word) + bit * bits
+                    }
+                    _ => self.get_cycles(&name, Size::Word),
+                };
+                cycles + self.get_operand_cycles(source, Size::Word)
+            }
+            Instruction::DIVx(source, _, sign) => {
+                let name = match sign {
+                    Sign::Unsigned => "DIVU",
+                    Sign::Signed => "DIVS",
+                };
+                self.get_cycles(name, Size::Word) + self.get_operand_cycles(source, Size::Word)
+            }
+            Instruction::CHK(source, _) => {
+                self.get_cycles(&name, Size::Word) + self.get_operand_cycles(source, Size::Word)
+            }
+            Instruction::MULxL { source, .. } | Instruction::DIVxL { source, .. } => {
+                self.get_cycles(&name, Size::Long) + self.get_operand_cycles(source, Size::Long)
+            }
+            Instruction::LEA(source, _)
+            | Instruction::PEA(source)
+            | Instruction::JSR(source)
+            | Instruction::JMP(source) => self.get_control_cycles(&name, source),
+            Instruction::Scc(dest, _) => match dest {
+                Operand::Register(_) => self.get_cycles(&name, Size::Byte),
+                _ => self.get_variant_cycles(&name, Some("memory"), Size::Byte) + self.get_operand_cycles(dest, Size::Byte),
+            },
+            Instruction::BTST(_, dest)
+            | Instruction::BCHG(_, dest)
+            | Instruction::BSET(_, dest)
+            | Instruction::BCLR(_, dest) => {
+                let size = match dest {
+                    Operand::Register(_) => Size::Long,
+                    _ => Size::Byte,
+                };
+                self.get_cycles(&name, size) + self.get_operand_cycles(dest, size)
+            }
+            Instruction::Bcc(..) | Instruction::DBcc(..) => match branch_taken {
+                true => self.get_cycles(&name, Size::Word),
+                false => self.get_cycles(&format!("{} not taken", name), Size::Word),
+            },
+            Instruction::BFx { target, .. } => match target {
+                Operand::Register(_) => self.get_cycles("BFx", Size::Long),
+                _ => self.get_cycles("BFx memory", Size::Long) + self.get_operand_cycles(target, Size::Long),
+            },
+            Instruction::EXT(_, _, size) => self.get_cycles(&name, *size),
+            Instruction::MOVEtoSR(operand, _) | Instruction::MOVEfromSR(operand, _) => {
+                self.get_cycles(&name, Size::Word) + self.get_operand_cycles(operand, Size::Word)
+            }
+            Instruction::MOVEC { .. } => self.get_cycles("MOVEC", Size::Long),
+            _ => self.get_cycles(&name, Size::Word),
+        }
+    }
+}
+
+/// Name of the addressing mode in the effective address timings, none for the registers
+fn get_mode_name(operand: &Operand) -> Option<&'static str> {
+    match operand {
+        Operand::Indirect(_) => Some("(An)"),
+        Operand::PostIndirect(_) => Some("(An)+"),
+        Operand::PreIndirect(_) => Some("-(An)"),
+        Operand::IndirectDisplacement { .. } => Some("d(An)"),
+        Operand::IndirectIndex { .. } => Some("d(An,Xn)"),
+        Operand::MemoryIndirect { .. } => Some("([bd,An],Xn,od)"),
+        Operand::Absolute(address) => match fits_in_word(*address as i32 as i64) {
+            true => Some("Abs.W"),
+            false => Some("Abs.L"),
+        },
+        Operand::Immediate(_) => Some("Immediate"),
+        Operand::Register(_) | Operand::RegisterList(_) => None,
+    }
+}
+
+fn is_register_or_immediate(operand: &Operand) -> bool {
+    matches!(operand, Operand::Register(_) | Operand::Immediate(_))
+}
@@ -0,0 +1,158 @@
+use alloc::{format, string::{String, ToString}, vec::Vec};
+use serde::Serialize;
+
+use crate::{
+    debugger::MutationOperation,
+    instructions::{RegisterOperand, Size},
+    interpreter::{Interpreter, InterpreterStatus, RuntimeResult},
+};
+
+/*
+    Recording of the steps of a run, to be exported and analyzed in other tools. Every step has the address
+    and source line of the instruction, the registers and CCR after it and the memory it wrote.
+
+    JSON is an array of the steps, CSV has a row for every step with the columns:
+        step,pc,line,instruction,d0..d7,a0..a7,ccr,cycles,writes
+    where the writes are "address:bytes" in hex separated by spaces, line numbers start from 1.
+
+    The memory writes come from the history of the interpreter, they are only recorded if it keeps it,
+    a history size of 1 is enough
+*/
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MemoryWrite {
+    pub address: usize,
+    /// The bytes after the write
+    pub bytes: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TraceStep {
+    pub step: usize,
+    pub pc: usize,
+    pub line_index: usize,
+    /// The source of the instruction, trimmed
+    pub instruction: String,
+    /// d0-d7 then a0-a7, after the step
+    pub registers: Vec<u32>,
+    /// XNZVC like the real CCR register
+    pub ccr: u8,
+    pub cycles: u64,
+    pub memory_writes: Vec<MemoryWrite>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct TraceRecorder {
+    steps: Vec<TraceStep>,
+    /// Steps that are kept, the oldest ones are removed
+    limit: Option<usize>,
+}
+
+/// Quotes a field of a CSV row if it needs it
+fn escape_csv(field: &str) -> String {
+    match field.contains([',', '"', '\n']) {
+        true => format!("\"{}\"", field.replace('"', "\"\"")),
+        false => field.to_string(),
+    }
+}
+
+impl TraceRecorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn with_limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+    /// Runs a step of the interpreter and records it, a failed step is not recorded
+    pub fn record_step(&mut self, interpreter: &mut Interpreter) -> RuntimeResult<InterpreterStatus> {
+        let pc = interpreter.get_pc();
+        let (line_index, instruction) = match interpreter.get_instruction_at(pc) {
+            Some(line) => (line.parsed_line.line_index, line.parsed_line.line.trim().to_string()),
+            None => (0, String::new()),
+        };
+        let status = interpreter.step()?;
+        let registers = (0..8)
+            .map(RegisterOperand::Data)
+            .chain((0..8).map(RegisterOperand::Address))
+            .map(|register| interpreter.get_register_value(&register, Size::Long))
+            .collect();
+        let memory = interpreter.get_memory();
+        let memory_writes = interpreter
+            .get_previous_mutations()
+            .map(|mutations| {
+                mutations
+                    .iter()
+                    .filter_map(|mutation| {
+                        let (address, length) = match mutation {
+                            MutationOperation::WriteMemory { address, size, .. } => (*address, size.to_bytes()),
+                            MutationOperation::WriteMemoryBytes { address, old } => (*address, old.len()),
+                            _ => return None,
+                        };
+                        let bytes = memory.read_bytes(address, length).ok()?.to_vec();
+                        Some(MemoryWrite { address, bytes })
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        let step = TraceStep {
+            step: self.steps.last().map(|step| step.step + 1).unwrap_or(0),
+            pc,
+            line_index,
+            instruction,
+            registers,
+            ccr: interpreter.get_cpu().wasm_get_ccr().to_ccr(),
+            cycles: interpreter.get_cycles(),
+            memory_writes,
+        };
+        self.steps.push(step);
+        if let Some(limit) = self.limit {
+            if self.steps.len() > limit {
+                self.steps.remove(0);
+            }
+        }
+        Ok(status)
+    }
+    pub fn get_steps(&self) -> &Vec<TraceStep> {
+        &self.steps
+    }
+    pub fn clear(&mut self) {
+        self.steps.clear();
+    }
+    #[cfg(feature = "serialize")]
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(&self.steps).unwrap_or_default()
+    }
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("step,pc,line,instruction");
+        for name in ["d", "a"] {
+            for index in 0..8 {
+                csv.push_str(&format!(",{}{}", name, index));
+            }
+        }
+        csv.push_str(",ccr,cycles,writes\n");
+        for step in &self.steps {
+            let registers: Vec<String> = step.registers.iter().map(|value| format!("{:08X}", value)).collect();
+            let writes: Vec<String> = step
+                .memory_writes
+                .iter()
+                .map(|write| {
+                    let bytes: String = write.bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+                    format!("{:X}:{}", write.address, bytes)
+                })
+                .collect();
+            csv.push_str(&format!(
+                "{},{:X},{},{},{},{:05b},{},{}\n",
+                step.step,
+                step.pc,
+                step.line_index + 1,
+                escape_csv(&step.instruction),
+                registers.join(","),
+                step.ccr,
+                step.cycles,
+                writes.join(" ")
+            ));
+        }
+        csv
+    }
+}
@@ -11,7 +11,12 @@ export type Interrupt = { type: "DisplayStringWithCRLF", value: string } |
 { type: "ReadChar" } |
 { type: "GetTime" } |
 { type: "Terminate" } | 
-{ type: "DisplayChar", value: string }
+{ type: "DisplayChar", value: string } |
+{ type: "IsInputPending" } |
+{ type: "ClearScreen" } |
+{ type: "PositionCursor", value: [number, number] } |
+{ type: "SetEcho", value: boolean } |
+{ type: "ReadNumberWithPrompt", value: string }
 "#;
 
 #[wasm_bindgen(typescript_custom_section)]
@@ -24,20 +29,42 @@ export type InterruptResult = { type: "DisplayStringWithCRLF" } |
 { type: "ReadChar", value: string } |
 { type: "GetTime", value: number } |
 { type: "DisplayChar" } | 
-{ type: "Terminate" }
+{ type: "Terminate" } |
+{ type: "IsInputPending", value: boolean } |
+{ type: "ClearScreen" } |
+{ type: "PositionCursor" } |
+{ type: "SetEcho" } |
+{ type: "ReadNumberWithPrompt", value: number }
 "#;
 
 #[wasm_bindgen(typescript_custom_section)]
 pub const IRuntimeError: &'static str = r#"
 export type RuntimeError = { type: "Raw", value: string } |
 { type: "ExecutionLimit", value: number } |
+{ type: "SandboxLimit", value: SandboxLimit } |
 { type: "OutOfBounds", value: string } |
 { type: "DivisionByZero" } |
+{ type: "Exception", value: Exception } |
 { type: "IncorrectAddressingMode", value: string } |
+{ type: "UnsupportedInstruction", value: string } |
 { type: "Unimplemented" } |
-{ type: "AddressError", address: number, size: Size }
+{ type: "AddressError", address: number, size: Size } |
+{ type: "ProtectedWrite", value: number } |
+{ type: "PrivilegeViolation", value: string } |
+{ type: "AccessViolation", value: { address: number, access: MemoryAccess, region: string | null } }
+export type Exception = { type: "AddressError" | "IllegalInstruction" | "DivideByZero" | "Chk" | "TrapV" | "PrivilegeViolation" } |
+{ type: "Trap" | "Autovector", value: number }
 
+"#;
 
+#[wasm_bindgen(typescript_custom_section)]
+pub const ISandboxLimit: &'static str = r#"
+export type SandboxLimit = { type: "Instructions", value: number } |
+{ type: "Cycles", value: number } |
+{ type: "WallTime", value: number } |
+{ type: "Output", value: number } |
+{ type: "Traps", value: number } |
+{ type: "Memory", value: number }
 "#;
 
 #[wasm_bindgen(typescript_custom_section)]
@@ -73,7 +100,26 @@ pub const IParsedLine: &'static str = r#"
 export type ParsedLine = {
     line: string,
     line_index: number,
-    parsed: LexedLine
+    parsed: LexedLine,
+    code_span: TextSpan,
+    operand_spans: TextSpan[],
+    macro_origin: MacroOrigin | null
+}"#;
+
+#[wasm_bindgen(typescript_custom_section)]
+pub const IMacroOrigin: &'static str = r#"
+export type MacroOrigin = {
+    name: string,
+    line_index: number,
+    expansion: number
+}"#;
+
+#[wasm_bindgen(typescript_custom_section)]
+pub const ITextSpan: &'static str = r#"
+export type TextSpan = {
+    line_index: number,
+    start: number,
+    end: number
 }"#;
 
 #[wasm_bindgen(typescript_custom_section)]
@@ -88,7 +134,9 @@ export type LexedLine = {
 } | {
     type: "Label",
     value: {
-        name: string
+        name: string,
+        local: boolean,
+        owner: string | null
     }
 } | {
     type: "Directive",
@@ -107,6 +155,68 @@ export type LexedLine = {
     value: {
         content: string
     }
+} | {
+    type: "Error",
+    value: {
+        content: string,
+        diagnostics: LexError[]
+    }
+}
+"#;
+
+#[wasm_bindgen(typescript_custom_section)]
+pub const ILexError: &'static str = r#"
+export type LexError = {
+    type: "InvalidRegister" | "InvalidRegisterRange" | "InvalidMemoryIndirect" | "InvalidSize" | "InvalidScale" | "InvalidIndirect" | "InvalidBitfield",
+    value: string
+} | {
+    type: "InvalidMacro",
+    value: MacroError
+} | {
+    type: "InvalidConditional",
+    value: ConditionalError
+} | {
+    type: "InvalidRepeat",
+    value: RepeatError
+} | {
+    type: "OnLine",
+    value: {
+        span: TextSpan,
+        error: LexError
+    }
+}
+export type ConditionalError = {
+    type: "Unterminated" | "MissingArgument",
+    value: string
+} | {
+    type: "InvalidExpression",
+    value: ExpressionError
+} | {
+    type: "UnexpectedElse" | "UnexpectedEndc" | "DuplicateElse"
+}
+export type ExpressionError = {
+    type: "InvalidNumber" | "Overflow" | "UnknownSymbol" | "UnexpectedToken",
+    value: string
+} | {
+    type: "InvalidShift" | "InvalidExponent",
+    value: number
+} | {
+    type: "Empty" | "UnexpectedEnd" | "DivisionByZero" | "TooDeep"
+}
+export type RepeatError = {
+    type: "InvalidCount",
+    value: ExpressionError
+} | {
+    type: "NegativeCount",
+    value: number
+} | {
+    type: "Unterminated" | "UnexpectedEndr" | "MissingCount" | "TooLarge"
+}
+export type MacroError = {
+    type: "Unterminated" | "NestedDefinition" | "Duplicate" | "TooDeep" | "TooLarge",
+    value: string
+} | {
+    type: "UnexpectedEndm"
 }
 "#;
 
@@ -114,7 +224,23 @@ export type LexedLine = {
 pub const IInterpreterOptions: &'static str = r#"
 export type InterpreterOptions = {
     keep_history: boolean
-    history_size: number    
+    history_size: number
+    cpu_model?: CpuModel
+    fpu?: boolean
+    lesson_profile?: LessonProfile | null
+    memory_regions?: MemoryRegion[]
+}
+export type MemoryAccess = "Read" | "Write" | "Execute"
+export type MemoryRegion = {
+    name: string,
+    start: number,
+    end: number,
+    /** The flags joined by |, like "READ | WRITE" */
+    permissions: string
+}
+export type LessonProfile = {
+    name: string,
+    instructions: string[]
 }
 "#;
 #[wasm_bindgen(typescript_custom_section)]
@@ -128,7 +254,57 @@ export type ExecutionStep = {
     new_ccr: {
         bits: number,
     },
-    line: number
+    line: number,
+    cycles: number
+}
+"#;
+#[wasm_bindgen(typescript_custom_section)]
+pub const IStepResult: &'static str = r#"
+export type StateChange = {
+    type: "Register",
+    value: {
+        register: RegisterOperand,
+        old: number,
+        new: number
+    }
+} | {
+    type: "StatusRegister",
+    value: {
+        old: number,
+        new: number
+    }
+} | {
+    type: "Memory",
+    value: {
+        address: number,
+        bytes: number[]
+    }
+}
+export type StepResult = {
+    line_index: number | null,
+    pc: number,
+    status: "Running" | "Interrupt" | "Terminated" | "TerminatedWithException",
+    executed: number,
+    changes: StateChange[],
+    break_hit: BreakHit | null
+}
+export type BreakpointLocation = { type: "Line", value: number } |
+{ type: "Label", value: string } |
+{ type: "Address", value: number }
+export type BreakHit = {
+    type: "Breakpoint",
+    value: {
+        id: number,
+        address: number
+    }
+} | {
+    type: "Watchpoint",
+    value: {
+        id: number,
+        address: number,
+        length: number,
+        access: MemoryAccess
+    }
 }
 "#;
 #[wasm_bindgen(typescript_custom_section)]
@@ -153,6 +329,23 @@ export type MutationOperation = {
         address: number,
         old: number[]
     }
+} | {
+    type: "WriteControlRegister",
+    value: {
+        register: ControlRegister,
+        old: number
+    }
+} | {
+    type: "WriteStatusRegister",
+    value: {
+        old: number
+    }
+} | {
+    type: "WriteCoprocessorState",
+    value: {
+        id: number,
+        old: number[]
+    }
 }
 "#;
 #[wasm_bindgen(typescript_custom_section)]
@@ -175,6 +368,11 @@ export type LexedOperand = {
 } | {
     type: "Label",
     value: string
+} | {
+    type: "RegisterRange",
+    value: {
+        mask: number
+    }
 } | {
     type: "Other",
     value: string
@@ -190,6 +388,25 @@ export type LexedOperand = {
         offset: String,
         operands: LexedOperand[]
     }
+} | {
+    type: "MemoryIndirect",
+    value: {
+        inner: LexedOperand[],
+        outer: LexedOperand[]
+    }
+} | {
+    type: "ScaledRegister",
+    value: [type: LexedRegisterType, name: string, size: LexedSize, scale: number]
+} | {
+    type: "RegisterPair",
+    value: [high: string, low: string]
+} | {
+    type: "Bitfield",
+    value: {
+        operand: LexedOperand,
+        offset: string,
+        width: string
+    }
 }
 "#;
 #[wasm_bindgen(typescript_custom_section)]
@@ -197,5 +414,358 @@ pub const ILexedRegisterType: &'static str = r#"
 export enum LexedRegisterType {
     LexedData = "Data",
     LexedAddress = "Address",
+    LexedSP = "SP",
+    LexedFloat = "Float",
+}
+"#;
+#[wasm_bindgen(typescript_custom_section)]
+pub const ITimingTable: &'static str = r#"
+export type TimingEntry = {
+    instruction: string,
+    byte_word: number,
+    long: number
+}
+export type TimingTable = {
+    model: CpuModel,
+    description: string,
+    entries: TimingEntry[],
+    effective_address: TimingEntry[],
+    loop_mode_saving: number | null
+}
+export type SourceMapEntry = {
+    address: number,
+    line_index: number,
+    macro_line_index: number | null
+}
+export type LineCycles = {
+    line_index: number,
+    executions: number,
+    cycles: number
+}
+"#;
+#[wasm_bindgen(typescript_custom_section)]
+pub const IAssembledProgram: &'static str = r#"
+export type EncodedLine = {
+    line_index: number,
+    address: number,
+    length: number
+}
+export type MachineCode = {
+    origin: number,
+    bytes: number[],
+    start_address: number,
+    lines: EncodedLine[]
+}
+export type ListingRow = {
+    line_index: number,
+    address: number | null,
+    bytes: number[],
+    source: string,
+    macro_name: string | null
+}
+export type MapSymbol = {
+    name: string,
+    kind: "Label" | "Equ" | "External",
+    value: number,
+    size: number,
+    line_index: number
+}
+export type AssembledProgram = {
+    machine_code: MachineCode,
+    listing: ListingRow[],
+    symbols: MapSymbol[]
+}
+"#;
+#[wasm_bindgen(typescript_custom_section)]
+pub const IFormatterOptions: &'static str = r#"
+export type LetterCase = "Lower" | "Upper" | "Preserve"
+export type FormatterOptions = {
+    label_column: number,
+    mnemonic_column: number,
+    operands_column: number,
+    comment_column: number,
+    case: LetterCase,
+    space_after_comma: boolean
+}
+"#;
+#[cfg(feature = "wasm")]
+#[wasm_bindgen(typescript_custom_section)]
+pub const IDiagnostic: &'static str = r#"
+export type RelatedSpan = {
+    line_index: number,
+    start: number,
+    end: number,
+    message: string
+}
+export type Severity = "Error" | "Warning" | "Info"
+export type DiagnosticCode = "InvalidRegister" | "InvalidRegisterRange" | "InvalidMemoryIndirect" | "InvalidRegisterSize"
+    | "InvalidScale" | "InvalidIndirect" | "InvalidBitfield" | "InvalidMacro" | "InvalidConditional" | "InvalidRepeat"
+    | "InvalidStatement" | "UnknownInstruction" | "DuplicateLabel" | "InvalidSize" | "InvalidAddressingMode"
+    | "OperandCount" | "ValueOutOfRange" | "UnsupportedByModel" | "NotInLesson" | "InvalidDirectiveArguments"
+    | "UnknownDirective" | "InvalidInstruction" | "InvalidDirective" | "OddAddress" | "InvalidOrg" | "OrgBelowAddress"
+    | "InvalidLength" | "UnencodableOperand" | "FieldOutOfRange" | "UnstableLayout"
+export type Suggestion = {
+    message: string,
+    edits: TextEdit[]
+}
+export type Diagnostic = {
+    severity: Severity,
+    code: DiagnosticCode,
+    span: TextSpan | null,
+    message: string,
+    suggestion: Suggestion | null,
+    related: RelatedSpan[]
+}
+"#;
+#[cfg(feature = "wasm")]
+#[wasm_bindgen(typescript_custom_section)]
+pub const ISemanticTokensEdit: &'static str = r#"
+export type SemanticTokensEdit = {
+    start: number,
+    delete_count: number,
+    data: number[]
+}
+"#;
+#[cfg(feature = "wasm")]
+#[wasm_bindgen(typescript_custom_section)]
+pub const ICompletionCandidate: &'static str = r#"
+export type CompletionKind = "Instruction" | "Directive" | "AddressingMode" | "Register" | "Label" | "Constant"
+export type CompletionCandidate = {
+    label: string,
+    kind: CompletionKind,
+    detail: string | null,
+    documentation: string | null,
+    snippet: string | null
+}
+"#;
+#[cfg(feature = "wasm")]
+#[wasm_bindgen(typescript_custom_section)]
+pub const IInstructionInfo: &'static str = r#"
+export type FlagEffect = "Unaffected" | "Affected" | "Cleared" | "Set" | "Undefined"
+export type InstructionInfo = {
+    mnemonic: string,
+    family: string,
+    description: string,
+    operation: string,
+    flags: { x: FlagEffect, n: FlagEffect, z: FlagEffect, v: FlagEffect, c: FlagEffect },
+    sizes: string[],
+    operands: string[][],
+    encodings: string[],
+    cycles: { mode: string, byte_word: number, long: number }[],
+    cpu_model: CpuModel,
+    minimum_model: CpuModel,
+    available: boolean
+}
+"#;
+#[cfg(feature = "wasm")]
+#[wasm_bindgen(typescript_custom_section)]
+pub const ISymbolReferences: &'static str = r#"
+export type SourceSpan = {
+    line: number,
+    start: number,
+    end: number
+}
+export type SymbolReferences = {
+    name: string,
+    token_type: string,
+    definition: SourceSpan | null,
+    references: SourceSpan[]
+}
+"#;
+#[cfg(feature = "wasm")]
+#[wasm_bindgen(typescript_custom_section)]
+pub const ILineDiff: &'static str = r#"
+export type OperandDiff = {
+    index: number,
+    before: LexedOperand | null,
+    after: LexedOperand | null
+}
+export type LineDiff = {
+    type: "Removed" | "Inserted",
+    value: { line_index: number, line: LexedLine }
+} | {
+    type: "Changed",
+    value: { before_index: number, after_index: number, before: LexedLine, after: LexedLine, operands: OperandDiff[] }
+}
+"#;
+#[cfg(feature = "wasm")]
+#[wasm_bindgen(typescript_custom_section)]
+pub const IOutlineSymbol: &'static str = r#"
+export type SourceRange = {
+    start_line: number,
+    start: number,
+    end_line: number,
+    end: number
+}
+export type OutlineSymbol = {
+    name: string,
+    kind: "Label" | "Constant" | "Macro" | "Section",
+    detail: string | null,
+    value: number | null,
+    range: SourceRange,
+    selection_range: SourceSpan,
+    children: OutlineSymbol[]
+}
+"#;
+#[cfg(feature = "wasm")]
+#[wasm_bindgen(typescript_custom_section)]
+pub const IFoldingRange: &'static str = r#"
+export type FoldingRange = {
+    start_line: number,
+    end_line: number,
+    kind: "Subroutine" | "Data" | "Macro" | "Repeat" | "Conditional"
+}
+"#;
+#[cfg(feature = "wasm")]
+#[wasm_bindgen(typescript_custom_section)]
+pub const IInlayHint: &'static str = r#"
+export type InlayHint = {
+    line: number,
+    column: number,
+    label: string,
+    kind: "Address" | "Size" | "Cycles" | "Value"
+}
+"#;
+#[cfg(feature = "wasm")]
+#[wasm_bindgen(typescript_custom_section)]
+pub const ISignatureHelp: &'static str = r#"
+export type SignatureParameter = {
+    name: string,
+    start: number,
+    end: number,
+    modes: string[]
+}
+export type SignatureHelp = {
+    label: string,
+    description: string | null,
+    parameters: SignatureParameter[],
+    active_parameter: number | null
+}
+"#;
+#[cfg(feature = "wasm")]
+#[wasm_bindgen(typescript_custom_section)]
+pub const ICodeAction: &'static str = r#"
+export type TextEdit = {
+    line: number,
+    start: number,
+    end: number,
+    new_text: string
+}
+export type CodeAction = {
+    title: string,
+    kind: "QuickFix" | "Refactor",
+    diagnostic: string | null,
+    edits: TextEdit[]
+}
+"#;
+#[cfg(feature = "wasm")]
+#[wasm_bindgen(typescript_custom_section)]
+pub const ISimilarityReport: &'static str = r#"
+export type SimilarityReport = {
+    score: number,
+    statements: number,
+    opcodes: number,
+    matched_lines: [number, number][]
+}
+"#;
+#[cfg(feature = "wasm")]
+#[wasm_bindgen(typescript_custom_section)]
+pub const ICostReport: &'static str = r#"
+export type LineCost = {
+    line_index: number,
+    address: number,
+    bytes: number,
+    best_cycles: number,
+    worst_cycles: number
+}
+export type SubroutineCost = {
+    name: string | null,
+    start_line: number,
+    end_line: number,
+    bytes: number,
+    best_cycles: number,
+    worst_cycles: number
+}
+export type CostReport = {
+    cpu_model: CpuModel,
+    lines: LineCost[],
+    subroutines: SubroutineCost[],
+    total_bytes: number
+}
+"#;
+#[cfg(feature = "wasm")]
+#[wasm_bindgen(typescript_custom_section)]
+pub const IQuizItem: &'static str = r#"
+export type Distractor = {
+    value: number,
+    mistake: string
+}
+export type QuizItem = {
+    snippet: string,
+    question: string,
+    answer: number,
+    distractors: Distractor[]
+}
+"#;
+#[cfg(feature = "wasm")]
+#[wasm_bindgen(typescript_custom_section)]
+pub const IExplanation: &'static str = r#"
+export type Explanation = {
+    address: number,
+    line_index: number,
+    text: string
+}
+"#;
+#[cfg(feature = "wasm")]
+#[wasm_bindgen(typescript_custom_section)]
+pub const IFlagExplanation: &'static str = r#"
+export type FlagReason = {
+    flag: "Extend" | "Negative" | "Zero" | "Overflow" | "Carry",
+    value: boolean,
+    changed: boolean,
+    reason: string
+}
+export type FlagExplanation = {
+    operation: "Add" | "Subtract" | "Compare" | "Negate" | "And" | "Or" | "ExclusiveOr" | "Not" | "Move" | "Test" | "Clear",
+    size: Size,
+    source: number,
+    destination: number,
+    result: number,
+    flags: FlagReason[]
+}
+"#;
+#[wasm_bindgen(typescript_custom_section)]
+pub const IStackView: &'static str = r#"
+export type SavedRegister = {
+    register: RegisterOperand,
+    address: number,
+    value: number
+}
+export type StackVariable = {
+    name: string | null,
+    offset: number,
+    address: number,
+    size: number,
+    value: number | null
+}
+export type StackFrame = {
+    function: string,
+    function_address: number,
+    call_site: number,
+    return_address: number,
+    return_address_slot: number | null,
+    frame_register: RegisterOperand | null,
+    frame_pointer: number | null,
+    locals_size: number,
+    saved_registers: SavedRegister[],
+    locals: StackVariable[],
+    arguments: StackVariable[],
+    top: number,
+    bottom: number
+}
+export type StackView = {
+    stack_pointer: number,
+    stack_top: number,
+    frames: StackFrame[]
 }
 "#;
@@ -0,0 +1,120 @@
+use alloc::{string::{String, ToString}, vec::Vec};
+use core::{error::Error, fmt};
+
+use crate::{constants::EQU, S68k, SemanticError};
+
+/*
+    Entry point for code that comes from people that are not trusted, like the submissions of a server.
+    The whole front end (lexer and semantic checker) doesn't panic on any input, there is no recursion that
+    depends on the input other than the nesting of an operand, which is bounded by the length of the line,
+    and the expansion of equs is bounded by MAX_EXPRESSION_LENGTH.
+    What is left is the size of the input, the work of the lexer grows with the number of lines times
+    the number of equs, so the input is checked against the limits before it is lexed and it is rejected
+    without doing any work if it is over them. Compiling the checked program doesn't panic either, but it
+    allocates the memory that the ds and dcb directives ask for, so it is not part of this.
+    Running the program is limited by the SandboxConfig of the interpreter options
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UntrustedLimits {
+    pub max_bytes: usize,
+    pub max_lines: usize,
+    /// In bytes
+    pub max_line_length: usize,
+    pub max_equs: usize,
+}
+
+impl Default for UntrustedLimits {
+    fn default() -> Self {
+        Self {
+            max_bytes: 1 << 20,
+            max_lines: 1 << 14,
+            max_line_length: 1 << 12,
+            max_equs: 1 << 8,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UntrustedError {
+    TooLarge { bytes: usize, max: usize },
+    TooManyLines { lines: usize, max: usize },
+    LineTooLong { line_index: usize, length: usize, max: usize },
+    TooManyEqus { equs: usize, max: usize },
+}
+
+impl fmt::Display for UntrustedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UntrustedError::TooLarge { bytes, max } => {
+                write!(f, "The code is {} bytes long, the limit is {}", bytes, max)
+            }
+            UntrustedError::TooManyLines { lines, max } => {
+                write!(f, "The code has {} lines, the limit is {}", lines, max)
+            }
+            UntrustedError::LineTooLong { line_index, length, max } => write!(
+                f,
+                "Line {} is {} bytes long, the limit is {}",
+                line_index, length, max
+            ),
+            UntrustedError::TooManyEqus { equs, max } => {
+                write!(f, "The code has {} equs, the limit is {}", equs, max)
+            }
+        }
+    }
+}
+
+impl Error for UntrustedError {}
+
+impl From<UntrustedError> for String {
+    fn from(error: UntrustedError) -> Self {
+        error.to_string()
+    }
+}
+
+fn check_limits(code: &str, limits: &UntrustedLimits) -> Result<(), UntrustedError> {
+    if code.len() > limits.max_bytes {
+        return Err(UntrustedError::TooLarge {
+            bytes: code.len(),
+            max: limits.max_bytes,
+        });
+    }
+    let mut lines = 0;
+    let mut equs = 0;
+    for (line_index, line) in code.lines().enumerate() {
+        lines += 1;
+        if line.len() > limits.max_line_length {
+            return Err(UntrustedError::LineTooLong {
+                line_index,
+                length: line.len(),
+                max: limits.max_line_length,
+            });
+        }
+        //same rule as the lexer, the name is followed by the directive
+        if line.split_whitespace().nth(1) == Some(EQU) {
+            equs += 1;
+        }
+    }
+    if lines > limits.max_lines {
+        return Err(UntrustedError::TooManyLines {
+            lines,
+            max: limits.max_lines,
+        });
+    }
+    if equs > limits.max_equs {
+        return Err(UntrustedError::TooManyEqus {
+            equs,
+            max: limits.max_equs,
+        });
+    }
+    Ok(())
+}
+
+/// Lexes and checks the code if it is within the limits, the errors of the code are returned with the program.
+/// Never panics, and the time and memory it takes are bounded by the limits
+pub fn parse_untrusted(code: &str, limits: &UntrustedLimits) -> Result<(S68k, Vec<SemanticError>), UntrustedError> {
+    check_limits(code, limits)?;
+    let s68k = S68k::new(code);
+    let errors = s68k.semantic_check();
+    Ok((s68k, errors))
+}
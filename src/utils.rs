@@ -1,11 +1,20 @@
-use std::{collections::HashMap, str::FromStr};
-
-use lazy_static::lazy_static;
-use regex::Regex;
-
-use crate::instructions::Label;
+use alloc::{string::{String, ToString}, vec, vec::Vec};
+#[cfg(feature = "assembler")]
+use crate::{
+    expression::{evaluate, is_literal, parse_literal, ExpressionError},
+    symbol::LabelTable,
+};
+
+/// Shows the panics in the browser console, when the hook is enabled
+pub fn set_panic_hook() {
+    #[cfg(feature = "console_error_panic_hook")]
+    console_error_panic_hook::set_once();
+}
 
 pub fn num_to_signed_base(num: i64, base: i64) -> Result<i64, &'static str> {
+    if !(1..=63).contains(&base) {
+        return Err("Invalid number of bits");
+    }
     let bound = 1i64 << (base - 1);
     if num >= bound * 2 || num < -bound {
         return Err("Number out of bounds");
@@ -17,187 +26,32 @@ pub fn num_to_signed_base(num: i64, base: i64) -> Result<i64, &'static str> {
     }
 }
 
-pub const VALID_ARITHMETICAL_REGEX: &str =
-    r"((?:[%@$]*\w+)|(?:'\S*'))((?:\*\*)|[\+\-\*/\^%\|\&\^])?(\S+)?";
-pub const VALID_ARITHMETICAL_TOKENS: &str = r"(('.+')|(\*\*|[+\-*\&/^()|])|([%@$]?\w*)|)";
-lazy_static! {
-    static ref ARITHMETICAL_REGEX: Regex = Regex::new(VALID_ARITHMETICAL_REGEX).unwrap();
-    static ref ARITHMETICAL_TOKEN_REGEX: Regex = Regex::new(VALID_ARITHMETICAL_TOKENS).unwrap();
-}
-#[derive(Copy, Clone, Debug, PartialEq)]
-pub enum ArithmeticalOperandToken {
-    Add,
-    Sub,
-    Mul,
-    Div,
-    Mod,
-    Pow,
-    BitAnd,
-    BitOr,
-    BitXor,
-    OpenBracket,
-    CloseBracket,
-}
-
-impl FromStr for ArithmeticalOperandToken {
-    type Err = String;
-    fn from_str(s: &str) -> Result<ArithmeticalOperandToken, String> {
-        Ok(match s {
-            "+" => Self::Add,
-            "-" => Self::Sub,
-            "*" => Self::Mul,
-            "/" => Self::Div,
-            "\\" => Self::Mod,
-            "**" => Self::Pow,
-            "&" => Self::BitAnd,
-            "|" => Self::BitOr,
-            "^" => Self::BitXor,
-            "(" => Self::OpenBracket,
-            ")" => Self::CloseBracket,
-            _ => return Err(format!("Invalid Operand {}", s)),
-        })
-    }
-}
-
-impl ArithmeticalOperandToken {
-    pub fn to_precedence(&self) -> u8 {
-        match self {
-            Self::OpenBracket | Self::CloseBracket => 0,
-            Self::BitAnd => 1,
-            Self::BitXor => 2,
-            Self::BitOr => 3,
-            Self::Add | Self::Sub => 4,
-            Self::Mul | Self::Div | Self::Mod => 5,
-            Self::Pow => 6,
-        }
+/// The digits of the number in a base from 2 to 36, with uppercase letters
+pub fn to_radix(mut number: u32, base: u32) -> Option<String> {
+    if !(2..=36).contains(&base) {
+        return None;
     }
-}
-
-#[derive(Copy, Clone, Debug, PartialEq)]
-pub enum ArithmeticalToken {
-    Number(i64),
-    Operand(ArithmeticalOperandToken),
-}
-
-pub fn to_reverse_polish_notation(tokens: &[ArithmeticalToken]) -> Result<Vec<ArithmeticalToken>, String> {
-    let mut operators: Vec<ArithmeticalOperandToken> = Vec::new();
-    let mut result: Vec<ArithmeticalToken> = Vec::new();
-    for token in tokens.iter() {
-        match token {
-            ArithmeticalToken::Number(_) => {
-                result.push(*token);
-            }
-            ArithmeticalToken::Operand(op) => {
-                match op {
-                    ArithmeticalOperandToken::OpenBracket => operators.push(*op),
-                    ArithmeticalOperandToken::CloseBracket => {
-                        //adds all operands untill it finds a open bracket
-                        while operators.last() != Some(&ArithmeticalOperandToken::OpenBracket) {
-                            match operators.pop() {
-                                Some(op) => result.push(ArithmeticalToken::Operand(op)),
-                                None => return Err("Invalid expression, could not pop arguments".to_string()),
-                            }
-                        }
-                        match operators.pop() {
-                            None => return Err("Invalid expression, could not find open bracket".to_string()),
-                            _ => {}
-                        }
-                    }
-                    _ => {
-                        while should_unwind(&operators, op) {
-                            match operators.pop() {
-                                None => return Err("Invalid expression, failed to unwind".to_string()),
-                                Some(op) => result.push(ArithmeticalToken::Operand(op)),
-                            }
-                        }
-                        operators.push(*op);
-                    }
-                }
-            }
+    let mut digits = vec![];
+    loop {
+        digits.push(core::char::from_digit(number % base, base)?.to_ascii_uppercase());
+        number /= base;
+        if number == 0 {
+            break;
         }
     }
-    for op in operators.iter().rev() {
-        result.push(ArithmeticalToken::Operand(*op));
-    }
-    Ok(result)
+    Some(digits.iter().rev().collect())
 }
 
-fn should_unwind(operators: &[ArithmeticalOperandToken], next: &ArithmeticalOperandToken) -> bool {
-    match operators.last() {
-        None => false,
-        Some(last) => {
-            last.to_precedence() >= next.to_precedence()
-        }
-    }
+#[cfg(feature = "assembler")]
+pub fn parse_absolute_expression(str: &str, labels: &LabelTable) -> Result<i64, String> {
+    Ok(evaluate(str, &|name| labels.get(name).map(|label| label.address as i64))?)
 }
 
-fn calculate_rpn(tokens: &[ArithmeticalToken]) -> Result<i64, String> {
-    if tokens.is_empty(){
-        return Err("Invalid number of arguments for expression, it must not be empty".to_string());
-    }
-    let mut stack = Vec::new();
-    for token in tokens.iter() {
-        match token {
-            ArithmeticalToken::Number(num) => stack.push(*num),
-            ArithmeticalToken::Operand(op) => {
-                match (stack.pop(), stack.pop()) {
-                    (Some(second), Some(first)) => {
-                        let result = match op {
-                            ArithmeticalOperandToken::Add => first + second,
-                            ArithmeticalOperandToken::Sub => first - second,
-                            ArithmeticalOperandToken::Mul => first * second,
-                            ArithmeticalOperandToken::Div => first / second,
-                            ArithmeticalOperandToken::Mod => first % second,
-                            ArithmeticalOperandToken::Pow => first.pow(second as u32),
-                            ArithmeticalOperandToken::BitAnd => first & second,
-                            ArithmeticalOperandToken::BitOr => first | second,
-                            ArithmeticalOperandToken::BitXor => first ^ second,
-
-                            _ => return Err(format!("Invalid operand \"{:?}\"", op)),
-                        };
-                        stack.push(result)
-                    }
-                    //to handle the cases like #-1, does not work with 10 - -1 
-                    (Some(unary), None) => {
-                        let result = match op {
-                            ArithmeticalOperandToken::Add => unary,
-                            ArithmeticalOperandToken::Sub => -unary,
-                            _ => return Err(format!("Invalid operand \"{:?}\"", op)),
-                        };
-                        stack.push(result)
-                    }
-                    _ => return Err(format!("Invalid number of arguments for expression \"{:?}\"", op))
-                }
-            }
-        }
+/// Fails for a chunk size of 0, like the one of an unknown size
+pub fn parse_string_into_padded_bytes(str: &str, chunk_size: usize) -> Result<Vec<u8>, String> {
+    if chunk_size == 0 {
+        return Err("Invalid chunk size of 0 bytes".to_string());
     }
-    match stack.pop() {
-        Some(num) => Ok(num),
-        None => Err("Invalid number of arguments for expression".to_string())
-    }
-}
-
-pub fn parse_absolute_expression(str: &str, labels: &HashMap<String, Label>) -> Result<i64, String> {
-    let tokens: Vec<&str> = ARITHMETICAL_TOKEN_REGEX
-        .find_iter(str)
-        .map(|m| m.as_str())
-        .collect();
-    let parsed_tokens = tokens
-        .iter()
-        .map(|t| match t.parse::<ArithmeticalOperandToken>() {
-            Ok(parsed) => Ok(ArithmeticalToken::Operand(parsed)),
-            Err(_) => Ok(ArithmeticalToken::Number(
-                parse_absolute(t, labels)? as i64
-            )),
-        })
-        .collect::<Result<Vec<ArithmeticalToken>, String>>()?;
-
-    let rpn_tokens = to_reverse_polish_notation(&parsed_tokens)?;
-    let result = calculate_rpn(&rpn_tokens)?;
-    Ok(result)
-}
-
-pub fn parse_string_into_padded_bytes(str: &str, chunk_size: usize) -> Vec<u8> {
     //TODO to decide if i should use utf-8 or ascii
     let mut bytes = str.as_bytes().to_vec(); //full utf-8 bytes
     //let mut bytes = str.chars().map(|c| c as u8).collect::<Vec<u8>>(); //ascii bytes
@@ -207,7 +61,7 @@ pub fn parse_string_into_padded_bytes(str: &str, chunk_size: usize) -> Vec<u8> {
         bytes.resize(bytes.len() + padding, 0);
     }
 
-    bytes
+    Ok(bytes)
 }
 
 pub fn parse_string_into_u32_chunks(str: &str, align_left: bool) -> Vec<u32> {
@@ -229,40 +83,77 @@ pub fn parse_string_into_u32_chunks(str: &str, align_left: bool) -> Vec<u32> {
     result
 }
 
-pub fn parse_absolute(str: &str, labels: &HashMap<String, Label>) -> Result<u32, String> {
-    match str.chars().collect::<Vec<char>>()[..] {
-        ['%', ..] => match i64::from_str_radix(&str[1..], 2) {
-            Ok(n) => Ok(n as u32),
-            Err(e) => Err(format!("Invalid binary number: {}, {}", str, e)),
-        },
-        ['@', ..] => match i64::from_str_radix(&str[1..], 8) {
-            Ok(n) => Ok(n as u32),
-            Err(e) => Err(format!("Invalid octal number: {}, {}", str, e)),
-        },
-        ['$', ..] => match i64::from_str_radix(&str[1..], 16) {
-            Ok(n) => Ok(n as u32),
-            Err(e) => Err(format!("Invalid hexadecimal number: {}, {}", str, e)),
-        },
-        ['\'', .., '\''] => {
-            //parse characters into list of bytes
-            let chunks = parse_string_into_u32_chunks(&str[1..str.len() - 1], false);
-            if chunks.len() > 1 {
-                return Err(format!("String exceedes 32bits: {}", str));
+#[cfg(feature = "assembler")]
+pub fn parse_absolute(str: &str, labels: &LabelTable) -> Result<u32, String> {
+    match labels.get(str) {
+        Some(label) => Ok(label.address as u32),
+        None if is_literal(str) => Ok(parse_literal(str)?.value as u32),
+        None => Err(ExpressionError::UnknownSymbol(str.to_string()).into()),
+    }
+}
+
+const REGISTER_NAMES: &[&str] = &[
+    "sp", "pc", "sr", "ccr", "usp", "vbr", "sfc", "dfc", "fpcr", "fpsr", "fpiar",
+];
+
+/// Whether the word is the name of a register, in any case
+pub fn is_register(word: &str) -> bool {
+    let word = word.to_lowercase();
+    match word.as_bytes() {
+        [b'd' | b'a', b'0'..=b'7'] => true,
+        [b'f', b'p', b'0'..=b'7'] => true,
+        _ => REGISTER_NAMES.contains(&word.as_str()),
+    }
+}
+
+/// Splits the code from the comment, like the lexer a comment starts with ; or * after a whitespace
+pub fn split_comment(line: &str) -> (&str, Option<&str>) {
+    let mut in_string = false;
+    let mut previous = ' ';
+    for (i, c) in line.char_indices() {
+        match c {
+            '\'' => in_string = !in_string,
+            ';' | '*' if !in_string && (i == 0 || previous.is_whitespace()) => {
+                return (&line[..i], Some(&line[i..]));
             }
-            if chunks.is_empty() {
-                return Err(format!("Empty string: {}", str));
+            _ => {}
+        }
+        previous = c;
+    }
+    (line, None)
+}
+
+/// Splits the operands at the commas that are not inside strings or parentheses
+pub fn split_operands(operands: &str) -> Vec<String> {
+    let mut result = vec![];
+    let mut current = String::new();
+    let mut depth = 0;
+    let mut in_string = false;
+    for c in operands.chars() {
+        match c {
+            '\'' => in_string = !in_string,
+            '(' | '[' | '{' if !in_string => depth += 1,
+            ')' | ']' | '}' if !in_string => depth -= 1,
+            ',' if !in_string && depth == 0 => {
+                result.push(current.trim().to_string());
+                current = String::new();
+                continue;
             }
-            Ok(chunks[0])
+            _ => {}
+        }
+        current.push(c);
+    }
+    if !current.trim().is_empty() || !result.is_empty() {
+        result.push(current.trim().to_string());
+    }
+    result
+}
+
+pub fn is_identifier(name: &str) -> bool {
+    match name.chars().next() {
+        Some(first) if first.is_alphabetic() || first == '_' => {
+            name.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '.')
         }
-        [..] => match labels.get(str) {
-            Some(label) => Ok((label.address as i64) as u32),
-            None => match str.parse::<i64>() {
-                Ok(value) => Ok(value as u32),
-                Err(e) => Err(format!(
-                    "Invalid decimal number or non existing label: {}, {}",
-                    str, e
-                )),
-            },
-        },
+        _ => false,
     }
 }
@@ -0,0 +1,375 @@
+use alloc::{string::String, vec::Vec};
+use crate::lexer::{LexedLine, LexedOperand, LexedSize, ParsedLine};
+#[cfg(feature = "assembler")]
+use crate::{
+    compiler::InstructionLine,
+    instructions::{BitfieldValue, FloatOperand, IndexRegister, Instruction, Operand, RegisterOperand},
+};
+
+/*
+    Visitors over the two trees of the assembler, the lexed lines with their operands and the compiled
+    instructions with their operands. Every method has a default that walks into the children, so a pass
+    only overrides the nodes it cares about and calls the matching walk_* function to keep descending.
+    Visitor reads the tree, VisitorMut is the same walk with mutable references for rewriters.
+    Registers stored as a plain address register number (like in (a0)+) are not visited as registers,
+    only the RegisterOperand fields are. Lines with lex errors are visited as unknown lines.
+    The compiled tree is only visited with the assembler feature
+*/
+
+//the bodies of the walks are the same for both visitors, match ergonomics pick & or &mut from the input
+macro_rules! walk_lexed_line_body {
+    ($visitor:ident, $line:expr, $label:ident, $directive:ident, $instruction:ident, $comment:ident, $unknown:ident) => {
+        match $line {
+            LexedLine::Label { name, .. } => $visitor.$label(name),
+            LexedLine::Directive { name, size, args } => $visitor.$directive(name, size, args),
+            LexedLine::Instruction { name, operands, size } => $visitor.$instruction(name, size, operands),
+            LexedLine::Comment { content } => $visitor.$comment(content),
+            LexedLine::Unknown { content } | LexedLine::Error { content, .. } => $visitor.$unknown(content),
+            LexedLine::Empty => {}
+        }
+    };
+}
+
+macro_rules! walk_lexed_operand_body {
+    ($visitor:ident, $operand:expr, $visit:ident) => {
+        match $operand {
+            LexedOperand::Bitfield { operand, .. }
+            | LexedOperand::Indirect(operand)
+            | LexedOperand::IndirectDisplacement { operand, .. }
+            | LexedOperand::PostIndirect(operand)
+            | LexedOperand::PreIndirect(operand) => $visitor.$visit(operand),
+            LexedOperand::IndirectIndex { operands, .. } => {
+                for operand in operands {
+                    $visitor.$visit(operand);
+                }
+            }
+            LexedOperand::MemoryIndirect { inner, outer } => {
+                for operand in inner {
+                    $visitor.$visit(operand);
+                }
+                for operand in outer {
+                    $visitor.$visit(operand);
+                }
+            }
+            LexedOperand::Immediate(_)
+            | LexedOperand::RegisterRange { .. }
+            | LexedOperand::Register(_, _)
+            | LexedOperand::RegisterWithSize(_, _, _)
+            | LexedOperand::ScaledRegister(_, _, _, _)
+            | LexedOperand::RegisterPair(_, _)
+            | LexedOperand::Absolute(_)
+            | LexedOperand::Label(_)
+            | LexedOperand::Other(_) => {}
+        }
+    };
+}
+
+#[cfg(feature = "assembler")]
+macro_rules! walk_instruction_body {
+    ($visitor:ident, $instruction:expr, $operand:ident, $register:ident, $float:ident) => {
+        match $instruction {
+            Instruction::ADDA(source, register, _)
+            | Instruction::SUBA(source, register, _)
+            | Instruction::CMPA(source, register, _)
+            | Instruction::MOVEA(source, register, _)
+            | Instruction::DIVx(source, register, _)
+            | Instruction::MULx(source, register, _)
+            | Instruction::CMP(source, register, _) => {
+                $visitor.$operand(source);
+                $visitor.$register(register);
+            }
+            Instruction::CHK(source, register) => {
+                $visitor.$operand(source);
+                $visitor.$register(register);
+            }
+            Instruction::LEA(source, register) => {
+                $visitor.$operand(source);
+                $visitor.$register(register);
+            }
+            Instruction::MOVE(source, dest, _)
+            | Instruction::ADD(source, dest, _)
+            | Instruction::SUB(source, dest, _)
+            | Instruction::CMPM(source, dest, _)
+            | Instruction::OR(source, dest, _)
+            | Instruction::AND(source, dest, _)
+            | Instruction::EOR(source, dest, _)
+            | Instruction::MOVES(source, dest, _)
+            | Instruction::ASd(source, dest, _, _)
+            | Instruction::ROd(source, dest, _, _)
+            | Instruction::LSd(source, dest, _, _)
+            | Instruction::BTST(source, dest)
+            | Instruction::BCLR(source, dest)
+            | Instruction::BSET(source, dest)
+            | Instruction::BCHG(source, dest) => {
+                $visitor.$operand(source);
+                $visitor.$operand(dest);
+            }
+            Instruction::MOVEM { target, .. }
+            | Instruction::ADDQ(_, target, _)
+            | Instruction::SUBQ(_, target, _)
+            | Instruction::ADDI(_, target, _)
+            | Instruction::SUBI(_, target, _)
+            | Instruction::ANDI(_, target, _)
+            | Instruction::ORI(_, target, _)
+            | Instruction::EORI(_, target, _)
+            | Instruction::CMPI(_, target, _)
+            | Instruction::CLR(target, _)
+            | Instruction::NEG(target, _)
+            | Instruction::NOT(target, _)
+            | Instruction::TST(target, _)
+            | Instruction::Scc(target, _)
+            | Instruction::PEA(target)
+            | Instruction::JSR(target)
+            | Instruction::JMP(target)
+            | Instruction::MOVEtoSR(target, _)
+            | Instruction::MOVEfromSR(target, _)
+            | Instruction::LINEF(_, Some(target)) => $visitor.$operand(target),
+            Instruction::MOVEQ(_, register)
+            | Instruction::SWAP(register)
+            | Instruction::EXT(register, _, _)
+            | Instruction::DBcc(register, _, _)
+            | Instruction::LINK(register, _)
+            | Instruction::UNLK(register)
+            | Instruction::MOVEC { register, .. }
+            | Instruction::MOVEUSP { register, .. } => $visitor.$register(register),
+            Instruction::EXG(first, second) => {
+                $visitor.$register(first);
+                $visitor.$register(second);
+            }
+            Instruction::MULxL { source, low: first, high: second, .. }
+            | Instruction::DIVxL { source, quotient: first, remainder: second, .. } => {
+                $visitor.$operand(source);
+                $visitor.$register(first);
+                if let Some(second) = second {
+                    $visitor.$register(second);
+                }
+            }
+            Instruction::BFx { target, offset, width, register, .. } => {
+                $visitor.$operand(target);
+                for value in [offset, width] {
+                    if let BitfieldValue::Register(value) = value {
+                        $visitor.$register(value);
+                    }
+                }
+                if let Some(register) = register {
+                    $visitor.$register(register);
+                }
+            }
+            Instruction::FMOVE(source, dest, _) => {
+                $visitor.$float(source);
+                $visitor.$float(dest);
+            }
+            Instruction::FADD(source, _, _)
+            | Instruction::FSUB(source, _, _)
+            | Instruction::FMUL(source, _, _)
+            | Instruction::FDIV(source, _, _)
+            | Instruction::FCMP(source, _, _) => $visitor.$float(source),
+            Instruction::LINEF(_, None)
+            | Instruction::Bcc(_, _)
+            | Instruction::BRA(_)
+            | Instruction::BSR(_)
+            | Instruction::TRAP(_)
+            | Instruction::TRAPV
+            | Instruction::ILLEGAL
+            | Instruction::SIMHALT
+            | Instruction::NOP
+            | Instruction::RTS
+            | Instruction::FBcc(_, _)
+            | Instruction::RTD(_)
+            | Instruction::RTE
+            | Instruction::RTR
+            | Instruction::ANDItoSR(..)
+            | Instruction::ORItoSR(..)
+            | Instruction::EORItoSR(..) => {}
+        }
+    };
+}
+
+#[cfg(feature = "assembler")]
+macro_rules! walk_operand_body {
+    ($visitor:ident, $operand:expr, $register:ident, $index:ident) => {
+        match $operand {
+            Operand::Register(register) | Operand::IndirectDisplacement { base: register, .. } => {
+                $visitor.$register(register)
+            }
+            Operand::IndirectIndex { base, index, .. } => {
+                $visitor.$register(base);
+                $visitor.$index(index);
+            }
+            Operand::MemoryIndirect { base, index, .. } => {
+                if let Some(base) = base {
+                    $visitor.$register(base);
+                }
+                if let Some(index) = index {
+                    $visitor.$index(index);
+                }
+            }
+            Operand::Immediate(_)
+            | Operand::Indirect(_)
+            | Operand::PostIndirect(_)
+            | Operand::PreIndirect(_)
+            | Operand::Absolute(_)
+            | Operand::RegisterList(_) => {}
+        }
+    };
+}
+
+pub trait Visitor {
+    fn visit_parsed_line(&mut self, line: &ParsedLine) {
+        walk_parsed_line(self, line)
+    }
+    fn visit_lexed_line(&mut self, line: &LexedLine) {
+        walk_lexed_line(self, line)
+    }
+    fn visit_label(&mut self, _name: &str) {}
+    fn visit_directive(&mut self, _name: &str, _size: &LexedSize, _args: &[String]) {}
+    fn visit_instruction(&mut self, _name: &str, _size: &LexedSize, operands: &[LexedOperand]) {
+        for operand in operands {
+            self.visit_lexed_operand(operand);
+        }
+    }
+    fn visit_comment(&mut self, _content: &str) {}
+    fn visit_unknown(&mut self, _content: &str) {}
+    fn visit_lexed_operand(&mut self, operand: &LexedOperand) {
+        walk_lexed_operand(self, operand)
+    }
+
+    #[cfg(feature = "assembler")]
+    fn visit_instruction_line(&mut self, line: &InstructionLine) {
+        walk_instruction_line(self, line)
+    }
+    #[cfg(feature = "assembler")]
+    fn visit_compiled_instruction(&mut self, instruction: &Instruction) {
+        walk_instruction(self, instruction)
+    }
+    #[cfg(feature = "assembler")]
+    fn visit_operand(&mut self, operand: &Operand) {
+        walk_operand(self, operand)
+    }
+    #[cfg(feature = "assembler")]
+    fn visit_float_operand(&mut self, operand: &FloatOperand) {
+        walk_float_operand(self, operand)
+    }
+    #[cfg(feature = "assembler")]
+    fn visit_index_register(&mut self, index: &IndexRegister) {
+        self.visit_register(&index.register)
+    }
+    #[cfg(feature = "assembler")]
+    fn visit_register(&mut self, _register: &RegisterOperand) {}
+}
+
+pub fn walk_parsed_line<V: Visitor + ?Sized>(visitor: &mut V, line: &ParsedLine) {
+    visitor.visit_lexed_line(&line.parsed)
+}
+pub fn walk_lexed_line<V: Visitor + ?Sized>(visitor: &mut V, line: &LexedLine) {
+    walk_lexed_line_body!(visitor, line, visit_label, visit_directive, visit_instruction, visit_comment, visit_unknown)
+}
+pub fn walk_lexed_operand<V: Visitor + ?Sized>(visitor: &mut V, operand: &LexedOperand) {
+    walk_lexed_operand_body!(visitor, operand, visit_lexed_operand)
+}
+/// Walks the compiled instruction, the source line is left to visit_parsed_line
+#[cfg(feature = "assembler")]
+pub fn walk_instruction_line<V: Visitor + ?Sized>(visitor: &mut V, line: &InstructionLine) {
+    visitor.visit_compiled_instruction(&line.instruction)
+}
+#[cfg(feature = "assembler")]
+pub fn walk_instruction<V: Visitor + ?Sized>(visitor: &mut V, instruction: &Instruction) {
+    walk_instruction_body!(visitor, instruction, visit_operand, visit_register, visit_float_operand)
+}
+#[cfg(feature = "assembler")]
+pub fn walk_operand<V: Visitor + ?Sized>(visitor: &mut V, operand: &Operand) {
+    walk_operand_body!(visitor, operand, visit_register, visit_index_register)
+}
+#[cfg(feature = "assembler")]
+pub fn walk_float_operand<V: Visitor + ?Sized>(visitor: &mut V, operand: &FloatOperand) {
+    if let FloatOperand::Effective(operand) = operand {
+        visitor.visit_operand(operand)
+    }
+}
+
+pub trait VisitorMut {
+    fn visit_parsed_line_mut(&mut self, line: &mut ParsedLine) {
+        walk_parsed_line_mut(self, line)
+    }
+    fn visit_lexed_line_mut(&mut self, line: &mut LexedLine) {
+        walk_lexed_line_mut(self, line)
+    }
+    fn visit_label_mut(&mut self, _name: &mut String) {}
+    fn visit_directive_mut(&mut self, _name: &mut String, _size: &mut LexedSize, _args: &mut Vec<String>) {}
+    fn visit_instruction_mut(&mut self, _name: &mut String, _size: &mut LexedSize, operands: &mut Vec<LexedOperand>) {
+        for operand in operands {
+            self.visit_lexed_operand_mut(operand);
+        }
+    }
+    fn visit_comment_mut(&mut self, _content: &mut String) {}
+    fn visit_unknown_mut(&mut self, _content: &mut String) {}
+    fn visit_lexed_operand_mut(&mut self, operand: &mut LexedOperand) {
+        walk_lexed_operand_mut(self, operand)
+    }
+
+    #[cfg(feature = "assembler")]
+    fn visit_instruction_line_mut(&mut self, line: &mut InstructionLine) {
+        walk_instruction_line_mut(self, line)
+    }
+    #[cfg(feature = "assembler")]
+    fn visit_compiled_instruction_mut(&mut self, instruction: &mut Instruction) {
+        walk_instruction_mut(self, instruction)
+    }
+    #[cfg(feature = "assembler")]
+    fn visit_operand_mut(&mut self, operand: &mut Operand) {
+        walk_operand_mut(self, operand)
+    }
+    #[cfg(feature = "assembler")]
+    fn visit_float_operand_mut(&mut self, operand: &mut FloatOperand) {
+        walk_float_operand_mut(self, operand)
+    }
+    #[cfg(feature = "assembler")]
+    fn visit_index_register_mut(&mut self, index: &mut IndexRegister) {
+        self.visit_register_mut(&mut index.register)
+    }
+    #[cfg(feature = "assembler")]
+    fn visit_register_mut(&mut self, _register: &mut RegisterOperand) {}
+}
+
+pub fn walk_parsed_line_mut<V: VisitorMut + ?Sized>(visitor: &mut V, line: &mut ParsedLine) {
+    visitor.visit_lexed_line_mut(&mut line.parsed)
+}
+pub fn walk_lexed_line_mut<V: VisitorMut + ?Sized>(visitor: &mut V, line: &mut LexedLine) {
+    walk_lexed_line_body!(
+        visitor,
+        line,
+        visit_label_mut,
+        visit_directive_mut,
+        visit_instruction_mut,
+        visit_comment_mut,
+        visit_unknown_mut
+    )
+}
+pub fn walk_lexed_operand_mut<V: VisitorMut + ?Sized>(visitor: &mut V, operand: &mut LexedOperand) {
+    walk_lexed_operand_body!(visitor, operand, visit_lexed_operand_mut)
+}
+/// Walks the compiled instruction, the source line is left to visit_parsed_line_mut
+#[cfg(feature = "assembler")]
+pub fn walk_instruction_line_mut<V: VisitorMut + ?Sized>(visitor: &mut V, line: &mut InstructionLine) {
+    visitor.visit_compiled_instruction_mut(&mut line.instruction)
+}
+#[cfg(feature = "assembler")]
+pub fn walk_instruction_mut<V: VisitorMut + ?Sized>(visitor: &mut V, instruction: &mut Instruction) {
+    walk_instruction_body!(
+        visitor,
+        instruction,
+        visit_operand_mut,
+        visit_register_mut,
+        visit_float_operand_mut
+    )
+}
+#[cfg(feature = "assembler")]
+pub fn walk_operand_mut<V: VisitorMut + ?Sized>(visitor: &mut V, operand: &mut Operand) {
+    walk_operand_body!(visitor, operand, visit_register_mut, visit_index_register_mut)
+}
+#[cfg(feature = "assembler")]
+pub fn walk_float_operand_mut<V: VisitorMut + ?Sized>(visitor: &mut V, operand: &mut FloatOperand) {
+    if let FloatOperand::Effective(operand) = operand {
+        visitor.visit_operand_mut(operand)
+    }
+}
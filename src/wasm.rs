@@ -0,0 +1,486 @@
+use serde::Serialize;
+use wasm_bindgen::{prelude::*, JsCast};
+
+use crate::{
+    diff::diff,
+    folding::get_folding_ranges,
+    formatter::{format_code, FormatterOptions},
+    outline::get_outline,
+    references::find_references,
+    semantic_tokens::{encode_semantic_tokens, get_semantic_tokens_edit},
+    similarity::compare_programs,
+    S68k,
+};
+#[cfg(feature = "assembler")]
+use crate::{
+    code_actions::get_code_actions, compiler::Compiler, cost_report::get_cost_report, diagnostic::Diagnostic, completion::get_completions, cpu_model::CpuModel, inlay_hints::get_inlay_hints,
+    instruction_info::InstructionInfo, signature_help::get_signature_help,
+    assembler::{EncodeError, MachineCode}, listing::ListingRow, symbol_map::MapSymbol,
+};
+#[cfg(feature = "interpreter")]
+use crate::explain::explain_step;
+#[cfg(feature = "interpreter")]
+use crate::flag_explanation::explain_step_flags;
+#[cfg(feature = "interpreter")]
+use crate::{assertions::parse_register, quiz::generate_quiz};
+#[cfg(feature = "interpreter")]
+use crate::{breakpoints::BreakpointLocation, debugger::StepResult, memory_region::Permissions};
+#[cfg(feature = "interpreter")]
+use crate::interpreter::{Interpreter, InterpreterOptions, InterpreterStatus, RuntimeError};
+#[cfg(feature = "interpreter")]
+use crate::interrupt_source::PeriodicInterrupt;
+
+/*
+    JS friendly entry points for web editors, they wrap the lexer, semantic checker, compiler and interpreter
+    and return plain JS objects typed in ts_types, so an editor can use the crate without keeping its own shim.
+    Only compiled with the "wasm" feature, the functions of the parts that are not enabled are left out
+*/
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(typescript_type = "ParsedLine[]")]
+    pub type ParsedLineArray;
+    #[wasm_bindgen(typescript_type = "Diagnostic[]")]
+    pub type DiagnosticArray;
+    #[wasm_bindgen(typescript_type = "InstructionInfo | undefined")]
+    pub type InstructionInfoResult;
+    #[wasm_bindgen(typescript_type = "InstructionInfo[]")]
+    pub type InstructionInfoArray;
+    #[wasm_bindgen(typescript_type = "CompletionCandidate[]")]
+    pub type CompletionCandidateArray;
+    #[wasm_bindgen(typescript_type = "SymbolReferences | undefined")]
+    pub type SymbolReferencesResult;
+    #[wasm_bindgen(typescript_type = "SemanticTokensEdit | undefined")]
+    pub type SemanticTokensEditResult;
+    #[wasm_bindgen(typescript_type = "LineDiff[]")]
+    pub type LineDiffArray;
+    #[wasm_bindgen(typescript_type = "SimilarityReport")]
+    pub type SimilarityReportResult;
+    #[wasm_bindgen(typescript_type = "CostReport")]
+    pub type CostReportResult;
+    #[wasm_bindgen(typescript_type = "QuizItem")]
+    pub type QuizItemResult;
+    #[wasm_bindgen(typescript_type = "OutlineSymbol[]")]
+    pub type OutlineSymbolArray;
+    #[wasm_bindgen(typescript_type = "FoldingRange[]")]
+    pub type FoldingRangeArray;
+    #[wasm_bindgen(typescript_type = "InlayHint[]")]
+    pub type InlayHintArray;
+    #[wasm_bindgen(typescript_type = "CodeAction[]")]
+    pub type CodeActionArray;
+    #[wasm_bindgen(typescript_type = "SignatureHelp | undefined")]
+    pub type SignatureHelpResult;
+    #[wasm_bindgen(typescript_type = "Explanation")]
+    pub type ExplanationResult;
+    #[wasm_bindgen(typescript_type = "FlagExplanation | undefined")]
+    pub type FlagExplanationResult;
+    #[wasm_bindgen(typescript_type = "StepResult")]
+    pub type DebugStepResult;
+    #[wasm_bindgen(typescript_type = "BreakHit | undefined")]
+    pub type BreakHitResult;
+    #[wasm_bindgen(typescript_type = "AssembledProgram")]
+    pub type AssembledProgramResult;
+    #[wasm_bindgen(typescript_type = "LineCycles[]")]
+    pub type LineCyclesArray;
+}
+
+/// The machine code of a program with the rows of its listing and its symbol map
+#[cfg(feature = "assembler")]
+#[derive(Debug, Clone, Serialize)]
+pub struct AssembledProgram {
+    pub machine_code: MachineCode,
+    pub listing: Vec<ListingRow>,
+    pub symbols: Vec<MapSymbol>,
+}
+
+#[cfg(feature = "interpreter")]
+fn to_step_result(result: Result<StepResult, RuntimeError>) -> Result<DebugStepResult, JsValue> {
+    match result {
+        Ok(step) => Ok(to_js_value(&step).unchecked_into()),
+        Err(e) => Err(to_js_value(&e)),
+    }
+}
+
+fn to_js_value<T: Serialize + ?Sized>(value: &T) -> JsValue {
+    match serde_wasm_bindgen::to_value(value) {
+        Ok(value) => value,
+        Err(e) => JsValue::from_str(&e.to_string()),
+    }
+}
+
+#[cfg(feature = "assembler")]
+fn to_diagnostic_array(diagnostics: &[Diagnostic]) -> DiagnosticArray {
+    to_js_value(diagnostics).unchecked_into()
+}
+
+#[wasm_bindgen]
+pub fn lex(code: String) -> Result<ParsedLineArray, JsValue> {
+    let s68k = S68k::new(code);
+    match serde_wasm_bindgen::to_value(s68k.get_lexed_lines()) {
+        Ok(lines) => Ok(lines.unchecked_into()),
+        Err(e) => Err(JsValue::from_str(&e.to_string())),
+    }
+}
+
+#[cfg(feature = "assembler")]
+#[wasm_bindgen]
+pub fn check(code: String, cpu_model: CpuModel) -> DiagnosticArray {
+    let mut s68k = S68k::new(code);
+    s68k.set_cpu_model(cpu_model);
+    to_diagnostic_array(&s68k.get_diagnostics())
+}
+
+/// The diagnostic of an error found after the semantic check, like one of the compiler or the assembler
+#[cfg(feature = "assembler")]
+fn to_error_diagnostic(s68k: &S68k, error: &EncodeError) -> DiagnosticArray {
+    to_diagnostic_array(&[error.to_diagnostic(s68k.get_code())])
+}
+
+#[cfg(feature = "assembler")]
+fn check_code(code: String, cpu_model: CpuModel) -> Result<S68k, DiagnosticArray> {
+    let mut s68k = S68k::new(code);
+    s68k.set_cpu_model(cpu_model);
+    let diagnostics = s68k.get_diagnostics();
+    match diagnostics.is_empty() {
+        true => Ok(s68k),
+        false => Err(to_diagnostic_array(&diagnostics)),
+    }
+}
+
+/// Checks and compiles the code, on failure the diagnostics are thrown
+#[cfg(feature = "assembler")]
+#[wasm_bindgen]
+pub fn assemble(code: String, cpu_model: CpuModel) -> Result<Compiler, DiagnosticArray> {
+    let s68k = check_code(code, cpu_model)?;
+    s68k.compile()
+        .map_err(|e| to_error_diagnostic(&s68k, &EncodeError::from(e)))
+}
+
+/// Checks and encodes the code to machine code, with its listing and symbol map
+#[cfg(feature = "assembler")]
+#[wasm_bindgen]
+pub fn assemble_machine_code(code: String, cpu_model: CpuModel) -> Result<AssembledProgramResult, DiagnosticArray> {
+    let s68k = check_code(code, cpu_model)?;
+    let assembler = s68k
+        .assemble()
+        .map_err(|e| to_error_diagnostic(&s68k, &e))?;
+    let program = AssembledProgram {
+        listing: assembler.generate_listing().rows,
+        symbols: assembler.get_symbol_map().symbols,
+        machine_code: assembler.into_machine_code(),
+    };
+    Ok(to_js_value(&program).unchecked_into())
+}
+
+/// Formats the code with the options, missing options use the default style
+#[wasm_bindgen]
+pub fn format(code: String, options: JsValue) -> Result<String, JsValue> {
+    let options: FormatterOptions = match options.is_undefined() || options.is_null() {
+        true => FormatterOptions::default(),
+        false => serde_wasm_bindgen::from_value(options).map_err(|e| JsValue::from_str(&e.to_string()))?,
+    };
+    Ok(format_code(&code, &options))
+}
+
+/// Bytes and cycles of every line and subroutine, on failure the diagnostics are thrown like in assemble
+#[cfg(feature = "assembler")]
+#[wasm_bindgen]
+pub fn cost_report(code: String, cpu_model: CpuModel) -> Result<CostReportResult, DiagnosticArray> {
+    let compiled = assemble(code.clone(), cpu_model)?;
+    let mut s68k = S68k::new(code);
+    s68k.set_cpu_model(cpu_model);
+    Ok(to_js_value(&get_cost_report(&s68k, &compiled)).unchecked_into())
+}
+
+/// Documentation of the mnemonic with the timings of the model, undefined if it is not an instruction
+#[cfg(feature = "assembler")]
+#[wasm_bindgen]
+pub fn instruction_info(name: String, cpu_model: CpuModel) -> InstructionInfoResult {
+    to_js_value(&InstructionInfo::lookup_for_model(&name, cpu_model)).unchecked_into()
+}
+
+/// Documentation of every mnemonic with the timings of the model, for documentation sites and quizzes
+#[cfg(feature = "assembler")]
+#[wasm_bindgen]
+pub fn instruction_database(cpu_model: CpuModel) -> InstructionInfoArray {
+    to_js_value(&InstructionInfo::all(cpu_model)).unchecked_into()
+}
+
+/// Operands of the instruction under the cursor with their legal addressing modes, the column is counted in characters
+#[cfg(feature = "assembler")]
+#[wasm_bindgen]
+pub fn signature_help(code: String, cpu_model: CpuModel, line: usize, column: usize) -> SignatureHelpResult {
+    let mut s68k = S68k::new(code);
+    s68k.set_cpu_model(cpu_model);
+    to_js_value(&get_signature_help(&s68k, line, column)).unchecked_into()
+}
+
+/// Quick fixes of the errors on the line and the refactors of the line
+#[cfg(feature = "assembler")]
+#[wasm_bindgen]
+pub fn code_actions(code: String, cpu_model: CpuModel, line: usize) -> CodeActionArray {
+    let mut s68k = S68k::new(code);
+    s68k.set_cpu_model(cpu_model);
+    to_js_value(&get_code_actions(&s68k, line)).unchecked_into()
+}
+
+/// Completion candidates at the line and column of the code, the column is counted in characters
+#[cfg(feature = "assembler")]
+#[wasm_bindgen]
+pub fn complete(code: String, cpu_model: CpuModel, line: usize, column: usize) -> CompletionCandidateArray {
+    let mut s68k = S68k::new(code);
+    s68k.set_cpu_model(cpu_model);
+    to_js_value(&get_completions(&s68k, line, column)).unchecked_into()
+}
+
+/// Definition and uses of the symbol at the line and column, the column is in UTF-16 code units
+#[wasm_bindgen]
+pub fn references(code: String, line: usize, column: usize) -> SymbolReferencesResult {
+    to_js_value(&find_references(&S68k::new(code), line, column)).unchecked_into()
+}
+
+/// Labels and equs of the code for a symbols sidebar, local labels are children of their label
+#[wasm_bindgen]
+pub fn outline(code: String) -> OutlineSymbolArray {
+    to_js_value(&get_outline(&S68k::new(code))).unchecked_into()
+}
+
+/// Subroutines and long data runs that an editor can fold, the lines are inclusive
+#[wasm_bindgen]
+pub fn folding_ranges(code: String) -> FoldingRangeArray {
+    to_js_value(&get_folding_ranges(&S68k::new(code))).unchecked_into()
+}
+
+/// Address, size and cycles of every instruction and the values of the equs where they are used
+#[cfg(feature = "assembler")]
+#[wasm_bindgen]
+pub fn inlay_hints(code: String) -> InlayHintArray {
+    to_js_value(&get_inlay_hints(&S68k::new(code))).unchecked_into()
+}
+
+/// Instructions changed, inserted and removed from the first program to the second
+#[wasm_bindgen]
+pub fn diff_programs(before: String, after: String) -> LineDiffArray {
+    let (before, after) = (S68k::new(before), S68k::new(after));
+    to_js_value(&diff(before.get_lexed_lines(), after.get_lexed_lines())).unchecked_into()
+}
+
+/// How similar the structure of two programs is, ignoring comments and the names of labels and registers
+#[wasm_bindgen]
+pub fn program_similarity(a: String, b: String) -> SimilarityReportResult {
+    let (a, b) = (S68k::new(a), S68k::new(b));
+    to_js_value(&compare_programs(a.get_lexed_lines(), b.get_lexed_lines())).unchecked_into()
+}
+
+/// Semantic tokens of the code in the LSP encoding, 5 numbers per token
+#[wasm_bindgen]
+pub fn semantic_tokens(code: String) -> Vec<u32> {
+    encode_semantic_tokens(&S68k::new(code).get_semantic_tokens())
+}
+
+/// Edit that turns the previous encoded tokens into the ones of the code, undefined when nothing changed
+#[wasm_bindgen]
+pub fn semantic_tokens_edit(previous: Vec<u32>, code: String) -> SemanticTokensEditResult {
+    let current = encode_semantic_tokens(&S68k::new(code).get_semantic_tokens());
+    to_js_value(&get_semantic_tokens_edit(&previous, &current)).unchecked_into()
+}
+
+/// Runs the snippet and makes a quiz on the value of the register, like "d2", after it
+#[cfg(feature = "interpreter")]
+#[wasm_bindgen]
+pub fn quiz(snippet: String, register: String) -> Result<QuizItemResult, JsValue> {
+    let register = parse_register(&register.to_lowercase())
+        .ok_or_else(|| JsValue::from_str(&format!("Unknown register \"{}\"", register)))?;
+    match generate_quiz(&snippet, register) {
+        Ok(item) => Ok(to_js_value(&item).unchecked_into()),
+        Err(e) => Err(JsValue::from_str(&e)),
+    }
+}
+
+#[cfg(feature = "interpreter")]
+#[wasm_bindgen]
+pub struct InterpreterHandle {
+    interpreter: Interpreter,
+    breakpoints: Vec<usize>,
+}
+
+#[cfg(feature = "interpreter")]
+impl InterpreterHandle {
+    pub fn new(program: Compiler, options: Option<InterpreterOptions>) -> Self {
+        let mut options = options.unwrap_or_default();
+        options.cpu_model = program.get_cpu_model();
+        Self {
+            interpreter: Interpreter::new(program, Some(options)),
+            breakpoints: vec![],
+        }
+    }
+    pub fn get_interpreter(&self) -> &Interpreter {
+        &self.interpreter
+    }
+    /// Runs until a breakpoint, an interrupt or the end of the program
+    pub fn run(&mut self, limit: Option<usize>) -> Result<InterpreterStatus, RuntimeError> {
+        self.interpreter.run_with_breakpoints(&self.breakpoints, limit)
+    }
+}
+
+#[cfg(feature = "interpreter")]
+fn to_interpreter_options(options: JsValue) -> Result<Option<InterpreterOptions>, JsValue> {
+    match options.is_undefined() || options.is_null() {
+        true => Ok(None),
+        false => match serde_wasm_bindgen::from_value(options) {
+            Ok(options) => Ok(Some(options)),
+            Err(e) => Err(JsValue::from_str(&e.to_string())),
+        },
+    }
+}
+
+/// The whole pipeline in one call, checks and compiles the code and creates its interpreter.
+/// The diagnostics are thrown when the code has errors, a string when the options are invalid
+#[cfg(feature = "interpreter")]
+#[wasm_bindgen]
+pub fn create_interpreter(code: String, cpu_model: CpuModel, options: JsValue) -> Result<InterpreterHandle, JsValue> {
+    let options = to_interpreter_options(options)?;
+    let program = assemble(code, cpu_model).map_err(JsValue::from)?;
+    Ok(InterpreterHandle::new(program, options))
+}
+
+#[cfg(feature = "interpreter")]
+#[wasm_bindgen]
+impl InterpreterHandle {
+    #[wasm_bindgen(constructor)]
+    pub fn wasm_new(program: Compiler, options: JsValue) -> Result<InterpreterHandle, JsValue> {
+        Ok(Self::new(program, to_interpreter_options(options)?))
+    }
+    pub fn step(&mut self) -> Result<InterpreterStatus, JsValue> {
+        self.interpreter.step().map_err(|e| to_js_value(&e))
+    }
+    /// Runs one instruction and explains what it did in plain language
+    pub fn step_explained(&mut self) -> Result<ExplanationResult, JsValue> {
+        match explain_step(&mut self.interpreter) {
+            Ok(explanation) => Ok(to_js_value(&explanation).unchecked_into()),
+            Err(e) => Err(to_js_value(&e)),
+        }
+    }
+    /// Runs one instruction and explains why each flag has its value, undefined if the instruction is not explained
+    pub fn step_explained_flags(&mut self) -> Result<FlagExplanationResult, JsValue> {
+        match explain_step_flags(&mut self.interpreter) {
+            Ok(explanation) => Ok(to_js_value(&explanation).unchecked_into()),
+            Err(e) => Err(to_js_value(&e)),
+        }
+    }
+    /// Runs one instruction and returns what it changed
+    pub fn step_into(&mut self) -> Result<DebugStepResult, JsValue> {
+        to_step_result(self.interpreter.step_into())
+    }
+    /// Like step_into, but a BSR or JSR runs until its subroutine returns
+    pub fn step_over(&mut self) -> Result<DebugStepResult, JsValue> {
+        to_step_result(self.interpreter.step_over())
+    }
+    pub fn step_out(&mut self) -> Result<DebugStepResult, JsValue> {
+        to_step_result(self.interpreter.step_out())
+    }
+    pub fn run_until_line(&mut self, line_index: usize) -> Result<DebugStepResult, JsValue> {
+        to_step_result(self.interpreter.run_until_line(line_index))
+    }
+    /// Undoes the last instruction, the interpreter must keep the history
+    pub fn step_back(&mut self) -> Result<DebugStepResult, JsValue> {
+        to_step_result(self.interpreter.step_back())
+    }
+    pub fn set_history_size(&mut self, history_size: usize) {
+        self.interpreter.set_history_size(history_size);
+    }
+    #[wasm_bindgen(js_name = run)]
+    pub fn wasm_run(&mut self, limit: Option<usize>) -> Result<InterpreterStatus, JsValue> {
+        self.run(limit).map_err(|e| to_js_value(&e))
+    }
+    pub fn undo(&mut self) -> Result<(), JsValue> {
+        match self.interpreter.undo() {
+            Ok(_) => Ok(()),
+            Err(e) => Err(to_js_value(&e)),
+        }
+    }
+    pub fn set_breakpoints(&mut self, lines: Vec<usize>) {
+        self.breakpoints = lines;
+    }
+    pub fn add_breakpoint(&mut self, line: usize) {
+        if !self.breakpoints.contains(&line) {
+            self.breakpoints.push(line);
+        }
+    }
+    pub fn remove_breakpoint(&mut self, line: usize) {
+        self.breakpoints.retain(|l| *l != line);
+    }
+    pub fn get_breakpoints(&self) -> Vec<usize> {
+        self.breakpoints.clone()
+    }
+    /// Adds a breakpoint on a line, label or address, returns its id
+    pub fn add_breakpoint_at(&mut self, location: JsValue) -> Result<usize, JsValue> {
+        let location: BreakpointLocation =
+            serde_wasm_bindgen::from_value(location).map_err(|e| JsValue::from_str(&e.to_string()))?;
+        self.interpreter.add_breakpoint(location).map_err(|e| to_js_value(&e))
+    }
+    pub fn remove_breakpoint_by_id(&mut self, id: usize) -> bool {
+        self.interpreter.remove_breakpoint(id)
+    }
+    /// Pauses after an instruction reads or writes the range, returns its id
+    pub fn add_watchpoint(&mut self, address: usize, length: usize, read: bool, write: bool) -> usize {
+        let mut accesses = Permissions::empty();
+        accesses.set(Permissions::READ, read);
+        accesses.set(Permissions::WRITE, write);
+        self.interpreter.add_watchpoint(address, length, accesses)
+    }
+    pub fn remove_watchpoint(&mut self, id: usize) -> bool {
+        self.interpreter.remove_watchpoint(id)
+    }
+    /// The breakpoint or watchpoint that paused the last run
+    pub fn get_last_break(&self) -> BreakHitResult {
+        to_js_value(&self.interpreter.get_last_break()).unchecked_into()
+    }
+    pub fn get_status(&self) -> InterpreterStatus {
+        *self.interpreter.get_status()
+    }
+    pub fn get_pc(&self) -> usize {
+        self.interpreter.get_pc()
+    }
+    pub fn get_d_regs(&self) -> Vec<u32> {
+        self.interpreter.get_cpu().wasm_get_d_regs_value()
+    }
+    pub fn get_a_regs(&self) -> Vec<u32> {
+        self.interpreter.get_cpu().wasm_get_a_regs_value()
+    }
+    pub fn get_ccr(&self) -> u16 {
+        self.interpreter.wasm_get_flags_as_number()
+    }
+    pub fn get_sr(&self) -> u16 {
+        self.interpreter.get_sr()
+    }
+    pub fn get_cycles(&self) -> u64 {
+        self.interpreter.get_cycles()
+    }
+    /// The cycles and executions of every line that ran
+    pub fn get_line_cycles(&self) -> LineCyclesArray {
+        to_js_value(&self.interpreter.get_line_cycles()).unchecked_into()
+    }
+    /// Makes the interrupt level pending, from 1 to 7
+    pub fn raise_interrupt(&mut self, level: u8) -> Result<(), JsValue> {
+        self.interpreter.raise_interrupt(level).map_err(|e| to_js_value(&e))
+    }
+    /// Raises the level every period of cycles, like a vertical blank
+    pub fn add_periodic_interrupt(&mut self, level: u8, period: u64) {
+        self.interpreter
+            .add_interrupt_source(Box::new(PeriodicInterrupt::new(level, period)));
+    }
+    pub fn read_memory(&self, address: usize, size: usize) -> Vec<u8> {
+        self.interpreter.wasm_read_memory_bytes(address, size)
+    }
+    pub fn get_current_interrupt(&self) -> Result<JsValue, JsValue> {
+        match self.interpreter.get_current_interrupt() {
+            Ok(interrupt) => Ok(to_js_value(&interrupt)),
+            Err(e) => Err(to_js_value(&e)),
+        }
+    }
+    pub fn answer_interrupt(&mut self, value: JsValue) -> Result<(), JsValue> {
+        self.interpreter
+            .wasm_answer_interrupt(value)
+            .map_err(|e| JsValue::from_str(&e))
+    }
+}